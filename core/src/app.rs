@@ -0,0 +1,440 @@
+//! Entry point for games built on top of the engine.
+
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+
+use winit::{
+    dpi::LogicalSize,
+    event::{ElementState, Event, KeyEvent, WindowEvent},
+    event_loop::{ControlFlow, EventLoop, EventLoopBuilder},
+    keyboard::{Key, NamedKey},
+};
+
+#[cfg(target_os = "android")]
+pub use winit::platform::android::activity::AndroidApp;
+
+use crate::{
+    engine::{Engine, Plugins},
+    identifier,
+    input::InputEvent,
+    loading::{LoadingPhase, LoadingScreen},
+    logging::{self, LogConfig},
+    playback::{self, InputTimeline},
+    regression,
+    render,
+    sim::{self, SimEvent, Viewport},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::instance::{self, Instance};
+
+/// Game code implements this and hands it to [`EngineBuilder::run`] instead of editing the runner.
+/// The application is moved onto the sim thread, so every callback runs there.
+pub trait Application: Send + 'static {
+    /// Called once on the sim thread before the first tick, add plugins and systems here.
+    fn setup(&mut self, _engine: &mut Engine) {}
+
+    /// Called once the loading phase is over, right before the first `fixed_update`.
+    /// Only called when the engine was built with a loading screen.
+    fn on_loaded(&mut self, _engine: &mut Engine) {}
+
+    /// Called at the fixed sim rate after the engine systems ran, `dt` is always `sim::FIXED_TIMESTEP`.
+    fn fixed_update(&mut self, _engine: &mut Engine, _dt: std::time::Duration) {}
+
+    /// Keyboard, mouse and touch input, from the window or a playback timeline.
+    fn handle_input(&mut self, _engine: &mut Engine, _event: &InputEvent) {}
+
+    /// Window events are forwarded from the main thread and delivered before the next tick.
+    fn handle_event(&mut self, _engine: &mut Engine, _event: &WindowEvent) {}
+
+    /// Another copy of the game was launched with `args` while single instance mode is on.
+    fn handle_instance_launch(&mut self, _engine: &mut Engine, _args: &[String]) {}
+
+    /// Called on the sim thread once shutdown has been requested.
+    fn shutdown(&mut self, _engine: &mut Engine) {}
+}
+
+/// Runs `app` with the default engine configuration.
+pub fn run<A: Application>(app: A) -> Result<(), Box<dyn std::error::Error>> {
+    EngineBuilder::new().run(app)
+}
+
+// Events posted to the window's event loop from other engine threads.
+enum EngineEvent {
+    InstanceLaunched(Vec<String>),
+}
+
+/// Wires up logging, the sim thread, the window and the render thread, and tears them down
+/// in the reverse order once the window closes.
+pub struct EngineBuilder {
+    title: String,
+    window_size: (f64, f64),
+    init_logging: bool,
+    log_config: LogConfig,
+    single_instance: Option<String>,
+    engine: Engine,
+    #[cfg(target_os = "android")]
+    android_app: Option<AndroidApp>,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self {
+            title: "Midnight2 Application".to_owned(),
+            window_size: (1280.0, 720.0),
+            init_logging: true,
+            log_config: LogConfig::default(),
+            single_instance: None,
+            engine: Engine::new(),
+            #[cfg(target_os = "android")]
+            android_app: None,
+        }
+    }
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    pub fn with_window_size(mut self, width: f64, height: f64) -> Self {
+        self.window_size = (width, height);
+        self
+    }
+
+    /// Disable when embedding into a host that already installed a logger.
+    pub fn with_logging(mut self, enabled: bool) -> Self {
+        self.init_logging = enabled;
+        self
+    }
+
+    /// E.g. switch to JSON lines for external tooling, see `logging::LogFormat`.
+    pub fn with_log_config(mut self, config: LogConfig) -> Self {
+        self.log_config = config;
+        self
+    }
+
+    /// Launching the game again while it runs focuses the existing window and forwards the
+    /// command line to `Application::handle_instance_launch` instead of starting a second copy.
+    /// `name` should be unique to the game.
+    pub fn with_single_instance(mut self, name: impl Into<String>) -> Self {
+        self.single_instance = Some(name.into());
+        self
+    }
+
+    /// Required on Android, pass along the app handed to the game's `android_main`.
+    #[cfg(target_os = "android")]
+    pub fn with_android_app(mut self, android_app: AndroidApp) -> Self {
+        self.android_app = Some(android_app);
+        self
+    }
+
+    /// Holds back `Application::fixed_update` until everything registered with the
+    /// `LoadingPhase` progress has finished loading.
+    pub fn with_loading_screen(mut self, screen: LoadingScreen) -> Self {
+        self.engine.insert_resource(LoadingPhase::new(screen));
+        self
+    }
+
+    pub fn add_plugins<P: Plugins>(mut self, plugins: P) -> Self {
+        self.engine.add_plugins(plugins);
+        self
+    }
+
+    /// Blocks the calling thread until the window closes and every engine thread has been joined.
+    /// On most platforms this has to be called from the main thread. On wasm32 it returns as soon
+    /// as the browser owns the event loop.
+    pub fn run<A: Application>(self, app: A) -> Result<(), Box<dyn std::error::Error>> {
+        if self.init_logging {
+            logging::init_with(&self.log_config);
+        }
+        info!("Hello midnight!");
+        let id = identifier::ThreadLocalId::allocate().ok_or("Out of thread local ids")?;
+        info!("Main Thread ID: {:?}", id);
+
+        #[cfg(target_arch = "wasm32")]
+        return run_inline(&self.title, self.window_size, app, self.engine);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.run_threaded(app)
+    }
+
+    /// Runs `app` headless against a scripted input timeline instead of a window, see
+    /// `playback::play`. Nothing is rendered.
+    pub fn run_playback<A: Application>(
+        self,
+        app: A,
+        timeline: &InputTimeline,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.init_logging {
+            logging::init_with(&self.log_config);
+        }
+        info!("Playing back {} scripted events!", timeline.entries().len());
+        let ticks = playback::play(app, self.engine, timeline);
+        info!("Playback finished after {} ticks!", ticks);
+        logging::flush();
+        Ok(())
+    }
+
+    /// Plays back a recording made with `RegressionPlugin::record` and fails if any of its
+    /// checkpoints differ, see `regression::verify`.
+    pub fn run_verify<A: Application>(
+        self,
+        app: A,
+        timeline: &InputTimeline,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if self.init_logging {
+            logging::init_with(&self.log_config);
+        }
+        info!("Verifying {} recorded events!", timeline.entries().len());
+        let result = regression::verify(app, self.engine, timeline);
+        logging::flush();
+        match result {
+            Ok(ticks) => {
+                info!("Every checkpoint matched after {} ticks!", ticks);
+                Ok(())
+            }
+            Err(mismatches) => {
+                for mismatch in &mismatches {
+                    error!("{}", mismatch);
+                }
+                Err(format!("{} checkpoints didn't match", mismatches.len()).into())
+            }
+        }
+    }
+
+    /// Runs `app` at the fixed sim rate on the calling thread without a window or renderer, e.g.
+    /// for a dedicated server. Blocks until `sim::shutdown` is called.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn run_headless<A: Application>(self, app: A) -> Result<(), Box<dyn std::error::Error>> {
+        if self.init_logging {
+            logging::init_with(&self.log_config);
+        }
+        info!("Running headless!");
+        // Nothing sends window events, the sender only has to outlive the loop.
+        let (_event_sender, event_receiver) = mpsc::channel();
+        let mut sim_loop = {
+            log_scope!("Sim setup");
+            sim::SimLoop::new(app, self.engine, event_receiver)
+        };
+        while !sim::should_shutdown() {
+            std::thread::sleep(sim_loop.update());
+        }
+        sim_loop.shutdown();
+        logging::flush();
+        Ok(())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[allow(unused_mut)]
+    fn build_event_loop(&mut self) -> Result<EventLoop<EngineEvent>, winit::error::EventLoopError> {
+        let mut builder = EventLoopBuilder::with_user_event();
+        #[cfg(target_os = "android")]
+        if let Some(android_app) = self.android_app.take() {
+            use winit::platform::android::EventLoopBuilderExtAndroid;
+            builder.with_android_app(android_app);
+        }
+        builder.build()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn run_threaded<A: Application>(mut self, app: A) -> Result<(), Box<dyn std::error::Error>> {
+        let instance = match &self.single_instance {
+            Some(name) => {
+                log_scope!("Single instance check");
+                let args: Vec<String> = std::env::args().skip(1).collect();
+                match instance::acquire(name, &args) {
+                    Ok(Instance::Forwarded) => {
+                        info!("{} is already running, handed our arguments to it!", name);
+                        return Ok(());
+                    }
+                    Ok(Instance::Primary(server)) => Some(server),
+                    Err(err) => {
+                        warn!("Single instance check failed, continuing anyway: {}", err);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let (event_sender, event_receiver) = mpsc::channel();
+        let event_loop = self.build_event_loop()?;
+
+        // Kept alive until run returns, dropping it stops listening for later launches.
+        let _instance_listener = match instance {
+            Some(server) => {
+                let proxy = event_loop.create_proxy();
+                Some(server.listen(move |args| {
+                    let _ = proxy.send_event(EngineEvent::InstanceLaunched(args));
+                })?)
+            }
+            None => None,
+        };
+
+        let sim_thread = {
+            log_scope!("Sim init");
+            sim::init(app, self.engine, event_receiver)?
+        };
+
+        let window_result = spawn_window(event_loop, &self.title, self.window_size, event_sender);
+        if window_result.is_err() {
+            // The event loop never ran, so nothing told the sim to stop.
+            sim::shutdown();
+        }
+
+        info!("Shutting down, joining sim thread!");
+        let sim_result = sim_thread
+            .join()
+            .map_err(|_| "Sim thread panicked, typically this ocurrs during shutdown".into());
+        logging::flush();
+        window_result.and(sim_result)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn_window(
+    event_loop: EventLoop<EngineEvent>,
+    title: &str,
+    (width, height): (f64, f64),
+    event_sender: mpsc::Sender<SimEvent>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Spawning window!");
+
+    let window = Arc::new(
+        winit::window::WindowBuilder::new()
+            .with_title(title)
+            .with_inner_size(LogicalSize::new(width, height))
+            .build(&event_loop)?,
+    );
+    let _ = event_sender.send(SimEvent::Viewport(Viewport::of(&window)));
+
+    // The renderer is created on the first Resumed event, mobile platforms don't hand out a
+    // native window before that.
+    let mut render_thread: Option<JoinHandle<()>> = None;
+    let mut render_error: Option<Box<dyn std::error::Error>> = None;
+
+    event_loop.run(|e, target| {
+        target.set_control_flow(ControlFlow::Poll);
+        match e {
+            Event::Resumed => match render_thread {
+                None => {
+                    log_scope!("Renderer init");
+                    match render::init(window.clone()) {
+                        Ok(handle) => render_thread = Some(handle),
+                        Err(err) => {
+                            render_error = Some(err);
+                            target.exit();
+                        }
+                    }
+                }
+                Some(_) => render::resume(),
+            },
+            Event::Suspended if render_thread.is_some() => render::suspend(),
+            Event::UserEvent(EngineEvent::InstanceLaunched(args)) => {
+                info!("Another instance was launched with {:?}", args);
+                window.set_minimized(false);
+                window.focus_window();
+                let _ = event_sender.send(SimEvent::InstanceLaunched(args));
+            }
+            Event::LoopExiting => {
+                info!("Spinning down render!");
+                render::shutdown();
+                render_thread.take().map(JoinHandle::join);
+                info!("Done!");
+
+                info!("Spinning down sim!");
+                sim::shutdown();
+                info!("Done!");
+            }
+            Event::WindowEvent { event, .. } => {
+                let exit_requested = matches!(
+                    event,
+                    WindowEvent::KeyboardInput {
+                        event: KeyEvent {
+                            logical_key: Key::Named(NamedKey::Escape),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                        ..
+                    } | WindowEvent::CloseRequested
+                );
+                // The sim thread only goes away during shutdown, nothing left to deliver to.
+                let _ = event_sender.send(SimEvent::Window(event));
+                if exit_requested {
+                    target.exit();
+                }
+            }
+            _ => {}
+        }
+    })?;
+
+    match render_error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+// Browsers give us no threads to park the sim and renderer on, so both are stepped from the
+// event loop instead. `spawn` hands the loop to the browser and returns immediately.
+#[cfg(target_arch = "wasm32")]
+fn run_inline<A: Application>(
+    title: &str,
+    (width, height): (f64, f64),
+    app: A,
+    engine: Engine,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use winit::platform::web::{EventLoopExtWebSys, WindowBuilderExtWebSys};
+
+    info!("Spawning canvas!");
+
+    let event_loop = winit::event_loop::EventLoop::new()?;
+    let window = Arc::new(
+        winit::window::WindowBuilder::new()
+            .with_title(title)
+            .with_inner_size(LogicalSize::new(width, height))
+            .with_append(true)
+            .build(&event_loop)?,
+    );
+
+    let mut renderer = Some(render::create(window.clone())?);
+    let (event_sender, event_receiver) = mpsc::channel();
+    let _ = event_sender.send(SimEvent::Viewport(Viewport::of(&window)));
+    let mut sim_loop = Some(sim::SimLoop::new(app, engine, event_receiver));
+
+    event_loop.spawn(move |e, target| {
+        let _ = &window;
+        target.set_control_flow(ControlFlow::Poll);
+        match e {
+            Event::AboutToWait => {
+                if let Some(sim_loop) = sim_loop.as_mut() {
+                    sim_loop.update();
+                }
+                if let Some(renderer) = renderer.as_mut() {
+                    render::render_frame(renderer);
+                }
+            }
+            Event::LoopExiting => {
+                info!("Spinning down render!");
+                renderer.take().map(render::destroy);
+                info!("Spinning down sim!");
+                sim_loop.take().map(sim::SimLoop::shutdown);
+                info!("Done!");
+            }
+            Event::WindowEvent { event, .. } => {
+                let exit_requested = matches!(event, WindowEvent::CloseRequested);
+                let _ = event_sender.send(SimEvent::Window(event));
+                if exit_requested {
+                    target.exit();
+                }
+            }
+            _ => {}
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,162 @@
+//! Engine assertions. `engine_assert!` logs the failing expression with its location, the
+//! current sim tick and render frame and an optional entity, then panics in debug builds. Release
+//! builds follow `set_release_mode`, logging a warning by default. `engine_ensure!` reports once
+//! per call site and never panics, it evaluates to the condition so callers can bail out.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{render, sim};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReleaseAssertMode {
+    Panic,
+    Warn,
+}
+
+static RELEASE_PANICS: AtomicBool = AtomicBool::new(false);
+
+// How failed `engine_assert!`s behave in release builds, debug builds always panic.
+pub fn set_release_mode(mode: ReleaseAssertMode) {
+    RELEASE_PANICS.store(mode == ReleaseAssertMode::Panic, Ordering::Relaxed);
+}
+
+fn context(
+    expression: &str,
+    file: &str,
+    line: u32,
+    entity: Option<&dyn fmt::Debug>,
+    message: Option<fmt::Arguments>,
+) -> String {
+    let mut context = format!(
+        "`{}` at {}:{} (tick {}, frame {})",
+        expression,
+        file,
+        line,
+        sim::current_tick(),
+        render::current_frame()
+    );
+    if let Some(entity) = entity {
+        context += &format!(" for {:?}", entity);
+    }
+    if let Some(message) = message {
+        context += &format!(": {}", message);
+    }
+    context
+}
+
+// Called by `engine_assert!`, not meant to be used directly.
+#[doc(hidden)]
+#[cold]
+pub fn assert_failed(
+    expression: &str,
+    file: &str,
+    line: u32,
+    entity: Option<&dyn fmt::Debug>,
+    message: Option<fmt::Arguments>,
+) {
+    let context = context(expression, file, line, entity, message);
+    if cfg!(debug_assertions) || RELEASE_PANICS.load(Ordering::Relaxed) {
+        error!(target: "assert", "Assertion failed: {}", context);
+        panic!("Assertion failed: {}", context);
+    }
+    warn!(target: "assert", "Assertion failed: {}", context);
+}
+
+// Called by `engine_ensure!`, `reported` is the call site's flag.
+#[doc(hidden)]
+#[cold]
+pub fn ensure_failed(
+    reported: &AtomicBool,
+    expression: &str,
+    file: &str,
+    line: u32,
+    entity: Option<&dyn fmt::Debug>,
+    message: Option<fmt::Arguments>,
+) {
+    if !reported.swap(true, Ordering::Relaxed) {
+        let context = context(expression, file, line, entity, message);
+        error!(target: "assert", "Ensure failed: {}", context);
+    }
+}
+
+/// `engine_assert!(cond)`, `engine_assert!(cond, "format", args..)`, or with the entity being
+/// worked on: `engine_assert!(entity: e, cond, ..)`.
+#[macro_export]
+macro_rules! engine_assert {
+    (entity: $entity:expr, $cond:expr $(,)?) => {
+        if !$cond {
+            $crate::assert::assert_failed(stringify!($cond), file!(), line!(), Some(&$entity), None);
+        }
+    };
+    (entity: $entity:expr, $cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $crate::assert::assert_failed(
+                stringify!($cond), file!(), line!(), Some(&$entity), Some(format_args!($($arg)+)),
+            );
+        }
+    };
+    ($cond:expr $(,)?) => {
+        if !$cond {
+            $crate::assert::assert_failed(stringify!($cond), file!(), line!(), None, None);
+        }
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $crate::assert::assert_failed(
+                stringify!($cond), file!(), line!(), None, Some(format_args!($($arg)+)),
+            );
+        }
+    };
+}
+
+/// Same arguments as `engine_assert!`, evaluates to the condition.
+#[macro_export]
+macro_rules! engine_ensure {
+    (entity: $entity:expr, $cond:expr $(,)?) => {
+        $crate::engine_ensure!(@check $cond, Some(&$entity), None)
+    };
+    (entity: $entity:expr, $cond:expr, $($arg:tt)+) => {
+        $crate::engine_ensure!(@check $cond, Some(&$entity), Some(format_args!($($arg)+)))
+    };
+    (@check $cond:expr, $entity:expr, $message:expr) => {{
+        let passed: bool = $cond;
+        if !passed {
+            static REPORTED: ::std::sync::atomic::AtomicBool =
+                ::std::sync::atomic::AtomicBool::new(false);
+            $crate::assert::ensure_failed(
+                &REPORTED, stringify!($cond), file!(), line!(), $entity, $message,
+            );
+        }
+        passed
+    }};
+    ($cond:expr $(,)?) => {
+        $crate::engine_ensure!(@check $cond, None, None)
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        $crate::engine_ensure!(@check $cond, None, Some(format_args!($($arg)+)))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn ensure_reports_without_panicking() {
+        let mut failures = 0;
+        for value in 0..3 {
+            if !engine_ensure!(value > 5, "value {} too small", value) {
+                failures += 1;
+            }
+        }
+        assert_eq!(failures, 3);
+        assert!(engine_ensure!(entity: "player", 1 + 1 == 2));
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "`1 > 2` at"))]
+    fn assert_panics_in_debug() {
+        engine_assert!(entity: 7u32, 1 > 2, "numbers are broken");
+    }
+}
@@ -0,0 +1,172 @@
+use std::time::Duration;
+
+use crate::cvars::Cvars;
+
+// Voices play on one of the category buses, which all feed the master bus. Each has the volume
+// and mute an options menu exposes, plus a fade games control, e.g. to duck music in a cutscene.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Bus {
+    Master,
+    Music,
+    Sfx,
+    Voice,
+}
+
+impl Bus {
+    pub const ALL: [Bus; 4] = [Bus::Master, Bus::Music, Bus::Sfx, Bus::Voice];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Bus::Master => "master",
+            Bus::Music => "music",
+            Bus::Sfx => "sfx",
+            Bus::Voice => "voice",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Bus> {
+        Bus::ALL.into_iter().find(|bus| bus.name() == name)
+    }
+
+    fn cvar_names(self) -> (&'static str, &'static str) {
+        match self {
+            Bus::Master => ("snd.master_volume", "snd.master_mute"),
+            Bus::Music => ("snd.music_volume", "snd.music_mute"),
+            Bus::Sfx => ("snd.sfx_volume", "snd.sfx_mute"),
+            Bus::Voice => ("snd.voice_volume", "snd.voice_mute"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BusSettings {
+    pub volume: f32,
+    pub muted: bool,
+}
+
+impl Default for BusSettings {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+            muted: false,
+        }
+    }
+}
+
+pub fn register_cvars(cvars: &mut Cvars) {
+    for bus in Bus::ALL {
+        let (volume, mute) = bus.cvar_names();
+        cvars.register(volume, 1.0, "bus volume, 0 to 1");
+        cvars.register(mute, false, "silences the bus");
+    }
+}
+
+pub fn from_cvars(cvars: &Cvars, bus: Bus) -> BusSettings {
+    let (volume, mute) = bus.cvar_names();
+    BusSettings {
+        volume: cvars.get_float(volume).unwrap_or(1.0).clamp(0.0, 1.0) as f32,
+        muted: cvars.get_bool(mute).unwrap_or(false),
+    }
+}
+
+// A gain multiplier moving linearly to a target, stepped once per mixed block.
+pub(super) struct Fade {
+    level: f32,
+    target: f32,
+    // Change per frame, 0 when not fading.
+    step: f32,
+}
+
+impl Default for Fade {
+    fn default() -> Self {
+        Self {
+            level: 1.0,
+            target: 1.0,
+            step: 0.0,
+        }
+    }
+}
+
+impl Fade {
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn is_fading(&self) -> bool {
+        self.step != 0.0
+    }
+
+    pub fn fade_to(&mut self, level: f32, duration: Duration, sample_rate: u32) {
+        let frames = duration.as_secs_f32() * sample_rate as f32;
+        self.target = level.max(0.0);
+        if frames < 1.0 {
+            self.level = self.target;
+            self.step = 0.0;
+        } else {
+            self.step = (self.target - self.level) / frames;
+        }
+    }
+
+    pub fn advance(&mut self, frames: usize) {
+        if self.step != 0.0 {
+            self.level += self.step * frames as f32;
+            if (self.step > 0.0 && self.level >= self.target)
+                || (self.step < 0.0 && self.level <= self.target)
+            {
+                self.level = self.target;
+                self.step = 0.0;
+            }
+        }
+    }
+}
+
+// Mixer side state of a bus. Gain changes are ramped over one block so they don't click.
+pub(super) struct BusState {
+    settings: BusSettings,
+    fade: Fade,
+    // Gain the last block ended at.
+    gain: f32,
+}
+
+impl Default for BusState {
+    fn default() -> Self {
+        Self {
+            settings: BusSettings::default(),
+            fade: Fade::default(),
+            gain: 1.0,
+        }
+    }
+}
+
+impl BusState {
+    pub fn set(&mut self, settings: BusSettings) {
+        self.settings = settings;
+    }
+
+    pub fn fade_to(&mut self, level: f32, duration: Duration, sample_rate: u32) {
+        self.fade.fade_to(level, duration, sample_rate);
+    }
+
+    pub fn target_gain(&self) -> f32 {
+        if self.settings.muted {
+            0.0
+        } else {
+            self.settings.volume * self.fade.level()
+        }
+    }
+
+    // Scales interleaved `samples` by the bus gain, advancing the fade by their frames.
+    pub fn apply(&mut self, samples: &mut [f32], channels: usize) {
+        let frames = samples.len() / channels;
+        self.fade.advance(frames);
+        let (from, to) = (self.gain, self.target_gain());
+        for (frame, chunk) in samples.chunks_mut(channels).enumerate() {
+            let gain = from + (to - from) * (frame + 1) as f32 / frames as f32;
+            for sample in chunk {
+                *sample *= gain;
+            }
+        }
+        self.gain = to;
+    }
+}
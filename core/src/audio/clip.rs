@@ -0,0 +1,50 @@
+use std::{sync::Arc, time::Duration};
+
+// Decoded sound, interleaved f32 samples. Cloning shares the samples, so clips are cheap to hand
+// to the mixer and keep around as handles.
+#[derive(Clone, Debug)]
+pub struct AudioClip {
+    samples: Arc<[f32]>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl AudioClip {
+    pub fn from_samples(samples: Vec<f32>, channels: u16, sample_rate: u32) -> Self {
+        assert!(channels > 0, "Audio clips need at least one channel");
+        Self {
+            samples: samples.into(),
+            channels,
+            sample_rate,
+        }
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels as usize
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.frames() as f64 / self.sample_rate as f64)
+    }
+
+    // Channels past the clip's own repeat its last one, so mono clips play on every speaker.
+    pub fn sample(&self, frame: usize, channel: usize) -> f32 {
+        let channel = channel.min(self.channels as usize - 1);
+        self.samples
+            .get(frame * self.channels as usize + channel)
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    pub fn same_samples(&self, other: &AudioClip) -> bool {
+        Arc::ptr_eq(&self.samples, &other.samples)
+    }
+}
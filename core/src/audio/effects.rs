@@ -0,0 +1,406 @@
+use std::{f32::consts::TAU, time::Duration};
+
+// Effects each bus can run, in slot order, before its gain is applied.
+pub const EFFECT_SLOTS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    // Cuts everything above `cutoff` Hz, e.g. to muffle the world while the player is underwater.
+    LowPass {
+        cutoff: f32,
+    },
+    // `room_size` and `damping` go from 0 to 1, `mix` is the share of reverberated signal.
+    Reverb {
+        room_size: f32,
+        damping: f32,
+        mix: f32,
+    },
+    // Turns the signal above `threshold` dB down by `ratio`, then adds `makeup` dB.
+    Compressor {
+        threshold: f32,
+        ratio: f32,
+        attack: Duration,
+        release: Duration,
+        makeup: f32,
+    },
+}
+
+impl Effect {
+    pub fn low_pass(cutoff: f32) -> Self {
+        Effect::LowPass { cutoff }
+    }
+
+    pub fn reverb() -> Self {
+        Effect::Reverb {
+            room_size: 0.5,
+            damping: 0.5,
+            mix: 0.3,
+        }
+    }
+
+    pub fn compressor() -> Self {
+        Effect::Compressor {
+            threshold: -12.0,
+            ratio: 4.0,
+            attack: Duration::from_millis(10),
+            release: Duration::from_millis(100),
+            makeup: 0.0,
+        }
+    }
+
+    // Parameters `t` of the way to `to`, None for effects of different kinds.
+    fn lerp(&self, to: &Effect, t: f32) -> Option<Effect> {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        let lerp_duration = |a: Duration, b: Duration| {
+            Duration::from_secs_f32(lerp(a.as_secs_f32(), b.as_secs_f32()))
+        };
+        match (*self, *to) {
+            (Effect::LowPass { cutoff: a }, Effect::LowPass { cutoff: b }) => {
+                // Cutoffs move in octaves, so sweeps sound even.
+                Some(Effect::LowPass {
+                    cutoff: lerp(a.max(1.0).log2(), b.max(1.0).log2()).exp2(),
+                })
+            }
+            (
+                Effect::Reverb {
+                    room_size,
+                    damping,
+                    mix,
+                },
+                Effect::Reverb {
+                    room_size: to_room_size,
+                    damping: to_damping,
+                    mix: to_mix,
+                },
+            ) => Some(Effect::Reverb {
+                room_size: lerp(room_size, to_room_size),
+                damping: lerp(damping, to_damping),
+                mix: lerp(mix, to_mix),
+            }),
+            (
+                Effect::Compressor {
+                    threshold,
+                    ratio,
+                    attack,
+                    release,
+                    makeup,
+                },
+                Effect::Compressor {
+                    threshold: to_threshold,
+                    ratio: to_ratio,
+                    attack: to_attack,
+                    release: to_release,
+                    makeup: to_makeup,
+                },
+            ) => Some(Effect::Compressor {
+                threshold: lerp(threshold, to_threshold),
+                ratio: lerp(ratio, to_ratio),
+                attack: lerp_duration(attack, to_attack),
+                release: lerp_duration(release, to_release),
+                makeup: lerp(makeup, to_makeup),
+            }),
+            _ => None,
+        }
+    }
+}
+
+// Mixer side effects of one bus. Everything an effect needs is allocated when it's set, so
+// processing a block never allocates.
+#[derive(Default)]
+pub(super) struct EffectChain {
+    slots: [Option<Slot>; EFFECT_SLOTS],
+}
+
+struct Slot {
+    effect: Effect,
+    // Automation, the effect moves from `from` to `target` over `frames`.
+    from: Effect,
+    target: Effect,
+    elapsed: usize,
+    frames: usize,
+    state: State,
+}
+
+enum State {
+    LowPass(Vec<Biquad>),
+    Reverb(Vec<Reverb>),
+    Compressor { reduction: f32 },
+}
+
+impl EffectChain {
+    // Sets or clears a slot. An effect of the kind already in the slot moves its parameters there
+    // over `duration` and keeps its state, so reverb tails don't cut off.
+    pub fn set(
+        &mut self,
+        slot: usize,
+        effect: Option<Effect>,
+        duration: Duration,
+        sample_rate: u32,
+        channels: usize,
+    ) {
+        let Some(entry) = self.slots.get_mut(slot) else {
+            return;
+        };
+        let Some(effect) = effect else {
+            *entry = None;
+            return;
+        };
+        match entry {
+            Some(current) if current.effect.lerp(&effect, 0.0).is_some() => {
+                current.from = current.effect;
+                current.target = effect;
+                current.elapsed = 0;
+                current.frames = (duration.as_secs_f32() * sample_rate as f32) as usize;
+            }
+            _ => {
+                *entry = Some(Slot {
+                    effect,
+                    from: effect,
+                    target: effect,
+                    elapsed: 0,
+                    frames: 0,
+                    state: State::new(&effect, sample_rate, channels),
+                })
+            }
+        }
+    }
+
+    // Runs the effects over interleaved `samples` in place.
+    pub fn process(&mut self, samples: &mut [f32], channels: usize, sample_rate: u32) {
+        let frames = samples.len() / channels;
+        for slot in self.slots.iter_mut().flatten() {
+            if slot.effect != slot.target {
+                slot.elapsed += frames;
+                let t = if slot.frames == 0 {
+                    1.0
+                } else {
+                    (slot.elapsed as f32 / slot.frames as f32).min(1.0)
+                };
+                // Snapped at the end, the lerp doesn't land exactly on the target and would keep
+                // automating forever.
+                slot.effect = if t < 1.0 {
+                    slot.from.lerp(&slot.target, t).unwrap_or(slot.target)
+                } else {
+                    slot.target
+                };
+            }
+            slot.state
+                .process(&slot.effect, samples, channels, sample_rate);
+        }
+    }
+}
+
+impl State {
+    fn new(effect: &Effect, sample_rate: u32, channels: usize) -> Self {
+        match effect {
+            Effect::LowPass { .. } => State::LowPass(vec![Biquad::default(); channels]),
+            Effect::Reverb { .. } => State::Reverb(
+                (0..channels)
+                    .map(|channel| Reverb::new(sample_rate, channel))
+                    .collect(),
+            ),
+            Effect::Compressor { .. } => State::Compressor { reduction: 0.0 },
+        }
+    }
+
+    fn process(&mut self, effect: &Effect, samples: &mut [f32], channels: usize, sample_rate: u32) {
+        match (self, *effect) {
+            (State::LowPass(filters), Effect::LowPass { cutoff }) => {
+                let coefficients = Coefficients::low_pass(cutoff, sample_rate);
+                for frame in samples.chunks_mut(channels) {
+                    for (sample, filter) in frame.iter_mut().zip(filters.iter_mut()) {
+                        *sample = filter.process(&coefficients, *sample);
+                    }
+                }
+            }
+            (
+                State::Reverb(reverbs),
+                Effect::Reverb {
+                    room_size,
+                    damping,
+                    mix,
+                },
+            ) => {
+                let feedback = 0.7 + 0.28 * room_size.clamp(0.0, 1.0);
+                let damping = 0.4 * damping.clamp(0.0, 1.0);
+                let mix = mix.clamp(0.0, 1.0);
+                for frame in samples.chunks_mut(channels) {
+                    for (sample, reverb) in frame.iter_mut().zip(reverbs.iter_mut()) {
+                        let wet = reverb.process(*sample, feedback, damping);
+                        *sample = *sample * (1.0 - mix) + wet * mix;
+                    }
+                }
+            }
+            (
+                State::Compressor { reduction },
+                Effect::Compressor {
+                    threshold,
+                    ratio,
+                    attack,
+                    release,
+                    makeup,
+                },
+            ) => {
+                let coefficient = |time: Duration| {
+                    (-1.0 / (time.as_secs_f32() * sample_rate as f32).max(1.0)).exp()
+                };
+                let (attack, release) = (coefficient(attack), coefficient(release));
+                let slope = 1.0 / ratio.max(1.0) - 1.0;
+                // Stereo linked, the loudest channel sets the gain of all so the image stays put.
+                for frame in samples.chunks_mut(channels) {
+                    let peak = frame
+                        .iter()
+                        .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+                    let level = 20.0 * (peak + 1e-9).log10();
+                    let target = (level - threshold).max(0.0) * slope;
+                    let coefficient = if target < *reduction { attack } else { release };
+                    *reduction = target + (*reduction - target) * coefficient;
+                    let gain = 10f32.powf((*reduction + makeup) / 20.0);
+                    for sample in frame {
+                        *sample *= gain;
+                    }
+                }
+            }
+            // Slots are rebuilt when the kind changes, the state always matches.
+            _ => {}
+        }
+    }
+}
+
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Coefficients {
+    // Butterworth low pass, from the RBJ audio EQ cookbook.
+    fn low_pass(cutoff: f32, sample_rate: u32) -> Self {
+        let cutoff = cutoff.clamp(10.0, sample_rate as f32 * 0.49);
+        let w0 = TAU * cutoff / sample_rate as f32;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / std::f32::consts::SQRT_2;
+        let a0 = 1.0 + alpha;
+        Self {
+            b0: (1.0 - cos) / 2.0 / a0,
+            b1: (1.0 - cos) / a0,
+            b2: (1.0 - cos) / 2.0 / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Biquad {
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    fn process(&mut self, c: &Coefficients, x: f32) -> f32 {
+        let y = c.b0 * x + c.b1 * self.x1 + c.b2 * self.x2 - c.a1 * self.y1 - c.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+// Freeverb tunings at 44.1kHz, scaled to the device rate.
+const COMB_LENGTHS: [usize; 4] = [1116, 1188, 1277, 1356];
+const ALLPASS_LENGTHS: [usize; 2] = [556, 441];
+// Added to the lengths on odd channels, decorrelating left and right.
+const STEREO_SPREAD: usize = 23;
+
+// Schroeder reverb for one channel, parallel combs into serial allpasses.
+struct Reverb {
+    combs: Vec<(Vec<f32>, usize, f32)>,
+    allpasses: Vec<(Vec<f32>, usize)>,
+}
+
+impl Reverb {
+    fn new(sample_rate: u32, channel: usize) -> Self {
+        let spread = if channel % 2 == 1 { STEREO_SPREAD } else { 0 };
+        let length =
+            |base: usize| ((base + spread) as u64 * sample_rate as u64 / 44_100).max(1) as usize;
+        Self {
+            combs: COMB_LENGTHS
+                .iter()
+                .map(|&base| (vec![0.0; length(base)], 0, 0.0))
+                .collect(),
+            allpasses: ALLPASS_LENGTHS
+                .iter()
+                .map(|&base| (vec![0.0; length(base)], 0))
+                .collect(),
+        }
+    }
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let input = input * 0.015;
+        let mut out = 0.0;
+        for (buffer, index, filtered) in &mut self.combs {
+            let delayed = buffer[*index];
+            *filtered = delayed * (1.0 - damping) + *filtered * damping;
+            buffer[*index] = input + *filtered * feedback;
+            *index = (*index + 1) % buffer.len();
+            out += delayed;
+        }
+        for (buffer, index) in &mut self.allpasses {
+            let delayed = buffer[*index];
+            buffer[*index] = out + delayed * 0.5;
+            *index = (*index + 1) % buffer.len();
+            out = delayed - out;
+        }
+        out * 3.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn low_pass_automation_and_compressor() {
+        let mut chain = EffectChain::default();
+        chain.set(
+            0,
+            Some(Effect::low_pass(20_000.0)),
+            Duration::ZERO,
+            48_000,
+            1,
+        );
+        // Sweeps down to 100Hz over the first 480 frames.
+        chain.set(
+            0,
+            Some(Effect::low_pass(100.0)),
+            Duration::from_millis(10),
+            48_000,
+            1,
+        );
+
+        // The highest frequency there is, gone once the sweep is done.
+        let mut nyquist: Vec<f32> = (0..960)
+            .map(|i| if i % 2 == 0 { 0.5 } else { -0.5 })
+            .collect();
+        for block in nyquist.chunks_mut(240) {
+            chain.process(block, 1, 48_000);
+        }
+        assert!(nyquist[900..].iter().all(|sample| sample.abs() < 0.01));
+        let slot = chain.slots[0].as_ref().unwrap();
+        assert_eq!(slot.effect, slot.target);
+
+        // A steady signal 12dB over the threshold comes out 3dB over it at 4:1.
+        let mut chain = EffectChain::default();
+        chain.set(0, Some(Effect::compressor()), Duration::ZERO, 48_000, 2);
+        let mut loud = vec![1.0; 48_000];
+        chain.process(&mut loud, 2, 48_000);
+        let level = 20.0 * loud[loud.len() - 1].log10();
+        assert!((level - -9.0).abs() < 0.01, "{}", level);
+    }
+}
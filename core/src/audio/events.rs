@@ -0,0 +1,382 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    fs,
+    hash::{BuildHasher, Hasher},
+    path::Path,
+};
+
+use super::{
+    Attenuation, AttenuationCurve, Audio, AudioClip, AudioScene, AudioTransform, Bus, VoiceParams,
+};
+use crate::config::Config;
+
+// What the sim reports, e.g. a footstep on grass or an impact of some strength. The sound event
+// table decides what it sounds like.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SoundEvent {
+    pub name: String,
+    // Narrows down the entry, e.g. the surface material.
+    pub tag: Option<String>,
+    // 0 to 1, picks between entries and can scale the volume.
+    pub strength: f32,
+    // Where it happened, positional entries play there.
+    pub position: Option<[f32; 3]>,
+}
+
+impl SoundEvent {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            tag: None,
+            strength: 1.0,
+            position: None,
+        }
+    }
+
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.to_owned());
+        self
+    }
+
+    pub fn with_strength(mut self, strength: f32) -> Self {
+        self.strength = strength;
+        self
+    }
+
+    pub fn at(mut self, position: [f32; 3]) -> Self {
+        self.position = Some(position);
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct SoundEntry {
+    name: String,
+    event: String,
+    tag: Option<String>,
+    min_strength: f32,
+    clips: Vec<String>,
+    volume: f32,
+    volume_variation: f32,
+    pitch: f32,
+    pitch_variation: f32,
+    // How much quieter weak events are, 0 plays every strength at full volume.
+    strength_volume: f32,
+    bus: Bus,
+    priority: u8,
+    attenuation: Option<Attenuation>,
+}
+
+// Maps sound events to clip sets, loaded from a file in the engine's config format. Each section
+// is an entry:
+//
+//     [footstep_grass]
+//     event = footstep
+//     tag = grass
+//     clips = step_grass1, step_grass2, step_grass3
+//     volume = 0.8
+//     volume_variation = 0.1
+//     pitch_variation = 0.05
+//     attenuation = inverse
+//     max_distance = 30
+//
+// An event plays the entry with its tag, or failing that one without a tag, with the highest
+// `min_strength` the event reaches. Optional keys are `tag`, `min_strength`, `volume`, `pitch`,
+// their `_variation`s, `strength_volume`, `bus`, `priority`, and `attenuation` (inverse, linear
+// or exponential) with `min_distance`, `max_distance` and `rolloff` for positional entries.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SoundTable {
+    entries: Vec<SoundEntry>,
+}
+
+impl SoundTable {
+    pub fn parse(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let config = Config::parse(text)?;
+        let mut sections: BTreeMap<&str, Vec<(&str, &str)>> = BTreeMap::new();
+        for (key, value) in config.iter() {
+            let (section, field) = key
+                .rsplit_once('.')
+                .ok_or_else(|| format!("{}: not in an entry section", key))?;
+            sections.entry(section).or_default().push((field, value));
+        }
+        let entries = sections
+            .into_iter()
+            .map(|(name, fields)| SoundEntry::parse(name, &fields))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { entries })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path.as_ref())?;
+        Self::parse(&text).map_err(|err| format!("{}: {}", path.as_ref().display(), err).into())
+    }
+
+    fn find(&self, event: &SoundEvent) -> Option<&SoundEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.event == event.name && entry.min_strength <= event.strength)
+            .filter(|entry| entry.tag.is_none() || entry.tag == event.tag)
+            .max_by(|a, b| {
+                a.tag
+                    .is_some()
+                    .cmp(&b.tag.is_some())
+                    .then(a.min_strength.total_cmp(&b.min_strength))
+            })
+    }
+}
+
+impl SoundEntry {
+    fn parse(name: &str, fields: &[(&str, &str)]) -> Result<Self, String> {
+        let get = |key: &str| {
+            fields
+                .iter()
+                .find(|(field, _)| *field == key)
+                .map(|(_, value)| *value)
+        };
+        let number = |key: &str, default: f32| match get(key) {
+            Some(value) => value
+                .parse::<f32>()
+                .map_err(|_| format!("{}.{}: expected a number, got {}", name, key, value)),
+            None => Ok(default),
+        };
+        let event = get("event").ok_or_else(|| format!("{}: missing event", name))?;
+        let clips: Vec<String> = get("clips")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|clip| !clip.is_empty())
+            .map(str::to_owned)
+            .collect();
+        if clips.is_empty() {
+            return Err(format!("{}: no clips", name));
+        }
+        let bus = match get("bus") {
+            Some(bus) => {
+                Bus::from_name(bus).ok_or_else(|| format!("{}: unknown bus {}", name, bus))?
+            }
+            None => Bus::Sfx,
+        };
+        let priority = match get("priority") {
+            Some(priority) => priority
+                .parse()
+                .map_err(|_| format!("{}.priority: expected 0 to 255, got {}", name, priority))?,
+            None => VoiceParams::default().priority,
+        };
+        let attenuation = match get("attenuation") {
+            Some(curve) => {
+                let curve = match curve {
+                    "inverse" => AttenuationCurve::Inverse,
+                    "linear" => AttenuationCurve::Linear,
+                    "exponential" => AttenuationCurve::Exponential,
+                    _ => return Err(format!("{}: unknown attenuation {}", name, curve)),
+                };
+                let defaults = Attenuation::default();
+                Some(Attenuation {
+                    curve,
+                    min_distance: number("min_distance", defaults.min_distance)?,
+                    max_distance: number("max_distance", defaults.max_distance)?,
+                    rolloff: number("rolloff", defaults.rolloff)?,
+                })
+            }
+            None => None,
+        };
+        Ok(Self {
+            name: name.to_owned(),
+            event: event.to_owned(),
+            tag: get("tag").map(str::to_owned),
+            min_strength: number("min_strength", 0.0)?,
+            clips,
+            volume: number("volume", 1.0)?,
+            volume_variation: number("volume_variation", 0.0)?,
+            pitch: number("pitch", 1.0)?,
+            pitch_variation: number("pitch_variation", 0.0)?,
+            strength_volume: number("strength_volume", 0.0)?,
+            bus,
+            priority,
+            attenuation,
+        })
+    }
+}
+
+// Plays sound events through the table, inserted as a resource by `AudioPlugin`. The sim posts
+// events during its tick and they play on the next audio update. Clips are registered by the
+// names the table uses.
+pub struct SoundEvents {
+    table: SoundTable,
+    clips: HashMap<String, AudioClip>,
+    queue: Vec<SoundEvent>,
+    // Clip each entry played last, so sets of two or more never repeat back to back.
+    last: HashMap<String, usize>,
+    rng: u64,
+}
+
+impl Default for SoundEvents {
+    fn default() -> Self {
+        Self {
+            table: SoundTable::default(),
+            clips: HashMap::new(),
+            queue: Vec::new(),
+            last: HashMap::new(),
+            rng: RandomState::new().build_hasher().finish() | 1,
+        }
+    }
+}
+
+impl SoundEvents {
+    pub fn set_table(&mut self, table: SoundTable) {
+        self.table = table;
+        self.last.clear();
+    }
+
+    pub fn add_clip(&mut self, name: &str, clip: AudioClip) {
+        self.clips.insert(name.to_owned(), clip);
+    }
+
+    // For reproducible variations, e.g. in replays.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = seed | 1;
+    }
+
+    pub fn post(&mut self, event: SoundEvent) {
+        self.queue.push(event);
+    }
+
+    // Plays the events posted since the last update, called once per tick by `AudioPlugin`.
+    pub fn update(&mut self, audio: &mut Audio, scene: &AudioScene) {
+        for event in std::mem::take(&mut self.queue) {
+            if let Some((clip, params)) = self.voice(&event, scene) {
+                audio.play(&clip, params);
+            }
+        }
+    }
+
+    // What an event plays, scaled by the listener's gain like the scene's sources.
+    fn voice(
+        &mut self,
+        event: &SoundEvent,
+        scene: &AudioScene,
+    ) -> Option<(AudioClip, VoiceParams)> {
+        let (clip, mut params, attenuation) = self.resolve(event)?;
+        params.gain *= scene.listener().map_or(1.0, |listener| listener.gain);
+        if let (Some(attenuation), Some(position)) = (attenuation, event.position) {
+            scene.spatialize(&mut params, &AudioTransform::at(position), &attenuation);
+        }
+        Some((clip, params))
+    }
+
+    // Picks the clip and its variation for an event, None when nothing in the table matches.
+    fn resolve(
+        &mut self,
+        event: &SoundEvent,
+    ) -> Option<(AudioClip, VoiceParams, Option<Attenuation>)> {
+        let Some(entry) = self.table.find(event).cloned() else {
+            debug!("No sound for event {} ({:?})", event.name, event.tag);
+            return None;
+        };
+        let last = self.last.get(&entry.name).copied();
+        let index = match entry.clips.len() {
+            1 => 0,
+            count => {
+                // Drawn from the others, skipping over the last one.
+                let index = self.next_below(count - last.map_or(0, |_| 1));
+                match last {
+                    Some(last) if index >= last => index + 1,
+                    _ => index,
+                }
+            }
+        };
+        let Some(clip) = self.clips.get(&entry.clips[index]) else {
+            warn!(
+                "Sound {} plays unknown clip {}",
+                entry.name, entry.clips[index]
+            );
+            return None;
+        };
+        let clip = clip.clone();
+        self.last.insert(entry.name.clone(), index);
+        let strength = event.strength.clamp(0.0, 1.0);
+        let gain = entry.volume
+            * (1.0 + self.next_signed() * entry.volume_variation)
+            * (1.0 - entry.strength_volume * (1.0 - strength));
+        let pitch = entry.pitch * (1.0 + self.next_signed() * entry.pitch_variation);
+        let params = VoiceParams {
+            gain: gain.max(0.0),
+            pitch: pitch.max(0.01),
+            bus: entry.bus,
+            priority: entry.priority,
+            ..Default::default()
+        };
+        Some((clip, params, entry.attenuation))
+    }
+
+    // xorshift64*, plenty for picking sounds.
+    fn next(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn next_below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    // Uniform in -1 to 1.
+    fn next_signed(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{audio::AudioListener, identifier::SnowflakeId};
+
+    #[test]
+    fn picks_entries_and_varies_clips() {
+        let table = SoundTable::parse(
+            "[footstep]\nevent = footstep\nclips = step1, step2\n\
+             [footstep_grass]\nevent = footstep\ntag = grass\nclips = grass1, grass2, grass3\npitch_variation = 0.1\n\
+             [impact_heavy]\nevent = impact\nmin_strength = 0.5\nclips = heavy\nbus = voice\n",
+        )
+        .unwrap();
+        let mut events = SoundEvents::default();
+        events.set_table(table);
+        events.set_seed(7);
+        for clip in ["step1", "step2", "grass1", "grass2", "grass3", "heavy"] {
+            events.add_clip(clip, AudioClip::from_samples(vec![0.0], 1, 100));
+        }
+
+        // Untagged entries are the fallback, and the set never repeats back to back.
+        let mut previous = None;
+        for _ in 0..8 {
+            let footstep = SoundEvent::new("footstep").with_tag("stone");
+            let (_, params, _) = events.resolve(&footstep).unwrap();
+            assert_eq!(params.pitch, 1.0);
+            let played = events.last["footstep"];
+            assert_ne!(Some(played), previous);
+            previous = Some(played);
+        }
+        let grass = SoundEvent::new("footstep").with_tag("grass");
+        let (_, params, _) = events.resolve(&grass).unwrap();
+        assert!((0.9..=1.1).contains(&params.pitch));
+        assert!(events.last.contains_key("footstep_grass"));
+
+        // Too weak for the only impact entry.
+        assert!(events
+            .resolve(&SoundEvent::new("impact").with_strength(0.2))
+            .is_none());
+        let (_, params, _) = events.resolve(&SoundEvent::new("impact")).unwrap();
+        assert_eq!(params.bus, Bus::Voice);
+
+        // Quieted along with the rest of the listener's sounds.
+        let mut scene = AudioScene::default();
+        let mut listener = AudioListener::new(SnowflakeId::from_bits(1));
+        listener.gain = 0.5;
+        scene.set_listener(Some(listener));
+        let (_, params) = events.voice(&SoundEvent::new("impact"), &scene).unwrap();
+        assert_eq!(params.gain, 0.5);
+
+        assert!(SoundTable::parse("[broken]\nevent = footstep\n").is_err());
+    }
+}
@@ -0,0 +1,596 @@
+use std::{mem, time::Duration};
+
+use super::{
+    bus::{BusSettings, BusState, Fade},
+    clip::AudioClip,
+    effects::{Effect, EffectChain},
+    queue::{Consumer, Producer},
+    stream::{StreamFrame, StreamReader},
+    AudioEvent, Bus, VoiceId, VoiceParams, DEFAULT_MAX_VIRTUAL_VOICES, DEFAULT_MAX_VOICES,
+};
+use crate::metrics;
+
+pub(super) enum Command {
+    Play {
+        voice: VoiceId,
+        clip: AudioClip,
+        params: VoiceParams,
+    },
+    PlayStream {
+        voice: VoiceId,
+        stream: StreamReader,
+        params: VoiceParams,
+    },
+    SetParams(VoiceId, VoiceParams),
+    Stop(VoiceId),
+    // Fades the voice's gain by a factor, stopping it once there when the flag is set.
+    FadeVoice(VoiceId, f32, Duration, bool),
+    // Voices mixed at once, and voices kept virtual on top.
+    SetVoiceLimits(usize, usize),
+    SetBus(Bus, BusSettings),
+    FadeBus(Bus, f32, Duration),
+    // Sets a bus effect slot, moving the parameters over the duration when it holds an effect of
+    // the same kind.
+    SetEffect(Bus, usize, Option<Effect>, Duration),
+}
+
+enum Source {
+    Clip {
+        clip: AudioClip,
+        // In clip frames, fractional when the clip is resampled or pitched.
+        position: f64,
+    },
+    Stream {
+        stream: StreamReader,
+        // The two frames last read, `position` is between them and the next frame is read once
+        // it reaches 1.
+        previous: Vec<f32>,
+        next: Vec<f32>,
+        position: f64,
+    },
+}
+
+struct Voice {
+    id: VoiceId,
+    source: Source,
+    params: VoiceParams,
+    fade: Fade,
+    stop_after_fade: bool,
+    // Left and right gain the last block ended at, so gain and pan changes ramp.
+    gains: Option<[f32; 2]>,
+    // Low pass state of panned voices.
+    filtered: f32,
+    // The source's samples at the current position.
+    frame: Vec<f32>,
+    // Picked to be mixed this block, virtual voices only keep their place in the source.
+    audible: bool,
+    // Virtual because more important voices took every slot, rather than for being too quiet.
+    culled: bool,
+    // Mixed last block, so it ramps out before going virtual.
+    mixed: bool,
+}
+
+// Quieter than this, -60dB, isn't worth mixing.
+const AUDIBLE_GAIN: f32 = 0.001;
+
+// Runs on the mixer thread, sums the playing voices into blocks of device samples.
+pub(super) struct Mixer {
+    sample_rate: u32,
+    channels: usize,
+    buses: [BusState; Bus::ALL.len()],
+    effects: [EffectChain; Bus::ALL.len()],
+    // Scratch buffer the voices of one bus are summed in.
+    bus_samples: Vec<f32>,
+    voices: Vec<Voice>,
+    max_voices: usize,
+    max_virtual_voices: usize,
+    // Scratch for ranking the voices.
+    order: Vec<usize>,
+    events: Producer<AudioEvent>,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: u32, channels: usize, events: Producer<AudioEvent>) -> Self {
+        Self {
+            sample_rate,
+            channels,
+            buses: Default::default(),
+            effects: Default::default(),
+            bus_samples: Vec::new(),
+            voices: Vec::new(),
+            max_voices: DEFAULT_MAX_VOICES,
+            max_virtual_voices: DEFAULT_MAX_VIRTUAL_VOICES,
+            order: Vec::new(),
+            events,
+        }
+    }
+
+    pub fn voice_count(&self) -> usize {
+        self.voices.len()
+    }
+
+    // Voices past `max_voices`, not the ones in it that are only too quiet to mix.
+    pub fn virtual_voice_count(&self) -> usize {
+        self.voices.iter().filter(|voice| voice.culled).count()
+    }
+
+    pub fn process_commands(&mut self, commands: &mut Consumer<Command>) {
+        while let Some(command) = commands.pop() {
+            match command {
+                Command::Play {
+                    voice,
+                    clip,
+                    params,
+                } => self.voices.push(Voice::new(
+                    voice,
+                    Source::Clip {
+                        clip,
+                        position: 0.0,
+                    },
+                    params,
+                )),
+                Command::PlayStream {
+                    voice,
+                    stream,
+                    params,
+                } => {
+                    let channels = stream.channels();
+                    self.voices.push(Voice::new(
+                        voice,
+                        Source::Stream {
+                            stream,
+                            previous: vec![0.0; channels],
+                            next: vec![0.0; channels],
+                            // Reads the first two frames before playing.
+                            position: 2.0,
+                        },
+                        params,
+                    ))
+                }
+                Command::SetParams(id, params) => {
+                    if let Some(voice) = self.voices.iter_mut().find(|voice| voice.id == id) {
+                        voice.params = VoiceParams {
+                            bus: voice.params.bus,
+                            ..params
+                        };
+                    }
+                }
+                Command::FadeVoice(id, level, duration, stop) => {
+                    if let Some(voice) = self.voices.iter_mut().find(|voice| voice.id == id) {
+                        voice.fade.fade_to(level, duration, self.sample_rate);
+                        voice.stop_after_fade = stop;
+                    }
+                }
+                Command::SetVoiceLimits(max_voices, max_virtual_voices) => {
+                    self.max_voices = max_voices;
+                    self.max_virtual_voices = max_virtual_voices;
+                }
+                Command::Stop(id) => {
+                    if let Some(index) = self.voices.iter().position(|voice| voice.id == id) {
+                        self.voices.swap_remove(index);
+                        self.send(AudioEvent::Finished(id));
+                    }
+                }
+                Command::SetBus(bus, settings) => self.buses[bus as usize].set(settings),
+                Command::FadeBus(bus, level, duration) => {
+                    self.buses[bus as usize].fade_to(level, duration, self.sample_rate)
+                }
+                Command::SetEffect(bus, slot, effect, duration) => self.effects[bus as usize].set(
+                    slot,
+                    effect,
+                    duration,
+                    self.sample_rate,
+                    self.channels,
+                ),
+            }
+        }
+    }
+
+    // Fills `out` with interleaved samples, its length must be a multiple of the channel count.
+    pub fn render(&mut self, out: &mut [f32]) {
+        out.fill(0.0);
+        self.bus_samples.resize(out.len(), 0.0);
+        let mut finished = Vec::new();
+        self.rank_voices();
+        for bus in [Bus::Music, Bus::Sfx, Bus::Voice] {
+            self.bus_samples.fill(0.0);
+            for voice in self
+                .voices
+                .iter_mut()
+                .filter(|voice| voice.params.bus == bus)
+            {
+                if !voice.render(&mut self.bus_samples, self.channels, self.sample_rate) {
+                    finished.push(voice.id);
+                }
+            }
+            self.effects[bus as usize].process(
+                &mut self.bus_samples,
+                self.channels,
+                self.sample_rate,
+            );
+            self.buses[bus as usize].apply(&mut self.bus_samples, self.channels);
+            for (out, sample) in out.iter_mut().zip(&self.bus_samples) {
+                *out += sample;
+            }
+        }
+        // Voices on the master bus skip the category volumes.
+        for voice in self
+            .voices
+            .iter_mut()
+            .filter(|voice| voice.params.bus == Bus::Master)
+        {
+            if !voice.render(out, self.channels, self.sample_rate) {
+                finished.push(voice.id);
+            }
+        }
+        self.effects[Bus::Master as usize].process(out, self.channels, self.sample_rate);
+        self.buses[Bus::Master as usize].apply(out, self.channels);
+        for sample in out.iter_mut() {
+            *sample = sample.clamp(-1.0, 1.0);
+        }
+        self.voices.retain(|voice| !finished.contains(&voice.id));
+        for id in finished {
+            self.send(AudioEvent::Finished(id));
+        }
+    }
+
+    // Picks the voices to mix, the most important first and among equals the loudest. The rest
+    // go virtual, and past the virtual limit the least important are stolen.
+    fn rank_voices(&mut self) {
+        let buses = &self.buses;
+        let audibility = |voice: &Voice| {
+            voice.params.gain * voice.fade.level() * buses[voice.params.bus as usize].target_gain()
+        };
+        let voices = &self.voices;
+        self.order.clear();
+        self.order.extend(0..voices.len());
+        self.order.sort_by(|&a, &b| {
+            let (a, b) = (&voices[a], &voices[b]);
+            b.params
+                .priority
+                .cmp(&a.params.priority)
+                .then(audibility(b).total_cmp(&audibility(a)))
+        });
+        let mut stolen = Vec::new();
+        for (rank, &index) in self.order.iter().enumerate() {
+            let voice = &mut self.voices[index];
+            voice.culled = rank >= self.max_voices;
+            voice.audible = !voice.culled && audibility(voice) >= AUDIBLE_GAIN;
+            if rank >= self.max_voices + self.max_virtual_voices {
+                stolen.push(voice.id);
+            }
+        }
+        if !stolen.is_empty() {
+            metrics::increment("audio_voices_stolen_total", stolen.len() as u64);
+            self.voices.retain(|voice| !stolen.contains(&voice.id));
+            for id in stolen {
+                self.send(AudioEvent::Finished(id));
+            }
+        }
+    }
+
+    fn send(&mut self, event: AudioEvent) {
+        // Nobody polling, the sim side only needs recent events.
+        let _ = self.events.push(event);
+    }
+}
+
+impl Source {
+    fn channels(&self) -> usize {
+        match self {
+            Source::Clip { clip, .. } => clip.channels() as usize,
+            Source::Stream { stream, .. } => stream.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self {
+            Source::Clip { clip, .. } => clip.sample_rate(),
+            Source::Stream { stream, .. } => stream.sample_rate(),
+        }
+    }
+
+    // Writes the samples at the current position to `out`, returns false once the source ended.
+    fn read(&mut self, looping: bool, out: &mut [f32]) -> bool {
+        match self {
+            Source::Clip { clip, position } => {
+                let clip_frames = clip.frames();
+                if *position >= clip_frames as f64 {
+                    if !looping || clip_frames == 0 {
+                        return false;
+                    }
+                    *position %= clip_frames as f64;
+                }
+                // Linear interpolation between neighbouring clip frames.
+                let index = *position as usize;
+                let t = (*position - index as f64) as f32;
+                let next = if index + 1 < clip_frames {
+                    index + 1
+                } else if looping {
+                    0
+                } else {
+                    index
+                };
+                for (channel, out) in out.iter_mut().enumerate() {
+                    let a = clip.sample(index, channel);
+                    let b = clip.sample(next, channel);
+                    *out = a + (b - a) * t;
+                }
+                true
+            }
+            // Streams loop on the decoding side.
+            Source::Stream {
+                stream,
+                previous,
+                next,
+                position,
+            } => {
+                while *position >= 1.0 {
+                    match stream.next_frame(previous) {
+                        StreamFrame::Ready => {
+                            mem::swap(previous, next);
+                            *position -= 1.0;
+                        }
+                        // Silence until decoding catches up, then carry on from where it was.
+                        StreamFrame::Underrun => {
+                            *position = 1.0;
+                            out.fill(0.0);
+                            return true;
+                        }
+                        StreamFrame::Ended => return false,
+                    }
+                }
+                let t = *position as f32;
+                for ((out, a), b) in out.iter_mut().zip(previous.iter()).zip(next.iter()) {
+                    *out = a + (b - a) * t;
+                }
+                true
+            }
+        }
+    }
+
+    fn advance(&mut self, step: f64) {
+        match self {
+            Source::Clip { position, .. } | Source::Stream { position, .. } => *position += step,
+        }
+    }
+}
+
+impl Voice {
+    fn new(id: VoiceId, source: Source, params: VoiceParams) -> Self {
+        Self {
+            id,
+            frame: vec![0.0; source.channels().max(1)],
+            source,
+            params,
+            fade: Fade::default(),
+            stop_after_fade: false,
+            gains: None,
+            filtered: 0.0,
+            audible: false,
+            culled: false,
+            mixed: false,
+        }
+    }
+
+    // Mixes the voice into interleaved `out`, or only moves it along while virtual. Returns false
+    // once it played to the end.
+    fn render(&mut self, out: &mut [f32], channels: usize, sample_rate: u32) -> bool {
+        if self.audible || self.mixed {
+            self.mixed = self.audible;
+            return self.mix(out, channels, sample_rate);
+        }
+        let frames = out.len() / channels;
+        self.fade.advance(frames);
+        // Ramps in from silence when it turns audible again.
+        self.gains = Some([0.0; 2]);
+        let step = self.step(sample_rate);
+        let playing = match &mut self.source {
+            Source::Clip { clip, position } => {
+                *position += step * frames as f64;
+                let clip_frames = clip.frames() as f64;
+                if *position >= clip_frames && self.params.looping && clip_frames > 0.0 {
+                    *position %= clip_frames;
+                }
+                *position < clip_frames
+            }
+            // Streams have to be read to keep up.
+            Source::Stream { .. } => (0..frames).all(|_| {
+                let playing = self.source.read(self.params.looping, &mut self.frame);
+                self.source.advance(step);
+                playing
+            }),
+        };
+        playing && (!self.stop_after_fade || self.fade.is_fading())
+    }
+
+    fn step(&self, sample_rate: u32) -> f64 {
+        self.source.sample_rate() as f64 / sample_rate as f64 * self.params.pitch.max(0.0) as f64
+    }
+
+    fn mix(&mut self, out: &mut [f32], channels: usize, sample_rate: u32) -> bool {
+        let step = self.step(sample_rate);
+        let frames = out.len() / channels;
+        self.fade.advance(frames);
+        // Going virtual, ramps out over the block.
+        let to = if self.audible {
+            self.target_gains(channels)
+        } else {
+            [0.0; 2]
+        };
+        let from = *self.gains.get_or_insert(to);
+        let lowpass = lowpass_coefficient(self.params.brightness, sample_rate);
+        for (i, frame) in out.chunks_mut(channels).enumerate() {
+            if !self.source.read(self.params.looping, &mut self.frame) {
+                self.gains = Some(to);
+                return false;
+            }
+            let ramp = (i + 1) as f32 / frames as f32;
+            let left = from[0] + (to[0] - from[0]) * ramp;
+            let right = from[1] + (to[1] - from[1]) * ramp;
+            if self.params.pan.is_some() {
+                let mono = self.frame.iter().sum::<f32>() / self.frame.len() as f32;
+                self.filtered += (mono - self.filtered) * lowpass;
+                // Anything past the front pair stays silent.
+                frame[0] += self.filtered * left;
+                if channels > 1 {
+                    frame[1] += self.filtered * right;
+                }
+            } else {
+                // Channels past the source's own repeat its last one.
+                let last = self.frame.len() - 1;
+                for (channel, out) in frame.iter_mut().enumerate() {
+                    *out += self.frame[channel.min(last)] * left;
+                }
+            }
+            self.source.advance(step);
+        }
+        self.gains = Some(to);
+        !self.stop_after_fade || self.fade.is_fading()
+    }
+
+    fn target_gains(&self, channels: usize) -> [f32; 2] {
+        let gain = self.params.gain * self.fade.level();
+        match self.params.pan {
+            // Equal power, so a source keeps its loudness as it moves across.
+            Some(pan) if channels > 1 => {
+                let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+                [gain * angle.cos(), gain * angle.sin()]
+            }
+            _ => [gain, gain],
+        }
+    }
+}
+
+// One pole low pass coefficient, the cutoff falls from the top of hearing as the brightness drops.
+fn lowpass_coefficient(brightness: f32, sample_rate: u32) -> f32 {
+    if brightness >= 1.0 {
+        return 1.0;
+    }
+    let cutoff = 20_000.0 * brightness.max(0.01).powi(2);
+    1.0 - (-std::f32::consts::TAU * cutoff / sample_rate as f32).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::queue;
+
+    #[test]
+    fn resamples_and_finishes() {
+        let (mut commands, mut command_queue) = queue::channel(4);
+        let (events, mut event_queue) = queue::channel(4);
+        // Stereo output at twice the clip's rate, every clip frame lasts two output frames.
+        let mut mixer = Mixer::new(200, 2, events);
+        let clip = AudioClip::from_samples(vec![0.0, 1.0], 1, 100);
+        let params = VoiceParams {
+            gain: 0.5,
+            ..Default::default()
+        };
+        let voice = VoiceId(0);
+        let _ = commands.push(Command::Play {
+            voice,
+            clip,
+            params,
+        });
+        mixer.process_commands(&mut command_queue);
+
+        let mut out = [1.0; 12];
+        mixer.render(&mut out);
+        assert_eq!(
+            out,
+            [0.0, 0.0, 0.25, 0.25, 0.5, 0.5, 0.5, 0.5, 0.0, 0.0, 0.0, 0.0]
+        );
+        assert_eq!(event_queue.pop(), Some(AudioEvent::Finished(voice)));
+        assert_eq!(mixer.voice_count(), 0);
+    }
+
+    #[test]
+    fn virtual_voices_and_stealing() {
+        let (mut commands, mut command_queue) = queue::channel(8);
+        let (events, mut event_queue) = queue::channel(4);
+        let mut mixer = Mixer::new(100, 1, events);
+        let ramp = AudioClip::from_samples(
+            (0..100).map(|i| i as f32 / 100.0).collect::<Vec<_>>(),
+            1,
+            100,
+        );
+        let _ = commands.push(Command::SetVoiceLimits(1, 1));
+        for (voice, priority) in [(0, 0), (1, 255), (2, 128)] {
+            let params = VoiceParams {
+                priority,
+                ..Default::default()
+            };
+            let _ = commands.push(Command::Play {
+                voice: VoiceId(voice),
+                clip: ramp.clone(),
+                params,
+            });
+        }
+        mixer.process_commands(&mut command_queue);
+
+        // Only the most important voice is mixed, the least important is stolen.
+        let mut out = [0.0; 2];
+        mixer.render(&mut out);
+        assert_eq!(out, [0.0, 0.01]);
+        assert_eq!(event_queue.pop(), Some(AudioEvent::Finished(VoiceId(0))));
+        assert_eq!(mixer.virtual_voice_count(), 1);
+
+        // The virtual voice kept its place and ramps back in.
+        let _ = commands.push(Command::Stop(VoiceId(1)));
+        mixer.process_commands(&mut command_queue);
+        mixer.render(&mut out);
+        assert_eq!(out, [0.01, 0.03]);
+        assert_eq!(mixer.virtual_voice_count(), 0);
+
+        // Silent voices aren't mixed, but with a slot free they weren't culled either.
+        let _ = commands.push(Command::SetVoiceLimits(2, 1));
+        let _ = commands.push(Command::Play {
+            voice: VoiceId(3),
+            clip: ramp.clone(),
+            params: VoiceParams {
+                gain: 0.0,
+                ..Default::default()
+            },
+        });
+        mixer.process_commands(&mut command_queue);
+        mixer.render(&mut out);
+        assert_eq!(mixer.voice_count(), 2);
+        assert_eq!(mixer.virtual_voice_count(), 0);
+    }
+
+    #[test]
+    fn bus_mute_and_fade() {
+        let (mut commands, mut command_queue) = queue::channel(8);
+        let (events, _event_queue) = queue::channel(4);
+        let mut mixer = Mixer::new(100, 1, events);
+        let clip = AudioClip::from_samples(vec![0.5; 100], 1, 100);
+        for (voice, bus) in [(VoiceId(0), Bus::Music), (VoiceId(1), Bus::Sfx)] {
+            let params = VoiceParams {
+                bus,
+                looping: true,
+                ..Default::default()
+            };
+            let _ = commands.push(Command::Play {
+                voice,
+                clip: clip.clone(),
+                params,
+            });
+        }
+        let muted = BusSettings {
+            muted: true,
+            ..Default::default()
+        };
+        let _ = commands.push(Command::SetBus(Bus::Music, muted));
+        let _ = commands.push(Command::FadeBus(Bus::Sfx, 0.0, Duration::from_millis(40)));
+        mixer.process_commands(&mut command_queue);
+
+        // The mute ramps in over the first block, the fade takes four.
+        let mut out = [0.0; 1];
+        mixer.render(&mut out);
+        assert_eq!(out[0], 0.375);
+        let mut out = [0.0; 3];
+        mixer.render(&mut out);
+        assert_eq!(out, [0.25, 0.125, 0.0]);
+    }
+}
@@ -0,0 +1,555 @@
+//! Audio output. The sim talks to a dedicated mixer thread through a lock free command queue; the
+//! mixer renders the playing voices ahead into a sample queue, which the device callback only
+//! copies out of, so nothing on the real time path locks or allocates.
+//!
+//! `AudioPlugin` opens the output device and inserts the `Audio` resource. Dropping it, which
+//! happens when the engine shuts down, stops the mixer thread and joins it.
+//!
+//! Music streams instead of loading whole: each track decodes on its own thread into a short
+//! queue of blocks the mixer reads from. Ogg Vorbis decoding needs the `ogg` feature.
+//!
+//! There is no mixer thread on wasm32 yet, `Audio::start` fails there.
+
+#![cfg_attr(target_arch = "wasm32", allow(dead_code, unused_imports))]
+
+mod bus;
+mod clip;
+mod effects;
+mod events;
+mod mixer;
+mod music;
+#[cfg(not(target_arch = "wasm32"))]
+mod output;
+mod queue;
+mod scene;
+mod spatial;
+mod stream;
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+pub use bus::{Bus, BusSettings};
+pub use clip::AudioClip;
+pub use effects::{Effect, EFFECT_SLOTS};
+pub use events::{SoundEvent, SoundEvents, SoundTable};
+pub use music::{Music, MusicTrack};
+pub use scene::{AudioListener, AudioScene, AudioSource};
+pub use spatial::{Attenuation, AttenuationCurve, AudioTransform, SpatialConfig};
+#[cfg(feature = "ogg")]
+pub use stream::OggDecoder;
+pub use stream::{ClipDecoder, Decoder, LoopSection};
+
+use crate::{
+    cvars::{self, Cvars},
+    engine::{Engine, Plugin, Resources},
+};
+use mixer::Command;
+use queue::{Consumer, Producer};
+
+const COMMAND_QUEUE_SIZE: usize = 1024;
+const EVENT_QUEUE_SIZE: usize = 1024;
+const DEFAULT_MAX_VOICES: usize = 32;
+const DEFAULT_MAX_VIRTUAL_VOICES: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VoiceId(u64);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoiceParams {
+    pub gain: f32,
+    // Playback speed, 2 is an octave up.
+    pub pitch: f32,
+    pub looping: bool,
+    // Can't be changed once playing.
+    pub bus: Bus,
+    // Set to play the clip downmixed to mono and panned, -1 is hard left and 1 hard right.
+    pub pan: Option<f32>,
+    // Low pass on panned voices, 1 is unfiltered and lower sounds duller.
+    pub brightness: f32,
+    // Higher is mixed first when there are more voices than `AudioConfig::max_voices`.
+    pub priority: u8,
+}
+
+impl Default for VoiceParams {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            pitch: 1.0,
+            looping: false,
+            bus: Bus::Sfx,
+            pan: None,
+            brightness: 1.0,
+            priority: 128,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioEvent {
+    // Played to the end, stopped, or stolen by more important voices.
+    Finished(VoiceId),
+}
+
+#[derive(Debug)]
+pub enum AudioError {
+    NoDevice,
+    DeviceNotFound(String),
+    UnsupportedFormat(String),
+    Unsupported,
+    Backend(String),
+    Decode(String),
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AudioError::NoDevice => write!(f, "no audio output device"),
+            AudioError::DeviceNotFound(name) => write!(f, "audio device {} not found", name),
+            AudioError::UnsupportedFormat(format) => {
+                write!(f, "unsupported sample format {}", format)
+            }
+            AudioError::Unsupported => write!(f, "audio isn't supported on this platform"),
+            AudioError::Backend(err) => write!(f, "{}", err),
+            AudioError::Decode(err) => write!(f, "failed to decode audio: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+// Names of the output devices, for an options menu. Pass one as `AudioConfig::device`.
+pub fn output_devices() -> Result<Vec<String>, AudioError> {
+    #[cfg(not(target_arch = "wasm32"))]
+    return output::output_devices();
+    #[cfg(target_arch = "wasm32")]
+    Err(AudioError::Unsupported)
+}
+
+#[derive(Clone, Debug)]
+pub struct AudioConfig {
+    // None for the system default.
+    pub device: Option<String>,
+    // How far ahead the mixer renders. Lower reacts faster, but risks gaps when the mixer thread
+    // is late.
+    pub latency: Duration,
+    // Voices mixed at once. The least important of the rest go virtual, they keep playing
+    // silently and are mixed again once there's room.
+    pub max_voices: usize,
+    // Virtual voices kept on top of those, past it the least important are stopped.
+    pub max_virtual_voices: usize,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            device: None,
+            latency: Duration::from_millis(40),
+            max_voices: DEFAULT_MAX_VOICES,
+            max_virtual_voices: DEFAULT_MAX_VIRTUAL_VOICES,
+        }
+    }
+}
+
+// The sim's handle to the mixer, inserted as a resource by `AudioPlugin`.
+pub struct Audio {
+    commands: Producer<Command>,
+    event_queue: Consumer<AudioEvent>,
+    // Events received by the last `update`, kept for `poll_event` and `finished`.
+    events: VecDeque<AudioEvent>,
+    finished: Vec<VoiceId>,
+    next_voice: u64,
+    buses: [BusSettings; Bus::ALL.len()],
+    effects: [[Option<Effect>; EFFECT_SLOTS]; Bus::ALL.len()],
+    device: String,
+    sample_rate: u32,
+    channels: usize,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Audio {
+    // Opens the output device and starts the mixer thread.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn start(config: &AudioConfig) -> Result<Self, AudioError> {
+        use std::{sync::mpsc, thread, time::Instant};
+
+        use crate::{
+            memory::{self, MemoryTag},
+            metrics,
+        };
+
+        // Mixed in blocks of this many frames, small enough to keep up with low latencies.
+        const BLOCK_FRAMES: usize = 256;
+
+        let (commands, mut command_queue) = queue::channel(COMMAND_QUEUE_SIZE);
+        let (event_producer, event_queue) = queue::channel(EVENT_QUEUE_SIZE);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (ready, opened) = mpsc::channel();
+        let voice_limits = (config.max_voices, config.max_virtual_voices);
+        let config = config.clone();
+        let stop = shutdown.clone();
+        let thread = thread::Builder::new()
+            .name("audio".to_owned())
+            .spawn(move || {
+                let _memory_tag = memory::scope(MemoryTag::Audio);
+                let device = match output::Device::select(config.device.as_deref()) {
+                    Ok(device) => device,
+                    Err(err) => {
+                        let _ = ready.send(Err(err));
+                        return;
+                    }
+                };
+                // The queue holds the latency worth of samples, the mixer never renders further
+                // ahead than that.
+                let (sample_rate, channels) = (device.sample_rate(), device.channels());
+                let capacity =
+                    (sample_rate as f64 * config.latency.as_secs_f64()) as usize * channels;
+                let (mut samples, device_samples) =
+                    queue::channel(capacity.max(BLOCK_FRAMES * channels));
+                let underruns = Arc::new(AtomicU64::new(0));
+                let stream = match device.play(device_samples, underruns.clone()) {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        let _ = ready.send(Err(err));
+                        return;
+                    }
+                };
+                let _ = ready.send(Ok((device.name.clone(), sample_rate, channels)));
+
+                let mut mixer = mixer::Mixer::new(sample_rate, channels, event_producer);
+                let mut block = vec![0.0; BLOCK_FRAMES * channels];
+                let mut reported_underruns = 0;
+                // Time spent rendering against the time rendered, above 1 the mixer can't keep up.
+                let (mut render_time, mut rendered) = (Duration::ZERO, 0);
+                let mut reported = Instant::now();
+                while !stop.load(Ordering::Acquire) {
+                    mixer.process_commands(&mut command_queue);
+                    while samples.free() >= block.len() {
+                        let start = Instant::now();
+                        mixer.render(&mut block);
+                        render_time += start.elapsed();
+                        rendered += BLOCK_FRAMES;
+                        for &sample in &block {
+                            let _ = samples.push(sample);
+                        }
+                    }
+                    if reported.elapsed() >= Duration::from_secs(1) && rendered > 0 {
+                        let played = rendered as f64 / sample_rate as f64;
+                        metrics::set_gauge("audio_mixer_load", render_time.as_secs_f64() / played);
+                        (render_time, rendered) = (Duration::ZERO, 0);
+                        reported = Instant::now();
+                    }
+                    let underruns = underruns.load(Ordering::Relaxed);
+                    metrics::increment("audio_underruns_total", underruns - reported_underruns);
+                    reported_underruns = underruns;
+                    let virtual_voices = mixer.virtual_voice_count();
+                    metrics::set_gauge(
+                        "audio_voices",
+                        (mixer.voice_count() - virtual_voices) as f64,
+                    );
+                    metrics::set_gauge("audio_virtual_voices", virtual_voices as f64);
+                    thread::sleep(Duration::from_millis(1));
+                }
+                info!("Audio mixer stopped");
+                drop(stream);
+            })
+            .map_err(|err| AudioError::Backend(err.to_string()))?;
+
+        let (device, sample_rate, channels) = match opened.recv() {
+            Ok(Ok(opened)) => opened,
+            Ok(Err(err)) => {
+                let _ = thread.join();
+                return Err(err);
+            }
+            Err(_) => {
+                let _ = thread.join();
+                return Err(AudioError::Backend("audio thread panicked".to_owned()));
+            }
+        };
+        info!(
+            "Opened audio device {} at {}Hz, {} channels",
+            device, sample_rate, channels
+        );
+        let mut audio = Self {
+            commands,
+            event_queue,
+            events: VecDeque::new(),
+            finished: Vec::new(),
+            next_voice: 0,
+            buses: Default::default(),
+            effects: Default::default(),
+            device,
+            sample_rate,
+            channels,
+            shutdown,
+            thread: Some(thread),
+        };
+        audio.set_voice_limits(voice_limits.0, voice_limits.1);
+        Ok(audio)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn start(_config: &AudioConfig) -> Result<Self, AudioError> {
+        Err(AudioError::Unsupported)
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn play(&mut self, clip: &AudioClip, params: VoiceParams) -> VoiceId {
+        let voice = VoiceId(self.next_voice);
+        self.next_voice += 1;
+        self.send(Command::Play {
+            voice,
+            clip: clip.clone(),
+            params,
+        });
+        voice
+    }
+
+    // Plays a long track, decoding it a little ahead on a thread of its own. Looping is up to the
+    // section, `params.looping` is ignored.
+    pub fn play_stream(
+        &mut self,
+        decoder: Box<dyn Decoder>,
+        section: Option<LoopSection>,
+        params: VoiceParams,
+    ) -> Result<VoiceId, AudioError> {
+        let stream = stream::spawn(decoder, section)?;
+        let voice = VoiceId(self.next_voice);
+        self.next_voice += 1;
+        self.send(Command::PlayStream {
+            voice,
+            stream,
+            params,
+        });
+        Ok(voice)
+    }
+
+    pub fn set_params(&mut self, voice: VoiceId, params: VoiceParams) {
+        self.send(Command::SetParams(voice, params));
+    }
+
+    pub fn stop(&mut self, voice: VoiceId) {
+        self.send(Command::Stop(voice));
+    }
+
+    // Fades the voice to `level` times its gain over `duration`.
+    pub fn fade_voice(&mut self, voice: VoiceId, level: f32, duration: Duration) {
+        self.send(Command::FadeVoice(voice, level, duration, false));
+    }
+
+    // Fades the voice out and stops it.
+    pub fn fade_out(&mut self, voice: VoiceId, duration: Duration) {
+        self.send(Command::FadeVoice(voice, 0.0, duration, true));
+    }
+
+    pub fn set_voice_limits(&mut self, max_voices: usize, max_virtual_voices: usize) {
+        self.send(Command::SetVoiceLimits(max_voices, max_virtual_voices));
+    }
+
+    pub fn bus(&self, bus: Bus) -> BusSettings {
+        self.buses[bus as usize]
+    }
+
+    pub fn set_bus(&mut self, bus: Bus, settings: BusSettings) {
+        self.buses[bus as usize] = settings;
+        self.send(Command::SetBus(bus, settings));
+    }
+
+    pub fn set_volume(&mut self, bus: Bus, volume: f32) {
+        let settings = BusSettings {
+            volume: volume.clamp(0.0, 1.0),
+            ..self.bus(bus)
+        };
+        self.set_bus(bus, settings);
+    }
+
+    pub fn set_muted(&mut self, bus: Bus, muted: bool) {
+        let settings = BusSettings {
+            muted,
+            ..self.bus(bus)
+        };
+        self.set_bus(bus, settings);
+    }
+
+    // Fades the bus to `level` times its volume over `duration`, independent of the volume
+    // setting. Fade back to 1 to undo.
+    pub fn fade(&mut self, bus: Bus, level: f32, duration: Duration) {
+        self.send(Command::FadeBus(bus, level, duration));
+    }
+
+    pub fn effect(&self, bus: Bus, slot: usize) -> Option<Effect> {
+        self.effects[bus as usize][slot]
+    }
+
+    // Puts an effect in one of the bus's `EFFECT_SLOTS`, or clears the slot with None.
+    pub fn set_effect(&mut self, bus: Bus, slot: usize, effect: Option<Effect>) {
+        self.automate_effect(bus, slot, effect, Duration::ZERO);
+    }
+
+    // Moves the parameters of the effect in the slot to `effect`'s over `duration`, e.g. closing a
+    // low pass as the player dives. Effects of another kind replace it right away.
+    pub fn automate_effect(
+        &mut self,
+        bus: Bus,
+        slot: usize,
+        effect: Option<Effect>,
+        duration: Duration,
+    ) {
+        assert!(slot < EFFECT_SLOTS, "Effect slot {} out of range", slot);
+        self.effects[bus as usize][slot] = effect;
+        self.send(Command::SetEffect(bus, slot, effect, duration));
+    }
+
+    // Collects what the mixer reported, called once per tick by `AudioPlugin`.
+    pub fn update(&mut self) {
+        self.finished.clear();
+        while let Some(event) = self.event_queue.pop() {
+            let AudioEvent::Finished(voice) = event;
+            self.finished.push(voice);
+            if self.events.len() == EVENT_QUEUE_SIZE {
+                self.events.pop_front();
+            }
+            self.events.push_back(event);
+        }
+    }
+
+    // Voices that finished during the last tick.
+    pub fn finished(&self) -> &[VoiceId] {
+        &self.finished
+    }
+
+    // Events since the last call, for the game's fixed_update.
+    pub fn poll_event(&mut self) -> Option<AudioEvent> {
+        self.events.pop_front()
+    }
+
+    fn send(&mut self, command: Command) {
+        if self.commands.push(command).is_err() {
+            warn!("Audio command queue full, dropping a command");
+        }
+    }
+}
+
+impl Drop for Audio {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            info!("Shutting down, joining audio thread!");
+            if thread.join().is_err() {
+                error!("Audio thread panicked");
+            }
+        }
+    }
+}
+
+// Opens the output device on build. Without one the game runs silent, there is no `Audio`
+// resource then.
+#[derive(Default)]
+pub struct AudioPlugin {
+    pub config: AudioConfig,
+}
+
+impl AudioPlugin {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_device(mut self, device: &str) -> Self {
+        self.config.device = Some(device.to_owned());
+        self
+    }
+}
+
+impl Plugin for AudioPlugin {
+    fn build(&self, engine: &mut Engine) {
+        match Audio::start(&self.config) {
+            Ok(audio) => {
+                engine.insert_resource(audio);
+            }
+            Err(err) => {
+                error!("Failed to start audio, running without sound: {}", err);
+                return;
+            }
+        }
+        engine.insert_resource(AudioScene::default());
+        engine.insert_resource(Music::default());
+        engine.insert_resource(SoundEvents::default());
+        let cvars = cvars::cvars(engine);
+        bus::register_cvars(cvars);
+        cvars.register(
+            "snd.max_voices",
+            self.config.max_voices as i64,
+            "voices mixed at once, the rest go virtual",
+        );
+        // Only cvar changes are applied, so volumes set through `Audio` stick until the cvar is
+        // changed again.
+        let mut applied: [Option<BusSettings>; Bus::ALL.len()] = Default::default();
+        let (mut max_voices, max_virtual_voices) =
+            (self.config.max_voices, self.config.max_virtual_voices);
+        engine.add_system(move |resources: &mut Resources| {
+            let settings = resources
+                .get::<Cvars>()
+                .map(|cvars| Bus::ALL.map(|bus| bus::from_cvars(cvars, bus)));
+            let voices = resources
+                .get::<Cvars>()
+                .and_then(|cvars| cvars.get_int("snd.max_voices"))
+                .map(|voices| voices.max(0) as usize);
+            let Some(audio) = resources.get_mut::<Audio>() else {
+                return;
+            };
+            audio.update();
+            if let Some(voices) = voices.filter(|&voices| voices != max_voices) {
+                max_voices = voices;
+                audio.set_voice_limits(max_voices, max_virtual_voices);
+            }
+            for bus in Bus::ALL {
+                let Some(settings) = settings.map(|settings| settings[bus as usize]) else {
+                    break;
+                };
+                if applied[bus as usize] != Some(settings) {
+                    applied[bus as usize] = Some(settings);
+                    audio.set_bus(bus, settings);
+                }
+            }
+            // Taken out while it drives the mixer, resources only lend one at a time.
+            if let Some(mut scene) = resources.remove::<AudioScene>() {
+                if let Some(audio) = resources.get_mut::<Audio>() {
+                    scene.update(audio);
+                }
+                // Plays against this tick's listener.
+                if let Some(mut events) = resources.remove::<SoundEvents>() {
+                    if let Some(audio) = resources.get_mut::<Audio>() {
+                        events.update(audio, &scene);
+                    }
+                    resources.insert(events);
+                }
+                resources.insert(scene);
+            }
+            if let Some(mut music) = resources.remove::<Music>() {
+                if let Some(audio) = resources.get_mut::<Audio>() {
+                    music.update(audio);
+                }
+                resources.insert(music);
+            }
+        });
+    }
+}
@@ -0,0 +1,173 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use super::{
+    stream::{ClipDecoder, Decoder, LoopSection},
+    Audio, AudioClip, AudioError, Bus, VoiceId, VoiceParams,
+};
+
+type Open = dyn Fn() -> Result<Box<dyn Decoder>, AudioError> + Send + Sync;
+
+// A piece of music, decoded from the start every time it plays.
+#[derive(Clone)]
+pub struct MusicTrack {
+    open: Arc<Open>,
+    pub section: Option<LoopSection>,
+    pub volume: f32,
+}
+
+impl MusicTrack {
+    pub fn new(
+        open: impl Fn() -> Result<Box<dyn Decoder>, AudioError> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            open: Arc::new(open),
+            section: None,
+            volume: 1.0,
+        }
+    }
+
+    // Streams an Ogg Vorbis file, opened again whenever the track starts.
+    #[cfg(feature = "ogg")]
+    pub fn ogg(path: impl Into<std::path::PathBuf>) -> Self {
+        let path = path.into();
+        Self::new(move || Ok(Box::new(super::OggDecoder::open(&path)?) as Box<dyn Decoder>))
+    }
+
+    pub fn clip(clip: AudioClip) -> Self {
+        Self::new(move || Ok(Box::new(ClipDecoder::new(clip.clone())) as Box<dyn Decoder>))
+    }
+
+    pub fn looping(mut self, section: LoopSection) -> Self {
+        self.section = Some(section);
+        self
+    }
+
+    pub fn with_volume(mut self, volume: f32) -> Self {
+        self.volume = volume;
+        self
+    }
+}
+
+struct Playing {
+    track: String,
+    voice: VoiceId,
+}
+
+enum Change {
+    Play(String, Duration),
+    Stop(Duration),
+}
+
+// Plays one track at a time on the music bus, crossfading between them. Games register their
+// tracks and which one each game state plays, then call `set_state` on every state transition.
+// Inserted as a resource by `AudioPlugin`.
+pub struct Music {
+    tracks: HashMap<String, MusicTrack>,
+    states: HashMap<String, String>,
+    state: Option<String>,
+    pub crossfade: Duration,
+    playing: Option<Playing>,
+    // Applied by the next `update`, only the latest change matters.
+    pending: Option<Change>,
+}
+
+impl Default for Music {
+    fn default() -> Self {
+        Self {
+            tracks: HashMap::new(),
+            states: HashMap::new(),
+            state: None,
+            crossfade: Duration::from_secs(2),
+            playing: None,
+            pending: None,
+        }
+    }
+}
+
+impl Music {
+    pub fn add_track(&mut self, name: &str, track: MusicTrack) {
+        self.tracks.insert(name.to_owned(), track);
+    }
+
+    // Plays `track` while the game is in `state`.
+    pub fn bind_state(&mut self, state: &str, track: &str) {
+        self.states.insert(state.to_owned(), track.to_owned());
+    }
+
+    pub fn state(&self) -> Option<&str> {
+        self.state.as_deref()
+    }
+
+    // Crossfades to the state's track. A state bound to the track that's already playing keeps
+    // it going, a state without one fades the music out.
+    pub fn set_state(&mut self, state: &str) {
+        self.state = Some(state.to_owned());
+        match self.states.get(state) {
+            Some(track) => self.pending = Some(Change::Play(track.clone(), self.crossfade)),
+            None => self.pending = Some(Change::Stop(self.crossfade)),
+        }
+    }
+
+    pub fn play(&mut self, track: &str, crossfade: Duration) {
+        self.pending = Some(Change::Play(track.to_owned(), crossfade));
+    }
+
+    pub fn stop(&mut self, fade: Duration) {
+        self.pending = Some(Change::Stop(fade));
+    }
+
+    // Name of the track playing, None once it ended or faded out.
+    pub fn playing(&self) -> Option<&str> {
+        self.playing.as_ref().map(|playing| playing.track.as_str())
+    }
+
+    // Applies the latest change, called once per tick by `AudioPlugin`.
+    pub fn update(&mut self, audio: &mut Audio) {
+        if self
+            .playing
+            .as_ref()
+            .is_some_and(|playing| audio.finished().contains(&playing.voice))
+        {
+            self.playing = None;
+        }
+        match self.pending.take() {
+            Some(Change::Play(name, crossfade)) => {
+                if self.playing() == Some(name.as_str()) {
+                    return;
+                }
+                let Some(track) = self.tracks.get(&name) else {
+                    warn!("Unknown music track {}", name);
+                    return;
+                };
+                let params = VoiceParams {
+                    gain: track.volume,
+                    bus: Bus::Music,
+                    // Never stolen for sound effects.
+                    priority: u8::MAX,
+                    ..Default::default()
+                };
+                let voice = match (track.open)()
+                    .and_then(|decoder| audio.play_stream(decoder, track.section, params))
+                {
+                    Ok(voice) => voice,
+                    Err(err) => {
+                        error!("Failed to play music track {}: {}", name, err);
+                        return;
+                    }
+                };
+                if let Some(previous) = self.playing.take() {
+                    audio.fade_out(previous.voice, crossfade);
+                }
+                audio.fade_voice(voice, 0.0, Duration::ZERO);
+                audio.fade_voice(voice, 1.0, crossfade);
+                self.playing = Some(Playing { track: name, voice });
+            }
+            Some(Change::Stop(fade)) => {
+                if let Some(previous) = self.playing.take() {
+                    audio.fade_out(previous.voice, fade);
+                }
+            }
+            None => {}
+        }
+    }
+}
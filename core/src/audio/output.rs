@@ -0,0 +1,122 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, SampleFormat, SizedSample,
+};
+
+use super::{queue::Consumer, AudioError};
+
+impl From<cpal::DevicesError> for AudioError {
+    fn from(err: cpal::DevicesError) -> Self {
+        AudioError::Backend(err.to_string())
+    }
+}
+
+impl From<cpal::DefaultStreamConfigError> for AudioError {
+    fn from(err: cpal::DefaultStreamConfigError) -> Self {
+        AudioError::Backend(err.to_string())
+    }
+}
+
+impl From<cpal::BuildStreamError> for AudioError {
+    fn from(err: cpal::BuildStreamError) -> Self {
+        AudioError::Backend(err.to_string())
+    }
+}
+
+impl From<cpal::PlayStreamError> for AudioError {
+    fn from(err: cpal::PlayStreamError) -> Self {
+        AudioError::Backend(err.to_string())
+    }
+}
+
+pub fn output_devices() -> Result<Vec<String>, AudioError> {
+    Ok(cpal::default_host()
+        .output_devices()?
+        .filter_map(|device| device.name().ok())
+        .collect())
+}
+
+// A device picked for output, with the format it will be opened in.
+pub struct Device {
+    device: cpal::Device,
+    supported: cpal::SupportedStreamConfig,
+    pub name: String,
+}
+
+impl Device {
+    // `name` or the default device when None.
+    pub fn select(name: Option<&str>) -> Result<Self, AudioError> {
+        let host = cpal::default_host();
+        let device = match name {
+            Some(name) => host
+                .output_devices()?
+                .find(|device| device.name().is_ok_and(|found| found == name))
+                .ok_or_else(|| AudioError::DeviceNotFound(name.to_owned()))?,
+            None => host.default_output_device().ok_or(AudioError::NoDevice)?,
+        };
+        Ok(Self {
+            supported: device.default_output_config()?,
+            name: device.name().unwrap_or_else(|_| "unknown".to_owned()),
+            device,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.supported.sample_rate().0
+    }
+
+    pub fn channels(&self) -> usize {
+        self.supported.channels() as usize
+    }
+
+    // Starts playing whatever the mixer pushes into `samples`. The device callback counts the
+    // buffers it couldn't fill completely in `underruns`. cpal streams can't move between threads
+    // on every platform, so this is called and dropped on the mixer thread.
+    pub fn play(
+        &self,
+        samples: Consumer<f32>,
+        underruns: Arc<AtomicU64>,
+    ) -> Result<cpal::Stream, AudioError> {
+        let config = self.supported.config();
+        let stream = match self.supported.sample_format() {
+            SampleFormat::F32 => build::<f32>(&self.device, &config, samples, underruns)?,
+            SampleFormat::I16 => build::<i16>(&self.device, &config, samples, underruns)?,
+            SampleFormat::U16 => build::<u16>(&self.device, &config, samples, underruns)?,
+            format => return Err(AudioError::UnsupportedFormat(format.to_string())),
+        };
+        stream.play()?;
+        Ok(stream)
+    }
+}
+
+fn build<T: SizedSample + FromSample<f32>>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut samples: Consumer<f32>,
+    underruns: Arc<AtomicU64>,
+) -> Result<cpal::Stream, AudioError> {
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut starved = false;
+            for out in data.iter_mut() {
+                let sample = samples.pop().unwrap_or_else(|| {
+                    starved = true;
+                    0.0
+                });
+                *out = T::from_sample(sample);
+            }
+            if starved {
+                underruns.fetch_add(1, Ordering::Relaxed);
+            }
+        },
+        |err| error!("Audio stream error: {}", err),
+        None,
+    )?;
+    Ok(stream)
+}
@@ -0,0 +1,124 @@
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+// Bounded single producer single consumer queue. Neither end ever locks or allocates, so the
+// consumer can be the audio device callback.
+struct Shared<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    // Total values popped and pushed, slots are indexed modulo the capacity.
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// Only the producer writes slots between head and tail, only the consumer reads them.
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        let head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        for index in head..tail {
+            unsafe {
+                self.slots[index % self.slots.len()]
+                    .get_mut()
+                    .assume_init_drop()
+            };
+        }
+    }
+}
+
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub fn channel<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity > 0, "Queue capacity must not be 0");
+    let shared = Arc::new(Shared {
+        slots: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            shared: shared.clone(),
+        },
+        Consumer { shared },
+    )
+}
+
+impl<T> Producer<T> {
+    // Hands the value back if the queue is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let shared = &*self.shared;
+        let tail = shared.tail.load(Ordering::Relaxed);
+        if tail - shared.head.load(Ordering::Acquire) == shared.slots.len() {
+            return Err(value);
+        }
+        unsafe { (*shared.slots[tail % shared.slots.len()].get()).write(value) };
+        shared.tail.store(tail + 1, Ordering::Release);
+        Ok(())
+    }
+
+    // Slots free right now, more may free up concurrently.
+    pub fn free(&self) -> usize {
+        let shared = &*self.shared;
+        shared.slots.len()
+            - (shared.tail.load(Ordering::Relaxed) - shared.head.load(Ordering::Acquire))
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        let shared = &*self.shared;
+        let head = shared.head.load(Ordering::Relaxed);
+        if head == shared.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = unsafe { (*shared.slots[head % shared.slots.len()].get()).assume_init_read() };
+        shared.head.store(head + 1, Ordering::Release);
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spsc_across_threads() {
+        let (mut producer, mut consumer) = channel(16);
+        let sender = std::thread::spawn(move || {
+            for value in 0..10_000u32 {
+                let mut value = value;
+                while let Err(back) = producer.push(value) {
+                    value = back;
+                    std::thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        while expected < 10_000 {
+            match consumer.pop() {
+                Some(value) => {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                None => std::thread::yield_now(),
+            }
+        }
+        sender.join().unwrap();
+        assert!(consumer.pop().is_none());
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use super::{
+    spatial::{self, Attenuation, AudioTransform, SpatialConfig},
+    Audio, AudioClip, Bus, VoiceId, VoiceParams,
+};
+use crate::identifier::SnowflakeId;
+
+// Sound an entity makes. Keep it in the `AudioScene` while the entity exists, the scene starts,
+// updates and stops its voice to match.
+#[derive(Clone, Debug)]
+pub struct AudioSource {
+    pub clip: AudioClip,
+    pub looping: bool,
+    pub gain: f32,
+    pub pitch: f32,
+    pub bus: Bus,
+    // Positional when set, the source is then panned, attenuated and doppler shifted relative to
+    // the listener. Without it the clip plays as is, for music and UI sounds.
+    pub attenuation: Option<Attenuation>,
+    // See `VoiceParams::priority`.
+    pub priority: u8,
+    // Set to false to stop, or true again to restart from the beginning. Cleared when a clip that
+    // doesn't loop played to the end.
+    pub playing: bool,
+}
+
+impl AudioSource {
+    pub fn new(clip: AudioClip) -> Self {
+        Self {
+            clip,
+            looping: false,
+            gain: 1.0,
+            pitch: 1.0,
+            bus: Bus::Sfx,
+            attenuation: None,
+            priority: VoiceParams::default().priority,
+            playing: true,
+        }
+    }
+
+    pub fn spatial(clip: AudioClip, attenuation: Attenuation) -> Self {
+        Self {
+            attenuation: Some(attenuation),
+            ..Self::new(clip)
+        }
+    }
+
+    fn params(&self, listener_gain: f32) -> VoiceParams {
+        VoiceParams {
+            gain: self.gain * listener_gain,
+            pitch: self.pitch,
+            looping: self.looping,
+            bus: self.bus,
+            priority: self.priority,
+            ..Default::default()
+        }
+    }
+}
+
+// The entity that hears the scene, usually the camera or the player.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioListener {
+    pub entity: SnowflakeId,
+    pub gain: f32,
+}
+
+impl AudioListener {
+    pub fn new(entity: SnowflakeId) -> Self {
+        Self { entity, gain: 1.0 }
+    }
+}
+
+struct PlayingVoice {
+    voice: VoiceId,
+    clip: AudioClip,
+    params: VoiceParams,
+}
+
+// Audio components by entity. There is no ECS to query yet, so entities keep their sources and
+// transforms here and `AudioPlugin` syncs the mixer with them every tick.
+#[derive(Default)]
+pub struct AudioScene {
+    sources: HashMap<SnowflakeId, AudioSource>,
+    transforms: HashMap<SnowflakeId, AudioTransform>,
+    listener: Option<AudioListener>,
+    spatial: SpatialConfig,
+    voices: HashMap<SnowflakeId, PlayingVoice>,
+}
+
+impl AudioScene {
+    pub fn insert(&mut self, entity: SnowflakeId, source: AudioSource) {
+        self.sources.insert(entity, source);
+    }
+
+    pub fn get(&self, entity: SnowflakeId) -> Option<&AudioSource> {
+        self.sources.get(&entity)
+    }
+
+    pub fn get_mut(&mut self, entity: SnowflakeId) -> Option<&mut AudioSource> {
+        self.sources.get_mut(&entity)
+    }
+
+    // Stops the entity's sound, call when it despawns.
+    pub fn remove(&mut self, entity: SnowflakeId) -> Option<AudioSource> {
+        self.transforms.remove(&entity);
+        self.sources.remove(&entity)
+    }
+
+    // Where a source or the listener is, set it every tick for entities that move.
+    pub fn set_transform(&mut self, entity: SnowflakeId, transform: AudioTransform) {
+        self.transforms.insert(entity, transform);
+    }
+
+    pub fn transform(&self, entity: SnowflakeId) -> Option<&AudioTransform> {
+        self.transforms.get(&entity)
+    }
+
+    pub fn spatial_config(&self) -> &SpatialConfig {
+        &self.spatial
+    }
+
+    pub fn set_spatial_config(&mut self, config: SpatialConfig) {
+        self.spatial = config;
+    }
+
+    pub fn listener(&self) -> Option<&AudioListener> {
+        self.listener.as_ref()
+    }
+
+    pub fn set_listener(&mut self, listener: Option<AudioListener>) {
+        self.listener = listener;
+    }
+
+    // Starts and stops voices for sources that changed, and passes on gain and pitch changes along
+    // with where positional sources are relative to the listener.
+    pub fn update(&mut self, audio: &mut Audio) {
+        let listener_gain = self.listener.map_or(1.0, |listener| listener.gain);
+        self.voices.retain(|entity, playing| {
+            let finished = audio.finished().contains(&playing.voice);
+            match self.sources.get_mut(entity) {
+                Some(source) if finished => {
+                    source.playing = false;
+                    false
+                }
+                // A different clip or bus needs a new voice.
+                Some(source)
+                    if source.playing
+                        && source.clip.same_samples(&playing.clip)
+                        && source.bus == playing.params.bus =>
+                {
+                    true
+                }
+                _ => {
+                    if !finished {
+                        audio.stop(playing.voice);
+                    }
+                    false
+                }
+            }
+        });
+        for (&entity, source) in self.sources.iter().filter(|(_, source)| source.playing) {
+            let mut params = source.params(listener_gain);
+            if let Some(attenuation) = &source.attenuation {
+                self.spatialize(&mut params, &self.transform_or_default(entity), attenuation);
+            }
+            match self.voices.get_mut(&entity) {
+                Some(playing) if playing.params != params => {
+                    audio.set_params(playing.voice, params);
+                    playing.params = params;
+                }
+                Some(_) => {}
+                None => {
+                    let voice = audio.play(&source.clip, params);
+                    self.voices.insert(
+                        entity,
+                        PlayingVoice {
+                            voice,
+                            clip: source.clip.clone(),
+                            params,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    // Pans, attenuates and doppler shifts a sound at `transform` relative to the listener. Without
+    // a listener `params` are left as they are.
+    pub fn spatialize(
+        &self,
+        params: &mut VoiceParams,
+        transform: &AudioTransform,
+        attenuation: &Attenuation,
+    ) {
+        let Some(listener) = self.listener else {
+            return;
+        };
+        let spatialized = spatial::spatialize(
+            transform,
+            &self.transform_or_default(listener.entity),
+            attenuation,
+            &self.spatial,
+        );
+        params.gain *= spatialized.gain;
+        params.pitch *= spatialized.pitch;
+        params.pan = Some(spatialized.pan);
+        params.brightness = spatialized.brightness;
+    }
+
+    fn transform_or_default(&self, entity: SnowflakeId) -> AudioTransform {
+        self.transforms.get(&entity).copied().unwrap_or_default()
+    }
+}
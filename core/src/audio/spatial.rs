@@ -0,0 +1,217 @@
+// Spatialization of sources relative to the listener, computed on the sim side each tick. The
+// mixer only gets a gain, a pan, a brightness and a pitch factor per voice.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttenuationCurve {
+    // Physically plausible, loud close up with a long tail.
+    Inverse,
+    Linear,
+    Exponential,
+}
+
+// How a source gets quieter with distance. Full volume up to `min_distance`, and no quieter than
+// at `max_distance` beyond it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attenuation {
+    pub curve: AttenuationCurve,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    pub rolloff: f32,
+}
+
+impl Default for Attenuation {
+    fn default() -> Self {
+        Self {
+            curve: AttenuationCurve::Inverse,
+            min_distance: 1.0,
+            max_distance: 100.0,
+            rolloff: 1.0,
+        }
+    }
+}
+
+impl Attenuation {
+    pub fn gain(&self, distance: f32) -> f32 {
+        let min = self.min_distance.max(0.001);
+        let max = self.max_distance.max(min);
+        let distance = distance.clamp(min, max);
+        let gain = match self.curve {
+            AttenuationCurve::Inverse => min / (min + self.rolloff * (distance - min)),
+            AttenuationCurve::Linear if max > min => {
+                1.0 - self.rolloff * (distance - min) / (max - min)
+            }
+            AttenuationCurve::Linear => 1.0,
+            AttenuationCurve::Exponential => (distance / min).powf(-self.rolloff),
+        };
+        gain.clamp(0.0, 1.0)
+    }
+}
+
+// Where an entity is for audio, in world units (meters for the doppler effect to sound right).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioTransform {
+    pub position: [f32; 3],
+    pub velocity: [f32; 3],
+    // Only used for the listener.
+    pub forward: [f32; 3],
+    pub up: [f32; 3],
+}
+
+impl Default for AudioTransform {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 3],
+            velocity: [0.0; 3],
+            forward: [0.0, 0.0, -1.0],
+            up: [0.0, 1.0, 0.0],
+        }
+    }
+}
+
+impl AudioTransform {
+    pub fn at(position: [f32; 3]) -> Self {
+        Self {
+            position,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpatialConfig {
+    pub speed_of_sound: f32,
+    // Scales the doppler effect, 0 turns it off.
+    pub doppler_factor: f32,
+    // Brightness of a source straight behind the listener, a cheap stand-in for the head
+    // shadowing that makes rear sounds duller.
+    pub rear_brightness: f32,
+}
+
+impl Default for SpatialConfig {
+    fn default() -> Self {
+        Self {
+            speed_of_sound: 343.0,
+            doppler_factor: 1.0,
+            rear_brightness: 0.4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spatialized {
+    pub gain: f32,
+    // -1 is hard left, 1 hard right.
+    pub pan: f32,
+    // 1 is unfiltered, lower is duller.
+    pub brightness: f32,
+    // Doppler shift, multiplies the source's pitch.
+    pub pitch: f32,
+}
+
+pub fn spatialize(
+    source: &AudioTransform,
+    listener: &AudioTransform,
+    attenuation: &Attenuation,
+    config: &SpatialConfig,
+) -> Spatialized {
+    let offset = sub(source.position, listener.position);
+    let distance = length(offset);
+    let gain = attenuation.gain(distance);
+    if distance < 1e-4 {
+        return Spatialized {
+            gain,
+            pan: 0.0,
+            brightness: 1.0,
+            pitch: 1.0,
+        };
+    }
+    let direction = scale(offset, 1.0 / distance);
+    let forward = normalize(listener.forward);
+    let right = normalize(cross(forward, listener.up));
+    let pan = dot(direction, right).clamp(-1.0, 1.0);
+    let behind = (-dot(direction, forward)).max(0.0);
+    let brightness = 1.0 - behind * (1.0 - config.rear_brightness.clamp(0.0, 1.0));
+
+    // Velocities along the line from the source to the listener.
+    let c = config.speed_of_sound.max(1.0);
+    let source_speed = (-dot(source.velocity, direction) * config.doppler_factor).min(c * 0.5);
+    let listener_speed = (-dot(listener.velocity, direction) * config.doppler_factor).max(-c * 0.5);
+    let pitch = ((c - listener_speed) / (c - source_speed)).clamp(0.5, 2.0);
+    Spatialized {
+        gain,
+        pan,
+        brightness,
+        pitch,
+    }
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: [f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn length(a: [f32; 3]) -> f32 {
+    dot(a, a).sqrt()
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let length = length(a);
+    if length > 0.0 {
+        scale(a, 1.0 / length)
+    } else {
+        a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pans_attenuates_and_shifts() {
+        let listener = AudioTransform::default();
+        let config = SpatialConfig::default();
+        let attenuation = Attenuation::default();
+
+        // Two meters to the right, at rest.
+        let right = spatialize(
+            &AudioTransform::at([2.0, 0.0, 0.0]),
+            &listener,
+            &attenuation,
+            &config,
+        );
+        assert_eq!(right.pan, 1.0);
+        assert_eq!(right.gain, 0.5);
+        assert_eq!(right.pitch, 1.0);
+        assert_eq!(right.brightness, 1.0);
+
+        // Behind, coming closer.
+        let behind = spatialize(
+            &AudioTransform {
+                velocity: [0.0, 0.0, -34.3],
+                ..AudioTransform::at([0.0, 0.0, 10.0])
+            },
+            &listener,
+            &attenuation,
+            &config,
+        );
+        assert!(behind.pan.abs() < 1e-6);
+        assert!((behind.brightness - config.rear_brightness).abs() < 1e-6);
+        assert!((behind.pitch - 1.0 / 0.9).abs() < 1e-4);
+    }
+}
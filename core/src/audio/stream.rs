@@ -0,0 +1,411 @@
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use super::{
+    queue::{self, Consumer, Producer},
+    AudioClip, AudioError,
+};
+
+// Decoded blocks buffered ahead of the mixer, about a second and a half of Vorbis packets.
+const STREAM_QUEUE_SIZE: usize = 64;
+// Frames per block the in-memory decoder hands out.
+const CLIP_BLOCK_FRAMES: usize = 2048;
+
+// A source of samples decoded a little at a time, so long tracks never sit in memory whole.
+pub trait Decoder: Send {
+    fn channels(&self) -> u16;
+    fn sample_rate(&self) -> u32;
+    // Appends the next interleaved samples to `out`, returns false without appending anything at
+    // the end of the track.
+    fn decode(&mut self, out: &mut Vec<f32>) -> Result<bool, AudioError>;
+    // Goes back to the first frame.
+    fn rewind(&mut self) -> Result<(), AudioError>;
+}
+
+// Streams a clip that's already in memory, for short tracks and tests.
+pub struct ClipDecoder {
+    clip: AudioClip,
+    frame: usize,
+}
+
+impl ClipDecoder {
+    pub fn new(clip: AudioClip) -> Self {
+        Self { clip, frame: 0 }
+    }
+}
+
+impl Decoder for ClipDecoder {
+    fn channels(&self) -> u16 {
+        self.clip.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.clip.sample_rate()
+    }
+
+    fn decode(&mut self, out: &mut Vec<f32>) -> Result<bool, AudioError> {
+        let end = (self.frame + CLIP_BLOCK_FRAMES).min(self.clip.frames());
+        if self.frame == end {
+            return Ok(false);
+        }
+        let channels = self.clip.channels() as usize;
+        for frame in self.frame..end {
+            out.extend((0..channels).map(|channel| self.clip.sample(frame, channel)));
+        }
+        self.frame = end;
+        Ok(true)
+    }
+
+    fn rewind(&mut self) -> Result<(), AudioError> {
+        self.frame = 0;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "ogg")]
+pub use ogg::OggDecoder;
+
+#[cfg(feature = "ogg")]
+mod ogg {
+    use std::{
+        fs::File,
+        io::{BufReader, Read, Seek, SeekFrom},
+        path::Path,
+    };
+
+    use lewton::inside_ogg::OggStreamReader;
+
+    use super::Decoder;
+    use crate::audio::AudioError;
+
+    // Ogg Vorbis, decoded one packet at a time.
+    pub struct OggDecoder<R: Read + Seek> {
+        // Only None while rewinding.
+        reader: Option<OggStreamReader<R>>,
+    }
+
+    impl OggDecoder<BufReader<File>> {
+        pub fn open(path: impl AsRef<Path>) -> Result<Self, AudioError> {
+            let path = path.as_ref();
+            let file = File::open(path)
+                .map_err(|err| AudioError::Decode(format!("{}: {}", path.display(), err)))?;
+            Self::new(BufReader::new(file))
+        }
+    }
+
+    impl<R: Read + Seek> OggDecoder<R> {
+        pub fn new(reader: R) -> Result<Self, AudioError> {
+            let reader = OggStreamReader::new(reader).map_err(decode_error)?;
+            Ok(Self {
+                reader: Some(reader),
+            })
+        }
+
+        fn reader(&mut self) -> &mut OggStreamReader<R> {
+            self.reader
+                .as_mut()
+                .expect("Ogg reader lost while rewinding")
+        }
+    }
+
+    impl<R: Read + Seek + Send> Decoder for OggDecoder<R> {
+        fn channels(&self) -> u16 {
+            self.reader
+                .as_ref()
+                .map_or(0, |reader| reader.ident_hdr.audio_channels as u16)
+        }
+
+        fn sample_rate(&self) -> u32 {
+            self.reader
+                .as_ref()
+                .map_or(0, |reader| reader.ident_hdr.audio_sample_rate)
+        }
+
+        fn decode(&mut self, out: &mut Vec<f32>) -> Result<bool, AudioError> {
+            // Packets can decode to nothing, e.g. the first one.
+            loop {
+                match self.reader().read_dec_packet_itl().map_err(decode_error)? {
+                    Some(samples) if samples.is_empty() => continue,
+                    Some(samples) => {
+                        out.extend(samples.iter().map(|&sample| sample as f32 / 32768.0));
+                        return Ok(true);
+                    }
+                    None => return Ok(false),
+                }
+            }
+        }
+
+        // Vorbis only seeks to page boundaries, reopening is the exact way back to the start.
+        fn rewind(&mut self) -> Result<(), AudioError> {
+            let reader = self.reader.take().expect("Ogg reader lost while rewinding");
+            let mut inner = reader.into_inner().into_inner();
+            inner
+                .seek(SeekFrom::Start(0))
+                .map_err(|err| AudioError::Decode(err.to_string()))?;
+            self.reader = Some(OggStreamReader::new(inner).map_err(decode_error)?);
+            Ok(())
+        }
+    }
+
+    fn decode_error(err: lewton::VorbisError) -> AudioError {
+        AudioError::Decode(err.to_string())
+    }
+}
+
+// Loops a stretch of the track, in frames. Playback runs from the start of the track into the
+// section, so an intro plays once, then repeats from `start` until stopped. No `end` loops at the
+// end of the track.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct LoopSection {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl LoopSection {
+    pub fn whole_track() -> Self {
+        Self::default()
+    }
+
+    pub fn from_seconds(start: f64, end: Option<f64>, sample_rate: u32) -> Self {
+        let frames = |seconds: f64| (seconds * sample_rate as f64).round() as u64;
+        Self {
+            start: frames(start),
+            end: end.map(frames),
+        }
+    }
+}
+
+struct Shared {
+    // Set once the last block was pushed.
+    ended: AtomicBool,
+    // Set when the mixer let go of the stream.
+    closed: AtomicBool,
+}
+
+// Decodes on a thread of its own, keeping the block queue full.
+struct Streamer {
+    decoder: Box<dyn Decoder>,
+    channels: usize,
+    section: Option<LoopSection>,
+    // Frame of the track the next decoded sample belongs to.
+    position: u64,
+    // Decoded past the loop start when rewinding, plays next.
+    carry: Vec<f32>,
+}
+
+impl Streamer {
+    // The next block of samples with the loop section applied, None at the end of the track.
+    fn next_block(&mut self) -> Result<Option<Vec<f32>>, AudioError> {
+        loop {
+            let mut block = mem::take(&mut self.carry);
+            let ended = block.is_empty() && !self.decoder.decode(&mut block)?;
+            let frames = (block.len() / self.channels) as u64;
+            let loop_end = self
+                .section
+                .and_then(|section| section.end)
+                .filter(|&end| self.position + frames >= end);
+            if let Some(end) = loop_end {
+                block.truncate(end.saturating_sub(self.position) as usize * self.channels);
+            }
+            let from = self.position;
+            self.position += (block.len() / self.channels) as u64;
+            match self.section {
+                // Nothing played since the last restart, the section is empty.
+                Some(section) if ended && from == section.start => return Ok(None),
+                Some(section) if ended || loop_end.is_some() => self.restart(section.start)?,
+                None if ended => return Ok(None),
+                _ => {}
+            }
+            if !block.is_empty() {
+                return Ok(Some(block));
+            }
+        }
+    }
+
+    fn restart(&mut self, start: u64) -> Result<(), AudioError> {
+        self.decoder.rewind()?;
+        self.position = 0;
+        self.carry.clear();
+        let mut block = Vec::new();
+        while self.position < start {
+            block.clear();
+            if !self.decoder.decode(&mut block)? {
+                return Err(AudioError::Decode(
+                    "loop starts past the end of the track".to_owned(),
+                ));
+            }
+            let frames = (block.len() / self.channels) as u64;
+            if self.position + frames > start {
+                self.carry = block.split_off((start - self.position) as usize * self.channels);
+                self.position = start;
+            } else {
+                self.position += frames;
+            }
+        }
+        Ok(())
+    }
+
+    fn run(mut self, mut blocks: Producer<Vec<f32>>, shared: Arc<Shared>) {
+        let mut pending = None;
+        while !shared.closed.load(Ordering::Acquire) {
+            let block = match pending.take() {
+                Some(block) => block,
+                None => match self.next_block() {
+                    Ok(Some(block)) => block,
+                    Ok(None) => break,
+                    Err(err) => {
+                        error!("Failed to decode audio stream: {}", err);
+                        break;
+                    }
+                },
+            };
+            if let Err(block) = blocks.push(block) {
+                pending = Some(block);
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+        shared.ended.store(true, Ordering::Release);
+    }
+}
+
+pub(super) enum StreamFrame {
+    Ready,
+    // Decoding fell behind, nothing to play right now.
+    Underrun,
+    Ended,
+}
+
+// The mixer's end of a stream.
+pub(super) struct StreamReader {
+    blocks: Consumer<Vec<f32>>,
+    block: Vec<f32>,
+    offset: usize,
+    channels: usize,
+    sample_rate: u32,
+    shared: Arc<Shared>,
+}
+
+impl StreamReader {
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Copies the next frame into `out`, which holds one sample per channel.
+    pub fn next_frame(&mut self, out: &mut [f32]) -> StreamFrame {
+        if self.offset >= self.block.len() {
+            // Checked before popping, the last block is pushed before the flag is set.
+            let ended = self.shared.ended.load(Ordering::Acquire);
+            match self.blocks.pop() {
+                Some(block) => {
+                    self.block = block;
+                    self.offset = 0;
+                }
+                None if ended => return StreamFrame::Ended,
+                None => return StreamFrame::Underrun,
+            }
+        }
+        out.copy_from_slice(&self.block[self.offset..self.offset + self.channels]);
+        self.offset += self.channels;
+        StreamFrame::Ready
+    }
+}
+
+impl Drop for StreamReader {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+    }
+}
+
+// Starts decoding on a new thread, which stops when the track ends or the reader is dropped.
+pub(super) fn spawn(
+    decoder: Box<dyn Decoder>,
+    section: Option<LoopSection>,
+) -> Result<StreamReader, AudioError> {
+    let channels = decoder.channels() as usize;
+    let sample_rate = decoder.sample_rate();
+    if channels == 0 || sample_rate == 0 {
+        return Err(AudioError::Decode(format!(
+            "invalid stream format, {} channels at {}Hz",
+            channels, sample_rate
+        )));
+    }
+    if let Some(LoopSection {
+        start,
+        end: Some(end),
+    }) = section
+    {
+        if end <= start {
+            return Err(AudioError::Decode(format!(
+                "loop section ends at frame {} before it starts at {}",
+                end, start
+            )));
+        }
+    }
+    let (producer, blocks) = queue::channel(STREAM_QUEUE_SIZE);
+    let shared = Arc::new(Shared {
+        ended: AtomicBool::new(false),
+        closed: AtomicBool::new(false),
+    });
+    let streamer = Streamer {
+        decoder,
+        channels,
+        section,
+        position: 0,
+        carry: Vec::new(),
+    };
+    let thread_shared = shared.clone();
+    thread::Builder::new()
+        .name("audio-stream".to_owned())
+        .spawn(move || streamer.run(producer, thread_shared))
+        .map_err(|err| AudioError::Backend(err.to_string()))?;
+    Ok(StreamReader {
+        blocks,
+        block: Vec::new(),
+        offset: 0,
+        channels,
+        sample_rate,
+        shared,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plays_intro_then_loops_section() {
+        let clip = AudioClip::from_samples((0..10).map(|i| i as f32).collect::<Vec<_>>(), 1, 10);
+        let mut reader = spawn(
+            Box::new(ClipDecoder::new(clip)),
+            Some(LoopSection {
+                start: 6,
+                end: Some(8),
+            }),
+        )
+        .unwrap();
+        let mut played = Vec::new();
+        let mut frame = [0.0];
+        while played.len() < 12 {
+            match reader.next_frame(&mut frame) {
+                StreamFrame::Ready => played.push(frame[0]),
+                StreamFrame::Underrun => thread::yield_now(),
+                StreamFrame::Ended => panic!("looping stream ended"),
+            }
+        }
+        assert_eq!(
+            played,
+            [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 6.0, 7.0, 6.0, 7.0]
+        );
+    }
+}
@@ -0,0 +1,240 @@
+//! Orthographic camera for 2D games. World units map to pixels at a fixed rate, and the pixel
+//! perfect mode renders at a low internal resolution, scaled up to the window by a whole number
+//! and letterboxed, so pixel art stays crisp at any window size.
+//!
+//! The renderer doesn't draw sprites or into offscreen targets yet. The camera works out the
+//! matrices, the internal target's placement in the window and cursor mapping, and hands picking
+//! and the editor a camera that already includes the letterbox.
+
+use crate::{
+    editor::{Editor, EditorCamera},
+    engine::{Engine, Plugin, Resources},
+    picking::Picking,
+    sim::Viewport,
+};
+
+// Where the internal target lands in the window, in window pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Presentation {
+    pub offset: [f32; 2],
+    pub size: [f32; 2],
+    // Window pixels per target pixel.
+    pub scale: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera2d {
+    // The world point at the center of the screen.
+    pub position: [f32; 2],
+    // World units one pixel of the target covers, 1/16 for 16 pixel tiles one unit wide.
+    pub units_per_pixel: f32,
+    // The internal resolution in pixel perfect mode.
+    pub pixel_perfect: Option<[u32; 2]>,
+    window: [f32; 2],
+}
+
+impl Default for Camera2d {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 2],
+            units_per_pixel: 1.0,
+            pixel_perfect: None,
+            window: Viewport::default().size,
+        }
+    }
+}
+
+impl Camera2d {
+    pub fn pixel_perfect(resolution: [u32; 2], units_per_pixel: f32) -> Self {
+        Self {
+            units_per_pixel,
+            pixel_perfect: Some(resolution),
+            ..Self::default()
+        }
+    }
+
+    pub fn set_window_size(&mut self, size: [f32; 2]) {
+        self.window = size.map(|side| side.max(1.0));
+    }
+
+    pub fn window_size(&self) -> [f32; 2] {
+        self.window
+    }
+
+    // Size of what's rendered, the internal resolution or the whole window.
+    pub fn target_size(&self) -> [f32; 2] {
+        match self.pixel_perfect {
+            Some(resolution) => resolution.map(|side| side.max(1) as f32),
+            None => self.window,
+        }
+    }
+
+    pub fn presentation(&self) -> Presentation {
+        let target = self.target_size();
+        if self.pixel_perfect.is_none() {
+            return Presentation {
+                offset: [0.0; 2],
+                size: target,
+                scale: 1.0,
+            };
+        }
+        // Whole multiples only, a window smaller than the target still shows it at 1x, cropped.
+        let fit = (self.window[0] / target[0]).min(self.window[1] / target[1]);
+        let scale = fit.floor().max(1.0);
+        let size = target.map(|side| side * scale);
+        Presentation {
+            offset: std::array::from_fn(|axis| ((self.window[axis] - size[axis]) * 0.5).floor()),
+            size,
+            scale,
+        }
+    }
+
+    // The position the view is built from, on the pixel grid in pixel perfect mode so the whole
+    // scene moves in steps of one target pixel instead of shimmering.
+    pub fn view_position(&self) -> [f32; 2] {
+        match self.pixel_perfect {
+            Some(_) => self.snap(self.position),
+            None => self.position,
+        }
+    }
+
+    // Rounds a world position to the nearest target pixel, for sprite positions.
+    pub fn snap(&self, point: [f32; 2]) -> [f32; 2] {
+        let unit = self.units_per_pixel;
+        if unit <= 0.0 {
+            return point;
+        }
+        point.map(|coordinate| (coordinate / unit).round() * unit)
+    }
+
+    // Column major, world to the target's clip space. Z from -1 to 1 maps to depth 1 to 0, so
+    // larger z draws in front.
+    pub fn view_projection(&self) -> [[f32; 4]; 4] {
+        let half = self
+            .target_size()
+            .map(|side| side * 0.5 * self.units_per_pixel);
+        let [x, y] = self.view_position();
+        [
+            [1.0 / half[0], 0.0, 0.0, 0.0],
+            [0.0, 1.0 / half[1], 0.0, 0.0],
+            [0.0, 0.0, -0.5, 0.0],
+            [-x / half[0], -y / half[1], 0.5, 1.0],
+        ]
+    }
+
+    // The view projection as seen through the letterbox, for anything that works in window
+    // pixels like picking and the editor's gizmos.
+    pub fn editor_camera(&self) -> EditorCamera {
+        let presentation = self.presentation();
+        // Target clip space to window clip space.
+        let scale: [f32; 2] =
+            std::array::from_fn(|axis| presentation.size[axis] / self.window[axis]);
+        let center: [f32; 2] = std::array::from_fn(|axis| {
+            (presentation.offset[axis] + presentation.size[axis] * 0.5) / self.window[axis] * 2.0
+                - 1.0
+        });
+        let shift = [center[0], -center[1]];
+        let mut matrix = self.view_projection();
+        for column in &mut matrix {
+            for axis in 0..2 {
+                column[axis] = column[axis] * scale[axis] + column[3] * shift[axis];
+            }
+        }
+        EditorCamera {
+            view_projection: matrix,
+            viewport: self.window,
+        }
+    }
+
+    pub fn screen_to_world(&self, cursor: [f32; 2]) -> [f32; 2] {
+        let presentation = self.presentation();
+        let target = self.target_size();
+        let [x, y] = self.view_position();
+        let pixel: [f32; 2] = std::array::from_fn(|axis| {
+            (cursor[axis] - presentation.offset[axis]) / presentation.scale - target[axis] * 0.5
+        });
+        [
+            x + pixel[0] * self.units_per_pixel,
+            y - pixel[1] * self.units_per_pixel,
+        ]
+    }
+
+    pub fn world_to_screen(&self, point: [f32; 2]) -> [f32; 2] {
+        let presentation = self.presentation();
+        let target = self.target_size();
+        let [x, y] = self.view_position();
+        let pixel = [
+            (point[0] - x) / self.units_per_pixel,
+            (y - point[1]) / self.units_per_pixel,
+        ];
+        std::array::from_fn(|axis| {
+            (pixel[axis] + target[axis] * 0.5) * presentation.scale + presentation.offset[axis]
+        })
+    }
+}
+
+pub struct Camera2dPlugin;
+
+impl Plugin for Camera2dPlugin {
+    fn build(&self, engine: &mut Engine) {
+        let resources = engine.resources_mut();
+        if !resources.contains::<Camera2d>() {
+            resources.insert(Camera2d::default());
+        }
+        engine.add_system(|resources: &mut Resources| {
+            let viewport = resources.get::<Viewport>().copied().unwrap_or_default();
+            let Some(camera) = resources.get_mut::<Camera2d>() else {
+                return;
+            };
+            camera.set_window_size(viewport.size);
+            let editor_camera = camera.editor_camera();
+            if let Some(picking) = resources.get_mut::<Picking>() {
+                picking.set_camera(editor_camera);
+            }
+            if let Some(editor) = resources.get_mut::<Editor>() {
+                editor.set_camera(editor_camera);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f32; 2], b: [f32; 2]) -> bool {
+        (0..2).all(|axis| (a[axis] - b[axis]).abs() < 1e-3)
+    }
+
+    #[test]
+    fn scales_pixel_art_by_whole_numbers() {
+        let mut camera = Camera2d::pixel_perfect([320, 180], 1.0 / 16.0);
+        camera.set_window_size([1300.0, 740.0]);
+        assert_eq!(
+            camera.presentation(),
+            Presentation {
+                offset: [10.0, 10.0],
+                size: [1280.0, 720.0],
+                scale: 4.0,
+            }
+        );
+
+        camera.position = [10.01, 5.0];
+        assert_eq!(camera.view_position(), [10.0, 5.0]);
+        assert_eq!(camera.snap([1.03, -0.45]), [1.0, -0.4375]);
+        assert!(close(camera.screen_to_world([650.0, 370.0]), [10.0, 5.0]));
+        // One target pixel right and up is four window pixels.
+        assert!(close(
+            camera.screen_to_world([654.0, 366.0]),
+            [10.0625, 5.0625]
+        ));
+        assert!(close(camera.world_to_screen([11.0, 5.0]), [714.0, 370.0]));
+        // The letterboxed camera projects to the same window pixels.
+        let projected = camera.editor_camera().project([11.0, 5.0, 0.0]).unwrap();
+        assert!(close(projected, [714.0, 370.0]));
+
+        // Too small a window still shows the target at 1x.
+        camera.set_window_size([300.0, 200.0]);
+        assert_eq!(camera.presentation().scale, 1.0);
+    }
+}
@@ -0,0 +1,335 @@
+//! Color grading with 3D lookup tables. A LUT maps every input color to a graded one, so palette
+//! and mood changes are authored in an image tool and exported as `.cube` files instead of
+//! written as shader code. `ColorGrading` holds the LUT in use and blends to the next one over
+//! time, e.g. when the player walks into a cave.
+//!
+//! The renderer doesn't have a post-processing stack yet. The grading pass it will get samples
+//! `from` and `to` with the blend factor, `apply` does the same on the CPU and is what it has to
+//! match.
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use crate::{
+    cvars,
+    engine::{Engine, Plugin, Resources},
+    sim::Time,
+    vfs::Vfs,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut {
+    pub title: Option<String>,
+    size: usize,
+    // Input colors outside these are clamped.
+    domain: [[f32; 3]; 2],
+    // size³ entries, red changing fastest then green then blue, the layout of the 3D texture.
+    texels: Vec<[f32; 3]>,
+}
+
+impl Lut {
+    // Leaves colors as they are.
+    pub fn identity(size: usize) -> Self {
+        let size = size.max(2);
+        let step = 1.0 / (size - 1) as f32;
+        let texels = (0..size * size * size)
+            .map(|index| {
+                [index % size, index / size % size, index / (size * size)]
+                    .map(|channel| channel as f32 * step)
+            })
+            .collect();
+        Self {
+            title: None,
+            size,
+            domain: [[0.0; 3], [1.0; 3]],
+            texels,
+        }
+    }
+
+    // Adobe's `.cube` format, which Resolve, Photoshop and most grading tools export.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut title = None;
+        let mut size = None;
+        let mut domain = [[0.0; 3], [1.0; 3]];
+        let mut texels = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |err: String| format!("LUT line {}: {}", index + 1, err);
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match keyword {
+                "TITLE" => title = Some(rest.trim().trim_matches('"').to_owned()),
+                "LUT_3D_SIZE" => {
+                    let value = rest.trim();
+                    let value = value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|size| (2..=256).contains(size))
+                        .ok_or_else(|| error(format!("{} isn't a LUT size", value)))?;
+                    size = Some(value);
+                }
+                "DOMAIN_MIN" => domain[0] = parse_color(rest).map_err(error)?,
+                "DOMAIN_MAX" => domain[1] = parse_color(rest).map_err(error)?,
+                "LUT_1D_SIZE" => return Err(error("1D LUTs aren't supported".to_owned())),
+                _ => texels.push(parse_color(line).map_err(error)?),
+            }
+        }
+        let size = size.ok_or("LUT has no LUT_3D_SIZE")?;
+        if texels.len() != size * size * size {
+            return Err(format!(
+                "LUT of size {} needs {} entries, has {}",
+                size,
+                size * size * size,
+                texels.len()
+            ));
+        }
+        if (0..3).any(|channel| domain[1][channel] <= domain[0][channel]) {
+            return Err("LUT domain is empty".to_owned());
+        }
+        Ok(Self {
+            title,
+            size,
+            domain,
+            texels,
+        })
+    }
+
+    // Read through the VFS so mods can replace a game's LUTs.
+    pub fn load(vfs: &Vfs, path: &str) -> Result<Self, String> {
+        let source = vfs
+            .read_to_string(path)
+            .map_err(|err| format!("Failed to read LUT {}: {}", path, err))?;
+        Self::parse(&source).map_err(|err| format!("{}: {}", path, err))
+    }
+
+    pub fn load_file(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read LUT {}: {}", path.display(), err))?;
+        Self::parse(&source).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    // Entries along each side.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn texels(&self) -> &[[f32; 3]] {
+        &self.texels
+    }
+
+    // Trilinear, like the texture sampler.
+    pub fn sample(&self, color: [f32; 3]) -> [f32; 3] {
+        let last = self.size - 1;
+        let [min, max] = self.domain;
+        let position: [f32; 3] = std::array::from_fn(|channel| {
+            let t = (color[channel] - min[channel]) / (max[channel] - min[channel]);
+            t.clamp(0.0, 1.0) * last as f32
+        });
+        let low = position.map(|p| (p.floor() as usize).min(last - 1));
+        let t: [f32; 3] = std::array::from_fn(|channel| position[channel] - low[channel] as f32);
+        let mut graded = [0.0; 3];
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut at = [0; 3];
+            for channel in 0..3 {
+                let high = corner & (1 << channel) != 0;
+                at[channel] = low[channel] + high as usize;
+                weight *= if high { t[channel] } else { 1.0 - t[channel] };
+            }
+            let texel = self.texels[at[0] + at[1] * self.size + at[2] * self.size * self.size];
+            for channel in 0..3 {
+                graded[channel] += texel[channel] * weight;
+            }
+        }
+        graded
+    }
+
+    // A LUT that grades like `from` and `to` blended by `t`, at the larger of their sizes.
+    pub fn mix(from: &Lut, to: &Lut, t: f32) -> Self {
+        let mut mixed = Self::identity(from.size.max(to.size));
+        for texel in &mut mixed.texels {
+            let (a, b) = (from.sample(*texel), to.sample(*texel));
+            *texel = std::array::from_fn(|channel| a[channel] + (b[channel] - a[channel]) * t);
+        }
+        mixed
+    }
+}
+
+fn parse_color(text: &str) -> Result<[f32; 3], String> {
+    let values: Vec<f32> = text
+        .split_whitespace()
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("{} is not a number", value))
+        })
+        .collect::<Result<_, _>>()?;
+    values
+        .try_into()
+        .map_err(|_| format!("expected three numbers, got {}", text.trim()))
+}
+
+pub struct ColorGrading {
+    from: Arc<Lut>,
+    to: Arc<Lut>,
+    elapsed: Duration,
+    duration: Duration,
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        let identity = Arc::new(Lut::identity(2));
+        Self {
+            from: identity.clone(),
+            to: identity,
+            elapsed: Duration::ZERO,
+            duration: Duration::ZERO,
+        }
+    }
+}
+
+impl ColorGrading {
+    // Blends to `lut` over `duration`, zero switches right away. Starting while another blend is
+    // running continues from the colors on screen.
+    pub fn set(&mut self, lut: Arc<Lut>, duration: Duration) {
+        if self.is_blending() {
+            self.from = Arc::new(Lut::mix(&self.from, &self.to, self.blend()));
+        } else {
+            self.from = self.to.clone();
+        }
+        self.to = lut;
+        self.elapsed = Duration::ZERO;
+        self.duration = duration;
+        if duration.is_zero() {
+            self.from = self.to.clone();
+        }
+    }
+
+    pub fn from(&self) -> &Arc<Lut> {
+        &self.from
+    }
+
+    pub fn to(&self) -> &Arc<Lut> {
+        &self.to
+    }
+
+    // How far from `from` to `to`, 0 to 1.
+    pub fn blend(&self) -> f32 {
+        match self.duration.as_secs_f32() {
+            duration if duration > 0.0 => (self.elapsed.as_secs_f32() / duration).min(1.0),
+            _ => 1.0,
+        }
+    }
+
+    pub fn is_blending(&self) -> bool {
+        self.elapsed < self.duration
+    }
+
+    pub fn apply(&self, color: [f32; 3]) -> [f32; 3] {
+        let (a, b, t) = (self.from.sample(color), self.to.sample(color), self.blend());
+        std::array::from_fn(|channel| a[channel] + (b[channel] - a[channel]) * t)
+    }
+
+    pub fn advance(&mut self, delta: Duration) {
+        if !self.is_blending() {
+            return;
+        }
+        self.elapsed = (self.elapsed + delta).min(self.duration);
+        if !self.is_blending() {
+            self.from = self.to.clone();
+        }
+    }
+}
+
+pub struct ColorGradingPlugin;
+
+impl Plugin for ColorGradingPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(ColorGrading::default())
+            .add_system(|resources: &mut Resources| {
+                let delta = resources
+                    .get::<Time>()
+                    .map_or(Duration::ZERO, |time| time.delta);
+                if let Some(grading) = resources.get_mut::<ColorGrading>() {
+                    grading.advance(delta);
+                }
+            });
+
+        cvars::cvars(engine).register_command(
+            "grading.lut",
+            "grading.lut <path> [seconds], blends to a .cube LUT",
+            |resources, args| {
+                let (path, duration) = match args {
+                    [path] => (path, Duration::ZERO),
+                    [path, seconds] => (
+                        path,
+                        seconds
+                            .parse::<f32>()
+                            .ok()
+                            .and_then(|seconds| Duration::try_from_secs_f32(seconds).ok())
+                            .ok_or_else(|| format!("{} isn't a duration", seconds))?,
+                    ),
+                    _ => return Err("usage: grading.lut <path> [seconds]".to_owned()),
+                };
+                let lut = match resources.get::<Vfs>() {
+                    Some(vfs) => Lut::load(vfs, path)?,
+                    None => Lut::load_file(path)?,
+                };
+                let grading = resources
+                    .get_mut::<ColorGrading>()
+                    .ok_or("no color grading")?;
+                grading.set(Arc::new(lut), duration);
+                Ok(())
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WARM: &str = "
+        TITLE \"Warm\"
+        # Red up, blue down.
+        LUT_3D_SIZE 2
+        0.1 0 0
+        1 0 0
+        0.1 1 0
+        1 1 0
+        0.1 0 0.8
+        1 0 0.8
+        0.1 1 0.8
+        1 1 0.8
+    ";
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        (0..3).all(|channel| (a[channel] - b[channel]).abs() < 1e-5)
+    }
+
+    #[test]
+    fn blends_between_luts() {
+        let warm = Lut::parse(WARM).unwrap();
+        assert_eq!(warm.title.as_deref(), Some("Warm"));
+        assert!(close(warm.sample([0.5, 0.5, 0.5]), [0.55, 0.5, 0.4]));
+        assert!(Lut::parse("LUT_3D_SIZE 2\n0 0 0").is_err());
+        let identity = Lut::identity(5);
+        assert!(close(identity.sample([0.3, 0.7, 0.1]), [0.3, 0.7, 0.1]));
+
+        let mut grading = ColorGrading::default();
+        grading.set(Arc::new(warm), Duration::from_secs(2));
+        assert!(close(grading.apply([0.0, 0.5, 1.0]), [0.0, 0.5, 1.0]));
+        grading.advance(Duration::from_secs(1));
+        assert!(close(grading.apply([0.0, 0.5, 1.0]), [0.05, 0.5, 0.9]));
+
+        // Going back halfway through starts from the half warm colors.
+        grading.set(Arc::new(Lut::identity(2)), Duration::from_secs(1));
+        assert!(close(grading.apply([0.0, 0.5, 1.0]), [0.05, 0.5, 0.9]));
+        grading.advance(Duration::from_secs(3));
+        assert!(!grading.is_blending());
+        assert!(close(grading.apply([0.0, 0.5, 1.0]), [0.0, 0.5, 1.0]));
+    }
+}
@@ -0,0 +1,127 @@
+//! Engine config file. Plain `key = value` lines, `#` starts a comment at the start of a line or
+//! after whitespace outside double quotes, so values like `a.com/#top` or `"Part #2"` keep
+//! theirs. `[section]` prefixes the keys below it, so `filter = warn` under `[log]` is read as
+//! `log.filter`.
+
+use std::{collections::BTreeMap, env, fs, io, path::Path, path::PathBuf, str::FromStr};
+
+pub const FILE_NAME: &str = "midnight2.cfg";
+
+// Next to the executable, so shipped builds find it regardless of the working directory.
+pub fn default_path() -> PathBuf {
+    env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(FILE_NAME)))
+        .unwrap_or_else(|| PathBuf::from(FILE_NAME))
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    values: BTreeMap<String, String>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = Config::default();
+        let mut section = String::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                section = name.trim().to_owned();
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected `key = value`", index + 1))?;
+            let key = key.trim();
+            if key.is_empty() {
+                return Err(format!("line {}: missing key", index + 1).into());
+            }
+            let key = match section.as_str() {
+                "" => key.to_owned(),
+                section => format!("{}.{}", section, key),
+            };
+            config.values.insert(key, value.trim().to_owned());
+        }
+        Ok(config)
+    }
+
+    // A missing file is an empty config, only unreadable or malformed files are errors.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        match fs::read_to_string(path.as_ref()) {
+            Ok(text) => Self::parse(&text)
+                .map_err(|err| format!("{}: {}", path.as_ref().display(), err).into()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn load_default() -> Result<Self, Box<dyn std::error::Error>> {
+        Self::load(default_path())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(key).map(String::as_str)
+    }
+
+    pub fn get_parsed<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.get(key).and_then(|value| value.parse().ok())
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.values.insert(key.to_owned(), value.to_owned());
+    }
+
+    // Sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut previous: Option<char> = None;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted && previous.is_none_or(char::is_whitespace) => return &line[..index],
+            _ => {}
+        }
+        previous = Some(c);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sections_and_comments() {
+        let config = Config::parse(
+            "# engine settings\nwindow_width = 1280\n\n[log]\nfilter = warn,midnight2_core::sim=debug # noisy\n",
+        )
+        .unwrap();
+        assert_eq!(config.get_parsed::<u32>("window_width"), Some(1280));
+        assert_eq!(
+            config.get("log.filter"),
+            Some("warn,midnight2_core::sim=debug")
+        );
+        assert!(Config::parse("[log]\njust a line").is_err());
+
+        let config =
+            Config::parse("color = #ff8000\ntitle = \"Part #2\" # quoted\nurl = a.com/#top\n")
+                .unwrap();
+        assert_eq!(config.get("color"), Some(""));
+        assert_eq!(config.get("title"), Some("\"Part #2\""));
+        assert_eq!(config.get("url"), Some("a.com/#top"));
+    }
+}
@@ -0,0 +1,170 @@
+//! In-game console state. Shows recent lines from the log history, toggled with `~`, with
+//! scrolling, a text filter and a command line.
+
+use std::collections::VecDeque;
+
+use log::LevelFilter;
+use winit::keyboard::KeyCode;
+
+use crate::{
+    engine::{Engine, Plugin, Resources},
+    input::InputEvent,
+    logging::{self, LogLine},
+};
+
+const SCROLL_PAGE: usize = 10;
+
+#[derive(Default)]
+pub struct Console {
+    open: bool,
+    input: String,
+    // Lines scrolled up from the newest one.
+    scroll: usize,
+    filter: String,
+    min_level: Option<LevelFilter>,
+    // Lines up to this sequence were cleared and are hidden.
+    cleared_through: Option<u64>,
+    // Submitted lines that aren't built-in commands, for whoever handles commands.
+    pending_commands: VecDeque<String>,
+}
+
+impl Console {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn toggle(&mut self) {
+        self.open = !self.open;
+        self.scroll = 0;
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    pub fn filter(&self) -> &str {
+        &self.filter
+    }
+
+    pub fn scroll_by(&mut self, lines: isize) {
+        self.scroll = self.scroll.saturating_add_signed(lines);
+    }
+
+    fn shows(&self, line: &LogLine) -> bool {
+        if self
+            .cleared_through
+            .is_some_and(|cleared| line.sequence <= cleared)
+            || self.min_level.is_some_and(|level| line.level > level)
+        {
+            return false;
+        }
+        self.filter.is_empty()
+            || line.message.to_lowercase().contains(&self.filter)
+            || line.target.to_lowercase().contains(&self.filter)
+    }
+
+    /// Up to `rows` lines to draw, oldest first, with filtering and scrolling applied.
+    pub fn visible_lines(&mut self, rows: usize) -> Vec<LogLine> {
+        let Some(history) = logging::history() else {
+            return Vec::new();
+        };
+        let lines = history.lines(|line| self.shows(line));
+        self.scroll = self.scroll.min(lines.len().saturating_sub(rows));
+        let end = lines.len() - self.scroll;
+        lines[end.saturating_sub(rows)..end].to_vec()
+    }
+
+    /// Commands the console doesn't handle itself, oldest first.
+    pub fn drain_commands(&mut self) -> impl Iterator<Item = String> + '_ {
+        self.pending_commands.drain(..)
+    }
+
+    pub fn submit(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        info!(target: "console", "> {}", line);
+        self.scroll = 0;
+
+        let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "clear" => self.cleared_through = logging::history().and_then(|h| h.latest_sequence()),
+            "filter" => self.filter = args.trim().to_lowercase(),
+            "level" => match args.trim() {
+                "" => self.min_level = None,
+                level => match level.parse() {
+                    Ok(level) => self.min_level = Some(level),
+                    Err(_) => warn!(target: "console", "Unknown log level {}", level),
+                },
+            },
+            "help" => info!(
+                target: "console",
+                "clear, filter <text>, level <error|warn|info|debug|trace>, cvars, help"
+            ),
+            _ => self.pending_commands.push_back(line.to_owned()),
+        }
+    }
+
+    // Returns true if the console swallowed the event.
+    pub fn handle_input(&mut self, event: &InputEvent) -> bool {
+        match event {
+            InputEvent::Key {
+                code: KeyCode::Backquote,
+                pressed,
+            } => {
+                if *pressed {
+                    self.toggle();
+                }
+                true
+            }
+            _ if !self.open => false,
+            InputEvent::Key {
+                code,
+                pressed: true,
+            } => {
+                match code {
+                    KeyCode::Enter => {
+                        let line = std::mem::take(&mut self.input);
+                        self.submit(&line);
+                    }
+                    KeyCode::Backspace => {
+                        self.input.pop();
+                    }
+                    KeyCode::PageUp => self.scroll_by(SCROLL_PAGE as isize),
+                    KeyCode::PageDown => self.scroll_by(-(SCROLL_PAGE as isize)),
+                    _ => {}
+                }
+                true
+            }
+            InputEvent::Text(text) => {
+                self.input.extend(
+                    text.chars()
+                        .filter(|c| !c.is_control() && *c != '`' && *c != '~'),
+                );
+                true
+            }
+            InputEvent::MouseWheel { dy, .. } => {
+                self.scroll_by((dy / 20.0).round() as isize);
+                true
+            }
+            // Key releases, and nothing underneath should see them while the console is up.
+            InputEvent::Key { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(Console::default())
+            .add_input_handler(|resources: &mut Resources, event: &InputEvent| {
+                resources
+                    .get_mut::<Console>()
+                    .is_some_and(|console| console.handle_input(event))
+            });
+    }
+}
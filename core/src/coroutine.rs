@@ -0,0 +1,375 @@
+//! Gameplay coroutines: async blocks the sim polls once per tick, for cutscenes and multi-step
+//! sequences that would otherwise be state machines.
+//!
+//! ```ignore
+//! coroutines.start(async {
+//!     with_resources(|resources| open_door(resources));
+//!     wait_ticks(30).await;
+//!     let DialogueFinished(line) = wait_for_event::<DialogueFinished>().await;
+//!     wait_for_load(level_progress).await;
+//! });
+//! ```
+//!
+//! They run when `CoroutinePlugin`'s system does, after the systems of the plugins added before
+//! it and before the application's `fixed_update`, and hold while the sim is paused. Between
+//! awaits a coroutine reaches the engine through `with_resources`.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    marker::PhantomData,
+    mem,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    engine::{Engine, Plugin, Resources},
+    loading::LoadProgress,
+    metrics,
+    sim::Time,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CoroutineId(u64);
+
+impl fmt::Display for CoroutineId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+type Event = Box<dyn Any + Send>;
+
+// The running coroutines, polled by `CoroutinePlugin`'s system every tick.
+#[derive(Default)]
+pub struct Coroutines {
+    next_id: u64,
+    // Game ticks run so far, what `wait_ticks` counts.
+    tick: u64,
+    running: Vec<(CoroutineId, Task)>,
+    // For `wait_for_event`, delivered on the next run.
+    events: Vec<Event>,
+    finished: VecDeque<CoroutineId>,
+}
+
+impl Coroutines {
+    // Starts polling `coroutine` on the next run.
+    pub fn start<F>(&mut self, coroutine: F) -> CoroutineId
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let id = CoroutineId(self.next_id);
+        self.next_id += 1;
+        self.running.push((id, Box::pin(coroutine)));
+        id
+    }
+
+    // Drops the coroutine where it's waiting.
+    pub fn cancel(&mut self, id: CoroutineId) -> bool {
+        let len = self.running.len();
+        self.running.retain(|(running, _)| *running != id);
+        self.running.len() != len
+    }
+
+    pub fn is_running(&self, id: CoroutineId) -> bool {
+        self.running.iter().any(|(running, _)| *running == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.running.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    // Wakes the coroutines waiting for a `T` on the next run. Within a coroutine use `emit`.
+    pub fn emit<T: Clone + Send + 'static>(&mut self, event: T) {
+        self.events.push(Box::new(event));
+    }
+
+    // Coroutines that ran to completion, oldest first.
+    pub fn poll_finished(&mut self) -> Option<CoroutineId> {
+        self.finished.pop_front()
+    }
+
+    // Polls every coroutine once, unless the sim is paused. `resources` must not hold this.
+    pub fn run(&mut self, time: &Time, resources: &mut Resources) {
+        if time.delta.is_zero() {
+            return;
+        }
+        self.tick += 1;
+        let previous = CURRENT.with(|current| {
+            current.borrow_mut().replace(Current {
+                resources,
+                tick: self.tick,
+                events: mem::take(&mut self.events),
+                emitted: Vec::new(),
+                borrowed: false,
+            })
+        });
+        // A panicking coroutine mustn't leave the resources pointer behind.
+        let restore = RestoreCurrent(previous);
+
+        let mut context = Context::from_waker(Waker::noop());
+        let mut index = 0;
+        while index < self.running.len() {
+            let (id, task) = &mut self.running[index];
+            let id = *id;
+            if task.as_mut().poll(&mut context).is_ready() {
+                drop(self.running.remove(index));
+                self.finished.push_back(id);
+            } else {
+                index += 1;
+            }
+        }
+
+        self.events
+            .extend(current(|current| mem::take(&mut current.emitted)));
+        drop(restore);
+    }
+}
+
+// Puts back what `CURRENT` held before `run` when dropped.
+struct RestoreCurrent(Option<Current>);
+
+impl Drop for RestoreCurrent {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+    }
+}
+
+// Ends a `with_resources` borrow when dropped.
+struct EndBorrow;
+
+impl Drop for EndBorrow {
+    fn drop(&mut self) {
+        CURRENT.with(|current| {
+            if let Some(current) = current.borrow_mut().as_mut() {
+                current.borrowed = false;
+            }
+        });
+    }
+}
+
+// What the coroutines see while `Coroutines::run` polls them.
+struct Current {
+    resources: *mut Resources,
+    tick: u64,
+    events: Vec<Event>,
+    emitted: Vec<Event>,
+    // Inside `with_resources`, which can't nest.
+    borrowed: bool,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Current>> = const { RefCell::new(None) };
+}
+
+fn current<R>(f: impl FnOnce(&mut Current) -> R) -> R {
+    CURRENT.with(|current| {
+        f(current
+            .borrow_mut()
+            .as_mut()
+            .expect("only coroutines can use this"))
+    })
+}
+
+// Runs `f` with the engine's resources, from inside a coroutine between awaits.
+pub fn with_resources<R>(f: impl FnOnce(&mut Resources) -> R) -> R {
+    let resources = current(|current| {
+        assert!(!current.borrowed, "with_resources can't nest");
+        current.borrowed = true;
+        current.resources
+    });
+    let _borrow = EndBorrow;
+    // Only set while `run` is polling, which holds the only borrow of the resources.
+    f(unsafe { &mut *resources })
+}
+
+// Wakes coroutines waiting for a `T` on the next tick, from inside a coroutine.
+pub fn emit<T: Clone + Send + 'static>(event: T) {
+    current(|current| current.emitted.push(Box::new(event)));
+}
+
+// Resumes `ticks` game ticks later, zero doesn't wait.
+pub fn wait_ticks(ticks: u64) -> WaitTicks {
+    WaitTicks { ticks, until: None }
+}
+
+pub struct WaitTicks {
+    ticks: u64,
+    until: Option<u64>,
+}
+
+impl Future for WaitTicks {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+        let tick = current(|current| current.tick);
+        let ticks = self.ticks;
+        let until = *self.until.get_or_insert(tick + ticks);
+        if tick >= until {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+// Resumes with the next `T` emitted, each waiter gets the first one of a tick.
+pub fn wait_for_event<T: Clone + Send + 'static>() -> WaitForEvent<T> {
+    WaitForEvent(PhantomData)
+}
+
+pub struct WaitForEvent<T>(PhantomData<fn() -> T>);
+
+impl<T: Clone + Send + 'static> Future for WaitForEvent<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, _: &mut Context) -> Poll<T> {
+        current(|current| {
+            current
+                .events
+                .iter()
+                .find_map(|event| event.downcast_ref::<T>())
+                .cloned()
+                .map_or(Poll::Pending, Poll::Ready)
+        })
+    }
+}
+
+// Resumes on the first tick `condition` holds, checking it once a tick.
+pub fn wait_until<F>(condition: F) -> WaitUntil<F>
+where
+    F: FnMut(&mut Resources) -> bool + Unpin,
+{
+    WaitUntil(condition)
+}
+
+pub struct WaitUntil<F>(F);
+
+impl<F> Future for WaitUntil<F>
+where
+    F: FnMut(&mut Resources) -> bool + Unpin,
+{
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+        if with_resources(&mut self.0) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+// Resumes once everything tracked by `progress` has loaded.
+pub fn wait_for_load(progress: LoadProgress) -> WaitUntil<impl FnMut(&mut Resources) -> bool> {
+    wait_until(move |_: &mut Resources| progress.is_done())
+}
+
+pub struct CoroutinePlugin;
+
+impl Plugin for CoroutinePlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(Coroutines::default())
+            .add_system(|resources: &mut Resources| {
+                let time = resources.get::<Time>().copied().unwrap_or_default();
+                // Out of the resources while it runs, so coroutines can borrow them.
+                let Some(mut coroutines) = resources.remove::<Coroutines>() else {
+                    return;
+                };
+                coroutines.run(&time, resources);
+                metrics::set_gauge("coroutines_running", coroutines.len() as f64);
+                resources.insert(coroutines);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct Opened(&'static str);
+
+    #[test]
+    fn waits_for_ticks_events_and_conditions() {
+        let mut resources = Resources::default();
+        resources.insert(Vec::<&'static str>::new());
+        let mut coroutines = Coroutines::default();
+        let log =
+            |line| with_resources(|resources| resources.get_mut::<Vec<_>>().unwrap().push(line));
+        let cutscene = coroutines.start(async move {
+            log("start");
+            wait_ticks(2).await;
+            log("waited");
+            let Opened(door) = wait_for_event::<Opened>().await;
+            log(door);
+            emit(());
+            wait_until(|resources| resources.contains::<u32>()).await;
+            log("done");
+        });
+        let listener = coroutines.start(async move {
+            wait_for_event::<()>().await;
+            log("heard");
+        });
+
+        let tick = Time {
+            delta: Duration::from_millis(16),
+            ..Time::default()
+        };
+        let paused = Time::default();
+        let run = |coroutines: &mut Coroutines, resources: &mut Resources, time: &Time| {
+            coroutines.run(time, resources);
+            resources.get::<Vec<&'static str>>().unwrap().clone()
+        };
+        assert_eq!(run(&mut coroutines, &mut resources, &tick), ["start"]);
+        assert_eq!(run(&mut coroutines, &mut resources, &paused), ["start"]);
+        run(&mut coroutines, &mut resources, &tick);
+        assert_eq!(
+            run(&mut coroutines, &mut resources, &tick),
+            ["start", "waited"]
+        );
+
+        coroutines.emit(Opened("vault"));
+        assert_eq!(
+            run(&mut coroutines, &mut resources, &tick),
+            ["start", "waited", "vault"]
+        );
+        resources.insert(7u32);
+        assert_eq!(
+            run(&mut coroutines, &mut resources, &tick),
+            ["start", "waited", "vault", "done", "heard"]
+        );
+        assert!(coroutines.is_empty());
+        assert_eq!(coroutines.poll_finished(), Some(cutscene));
+        assert_eq!(coroutines.poll_finished(), Some(listener));
+    }
+
+    #[test]
+    fn panics_leave_nothing_behind() {
+        let mut resources = Resources::default();
+        let mut coroutines = Coroutines::default();
+        coroutines.start(async { with_resources(|_| panic!("oops")) });
+        let tick = Time {
+            delta: Duration::from_millis(16),
+            ..Time::default()
+        };
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            coroutines.run(&tick, &mut resources)
+        }));
+        assert!(result.is_err());
+        assert!(CURRENT.with(|current| current.borrow().is_none()));
+    }
+}
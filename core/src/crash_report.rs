@@ -0,0 +1,159 @@
+//! Crash report bundles. `write_bundle` zips the recent log history, attached log files, the
+//! engine config, metrics and system/GPU info into `reports/` next to the executable, so users
+//! can attach a single file to a bug report. Also written on panic once `enable_on_panic` is set.
+
+use std::{
+    env,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Mutex, PoisonError, TryLockError,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{config, logging, metrics, render, sim};
+
+static ON_PANIC: AtomicBool = AtomicBool::new(false);
+static GPU_INFO: Mutex<Option<String>> = Mutex::new(None);
+static ATTACHED_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+// Bundles written by this process so far, keeps names unique within a second.
+static BUNDLES: AtomicU32 = AtomicU32::new(0);
+
+pub fn enable_on_panic(enabled: bool) {
+    ON_PANIC.store(enabled, Ordering::Relaxed);
+}
+
+// Set by the renderer once it picked an adapter.
+pub fn set_gpu_info(info: String) {
+    *GPU_INFO.lock().unwrap() = Some(info);
+}
+
+// Includes the file as it is when the bundle is written, e.g. a JSON log file.
+pub fn attach_file<P: Into<PathBuf>>(path: P) {
+    ATTACHED_FILES.lock().unwrap().push(path.into());
+}
+
+pub fn reports_dir() -> PathBuf {
+    config::default_path()
+        .parent()
+        .map(|dir| dir.join("reports"))
+        .unwrap_or_else(|| PathBuf::from("reports"))
+}
+
+// Called from the logging panic hook after the panic was logged.
+pub(crate) fn on_panic(message: &str) {
+    if !ON_PANIC.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    match write(message, true) {
+        Ok(path) => error!("Crash report written to {}", path.display()),
+        Err(err) => error!("Failed to write crash report: {}", err),
+    }
+}
+
+pub fn write_bundle(reason: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    write(reason, false)
+}
+
+// From a panic hook every lock is only tried, the panicking thread may be holding one. Whatever
+// is locked is left out of the report.
+fn write(reason: &str, panicking: bool) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let now = SystemTime::now();
+    let dir = reports_dir();
+    fs::create_dir_all(&dir)?;
+    let seconds = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    // The pid tells apart other instances crashing in the same second.
+    let path = dir.join(format!(
+        "crash-{}-{}-{}.zip",
+        seconds,
+        process::id(),
+        BUNDLES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let mut zip = ZipWriter::new(File::create_new(&path)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("report.txt", options)?;
+    zip.write_all(system_info(reason, now, panicking).as_bytes())?;
+
+    let history = logging::history().and_then(|history| {
+        if panicking {
+            history.try_lines()
+        } else {
+            Some(history.lines(|_| true))
+        }
+    });
+    if let Some(lines) = history {
+        zip.start_file("log.txt", options)?;
+        for line in lines {
+            writeln!(zip, "{:5} {} > {}", line.level, line.target, line.message)?;
+        }
+    }
+
+    let snapshot = if panicking {
+        metrics::try_snapshot()
+    } else {
+        Some(metrics::snapshot())
+    };
+    if let Some(snapshot) = snapshot {
+        zip.start_file("metrics.csv", options)?;
+        metrics::write_csv_of(&mut zip, &snapshot)?;
+    }
+
+    let mut files = vec![config::default_path()];
+    files.extend(read(&ATTACHED_FILES, panicking).unwrap_or_default());
+    for file in files {
+        // A missing config or log file shouldn't cost the whole report.
+        let Ok(contents) = fs::read(&file) else {
+            continue;
+        };
+        zip.start_file(format!("files/{}", file_name(&file)), options)?;
+        zip.write_all(&contents)?;
+    }
+
+    zip.finish()?;
+    Ok(path)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "unnamed".to_owned())
+}
+
+// A copy of what's in `mutex`, None if it's locked and `panicking`.
+fn read<T: Clone>(mutex: &Mutex<T>, panicking: bool) -> Option<T> {
+    if !panicking {
+        return Some(mutex.lock().unwrap_or_else(PoisonError::into_inner).clone());
+    }
+    match mutex.try_lock() {
+        Ok(value) => Some(value.clone()),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner().clone()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+fn system_info(reason: &str, now: SystemTime, panicking: bool) -> String {
+    let args: Vec<String> = env::args().collect();
+    let gpu = read(&GPU_INFO, panicking).flatten();
+    format!(
+        "reason: {}\ntime: {}\nversion: {}\nos: {} {}\ncpus: {}\nargs: {:?}\ntick: {}\nframe: {}\ngpu: {}\n",
+        reason,
+        logging::rfc3339_utc(now),
+        env!("CARGO_PKG_VERSION"),
+        env::consts::OS,
+        env::consts::ARCH,
+        thread::available_parallelism().map_or(0, |cpus| cpus.get()),
+        args,
+        sim::current_tick(),
+        render::current_frame(),
+        gpu.as_deref().unwrap_or("unknown")
+    )
+}
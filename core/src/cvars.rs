@@ -0,0 +1,377 @@
+//! Console variables and commands. Subsystems register typed variables (`r.vsync`) and commands
+//! (`profile`) with help text on the `Cvars` resource. Variables can be set from the engine
+//! config file (`vsync = false` under `[r]`), the command line (`+r.vsync=false`) and the
+//! in-game console (`r.vsync false`), commands are run from the console.
+
+use std::{collections::BTreeMap, env, fmt};
+
+use crate::{
+    config::Config,
+    console::Console,
+    crash_report,
+    engine::{Engine, Plugin, Resources},
+    profiling,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CvarValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl CvarValue {
+    // Parses `text` as the same type as `self`.
+    fn parse_as(&self, text: &str) -> Result<CvarValue, String> {
+        let invalid = |kind: &str| format!("expected {}, got {}", kind, text);
+        Ok(match self {
+            CvarValue::Bool(_) => CvarValue::Bool(match text {
+                "1" | "true" | "on" => true,
+                "0" | "false" | "off" => false,
+                _ => return Err(invalid("a bool")),
+            }),
+            CvarValue::Int(_) => CvarValue::Int(text.parse().map_err(|_| invalid("an integer"))?),
+            CvarValue::Float(_) => CvarValue::Float(text.parse().map_err(|_| invalid("a number"))?),
+            CvarValue::String(_) => CvarValue::String(text.to_owned()),
+        })
+    }
+}
+
+impl fmt::Display for CvarValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CvarValue::Bool(value) => write!(f, "{}", value),
+            CvarValue::Int(value) => write!(f, "{}", value),
+            CvarValue::Float(value) => write!(f, "{}", value),
+            CvarValue::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl From<bool> for CvarValue {
+    fn from(value: bool) -> Self {
+        CvarValue::Bool(value)
+    }
+}
+
+impl From<i64> for CvarValue {
+    fn from(value: i64) -> Self {
+        CvarValue::Int(value)
+    }
+}
+
+impl From<f64> for CvarValue {
+    fn from(value: f64) -> Self {
+        CvarValue::Float(value)
+    }
+}
+
+impl From<&str> for CvarValue {
+    fn from(value: &str) -> Self {
+        CvarValue::String(value.to_owned())
+    }
+}
+
+struct Cvar {
+    value: CvarValue,
+    default: CvarValue,
+    help: String,
+}
+
+pub type Command = Box<dyn FnMut(&mut Resources, &[&str]) -> Result<(), String> + Send>;
+
+struct CommandEntry {
+    run: Command,
+    help: String,
+    // When it was registered, see `Cvars::mark`.
+    serial: u64,
+}
+
+// The commands registered at some point, see `Cvars::mark`.
+#[derive(Clone, Copy, Debug)]
+pub struct CvarsMark {
+    serial: u64,
+}
+
+#[derive(Default)]
+pub struct Cvars {
+    vars: BTreeMap<String, Cvar>,
+    commands: BTreeMap<String, CommandEntry>,
+    next_serial: u64,
+    // Values from the config file or command line for variables that aren't registered yet.
+    overrides: BTreeMap<String, String>,
+}
+
+impl Cvars {
+    // Registers a variable, taking its value from an earlier config or command line override.
+    pub fn register<V: Into<CvarValue>>(&mut self, name: &str, default: V, help: &str) {
+        let default = default.into();
+        let mut value = default.clone();
+        if let Some(text) = self.overrides.remove(name) {
+            match default.parse_as(&text) {
+                Ok(parsed) => value = parsed,
+                Err(err) => warn!("Ignoring {} = {}: {}", name, text, err),
+            }
+        }
+        self.vars.insert(
+            name.to_owned(),
+            Cvar {
+                value,
+                default,
+                help: help.to_owned(),
+            },
+        );
+    }
+
+    pub fn register_command<F>(&mut self, name: &str, help: &str, run: F)
+    where
+        F: FnMut(&mut Resources, &[&str]) -> Result<(), String> + Send + 'static,
+    {
+        self.next_serial += 1;
+        self.commands.insert(
+            name.to_owned(),
+            CommandEntry {
+                run: Box::new(run),
+                help: help.to_owned(),
+                serial: self.next_serial,
+            },
+        );
+    }
+
+    // Remembers the commands registered so far for `rollback`. Hot reload uses it to drop a game
+    // library's commands before unloading it.
+    pub fn mark(&self) -> CvarsMark {
+        CvarsMark {
+            serial: self.next_serial,
+        }
+    }
+
+    // Drops the commands registered since `mark`, including those that replaced older ones.
+    // Variables stay, they don't hold on to any code.
+    pub fn rollback(&mut self, mark: CvarsMark) {
+        self.commands
+            .retain(|_, command| command.serial <= mark.serial);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CvarValue> {
+        self.vars.get(name).map(|var| &var.value)
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)? {
+            CvarValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_int(&self, name: &str) -> Option<i64> {
+        match self.get(name)? {
+            CvarValue::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn get_float(&self, name: &str) -> Option<f64> {
+        match self.get(name)? {
+            CvarValue::Float(value) => Some(*value),
+            CvarValue::Int(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            CvarValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    // Sets a registered variable, or remembers the value until it's registered.
+    pub fn set(&mut self, name: &str, text: &str) -> Result<(), String> {
+        match self.vars.get_mut(name) {
+            Some(var) => {
+                var.value = var.value.parse_as(text)?;
+                Ok(())
+            }
+            None => {
+                self.overrides.insert(name.to_owned(), text.to_owned());
+                Ok(())
+            }
+        }
+    }
+
+    pub fn reset(&mut self, name: &str) {
+        if let Some(var) = self.vars.get_mut(name) {
+            var.value = var.default.clone();
+        }
+    }
+
+    pub fn apply_config(&mut self, config: &Config) {
+        for (key, value) in config.iter() {
+            // Config values go through `set` so invalid ones are reported, not silently dropped.
+            if let Err(err) = self.set(key, value) {
+                warn!("Ignoring config {} = {}: {}", key, value, err);
+            }
+        }
+    }
+
+    // Applies `+name=value` arguments, other arguments are left alone.
+    pub fn apply_args<S: AsRef<str>>(&mut self, args: &[S]) {
+        for arg in args {
+            let Some((name, value)) = arg
+                .as_ref()
+                .strip_prefix('+')
+                .and_then(|arg| arg.split_once('='))
+            else {
+                continue;
+            };
+            if let Err(err) = self.set(name, value) {
+                warn!("Ignoring argument +{}={}: {}", name, value, err);
+            }
+        }
+    }
+
+    // Help lines for every variable and command, sorted by name.
+    pub fn help(&self) -> Vec<String> {
+        let vars = self
+            .vars
+            .iter()
+            .map(|(name, var)| format!("{} = {} ({})", name, var.value, var.help));
+        let commands = self
+            .commands
+            .iter()
+            .map(|(name, command)| format!("{}: {}", name, command.help));
+        vars.chain(commands).collect()
+    }
+
+    // Runs a console line: `name` shows a variable, `name value` sets it, anything else must be
+    // a registered command. The registry is taken out of `resources` while a command runs.
+    pub fn execute(resources: &mut Resources, line: &str) -> Result<(), String> {
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(());
+        };
+        let args: Vec<&str> = words.collect();
+        let mut cvars = resources.remove::<Cvars>().ok_or("no cvar registry")?;
+        let result = cvars.execute_with(resources, name, &args);
+        resources.insert(cvars);
+        result
+    }
+
+    fn execute_with(
+        &mut self,
+        resources: &mut Resources,
+        name: &str,
+        args: &[&str],
+    ) -> Result<(), String> {
+        if name == "cvars" {
+            for line in self.help() {
+                info!(target: "console", "{}", line);
+            }
+            return Ok(());
+        }
+        if let Some(var) = self.vars.get(name) {
+            return match args {
+                [] => {
+                    info!(target: "console", "{} = {} ({})", name, var.value, var.help);
+                    Ok(())
+                }
+                _ => self.set(name, &args.join(" ")),
+            };
+        }
+        match self.commands.get_mut(name) {
+            Some(command) => (command.run)(resources, args),
+            None => Err(format!("unknown command or variable {}, see cvars", name)),
+        }
+    }
+}
+
+// The registry on `engine`, created on first use so plugins can register in any order.
+pub fn cvars(engine: &mut Engine) -> &mut Cvars {
+    let resources = engine.resources_mut();
+    if !resources.contains::<Cvars>() {
+        resources.insert(Cvars::default());
+    }
+    resources.get_mut::<Cvars>().unwrap()
+}
+
+// Loads overrides from the config file and command line, runs console commands and registers
+// the engine's built-in commands.
+pub struct CvarsPlugin;
+
+impl Plugin for CvarsPlugin {
+    fn build(&self, engine: &mut Engine) {
+        let cvars = cvars(engine);
+        match Config::load_default() {
+            Ok(config) => cvars.apply_config(&config),
+            Err(err) => warn!("Failed to read the config file: {}", err),
+        }
+        cvars.apply_args(&env::args().skip(1).collect::<Vec<_>>());
+
+        cvars.register_command(
+            "profile",
+            "profile <start|stop>, the CPU profiler",
+            |_, args| {
+                match args {
+                    ["start"] => profiling::set_enabled(true),
+                    ["stop"] => profiling::set_enabled(false),
+                    _ => return Err("usage: profile <start|stop>".to_owned()),
+                }
+                Ok(())
+            },
+        );
+        cvars.register_command("crash_report", "writes a crash report bundle", |_, _| {
+            let path = crash_report::write_bundle("requested from the console")
+                .map_err(|err| err.to_string())?;
+            info!(target: "console", "Crash report written to {}", path.display());
+            Ok(())
+        });
+
+        engine.add_system(|resources: &mut Resources| {
+            let Some(console) = resources.get_mut::<Console>() else {
+                return;
+            };
+            let lines: Vec<String> = console.drain_commands().collect();
+            for line in lines {
+                if let Err(err) = Cvars::execute(resources, &line) {
+                    warn!(target: "console", "{}", err);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_apply_on_register() {
+        let mut cvars = Cvars::default();
+        cvars.apply_args(&["--playback", "+r.vsync=false", "+sim.tickrate=30"]);
+        cvars.register("r.vsync", true, "wait for vertical sync");
+        cvars.register("sim.tickrate", 60i64, "sim ticks per second");
+        assert_eq!(cvars.get_bool("r.vsync"), Some(false));
+        assert_eq!(cvars.get_int("sim.tickrate"), Some(30));
+        assert!(cvars.set("sim.tickrate", "fast").is_err());
+
+        let mut resources = Resources::default();
+        resources.insert(cvars);
+        Cvars::execute(&mut resources, "r.vsync on").unwrap();
+        assert!(Cvars::execute(&mut resources, "spawn").is_err());
+        let cvars = resources.get::<Cvars>().unwrap();
+        assert_eq!(cvars.get_bool("r.vsync"), Some(true));
+    }
+
+    #[test]
+    fn rollback_drops_later_commands() {
+        let mut cvars = Cvars::default();
+        cvars.register_command("quit", "quits", |_, _| Ok(()));
+        let mark = cvars.mark();
+        cvars.register_command("spawn", "spawns", |_, _| Ok(()));
+        cvars.register_command("quit", "quits differently", |_, _| Ok(()));
+        cvars.rollback(mark);
+        assert!(cvars.commands.is_empty());
+    }
+}
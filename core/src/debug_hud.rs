@@ -0,0 +1,150 @@
+//! Debug HUD with engine vitals, toggled with F2. Values come from the metrics registry and are
+//! refreshed twice a second into text lines for the overlay to draw.
+
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use winit::keyboard::KeyCode;
+
+use crate::{
+    engine::{Engine, Plugin, Resources},
+    gpu_memory,
+    input::InputEvent,
+    metrics::{self, Metric},
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct DebugHud {
+    visible: bool,
+    lines: Vec<String>,
+    // Counter values at the last refresh, for the per second rates.
+    last_refresh: Instant,
+    last_frames: u64,
+    last_ticks: u64,
+}
+
+impl Default for DebugHud {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            lines: Vec::new(),
+            last_refresh: Instant::now(),
+            last_frames: counter("render_frames_total"),
+            last_ticks: counter("sim_ticks_total"),
+        }
+    }
+}
+
+fn counter(name: &str) -> u64 {
+    match metrics::get(name) {
+        Some(Metric::Counter(count)) => count,
+        _ => 0,
+    }
+}
+
+// Gauges that aren't published yet show as "-".
+fn gauge(name: &str) -> String {
+    match metrics::get(name) {
+        Some(Metric::Gauge(value)) => format!("{}", value),
+        Some(Metric::Counter(count)) => format!("{}", count),
+        _ => "-".to_owned(),
+    }
+}
+
+fn scaled(name: &str, scale: f64) -> String {
+    match metrics::get(name) {
+        Some(Metric::Gauge(value)) => format!("{:.1}", value * scale),
+        _ => "-".to_owned(),
+    }
+}
+
+impl DebugHud {
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    fn refresh(&mut self) {
+        let elapsed = self.last_refresh.elapsed();
+        if elapsed < REFRESH_INTERVAL {
+            return;
+        }
+        let (frames, ticks) = (counter("render_frames_total"), counter("sim_ticks_total"));
+        let seconds = elapsed.as_secs_f64();
+        let fps = frames.saturating_sub(self.last_frames) as f64 / seconds;
+        let tps = ticks.saturating_sub(self.last_ticks) as f64 / seconds;
+        (self.last_refresh, self.last_frames, self.last_ticks) = (Instant::now(), frames, ticks);
+        if !self.visible {
+            return;
+        }
+
+        let vram_mib = gpu_memory::total_allocated() as f64 / (1024.0 * 1024.0);
+        self.lines = vec![
+            format!("fps {:.1}  ticks/s {:.1}", fps, tps),
+            format!("entities {}", gauge("ecs_entities")),
+            format!(
+                "draw calls {}  passes {}",
+                gauge("render_draw_calls"),
+                gauge("render_passes_total")
+            ),
+            format!("vram {:.1} MiB", vram_mib),
+            format!("asset queue {}", gauge("assets_queued")),
+            format!(
+                "net rtt {} ms  jitter {} ms  loss {}%",
+                scaled("net_rtt_seconds", 1000.0),
+                scaled("net_jitter_seconds", 1000.0),
+                scaled("net_packet_loss", 100.0)
+            ),
+            format!(
+                "net in {} KiB/s  out {} KiB/s",
+                scaled("net_received_bytes_per_second", 1.0 / 1024.0),
+                scaled("net_sent_bytes_per_second", 1.0 / 1024.0)
+            ),
+        ];
+    }
+
+    fn handle_input(&mut self, event: &InputEvent) -> bool {
+        if let InputEvent::Key {
+            code: KeyCode::F2,
+            pressed,
+        } = event
+        {
+            if *pressed {
+                self.toggle();
+            }
+            return true;
+        }
+        false
+    }
+}
+
+pub struct DebugHudPlugin;
+
+impl Plugin for DebugHudPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(DebugHud::default())
+            .add_system(|resources: &mut Resources| {
+                if let Some(hud) = resources.get_mut::<DebugHud>() {
+                    hud.refresh();
+                }
+            })
+            .add_input_handler(|resources: &mut Resources, event: &InputEvent| {
+                resources
+                    .get_mut::<DebugHud>()
+                    .is_some_and(|hud| hud.handle_input(event))
+            });
+    }
+}
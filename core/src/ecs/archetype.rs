@@ -0,0 +1,435 @@
+use std::{cell::UnsafeCell, collections::HashMap, ptr::NonNull};
+
+use super::{
+    component::{Column, Component, ComponentTicks, ComponentType},
+    entity::Entity,
+};
+
+// Every entity with exactly the same set of component types, stored column by column so
+// iterating one component touches contiguous memory. Rows line up across the columns and
+// `entities`.
+pub struct Archetype {
+    // Sorted.
+    types: Vec<ComponentType>,
+    // One per type, in the same order.
+    columns: Vec<Box<dyn Column>>,
+    // When each component was added and changed, lined up with `columns`.
+    ticks: Vec<Vec<UnsafeCell<ComponentTicks>>>,
+    entities: Vec<Entity>,
+}
+
+impl Archetype {
+    fn new(types: Vec<ComponentType>, columns: Vec<Box<dyn Column>>) -> Self {
+        Archetype {
+            ticks: columns.iter().map(|_| Vec::new()).collect(),
+            types,
+            columns,
+            entities: Vec::new(),
+        }
+    }
+
+    pub fn types(&self) -> &[ComponentType] {
+        &self.types
+    }
+
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn contains<T: Component>(&self) -> bool {
+        self.column_index(ComponentType::of::<T>()).is_some()
+    }
+
+    pub fn column<T: Component>(&self) -> Option<&[T]> {
+        let index = self.column_index(ComponentType::of::<T>())?;
+        self.columns[index]
+            .as_any()
+            .downcast_ref::<Vec<UnsafeCell<T>>>()
+            .map(|cells| values(cells))
+    }
+
+    pub fn ticks<T: Component>(&self) -> Option<&[ComponentTicks]> {
+        let index = self.column_index(ComponentType::of::<T>())?;
+        Some(values(&self.ticks[index]))
+    }
+
+    // Writes through here aren't seen as changes, `EcsWorld::query_mut` and queries track them.
+    pub fn column_mut<T: Component>(&mut self) -> Option<&mut [T]> {
+        self.column_with_entities_mut().map(|(_, column)| column)
+    }
+
+    // The column along with which entity each row is.
+    pub fn column_with_entities_mut<T: Component>(&mut self) -> Option<(&[Entity], &mut [T])> {
+        let index = self.column_index(ComponentType::of::<T>())?;
+        let column = self.columns[index]
+            .as_any_mut()
+            .downcast_mut::<Vec<UnsafeCell<T>>>()?;
+        Some((&self.entities, values_mut(column)))
+    }
+
+    // For queries fetching several columns mutably at once. The components are in cells, so
+    // systems running side by side can each write their own columns while sharing the archetype.
+    pub(crate) fn column_ptr<T: Component>(&self) -> Option<NonNull<T>> {
+        let index = self.column_index(ComponentType::of::<T>())?;
+        let column = self.columns[index]
+            .as_any()
+            .downcast_ref::<Vec<UnsafeCell<T>>>()?;
+        NonNull::new(UnsafeCell::raw_get(column.as_ptr()))
+    }
+
+    // The ticks to go with `column_ptr`.
+    pub(crate) fn ticks_ptr<T: Component>(&self) -> Option<NonNull<ComponentTicks>> {
+        let index = self.column_index(ComponentType::of::<T>())?;
+        NonNull::new(UnsafeCell::raw_get(self.ticks[index].as_ptr()))
+    }
+
+    // Every row of the column counts as changed at `tick`.
+    pub(crate) fn set_changed<T: Component>(&mut self, tick: u64) {
+        if let Some(index) = self.column_index(ComponentType::of::<T>()) {
+            for ticks in &mut self.ticks[index] {
+                ticks.get_mut().changed = tick;
+            }
+        }
+    }
+
+    fn column_index(&self, ty: ComponentType) -> Option<usize> {
+        self.types.binary_search(&ty).ok()
+    }
+
+    // Removes `row` from every column, returns the entity that moved into it, if any.
+    fn swap_remove(&mut self, row: usize) -> Option<Entity> {
+        for column in &mut self.columns {
+            column.swap_remove(row);
+        }
+        for ticks in &mut self.ticks {
+            ticks.swap_remove(row);
+        }
+        self.entities.swap_remove(row);
+        self.entities.get(row).copied()
+    }
+}
+
+// `UnsafeCell<T>` has the same layout as `T`. Reading is fine as long as nothing writes through
+// `column_ptr` or `ticks_ptr` meanwhile, which systems' declared access rules out.
+fn values<T>(cells: &[UnsafeCell<T>]) -> &[T] {
+    unsafe { &*(cells as *const [UnsafeCell<T>] as *const [T]) }
+}
+
+fn values_mut<T>(cells: &mut [UnsafeCell<T>]) -> &mut [T] {
+    unsafe { &mut *(cells as *mut [UnsafeCell<T>] as *mut [T]) }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Location {
+    archetype: usize,
+    row: usize,
+}
+
+// The components of every entity in a world, grouped by archetype. Entities are always in one,
+// those without components in the empty archetype. Doesn't check entities are alive, the world
+// does that before it gets here.
+pub(crate) struct Storage {
+    archetypes: Vec<Archetype>,
+    by_types: HashMap<Vec<ComponentType>, usize>,
+    // By entity index.
+    locations: Vec<Option<Location>>,
+}
+
+impl Storage {
+    pub(crate) fn new() -> Self {
+        Storage {
+            archetypes: vec![Archetype::new(Vec::new(), Vec::new())],
+            by_types: HashMap::from([(Vec::new(), 0)]),
+            locations: Vec::new(),
+        }
+    }
+
+    pub(crate) fn archetypes(&self) -> &[Archetype] {
+        &self.archetypes
+    }
+
+    pub(crate) fn archetypes_mut(&mut self) -> &mut [Archetype] {
+        &mut self.archetypes
+    }
+
+    pub(crate) fn spawn(&mut self, entity: Entity) {
+        let index = entity.id().index() as usize;
+        if self.locations.len() <= index {
+            self.locations.resize(index + 1, None);
+        }
+        self.archetypes[0].entities.push(entity);
+        self.locations[index] = Some(Location {
+            archetype: 0,
+            row: self.archetypes[0].len() - 1,
+        });
+    }
+
+    pub(crate) fn despawn(&mut self, entity: Entity) {
+        if let Some(location) = self.take_location(entity) {
+            let moved = self.archetypes[location.archetype].swap_remove(location.row);
+            self.moved(moved, location);
+        }
+    }
+
+    // A replaced component counts as changed at `tick`, a new one as added.
+    pub(crate) fn insert<T: Component>(&mut self, entity: Entity, component: T, tick: u64) {
+        let Some(location) = self.location(entity) else {
+            return;
+        };
+        let ty = ComponentType::of::<T>();
+        let archetype = &mut self.archetypes[location.archetype];
+        if let Some(index) = archetype.column_index(ty) {
+            archetype.column_mut::<T>().unwrap()[location.row] = component;
+            archetype.ticks[index][location.row].get_mut().changed = tick;
+            return;
+        }
+        let from = &self.archetypes[location.archetype];
+        let mut types = from.types.clone();
+        let position = types.binary_search(&ty).unwrap_err();
+        types.insert(position, ty);
+        let to = self.archetype(
+            types,
+            |from| {
+                let mut columns: Vec<_> =
+                    from.columns.iter().map(|column| column.empty()).collect();
+                columns.insert(position, Box::new(Vec::<UnsafeCell<T>>::new()));
+                columns
+            },
+            location.archetype,
+        );
+        self.move_entity(entity, location, to, None);
+        let to = &mut self.archetypes[to];
+        to.columns[position]
+            .as_any_mut()
+            .downcast_mut::<Vec<UnsafeCell<T>>>()
+            .expect("column of another type")
+            .push(UnsafeCell::new(component));
+        to.ticks[position].push(UnsafeCell::new(ComponentTicks::new(tick)));
+    }
+
+    pub(crate) fn remove<T: Component>(&mut self, entity: Entity) -> Option<T> {
+        let location = self.location(entity)?;
+        let ty = ComponentType::of::<T>();
+        let from = &mut self.archetypes[location.archetype];
+        let position = from.column_index(ty)?;
+        let component = from.columns[position]
+            .as_any_mut()
+            .downcast_mut::<Vec<UnsafeCell<T>>>()
+            .expect("column of another type")
+            .swap_remove(location.row)
+            .into_inner();
+        from.ticks[position].swap_remove(location.row);
+        let mut types = from.types.clone();
+        types.remove(position);
+        let to = self.archetype(
+            types,
+            |from| {
+                let mut columns: Vec<_> =
+                    from.columns.iter().map(|column| column.empty()).collect();
+                columns.remove(position);
+                columns
+            },
+            location.archetype,
+        );
+        self.move_entity(entity, location, to, Some(ty));
+        Some(component)
+    }
+
+    pub(crate) fn get<T: Component>(&self, entity: Entity) -> Option<&T> {
+        let location = self.location(entity)?;
+        self.archetypes[location.archetype]
+            .column::<T>()
+            .map(|column| &column[location.row])
+    }
+
+    // The component counts as changed at `tick`.
+    pub(crate) fn get_mut<T: Component>(&mut self, entity: Entity, tick: u64) -> Option<&mut T> {
+        let location = self.location(entity)?;
+        let archetype = &mut self.archetypes[location.archetype];
+        let index = archetype.column_index(ComponentType::of::<T>())?;
+        archetype.ticks[index][location.row].get_mut().changed = tick;
+        archetype
+            .column_mut::<T>()
+            .map(|column| &mut column[location.row])
+    }
+
+    // Like `get_mut` without borrowing the storage mutably, see `Archetype::column_ptr`.
+    pub(crate) fn get_ptr<T: Component>(&self, entity: Entity, tick: u64) -> Option<NonNull<T>> {
+        let location = self.location(entity)?;
+        let archetype = &self.archetypes[location.archetype];
+        let column = archetype.column_ptr::<T>()?;
+        unsafe {
+            (*archetype.ticks_ptr::<T>()?.as_ptr().add(location.row)).changed = tick;
+            Some(column.add(location.row))
+        }
+    }
+
+    pub(crate) fn ticks<T: Component>(&self, entity: Entity) -> Option<ComponentTicks> {
+        let location = self.location(entity)?;
+        self.archetypes[location.archetype]
+            .ticks::<T>()
+            .map(|ticks| ticks[location.row])
+    }
+
+    pub(crate) fn types(&self, entity: Entity) -> &[ComponentType] {
+        self.location(entity)
+            .map_or(&[], |location| &self.archetypes[location.archetype].types)
+    }
+
+    fn location(&self, entity: Entity) -> Option<Location> {
+        self.locations
+            .get(entity.id().index() as usize)
+            .copied()
+            .flatten()
+    }
+
+    fn take_location(&mut self, entity: Entity) -> Option<Location> {
+        self.locations
+            .get_mut(entity.id().index() as usize)
+            .and_then(Option::take)
+    }
+
+    // Whoever got swapped into `location` lives there now.
+    fn moved(&mut self, moved: Option<Entity>, location: Location) {
+        if let Some(moved) = moved {
+            self.locations[moved.id().index() as usize] = Some(location);
+        }
+    }
+
+    // The archetype with exactly `types`, created with `columns` from the one at `like` if
+    // there isn't one yet.
+    fn archetype(
+        &mut self,
+        types: Vec<ComponentType>,
+        columns: impl FnOnce(&Archetype) -> Vec<Box<dyn Column>>,
+        like: usize,
+    ) -> usize {
+        if let Some(index) = self.by_types.get(&types) {
+            return *index;
+        }
+        let columns = columns(&self.archetypes[like]);
+        self.archetypes.push(Archetype::new(types.clone(), columns));
+        let index = self.archetypes.len() - 1;
+        self.by_types.insert(types, index);
+        index
+    }
+
+    // Moves the row's components in every column both archetypes share and drops the others,
+    // except `taken`, which the caller already took out of its column. `to` ends up with the
+    // entity in its last row, a column only `to` has is left to the caller to fill.
+    fn move_entity(
+        &mut self,
+        entity: Entity,
+        location: Location,
+        to: usize,
+        taken: Option<ComponentType>,
+    ) {
+        let [from_archetype, to_archetype] = pair_mut(&mut self.archetypes, location.archetype, to);
+        let columns = from_archetype
+            .columns
+            .iter_mut()
+            .zip(&mut from_archetype.ticks);
+        for (ty, (column, ticks)) in from_archetype.types.iter().zip(columns) {
+            if Some(*ty) == taken {
+                continue;
+            }
+            match to_archetype.column_index(*ty) {
+                Some(index) => {
+                    column.move_row(location.row, to_archetype.columns[index].as_mut());
+                    to_archetype.ticks[index].push(ticks.swap_remove(location.row));
+                }
+                None => {
+                    column.swap_remove(location.row);
+                    ticks.swap_remove(location.row);
+                }
+            }
+        }
+        from_archetype.entities.swap_remove(location.row);
+        let moved = from_archetype.entities.get(location.row).copied();
+        to_archetype.entities.push(entity);
+        let row = to_archetype.len() - 1;
+        self.moved(moved, location);
+        self.locations[entity.id().index() as usize] = Some(Location { archetype: to, row });
+    }
+}
+
+fn pair_mut<T>(items: &mut [T], a: usize, b: usize) -> [&mut T; 2] {
+    assert_ne!(a, b);
+    if a < b {
+        let (left, right) = items.split_at_mut(b);
+        [&mut left[a], &mut right[0]]
+    } else {
+        let (left, right) = items.split_at_mut(a);
+        [&mut right[0], &mut left[b]]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ecs::EcsWorld;
+
+    #[derive(Debug, PartialEq)]
+    struct Position([f32; 2]);
+    #[derive(Debug, PartialEq)]
+    struct Velocity([f32; 2]);
+
+    #[test]
+    fn entities_move_between_archetypes() {
+        let mut world = EcsWorld::new();
+        let still = world.spawn();
+        let moving = world.spawn();
+        let other = world.spawn();
+        for (entity, x) in [(still, 0.0), (moving, 1.0), (other, 2.0)] {
+            world.insert(entity, Position([x, 0.0]));
+        }
+        world.insert(moving, Velocity([0.0, 1.0]));
+        world.insert(other, Velocity([0.0, 2.0]));
+
+        // Positions are split over two archetypes, each contiguous.
+        let with_velocity = world
+            .archetypes()
+            .iter()
+            .find(|archetype| archetype.contains::<Velocity>())
+            .unwrap();
+        assert_eq!(with_velocity.entities(), [moving, other]);
+        assert_eq!(
+            with_velocity.column::<Position>().unwrap(),
+            [Position([1.0, 0.0]), Position([2.0, 0.0])]
+        );
+
+        for archetype in world.archetypes_mut() {
+            let Some(velocities) = archetype.column::<Velocity>() else {
+                continue;
+            };
+            let velocities: Vec<_> = velocities.iter().map(|velocity| velocity.0).collect();
+            let positions = archetype.column_mut::<Position>().unwrap();
+            for (position, velocity) in positions.iter_mut().zip(velocities) {
+                position.0[1] += velocity[1];
+            }
+        }
+        assert_eq!(world.get::<Position>(other), Some(&Position([2.0, 2.0])));
+
+        // Removing the first moves the last into its row.
+        assert_eq!(world.remove::<Velocity>(moving), Some(Velocity([0.0, 1.0])));
+        assert_eq!(world.remove::<Velocity>(moving), None);
+        assert_eq!(world.get::<Velocity>(other), Some(&Velocity([0.0, 2.0])));
+        assert_eq!(world.get::<Position>(moving), Some(&Position([1.0, 1.0])));
+        assert!(world.despawn(still));
+        assert_eq!(world.get::<Position>(moving), Some(&Position([1.0, 1.0])));
+        let mut positions: Vec<_> = world
+            .query::<Position>()
+            .map(|(e, p)| (e, p.0[0]))
+            .collect();
+        positions.sort_by_key(|(entity, _)| *entity);
+        assert_eq!(positions, [(moving, 1.0), (other, 2.0)]);
+        assert_eq!(world.get::<Position>(still), None);
+    }
+}
@@ -0,0 +1,184 @@
+use super::{component::Component, ecs_world::EcsWorld, entity::Entity};
+
+type Command = Box<dyn FnOnce(&mut EcsWorld) + Send>;
+
+// Structural changes queued while the world is borrowed, to apply once it isn't. The schedule
+// gives every system one and applies them after the system's stage.
+#[derive(Default)]
+pub struct CommandQueue {
+    commands: Vec<Command>,
+}
+
+impl CommandQueue {
+    pub fn push(&mut self, command: impl FnOnce(&mut EcsWorld) + Send + 'static) {
+        self.commands.push(Box::new(command));
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    // Spawns the entities reserved so far, then runs the commands in the order they were queued.
+    pub fn apply(&mut self, world: &mut EcsWorld) {
+        world.flush();
+        for command in self.commands.drain(..) {
+            command(world);
+        }
+    }
+}
+
+// Queues changes to a world that's only borrowed shared. Commands on an entity that's despawned
+// by the time they're applied do nothing.
+pub struct Commands<'a> {
+    queue: &'a mut CommandQueue,
+    world: &'a EcsWorld,
+}
+
+impl<'a> Commands<'a> {
+    pub fn new(queue: &'a mut CommandQueue, world: &'a EcsWorld) -> Self {
+        Self { queue, world }
+    }
+
+    // The entity is reserved straight away, so its handle can be used in other commands, but
+    // only comes alive when the queue is applied.
+    pub fn spawn(&mut self) -> EntityCommands<'_, 'a> {
+        let entity = self.world.reserve_entity();
+        self.entity(entity)
+    }
+
+    pub fn entity(&mut self, entity: Entity) -> EntityCommands<'_, 'a> {
+        EntityCommands {
+            commands: self,
+            entity,
+        }
+    }
+
+    pub fn despawn(&mut self, entity: Entity) {
+        self.queue.push(move |world| {
+            world.despawn(entity);
+        });
+    }
+
+    pub fn despawn_recursive(&mut self, entity: Entity) {
+        self.queue.push(move |world| {
+            world.despawn_recursive(entity);
+        });
+    }
+
+    pub fn set_parent(&mut self, child: Entity, parent: Entity) {
+        self.queue.push(move |world| {
+            world.set_parent(child, parent);
+        });
+    }
+
+    pub fn remove_parent(&mut self, child: Entity) {
+        self.queue.push(move |world| {
+            world.remove_parent(child);
+        });
+    }
+
+    pub fn insert<T: Component>(&mut self, entity: Entity, component: T) {
+        self.queue.push(move |world| {
+            world.insert(entity, component);
+        });
+    }
+
+    pub fn remove<T: Component>(&mut self, entity: Entity) {
+        self.queue.push(move |world| {
+            world.remove::<T>(entity);
+        });
+    }
+
+    pub fn insert_resource<T: Component>(&mut self, resource: T) {
+        self.queue.push(move |world| {
+            world.insert_resource(resource);
+        });
+    }
+
+    pub fn remove_resource<T: Component>(&mut self) {
+        self.queue.push(|world| {
+            world.remove_resource::<T>();
+        });
+    }
+
+    // Anything else that needs the whole world.
+    pub fn add(&mut self, command: impl FnOnce(&mut EcsWorld) + Send + 'static) {
+        self.queue.push(command);
+    }
+}
+
+pub struct EntityCommands<'c, 'a> {
+    commands: &'c mut Commands<'a>,
+    entity: Entity,
+}
+
+impl EntityCommands<'_, '_> {
+    pub fn id(&self) -> Entity {
+        self.entity
+    }
+
+    pub fn insert<T: Component>(self, component: T) -> Self {
+        self.commands.insert(self.entity, component);
+        self
+    }
+
+    pub fn remove<T: Component>(self) -> Self {
+        self.commands.remove::<T>(self.entity);
+        self
+    }
+
+    pub fn set_parent(self, parent: Entity) -> Self {
+        self.commands.set_parent(self.entity, parent);
+        self
+    }
+
+    pub fn despawn(self) {
+        self.commands.despawn(self.entity);
+    }
+
+    pub fn despawn_recursive(self) {
+        self.commands.despawn_recursive(self.entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Health(u32);
+    struct Burning;
+
+    #[test]
+    fn commands_wait_for_apply() {
+        let mut world = EcsWorld::new();
+        let target = world.spawn();
+        world.insert(target, Health(3));
+        world.insert(target, Burning);
+
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        let spawned = commands.spawn().insert(Health(10)).id();
+        commands.entity(target).remove::<Burning>();
+        let doomed = commands.spawn().insert(Health(1)).id();
+        commands.despawn(doomed);
+        commands.insert_resource(7u32);
+        assert_eq!(queue.len(), 5);
+        // Nothing has happened yet, the spawned entity's handle just isn't alive.
+        assert!(!world.contains(spawned));
+        assert!(world.has::<Burning>(target));
+
+        queue.apply(&mut world);
+        assert!(queue.is_empty());
+        assert_eq!(world.get::<Health>(spawned).map(|h| h.0), Some(10));
+        assert!(!world.has::<Burning>(target));
+        assert!(!world.contains(doomed));
+        assert_eq!(world.res::<u32>(), Some(&7));
+        assert_eq!(world.len(), 2);
+        // Reserved slots don't collide with ones spawned directly.
+        assert!(![target, spawned, doomed].contains(&world.spawn()));
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    any::{self, Any, TypeId},
+    cell::UnsafeCell,
+};
+
+// Anything can be a component, the world only has to move it around and hand it to the sim
+// thread.
+pub trait Component: Any + Send {}
+
+impl<T: Any + Send> Component for T {}
+
+// Orders by the type, the name is only for debugging and tools.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct ComponentType {
+    id: TypeId,
+    name: &'static str,
+}
+
+impl ComponentType {
+    pub fn of<T: Component>() -> Self {
+        ComponentType {
+            id: TypeId::of::<T>(),
+            name: any::type_name::<T>(),
+        }
+    }
+
+    pub fn id(self) -> TypeId {
+        self.id
+    }
+
+    pub fn name(self) -> &'static str {
+        self.name
+    }
+}
+
+// The world's change ticks when a component was added and last written. A tick is "since"
+// another when it's newer, so the component changed after whoever looked at `since` ran.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct ComponentTicks {
+    pub added: u64,
+    pub changed: u64,
+}
+
+impl ComponentTicks {
+    pub fn new(tick: u64) -> Self {
+        ComponentTicks {
+            added: tick,
+            changed: tick,
+        }
+    }
+
+    pub fn is_added(&self, since: u64) -> bool {
+        self.added > since
+    }
+
+    pub fn is_changed(&self, since: u64) -> bool {
+        self.changed > since
+    }
+}
+
+// A type erased `Vec<T>` of one component type, a column of an archetype.
+pub(crate) trait Column: Send {
+    // An empty column of the same type, for a new archetype that has it too.
+    fn empty(&self) -> Box<dyn Column>;
+    // Drops the component in `row`, the last one takes its place.
+    fn swap_remove(&mut self, row: usize);
+    // Moves the component in `row` to the end of `to`, which holds the same type, the last one
+    // takes its place.
+    fn move_row(&mut self, row: usize, to: &mut dyn Column);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+// Each component in an `UnsafeCell`, so queries can write rows through a shared archetype, see
+// `Archetype::column_ptr`.
+impl<T: Component> Column for Vec<UnsafeCell<T>> {
+    fn empty(&self) -> Box<dyn Column> {
+        Box::new(Vec::<UnsafeCell<T>>::new())
+    }
+
+    fn swap_remove(&mut self, row: usize) {
+        Vec::swap_remove(self, row);
+    }
+
+    fn move_row(&mut self, row: usize, to: &mut dyn Column) {
+        let component = Vec::swap_remove(self, row);
+        to.as_any_mut()
+            .downcast_mut::<Vec<UnsafeCell<T>>>()
+            .expect("moved a component to a column of another type")
+            .push(component);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -1,5 +1,371 @@
+use std::{any::TypeId, collections::HashSet, ptr::NonNull};
+
 use crate::{
-    identifier::{GlobalId}
+    engine::Resources,
+    identifier::{GenerationalAllocator, GlobalId},
+};
+
+use super::{
+    archetype::{Archetype, Storage},
+    component::{Component, ComponentTicks, ComponentType},
+    entity::Entity,
+    events::Events,
+    hierarchy::{Children, Parent},
 };
 
-pub type WorldId = GlobalId;
\ No newline at end of file
+pub type WorldId = GlobalId;
+
+// The entities of one world and their components, plus singletons for state that belongs to
+// the world rather than any entity. Lives in the sim's resources and is only touched from the
+// sim thread, or the schedule's workers while it runs systems side by side.
+pub struct EcsWorld {
+    id: WorldId,
+    entities: GenerationalAllocator,
+    storage: Storage,
+    resources: Resources,
+    // Updates each of the `Events` added to `resources`.
+    event_updates: Vec<fn(&mut Resources)>,
+    // What inserts and writes are stamped with, see `ComponentTicks`.
+    change_tick: u64,
+    // Queries made straight from the world see changes since this.
+    last_change_tick: u64,
+}
+
+// The world's resources and events at some point, see `EcsWorld::mark`.
+pub struct WorldMark {
+    resources: HashSet<TypeId>,
+    event_updates: usize,
+}
+
+impl Default for EcsWorld {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EcsWorld {
+    pub fn new() -> Self {
+        EcsWorld {
+            id: GlobalId::allocate().expect("Out of global ids"),
+            entities: GenerationalAllocator::new(),
+            storage: Storage::new(),
+            resources: Resources::default(),
+            event_updates: Vec::new(),
+            change_tick: 1,
+            last_change_tick: 0,
+        }
+    }
+
+    pub fn id(&self) -> WorldId {
+        self.id
+    }
+
+    pub fn change_tick(&self) -> u64 {
+        self.change_tick
+    }
+
+    pub fn last_change_tick(&self) -> u64 {
+        self.last_change_tick
+    }
+
+    // Starts a new tick for changes to be stamped with and returns it.
+    pub fn increment_change_tick(&mut self) -> u64 {
+        self.change_tick += 1;
+        self.change_tick
+    }
+
+    // Queries made from the world afterwards only see changes from here on.
+    pub fn clear_trackers(&mut self) {
+        self.last_change_tick = self.change_tick;
+        self.increment_change_tick();
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        self.flush();
+        let entity = Entity::from_id(self.entities.allocate().expect("Out of entity ids"));
+        self.storage.spawn(entity);
+        entity
+    }
+
+    // An entity without components that isn't alive until the next `flush` or `spawn`, for
+    // handing out entities while the world is shared, see `Commands::spawn`.
+    pub fn reserve_entity(&self) -> Entity {
+        Entity::from_id(self.entities.reserve().expect("Out of entity ids"))
+    }
+
+    // Spawns the reserved entities.
+    pub fn flush(&mut self) {
+        for id in self.entities.flush() {
+            self.storage.spawn(Entity::from_id(id));
+        }
+    }
+
+    // Drops its components too, its children become roots, see `despawn_recursive`. Returns
+    // false if `entity` was already despawned.
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if !self.contains(entity) {
+            return false;
+        }
+        self.detach(entity);
+        self.entities.free(entity.id());
+        self.storage.despawn(entity);
+        true
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entities.is_alive(entity.id())
+    }
+
+    // Number of live entities.
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    // Adds the component or replaces the one it has. Returns false if `entity` is despawned, or
+    // for `Parent` and `Children`, which only change through `set_parent` and friends.
+    pub fn insert<T: Component>(&mut self, entity: Entity, component: T) -> bool {
+        if is_hierarchy::<T>() {
+            return false;
+        }
+        self.insert_unguarded(entity, component)
+    }
+
+    // None for `Parent` and `Children` too, see `insert`.
+    pub fn remove<T: Component>(&mut self, entity: Entity) -> Option<T> {
+        if is_hierarchy::<T>() {
+            return None;
+        }
+        self.remove_unguarded(entity)
+    }
+
+    // `insert` and `remove` for the hierarchy's own bookkeeping.
+    pub(super) fn insert_unguarded<T: Component>(&mut self, entity: Entity, component: T) -> bool {
+        if !self.contains(entity) {
+            return false;
+        }
+        self.storage.insert(entity, component, self.change_tick);
+        true
+    }
+
+    pub(super) fn remove_unguarded<T: Component>(&mut self, entity: Entity) -> Option<T> {
+        if !self.contains(entity) {
+            return None;
+        }
+        self.storage.remove(entity)
+    }
+
+    pub fn get<T: Component>(&self, entity: Entity) -> Option<&T> {
+        if !self.contains(entity) {
+            return None;
+        }
+        self.storage.get(entity)
+    }
+
+    // Counts as changing the component, whether or not it's written.
+    pub fn get_mut<T: Component>(&mut self, entity: Entity) -> Option<&mut T> {
+        if !self.contains(entity) {
+            return None;
+        }
+        self.storage.get_mut(entity, self.change_tick)
+    }
+
+    pub(crate) fn get_ptr<T: Component>(&self, entity: Entity) -> Option<NonNull<T>> {
+        if !self.contains(entity) {
+            return None;
+        }
+        self.storage.get_ptr(entity, self.change_tick)
+    }
+
+    pub fn ticks<T: Component>(&self, entity: Entity) -> Option<ComponentTicks> {
+        if !self.contains(entity) {
+            return None;
+        }
+        self.storage.ticks::<T>(entity)
+    }
+
+    pub fn has<T: Component>(&self, entity: Entity) -> bool {
+        self.get::<T>(entity).is_some()
+    }
+
+    // The entity's component types, sorted, empty if it's despawned.
+    pub fn component_types(&self, entity: Entity) -> &[ComponentType] {
+        if !self.contains(entity) {
+            return &[];
+        }
+        self.storage.types(entity)
+    }
+
+    // Every entity with a `T`, archetype by archetype.
+    pub fn query<T: Component>(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.storage.archetypes().iter().flat_map(|archetype| {
+            let column = archetype.column::<T>().unwrap_or(&[]);
+            archetype.entities().iter().copied().zip(column)
+        })
+    }
+
+    // Every component it visits counts as changed.
+    pub fn query_mut<T: Component>(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        let tick = self.change_tick;
+        self.storage
+            .archetypes_mut()
+            .iter_mut()
+            .filter_map(move |archetype| {
+                archetype.set_changed::<T>(tick);
+                archetype.column_with_entities_mut::<T>()
+            })
+            .flat_map(|(entities, column)| entities.iter().copied().zip(column))
+    }
+
+    // Adds the singleton or replaces the one there, which is returned.
+    pub fn insert_resource<T: Component>(&mut self, resource: T) -> Option<T> {
+        self.resources.insert(resource)
+    }
+
+    pub fn remove_resource<T: Component>(&mut self) -> Option<T> {
+        self.resources.remove()
+    }
+
+    pub fn contains_resource<T: Component>(&self) -> bool {
+        self.resources.contains::<T>()
+    }
+
+    pub fn res<T: Component>(&self) -> Option<&T> {
+        self.resources.get()
+    }
+
+    pub fn res_mut<T: Component>(&mut self) -> Option<&mut T> {
+        self.resources.get_mut()
+    }
+
+    // Adds an `Events<T>` resource that `update_events` updates, if there isn't one.
+    pub fn add_event<T: Component>(&mut self) {
+        if self.contains_resource::<Events<T>>() {
+            return;
+        }
+        self.insert_resource(Events::<T>::default());
+        self.event_updates.push(|resources| {
+            if let Some(events) = resources.get_mut::<Events<T>>() {
+                events.update();
+            }
+        });
+    }
+
+    // Drops the events sent before the last update, the schedule does this every run.
+    pub fn update_events(&mut self) {
+        for update in &self.event_updates {
+            update(&mut self.resources);
+        }
+    }
+
+    pub(crate) fn resources_mut(&mut self) -> &mut Resources {
+        &mut self.resources
+    }
+
+    // Remembers the resources and events added so far for `rollback`. Hot reload uses it to drop
+    // what a game library added before unloading it.
+    pub fn mark(&self) -> WorldMark {
+        WorldMark {
+            resources: self.resources.types(),
+            event_updates: self.event_updates.len(),
+        }
+    }
+
+    // Drops the resources and events added since `mark`. Resources that existed at the mark are
+    // kept even if they were replaced since.
+    pub fn rollback(&mut self, mark: &WorldMark) {
+        self.event_updates.truncate(mark.event_updates);
+        self.resources.retain_types(&mark.resources);
+    }
+
+    // Drops every component, the entities stay alive without any.
+    pub fn clear_components(&mut self) {
+        self.flush();
+        let entities: Vec<_> = self
+            .storage
+            .archetypes()
+            .iter()
+            .flat_map(|archetype| archetype.entities().iter().copied())
+            .collect();
+        self.storage = Storage::new();
+        for entity in entities {
+            self.storage.spawn(entity);
+        }
+    }
+
+    // Each archetype's columns line up row by row, `Query` fetches several at once from them.
+    pub fn archetypes(&self) -> &[Archetype] {
+        self.storage.archetypes()
+    }
+
+    pub fn archetypes_mut(&mut self) -> &mut [Archetype] {
+        self.storage.archetypes_mut()
+    }
+}
+
+fn is_hierarchy<T: Component>() -> bool {
+    TypeId::of::<T>() == TypeId::of::<Parent>() || TypeId::of::<T>() == TypeId::of::<Children>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn despawned_entities_stay_dead() {
+        let mut world = EcsWorld::new();
+        let first = world.spawn();
+        let second = world.spawn();
+        assert!(world.contains(first) && world.contains(second));
+
+        assert!(world.despawn(first));
+        assert!(!world.despawn(first));
+        assert!(!world.contains(first));
+
+        // The slot is reused, the old handle still doesn't match it.
+        let third = world.spawn();
+        assert_eq!(third.id().index(), first.id().index());
+        assert!(world.contains(third) && !world.contains(first));
+        assert_eq!(world.len(), 2);
+        assert_eq!(Entity::from_bits(third.to_bits()), third);
+        assert_ne!(world.id(), EcsWorld::new().id());
+    }
+
+    #[test]
+    fn resources_are_singletons() {
+        struct Gravity(f32);
+
+        let mut world = EcsWorld::new();
+        assert!(world.insert_resource(Gravity(9.8)).is_none());
+        world.res_mut::<Gravity>().unwrap().0 = 1.6;
+        assert_eq!(world.insert_resource(Gravity(3.7)).map(|g| g.0), Some(1.6));
+        assert_eq!(world.res::<Gravity>().map(|g| g.0), Some(3.7));
+        // Resources aren't components of any entity.
+        assert_eq!(world.len(), 0);
+        assert_eq!(world.remove_resource::<Gravity>().map(|g| g.0), Some(3.7));
+        assert!(!world.contains_resource::<Gravity>());
+    }
+
+    #[test]
+    fn rollback_keeps_entities() {
+        struct Gravity;
+
+        let mut world = EcsWorld::new();
+        world.add_event::<u32>();
+        let mark = world.mark();
+        world.add_event::<u64>();
+        world.insert_resource(Gravity);
+        let entity = world.spawn();
+        world.insert(entity, 1u8);
+        world.rollback(&mark);
+        world.clear_components();
+        assert!(world.contains_resource::<Events<u32>>());
+        assert!(!world.contains_resource::<Events<u64>>());
+        assert!(!world.contains_resource::<Gravity>());
+        assert!(world.contains(entity) && world.component_types(entity).is_empty());
+        assert_eq!(world.event_updates.len(), 1);
+    }
+}
@@ -0,0 +1,34 @@
+use std::fmt;
+
+use crate::identifier::GenerationalId;
+
+// A game object, just an ID that components hang off. Despawned entities' slots are reused with
+// a new generation, so an `Entity` kept around after despawn never refers to whatever spawned in
+// its place.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct Entity(GenerationalId);
+
+impl Entity {
+    pub(crate) fn from_id(id: GenerationalId) -> Self {
+        Entity(id)
+    }
+
+    pub fn id(self) -> GenerationalId {
+        self.0
+    }
+
+    // Stable, see `GenerationalId::to_bits`.
+    pub fn to_bits(self) -> u64 {
+        self.0.to_bits()
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        Entity(GenerationalId::from_bits(bits))
+    }
+}
+
+impl fmt::Display for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "entity {}", self.0)
+    }
+}
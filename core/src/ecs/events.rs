@@ -0,0 +1,165 @@
+// Messages of one type between systems, kept in the world as a resource. Events live for two
+// updates, the schedule updates them at the start of every run, so every system that runs each
+// time reads each event once, whether it runs before or after the system that sent it.
+pub struct Events<T> {
+    // Sent before the last update, dropped at the next.
+    previous: Vec<T>,
+    current: Vec<T>,
+    // How many events were sent before `previous`'s first.
+    start: usize,
+}
+
+impl<T> Default for Events<T> {
+    fn default() -> Self {
+        Self {
+            previous: Vec::new(),
+            current: Vec::new(),
+            start: 0,
+        }
+    }
+}
+
+impl<T> Events<T> {
+    pub fn send(&mut self, event: T) {
+        self.current.push(event);
+    }
+
+    pub fn send_batch(&mut self, events: impl IntoIterator<Item = T>) {
+        self.current.extend(events);
+    }
+
+    // Drops the events sent before the last update.
+    pub fn update(&mut self) {
+        self.start += self.previous.len();
+        std::mem::swap(&mut self.previous, &mut self.current);
+        self.current.clear();
+    }
+
+    // Drops every event, readers carry on from the next one sent.
+    pub fn clear(&mut self) {
+        self.start = self.sent();
+        self.previous.clear();
+        self.current.clear();
+    }
+
+    // Events still buffered.
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Every event ever sent.
+    fn sent(&self) -> usize {
+        self.start + self.len()
+    }
+}
+
+// Where a reader got to. Readers that fall more than an update behind miss events.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EventCursor {
+    read: usize,
+}
+
+impl EventCursor {
+    // A cursor that skips the events already sent.
+    pub fn at_end<T>(events: &Events<T>) -> Self {
+        Self {
+            read: events.sent(),
+        }
+    }
+
+    // The events sent since the cursor last read.
+    pub fn read<'e, T>(&mut self, events: &'e Events<T>) -> impl Iterator<Item = &'e T> {
+        let skip = self.read.saturating_sub(events.start);
+        self.read = events.sent();
+        events.previous.iter().chain(&events.current).skip(skip)
+    }
+
+    pub fn unread<T>(&self, events: &Events<T>) -> usize {
+        events.sent() - self.read.clamp(events.start, events.sent())
+    }
+}
+
+// A system's view of the events of one type, see `SystemWorld::event_reader`.
+pub struct EventReader<'a, T> {
+    events: &'a Events<T>,
+    cursor: &'a mut EventCursor,
+}
+
+impl<'a, T> EventReader<'a, T> {
+    pub fn new(events: &'a Events<T>, cursor: &'a mut EventCursor) -> Self {
+        Self { events, cursor }
+    }
+
+    pub fn read(&mut self) -> impl Iterator<Item = &'a T> {
+        self.cursor.read(self.events)
+    }
+
+    pub fn len(&self) -> usize {
+        self.cursor.unread(self.events)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Skips what's unread.
+    pub fn clear(&mut self) {
+        *self.cursor = EventCursor::at_end(self.events);
+    }
+}
+
+// A system's way to send events of one type, see `SystemWorld::event_writer`.
+pub struct EventWriter<'a, T> {
+    events: &'a mut Events<T>,
+}
+
+impl<'a, T> EventWriter<'a, T> {
+    pub fn new(events: &'a mut Events<T>) -> Self {
+        Self { events }
+    }
+
+    pub fn send(&mut self, event: T) {
+        self.events.send(event);
+    }
+
+    pub fn send_batch(&mut self, events: impl IntoIterator<Item = T>) {
+        self.events.send_batch(events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_last_two_updates() {
+        let mut events = Events::default();
+        let mut early = EventCursor::default();
+        events.send(1);
+        events.send(2);
+        assert!(early.read(&events).copied().eq([1, 2]));
+        let mut late = EventCursor::default();
+
+        events.update();
+        events.send(3);
+        assert_eq!(early.unread(&events), 1);
+        assert!(late.read(&events).copied().eq([1, 2, 3]));
+
+        // 1 and 2 are dropped now, a reader that missed them carries on from what's left.
+        events.update();
+        events.send(4);
+        let mut missed = EventCursor::default();
+        assert!(missed.read(&events).copied().eq([3, 4]));
+        assert!(early.read(&events).copied().eq([3, 4]));
+        assert_eq!(events.len(), 2);
+
+        let mut reader = EventReader::new(&events, &mut late);
+        assert_eq!(reader.len(), 1);
+        reader.clear();
+        assert!(reader.is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+use super::{ecs_world::EcsWorld, entity::Entity};
+
+// The entity this one hangs off. Only the world's hierarchy methods make, move and drop these,
+// which keep `Parent` and `Children` pointing at each other and free of cycles. Neither can be
+// cloned or built by hand, and the generic `insert` and `remove`, on the world or through
+// `Commands`, refuse them.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Parent(Entity);
+
+impl Parent {
+    pub fn get(&self) -> Entity {
+        self.0
+    }
+}
+
+// The entities hanging off this one, in the order they were added. Removed once the last one
+// goes. See `Parent` for how to keep the two in sync.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Children(Vec<Entity>);
+
+impl Children {
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.0.iter().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.0.contains(&entity)
+    }
+}
+
+impl EcsWorld {
+    pub fn parent(&self, entity: Entity) -> Option<Entity> {
+        self.get::<Parent>(entity).map(Parent::get)
+    }
+
+    pub fn children(&self, entity: Entity) -> &[Entity] {
+        self.get::<Children>(entity)
+            .map_or(&[], |children| &children.0)
+    }
+
+    // Children first, then their children and so on.
+    pub fn descendants(&self, entity: Entity) -> Vec<Entity> {
+        let mut descendants = self.children(entity).to_vec();
+        let mut next = 0;
+        while let Some(&descendant) = descendants.get(next) {
+            descendants.extend_from_slice(self.children(descendant));
+            next += 1;
+        }
+        descendants
+    }
+
+    // Moves `child` under `parent`, taking it from its old parent. Fails if either is despawned
+    // or `child` is `parent` or one of its ancestors.
+    pub fn set_parent(&mut self, child: Entity, parent: Entity) -> bool {
+        if !self.contains(child) || !self.contains(parent) || self.is_ancestor(child, parent) {
+            return false;
+        }
+        if self.parent(child) == Some(parent) {
+            return true;
+        }
+        self.remove_parent(child);
+        self.insert_unguarded(child, Parent(parent));
+        match self.get_mut::<Children>(parent) {
+            Some(children) => children.0.push(child),
+            None => {
+                self.insert_unguarded(parent, Children(vec![child]));
+            }
+        }
+        true
+    }
+
+    // Makes `child` a root again, returning its old parent.
+    pub fn remove_parent(&mut self, child: Entity) -> Option<Entity> {
+        let parent = self.remove_unguarded::<Parent>(child)?.0;
+        if let Some(children) = self.get_mut::<Children>(parent) {
+            children.0.retain(|&other| other != child);
+            if children.is_empty() {
+                self.remove_unguarded::<Children>(parent);
+            }
+        }
+        Some(parent)
+    }
+
+    // Despawns the entity and everything under it. Returns false if it was already despawned.
+    pub fn despawn_recursive(&mut self, entity: Entity) -> bool {
+        if !self.contains(entity) {
+            return false;
+        }
+        for descendant in self.descendants(entity) {
+            self.despawn(descendant);
+        }
+        self.despawn(entity)
+    }
+
+    // Whether `ancestor` is `entity` or above it.
+    fn is_ancestor(&self, ancestor: Entity, mut entity: Entity) -> bool {
+        loop {
+            if entity == ancestor {
+                return true;
+            }
+            match self.parent(entity) {
+                Some(parent) => entity = parent,
+                None => return false,
+            }
+        }
+    }
+
+    // Takes a despawning entity out of the hierarchy, its children become roots.
+    pub(super) fn detach(&mut self, entity: Entity) {
+        self.remove_parent(entity);
+        if let Some(children) = self.remove_unguarded::<Children>(entity) {
+            for child in children.0 {
+                self.remove_unguarded::<Parent>(child);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parents_and_children_stay_in_sync() {
+        let mut world = EcsWorld::new();
+        let [root, arm, hand, finger, other] = [(); 5].map(|_| world.spawn());
+        assert!(world.set_parent(arm, root));
+        assert!(world.set_parent(hand, arm));
+        assert!(world.set_parent(finger, hand));
+        assert_eq!(world.descendants(root), [arm, hand, finger]);
+        // No cycles.
+        assert!(!world.set_parent(root, finger));
+        assert!(!world.set_parent(arm, arm));
+
+        // Reparenting updates both sides.
+        assert!(world.set_parent(hand, other));
+        assert_eq!(world.parent(hand), Some(other));
+        assert!(world.children(arm).is_empty() && !world.has::<Children>(arm));
+        assert_eq!(world.children(other), [hand]);
+
+        // Plain despawns leave the children as roots, recursive ones take them along.
+        world.despawn(other);
+        assert_eq!(world.parent(hand), None);
+        assert!(world.set_parent(hand, arm));
+        assert!(world.despawn_recursive(root));
+        assert!([root, arm, hand, finger]
+            .iter()
+            .all(|&e| !world.contains(e)));
+        assert!(world.is_empty());
+    }
+
+    #[test]
+    fn generic_insert_and_remove_refuse_hierarchy_components() {
+        let mut world = EcsWorld::new();
+        let [a, b] = [(); 2].map(|_| world.spawn());
+        assert!(world.set_parent(b, a));
+        // Moving b's `Parent` onto a would make a its own grandparent.
+        assert_eq!(world.remove::<Parent>(b), None);
+        assert_eq!(world.remove::<Children>(a), None);
+        assert!(!world.insert(a, Parent(b)));
+        assert_eq!(world.parent(a), None);
+        assert_eq!(world.parent(b), Some(a));
+        assert!(!world.set_parent(a, b));
+    }
+}
@@ -1,3 +1,17 @@
+pub mod archetype;
+pub mod commands;
+pub mod component;
 pub mod ecs_world;
 pub mod entity;
-pub mod component;
\ No newline at end of file
+pub mod events;
+pub mod hierarchy;
+pub mod query;
+
+pub use archetype::Archetype;
+pub use commands::{CommandQueue, Commands, EntityCommands};
+pub use component::{Component, ComponentTicks, ComponentType};
+pub use ecs_world::{EcsWorld, WorldId, WorldMark};
+pub use entity::Entity;
+pub use events::{EventCursor, EventReader, EventWriter, Events};
+pub use hierarchy::{Children, Parent};
+pub use query::{Access, Added, Changed, Fetch, Filter, Mut, Query, ReadOnlyFetch, With, Without};
@@ -0,0 +1,693 @@
+//! Typed queries over a world's components. `Query<(Entity, &Position, &mut Velocity),
+//! Without<Frozen>>` visits every entity that has a position and a velocity and isn't frozen,
+//! archetype by archetype, fetching straight out of the columns:
+//!
+//! ```ignore
+//! let mut query = Query::<(&Position, &mut Velocity), With<Player>>::new(world);
+//! for (position, mut velocity) in query.iter_mut() {
+//!     velocity.0[1] -= GRAVITY * dt;
+//! }
+//! ```
+//!
+//! A query borrows the world for as long as it lives, mutably unless everything it fetches is
+//! read only, so the borrow checker keeps anything else from touching the components while its
+//! items are alive. Within one query, fetching a type mutably twice or both mutably and not is
+//! caught when the query is made, Rust can't tell two type parameters apart at compile time.
+//!
+//! Components fetched mutably come as `Mut`, which stamps the component as changed when it's
+//! written through. `Added<T>` and `Changed<T>` filter on those stamps, keeping entities whose
+//! `T` was added or changed since the system last ran, or for queries made straight from the
+//! world, since it last cleared its trackers:
+//!
+//! ```ignore
+//! for (entity, transform) in world.query::<(Entity, &Transform), Changed<Transform>>().iter_mut() {
+//!     propagate(entity, transform);
+//! }
+//! ```
+
+use std::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
+
+use super::{
+    archetype::Archetype,
+    component::{Component, ComponentTicks, ComponentType},
+    ecs_world::EcsWorld,
+    entity::Entity,
+};
+
+// The component types a query reads and writes.
+#[derive(Clone, Debug, Default)]
+pub struct Access {
+    reads: Vec<ComponentType>,
+    writes: Vec<ComponentType>,
+}
+
+impl Access {
+    pub fn read(&mut self, ty: ComponentType) {
+        self.reads.push(ty);
+    }
+
+    pub fn write(&mut self, ty: ComponentType) {
+        self.writes.push(ty);
+    }
+
+    pub fn reads(&self) -> &[ComponentType] {
+        &self.reads
+    }
+
+    pub fn writes(&self) -> &[ComponentType] {
+        &self.writes
+    }
+
+    // A type written more than once, or written and read.
+    pub fn conflict(&self) -> Option<ComponentType> {
+        self.writes.iter().enumerate().find_map(|(i, ty)| {
+            (self.writes[i + 1..].contains(ty) || self.reads.contains(ty)).then_some(*ty)
+        })
+    }
+
+    // Whether one of them writes something the other reads or writes.
+    pub fn conflicts_with(&self, other: &Access) -> bool {
+        let touches = |access: &Access, ty| access.reads.contains(ty) || access.writes.contains(ty);
+        self.writes.iter().any(|ty| touches(other, ty))
+            || other.writes.iter().any(|ty| touches(self, ty))
+    }
+
+    // Something `other` accesses that this doesn't allow, writing what this only reads say.
+    pub fn uncovered(&self, other: &Access) -> Option<ComponentType> {
+        let reads = other
+            .reads
+            .iter()
+            .find(|ty| !self.reads.contains(ty) && !self.writes.contains(ty));
+        let writes = other.writes.iter().find(|ty| !self.writes.contains(ty));
+        reads.or(writes).copied()
+    }
+}
+
+/// What a query fetches for each entity: `Entity`, `&T`, `&mut T`, `Option` of any of those or
+/// a tuple of them.
+///
+/// # Safety
+///
+/// `access` has to report every component `fetch` hands out, mutably if it does, since that's
+/// all that keeps two items from aliasing.
+pub unsafe trait Fetch {
+    type Item<'a>;
+    // Whatever `fetch` needs from one archetype, pointers to the start of its columns.
+    type Columns: Copy;
+
+    fn access(access: &mut Access);
+
+    fn matches(archetype: &Archetype) -> bool;
+
+    /// # Safety
+    ///
+    /// `archetype` matches and is valid for as long as the columns are used, and nothing else
+    /// accesses the components `access` reports meanwhile. Columns for read only fetches may
+    /// come from a shared borrow. Writes are stamped with `change_tick`.
+    unsafe fn columns(archetype: NonNull<Archetype>, change_tick: u64) -> Self::Columns;
+
+    /// # Safety
+    ///
+    /// `row` is in the archetype the columns came from, and each row is fetched once at a time
+    /// for fetches that write.
+    unsafe fn fetch<'a>(columns: Self::Columns, row: usize) -> Self::Item<'a>;
+}
+
+/// A fetch that only reads, so queries with it can share the world.
+///
+/// # Safety
+///
+/// The fetch never writes or hands out a mutable reference.
+pub unsafe trait ReadOnlyFetch: Fetch {}
+
+unsafe impl Fetch for Entity {
+    type Item<'a> = Entity;
+    type Columns = NonNull<Entity>;
+
+    fn access(_: &mut Access) {}
+
+    fn matches(_: &Archetype) -> bool {
+        true
+    }
+
+    unsafe fn columns(archetype: NonNull<Archetype>, _: u64) -> Self::Columns {
+        NonNull::from(archetype.as_ref().entities()).cast()
+    }
+
+    unsafe fn fetch<'a>(columns: Self::Columns, row: usize) -> Self::Item<'a> {
+        *columns.as_ptr().add(row)
+    }
+}
+
+unsafe impl ReadOnlyFetch for Entity {}
+
+unsafe impl<T: Component> Fetch for &T {
+    type Item<'a> = &'a T;
+    type Columns = NonNull<T>;
+
+    fn access(access: &mut Access) {
+        access.read(ComponentType::of::<T>());
+    }
+
+    fn matches(archetype: &Archetype) -> bool {
+        archetype.contains::<T>()
+    }
+
+    unsafe fn columns(archetype: NonNull<Archetype>, _: u64) -> Self::Columns {
+        let column = archetype
+            .as_ref()
+            .column::<T>()
+            .expect("fetched a missing column");
+        NonNull::from(column).cast()
+    }
+
+    unsafe fn fetch<'a>(columns: Self::Columns, row: usize) -> &'a T {
+        &*columns.as_ptr().add(row)
+    }
+}
+
+unsafe impl<T: Component> ReadOnlyFetch for &T {}
+
+unsafe impl<T: Component> Fetch for &mut T {
+    type Item<'a> = Mut<'a, T>;
+    type Columns = (NonNull<T>, NonNull<ComponentTicks>, u64);
+
+    fn access(access: &mut Access) {
+        access.write(ComponentType::of::<T>());
+    }
+
+    fn matches(archetype: &Archetype) -> bool {
+        archetype.contains::<T>()
+    }
+
+    unsafe fn columns(archetype: NonNull<Archetype>, change_tick: u64) -> Self::Columns {
+        let archetype = archetype.as_ref();
+        let missing = "fetched a missing column";
+        (
+            archetype.column_ptr::<T>().expect(missing),
+            archetype.ticks_ptr::<T>().expect(missing),
+            change_tick,
+        )
+    }
+
+    unsafe fn fetch<'a>((column, ticks, change_tick): Self::Columns, row: usize) -> Mut<'a, T> {
+        Mut {
+            value: &mut *column.as_ptr().add(row),
+            ticks: &mut *ticks.as_ptr().add(row),
+            change_tick,
+        }
+    }
+}
+
+// A component fetched mutably. Writing through it counts as changing it, reading doesn't.
+pub struct Mut<'a, T> {
+    value: &'a mut T,
+    ticks: &'a mut ComponentTicks,
+    change_tick: u64,
+}
+
+impl<'a, T> Mut<'a, T> {
+    pub fn ticks(&self) -> ComponentTicks {
+        *self.ticks
+    }
+
+    // The component as a plain reference, changed or not.
+    pub fn into_inner(self) -> &'a mut T {
+        self.ticks.changed = self.change_tick;
+        self.value
+    }
+}
+
+impl<T> Deref for Mut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T> DerefMut for Mut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.ticks.changed = self.change_tick;
+        self.value
+    }
+}
+
+// Matches whether or not the archetype has what's inside, None for those that don't.
+unsafe impl<Q: Fetch> Fetch for Option<Q> {
+    type Item<'a> = Option<Q::Item<'a>>;
+    type Columns = Option<Q::Columns>;
+
+    fn access(access: &mut Access) {
+        Q::access(access);
+    }
+
+    fn matches(_: &Archetype) -> bool {
+        true
+    }
+
+    unsafe fn columns(archetype: NonNull<Archetype>, change_tick: u64) -> Self::Columns {
+        Q::matches(archetype.as_ref()).then(|| Q::columns(archetype, change_tick))
+    }
+
+    unsafe fn fetch<'a>(columns: Self::Columns, row: usize) -> Self::Item<'a> {
+        columns.map(|columns| Q::fetch(columns, row))
+    }
+}
+
+unsafe impl<Q: ReadOnlyFetch> ReadOnlyFetch for Option<Q> {}
+
+// Which entities a query visits, on top of having what it fetches. Most filters only look at
+// the archetype, change filters look at each row.
+pub trait Filter {
+    // Whatever `keeps` needs from one archetype.
+    type Columns: Copy;
+
+    // The components the filter looks at, without fetching them.
+    fn access(_: &mut Access) {}
+
+    fn matches(archetype: &Archetype) -> bool;
+
+    fn columns(archetype: &Archetype) -> Self::Columns;
+
+    /// # Safety
+    ///
+    /// `archetype` matched and `row` is in it.
+    unsafe fn keeps(columns: Self::Columns, row: usize, since: u64) -> bool;
+}
+
+impl Filter for () {
+    type Columns = ();
+
+    fn matches(_: &Archetype) -> bool {
+        true
+    }
+
+    fn columns(_: &Archetype) {}
+
+    unsafe fn keeps(_: (), _: usize, _: u64) -> bool {
+        true
+    }
+}
+
+// Only entities that have a `T`, without fetching it.
+pub struct With<T>(PhantomData<T>);
+
+impl<T: Component> Filter for With<T> {
+    type Columns = ();
+
+    fn matches(archetype: &Archetype) -> bool {
+        archetype.contains::<T>()
+    }
+
+    fn columns(_: &Archetype) {}
+
+    unsafe fn keeps(_: (), _: usize, _: u64) -> bool {
+        true
+    }
+}
+
+// Only entities that don't have a `T`.
+pub struct Without<T>(PhantomData<T>);
+
+impl<T: Component> Filter for Without<T> {
+    type Columns = ();
+
+    fn matches(archetype: &Archetype) -> bool {
+        !archetype.contains::<T>()
+    }
+
+    fn columns(_: &Archetype) {}
+
+    unsafe fn keeps(_: (), _: usize, _: u64) -> bool {
+        true
+    }
+}
+
+// Only entities whose `T` was added since the query's tick.
+pub struct Added<T>(PhantomData<T>);
+
+impl<T: Component> Filter for Added<T> {
+    type Columns = NonNull<ComponentTicks>;
+
+    fn access(access: &mut Access) {
+        access.read(ComponentType::of::<T>());
+    }
+
+    fn matches(archetype: &Archetype) -> bool {
+        archetype.contains::<T>()
+    }
+
+    fn columns(archetype: &Archetype) -> Self::Columns {
+        archetype
+            .ticks_ptr::<T>()
+            .expect("filtered on a missing column")
+    }
+
+    unsafe fn keeps(columns: Self::Columns, row: usize, since: u64) -> bool {
+        (*columns.as_ptr().add(row)).is_added(since)
+    }
+}
+
+// Only entities whose `T` was added or written since the query's tick.
+pub struct Changed<T>(PhantomData<T>);
+
+impl<T: Component> Filter for Changed<T> {
+    type Columns = NonNull<ComponentTicks>;
+
+    fn access(access: &mut Access) {
+        access.read(ComponentType::of::<T>());
+    }
+
+    fn matches(archetype: &Archetype) -> bool {
+        archetype.contains::<T>()
+    }
+
+    fn columns(archetype: &Archetype) -> Self::Columns {
+        archetype
+            .ticks_ptr::<T>()
+            .expect("filtered on a missing column")
+    }
+
+    unsafe fn keeps(columns: Self::Columns, row: usize, since: u64) -> bool {
+        (*columns.as_ptr().add(row)).is_changed(since)
+    }
+}
+
+macro_rules! impl_query_for_tuple {
+    ($($name:ident),+) => {
+        #[allow(non_snake_case)]
+        unsafe impl<$($name: Fetch),+> Fetch for ($($name,)+) {
+            type Item<'a> = ($($name::Item<'a>,)+);
+            type Columns = ($($name::Columns,)+);
+
+            fn access(access: &mut Access) {
+                $($name::access(access);)+
+            }
+
+            fn matches(archetype: &Archetype) -> bool {
+                $($name::matches(archetype))&&+
+            }
+
+            unsafe fn columns(archetype: NonNull<Archetype>, change_tick: u64) -> Self::Columns {
+                ($($name::columns(archetype, change_tick),)+)
+            }
+
+            unsafe fn fetch<'a>(columns: Self::Columns, row: usize) -> Self::Item<'a> {
+                let ($($name,)+) = columns;
+                ($($name::fetch($name, row),)+)
+            }
+        }
+
+        unsafe impl<$($name: ReadOnlyFetch),+> ReadOnlyFetch for ($($name,)+) {}
+
+        #[allow(non_snake_case)]
+        impl<$($name: Filter),+> Filter for ($($name,)+) {
+            type Columns = ($($name::Columns,)+);
+
+            fn access(access: &mut Access) {
+                $($name::access(access);)+
+            }
+
+            fn matches(archetype: &Archetype) -> bool {
+                $($name::matches(archetype))&&+
+            }
+
+            fn columns(archetype: &Archetype) -> Self::Columns {
+                ($($name::columns(archetype),)+)
+            }
+
+            unsafe fn keeps(columns: Self::Columns, row: usize, since: u64) -> bool {
+                let ($($name,)+) = columns;
+                $($name::keeps($name, row, since))&&+
+            }
+        }
+    };
+}
+
+impl_query_for_tuple!(A);
+impl_query_for_tuple!(A, B);
+impl_query_for_tuple!(A, B, C);
+impl_query_for_tuple!(A, B, C, D);
+impl_query_for_tuple!(A, B, C, D, E);
+impl_query_for_tuple!(A, B, C, D, E, F);
+impl_query_for_tuple!(A, B, C, D, E, F, G);
+impl_query_for_tuple!(A, B, C, D, E, F, G, H);
+
+pub struct Query<'w, Q: Fetch, F: Filter = ()> {
+    archetypes: NonNull<[Archetype]>,
+    // Change filters keep what changed after this, writes are stamped with `change_tick`.
+    since: u64,
+    change_tick: u64,
+    world: PhantomData<&'w mut EcsWorld>,
+    fetch: PhantomData<(Q, F)>,
+}
+
+impl<'w, Q: Fetch, F: Filter> Query<'w, Q, F> {
+    // Panics if `Q` fetches a component mutably more than once or both mutably and not.
+    pub fn new(world: &'w mut EcsWorld) -> Self {
+        check_access::<Q>();
+        Self {
+            since: world.last_change_tick(),
+            change_tick: world.change_tick(),
+            archetypes: NonNull::from(world.archetypes_mut()),
+            world: PhantomData,
+            fetch: PhantomData,
+        }
+    }
+
+    /// For the schedule, which hands queries over the same archetypes to systems running at once
+    /// and has them see changes since each system last ran.
+    ///
+    /// # Safety
+    ///
+    /// The archetypes must outlive 'w, and nothing else may touch the components `Q` writes or
+    /// write the ones it reads while the query is alive.
+    pub(crate) unsafe fn from_raw(
+        archetypes: NonNull<[Archetype]>,
+        since: u64,
+        change_tick: u64,
+    ) -> Self {
+        check_access::<Q>();
+        Self {
+            archetypes,
+            since,
+            change_tick,
+            world: PhantomData,
+            fetch: PhantomData,
+        }
+    }
+
+    pub fn iter_mut(&mut self) -> QueryIter<'_, Q, F> {
+        QueryIter::new(self.archetypes, self.since, self.change_tick)
+    }
+
+    // Matching entities, without visiting them.
+    pub fn count(&self) -> usize {
+        self.matching()
+            .map(|archetype| {
+                let columns = F::columns(archetype);
+                (0..archetype.len())
+                    .filter(|&row| unsafe { F::keeps(columns, row, self.since) })
+                    .count()
+            })
+            .sum()
+    }
+
+    fn matching(&self) -> impl Iterator<Item = &Archetype> {
+        unsafe { self.archetypes.as_ref() }
+            .iter()
+            .filter(|archetype| Q::matches(archetype) && F::matches(archetype))
+    }
+}
+
+impl<'w, Q: ReadOnlyFetch, F: Filter> Query<'w, Q, F> {
+    // A read only query only needs to share the world.
+    pub fn read(world: &'w EcsWorld) -> Self {
+        check_access::<Q>();
+        Self {
+            archetypes: NonNull::from(world.archetypes()),
+            since: world.last_change_tick(),
+            change_tick: world.change_tick(),
+            world: PhantomData,
+            fetch: PhantomData,
+        }
+    }
+
+    pub fn iter(&self) -> QueryIter<'_, Q, F> {
+        QueryIter::new(self.archetypes, self.since, self.change_tick)
+    }
+}
+
+fn check_access<Q: Fetch>() {
+    let mut access = Access::default();
+    Q::access(&mut access);
+    if let Some(ty) = access.conflict() {
+        panic!(
+            "query {} fetches {} mutably along with another fetch of it",
+            std::any::type_name::<Q>(),
+            ty.name()
+        );
+    }
+}
+
+pub struct QueryIter<'q, Q: Fetch, F: Filter> {
+    archetypes: NonNull<[Archetype]>,
+    since: u64,
+    change_tick: u64,
+    // The next archetype to look at.
+    next: usize,
+    // The columns of the one being visited, its length and the next row.
+    current: Option<(Q::Columns, F::Columns, usize, usize)>,
+    query: PhantomData<&'q mut (Q, F)>,
+}
+
+impl<'q, Q: Fetch, F: Filter> QueryIter<'q, Q, F> {
+    fn new(archetypes: NonNull<[Archetype]>, since: u64, change_tick: u64) -> Self {
+        Self {
+            archetypes,
+            since,
+            change_tick,
+            next: 0,
+            current: None,
+            query: PhantomData,
+        }
+    }
+}
+
+impl<'q, Q: Fetch, F: Filter> Iterator for QueryIter<'q, Q, F> {
+    type Item = Q::Item<'q>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((columns, filter, len, row)) = &mut self.current {
+                while *row < *len {
+                    *row += 1;
+                    if unsafe { !F::keeps(*filter, *row - 1, self.since) } {
+                        continue;
+                    }
+                    // The query checked its access when it was made and borrows the world for
+                    // 'q, every row is handed out once.
+                    return Some(unsafe { Q::fetch(*columns, *row - 1) });
+                }
+            }
+            if self.next == self.archetypes.len() {
+                return None;
+            }
+            let archetype = unsafe {
+                NonNull::new_unchecked(self.archetypes.as_ptr().cast::<Archetype>().add(self.next))
+            };
+            self.next += 1;
+            let archetype_ref = unsafe { archetype.as_ref() };
+            let matches = Q::matches(archetype_ref) && F::matches(archetype_ref);
+            let len = archetype_ref.len();
+            self.current = (matches && len > 0).then(|| {
+                (
+                    unsafe { Q::columns(archetype, self.change_tick) },
+                    F::columns(archetype_ref),
+                    len,
+                    0,
+                )
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Position(f32);
+    struct Velocity(f32);
+    struct Frozen;
+
+    #[test]
+    fn queries_fetch_and_filter() {
+        let mut world = EcsWorld::new();
+        let mut spawn = |velocity: Option<f32>, frozen: bool| {
+            let entity = world.spawn();
+            world.insert(entity, Position(0.0));
+            if let Some(velocity) = velocity {
+                world.insert(entity, Velocity(velocity));
+            }
+            if frozen {
+                world.insert(entity, Frozen);
+            }
+            entity
+        };
+        let moving = spawn(Some(1.0), false);
+        let frozen = spawn(Some(2.0), true);
+        let still = spawn(None, false);
+
+        let mut query = Query::<(&mut Position, &Velocity), Without<Frozen>>::new(&mut world);
+        assert_eq!(query.count(), 1);
+        for (mut position, velocity) in query.iter_mut() {
+            position.0 += velocity.0;
+        }
+
+        let query = Query::<(Entity, &Position, Option<&Velocity>)>::read(&world);
+        let mut seen: Vec<_> = query
+            .iter()
+            .map(|(entity, position, velocity)| (entity, position.0, velocity.map(|v| v.0)))
+            .collect();
+        seen.sort_by_key(|(entity, ..)| *entity);
+        assert_eq!(
+            seen,
+            [
+                (moving, 1.0, Some(1.0)),
+                (frozen, 0.0, Some(2.0)),
+                (still, 0.0, None)
+            ]
+        );
+        let frozen_only = Query::<Entity, With<Frozen>>::read(&world);
+        assert_eq!(frozen_only.iter().collect::<Vec<_>>(), [frozen]);
+    }
+
+    #[test]
+    fn change_filters_see_writes_since_the_last_tick() {
+        let mut world = EcsWorld::new();
+        let entities: Vec<_> = (0..3)
+            .map(|i| {
+                let entity = world.spawn();
+                world.insert(entity, Position(i as f32));
+                entity
+            })
+            .collect();
+        assert_eq!(Query::<Entity, Added<Position>>::read(&world).count(), 3);
+
+        world.clear_trackers();
+        assert_eq!(Query::<Entity, Changed<Position>>::read(&world).count(), 0);
+        // Only writing through `Mut` counts, reading doesn't.
+        for (entity, mut position) in Query::<(Entity, &mut Position)>::new(&mut world).iter_mut() {
+            if entity == entities[1] {
+                position.0 += 1.0;
+            } else {
+                assert!(position.0 >= 0.0);
+            }
+        }
+        world.insert(entities[2], Velocity(1.0));
+        let changed = Query::<Entity, Changed<Position>>::read(&world);
+        assert_eq!(changed.iter().collect::<Vec<_>>(), [entities[1]]);
+        let added = Query::<Entity, (Added<Velocity>, Without<Frozen>)>::read(&world);
+        assert_eq!(added.iter().collect::<Vec<_>>(), [entities[2]]);
+        // Moving to another archetype keeps the ticks.
+        assert!(!world
+            .ticks::<Position>(entities[2])
+            .unwrap()
+            .is_added(world.last_change_tick()));
+
+        world.clear_trackers();
+        assert_eq!(Query::<Entity, Changed<Position>>::read(&world).count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "mutably")]
+    fn aliasing_fetches_panic() {
+        let mut world = EcsWorld::new();
+        Query::<(&Position, &mut Position)>::new(&mut world);
+    }
+}
@@ -0,0 +1,351 @@
+use std::f32::consts::TAU;
+
+// Position, rotation as a unit quaternion (x, y, z, w), and scale of an entity, what the gizmos
+// edit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+// What the game renders through, so gizmos can be drawn and picked in screen space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EditorCamera {
+    // Column major, world to clip space.
+    pub view_projection: [[f32; 4]; 4],
+    // In the same pixels as cursor positions.
+    pub viewport: [f32; 2],
+}
+
+impl EditorCamera {
+    // Pixel position of a world point, None behind the camera.
+    pub fn project(&self, point: [f32; 3]) -> Option<[f32; 2]> {
+        let m = &self.view_projection;
+        let clip: [f32; 4] = std::array::from_fn(|row| {
+            m[0][row] * point[0] + m[1][row] * point[1] + m[2][row] * point[2] + m[3][row]
+        });
+        if clip[3] <= f32::EPSILON {
+            return None;
+        }
+        let (x, y) = (clip[0] / clip[3], clip[1] / clip[3]);
+        Some([
+            (x + 1.0) * 0.5 * self.viewport[0],
+            (1.0 - y) * 0.5 * self.viewport[1],
+        ])
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    Rotate,
+    Scale,
+}
+
+// A line for the debug overlay to draw, in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GizmoLine {
+    pub from: [f32; 3],
+    pub to: [f32; 3],
+    pub color: [f32; 4],
+}
+
+const AXIS_COLORS: [[f32; 4]; 3] = [
+    [0.9, 0.2, 0.2, 1.0],
+    [0.2, 0.8, 0.2, 1.0],
+    [0.2, 0.4, 0.9, 1.0],
+];
+const HOVER_COLOR: [f32; 4] = [1.0, 0.85, 0.1, 1.0];
+// How close the cursor has to be to a handle to grab it.
+const HOVER_PIXELS: f32 = 8.0;
+const CIRCLE_SEGMENTS: usize = 32;
+// Radians per pixel of cursor movement along the ring.
+const ROTATE_SPEED: f32 = 0.01;
+
+struct Drag {
+    axis: usize,
+    cursor: [f32; 2],
+    start: Transform,
+}
+
+// Translate, rotate and scale handles along the world axes, with the cursor picked and dragged
+// in screen space.
+pub struct Gizmo {
+    pub mode: GizmoMode,
+    // Length of the handles in world units.
+    pub size: f32,
+    hovered: Option<usize>,
+    drag: Option<Drag>,
+}
+
+impl Default for Gizmo {
+    fn default() -> Self {
+        Self {
+            mode: GizmoMode::default(),
+            size: 1.0,
+            hovered: None,
+            drag: None,
+        }
+    }
+}
+
+impl Gizmo {
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    // The handles around `transform`, by axis.
+    fn handles(&self, transform: &Transform) -> [Vec<([f32; 3], [f32; 3])>; 3] {
+        let origin = transform.translation;
+        std::array::from_fn(|axis| match self.mode {
+            GizmoMode::Translate | GizmoMode::Scale => {
+                vec![(origin, add(origin, scaled(unit(axis), self.size)))]
+            }
+            GizmoMode::Rotate => {
+                // A ring in the plane of the other two axes.
+                let (u, v) = (unit((axis + 1) % 3), unit((axis + 2) % 3));
+                let point = |i: usize| {
+                    let angle = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+                    add(
+                        origin,
+                        add(
+                            scaled(u, angle.cos() * self.size),
+                            scaled(v, angle.sin() * self.size),
+                        ),
+                    )
+                };
+                (0..CIRCLE_SEGMENTS)
+                    .map(|i| (point(i), point(i + 1)))
+                    .collect()
+            }
+        })
+    }
+
+    pub fn lines(&self, transform: &Transform) -> Vec<GizmoLine> {
+        let active = self.drag.as_ref().map(|drag| drag.axis).or(self.hovered);
+        let mut lines = Vec::new();
+        for (axis, segments) in self.handles(transform).into_iter().enumerate() {
+            let color = if active == Some(axis) {
+                HOVER_COLOR
+            } else {
+                AXIS_COLORS[axis]
+            };
+            lines.extend(
+                segments
+                    .into_iter()
+                    .map(|(from, to)| GizmoLine { from, to, color }),
+            );
+            if self.mode == GizmoMode::Scale {
+                // A cross at the tip tells scale from translate.
+                let tip = add(transform.translation, scaled(unit(axis), self.size));
+                let across = scaled(unit((axis + 1) % 3), self.size * 0.08);
+                lines.push(GizmoLine {
+                    from: sub(tip, across),
+                    to: add(tip, across),
+                    color,
+                });
+            }
+        }
+        lines
+    }
+
+    // Highlights the handle under the cursor, true if there is one.
+    pub fn hover(
+        &mut self,
+        camera: &EditorCamera,
+        transform: &Transform,
+        cursor: [f32; 2],
+    ) -> bool {
+        if self.drag.is_none() {
+            self.hovered = self
+                .handles(transform)
+                .iter()
+                .enumerate()
+                .filter_map(|(axis, segments)| {
+                    segments
+                        .iter()
+                        .filter_map(|(from, to)| {
+                            let (from, to) = (camera.project(*from)?, camera.project(*to)?);
+                            Some(distance_to_segment(cursor, from, to))
+                        })
+                        .min_by(f32::total_cmp)
+                        .map(|distance| (axis, distance))
+                })
+                .filter(|(_, distance)| *distance <= HOVER_PIXELS)
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(axis, _)| axis);
+        }
+        self.hovered.is_some()
+    }
+
+    // Grabs the hovered handle, false if the cursor isn't on one.
+    pub fn begin_drag(&mut self, transform: &Transform, cursor: [f32; 2]) -> bool {
+        let Some(axis) = self.hovered else {
+            return false;
+        };
+        self.drag = Some(Drag {
+            axis,
+            cursor,
+            start: *transform,
+        });
+        true
+    }
+
+    // The transform with the drag so far applied, None if nothing is being dragged.
+    pub fn drag(&self, camera: &EditorCamera, cursor: [f32; 2]) -> Option<Transform> {
+        let drag = self.drag.as_ref()?;
+        let mut transform = drag.start;
+        let moved = [cursor[0] - drag.cursor[0], cursor[1] - drag.cursor[1]];
+        let origin = drag.start.translation;
+        // How far along the handle, as seen on screen, the cursor moved, in handle lengths.
+        let along = || {
+            let from = camera.project(origin)?;
+            let to = camera.project(add(origin, scaled(unit(drag.axis), self.size)))?;
+            let axis = [to[0] - from[0], to[1] - from[1]];
+            let length = axis[0] * axis[0] + axis[1] * axis[1];
+            (length > 1.0).then(|| (moved[0] * axis[0] + moved[1] * axis[1]) / length)
+        };
+        match self.mode {
+            GizmoMode::Translate => {
+                transform.translation[drag.axis] += along()? * self.size;
+            }
+            GizmoMode::Scale => {
+                transform.scale[drag.axis] =
+                    drag.start.scale[drag.axis] * (1.0 + along()?).max(0.01);
+            }
+            GizmoMode::Rotate => {
+                let angle = (moved[0] - moved[1]) * ROTATE_SPEED;
+                transform.rotation = normalize(multiply(
+                    axis_angle(unit(drag.axis), angle),
+                    drag.start.rotation,
+                ));
+            }
+        }
+        Some(transform)
+    }
+
+    // Lets go of the handle, returning the transform from before the drag.
+    pub fn end_drag(&mut self) -> Option<Transform> {
+        self.drag.take().map(|drag| drag.start)
+    }
+}
+
+fn unit(axis: usize) -> [f32; 3] {
+    std::array::from_fn(|i| if i == axis { 1.0 } else { 0.0 })
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scaled(a: [f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn axis_angle(axis: [f32; 3], angle: f32) -> [f32; 4] {
+    let (sin, cos) = (angle * 0.5).sin_cos();
+    [axis[0] * sin, axis[1] * sin, axis[2] * sin, cos]
+}
+
+// Hamilton product, `a` applied after `b`.
+fn multiply(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    [
+        a[3] * b[0] + a[0] * b[3] + a[1] * b[2] - a[2] * b[1],
+        a[3] * b[1] - a[0] * b[2] + a[1] * b[3] + a[2] * b[0],
+        a[3] * b[2] + a[0] * b[1] - a[1] * b[0] + a[2] * b[3],
+        a[3] * b[3] - a[0] * b[0] - a[1] * b[1] - a[2] * b[2],
+    ]
+}
+
+fn normalize(q: [f32; 4]) -> [f32; 4] {
+    let length = q.iter().map(|c| c * c).sum::<f32>().sqrt();
+    q.map(|c| c / length)
+}
+
+fn distance_to_segment(point: [f32; 2], from: [f32; 2], to: [f32; 2]) -> f32 {
+    let segment = [to[0] - from[0], to[1] - from[1]];
+    let length = segment[0] * segment[0] + segment[1] * segment[1];
+    let t = if length > 0.0 {
+        (((point[0] - from[0]) * segment[0] + (point[1] - from[1]) * segment[1]) / length)
+            .clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let closest = [from[0] + segment[0] * t, from[1] + segment[1] * t];
+    ((point[0] - closest[0]).powi(2) + (point[1] - closest[1]).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Looking down -z from z = 10, one world unit is 100 pixels at the origin.
+    fn camera() -> EditorCamera {
+        let scale = 0.2;
+        EditorCamera {
+            view_projection: [
+                [scale, 0.0, 0.0, 0.0],
+                [0.0, scale, 0.0, 0.0],
+                [0.0, 0.0, 0.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+            viewport: [1000.0, 1000.0],
+        }
+    }
+
+    #[test]
+    fn drags_handles_in_screen_space() {
+        let camera = camera();
+        let transform = Transform::default();
+        let mut gizmo = Gizmo::default();
+
+        // The x handle runs right from the center of the screen.
+        assert!(!gizmo.hover(&camera, &transform, [700.0, 600.0]));
+        assert!(gizmo.hover(&camera, &transform, [550.0, 503.0]));
+        assert!(gizmo.begin_drag(&transform, [550.0, 503.0]));
+        let moved = gizmo.drag(&camera, [600.0, 540.0]).unwrap();
+        assert!((moved.translation[0] - 0.5).abs() < 1e-5);
+        assert_eq!(moved.translation[1..], [0.0, 0.0]);
+        assert_eq!(gizmo.end_drag(), Some(transform));
+
+        // Dragging the y handle its own length up doubles the height.
+        gizmo.mode = GizmoMode::Scale;
+        assert!(gizmo.hover(&camera, &transform, [500.0, 450.0]));
+        gizmo.begin_drag(&transform, [500.0, 450.0]);
+        let scaled = gizmo.drag(&camera, [500.0, 350.0]).unwrap();
+        assert_eq!(scaled.scale, [1.0, 2.0, 1.0]);
+        gizmo.end_drag();
+
+        // The z ring lies in the screen plane, the others are seen edge on. A quarter turn lands x
+        // on y.
+        gizmo.mode = GizmoMode::Rotate;
+        let on_ring = [570.7, 429.3];
+        assert!(gizmo.hover(&camera, &transform, on_ring));
+        gizmo.begin_drag(&transform, on_ring);
+        let pixels = std::f32::consts::FRAC_PI_2 / ROTATE_SPEED;
+        let rotated = gizmo
+            .drag(&camera, [on_ring[0] + pixels, on_ring[1]])
+            .unwrap();
+        let [x, y, z, w] = rotated.rotation;
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!(
+            x.abs() < 1e-5 && y.abs() < 1e-5 && (z - half).abs() < 1e-5 && (w - half).abs() < 1e-5
+        );
+    }
+}
@@ -0,0 +1,755 @@
+//! Editor mode, toggled with F4 or the `editor` cvar. It pauses the game and shows the entity
+//! hierarchy, an inspector for the selected entity's components and translate/rotate/scale
+//! gizmos. Edits can be undone and redone, and the world is saved back to the scene file.
+//!
+//! The engine has no UI toolkit, ECS or debug renderer yet, so like the debug HUD the editor
+//! produces what to show (text lines for the panels, world space lines for the gizmos) for the
+//! game's overlay to draw, and reaches entities through an `EditorWorld` the game implements.
+//! Components are named and their fields edited as `ScriptValue`s, the same view scripts get.
+//!
+//! Keys while editing: clicking or up and down pick an entity, W/E/R switch between translate, rotate and
+//! scale, Escape deselects, Delete despawns the selection, Ctrl+Z and Ctrl+Y undo and redo and
+//! Ctrl+S saves. Fields are edited from the console with `editor.set <component>[.field..] <value>`,
+//! e.g. `editor.set Health.max 150`, and `editor.spawn [name]` adds a child to the selection.
+
+mod gizmo;
+mod scene;
+mod undo;
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use winit::{event::MouseButton, keyboard::KeyCode};
+
+pub use gizmo::{EditorCamera, Gizmo, GizmoLine, GizmoMode, Transform};
+pub use scene::{Scene, SceneEntity};
+pub use undo::{Edit, UndoStack};
+
+use crate::{
+    cvars::{self, Cvars},
+    engine::{Engine, Plugin, Resources},
+    identifier::SnowflakeId,
+    input::InputEvent,
+    picking::{self, Aabb, Ray},
+    scripting::{ScriptHost, ScriptValue},
+    sim::SimPause,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityInfo {
+    pub id: SnowflakeId,
+    pub name: String,
+    pub parent: Option<SnowflakeId>,
+}
+
+// The game world as the editor sees it, on top of what scripts get.
+pub trait EditorWorld: ScriptHost {
+    fn entities(&self) -> Vec<EntityInfo>;
+    // The entity's component names, in the order the inspector lists them.
+    fn components(&self, entity: SnowflakeId) -> Vec<String>;
+    fn transform(&self, entity: SnowflakeId) -> Option<Transform>;
+    fn set_transform(&mut self, entity: SnowflakeId, transform: Transform);
+    fn remove_component(&mut self, entity: SnowflakeId, component: &str) -> Result<(), String>;
+    // Creates the entity with exactly this id and contents, or replaces it if it exists. Undo uses
+    // it to bring back despawned entities.
+    fn insert_entity(&mut self, entity: &SceneEntity) -> Result<(), String>;
+    // What clicking selects the entity by. A unit cube around its transform unless the game knows
+    // better.
+    fn bounds(&self, entity: SnowflakeId) -> Option<Aabb> {
+        self.transform(entity)
+            .map(|transform| Aabb::from_transform(&transform))
+    }
+}
+
+#[derive(Default)]
+pub struct Editor {
+    active: bool,
+    world: Option<Box<dyn EditorWorld>>,
+    selected: Option<SnowflakeId>,
+    pub gizmo: Gizmo,
+    camera: Option<EditorCamera>,
+    cursor: [f32; 2],
+    ctrl: bool,
+    // Entities in hierarchy order, for picking with the arrow keys.
+    order: Vec<SnowflakeId>,
+    hierarchy: Vec<String>,
+    inspector: Vec<String>,
+    undo: UndoStack,
+    scene_path: Option<PathBuf>,
+}
+
+impl Editor {
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn set_world(&mut self, world: Box<dyn EditorWorld>) {
+        self.world = Some(world);
+        self.undo.clear();
+    }
+
+    pub fn world(&mut self) -> Option<&mut (dyn EditorWorld + 'static)> {
+        self.world.as_deref_mut()
+    }
+
+    // Set by the game every frame it renders the editor.
+    pub fn set_camera(&mut self, camera: EditorCamera) {
+        self.camera = Some(camera);
+    }
+
+    pub fn selected(&self) -> Option<SnowflakeId> {
+        self.selected
+    }
+
+    pub fn select(&mut self, entity: Option<SnowflakeId>) {
+        self.end_drag();
+        self.selected = entity;
+    }
+
+    // Where `save` writes the scene, usually the file the game loaded it from.
+    pub fn set_scene_path(&mut self, path: impl Into<PathBuf>) {
+        self.scene_path = Some(path.into());
+    }
+
+    pub fn scene_path(&self) -> Option<&Path> {
+        self.scene_path.as_deref()
+    }
+
+    pub fn undo_stack(&self) -> &UndoStack {
+        &self.undo
+    }
+
+    // Whether the world differs from the scene as it was last saved.
+    pub fn is_modified(&self) -> bool {
+        self.undo.is_modified()
+    }
+
+    // One line per entity, children indented under their parents and the selection marked.
+    pub fn hierarchy_lines(&self) -> &[String] {
+        &self.hierarchy
+    }
+
+    // The selected entity's components and their fields.
+    pub fn inspector_lines(&self) -> &[String] {
+        &self.inspector
+    }
+
+    // Gizmo around the selected entity, for the debug overlay.
+    pub fn gizmo_lines(&self) -> Vec<GizmoLine> {
+        match (self.active, self.selected_transform()) {
+            (true, Some(transform)) => self.gizmo.lines(&transform),
+            _ => Vec::new(),
+        }
+    }
+
+    fn selected_transform(&self) -> Option<Transform> {
+        self.world.as_ref()?.transform(self.selected?)
+    }
+
+    // Sets a component of the selected entity, or a field inside it with a dotted path like
+    // `Health.max`.
+    pub fn set_field(&mut self, path: &str, value: ScriptValue) -> Result<(), String> {
+        let entity = self.selected.ok_or("nothing selected")?;
+        let world = self.world.as_deref_mut().ok_or("no editor world set")?;
+        let mut fields = path.split('.');
+        let component = fields.next().unwrap_or_default();
+        let before = world
+            .component(entity, component)
+            .ok_or_else(|| format!("{} has no {}", entity, component))?;
+        let mut current = before.clone();
+        let mut target = &mut current;
+        for field in fields {
+            target = match target {
+                ScriptValue::Table(table) => table
+                    .get_mut(field)
+                    .ok_or_else(|| format!("no field {} in {}", field, path))?,
+                ScriptValue::List(list) => field
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| list.get_mut(index))
+                    .ok_or_else(|| format!("no index {} in {}", field, path))?,
+                _ => return Err(format!("{} isn't a table", path)),
+            };
+        }
+        *target = value;
+        world.set_component(entity, component, current.clone())?;
+        self.undo.push(Edit::Component {
+            entity,
+            component: component.to_owned(),
+            before: Some(before),
+            after: Some(current),
+        });
+        Ok(())
+    }
+
+    // Spawns an entity as a child of the selection and selects it.
+    pub fn spawn(&mut self, name: Option<&str>) -> Result<SnowflakeId, String> {
+        let world = self.world.as_deref_mut().ok_or("no editor world set")?;
+        let id = world.spawn();
+        let mut entity =
+            SceneEntity::capture(world, id).ok_or_else(|| format!("{} didn't spawn", id))?;
+        if let Some(name) = name {
+            entity.name = name.to_owned();
+        }
+        entity.parent = self.selected;
+        world.insert_entity(&entity)?;
+        self.undo.push(Edit::Entity {
+            before: None,
+            after: Some(entity),
+        });
+        self.select(Some(id));
+        Ok(id)
+    }
+
+    // Despawns the selection. Its children keep pointing at it and show at the top level until
+    // it's brought back.
+    pub fn despawn_selected(&mut self) -> Result<(), String> {
+        let id = self.selected.ok_or("nothing selected")?;
+        let world = self.world.as_deref_mut().ok_or("no editor world set")?;
+        let entity = SceneEntity::capture(world, id).ok_or_else(|| format!("no entity {}", id))?;
+        world.despawn(id);
+        self.undo.push(Edit::Entity {
+            before: Some(entity),
+            after: None,
+        });
+        self.select(None);
+        Ok(())
+    }
+
+    pub fn undo(&mut self) -> Result<(), String> {
+        self.end_drag();
+        let world = self.world.as_deref_mut().ok_or("no editor world set")?;
+        match self.undo.undo(world)? {
+            Some(edit) => self.selected = edit.entity(),
+            None => info!("Nothing to undo"),
+        }
+        Ok(())
+    }
+
+    pub fn redo(&mut self) -> Result<(), String> {
+        self.end_drag();
+        let world = self.world.as_deref_mut().ok_or("no editor world set")?;
+        match self.undo.redo(world)? {
+            Some(edit) => self.selected = edit.entity(),
+            None => info!("Nothing to redo"),
+        }
+        Ok(())
+    }
+
+    // Writes the world to the scene path.
+    pub fn save(&mut self) -> Result<(), String> {
+        let path = self.scene_path.clone().ok_or("no scene path set")?;
+        self.save_as(path)
+    }
+
+    // Writes the world to `path`, which becomes the scene path.
+    pub fn save_as(&mut self, path: impl Into<PathBuf>) -> Result<(), String> {
+        let path = path.into();
+        let world = self.world.as_deref().ok_or("no editor world set")?;
+        let scene = Scene::capture(world);
+        scene
+            .save(&path)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        info!(
+            "Saved {} entities to {}",
+            scene.entities.len(),
+            path.display()
+        );
+        self.scene_path = Some(path);
+        self.undo.mark_saved();
+        Ok(())
+    }
+
+    // Ends a gizmo drag, recording the move. True if there was one.
+    fn end_drag(&mut self) -> bool {
+        let Some(before) = self.gizmo.end_drag() else {
+            return false;
+        };
+        let (Some(entity), Some(after)) = (self.selected, self.selected_transform()) else {
+            return true;
+        };
+        if after != before {
+            self.undo.push(Edit::Transform {
+                entity,
+                before,
+                after,
+            });
+        }
+        true
+    }
+
+    fn set_active(&mut self, active: bool) {
+        self.active = active;
+        self.end_drag();
+        info!("Editor {}", if active { "on" } else { "off" });
+    }
+
+    fn refresh(&mut self) {
+        self.order.clear();
+        self.hierarchy.clear();
+        self.inspector.clear();
+        let Some(world) = self.world.as_deref() else {
+            return;
+        };
+        let entities = world.entities();
+        let ids: HashSet<SnowflakeId> = entities.iter().map(|entity| entity.id).collect();
+        // Entities whose parent is gone show at the top level.
+        let mut children: HashMap<Option<SnowflakeId>, Vec<&EntityInfo>> = HashMap::new();
+        for entity in &entities {
+            let parent = entity.parent.filter(|parent| ids.contains(parent));
+            children.entry(parent).or_default().push(entity);
+        }
+        let mut stack: Vec<(usize, &EntityInfo)> =
+            children.get(&None).map_or_else(Vec::new, |roots| {
+                roots.iter().rev().map(|e| (0, *e)).collect()
+            });
+        while let Some((depth, entity)) = stack.pop() {
+            let marker = if self.selected == Some(entity.id) {
+                "> "
+            } else {
+                "  "
+            };
+            self.hierarchy.push(format!(
+                "{}{}{} #{}",
+                "  ".repeat(depth),
+                marker,
+                entity.name,
+                entity.id
+            ));
+            self.order.push(entity.id);
+            if let Some(entities) = children.get(&Some(entity.id)) {
+                stack.extend(entities.iter().rev().map(|child| (depth + 1, *child)));
+            }
+        }
+        if self
+            .selected
+            .is_some_and(|selected| !ids.contains(&selected))
+        {
+            self.selected = None;
+        }
+
+        let Some(selected) = self.selected else {
+            return;
+        };
+        for component in world.components(selected) {
+            match world.component(selected, &component) {
+                Some(ScriptValue::Table(fields)) => {
+                    self.inspector.push(component);
+                    self.inspector.extend(
+                        fields
+                            .iter()
+                            .map(|(field, value)| format!("  {} = {}", field, value)),
+                    );
+                }
+                Some(value) => self.inspector.push(format!("{} = {}", component, value)),
+                None => self.inspector.push(component),
+            }
+        }
+    }
+
+    fn select_step(&mut self, step: isize) {
+        if self.order.is_empty() {
+            return;
+        }
+        let index = match self
+            .selected
+            .and_then(|selected| self.order.iter().position(|id| *id == selected))
+        {
+            Some(index) => index.saturating_add_signed(step).min(self.order.len() - 1),
+            None if step < 0 => self.order.len() - 1,
+            None => 0,
+        };
+        self.select(Some(self.order[index]));
+    }
+
+    // The entity under the cursor.
+    fn pick(&self) -> Option<SnowflakeId> {
+        let ray = Ray::from_screen(self.camera.as_ref()?, self.cursor)?;
+        let world = self.world.as_deref()?;
+        let candidates = self
+            .order
+            .iter()
+            .filter_map(|entity| Some((*entity, world.bounds(*entity)?)));
+        picking::closest(candidates, &ray).map(|hit| hit.entity)
+    }
+
+    fn move_cursor(&mut self, cursor: [f32; 2]) -> bool {
+        self.cursor = cursor;
+        let (Some(camera), Some(transform)) = (self.camera, self.selected_transform()) else {
+            return false;
+        };
+        if let Some(moved) = self.gizmo.drag(&camera, cursor) {
+            if let (Some(world), Some(selected)) = (self.world.as_deref_mut(), self.selected) {
+                world.set_transform(selected, moved);
+            }
+            return true;
+        }
+        self.gizmo.hover(&camera, &transform, cursor);
+        false
+    }
+
+    // Returns true if the editor swallowed the event.
+    fn handle_input(&mut self, event: &InputEvent) -> bool {
+        match event {
+            _ if !self.active => false,
+            InputEvent::Key {
+                code: KeyCode::ControlLeft | KeyCode::ControlRight,
+                pressed,
+            } => {
+                self.ctrl = *pressed;
+                false
+            }
+            InputEvent::Key {
+                code,
+                pressed: true,
+            } => {
+                match code {
+                    KeyCode::ArrowUp => self.select_step(-1),
+                    KeyCode::ArrowDown => self.select_step(1),
+                    KeyCode::KeyW => self.gizmo.mode = GizmoMode::Translate,
+                    KeyCode::KeyE => self.gizmo.mode = GizmoMode::Rotate,
+                    KeyCode::KeyR => self.gizmo.mode = GizmoMode::Scale,
+                    KeyCode::Escape => self.select(None),
+                    KeyCode::Delete => {
+                        if let Err(err) = self.despawn_selected() {
+                            error!("Failed to despawn: {}", err);
+                        }
+                    }
+                    KeyCode::KeyZ if self.ctrl => {
+                        if let Err(err) = self.undo() {
+                            error!("Failed to undo: {}", err);
+                        }
+                    }
+                    KeyCode::KeyY if self.ctrl => {
+                        if let Err(err) = self.redo() {
+                            error!("Failed to redo: {}", err);
+                        }
+                    }
+                    KeyCode::KeyS if self.ctrl => {
+                        if let Err(err) = self.save() {
+                            error!("Failed to save the scene: {}", err);
+                        }
+                    }
+                    _ => return false,
+                }
+                true
+            }
+            InputEvent::CursorMoved { x, y } => self.move_cursor([*x as f32, *y as f32]),
+            InputEvent::MouseButton {
+                button: MouseButton::Left,
+                pressed: true,
+            } => {
+                let grabbed = self
+                    .selected_transform()
+                    .is_some_and(|transform| self.gizmo.begin_drag(&transform, self.cursor));
+                // Clicking off the handles selects what's under the cursor.
+                match self.pick() {
+                    _ if grabbed => true,
+                    Some(entity) => {
+                        self.select(Some(entity));
+                        true
+                    }
+                    None => false,
+                }
+            }
+            InputEvent::MouseButton {
+                button: MouseButton::Left,
+                pressed: false,
+            } => self.end_drag(),
+            _ => false,
+        }
+    }
+}
+
+fn parse_entity(text: &str) -> Result<SnowflakeId, String> {
+    text.parse()
+        .map(SnowflakeId::from_bits)
+        .map_err(|_| format!("expected an entity id, got {}", text))
+}
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, engine: &mut Engine) {
+        let cvars = cvars::cvars(engine);
+        cvars.register("editor", false, "editor mode, pauses the game");
+        cvars.register_command(
+            "editor.select",
+            "editor.select [entity], selects an entity in the editor",
+            |resources, args| {
+                let editor = resources.get_mut::<Editor>().ok_or("no editor")?;
+                match args {
+                    [] => editor.select(None),
+                    [entity] => editor.select(Some(parse_entity(entity)?)),
+                    _ => return Err("usage: editor.select [entity]".to_owned()),
+                }
+                Ok(())
+            },
+        );
+        cvars.register_command(
+            "editor.set",
+            "editor.set <component>[.field..] <value>, edits the selected entity",
+            |resources, args| {
+                let [path, value @ ..] = args else {
+                    return Err("usage: editor.set <component>[.field..] <value>".to_owned());
+                };
+                let value = value.join(" ").parse()?;
+                resources
+                    .get_mut::<Editor>()
+                    .ok_or("no editor")?
+                    .set_field(path, value)
+            },
+        );
+        cvars.register_command(
+            "editor.spawn",
+            "editor.spawn [name], spawns a child of the selected entity",
+            |resources, args| {
+                let editor = resources.get_mut::<Editor>().ok_or("no editor")?;
+                let name = (!args.is_empty()).then(|| args.join(" "));
+                editor.spawn(name.as_deref()).map(|_| ())
+            },
+        );
+        cvars.register_command(
+            "editor.despawn",
+            "despawns the selected entity",
+            |resources, _| {
+                resources
+                    .get_mut::<Editor>()
+                    .ok_or("no editor")?
+                    .despawn_selected()
+            },
+        );
+        cvars.register_command("editor.undo", "undoes the last edit", |resources, _| {
+            resources.get_mut::<Editor>().ok_or("no editor")?.undo()
+        });
+        cvars.register_command(
+            "editor.redo",
+            "redoes the last undone edit",
+            |resources, _| resources.get_mut::<Editor>().ok_or("no editor")?.redo(),
+        );
+        cvars.register_command(
+            "editor.save",
+            "editor.save [path], saves the world to the scene file",
+            |resources, args| {
+                let editor = resources.get_mut::<Editor>().ok_or("no editor")?;
+                match args {
+                    [] => editor.save(),
+                    [path] => editor.save_as(path),
+                    _ => Err("usage: editor.save [path]".to_owned()),
+                }
+            },
+        );
+
+        if !engine.resources().contains::<SimPause>() {
+            engine.insert_resource(SimPause::default());
+        }
+        engine
+            .insert_resource(Editor::default())
+            .add_system(|resources: &mut Resources| {
+                let wanted = resources
+                    .get::<Cvars>()
+                    .and_then(|cvars| cvars.get_bool("editor"))
+                    .unwrap_or(false);
+                let Some(editor) = resources.get_mut::<Editor>() else {
+                    return;
+                };
+                let toggled = editor.active != wanted;
+                if toggled {
+                    editor.set_active(wanted);
+                }
+                if editor.active {
+                    editor.refresh();
+                }
+                if let (true, Some(pause)) = (toggled, resources.get_mut::<SimPause>()) {
+                    if wanted {
+                        pause.pause("editor");
+                    } else {
+                        pause.resume("editor");
+                    }
+                }
+            })
+            .add_input_handler(|resources: &mut Resources, event: &InputEvent| {
+                if let InputEvent::Key {
+                    code: KeyCode::F4,
+                    pressed,
+                } = event
+                {
+                    if let (true, Some(cvars)) = (*pressed, resources.get_mut::<Cvars>()) {
+                        let active = cvars.get_bool("editor").unwrap_or(false);
+                        let _ = cvars.set("editor", if active { "false" } else { "true" });
+                    }
+                    return true;
+                }
+                resources
+                    .get_mut::<Editor>()
+                    .is_some_and(|editor| editor.handle_input(event))
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct TestWorld {
+        next: u64,
+        entities: BTreeMap<SnowflakeId, SceneEntity>,
+    }
+
+    impl ScriptHost for TestWorld {
+        fn spawn(&mut self) -> SnowflakeId {
+            self.next += 1;
+            let id = SnowflakeId::from_bits(self.next);
+            self.entities.insert(
+                id,
+                SceneEntity {
+                    id,
+                    name: format!("Entity {}", self.next),
+                    parent: None,
+                    transform: Some(Transform::default()),
+                    components: BTreeMap::new(),
+                },
+            );
+            id
+        }
+
+        fn despawn(&mut self, entity: SnowflakeId) -> bool {
+            self.entities.remove(&entity).is_some()
+        }
+
+        fn component(&self, entity: SnowflakeId, component: &str) -> Option<ScriptValue> {
+            self.entities
+                .get(&entity)?
+                .components
+                .get(component)
+                .cloned()
+        }
+
+        fn set_component(
+            &mut self,
+            entity: SnowflakeId,
+            component: &str,
+            value: ScriptValue,
+        ) -> Result<(), String> {
+            let entity = self.entities.get_mut(&entity).ok_or("no entity")?;
+            entity.components.insert(component.to_owned(), value);
+            Ok(())
+        }
+
+        fn query(&self, _components: &[String]) -> Vec<SnowflakeId> {
+            Vec::new()
+        }
+    }
+
+    impl EditorWorld for TestWorld {
+        fn entities(&self) -> Vec<EntityInfo> {
+            self.entities
+                .values()
+                .map(|entity| EntityInfo {
+                    id: entity.id,
+                    name: entity.name.clone(),
+                    parent: entity.parent,
+                })
+                .collect()
+        }
+
+        fn components(&self, entity: SnowflakeId) -> Vec<String> {
+            self.entities[&entity].components.keys().cloned().collect()
+        }
+
+        fn transform(&self, entity: SnowflakeId) -> Option<Transform> {
+            self.entities.get(&entity)?.transform
+        }
+
+        fn set_transform(&mut self, entity: SnowflakeId, transform: Transform) {
+            if let Some(entity) = self.entities.get_mut(&entity) {
+                entity.transform = Some(transform);
+            }
+        }
+
+        fn remove_component(&mut self, entity: SnowflakeId, component: &str) -> Result<(), String> {
+            let entity = self.entities.get_mut(&entity).ok_or("no entity")?;
+            entity.components.remove(component);
+            Ok(())
+        }
+
+        fn insert_entity(&mut self, entity: &SceneEntity) -> Result<(), String> {
+            self.entities.insert(entity.id, entity.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn edits_undo_and_save_to_the_scene_format() {
+        let mut editor = Editor::default();
+        editor.set_world(Box::<TestWorld>::default());
+        let crate_id = editor.spawn(Some("Crate #1")).unwrap();
+        editor
+            .world()
+            .unwrap()
+            .set_component(
+                crate_id,
+                "Health",
+                "{ current = 50, max = 100 }".parse().unwrap(),
+            )
+            .unwrap();
+        let lid = editor.spawn(Some("Lid")).unwrap();
+        assert!(editor.undo_stack().can_undo());
+
+        editor.select(Some(crate_id));
+        editor
+            .set_field("Health.max", ScriptValue::Int(150))
+            .unwrap();
+        let max = |editor: &mut Editor| {
+            let health = editor
+                .world()
+                .unwrap()
+                .component(crate_id, "Health")
+                .unwrap();
+            health.get("max").cloned()
+        };
+        editor.undo().unwrap();
+        assert_eq!(max(&mut editor), Some(ScriptValue::Int(100)));
+        editor.redo().unwrap();
+        assert_eq!(max(&mut editor), Some(ScriptValue::Int(150)));
+
+        editor.select(Some(lid));
+        editor.despawn_selected().unwrap();
+        assert!(SceneEntity::capture(editor.world().unwrap(), lid).is_none());
+        editor.undo().unwrap();
+        assert_eq!(editor.selected(), Some(lid));
+        let restored = SceneEntity::capture(editor.world().unwrap(), lid).unwrap();
+        assert_eq!(restored.parent, Some(crate_id));
+
+        let path =
+            std::env::temp_dir().join(format!("midnight2-scene-{}.scene", std::process::id()));
+        assert!(editor.is_modified());
+        editor.save_as(&path).unwrap();
+        assert!(!editor.is_modified());
+        let saved = Scene::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(saved, Scene::capture(editor.world().unwrap()));
+        assert_eq!(saved.entities[0].name, "Crate #1");
+
+        // Back where it was saved is unmodified again.
+        editor.redo().unwrap();
+        assert!(editor.is_modified());
+        editor.undo().unwrap();
+        assert!(!editor.is_modified());
+
+        // Components named like the entity's own keys stay components.
+        editor
+            .world()
+            .unwrap()
+            .set_component(crate_id, "name", ScriptValue::Int(1))
+            .unwrap();
+        let scene = Scene::capture(editor.world().unwrap());
+        assert_eq!(Scene::parse(&scene.to_string()), Ok(scene));
+        assert!(Scene::parse("[entity 1]\nHealth = 1").is_err());
+    }
+}
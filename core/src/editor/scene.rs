@@ -0,0 +1,212 @@
+use std::{collections::BTreeMap, fmt, fs, path::Path};
+
+use super::{EditorWorld, Transform};
+use crate::{identifier::SnowflakeId, scripting::ScriptValue};
+
+// Keys of an entity section that aren't components.
+const NAME: &str = "name";
+const PARENT: &str = "parent";
+const TRANSFORM: &str = "transform";
+// Components are keyed by their name after this, so one called e.g. `name` doesn't clash with
+// the keys above.
+const COMPONENT: &str = "component.";
+
+// One entity as it's saved, everything the editor can see of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneEntity {
+    pub id: SnowflakeId,
+    pub name: String,
+    pub parent: Option<SnowflakeId>,
+    pub transform: Option<Transform>,
+    pub components: BTreeMap<String, ScriptValue>,
+}
+
+impl SceneEntity {
+    // Snapshot of an entity in the world, None if it doesn't exist.
+    pub fn capture(world: &dyn EditorWorld, id: SnowflakeId) -> Option<Self> {
+        let info = world
+            .entities()
+            .into_iter()
+            .find(|entity| entity.id == id)?;
+        Some(Self::from_info(world, info.id, info.name, info.parent))
+    }
+
+    fn from_info(
+        world: &dyn EditorWorld,
+        id: SnowflakeId,
+        name: String,
+        parent: Option<SnowflakeId>,
+    ) -> Self {
+        let components = world
+            .components(id)
+            .into_iter()
+            .filter_map(|component| {
+                let value = world.component(id, &component)?;
+                Some((component, value))
+            })
+            .collect();
+        Self {
+            id,
+            name,
+            parent,
+            transform: world.transform(id),
+            components,
+        }
+    }
+}
+
+// The scene asset format. A section per entity with its name, parent, transform and components,
+// values written like `ScriptValue`s print:
+//
+//     [entity 7061234056355840]
+//     name = "Crate"
+//     parent = 7061234056351744
+//     transform = { rotation = [0.0, 0.0, 0.0, 1.0], scale = [1.0, 1.0, 1.0], translation = [2.0, 0.0, -1.5] }
+//     component.Health = { current = 50, max = 100 }
+//
+// Lines starting with `#` are comments. Unlike the config file there are no trailing comments,
+// strings may contain `#`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scene {
+    pub entities: Vec<SceneEntity>,
+}
+
+impl Scene {
+    // Everything in the world, in a stable order so saved scenes diff well.
+    pub fn capture(world: &dyn EditorWorld) -> Self {
+        let mut entities: Vec<SceneEntity> = world
+            .entities()
+            .into_iter()
+            .map(|info| SceneEntity::from_info(world, info.id, info.name, info.parent))
+            .collect();
+        entities.sort_by_key(|entity| entity.id);
+        Self { entities }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut entities: Vec<SceneEntity> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let id = header
+                    .trim()
+                    .strip_prefix("entity ")
+                    .and_then(|id| id.trim().parse().ok())
+                    .ok_or_else(|| error(format!("expected [entity <id>], got [{}]", header)))?;
+                entities.push(SceneEntity {
+                    id: SnowflakeId::from_bits(id),
+                    name: String::new(),
+                    parent: None,
+                    transform: None,
+                    components: BTreeMap::new(),
+                });
+                continue;
+            }
+            let entity = entities
+                .last_mut()
+                .ok_or_else(|| error("not in an entity section".to_owned()))?;
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| error("expected `key = value`".to_owned()))?;
+            let (key, value) = (key.trim(), value.parse::<ScriptValue>().map_err(error)?);
+            match key {
+                NAME => {
+                    entity.name = value
+                        .as_str()
+                        .ok_or_else(|| error("name must be a string".to_owned()))?
+                        .to_owned()
+                }
+                PARENT => match value {
+                    ScriptValue::Int(parent) => {
+                        entity.parent = Some(SnowflakeId::from_bits(parent as u64))
+                    }
+                    _ => return Err(error("parent must be an entity id".to_owned())),
+                },
+                TRANSFORM => {
+                    entity.transform = Some(
+                        transform_from_value(&value)
+                            .ok_or_else(|| error("malformed transform".to_owned()))?,
+                    )
+                }
+                "" => return Err(error("missing key".to_owned())),
+                key => match key.strip_prefix(COMPONENT) {
+                    Some(component) if !component.is_empty() => {
+                        entity.components.insert(component.to_owned(), value);
+                    }
+                    _ => return Err(error(format!("unknown key {}", key))),
+                },
+            }
+        }
+        Ok(Self { entities })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path.as_ref())?;
+        Self::parse(&text).map_err(|err| format!("{}: {}", path.as_ref().display(), err).into())
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+}
+
+impl fmt::Display for Scene {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, entity) in self.entities.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "[entity {}]", entity.id)?;
+            writeln!(f, "{} = {:?}", NAME, entity.name)?;
+            if let Some(parent) = entity.parent {
+                // Written as the signed int it reads back as.
+                writeln!(f, "{} = {}", PARENT, parent.to_bits() as i64)?;
+            }
+            if let Some(transform) = &entity.transform {
+                writeln!(f, "{} = {}", TRANSFORM, transform_to_value(transform))?;
+            }
+            for (component, value) in &entity.components {
+                writeln!(f, "{}{} = {}", COMPONENT, component, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn transform_to_value(transform: &Transform) -> ScriptValue {
+    let list = |values: &[f32]| {
+        ScriptValue::List(
+            values
+                .iter()
+                .map(|value| ScriptValue::Number(*value as f64))
+                .collect(),
+        )
+    };
+    ScriptValue::Table(BTreeMap::from([
+        ("translation".to_owned(), list(&transform.translation)),
+        ("rotation".to_owned(), list(&transform.rotation)),
+        ("scale".to_owned(), list(&transform.scale)),
+    ]))
+}
+
+fn transform_from_value(value: &ScriptValue) -> Option<Transform> {
+    fn array<const N: usize>(value: Option<&ScriptValue>) -> Option<[f32; N]> {
+        let ScriptValue::List(values) = value? else {
+            return None;
+        };
+        let values: Vec<f32> = values
+            .iter()
+            .map(|value| value.as_f64().map(|value| value as f32))
+            .collect::<Option<_>>()?;
+        values.try_into().ok()
+    }
+    Some(Transform {
+        translation: array(value.get("translation"))?,
+        rotation: array(value.get("rotation"))?,
+        scale: array(value.get("scale"))?,
+    })
+}
@@ -0,0 +1,150 @@
+use super::{EditorWorld, SceneEntity, Transform};
+use crate::{identifier::SnowflakeId, scripting::ScriptValue};
+
+// How many edits are kept, the oldest go first.
+const MAX_EDITS: usize = 256;
+
+// One undoable change, with the state on both sides so it can go either way.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edit {
+    Component {
+        entity: SnowflakeId,
+        component: String,
+        before: Option<ScriptValue>,
+        after: Option<ScriptValue>,
+    },
+    Transform {
+        entity: SnowflakeId,
+        before: Transform,
+        after: Transform,
+    },
+    // A spawn has no entity before, a despawn none after.
+    Entity {
+        before: Option<SceneEntity>,
+        after: Option<SceneEntity>,
+    },
+}
+
+impl Edit {
+    pub fn entity(&self) -> Option<SnowflakeId> {
+        match self {
+            Edit::Component { entity, .. } | Edit::Transform { entity, .. } => Some(*entity),
+            Edit::Entity { before, after } => after.as_ref().or(before.as_ref()).map(|e| e.id),
+        }
+    }
+
+    fn apply(&self, world: &mut dyn EditorWorld, forward: bool) -> Result<(), String> {
+        fn pick<T>(forward: bool, before: T, after: T) -> T {
+            if forward {
+                after
+            } else {
+                before
+            }
+        }
+        match self {
+            Edit::Component {
+                entity,
+                component,
+                before,
+                after,
+            } => match pick(forward, before, after) {
+                Some(value) => world.set_component(*entity, component, value.clone()),
+                None => world.remove_component(*entity, component),
+            },
+            Edit::Transform {
+                entity,
+                before,
+                after,
+            } => {
+                world.set_transform(*entity, *pick(forward, before, after));
+                Ok(())
+            }
+            Edit::Entity { before, after } => {
+                match (pick(forward, before, after), pick(forward, after, before)) {
+                    (Some(entity), _) => world.insert_entity(entity)?,
+                    (None, Some(entity)) => {
+                        world.despawn(entity.id);
+                    }
+                    (None, None) => {}
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// Edits made in the editor, for undo and redo. Making a new edit drops whatever was undone.
+#[derive(Debug, Default)]
+pub struct UndoStack {
+    // Each edit with the revision it leads to. A revision names the world's state after some
+    // edit, so undoing back to where the scene was saved makes it unmodified again.
+    done: Vec<(u64, Edit)>,
+    undone: Vec<(u64, Edit)>,
+    last_revision: u64,
+    // The revision before the oldest edit still in `done`.
+    base: u64,
+    saved: u64,
+}
+
+impl UndoStack {
+    // Records an edit that was already made to the world.
+    pub fn push(&mut self, edit: Edit) {
+        if self.done.len() == MAX_EDITS {
+            self.base = self.done.remove(0).0;
+        }
+        self.last_revision += 1;
+        self.done.push((self.last_revision, edit));
+        self.undone.clear();
+    }
+
+    // Reverts the last edit, returns it or None if there was nothing to undo.
+    pub fn undo(&mut self, world: &mut dyn EditorWorld) -> Result<Option<&Edit>, String> {
+        let Some((revision, edit)) = self.done.pop() else {
+            return Ok(None);
+        };
+        if let Err(err) = edit.apply(world, false) {
+            self.done.push((revision, edit));
+            return Err(err);
+        }
+        self.undone.push((revision, edit));
+        Ok(self.undone.last().map(|(_, edit)| edit))
+    }
+
+    pub fn redo(&mut self, world: &mut dyn EditorWorld) -> Result<Option<&Edit>, String> {
+        let Some((revision, edit)) = self.undone.pop() else {
+            return Ok(None);
+        };
+        if let Err(err) = edit.apply(world, true) {
+            self.undone.push((revision, edit));
+            return Err(err);
+        }
+        self.done.push((revision, edit));
+        Ok(self.done.last().map(|(_, edit)| edit))
+    }
+
+    fn revision(&self) -> u64 {
+        self.done.last().map_or(self.base, |(revision, _)| *revision)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.done.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone.is_empty()
+    }
+
+    // Whether the world is in a different state than at the last save, undoing or redoing back
+    // to it counts as unmodified.
+    pub fn is_modified(&self) -> bool {
+        self.revision() != self.saved
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved = self.revision();
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
@@ -0,0 +1,276 @@
+//! Registration point for subsystems, plugins add their systems and resources here.
+
+use std::{
+    any::{self, Any, TypeId},
+    collections::{HashMap, HashSet},
+    ptr::NonNull,
+};
+
+use crate::input::InputEvent;
+
+pub type System = Box<dyn FnMut(&mut Resources) + Send>;
+// Returns true when the event was consumed and shouldn't reach anything else.
+pub type InputHandler = Box<dyn FnMut(&mut Resources, &InputEvent) -> bool + Send>;
+
+// Type keyed storage for sim-wide state (settings, caches, subsystem handles).
+#[derive(Default)]
+pub struct Resources {
+    map: HashMap<TypeId, Box<dyn Any + Send>>,
+}
+
+impl Resources {
+    pub fn insert<T: Any + Send>(&mut self, resource: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(resource))
+            .and_then(|old| old.downcast().ok())
+            .map(|old| *old)
+    }
+
+    pub fn remove<T: Any + Send>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|old| old.downcast().ok())
+            .map(|old| *old)
+    }
+
+    pub fn contains<T: Any + Send>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn get<T: Any + Send>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|resource| resource.downcast_ref())
+    }
+
+    pub fn get_mut<T: Any + Send>(&mut self) -> Option<&mut T> {
+        self.map
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|resource| resource.downcast_mut())
+    }
+
+    pub(crate) fn types(&self) -> HashSet<TypeId> {
+        self.map.keys().copied().collect()
+    }
+
+    // Drops the resources that aren't of one of `types`.
+    pub(crate) fn retain_types(&mut self, types: &HashSet<TypeId>) {
+        self.map.retain(|resource, _| types.contains(resource));
+    }
+
+    // Every resource of the given types, for handing out to systems running side by side.
+    pub(crate) fn ptrs(
+        &mut self,
+        types: impl IntoIterator<Item = TypeId>,
+    ) -> HashMap<TypeId, NonNull<dyn Any + Send>> {
+        let types: Vec<_> = types.into_iter().collect();
+        self.map
+            .iter_mut()
+            .filter(|(ty, _)| types.contains(ty))
+            .map(|(&ty, resource)| (ty, NonNull::from(&mut **resource)))
+            .collect()
+    }
+}
+
+/// A feature (physics, audio, networking...) that can be composed into a project.
+pub trait Plugin: Send + 'static {
+    fn build(&self, engine: &mut Engine);
+
+    fn name(&self) -> &str {
+        any::type_name::<Self>()
+    }
+}
+
+/// Anything `Engine::add_plugins` accepts, a single plugin or a tuple of them.
+pub trait Plugins {
+    fn add_to(self, engine: &mut Engine);
+}
+
+impl<P: Plugin> Plugins for P {
+    fn add_to(self, engine: &mut Engine) {
+        engine.add_plugin(self);
+    }
+}
+
+macro_rules! impl_plugins_for_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: Plugins),+> Plugins for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn add_to(self, engine: &mut Engine) {
+                let ($($name,)+) = self;
+                $($name.add_to(engine);)+
+            }
+        }
+    };
+}
+
+impl_plugins_for_tuple!(A);
+impl_plugins_for_tuple!(A, B);
+impl_plugins_for_tuple!(A, B, C);
+impl_plugins_for_tuple!(A, B, C, D);
+impl_plugins_for_tuple!(A, B, C, D, E);
+impl_plugins_for_tuple!(A, B, C, D, E, F);
+impl_plugins_for_tuple!(A, B, C, D, E, F, G);
+impl_plugins_for_tuple!(A, B, C, D, E, F, G, H);
+
+/// What was registered at some point, see [`Engine::mark`].
+pub struct EngineMark {
+    systems: usize,
+    input_handlers: usize,
+    plugins: HashSet<TypeId>,
+    resources: HashSet<TypeId>,
+}
+
+#[derive(Default)]
+pub struct Engine {
+    resources: Resources,
+    systems: Vec<(&'static str, System)>,
+    input_handlers: Vec<InputHandler>,
+    plugins: HashSet<TypeId>,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_plugins<P: Plugins>(&mut self, plugins: P) -> &mut Self {
+        plugins.add_to(self);
+        self
+    }
+
+    fn add_plugin<P: Plugin>(&mut self, plugin: P) {
+        // Plugins commonly depend on each other, registering one twice would duplicate its systems.
+        if !self.plugins.insert(TypeId::of::<P>()) {
+            debug!("Plugin {} already added, skipping", plugin.name());
+            return;
+        }
+        info!("Adding plugin {}", plugin.name());
+        plugin.build(self);
+    }
+
+    pub fn has_plugin<P: Plugin>(&self) -> bool {
+        self.plugins.contains(&TypeId::of::<P>())
+    }
+
+    pub fn add_system<S: FnMut(&mut Resources) + Send + 'static>(
+        &mut self,
+        system: S,
+    ) -> &mut Self {
+        self.systems.push((any::type_name::<S>(), Box::new(system)));
+        self
+    }
+
+    /// Handlers see input before the application does, in registration order.
+    pub fn add_input_handler<H>(&mut self, handler: H) -> &mut Self
+    where
+        H: FnMut(&mut Resources, &InputEvent) -> bool + Send + 'static,
+    {
+        self.input_handlers.push(Box::new(handler));
+        self
+    }
+
+    // Returns true if a handler consumed the event.
+    pub fn handle_input(&mut self, event: &InputEvent) -> bool {
+        self.input_handlers
+            .iter_mut()
+            .any(|handler| handler(&mut self.resources, event))
+    }
+
+    pub fn insert_resource<T: Any + Send>(&mut self, resource: T) -> &mut Self {
+        self.resources.insert(resource);
+        self
+    }
+
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    pub fn resources_mut(&mut self) -> &mut Resources {
+        &mut self.resources
+    }
+
+    /// Remembers what's registered now so [`Engine::rollback`] can drop anything added later.
+    /// Hot reload uses it to drop what a game library registered before unloading the library.
+    pub fn mark(&self) -> EngineMark {
+        EngineMark {
+            systems: self.systems.len(),
+            input_handlers: self.input_handlers.len(),
+            plugins: self.plugins.clone(),
+            resources: self.resources.types(),
+        }
+    }
+
+    /// Drops the systems, input handlers, plugins and resources added since `mark`. Resources
+    /// that existed at the mark are kept even if they were replaced since.
+    pub fn rollback(&mut self, mark: &EngineMark) {
+        self.systems.truncate(mark.systems);
+        self.input_handlers.truncate(mark.input_handlers);
+        self.plugins.retain(|plugin| mark.plugins.contains(plugin));
+        self.resources.retain_types(&mark.resources);
+    }
+
+    /// Runs every registered system once, in registration order.
+    pub fn run_systems(&mut self) {
+        for (name, system) in self.systems.iter_mut() {
+            let _span = tracing::debug_span!("system", name = *name).entered();
+            profile_scope!(*name);
+            system(&mut self.resources);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Counter(u32);
+
+    struct CounterPlugin;
+
+    impl Plugin for CounterPlugin {
+        fn build(&self, engine: &mut Engine) {
+            engine
+                .insert_resource(Counter(0))
+                .add_system(|resources| resources.get_mut::<Counter>().unwrap().0 += 1);
+        }
+    }
+
+    struct EmptyPlugin;
+
+    impl Plugin for EmptyPlugin {
+        fn build(&self, _engine: &mut Engine) {}
+    }
+
+    #[test]
+    fn plugins_register_once() {
+        let mut engine = Engine::new();
+        engine.add_plugins((CounterPlugin, EmptyPlugin));
+        engine.add_plugins(CounterPlugin);
+        assert!(engine.has_plugin::<EmptyPlugin>());
+
+        engine.run_systems();
+        engine.run_systems();
+        assert_eq!(engine.resources().get::<Counter>().unwrap().0, 2);
+    }
+
+    #[test]
+    fn rollback_drops_what_was_added_after_the_mark() {
+        let mut engine = Engine::new();
+        engine.add_plugins(EmptyPlugin);
+        let mark = engine.mark();
+        engine.add_plugins(CounterPlugin);
+        engine.add_input_handler(|_, _| true);
+        engine.rollback(&mark);
+        engine.run_systems();
+        assert!(engine.has_plugin::<EmptyPlugin>());
+        assert!(!engine.has_plugin::<CounterPlugin>());
+        assert!(!engine.resources().contains::<Counter>());
+        assert!(!engine.handle_input(&InputEvent::Text("a".to_owned())));
+
+        // Registering it again after the rollback works as the first time did.
+        engine.add_plugins(CounterPlugin);
+        engine.run_systems();
+        assert_eq!(engine.resources().get::<Counter>().unwrap().0, 1);
+    }
+}
@@ -0,0 +1,526 @@
+//! Grass, bushes and trees scattered over the ground. A `FoliageLayer` is one kind of plant, a
+//! density map says how thickly it grows where, and scattering places instances on whatever the
+//! ground is through a height function, since there's no terrain type to ask. Placement only
+//! depends on the layer's seed and the cell an instance falls in, so rescattering after the map
+//! is painted only changes the cells that were.
+//!
+//! Instances are kept in square chunks so whole chunks past the fade distance are skipped, and
+//! the ones in range come out as per-instance data with how far they've faded, which the
+//! vegetation shaders dither against instead of blending. `WindParams` is the block those
+//! shaders share to sway with, following `Weather::wind` when there is weather. The renderer has
+//! no instanced pipeline yet, the batches published here are what it would upload and draw.
+
+use std::{collections::BTreeMap, f32::consts::TAU, sync::Mutex, time::Duration};
+
+use crate::{
+    engine::{Engine, Plugin, Resources},
+    metrics,
+    rand::Rng,
+    sim::Time,
+    weather::Weather,
+};
+
+// Meters along each side of a chunk.
+pub const CHUNK_SIZE: f32 = 32.0;
+
+// How much of a plant grows in each texel, 0..1, stretched over a rectangle of the world.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DensityMap {
+    width: usize,
+    height: usize,
+    values: Vec<f32>,
+    // The world x and z the first texel is at and how far the map reaches along each.
+    pub origin: [f32; 2],
+    pub size: [f32; 2],
+}
+
+impl DensityMap {
+    pub fn new(
+        width: usize,
+        height: usize,
+        values: Vec<f32>,
+        origin: [f32; 2],
+        size: [f32; 2],
+    ) -> Result<Self, String> {
+        if width == 0 || height == 0 || width.checked_mul(height) != Some(values.len()) {
+            return Err(format!(
+                "{} values don't make a {}x{} density map",
+                values.len(),
+                width,
+                height
+            ));
+        }
+        Ok(Self {
+            width,
+            height,
+            values: values.into_iter().map(|v| v.clamp(0.0, 1.0)).collect(),
+            origin,
+            size,
+        })
+    }
+
+    pub fn uniform(density: f32, origin: [f32; 2], size: [f32; 2]) -> Self {
+        Self::new(1, 1, vec![density], origin, size).expect("one value")
+    }
+
+    // An 8 bit binary PGM, what most paint tools export a grayscale mask as.
+    pub fn parse_pgm(bytes: &[u8], origin: [f32; 2], size: [f32; 2]) -> Result<Self, String> {
+        let mut fields = Vec::new();
+        let mut at = 0;
+        while fields.len() < 4 {
+            while at < bytes.len() && bytes[at].is_ascii_whitespace() {
+                at += 1;
+            }
+            if bytes.get(at) == Some(&b'#') {
+                while at < bytes.len() && bytes[at] != b'\n' {
+                    at += 1;
+                }
+                continue;
+            }
+            let start = at;
+            while at < bytes.len() && !bytes[at].is_ascii_whitespace() {
+                at += 1;
+            }
+            if start == at {
+                return Err("truncated PGM header".to_owned());
+            }
+            fields.push(String::from_utf8_lossy(&bytes[start..at]).into_owned());
+        }
+        if fields[0] != "P5" {
+            return Err(format!("{} isn't a binary PGM", fields[0]));
+        }
+        let number = |field: &str| {
+            field
+                .parse::<usize>()
+                .map_err(|_| format!("bad PGM header field {}", field))
+        };
+        let (width, height, max) = (
+            number(&fields[1])?,
+            number(&fields[2])?,
+            number(&fields[3])?,
+        );
+        if max == 0 || max > 255 {
+            return Err(format!("only 8 bit PGMs are supported, got max {}", max));
+        }
+        // One whitespace byte separates the header from the pixels.
+        let pixels = bytes.get(at + 1..).unwrap_or_default();
+        let count = width
+            .checked_mul(height)
+            .ok_or_else(|| format!("{}x{} is too big for a PGM", width, height))?;
+        if pixels.len() < count {
+            return Err(format!(
+                "{} bytes of pixels for a {}x{} PGM",
+                pixels.len(),
+                width,
+                height
+            ));
+        }
+        let values = pixels[..count]
+            .iter()
+            .map(|&p| p as f32 / max as f32)
+            .collect();
+        Self::new(width, height, values, origin, size)
+    }
+
+    pub fn contains(&self, x: f32, z: f32) -> bool {
+        let [u, v] = self.uv(x, z);
+        (0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v)
+    }
+
+    // Bilinear between texel centers, 0 outside the map.
+    pub fn sample(&self, x: f32, z: f32) -> f32 {
+        if !self.contains(x, z) {
+            return 0.0;
+        }
+        let [u, v] = self.uv(x, z);
+        let fx = (u * self.width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+        let fy = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (fx as usize, fy as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (tx, ty) = (fx - x0 as f32, fy - y0 as f32);
+        let at = |x: usize, y: usize| self.values[y * self.width + x];
+        let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * tx;
+        let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * tx;
+        top + (bottom - top) * ty
+    }
+
+    fn uv(&self, x: f32, z: f32) -> [f32; 2] {
+        [
+            (x - self.origin[0]) / self.size[0],
+            (z - self.origin[1]) / self.size[1],
+        ]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FoliageLayer {
+    pub mesh: String,
+    pub density: DensityMap,
+    // Instances per square meter where the density map is 1.
+    pub per_square_meter: f32,
+    // Each instance is scaled by a random amount in this range.
+    pub scale: [f32; 2],
+    // Instances start fading out at the first distance and are gone by the second.
+    pub fade: [f32; 2],
+    pub seed: u64,
+}
+
+impl FoliageLayer {
+    pub fn new(mesh: &str, density: DensityMap, per_square_meter: f32) -> Self {
+        Self {
+            mesh: mesh.to_owned(),
+            density,
+            per_square_meter,
+            scale: [0.8, 1.2],
+            fade: [40.0, 60.0],
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FoliageInstance {
+    pub position: [f32; 3],
+    // Radians around +y.
+    pub yaw: f32,
+    pub scale: f32,
+}
+
+// What the vertex shader reads per instance.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InstanceData {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub scale: f32,
+    // 0 fully there, 1 gone, dithered against in the fragment shader.
+    pub fade: f32,
+}
+
+// Places the layer's instances, `height` gives the ground's height at an x and z or None where
+// nothing should grow, over water or off the edge of the level.
+pub fn scatter(
+    layer: &FoliageLayer,
+    height: impl Fn(f32, f32) -> Option<f32>,
+) -> Vec<FoliageInstance> {
+    if layer.per_square_meter <= 0.0 {
+        return Vec::new();
+    }
+    // One candidate per cell, jittered inside it so rows don't show.
+    let spacing = layer.per_square_meter.recip().sqrt();
+    let map = &layer.density;
+    let cells = [
+        (map.size[0] / spacing).ceil() as i64,
+        (map.size[1] / spacing).ceil() as i64,
+    ];
+    let mut instances = Vec::new();
+    for j in 0..cells[1] {
+        for i in 0..cells[0] {
+            let mut rng = Rng::new(layer.seed ^ ((i as u64) << 32) ^ j as u64);
+            let x = map.origin[0] + (i as f32 + rng.next_f32()) * spacing;
+            let z = map.origin[1] + (j as f32 + rng.next_f32()) * spacing;
+            if rng.next_f32() >= map.sample(x, z) {
+                continue;
+            }
+            let Some(y) = height(x, z) else {
+                continue;
+            };
+            let yaw = rng.next_f32() * TAU;
+            let scale = layer.scale[0] + (layer.scale[1] - layer.scale[0]) * rng.next_f32();
+            instances.push(FoliageInstance {
+                position: [x, y, z],
+                yaw,
+                scale,
+            });
+        }
+    }
+    instances
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct ChunkKey([i32; 2]);
+
+impl ChunkKey {
+    fn of(x: f32, z: f32) -> Self {
+        Self([
+            (x / CHUNK_SIZE).floor() as i32,
+            (z / CHUNK_SIZE).floor() as i32,
+        ])
+    }
+
+    // Horizontal distance from `point` to the nearest point of the chunk.
+    fn distance(self, point: [f32; 3]) -> f32 {
+        let gap = |coordinate: f32, chunk: i32| {
+            let min = chunk as f32 * CHUNK_SIZE;
+            (min - coordinate)
+                .max(coordinate - min - CHUNK_SIZE)
+                .max(0.0)
+        };
+        gap(point[0], self.0[0]).hypot(gap(point[2], self.0[1]))
+    }
+}
+
+// The instances of one mesh to draw this frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FoliageBatch {
+    pub mesh: String,
+    pub instances: Vec<InstanceData>,
+}
+
+// The wind vegetation sways in, uploaded as is to the uniform buffer the shaders share. Laid out
+// in two vec4s.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindParams {
+    // Horizontal, normalized.
+    pub direction: [f32; 2],
+    // Meters per second.
+    pub speed: f32,
+    // Seconds, wraps so it keeps its precision.
+    pub time: f32,
+    // How much stronger than `speed` gusts get, 0..1.
+    pub gust_strength: f32,
+    // Gusts per second.
+    pub gust_frequency: f32,
+    // Meters between gust fronts as they sweep across.
+    pub gust_length: f32,
+    _padding: f32,
+}
+
+impl Default for WindParams {
+    fn default() -> Self {
+        Self {
+            direction: [1.0, 0.0],
+            speed: 2.0,
+            time: 0.0,
+            gust_strength: 0.5,
+            gust_frequency: 0.2,
+            gust_length: 20.0,
+            _padding: 0.0,
+        }
+    }
+}
+
+// Long enough that no gust frequency in use visibly restarts, short enough that f32 seconds stay
+// precise.
+const WIND_TIME_WRAP: f32 = 3600.0;
+
+impl WindParams {
+    pub fn advance(&mut self, delta: Duration) {
+        self.time = (self.time + delta.as_secs_f32()) % WIND_TIME_WRAP;
+    }
+
+    // Takes the direction and speed from a wind velocity.
+    pub fn set_velocity(&mut self, velocity: [f32; 3]) {
+        let speed = velocity[0].hypot(velocity[2]);
+        if speed > f32::EPSILON {
+            self.direction = [velocity[0] / speed, velocity[2] / speed];
+        }
+        self.speed = speed;
+    }
+
+    // Wind speed at a point including the gust passing over it, what the shaders compute.
+    pub fn speed_at(&self, position: [f32; 3]) -> f32 {
+        let along = position[0] * self.direction[0] + position[2] * self.direction[1];
+        let phase =
+            (self.time * self.gust_frequency - along / self.gust_length.max(f32::EPSILON)) * TAU;
+        let gust = phase.sin() * 0.5 + 0.5;
+        self.speed * (1.0 + self.gust_strength * gust)
+    }
+
+    // How far the top of a plant at `position` bends along x and z, with `stiffness` meters per
+    // second of wind per meter of bend.
+    pub fn sway(&self, position: [f32; 3], stiffness: f32) -> [f32; 2] {
+        let bend = self.speed_at(position) / stiffness.max(f32::EPSILON);
+        [self.direction[0] * bend, self.direction[1] * bend]
+    }
+}
+
+// What the render thread draws this frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FoliageFrame {
+    pub wind: WindParams,
+    pub batches: Vec<FoliageBatch>,
+}
+
+static FRAME: Mutex<Option<FoliageFrame>> = Mutex::new(None);
+
+// The latest foliage from the sim, for the render thread.
+pub fn frame() -> Option<FoliageFrame> {
+    FRAME.lock().unwrap().clone()
+}
+
+struct Placed {
+    layer: FoliageLayer,
+    chunks: BTreeMap<ChunkKey, Vec<FoliageInstance>>,
+}
+
+#[derive(Default)]
+pub struct Foliage {
+    layers: BTreeMap<String, Placed>,
+    camera: Option<[f32; 3]>,
+    pub wind: WindParams,
+}
+
+impl Foliage {
+    // Scatters the layer, replacing one with the same name.
+    pub fn add_layer(
+        &mut self,
+        name: &str,
+        layer: FoliageLayer,
+        height: impl Fn(f32, f32) -> Option<f32>,
+    ) {
+        let mut chunks: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for instance in scatter(&layer, height) {
+            let [x, _, z] = instance.position;
+            chunks.entry(ChunkKey::of(x, z)).or_default().push(instance);
+        }
+        self.layers
+            .insert(name.to_owned(), Placed { layer, chunks });
+    }
+
+    pub fn remove_layer(&mut self, name: &str) -> Option<FoliageLayer> {
+        self.layers.remove(name).map(|placed| placed.layer)
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&FoliageLayer> {
+        self.layers.get(name).map(|placed| &placed.layer)
+    }
+
+    pub fn instances(&self, name: &str) -> impl Iterator<Item = &FoliageInstance> {
+        self.layers
+            .get(name)
+            .into_iter()
+            .flat_map(|placed| placed.chunks.values().flatten())
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.layers
+            .values()
+            .flat_map(|placed| placed.chunks.values())
+            .map(Vec::len)
+            .sum()
+    }
+
+    // Set by the game every frame, distances fade from here.
+    pub fn set_camera(&mut self, position: [f32; 3]) {
+        self.camera = Some(position);
+    }
+
+    // Everything close enough to the camera, one batch per layer with anything in range.
+    pub fn batches(&self) -> Vec<FoliageBatch> {
+        let Some(camera) = self.camera else {
+            return Vec::new();
+        };
+        let mut batches = Vec::new();
+        for placed in self.layers.values() {
+            let [start, end] = placed.layer.fade;
+            let mut instances = Vec::new();
+            for (key, chunk) in &placed.chunks {
+                if key.distance(camera) >= end {
+                    continue;
+                }
+                for instance in chunk {
+                    let [x, y, z] = instance.position;
+                    let distance = ((x - camera[0]).powi(2)
+                        + (y - camera[1]).powi(2)
+                        + (z - camera[2]).powi(2))
+                    .sqrt();
+                    if distance >= end {
+                        continue;
+                    }
+                    let fade =
+                        ((distance - start) / (end - start).max(f32::EPSILON)).clamp(0.0, 1.0);
+                    instances.push(InstanceData {
+                        position: instance.position,
+                        yaw: instance.yaw,
+                        scale: instance.scale,
+                        fade,
+                    });
+                }
+            }
+            if !instances.is_empty() {
+                batches.push(FoliageBatch {
+                    mesh: placed.layer.mesh.clone(),
+                    instances,
+                });
+            }
+        }
+        batches
+    }
+}
+
+pub struct FoliagePlugin;
+
+impl Plugin for FoliagePlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(Foliage::default())
+            .add_system(|resources: &mut Resources| {
+                let delta = resources
+                    .get::<Time>()
+                    .map_or(Duration::ZERO, |time| time.delta);
+                let wind = resources.get::<Weather>().map(|weather| weather.wind);
+                let Some(foliage) = resources.get_mut::<Foliage>() else {
+                    return;
+                };
+                if let Some(wind) = wind {
+                    foliage.wind.set_velocity(wind);
+                }
+                foliage.wind.advance(delta);
+                let batches = foliage.batches();
+                metrics::set_gauge(
+                    "foliage_instances_drawn",
+                    batches.iter().map(|b| b.instances.len()).sum::<usize>() as f64,
+                );
+                *FRAME.lock().unwrap() = Some(FoliageFrame {
+                    wind: foliage.wind,
+                    batches,
+                });
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scatter_follows_density_and_fades_with_distance() {
+        // Grows on the left, bilinear filtering ramps it down to nothing across the middle.
+        let density = DensityMap::new(2, 1, vec![1.0, 0.0], [0.0, 0.0], [64.0, 64.0]).unwrap();
+        let mut layer = FoliageLayer::new("grass", density, 0.25);
+        layer.fade = [10.0, 30.0];
+        let ground = |_: f32, _: f32| Some(1.0);
+        let instances = scatter(&layer, ground);
+        assert_eq!(instances, scatter(&layer, ground));
+        assert!(instances.len() > 100);
+        assert!(instances
+            .iter()
+            .all(|i| i.position[0] < 48.0 && i.position[1] == 1.0));
+        let left = instances.iter().filter(|i| i.position[0] < 16.0).count();
+        assert!(left > instances.len() / 3);
+
+        let mut foliage = Foliage::default();
+        foliage.add_layer("grass", layer, ground);
+        assert_eq!(foliage.instance_count(), instances.len());
+        assert!(foliage.batches().is_empty());
+        foliage.set_camera([0.0, 1.0, 0.0]);
+        let batches = foliage.batches();
+        assert_eq!(batches.len(), 1);
+        for instance in &batches[0].instances {
+            let distance = instance.position[0].hypot(instance.position[2]);
+            assert!(distance < 30.0);
+            assert_eq!(instance.fade == 0.0, distance <= 10.0);
+        }
+
+        let bytes = b"P5\n# mask\n2 1\n255\n\xff\x00";
+        let parsed = DensityMap::parse_pgm(bytes, [0.0, 0.0], [64.0, 64.0]).unwrap();
+        assert_eq!(parsed.sample(8.0, 8.0), 1.0);
+        assert_eq!(parsed.sample(32.0, 8.0), 0.5);
+        assert_eq!(parsed.sample(70.0, 8.0), 0.0);
+        let huge = format!("P5 {} 2 255 \x7f", usize::MAX / 2 + 1);
+        assert!(DensityMap::parse_pgm(huge.as_bytes(), [0.0; 2], [1.0; 2]).is_err());
+        assert!(DensityMap::parse_pgm(b"P5 2 2 255 \xff", [0.0; 2], [1.0; 2]).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Frame time graph overlay. Render and sim threads record their frame and tick times here, the
+//! `FrameGraph` resource keeps a scrolling window of them with percentile readouts for the
+//! overlay. Toggled with F3.
+//!
+//! Where the backend supports pipeline statistics queries the renderer also reports what each
+//! render pass cost in shader invocations and primitives, for spotting overdraw or geometry that
+//! got far denser than intended.
+
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use winit::keyboard::KeyCode;
+
+use crate::{
+    engine::{Engine, Plugin, Resources},
+    input::InputEvent,
+};
+
+// Samples kept per series, about four seconds at 60 fps.
+pub const HISTORY_LEN: usize = 240;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameSeries {
+    CpuFrame,
+    GpuFrame,
+    SimTick,
+}
+
+impl FrameSeries {
+    pub const ALL: [FrameSeries; 3] = [
+        FrameSeries::CpuFrame,
+        FrameSeries::GpuFrame,
+        FrameSeries::SimTick,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FrameSeries::CpuFrame => "cpu frame",
+            FrameSeries::GpuFrame => "gpu frame",
+            FrameSeries::SimTick => "sim tick",
+        }
+    }
+}
+
+// Milliseconds, oldest first.
+static SAMPLES: Mutex<[VecDeque<f32>; 3]> =
+    Mutex::new([VecDeque::new(), VecDeque::new(), VecDeque::new()]);
+
+pub fn record(series: FrameSeries, time: Duration) {
+    let mut samples = SAMPLES.lock().unwrap();
+    let samples = &mut samples[series as usize];
+    if samples.len() == HISTORY_LEN {
+        samples.pop_front();
+    }
+    samples.push_back(time.as_secs_f32() * 1000.0);
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+    pub max: f32,
+}
+
+impl FrameStats {
+    pub fn from_samples(samples: &[f32]) -> Self {
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f32::total_cmp);
+        Self {
+            p50: percentile(&sorted, 0.50),
+            p95: percentile(&sorted, 0.95),
+            p99: percentile(&sorted, 0.99),
+            max: sorted.last().copied().unwrap_or_default(),
+        }
+    }
+}
+
+// Counts from a pipeline statistics query around one render pass.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PassStats {
+    pub vertex_invocations: u64,
+    // Primitives that reached the clipper and the ones that came out of it, after culling.
+    pub clipper_invocations: u64,
+    pub primitives_out: u64,
+    pub fragment_invocations: u64,
+}
+
+impl PassStats {
+    // Fragments shaded per pixel of a target with `pixels` pixels, 1 is every pixel shaded once.
+    pub fn overdraw(&self, pixels: u64) -> f32 {
+        if pixels == 0 {
+            return 0.0;
+        }
+        self.fragment_invocations as f32 / pixels as f32
+    }
+}
+
+// The passes of the latest frame whose statistics came back, in the order they ran.
+static PASS_STATS: Mutex<Vec<(&'static str, PassStats)>> = Mutex::new(Vec::new());
+
+// Called by the render thread once a frame's queries have been read back.
+pub fn record_pass_stats(passes: Vec<(&'static str, PassStats)>) {
+    *PASS_STATS.lock().unwrap() = passes;
+}
+
+// Nearest-rank percentile of already sorted samples.
+fn percentile(sorted: &[f32], fraction: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (fraction * sorted.len() as f32).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[derive(Default)]
+pub struct GraphSeries {
+    // Milliseconds, oldest first, at most HISTORY_LEN.
+    pub samples: Vec<f32>,
+    pub stats: FrameStats,
+}
+
+#[derive(Default)]
+pub struct FrameGraph {
+    visible: bool,
+    series: [GraphSeries; 3],
+    passes: Vec<(&'static str, PassStats)>,
+}
+
+impl FrameGraph {
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn series(&self, series: FrameSeries) -> &GraphSeries {
+        &self.series[series as usize]
+    }
+
+    // Empty when the backend has no pipeline statistics queries.
+    pub fn pass_stats(&self) -> &[(&'static str, PassStats)] {
+        &self.passes
+    }
+
+    // Copies the latest samples while visible, so a hidden graph costs nothing per tick.
+    fn update(&mut self) {
+        if !self.visible {
+            return;
+        }
+        let samples = SAMPLES.lock().unwrap();
+        for (graph, samples) in self.series.iter_mut().zip(samples.iter()) {
+            graph.samples.clear();
+            graph.samples.extend(samples.iter());
+            graph.stats = FrameStats::from_samples(&graph.samples);
+        }
+        self.passes.clone_from(&PASS_STATS.lock().unwrap());
+    }
+
+    fn handle_input(&mut self, event: &InputEvent) -> bool {
+        if let InputEvent::Key {
+            code: KeyCode::F3,
+            pressed,
+        } = event
+        {
+            if *pressed {
+                self.toggle();
+            }
+            return true;
+        }
+        false
+    }
+}
+
+pub struct FrameGraphPlugin;
+
+impl Plugin for FrameGraphPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(FrameGraph::default())
+            .add_system(|resources: &mut Resources| {
+                if let Some(graph) = resources.get_mut::<FrameGraph>() {
+                    graph.update();
+                }
+            })
+            .add_input_handler(|resources: &mut Resources, event: &InputEvent| {
+                resources
+                    .get_mut::<FrameGraph>()
+                    .is_some_and(|graph| graph.handle_input(event))
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let samples: Vec<f32> = (1..=100).rev().map(|ms| ms as f32).collect();
+        let stats = FrameStats::from_samples(&samples);
+        assert_eq!(stats.p50, 50.0);
+        assert_eq!(stats.p95, 95.0);
+        assert_eq!(stats.p99, 99.0);
+        assert_eq!(stats.max, 100.0);
+        assert_eq!(FrameStats::from_samples(&[]), FrameStats::default());
+    }
+
+    #[test]
+    fn pass_stats_reach_the_graph() {
+        let stats = PassStats {
+            fragment_invocations: 3_000,
+            ..PassStats::default()
+        };
+        assert_eq!(stats.overdraw(1_000), 3.0);
+        record_pass_stats(vec![("opaque", stats)]);
+        let mut graph = FrameGraph::default();
+        graph.update();
+        assert!(graph.pass_stats().is_empty());
+        graph.toggle();
+        graph.update();
+        assert_eq!(graph.pass_stats(), [("opaque", stats)]);
+    }
+}
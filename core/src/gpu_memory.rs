@@ -0,0 +1,91 @@
+//! GPU memory accounting. The renderer reports what it allocates per category, and
+//! `GpuMemoryPlugin` publishes the totals and the backend budget, when known, into metrics.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+use crate::{
+    engine::{Engine, Plugin},
+    metrics,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GpuMemoryCategory {
+    Textures,
+    Buffers,
+    // Swap chain images and other attachments.
+    RenderTargets,
+}
+
+impl GpuMemoryCategory {
+    pub const ALL: [GpuMemoryCategory; 3] = [
+        GpuMemoryCategory::Textures,
+        GpuMemoryCategory::Buffers,
+        GpuMemoryCategory::RenderTargets,
+    ];
+
+    fn metric_name(self) -> &'static str {
+        match self {
+            GpuMemoryCategory::Textures => "gpu_memory_textures_bytes",
+            GpuMemoryCategory::Buffers => "gpu_memory_buffers_bytes",
+            GpuMemoryCategory::RenderTargets => "gpu_memory_render_targets_bytes",
+        }
+    }
+}
+
+// What the backend reports for the local (VRAM) heap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GpuBudget {
+    pub budget: u64,
+    pub usage: u64,
+}
+
+static ALLOCATED: [AtomicU64; 3] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+static BUDGET: Mutex<Option<GpuBudget>> = Mutex::new(None);
+
+pub fn record_alloc(category: GpuMemoryCategory, bytes: u64) {
+    ALLOCATED[category as usize].fetch_add(bytes, Ordering::Relaxed);
+}
+
+pub fn record_free(category: GpuMemoryCategory, bytes: u64) {
+    let _ = ALLOCATED[category as usize].fetch_update(
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+        |allocated| Some(allocated.saturating_sub(bytes)),
+    );
+}
+
+pub fn allocated(category: GpuMemoryCategory) -> u64 {
+    ALLOCATED[category as usize].load(Ordering::Relaxed)
+}
+
+pub fn total_allocated() -> u64 {
+    GpuMemoryCategory::ALL.into_iter().map(allocated).sum()
+}
+
+// Set by the renderer on backends that can query the memory budget.
+pub fn set_budget(budget: Option<GpuBudget>) {
+    *BUDGET.lock().unwrap() = budget;
+}
+
+pub fn budget() -> Option<GpuBudget> {
+    *BUDGET.lock().unwrap()
+}
+
+pub struct GpuMemoryPlugin;
+
+impl Plugin for GpuMemoryPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine.add_system(|_| {
+            for category in GpuMemoryCategory::ALL {
+                metrics::set_gauge(category.metric_name(), allocated(category) as f64);
+            }
+            if let Some(budget) = budget() {
+                metrics::set_gauge("gpu_memory_budget_bytes", budget.budget as f64);
+                metrics::set_gauge("gpu_memory_usage_bytes", budget.usage as f64);
+            }
+        });
+    }
+}
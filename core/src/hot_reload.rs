@@ -0,0 +1,483 @@
+//! Dev mode where the game lives in a dynamic library that the runner reloads whenever cargo
+//! rebuilds it, so gameplay changes show up without restarting.
+//!
+//! The game crate is built as a `cdylib`, implements `ReloadableGame` and exports it with
+//! `export_game!`. The runner wraps the library in a `HotReloadGame` and runs that as its
+//! application. The engine and its resources live in the runner and survive reloads untouched.
+//! Everything the library registered (systems, input handlers, plugins, resources, console
+//! commands, schedule systems, the ECS world's resources and events) is dropped before it
+//! unloads and registered again by the new library's `setup`. Engine plugins belong in the
+//! runner's `EngineBuilder`, or their state is rebuilt on every reload.
+//!
+//! The ECS world's entities survive reloads, but any of its components may have been added by
+//! the library's code, so they all go. The components the game lists in `preserve` are encoded
+//! with their `Replicated` implementation first and decoded by the new library after its
+//! `setup`, along with the hierarchy. Anything else the game carries across itself with `save`
+//! and `restore`.
+//!
+//! The library is linked with its own copy of the engine, statics included. The runner's logger,
+//! metrics and sim state (`sim::current_tick`, `sim::shutdown` and so on) are handed to it when
+//! it's loaded, anything else kept in statics, e.g. `render_graph`'s passes or the profiler, the
+//! library has its own of that the runner never sees.
+//!
+//! Nothing checks that the library and the runner agree on type layouts, they have to be built
+//! by the same compiler from the same engine sources. Resources the runner created that the game
+//! replaces rather than changes in place keep code from the library too. That's fine while
+//! iterating, not for shipping.
+
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use libloading::Library;
+use winit::event::WindowEvent;
+
+use crate::{
+    app::Application,
+    cvars::{Cvars, CvarsMark},
+    ecs::{Component, EcsWorld, Entity, Parent, WorldMark},
+    engine::{Engine, EngineMark},
+    input::InputEvent,
+    metrics,
+    net::{
+        bits::{BitReader, BitWriter},
+        replication::Replicated,
+    },
+    schedule::{Schedule, ScheduleMark},
+    sim::{self, SimState},
+};
+
+/// Bumped whenever `ReloadableGame`, `Host` or `export_game!` change.
+pub const GAME_ABI_VERSION: u32 = 2;
+
+// How often the library is checked for a rebuild.
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+// How long the library has to stay unchanged before loading it, the linker writes it in place.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// A game that can be reloaded from a dynamic library, see the module docs.
+pub trait ReloadableGame: Application {
+    /// The components to carry across reloads, see the module docs.
+    fn preserve(&self, _components: &mut PreservedComponents) {}
+
+    /// State to carry into the rebuilt library, called right before the old one unloads.
+    fn save(&mut self, _engine: &mut Engine) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Called with what `save` returned on the new library's game, right after its `setup`.
+    fn restore(&mut self, _engine: &mut Engine, _state: &[u8]) {}
+}
+
+/// Components a game carries across reloads, by their `Replicated::NAME`.
+#[derive(Default)]
+pub struct PreservedComponents {
+    codecs: Vec<Codec>,
+}
+
+// Every entity with the component, and the component encoded.
+type Encoded = Vec<(Entity, Vec<u8>)>;
+
+struct Codec {
+    name: &'static str,
+    save: fn(&EcsWorld) -> Encoded,
+    load: fn(&mut EcsWorld, Entity, &[u8]) -> bool,
+}
+
+impl PreservedComponents {
+    pub fn add<C: Component + Replicated>(&mut self) -> &mut Self {
+        self.codecs.push(Codec {
+            name: C::NAME,
+            save: |world| {
+                world
+                    .query::<C>()
+                    .map(|(entity, component)| {
+                        let mut writer = BitWriter::new();
+                        component.encode(&mut writer);
+                        (entity, writer.finish())
+                    })
+                    .collect()
+            },
+            load: |world, entity, data| match C::decode(&mut BitReader::new(data)) {
+                Some(component) => world.insert(entity, component),
+                None => false,
+            },
+        });
+        self
+    }
+
+    fn get(&self, name: &str) -> Option<&Codec> {
+        self.codecs.iter().find(|codec| codec.name == name)
+    }
+}
+
+// The world's preserved components, owned by the runner so they outlive the library.
+#[derive(Default)]
+struct WorldSnapshot {
+    components: Vec<(String, Encoded)>,
+    // Children and their parents.
+    hierarchy: Vec<(Entity, Entity)>,
+}
+
+impl WorldSnapshot {
+    fn save(world: &EcsWorld, preserved: &PreservedComponents) -> Self {
+        Self {
+            components: preserved
+                .codecs
+                .iter()
+                .map(|codec| (codec.name.to_owned(), (codec.save)(world)))
+                .collect(),
+            hierarchy: world
+                .query::<Parent>()
+                .map(|(child, parent)| (child, parent.get()))
+                .collect(),
+        }
+    }
+
+    fn restore(self, world: &mut EcsWorld, preserved: &PreservedComponents) {
+        for (name, components) in self.components {
+            let Some(codec) = preserved.get(&name) else {
+                warn!(
+                    "Dropping {} {} components, the game doesn't preserve them any more",
+                    components.len(),
+                    name
+                );
+                continue;
+            };
+            let failed = components
+                .iter()
+                .filter(|(entity, data)| !(codec.load)(world, *entity, data))
+                .count();
+            if failed > 0 {
+                warn!(
+                    "Failed to decode {} of {} {} components",
+                    failed,
+                    components.len(),
+                    name
+                );
+            }
+        }
+        for (child, parent) in self.hierarchy {
+            world.set_parent(child, parent);
+        }
+    }
+}
+
+/// The runner's logger, metrics and sim state, handed to a game library when it's loaded so it
+/// doesn't use its own, see the module docs. Only `export_game!` needs this.
+#[doc(hidden)]
+pub struct Host {
+    logger: &'static dyn log::Log,
+    max_level: log::LevelFilter,
+    metrics: &'static metrics::Registry,
+    sim: &'static SimState,
+}
+
+impl Host {
+    fn current() -> Self {
+        Self {
+            logger: log::logger(),
+            max_level: log::max_level(),
+            metrics: metrics::registry(),
+            sim: sim::state(),
+        }
+    }
+
+    // Called in the library before the game is created.
+    pub fn install(&self) {
+        // Only fails if the library set a logger of its own, that one stays then.
+        let _ = log::set_logger(self.logger);
+        log::set_max_level(self.max_level);
+        metrics::share(self.metrics);
+        sim::share(self.sim);
+    }
+}
+
+/// Exports a `ReloadableGame` that implements `Default` from the game library, e.g.
+/// `export_game!(MyGame);` at the crate root.
+#[macro_export]
+macro_rules! export_game {
+    ($game:ty) => {
+        #[no_mangle]
+        pub fn midnight2_game_abi() -> u32 {
+            $crate::hot_reload::GAME_ABI_VERSION
+        }
+
+        #[no_mangle]
+        pub fn midnight2_game_create(
+            host: &$crate::hot_reload::Host,
+        ) -> Box<dyn $crate::hot_reload::ReloadableGame> {
+            host.install();
+            Box::new(<$game as ::std::default::Default>::default())
+        }
+    };
+}
+
+// What was registered before the game's setup, everything since is dropped before the library
+// unloads.
+struct Mark {
+    engine: EngineMark,
+    cvars: Option<CvarsMark>,
+    schedule: Option<ScheduleMark>,
+    world: Option<WorldMark>,
+}
+
+impl Mark {
+    fn take(engine: &Engine) -> Self {
+        let resources = engine.resources();
+        Self {
+            engine: engine.mark(),
+            cvars: resources.get::<Cvars>().map(Cvars::mark),
+            schedule: resources.get::<Schedule>().map(Schedule::mark),
+            world: resources.get::<EcsWorld>().map(EcsWorld::mark),
+        }
+    }
+
+    fn rollback(&self, engine: &mut Engine) {
+        engine.rollback(&self.engine);
+        let resources = engine.resources_mut();
+        if let (Some(cvars), Some(mark)) = (resources.get_mut::<Cvars>(), self.cvars) {
+            cvars.rollback(mark);
+        }
+        if let (Some(schedule), Some(mark)) = (resources.get_mut::<Schedule>(), self.schedule) {
+            schedule.rollback(mark);
+        }
+        if let Some(world) = resources.get_mut::<EcsWorld>() {
+            if let Some(mark) = &self.world {
+                world.rollback(mark);
+            }
+            world.clear_components();
+        }
+    }
+}
+
+struct Loaded {
+    game: Box<dyn ReloadableGame>,
+    preserved: PreservedComponents,
+    library: Library,
+    // What was actually loaded, a copy so cargo can overwrite the original meanwhile and the
+    // loader doesn't hand back the cached old library.
+    copy: PathBuf,
+}
+
+impl Loaded {
+    fn unload(self) {
+        // The game's code lives in the library, it has to go first.
+        drop(self.game);
+        drop(self.preserved);
+        drop(self.library);
+        let _ = fs::remove_file(&self.copy);
+    }
+}
+
+/// Runs the game from a dynamic library and reloads it when it's rebuilt.
+pub struct HotReloadGame {
+    path: PathBuf,
+    modified: std::time::SystemTime,
+    last_check: Instant,
+    generation: u32,
+    loaded: Option<Loaded>,
+    // Taken right before the game's setup, what it registered is dropped on reload.
+    mark: Option<Mark>,
+}
+
+impl HotReloadGame {
+    pub fn new(path: impl Into<PathBuf>) -> Result<Self, Box<dyn Error>> {
+        let path = path.into();
+        let modified = fs::metadata(&path)?.modified()?;
+        let mut game = Self {
+            path,
+            modified,
+            last_check: Instant::now(),
+            generation: 0,
+            loaded: None,
+            mark: None,
+        };
+        game.loaded = Some(game.load()?);
+        Ok(game)
+    }
+
+    /// Where cargo puts the library of crate `name` in a target directory like `target/debug`.
+    pub fn library_path(target_dir: impl AsRef<Path>, name: &str) -> PathBuf {
+        target_dir.as_ref().join(format!(
+            "{}{}{}",
+            std::env::consts::DLL_PREFIX,
+            name.replace('-', "_"),
+            std::env::consts::DLL_SUFFIX
+        ))
+    }
+
+    fn load(&mut self) -> Result<Loaded, Box<dyn Error>> {
+        self.generation += 1;
+        let stem = self
+            .path
+            .file_stem()
+            .map_or_else(|| "game".into(), |stem| stem.to_string_lossy());
+        let copy = std::env::temp_dir().join(format!(
+            "{}-{}-{}{}",
+            stem,
+            std::process::id(),
+            self.generation,
+            std::env::consts::DLL_SUFFIX
+        ));
+        fs::copy(&self.path, &copy)?;
+        match Self::open(&copy) {
+            Ok((game, library)) => {
+                let mut preserved = PreservedComponents::default();
+                game.preserve(&mut preserved);
+                Ok(Loaded {
+                    game,
+                    preserved,
+                    library,
+                    copy,
+                })
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&copy);
+                Err(err)
+            }
+        }
+    }
+
+    fn open(path: &Path) -> Result<(Box<dyn ReloadableGame>, Library), Box<dyn Error>> {
+        // Safety: only as sound as the module docs' requirement that the library is built from the
+        // same sources by the same compiler, that's what makes the signatures below line up.
+        unsafe {
+            let library = Library::new(path)?;
+            let game = {
+                let abi = library.get::<fn() -> u32>(b"midnight2_game_abi\0")?;
+                if abi() != GAME_ABI_VERSION {
+                    return Err(format!(
+                        "built for game ABI version {}, the runner has {}",
+                        abi(),
+                        GAME_ABI_VERSION
+                    )
+                    .into());
+                }
+                let create = library
+                    .get::<fn(&Host) -> Box<dyn ReloadableGame>>(b"midnight2_game_create\0")?;
+                create(&Host::current())
+            };
+            Ok((game, library))
+        }
+    }
+
+    fn check_for_rebuild(&mut self, engine: &mut Engine) {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return;
+        }
+        self.last_check = Instant::now();
+        // Missing while cargo relinks it.
+        let Ok(modified) = fs::metadata(&self.path).and_then(|metadata| metadata.modified()) else {
+            return;
+        };
+        if modified == self.modified || modified.elapsed().unwrap_or_default() < SETTLE_TIME {
+            return;
+        }
+        self.modified = modified;
+        self.reload(engine);
+    }
+
+    fn reload(&mut self, engine: &mut Engine) {
+        let started = Instant::now();
+        // The running game stays if the new library doesn't load.
+        let mut next = match self.load() {
+            Ok(next) => next,
+            Err(err) => {
+                error!("Failed to reload {}: {}", self.path.display(), err);
+                return;
+            }
+        };
+        let mut state = Vec::new();
+        let mut world = WorldSnapshot::default();
+        if let Some(mut previous) = self.loaded.take() {
+            state = previous.game.save(engine);
+            if let Some(saved) = engine.resources().get::<EcsWorld>() {
+                world = WorldSnapshot::save(saved, &previous.preserved);
+            }
+            if let Some(mark) = &self.mark {
+                mark.rollback(engine);
+            }
+            previous.unload();
+        }
+        self.mark = Some(Mark::take(engine));
+        next.game.setup(engine);
+        if let Some(restored) = engine.resources_mut().get_mut::<EcsWorld>() {
+            world.restore(restored, &next.preserved);
+        }
+        next.game.restore(engine, &state);
+        self.loaded = Some(next);
+        metrics::increment("hot_reloads_total", 1);
+        info!(
+            "Reloaded {} in {:?}, carried {} bytes of state",
+            self.path.display(),
+            started.elapsed(),
+            state.len()
+        );
+    }
+
+    fn game(&mut self) -> Option<&mut dyn ReloadableGame> {
+        self.loaded.as_mut().map(|loaded| loaded.game.as_mut())
+    }
+}
+
+impl Application for HotReloadGame {
+    fn setup(&mut self, engine: &mut Engine) {
+        self.mark = Some(Mark::take(engine));
+        if let Some(game) = self.game() {
+            game.setup(engine);
+        }
+    }
+
+    fn on_loaded(&mut self, engine: &mut Engine) {
+        if let Some(game) = self.game() {
+            game.on_loaded(engine);
+        }
+    }
+
+    fn fixed_update(&mut self, engine: &mut Engine, dt: Duration) {
+        self.check_for_rebuild(engine);
+        if let Some(game) = self.game() {
+            game.fixed_update(engine, dt);
+        }
+    }
+
+    fn handle_input(&mut self, engine: &mut Engine, event: &InputEvent) {
+        if let Some(game) = self.game() {
+            game.handle_input(engine, event);
+        }
+    }
+
+    fn handle_event(&mut self, engine: &mut Engine, event: &WindowEvent) {
+        if let Some(game) = self.game() {
+            game.handle_event(engine, event);
+        }
+    }
+
+    fn handle_instance_launch(&mut self, engine: &mut Engine, args: &[String]) {
+        if let Some(game) = self.game() {
+            game.handle_instance_launch(engine, args);
+        }
+    }
+
+    fn shutdown(&mut self, engine: &mut Engine) {
+        if let Some(game) = self.game() {
+            game.shutdown(engine);
+        }
+        // The engine outlives the application, nothing from the library may be left in it.
+        if let Some(mark) = self.mark.take() {
+            mark.rollback(engine);
+        }
+    }
+}
+
+impl Drop for HotReloadGame {
+    fn drop(&mut self) {
+        if let Some(loaded) = self.loaded.take() {
+            loaded.unload();
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// Generational IDs are an index into some slot storage plus the generation of that slot. Freed
+// slots are reused, but with a bumped generation, so an ID kept around after its slot was freed
+// never aliases the slot's new owner. The ECS entity and asset slot maps build on this.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct GenerationalId {
+    index: u32,
+    generation: u32,
+}
+
+impl GenerationalId {
+    pub fn index(self) -> u32 {
+        self.index
+    }
+
+    pub fn generation(self) -> u32 {
+        self.generation
+    }
+
+    // Generation in the high 32 bits, index in the low 32 bits. Stable, used by saves and the
+    // network.
+    pub fn to_bits(self) -> u64 {
+        (u64::from(self.generation) << 32) | u64::from(self.index)
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        GenerationalId {
+            index: bits as u32,
+            generation: (bits >> 32) as u32,
+        }
+    }
+}
+
+// e.g. 12v3 for generation 3 of slot 12.
+impl std::fmt::Display for GenerationalId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+// Not thread safe apart from `reserve`, each world or slot map owns its own allocator.
+#[derive(Default)]
+pub struct GenerationalAllocator {
+    // Current generation of every slot ever handed out.
+    generations: Vec<u32>,
+    // Freed slots, reused most recently freed first.
+    free: Vec<u32>,
+    alive: usize,
+    // New slots handed out by `reserve` since the last `flush`, past the end of `generations`.
+    reserved: AtomicU32,
+}
+
+impl GenerationalAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allocate(&mut self) -> Option<GenerationalId> {
+        self.flush();
+        if let Some(index) = self.free.pop() {
+            self.alive += 1;
+            return Some(GenerationalId {
+                index,
+                generation: self.generations[index as usize],
+            });
+        }
+        let index = u32::try_from(self.generations.len()).ok()?;
+        self.generations.push(0);
+        self.alive += 1;
+        Some(GenerationalId {
+            index,
+            generation: 0,
+        })
+    }
+
+    // An ID that only comes alive at the next `flush`, which `allocate` does too. Safe to call
+    // from several threads at once, it only ever hands out new slots.
+    pub fn reserve(&self) -> Option<GenerationalId> {
+        let reserved = self.reserved.fetch_add(1, Ordering::Relaxed);
+        let index = u32::try_from(self.generations.len())
+            .ok()
+            .and_then(|len| len.checked_add(reserved))
+            .filter(|&index| index != u32::MAX);
+        if index.is_none() {
+            self.reserved.fetch_sub(1, Ordering::Relaxed);
+        }
+        Some(GenerationalId {
+            index: index?,
+            generation: 0,
+        })
+    }
+
+    // Brings the reserved IDs to life and returns them.
+    pub fn flush(&mut self) -> Vec<GenerationalId> {
+        let reserved = std::mem::take(self.reserved.get_mut());
+        let start = self.generations.len() as u32;
+        self.generations
+            .resize(self.generations.len() + reserved as usize, 0);
+        self.alive += reserved as usize;
+        (start..start + reserved)
+            .map(|index| GenerationalId {
+                index,
+                generation: 0,
+            })
+            .collect()
+    }
+
+    // Returns false if `id` was already freed.
+    pub fn free(&mut self, id: GenerationalId) -> bool {
+        if !self.is_alive(id) {
+            return false;
+        }
+        self.alive -= 1;
+        let generation = &mut self.generations[id.index as usize];
+        // Live generations are below u32::MAX, which marks a retired slot. A slot that reaches it
+        // is never reused, so old IDs can't come back when the generation would wrap.
+        *generation += 1;
+        if *generation != u32::MAX {
+            self.free.push(id.index);
+        }
+        true
+    }
+
+    // Freeing bumps the slot's generation, so only the current owner's ID matches it.
+    pub fn is_alive(&self, id: GenerationalId) -> bool {
+        id.generation != u32::MAX && self.generations.get(id.index as usize) == Some(&id.generation)
+    }
+
+    // Number of live IDs.
+    pub fn len(&self) -> usize {
+        self.alive
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.alive == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reused_slots_get_new_generations() {
+        let mut allocator = GenerationalAllocator::new();
+        let first = allocator.allocate().unwrap();
+        let second = allocator.allocate().unwrap();
+        assert!(allocator.free(first));
+        assert!(!allocator.free(first));
+
+        let reused = allocator.allocate().unwrap();
+        assert_eq!(reused.index(), first.index());
+        assert_ne!(reused, first);
+        assert!(!allocator.is_alive(first));
+        assert!(allocator.is_alive(reused) && allocator.is_alive(second));
+        assert_eq!(allocator.len(), 2);
+    }
+
+    #[test]
+    fn worn_out_slots_are_retired() {
+        let mut allocator = GenerationalAllocator::new();
+        let id = allocator.allocate().unwrap();
+        // As if it had been freed and reused all but once.
+        let last = GenerationalId {
+            generation: u32::MAX - 1,
+            ..id
+        };
+        allocator.generations[id.index() as usize] = last.generation;
+        assert!(allocator.free(last));
+        assert!(!allocator.is_alive(last));
+        let next = allocator.allocate().unwrap();
+        assert_ne!(next.index(), id.index());
+        assert!(allocator.is_alive(next));
+    }
+}
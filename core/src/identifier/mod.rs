@@ -0,0 +1,240 @@
+//! Defines types and APIs for creating globally unique identifiers in the engine.
+
+mod generational;
+mod name;
+mod registry;
+#[cfg(feature = "serde")]
+mod serde;
+mod snowflake;
+mod typed;
+mod uuid;
+
+pub use generational::{GenerationalAllocator, GenerationalId};
+pub use name::Name;
+pub use registry::{IdNamespace, IdRegistry, NamespacedId};
+pub use snowflake::{SnowflakeGenerator, SnowflakeId};
+pub use typed::Id;
+pub use uuid::Uuid;
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::num::NonZeroUsize;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+// Global IDs are atomically guaranteed to be unique across all threads in an application,
+// Their primary use case is to allocate global IDs where uniqueness is required across multiple threads
+// ECS World is a primary example. Once an ID has been handed out, it will NEVER be allocated to another caller
+// Even if the original owner of that ID is gone.
+// IDs start at 1 so Option<GlobalId> is the size of a usize, and the largest value is reserved for
+// GlobalId::PLACEHOLDER.
+pub struct GlobalId(NonZeroUsize);
+
+// The last ID handed out.
+static ALLOCATED_GLOBAL_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl GlobalId {
+    // Never allocated, for slots that need an ID before a real one is known.
+    pub const PLACEHOLDER: GlobalId = GlobalId(NonZeroUsize::MAX);
+
+    pub fn allocate() -> Option<Self> {
+        Self::allocate_block(1).and_then(|mut block| block.next())
+    }
+
+    // Reserves `count` consecutive IDs with a single atomic update, for subsystems that allocate
+    // thousands per tick (streaming, particles) and would otherwise contend on the counter.
+    pub fn allocate_block(count: usize) -> Option<GlobalIdBlock> {
+        ALLOCATED_GLOBAL_ID
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |val| {
+                val.checked_add(count).filter(|last| *last < usize::MAX)
+            })
+            .map(|last| GlobalIdBlock {
+                next: last + 1,
+                end: last + 1 + count,
+            })
+            .ok()
+    }
+
+    pub fn value(self) -> usize {
+        self.0.get()
+    }
+
+    // Only for restoring IDs that were allocated before, e.g. from a snapshot. None for 0.
+    pub fn from_value(value: usize) -> Option<Self> {
+        NonZeroUsize::new(value).map(GlobalId)
+    }
+
+    pub fn is_placeholder(self) -> bool {
+        self == Self::PLACEHOLDER
+    }
+
+    // Allocated the first time a thread asks, so log records can tell threads apart.
+    pub fn current_thread() -> Option<Self> {
+        THREAD_GLOBAL_ID.with(|id| *id)
+    }
+}
+
+// A reserved range of GlobalIds, handed out in order. IDs left in a dropped block are never
+// allocated to anyone else.
+#[derive(Clone, Debug)]
+pub struct GlobalIdBlock {
+    next: usize,
+    end: usize,
+}
+
+impl Iterator for GlobalIdBlock {
+    type Item = GlobalId;
+
+    fn next(&mut self) -> Option<GlobalId> {
+        if self.next == self.end {
+            return None;
+        }
+        let id = GlobalId::from_value(self.next);
+        self.next += 1;
+        id
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.end - self.next, Some(self.end - self.next))
+    }
+}
+
+impl ExactSizeIterator for GlobalIdBlock {}
+
+impl fmt::Display for GlobalId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+thread_local! {
+    static THREAD_GLOBAL_ID: Option<GlobalId> = GlobalId::allocate();
+}
+
+// Identifies a thread for as long as the process runs, tokens of exited threads aren't reused.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub struct ThreadToken(GlobalId);
+
+impl ThreadToken {
+    pub fn current() -> Self {
+        ThreadToken(GlobalId::current_thread().expect("Out of global ids"))
+    }
+}
+
+// ThreadLocal Ids are atomically guaranteed to be unique within a given thread, they should NEVER be used
+// On another thread. They carry the token of the thread that allocated them and are !Send, so they can't
+// be moved to another thread by accident, and debug builds check the thread when the value is read.
+#[derive(Copy, Clone, PartialEq, Debug, Hash)]
+pub struct ThreadLocalId {
+    value: usize,
+    thread: ThreadToken,
+    // Raw pointers are !Send and !Sync.
+    _not_send: PhantomData<*const ()>,
+}
+
+thread_local! {
+    static ALLOCATED_THREAD_ID: AtomicUsize = AtomicUsize::new(0);  
+}
+
+impl ThreadLocalId {
+    pub fn allocate() -> Option<Self> {
+        ALLOCATED_THREAD_ID.with(|thread_id|
+            thread_id.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |val| {
+                val.checked_add(1)
+            })
+            .map(|value| ThreadLocalId {
+                value,
+                thread: ThreadToken::current(),
+                _not_send: PhantomData,
+            })
+            .ok())
+    }
+
+    pub fn value(&self) -> usize {
+        debug_assert!(
+            self.is_on_owner_thread(),
+            "ThreadLocalId {} used off its thread",
+            self.value
+        );
+        self.value
+    }
+
+    pub fn thread(&self) -> ThreadToken {
+        self.thread
+    }
+
+    pub fn is_on_owner_thread(&self) -> bool {
+        self.thread == ThreadToken::current()
+    }
+}
+
+// Remembers the thread that created it so subsystems bound to one thread, like the renderer, can
+// check their callers. Unlike ThreadLocalId this can be shared between threads.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct OwnerThread(ThreadToken);
+
+impl OwnerThread {
+    pub fn current() -> Self {
+        OwnerThread(ThreadToken::current())
+    }
+
+    pub fn is_current(&self) -> bool {
+        self.0 == ThreadToken::current()
+    }
+
+    #[track_caller]
+    pub fn debug_assert_current(&self) {
+        debug_assert!(self.is_current(), "Called off the owning thread {:?}", self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn global_ids_unique() {
+        let ids = std::iter::repeat_with(GlobalId::allocate)
+            .take(50)
+            .map(Option::unwrap)
+            .collect::<Vec<_>>();
+        for (i, &id1) in ids.iter().enumerate() {
+            // For the first element, i is 0 - so skip 1
+            for &id2 in ids.iter().skip(i + 1) {
+                assert_ne!(id1, id2, "WorldIds should not repeat");
+            }
+        }
+    }
+
+    #[test]
+    fn blocks_dont_overlap() {
+        let block = GlobalId::allocate_block(100).unwrap();
+        assert_eq!(block.len(), 100);
+        let ids: Vec<_> = block.collect();
+        let after = GlobalId::allocate().unwrap();
+        assert!(!ids.contains(&after));
+        assert_eq!(ids[99].value() - ids[0].value(), 99);
+    }
+
+    #[test]
+    fn global_id_niche() {
+        assert_eq!(std::mem::size_of::<Option<GlobalId>>(), std::mem::size_of::<usize>());
+        assert!(GlobalId::from_value(0).is_none());
+        assert!(GlobalId::allocate().unwrap() < GlobalId::PLACEHOLDER);
+    }
+
+    #[test]
+    fn thread_local_ids_know_their_thread() {
+        let id = ThreadLocalId::allocate().unwrap();
+        assert!(id.is_on_owner_thread());
+        let token = id.thread();
+        let owner = OwnerThread::current();
+        std::thread::spawn(move || {
+            assert_ne!(ThreadToken::current(), token);
+            assert!(!owner.is_current());
+        })
+        .join()
+        .unwrap();
+    }
+}
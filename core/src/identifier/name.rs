@@ -0,0 +1,116 @@
+use std::fmt;
+
+#[cfg(debug_assertions)]
+use std::{collections::HashMap, sync::RwLock};
+
+// A string identified by a stable 64 bit hash (FNV-1a) of it, for entity names, asset keys and
+// the like, so hot paths compare integers instead of strings. The hash is the same in every run
+// and on every platform, so names can be stored and sent over the network. Debug builds keep the
+// strings around to print names and to catch hash collisions.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Name(u64);
+
+#[cfg(debug_assertions)]
+static NAMES: RwLock<Option<HashMap<u64, &'static str>>> = RwLock::new(None);
+
+const fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
+
+impl Name {
+    pub fn new(name: &str) -> Self {
+        let id = Name(fnv1a(name.as_bytes()));
+        #[cfg(debug_assertions)]
+        id.intern(name);
+        id
+    }
+
+    // For constants, `Name::new` of the same string gives the same Name. Not registered for
+    // reverse lookup until some `Name::new` call interns it.
+    pub const fn from_static(name: &'static str) -> Self {
+        Name(fnv1a(name.as_bytes()))
+    }
+
+    pub const fn from_hash(hash: u64) -> Self {
+        Name(hash)
+    }
+
+    pub const fn hash(self) -> u64 {
+        self.0
+    }
+
+    // The original string, only known in debug builds.
+    pub fn as_str(self) -> Option<&'static str> {
+        #[cfg(debug_assertions)]
+        return NAMES
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|names| names.get(&self.0).copied());
+        #[cfg(not(debug_assertions))]
+        None
+    }
+
+    #[cfg(debug_assertions)]
+    fn intern(self, name: &str) {
+        if let Some(known) = NAMES
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|names| names.get(&self.0))
+        {
+            assert_eq!(*known, name, "Name hash collision");
+            return;
+        }
+        let mut names = NAMES.write().unwrap();
+        names
+            .get_or_insert_with(HashMap::new)
+            .entry(self.0)
+            // Leaked once per distinct name, names are expected to be a bounded set.
+            .or_insert_with(|| Box::leak(name.to_owned().into_boxed_str()));
+    }
+}
+
+impl From<&str> for Name {
+    fn from(name: &str) -> Self {
+        Name::new(name)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.as_str() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "#{:016x}", self.0),
+        }
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Name({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_stable() {
+        const PLAYER: Name = Name::from_static("player");
+        assert_eq!(Name::new("player"), PLAYER);
+        assert_ne!(Name::new("Player"), PLAYER);
+        // FNV-1a test vector, the hash must never change between versions.
+        assert_eq!(Name::new("a").hash(), 0xaf63_dc4c_8601_ec8c);
+        #[cfg(debug_assertions)]
+        assert_eq!(PLAYER.to_string(), "player");
+    }
+}
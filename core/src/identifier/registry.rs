@@ -0,0 +1,121 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    num::NonZeroU64,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use super::Name;
+
+// An ID unique within its namespace, e.g. the 3rd "render.texture".
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct NamespacedId {
+    pub namespace: Name,
+    pub value: NonZeroU64,
+}
+
+impl fmt::Display for NamespacedId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.namespace, self.value)
+    }
+}
+
+// Handle to one namespace's counter, allocating through it skips the registry lookup.
+#[derive(Clone)]
+pub struct IdNamespace {
+    name: Name,
+    allocated: Arc<AtomicU64>,
+}
+
+impl IdNamespace {
+    pub fn allocate(&self) -> Option<NamespacedId> {
+        self.allocated
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |val| {
+                val.checked_add(1)
+            })
+            .ok()
+            .and_then(|last| NonZeroU64::new(last + 1))
+            .map(|value| NamespacedId {
+                namespace: self.name,
+                value,
+            })
+    }
+
+    pub fn allocated(&self) -> u64 {
+        self.allocated.load(Ordering::Relaxed)
+    }
+}
+
+// Per namespace ID counters ("world", "render.texture", "net.session"), with introspection of
+// how many IDs each namespace handed out for debugging and tools.
+#[derive(Default)]
+pub struct IdRegistry {
+    namespaces: RwLock<BTreeMap<String, IdNamespace>>,
+}
+
+static GLOBAL_REGISTRY: IdRegistry = IdRegistry::new();
+
+impl IdRegistry {
+    pub const fn new() -> Self {
+        Self {
+            namespaces: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    // The process wide registry.
+    pub fn global() -> &'static IdRegistry {
+        &GLOBAL_REGISTRY
+    }
+
+    pub fn namespace(&self, name: &str) -> IdNamespace {
+        if let Some(namespace) = self.namespaces.read().unwrap().get(name) {
+            return namespace.clone();
+        }
+        self.namespaces
+            .write()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_insert_with(|| IdNamespace {
+                name: Name::new(name),
+                allocated: Arc::new(AtomicU64::new(0)),
+            })
+            .clone()
+    }
+
+    pub fn allocate(&self, namespace: &str) -> Option<NamespacedId> {
+        self.namespace(namespace).allocate()
+    }
+
+    // How many IDs every namespace handed out, sorted by namespace.
+    pub fn allocated(&self) -> Vec<(String, u64)> {
+        self.namespaces
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, namespace)| (name.clone(), namespace.allocated()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_count_separately() {
+        let registry = IdRegistry::new();
+        let textures = registry.namespace("render.texture");
+        assert_eq!(textures.allocate().unwrap().value.get(), 1);
+        assert_eq!(textures.allocate().unwrap().value.get(), 2);
+        let world = registry.allocate("world").unwrap();
+        assert_eq!(world.value.get(), 1);
+        assert_eq!(world.namespace, Name::new("world"));
+        assert_eq!(
+            registry.allocated(),
+            [("render.texture".to_owned(), 2), ("world".to_owned(), 1)]
+        );
+    }
+}
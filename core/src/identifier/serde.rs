@@ -0,0 +1,101 @@
+//! Serde support for identifiers, behind the `serde` feature. The encodings are stable and part
+//! of the save and network formats, don't change them:
+//!
+//! - `GlobalId`: u64, never 0.
+//! - `GenerationalId`: u64, generation in the high 32 bits, index in the low 32 bits.
+//! - `Uuid`: the hyphenated string in human readable formats, 16 bytes otherwise.
+//! - `Name`: its u64 FNV-1a hash.
+//! - `SnowflakeId`: u64, its bits as documented on the type.
+//! - `Id<T, R>`: exactly like the raw ID it wraps.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{GenerationalId, GlobalId, Id, Name, SnowflakeId, Uuid};
+
+impl Serialize for GlobalId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.value() as u64)
+    }
+}
+
+impl<'de> Deserialize<'de> for GlobalId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = u64::deserialize(deserializer)?;
+        usize::try_from(value)
+            .ok()
+            .and_then(GlobalId::from_value)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid GlobalId {}", value)))
+    }
+}
+
+impl Serialize for GenerationalId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.to_bits())
+    }
+}
+
+impl<'de> Deserialize<'de> for GenerationalId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u64::deserialize(deserializer).map(GenerationalId::from_bits)
+    }
+}
+
+impl Serialize for Uuid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(self.as_bytes())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Uuid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let text = <std::borrow::Cow<str>>::deserialize(deserializer)?;
+            text.parse().map_err(serde::de::Error::custom)
+        } else {
+            let bytes = <std::borrow::Cow<[u8]>>::deserialize(deserializer)?;
+            <[u8; 16]>::try_from(bytes.as_ref())
+                .map(Uuid::from_bytes)
+                .map_err(serde::de::Error::custom)
+        }
+    }
+}
+
+impl Serialize for Name {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.hash())
+    }
+}
+
+impl<'de> Deserialize<'de> for Name {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u64::deserialize(deserializer).map(Name::from_hash)
+    }
+}
+
+impl Serialize for SnowflakeId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.to_bits())
+    }
+}
+
+impl<'de> Deserialize<'de> for SnowflakeId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u64::deserialize(deserializer).map(SnowflakeId::from_bits)
+    }
+}
+
+impl<T: ?Sized, R: Serialize> Serialize for Id<T, R> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.raw().serialize(serializer)
+    }
+}
+
+impl<'de, T: ?Sized, R: Deserialize<'de>> Deserialize<'de> for Id<T, R> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        R::deserialize(deserializer).map(Id::from_raw)
+    }
+}
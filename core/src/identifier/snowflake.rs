@@ -0,0 +1,119 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Milliseconds since the Unix epoch at 2023-01-01T00:00:00Z.
+const EPOCH_MS: u64 = 1_672_531_200_000;
+const NODE_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+pub const MAX_NODE: u16 = (1 << NODE_BITS) - 1;
+
+// IDs for network spawned objects that are unique across machines without coordination and
+// sort roughly by creation time, for resolving conflicts. From high to low bits: 41 bits of
+// milliseconds since 2023, 10 bits of node id, 12 bits of per-millisecond sequence.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SnowflakeId(u64);
+
+impl SnowflakeId {
+    pub const fn from_bits(bits: u64) -> Self {
+        SnowflakeId(bits)
+    }
+
+    pub const fn to_bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn timestamp(self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(EPOCH_MS + (self.0 >> (NODE_BITS + SEQUENCE_BITS)))
+    }
+
+    pub fn node(self) -> u16 {
+        ((self.0 >> SEQUENCE_BITS) & u64::from(MAX_NODE)) as u16
+    }
+
+    pub fn sequence(self) -> u16 {
+        (self.0 & ((1 << SEQUENCE_BITS) - 1)) as u16
+    }
+}
+
+impl fmt::Debug for SnowflakeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SnowflakeId({}:{}:{})",
+            self.0 >> (NODE_BITS + SEQUENCE_BITS),
+            self.node(),
+            self.sequence()
+        )
+    }
+}
+
+impl fmt::Display for SnowflakeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// One per machine, e.g. with the node id the server assigned to the session's peer.
+pub struct SnowflakeGenerator {
+    node: u16,
+    // Milliseconds and sequence of the last ID, laid out like the ID without the node.
+    last: AtomicU64,
+}
+
+impl SnowflakeGenerator {
+    // None if `node` doesn't fit in 10 bits.
+    pub fn new(node: u16) -> Option<Self> {
+        (node <= MAX_NODE).then(|| Self {
+            node,
+            last: AtomicU64::new(0),
+        })
+    }
+
+    pub fn generate(&self) -> SnowflakeId {
+        self.generate_at(SystemTime::now())
+    }
+
+    // Never goes backwards, even when the clock does. More than 4096 IDs in one millisecond
+    // borrow sequence numbers from the next millisecond instead of waiting for it.
+    fn generate_at(&self, now: SystemTime) -> SnowflakeId {
+        let millis = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64)
+            .saturating_sub(EPOCH_MS);
+        let candidate = millis << SEQUENCE_BITS;
+        let previous = self
+            .last
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(candidate.max(last + 1))
+            })
+            .unwrap();
+        let last = candidate.max(previous + 1);
+        let (millis, sequence) = (last >> SEQUENCE_BITS, last & ((1 << SEQUENCE_BITS) - 1));
+        SnowflakeId(
+            (millis << (NODE_BITS + SEQUENCE_BITS))
+                | (u64::from(self.node) << SEQUENCE_BITS)
+                | sequence,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_increase_within_a_millisecond() {
+        let generator = SnowflakeGenerator::new(5).unwrap();
+        let now = SystemTime::now();
+        let ids: Vec<_> = (0..5000).map(|_| generator.generate_at(now)).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.iter().all(|id| id.node() == 5));
+        // The 4097th ID spilled into the next millisecond.
+        assert_eq!(ids[4096].sequence(), 0);
+        assert!(ids[4096].timestamp() > ids[0].timestamp());
+        assert!(SnowflakeGenerator::new(MAX_NODE + 1).is_none());
+    }
+}
@@ -0,0 +1,116 @@
+use std::{
+    any, fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+};
+
+use super::{GenerationalId, GlobalId};
+
+// An ID tagged with what it identifies, so e.g. an `Id<Texture>` can't be passed where an
+// `Id<Entity>` is expected. Same size and cost as the raw ID it wraps.
+pub struct Id<T: ?Sized, R = GlobalId> {
+    raw: R,
+    // fn() -> T keeps Id Send + Sync regardless of T.
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: ?Sized, R> Id<T, R> {
+    pub const fn from_raw(raw: R) -> Self {
+        Self {
+            raw,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn into_raw(self) -> R {
+        self.raw
+    }
+
+    pub fn raw(&self) -> &R {
+        &self.raw
+    }
+}
+
+impl<T: ?Sized> Id<T, GlobalId> {
+    pub fn allocate() -> Option<Self> {
+        GlobalId::allocate().map(Self::from_raw)
+    }
+}
+
+impl<T: ?Sized> Id<T, GenerationalId> {
+    pub fn index(&self) -> u32 {
+        self.raw.index()
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.raw.generation()
+    }
+}
+
+// Derives would require T: Clone etc., which marker types don't implement.
+impl<T: ?Sized, R: Clone> Clone for Id<T, R> {
+    fn clone(&self) -> Self {
+        Self::from_raw(self.raw.clone())
+    }
+}
+
+impl<T: ?Sized, R: Copy> Copy for Id<T, R> {}
+
+impl<T: ?Sized, R: PartialEq> PartialEq for Id<T, R> {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw == other.raw
+    }
+}
+
+impl<T: ?Sized, R: Eq> Eq for Id<T, R> {}
+
+impl<T: ?Sized, R: PartialOrd> PartialOrd for Id<T, R> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.raw.partial_cmp(&other.raw)
+    }
+}
+
+impl<T: ?Sized, R: Ord> Ord for Id<T, R> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.raw.cmp(&other.raw)
+    }
+}
+
+impl<T: ?Sized, R: fmt::Display> fmt::Display for Id<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.raw.fmt(f)
+    }
+}
+
+impl<T: ?Sized, R: Hash> Hash for Id<T, R> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.raw.hash(state);
+    }
+}
+
+// e.g. Id<Texture>(GlobalId(3)), with the module path stripped from the type name.
+impl<T: ?Sized, R: fmt::Debug> fmt::Debug for Id<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = any::type_name::<T>();
+        let name = name.rsplit("::").next().unwrap_or(name);
+        write!(f, "Id<{}>({:?})", name, self.raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Texture;
+
+    #[test]
+    fn debug_names_the_type() {
+        let id = Id::<Texture>::from_raw(GlobalId::allocate().unwrap());
+        assert!(format!("{:?}", id).starts_with("Id<Texture>(GlobalId("));
+        assert_eq!(
+            std::mem::size_of::<Id<Texture>>(),
+            std::mem::size_of::<GlobalId>()
+        );
+        assert_eq!(id, id.clone());
+    }
+}
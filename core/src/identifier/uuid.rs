@@ -0,0 +1,56 @@
+use std::{fmt, str::FromStr};
+
+// Identity that survives process restarts, for assets, save files and network sessions. The
+// in-memory counters (GlobalId, GenerationalId) start over every run.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Uuid(uuid::Uuid);
+
+impl Uuid {
+    pub const NIL: Uuid = Uuid(uuid::Uuid::nil());
+    // Namespace for name-based IDs the engine derives itself, e.g. asset paths.
+    pub const ENGINE_NAMESPACE: Uuid = Uuid(uuid::Uuid::from_u128(
+        0x6d69_646e_6967_6874_9e1f_2c4a_53b7_08d1,
+    ));
+
+    // Random (version 4).
+    pub fn new_v4() -> Self {
+        Uuid(uuid::Uuid::new_v4())
+    }
+
+    // Derived from a namespace and a name (version 5), the same inputs always give the same ID.
+    pub fn new_v5(namespace: &Uuid, name: &[u8]) -> Self {
+        Uuid(uuid::Uuid::new_v5(&namespace.0, name))
+    }
+
+    pub const fn from_bytes(bytes: [u8; 16]) -> Self {
+        Uuid(uuid::Uuid::from_bytes(bytes))
+    }
+
+    pub const fn as_bytes(&self) -> &[u8; 16] {
+        self.0.as_bytes()
+    }
+
+    pub fn is_nil(&self) -> bool {
+        self.0.is_nil()
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0.hyphenated(), f)
+    }
+}
+
+impl fmt::Debug for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Uuid({})", self)
+    }
+}
+
+impl FromStr for Uuid {
+    type Err = uuid::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        uuid::Uuid::parse_str(text).map(Uuid)
+    }
+}
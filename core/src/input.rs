@@ -0,0 +1,195 @@
+//! Platform independent input events. Window input is translated into these before it reaches
+//! the game, which also lets tests and tools script input without a window.
+
+use winit::{
+    event::{ElementState, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    Key {
+        code: KeyCode,
+        pressed: bool,
+    },
+    // Text produced by a key press, already run through the keyboard layout.
+    Text(String),
+    CursorMoved {
+        x: f64,
+        y: f64,
+    },
+    MouseButton {
+        button: MouseButton,
+        pressed: bool,
+    },
+    MouseWheel {
+        dx: f32,
+        dy: f32,
+    },
+    Touch {
+        id: u64,
+        phase: TouchPhase,
+        x: f64,
+        y: f64,
+    },
+}
+
+impl InputEvent {
+    // Lines are scrolled into pixels at this rate when the platform reports line deltas.
+    const PIXELS_PER_LINE: f32 = 20.0;
+
+    // One window event can produce several input events, a key press also types text.
+    pub fn from_window_event<F: FnMut(InputEvent)>(event: &WindowEvent, mut emit: F) {
+        match event {
+            WindowEvent::KeyboardInput { event, .. } => {
+                if let PhysicalKey::Code(code) = event.physical_key {
+                    emit(InputEvent::Key {
+                        code,
+                        pressed: event.state == ElementState::Pressed,
+                    });
+                }
+                if let Some(text) = event.text.as_ref().filter(|_| event.state.is_pressed()) {
+                    emit(InputEvent::Text(text.to_string()));
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => emit(InputEvent::CursorMoved {
+                x: position.x,
+                y: position.y,
+            }),
+            WindowEvent::MouseInput { state, button, .. } => emit(InputEvent::MouseButton {
+                button: *button,
+                pressed: *state == ElementState::Pressed,
+            }),
+            WindowEvent::MouseWheel { delta, .. } => emit(match delta {
+                MouseScrollDelta::LineDelta(dx, dy) => InputEvent::MouseWheel {
+                    dx: dx * Self::PIXELS_PER_LINE,
+                    dy: dy * Self::PIXELS_PER_LINE,
+                },
+                MouseScrollDelta::PixelDelta(delta) => InputEvent::MouseWheel {
+                    dx: delta.x as f32,
+                    dy: delta.y as f32,
+                },
+            }),
+            WindowEvent::Touch(touch) => emit(InputEvent::Touch {
+                id: touch.id,
+                phase: touch.phase,
+                x: touch.location.x,
+                y: touch.location.y,
+            }),
+            _ => {}
+        }
+    }
+}
+
+macro_rules! key {
+    ($code:ident) => {
+        (stringify!($code), KeyCode::$code)
+    };
+}
+
+// Keys that can be named in scripts and config files, spelled like their `KeyCode` variant.
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    key!(KeyA),
+    key!(KeyB),
+    key!(KeyC),
+    key!(KeyD),
+    key!(KeyE),
+    key!(KeyF),
+    key!(KeyG),
+    key!(KeyH),
+    key!(KeyI),
+    key!(KeyJ),
+    key!(KeyK),
+    key!(KeyL),
+    key!(KeyM),
+    key!(KeyN),
+    key!(KeyO),
+    key!(KeyP),
+    key!(KeyQ),
+    key!(KeyR),
+    key!(KeyS),
+    key!(KeyT),
+    key!(KeyU),
+    key!(KeyV),
+    key!(KeyW),
+    key!(KeyX),
+    key!(KeyY),
+    key!(KeyZ),
+    key!(Digit0),
+    key!(Digit1),
+    key!(Digit2),
+    key!(Digit3),
+    key!(Digit4),
+    key!(Digit5),
+    key!(Digit6),
+    key!(Digit7),
+    key!(Digit8),
+    key!(Digit9),
+    key!(F1),
+    key!(F2),
+    key!(F3),
+    key!(F4),
+    key!(F5),
+    key!(F6),
+    key!(F7),
+    key!(F8),
+    key!(F9),
+    key!(F10),
+    key!(F11),
+    key!(F12),
+    key!(ArrowUp),
+    key!(ArrowDown),
+    key!(ArrowLeft),
+    key!(ArrowRight),
+    key!(Space),
+    key!(Enter),
+    key!(Escape),
+    key!(Tab),
+    key!(Backspace),
+    key!(Delete),
+    key!(ShiftLeft),
+    key!(ShiftRight),
+    key!(ControlLeft),
+    key!(ControlRight),
+    key!(AltLeft),
+    key!(AltRight),
+    key!(Backquote),
+    key!(Minus),
+    key!(Equal),
+];
+
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|(_, code)| *code)
+}
+
+pub fn key_name(code: KeyCode) -> Option<&'static str> {
+    KEY_NAMES
+        .iter()
+        .find(|(_, key_code)| *key_code == code)
+        .map(|(name, _)| *name)
+}
+
+pub fn mouse_button_from_name(name: &str) -> Option<MouseButton> {
+    match name.to_ascii_lowercase().as_str() {
+        "left" => Some(MouseButton::Left),
+        "right" => Some(MouseButton::Right),
+        "middle" => Some(MouseButton::Middle),
+        "back" => Some(MouseButton::Back),
+        "forward" => Some(MouseButton::Forward),
+        other => other.parse().ok().map(MouseButton::Other),
+    }
+}
+
+pub fn mouse_button_name(button: MouseButton) -> String {
+    match button {
+        MouseButton::Left => "Left".to_owned(),
+        MouseButton::Right => "Right".to_owned(),
+        MouseButton::Middle => "Middle".to_owned(),
+        MouseButton::Back => "Back".to_owned(),
+        MouseButton::Forward => "Forward".to_owned(),
+        MouseButton::Other(button) => button.to_string(),
+    }
+}
@@ -0,0 +1,173 @@
+//! Single-instance guard. The first copy of a game listens on a loopback port derived from the
+//! instance name, later copies hand their command line to it and exit. Both sides greet each
+//! other first, so nothing is sent to some other program that happens to hold the port.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::identifier::Name;
+
+const HANDSHAKE: &str = "midnight2-instance";
+
+// Ports above the IANA dynamic range start are unlikely to collide with real services. The hash
+// has to be the same in every build, or two builds of a game wouldn't find each other.
+fn port_for(name: &str) -> u16 {
+    49152 + (Name::new(name).hash() % (u16::MAX as u64 - 49152)) as u16
+}
+
+pub enum Instance {
+    // We are the first copy, `InstanceServer::listen` receives arguments from later launches.
+    Primary(InstanceServer),
+    // Another copy is already running and has been sent our arguments.
+    Forwarded,
+}
+
+pub fn acquire(name: &str, args: &[String]) -> io::Result<Instance> {
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port_for(name)));
+    match TcpListener::bind(address) {
+        Ok(listener) => Ok(Instance::Primary(InstanceServer {
+            name: name.to_owned(),
+            listener,
+        })),
+        Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+            let mut stream = TcpStream::connect_timeout(&address, Duration::from_secs(1))?;
+            stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+            let handshake = format!("{} {}\n", HANDSHAKE, name);
+            stream.write_all(handshake.as_bytes())?;
+            let mut reply = String::new();
+            BufReader::new(&stream).read_line(&mut reply)?;
+            if reply != handshake {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is taken by something other than {}", address, name),
+                ));
+            }
+            let mut message = String::new();
+            for arg in args {
+                // One argument per line, so arguments themselves can't contain line breaks.
+                message += &arg.replace(['\r', '\n'], " ");
+                message.push('\n');
+            }
+            stream.write_all(message.as_bytes())?;
+            Ok(Instance::Forwarded)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+pub struct InstanceServer {
+    name: String,
+    listener: TcpListener,
+}
+
+impl InstanceServer {
+    // Spawns the listener thread, `on_launch` gets the arguments of every later launch.
+    pub fn listen<F>(self, mut on_launch: F) -> io::Result<InstanceListener>
+    where
+        F: FnMut(Vec<String>) + Send + 'static,
+    {
+        self.listener.set_nonblocking(true)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::Builder::new()
+            .name("instance listener".to_owned())
+            .spawn(move || {
+                while !thread_stop.load(Ordering::Relaxed) {
+                    match self.listener.accept() {
+                        Ok((stream, _)) => match self.read_launch(stream) {
+                            Ok(Some(args)) => on_launch(args),
+                            Ok(None) => {
+                                warn!("Ignoring connection that isn't a midnight2 instance")
+                            }
+                            Err(err) => warn!("Failed to read instance message: {}", err),
+                        },
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(Duration::from_millis(50));
+                        }
+                        Err(err) => {
+                            error!("Instance listener failed: {}", err);
+                            break;
+                        }
+                    }
+                }
+            })?;
+        Ok(InstanceListener {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    fn read_launch(&self, mut stream: TcpStream) -> io::Result<Option<Vec<String>>> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let mut lines = BufReader::new(stream.try_clone()?).lines();
+        let expected = format!("{} {}", HANDSHAKE, self.name);
+        match lines.next().transpose()? {
+            Some(handshake) if handshake == expected => {
+                // Greet back, the later copy only sends its arguments once it knows it's us.
+                stream.write_all(format!("{}\n", expected).as_bytes())?;
+                lines.collect::<io::Result<_>>().map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+// Stops and joins the listener thread when dropped, which also frees the port.
+pub struct InstanceListener {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for InstanceListener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread.take().map(JoinHandle::join);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn second_launch_forwards_args() {
+        let name = "midnight2-instance-test";
+        let Instance::Primary(server) = acquire(name, &[]).unwrap() else {
+            panic!("first launch should own the instance");
+        };
+        let (sender, receiver) = mpsc::channel();
+        let _listener = server
+            .listen(move |args| sender.send(args).unwrap())
+            .unwrap();
+
+        let args = vec!["--scene".to_owned(), "levels/intro.scene".to_owned()];
+        assert!(matches!(acquire(name, &args).unwrap(), Instance::Forwarded));
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), args);
+    }
+
+    #[test]
+    fn other_programs_on_the_port_get_no_args() {
+        let name = "midnight2-instance-impostor-test";
+        let impostor = TcpListener::bind((Ipv4Addr::LOCALHOST, port_for(name))).unwrap();
+        let thread = thread::spawn(move || {
+            let (mut stream, _) = impostor.accept().unwrap();
+            stream.write_all(b"HTTP/1.1 400 Bad Request\n").unwrap();
+            let mut received = String::new();
+            BufReader::new(stream).read_line(&mut received).unwrap();
+            received
+        });
+        let args = ["--secret".to_owned()];
+        assert!(acquire(name, &args).is_err());
+        assert_eq!(thread.join().unwrap(), format!("{} {}\n", HANDSHAKE, name));
+    }
+}
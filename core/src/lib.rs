@@ -1,7 +1,63 @@
 #[macro_use] extern crate log;
 
+// Declared first so log_scope! and profile_scope! are visible to the modules below.
+#[macro_use]
 pub mod logging;
+#[macro_use]
+pub mod profiling;
+
+pub mod app;
+pub mod assert;
+pub mod audio;
+pub mod camera2d;
+pub mod color_grading;
+pub mod config;
+pub mod crash_report;
+pub mod cvars;
+pub mod debug_hud;
+pub mod editor;
+pub mod console;
+pub mod coroutine;
+pub mod engine;
+pub mod foliage;
+pub mod frame_graph;
+pub mod gpu_memory;
+#[cfg(all(feature = "hot-reload", not(target_arch = "wasm32")))]
+pub mod hot_reload;
+pub mod input;
+pub mod instance;
+pub mod light_probe;
+pub mod loading;
+pub mod localization;
+pub(crate) mod markup;
+pub mod memory;
+pub mod mesh_lod;
+pub mod metrics;
+pub mod mods;
+pub mod net;
+pub mod occlusion;
+pub mod physics;
+pub mod picking;
+pub mod platform;
+pub mod portal;
+pub mod playback;
+pub mod rand;
+pub mod reflection;
+pub mod regression;
+pub mod save;
+pub mod schedule;
 pub mod render;
+pub mod render_graph;
+pub mod scripting;
 pub mod sim;
+pub mod tilemap;
+pub mod trail;
+pub mod tween;
+pub mod ui;
+pub mod vat;
+pub mod video;
+pub mod vfs;
+pub mod weather;
+pub mod workers;
 pub mod ecs;
 pub mod identifier;
\ No newline at end of file
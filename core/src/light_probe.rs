@@ -0,0 +1,502 @@
+//! Ambient light for things that move. Static geometry gets its ambient from the environment
+//! and baked lighting, characters and props can't, so a grid of light probes records the light
+//! arriving from every direction at points through the level as second order spherical
+//! harmonics, and each object is lit by the probes around it, blended by where it is. Outside
+//! the grid, or without one, objects fall back to the environment, projected the same way, so
+//! walking out of a probed area doesn't change how something is lit.
+//!
+//! Grids are baked offline and loaded, or relit at runtime a few probes a tick from whatever
+//! radiance the game gives them, there's nothing in the engine to trace the scene with. The
+//! coefficients come out ready for a shader's uniforms, nothing draws with them yet.
+
+use std::{collections::BTreeMap, f32::consts::PI, fmt};
+
+use crate::{
+    engine::{Engine, Plugin, Resources},
+    metrics,
+    vfs::Vfs,
+    weather::Sky,
+};
+
+// Directions the environment is sampled in when it's projected.
+const ENVIRONMENT_SAMPLES: usize = 128;
+const FILE_MAGIC: &[u8; 4] = b"MLPG";
+const FILE_VERSION: u32 = 1;
+
+// Radiance arriving from each direction, as the nine coefficients of the first three bands of
+// real spherical harmonics, per color channel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sh9 {
+    pub coefficients: [[f32; 3]; 9],
+}
+
+impl Sh9 {
+    // Projects `radiance` sampled in `samples` directions evenly over the sphere.
+    pub fn project(samples: usize, mut radiance: impl FnMut([f32; 3]) -> [f32; 3]) -> Self {
+        let mut sh = Self::default();
+        let samples = samples.max(1);
+        let weight = 4.0 * PI / samples as f32;
+        for i in 0..samples {
+            let direction = fibonacci_direction(i, samples);
+            let light = radiance(direction);
+            for (coefficient, basis) in sh.coefficients.iter_mut().zip(basis(direction)) {
+                for c in 0..3 {
+                    coefficient[c] += light[c] * basis * weight;
+                }
+            }
+        }
+        sh
+    }
+
+    pub fn uniform(radiance: [f32; 3]) -> Self {
+        let mut sh = Self::default();
+        sh.coefficients[0] = radiance.map(|c| c * (4.0 * PI).sqrt());
+        sh
+    }
+
+    pub fn lerp(&self, other: &Sh9, t: f32) -> Self {
+        let mut sh = *self;
+        for (a, b) in sh.coefficients.iter_mut().zip(&other.coefficients) {
+            for c in 0..3 {
+                a[c] += (b[c] - a[c]) * t;
+            }
+        }
+        sh
+    }
+
+    // What a white diffuse surface facing `normal` reflects, the ambient term.
+    pub fn ambient(&self, normal: [f32; 3]) -> [f32; 3] {
+        // Convolved with the cosine lobe per band, then over π for the diffuse BRDF.
+        const BAND: [f32; 9] = [
+            1.0,
+            2.0 / 3.0,
+            2.0 / 3.0,
+            2.0 / 3.0,
+            0.25,
+            0.25,
+            0.25,
+            0.25,
+            0.25,
+        ];
+        let mut ambient = [0.0; 3];
+        for ((coefficient, basis), band) in self
+            .coefficients
+            .iter()
+            .zip(basis(normalize(normal)))
+            .zip(BAND)
+        {
+            for c in 0..3 {
+                ambient[c] += coefficient[c] * basis * band;
+            }
+        }
+        ambient.map(|c| c.max(0.0))
+    }
+
+    // Padded to vec4s, how shaders take them.
+    pub fn to_uniform(&self) -> [[f32; 4]; 9] {
+        self.coefficients.map(|[r, g, b]| [r, g, b, 0.0])
+    }
+}
+
+fn basis([x, y, z]: [f32; 3]) -> [f32; 9] {
+    [
+        0.282_095,
+        0.488_603 * y,
+        0.488_603 * z,
+        0.488_603 * x,
+        1.092_548 * x * y,
+        1.092_548 * y * z,
+        0.315_392 * (3.0 * z * z - 1.0),
+        1.092_548 * x * z,
+        0.546_274 * (x * x - y * y),
+    ]
+}
+
+fn fibonacci_direction(i: usize, count: usize) -> [f32; 3] {
+    let golden = PI * (3.0 - 5.0f32.sqrt());
+    let y = 1.0 - (i as f32 + 0.5) / count as f32 * 2.0;
+    let radius = (1.0 - y * y).sqrt();
+    let angle = golden * i as f32;
+    [angle.cos() * radius, y, angle.sin() * radius]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length > 0.0 {
+        v.map(|c| c / length)
+    } else {
+        [0.0, 1.0, 0.0]
+    }
+}
+
+// Probes on a regular grid, x fastest then y then z.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeGrid {
+    pub origin: [f32; 3],
+    // Meters between neighbouring probes.
+    pub spacing: f32,
+    counts: [usize; 3],
+    probes: Vec<Sh9>,
+    // The next probe `relight` gets to.
+    cursor: usize,
+}
+
+impl ProbeGrid {
+    // A grid with every probe at `initial`, until it's baked.
+    pub fn new(origin: [f32; 3], spacing: f32, counts: [usize; 3], initial: Sh9) -> Self {
+        let counts = counts.map(|count| count.max(1));
+        Self {
+            origin,
+            spacing,
+            counts,
+            probes: vec![initial; counts[0] * counts[1] * counts[2]],
+            cursor: 0,
+        }
+    }
+
+    pub fn counts(&self) -> [usize; 3] {
+        self.counts
+    }
+
+    pub fn len(&self) -> usize {
+        self.probes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    pub fn probe(&self, cell: [usize; 3]) -> Option<&Sh9> {
+        self.index(cell).map(|index| &self.probes[index])
+    }
+
+    pub fn set_probe(&mut self, cell: [usize; 3], sh: Sh9) {
+        if let Some(index) = self.index(cell) {
+            self.probes[index] = sh;
+        }
+    }
+
+    pub fn position(&self, cell: [usize; 3]) -> [f32; 3] {
+        std::array::from_fn(|axis| self.origin[axis] + cell[axis] as f32 * self.spacing)
+    }
+
+    fn index(&self, [x, y, z]: [usize; 3]) -> Option<usize> {
+        let [w, h, d] = self.counts;
+        (x < w && y < h && z < d).then(|| x + w * (y + h * z))
+    }
+
+    fn cell(&self, index: usize) -> [usize; 3] {
+        let [w, h, _] = self.counts;
+        [index % w, index / w % h, index / (w * h)]
+    }
+
+    // `radiance` is the light arriving at a position from a direction.
+    pub fn bake(&mut self, samples: usize, radiance: impl FnMut([f32; 3], [f32; 3]) -> [f32; 3]) {
+        self.cursor = 0;
+        self.relight(self.len(), samples, radiance);
+    }
+
+    // Relights the next `count` probes, picking up where the last call stopped.
+    pub fn relight(
+        &mut self,
+        count: usize,
+        samples: usize,
+        mut radiance: impl FnMut([f32; 3], [f32; 3]) -> [f32; 3],
+    ) {
+        for _ in 0..count.min(self.len()) {
+            let position = self.position(self.cell(self.cursor));
+            self.probes[self.cursor] =
+                Sh9::project(samples, |direction| radiance(position, direction));
+            self.cursor = (self.cursor + 1) % self.len();
+        }
+    }
+
+    // Trilinear between the eight probes around `position`, clamped to the grid's edge.
+    pub fn sample(&self, position: [f32; 3]) -> Sh9 {
+        let mut low = [0; 3];
+        let mut t = [0.0; 3];
+        for axis in 0..3 {
+            let last = (self.counts[axis] - 1) as f32;
+            let f = ((position[axis] - self.origin[axis]) / self.spacing).clamp(0.0, last);
+            low[axis] = (f as usize).min(self.counts[axis].saturating_sub(2));
+            t[axis] = f - low[axis] as f32;
+        }
+        let mut sh = Sh9::default();
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut cell = low;
+            for axis in 0..3 {
+                if corner >> axis & 1 == 1 {
+                    cell[axis] += 1;
+                    weight *= t[axis];
+                } else {
+                    weight *= 1.0 - t[axis];
+                }
+            }
+            let Some(probe) = self.probe(cell) else {
+                continue;
+            };
+            for (a, b) in sh.coefficients.iter_mut().zip(&probe.coefficients) {
+                for c in 0..3 {
+                    a[c] += b[c] * weight;
+                }
+            }
+        }
+        sh
+    }
+
+    // How far `position` is outside the grid's bounds, 0 inside.
+    pub fn distance_outside(&self, position: [f32; 3]) -> f32 {
+        let max = self.position(self.counts.map(|count| count - 1));
+        let gaps: [f32; 3] = std::array::from_fn(|axis| {
+            (self.origin[axis] - position[axis])
+                .max(position[axis] - max[axis])
+                .max(0.0)
+        });
+        gaps.iter().map(|gap| gap * gap).sum::<f32>().sqrt()
+    }
+
+    // What bakers write: the magic, a version, the counts and the placement, then each probe's
+    // coefficients, all little endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = FILE_MAGIC.to_vec();
+        bytes.extend(FILE_VERSION.to_le_bytes());
+        for count in self.counts {
+            bytes.extend((count as u32).to_le_bytes());
+        }
+        for value in self.origin.iter().chain([&self.spacing]) {
+            bytes.extend(value.to_le_bytes());
+        }
+        for probe in &self.probes {
+            for value in probe.coefficients.iter().flatten() {
+                bytes.extend(value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut words = bytes
+            .get(4..)
+            .filter(|_| bytes.starts_with(FILE_MAGIC))
+            .ok_or("not a light probe grid")?
+            .chunks_exact(4)
+            .map(|word| <[u8; 4]>::try_from(word).unwrap());
+        let mut next = || words.next().ok_or("truncated light probe grid");
+        let version = u32::from_le_bytes(next()?);
+        if version != FILE_VERSION {
+            return Err(format!("unsupported light probe grid version {}", version));
+        }
+        let mut counts = [0; 3];
+        for count in &mut counts {
+            *count = u32::from_le_bytes(next()?) as usize;
+        }
+        if counts.contains(&0) {
+            return Err("light probe grid without probes".to_owned());
+        }
+        // Checked before allocating, the counts come straight from the file.
+        let probe_size = std::mem::size_of::<[[f32; 3]; 9]>();
+        let size = counts
+            .iter()
+            .try_fold(probe_size, |size, &count| size.checked_mul(count))
+            // The magic, version, counts, origin and spacing.
+            .and_then(|size| size.checked_add(9 * 4))
+            .ok_or("light probe grid too large")?;
+        if bytes.len() < size {
+            return Err("truncated light probe grid".to_owned());
+        }
+        let mut origin = [0.0; 3];
+        for value in &mut origin {
+            *value = f32::from_le_bytes(next()?);
+        }
+        let spacing = f32::from_le_bytes(next()?);
+        let mut grid = Self::new(origin, spacing, counts, Sh9::default());
+        for probe in &mut grid.probes {
+            for value in probe.coefficients.iter_mut().flatten() {
+                *value = f32::from_le_bytes(next()?);
+            }
+        }
+        Ok(grid)
+    }
+}
+
+// Reads a baked grid through the VFS, see `ProbeGrid::to_bytes`.
+pub fn load(vfs: &Vfs, path: &str) -> Result<ProbeGrid, String> {
+    let bytes = vfs
+        .read(path)
+        .map_err(|err| format!("Failed to read {}: {}", path, err))?;
+    ProbeGrid::from_bytes(&bytes).map_err(|err| format!("{}: {}", path, err))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ProbeObjectId(u64);
+
+impl fmt::Display for ProbeObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "probe object#{}", self.0)
+    }
+}
+
+type Radiance = Box<dyn FnMut([f32; 3], [f32; 3]) -> [f32; 3] + Send>;
+
+#[derive(Default)]
+pub struct LightProbes {
+    pub grid: Option<ProbeGrid>,
+    environment: Sh9,
+    // The sky the environment was last projected from.
+    sky: Option<Sky>,
+    next_id: u64,
+    // Where each object is and the light it gets there.
+    objects: BTreeMap<ProbeObjectId, ([f32; 3], Sh9)>,
+    radiance: Option<Radiance>,
+    // Probes the grid is relit by every tick, when there's radiance to relight from.
+    pub relight_per_tick: usize,
+    pub samples_per_probe: usize,
+}
+
+impl LightProbes {
+    pub fn environment(&self) -> &Sh9 {
+        &self.environment
+    }
+
+    // Sets the static environment directly, from an IBL cubemap's projection say. A sky
+    // replaces it when it changes.
+    pub fn set_environment(&mut self, environment: Sh9) {
+        self.environment = environment;
+    }
+
+    // Projects the sky into the environment when it changed since the last time.
+    pub fn follow_sky(&mut self, sky: &Sky) {
+        if self.sky.as_ref() != Some(sky) {
+            self.environment = Sh9::project(ENVIRONMENT_SAMPLES, |direction| sky.sample(direction));
+            self.sky = Some(sky.clone());
+        }
+    }
+
+    // What the grid is relit from at runtime, None for a baked grid left as it is.
+    pub fn set_radiance(
+        &mut self,
+        radiance: Option<impl FnMut([f32; 3], [f32; 3]) -> [f32; 3] + Send + 'static>,
+    ) {
+        self.radiance = radiance.map(|radiance| Box::new(radiance) as Radiance);
+    }
+
+    // The light at `position`, blending to the environment over a probe's spacing outside the
+    // grid.
+    pub fn sample(&self, position: [f32; 3]) -> Sh9 {
+        let Some(grid) = &self.grid else {
+            return self.environment;
+        };
+        let outside = grid.distance_outside(position) / grid.spacing.max(f32::EPSILON);
+        grid.sample(position)
+            .lerp(&self.environment, outside.min(1.0))
+    }
+
+    pub fn track(&mut self, position: [f32; 3]) -> ProbeObjectId {
+        self.next_id += 1;
+        let id = ProbeObjectId(self.next_id);
+        self.objects.insert(id, (position, self.sample(position)));
+        id
+    }
+
+    pub fn set_position(&mut self, id: ProbeObjectId, position: [f32; 3]) {
+        if let Some((current, _)) = self.objects.get_mut(&id) {
+            *current = position;
+        }
+    }
+
+    pub fn untrack(&mut self, id: ProbeObjectId) {
+        self.objects.remove(&id);
+    }
+
+    // The coefficients to light the object with, as of the last update.
+    pub fn lighting(&self, id: ProbeObjectId) -> Option<&Sh9> {
+        self.objects.get(&id).map(|(_, sh)| sh)
+    }
+
+    pub fn ambient(&self, id: ProbeObjectId, normal: [f32; 3]) -> [f32; 3] {
+        self.lighting(id).map_or([0.0; 3], |sh| sh.ambient(normal))
+    }
+
+    pub fn update(&mut self) {
+        if let (Some(grid), Some(radiance)) = (&mut self.grid, &mut self.radiance) {
+            grid.relight(self.relight_per_tick, self.samples_per_probe, radiance);
+        }
+        let lighting: Vec<_> = self
+            .objects
+            .iter()
+            .map(|(id, (position, _))| (*id, self.sample(*position)))
+            .collect();
+        for (id, sh) in lighting {
+            if let Some((_, current)) = self.objects.get_mut(&id) {
+                *current = sh;
+            }
+        }
+    }
+}
+
+pub struct LightProbePlugin;
+
+impl Plugin for LightProbePlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(LightProbes {
+                relight_per_tick: 4,
+                samples_per_probe: 64,
+                ..LightProbes::default()
+            })
+            .add_system(|resources: &mut Resources| {
+                let sky = resources.get::<Sky>().cloned();
+                let Some(probes) = resources.get_mut::<LightProbes>() else {
+                    return;
+                };
+                if let Some(sky) = sky {
+                    probes.follow_sky(&sky);
+                }
+                probes.update();
+                metrics::set_gauge("light_probe_objects", probes.objects.len() as f64);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 0.02)
+    }
+
+    #[test]
+    fn objects_blend_between_probes_and_environment() {
+        // Light from above only, half of it reaches a surface facing sideways.
+        let above = Sh9::project(512, |d| [d[1].max(0.0); 3]);
+        assert!(close(above.ambient([0.0, 1.0, 0.0]), [2.0 / 3.0; 3]));
+        assert!(above.ambient([1.0, 0.0, 0.0])[0] < 0.35);
+        assert!(close(
+            Sh9::uniform([0.5; 3]).ambient([0.3, -1.0, 0.2]),
+            [0.5; 3]
+        ));
+
+        // Dark at x = 0, bright at x = 2.
+        let mut grid = ProbeGrid::new([0.0; 3], 2.0, [2, 1, 1], Sh9::default());
+        grid.bake(64, |position, _| [position[0] / 2.0; 3]);
+        let bytes = grid.to_bytes();
+        assert!(ProbeGrid::from_bytes(&bytes[..bytes.len() - 4]).is_err());
+        let mut huge = bytes.clone();
+        huge[8..20].copy_from_slice(&[0xff; 12]);
+        assert!(ProbeGrid::from_bytes(&huge).is_err());
+        let grid = ProbeGrid::from_bytes(&bytes).unwrap();
+        let mut probes = LightProbes {
+            grid: Some(grid),
+            ..LightProbes::default()
+        };
+        probes.set_environment(Sh9::uniform([0.2; 3]));
+        let object = probes.track([0.5, 0.0, 0.0]);
+        assert!(close(probes.ambient(object, [0.0, 1.0, 0.0]), [0.25; 3]));
+        // Fully the environment a probe's spacing outside the grid.
+        probes.set_position(object, [2.0, 0.0, 3.0]);
+        probes.update();
+        assert!(close(probes.ambient(object, [0.0, 1.0, 0.0]), [0.2; 3]));
+    }
+}
@@ -0,0 +1,107 @@
+//! Loading phase between engine startup and the first game tick.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+// How the loading phase behaves. The game draws its own splash from `LoadingPhase::progress`.
+#[derive(Clone, Debug, Default)]
+pub struct LoadingScreen {
+    // Keeps the loading phase going for at least this long, even if nothing needs loading.
+    pub min_duration: Duration,
+}
+
+#[derive(Default)]
+struct Counters {
+    total: AtomicUsize,
+    completed: AtomicUsize,
+}
+
+// Cheap to clone and shareable with loader threads.
+#[derive(Clone, Default)]
+pub struct LoadProgress(Arc<Counters>);
+
+impl LoadProgress {
+    // Registers a unit of work, it counts as done once the returned task is dropped.
+    pub fn track(&self) -> LoadTask {
+        self.0.total.fetch_add(1, Ordering::Relaxed);
+        LoadTask(self.0.clone())
+    }
+
+    pub fn total(&self) -> usize {
+        self.0.total.load(Ordering::Relaxed)
+    }
+
+    pub fn completed(&self) -> usize {
+        self.0.completed.load(Ordering::Relaxed)
+    }
+
+    pub fn fraction(&self) -> f32 {
+        match self.total() {
+            0 => 1.0,
+            total => self.completed() as f32 / total as f32,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.completed() >= self.total()
+    }
+}
+
+pub struct LoadTask(Arc<Counters>);
+
+impl Drop for LoadTask {
+    fn drop(&mut self) {
+        self.0.completed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Resource present while the engine is loading, the sim holds back `Application::fixed_update`
+// until it reports finished.
+pub struct LoadingPhase {
+    pub screen: LoadingScreen,
+    pub progress: LoadProgress,
+    started: Instant,
+    finished: bool,
+}
+
+impl LoadingPhase {
+    pub fn new(screen: LoadingScreen) -> Self {
+        Self {
+            screen,
+            progress: LoadProgress::default(),
+            started: Instant::now(),
+            finished: false,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // Returns true on the tick the phase finishes.
+    pub(crate) fn update(&mut self) -> bool {
+        if self.finished
+            || !self.progress.is_done()
+            || self.started.elapsed() < self.screen.min_duration
+        {
+            return false;
+        }
+        info!(
+            "Loading finished after {:?}, {} tasks",
+            self.started.elapsed(),
+            self.progress.total()
+        );
+        self.finished = true;
+        true
+    }
+}
@@ -0,0 +1,444 @@
+//! Localized text. Language tables are loaded from `<dir>/<language>.ftl` and looked up with
+//! `tr!`, which works from anywhere, like the metrics registry:
+//!
+//! ```ignore
+//! let title = tr!("menu-play");
+//! let greeting = tr!("greeting", name = player.name);
+//! ```
+//!
+//! Tables use the basic subset of Fluent: `key = text` messages, `{ $arg }` placeables, terms
+//! (`-brand = Midnight`, used as `{ -brand }`) and indented continuation lines. Selectors and
+//! functions aren't supported. A message missing from the current language comes from the
+//! fallback language, and if it's missing there too the key itself is shown.
+//!
+//! The `-fonts` term lists fallback fonts the language needs beyond the game's own, e.g.
+//! `-fonts = NotoSansJP-Regular.otf`, for text renderers to load with `fonts()`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    error::Error,
+    fmt::Display,
+    fs,
+    path::Path,
+    sync::RwLock,
+};
+
+use crate::{
+    cvars::{self, Cvars},
+    engine::{Engine, Plugin, Resources},
+    metrics,
+};
+
+pub const DEFAULT_FALLBACK: &str = "en";
+const FONTS_TERM: &str = "-fonts";
+
+pub struct LanguageTable {
+    language: String,
+    // Terms are kept with their leading `-`.
+    messages: HashMap<String, String>,
+}
+
+impl LanguageTable {
+    pub fn parse(language: &str, source: &str) -> Result<Self, String> {
+        let mut messages = HashMap::new();
+        let mut current: Option<(String, String)> = None;
+        for (index, line) in source.lines().enumerate() {
+            let continued = line.starts_with([' ', '\t']) && !line.trim().is_empty();
+            if continued {
+                let (_, text) = current
+                    .as_mut()
+                    .ok_or_else(|| format!("line {}: continuation without a message", index + 1))?;
+                if !text.is_empty() {
+                    text.push('\n');
+                }
+                text.push_str(line.trim());
+                continue;
+            }
+            messages.extend(current.take());
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, text) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {}: expected `key = text`", index + 1))?;
+            let key = key.trim();
+            let name = key.strip_prefix('-').unwrap_or(key);
+            let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+            if !valid {
+                return Err(format!("line {}: invalid key {:?}", index + 1, key));
+            }
+            current = Some((key.to_owned(), text.trim().to_owned()));
+        }
+        messages.extend(current);
+        Ok(Self {
+            language: language.to_owned(),
+            messages,
+        })
+    }
+
+    // The language is the file name without the extension, e.g. `de.ftl`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let language = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_else(|| format!("{} has no language name", path.display()))?;
+        let source = fs::read_to_string(path)?;
+        Ok(Self::parse(language, &source).map_err(|err| format!("{}: {}", path.display(), err))?)
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.messages.get(key).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    fn fonts(&self) -> impl Iterator<Item = &str> {
+        self.get(FONTS_TERM)
+            .into_iter()
+            .flat_map(|fonts| fonts.split(','))
+            .map(str::trim)
+            .filter(|font| !font.is_empty())
+    }
+}
+
+struct Catalog {
+    tables: BTreeMap<String, LanguageTable>,
+    language: String,
+    fallback: String,
+    // Bumped whenever lookups might give different text.
+    generation: u64,
+    // Reported once each.
+    missing: BTreeSet<String>,
+}
+
+impl Catalog {
+    const fn new() -> Self {
+        Self {
+            tables: BTreeMap::new(),
+            language: String::new(),
+            fallback: String::new(),
+            generation: 0,
+            missing: BTreeSet::new(),
+        }
+    }
+
+    fn add_table(&mut self, table: LanguageTable) {
+        self.tables.insert(table.language.clone(), table);
+        self.missing.clear();
+        self.generation += 1;
+    }
+
+    fn set_language(&mut self, language: &str) -> Result<(), String> {
+        if !self.tables.contains_key(language) {
+            return Err(format!("no table for language {}", language));
+        }
+        if self.language != language {
+            self.language = language.to_owned();
+            self.missing.clear();
+            self.generation += 1;
+        }
+        Ok(())
+    }
+
+    fn set_fallback(&mut self, language: &str) {
+        self.fallback = language.to_owned();
+        self.generation += 1;
+    }
+
+    fn fonts(&self) -> Vec<String> {
+        let mut fonts: Vec<String> = Vec::new();
+        for table in [&self.language, &self.fallback]
+            .into_iter()
+            .filter_map(|language| self.tables.get(language))
+        {
+            for font in table.fonts() {
+                if !fonts.iter().any(|known| known == font) {
+                    fonts.push(font.to_owned());
+                }
+            }
+        }
+        fonts
+    }
+
+    fn translate(&self, key: &str, args: &[(&str, &dyn Display)]) -> Option<String> {
+        let (table, text) = self.lookup(key)?;
+        Some(format_message(text, args, table))
+    }
+
+    fn lookup(&self, key: &str) -> Option<(&LanguageTable, &str)> {
+        [&self.language, &self.fallback]
+            .into_iter()
+            .filter_map(|language| self.tables.get(language))
+            .find_map(|table| Some((table, table.get(key)?)))
+    }
+}
+
+static CATALOG: RwLock<Catalog> = RwLock::new(Catalog::new());
+
+// Adds a language, replacing its previous table.
+pub fn add_table(table: LanguageTable) {
+    CATALOG.write().unwrap().add_table(table);
+}
+
+// Loads every `.ftl` file in `dir`, returns the languages found.
+pub fn load_dir(dir: impl AsRef<Path>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut languages = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "ftl") {
+            let table = LanguageTable::load(&path)?;
+            languages.push(table.language.clone());
+            add_table(table);
+        }
+    }
+    languages.sort();
+    Ok(languages)
+}
+
+pub fn languages() -> Vec<String> {
+    CATALOG.read().unwrap().tables.keys().cloned().collect()
+}
+
+pub fn language() -> String {
+    CATALOG.read().unwrap().language.clone()
+}
+
+pub fn set_language(language: &str) -> Result<(), String> {
+    CATALOG.write().unwrap().set_language(language)
+}
+
+// Where messages missing from the current language come from, `LocalizationPlugin` sets
+// `DEFAULT_FALLBACK` unless told otherwise.
+pub fn set_fallback(language: &str) {
+    CATALOG.write().unwrap().set_fallback(language);
+}
+
+// Changes whenever text might have, so UI can cache translated strings until it does.
+pub fn generation() -> u64 {
+    CATALOG.read().unwrap().generation
+}
+
+// Fallback fonts of the current and the fallback language, in that order.
+pub fn fonts() -> Vec<String> {
+    CATALOG.read().unwrap().fonts()
+}
+
+// What `tr!` expands to.
+pub fn translate(key: &str, args: &[(&str, &dyn Display)]) -> String {
+    if let Some(text) = CATALOG.read().unwrap().translate(key, args) {
+        return text;
+    }
+    let mut catalog = CATALOG.write().unwrap();
+    if catalog.missing.insert(key.to_owned()) {
+        warn!("No text for {} in {}", key, catalog.language);
+        metrics::increment("localization_missing_total", 1);
+    }
+    key.to_owned()
+}
+
+// Fills in `{ $arg }`, `{ -term }` and `{ "literal" }` placeables, anything else is left as is.
+fn format_message(text: &str, args: &[(&str, &dyn Display)], table: &LanguageTable) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            rest = &rest[start..];
+            break;
+        };
+        let placeable = &rest[start..start + end + 1];
+        rest = &rest[start + end + 1..];
+        let inner = placeable[1..placeable.len() - 1].trim();
+        if let Some(name) = inner.strip_prefix('$') {
+            match args.iter().find(|(arg, _)| *arg == name) {
+                Some((_, value)) => out.push_str(&value.to_string()),
+                None => out.push_str(placeable),
+            }
+        } else if inner.starts_with('-') {
+            out.push_str(table.get(inner).unwrap_or(placeable));
+        } else if let Some(literal) = inner.strip_prefix('"').and_then(|l| l.strip_suffix('"')) {
+            out.push_str(literal);
+        } else {
+            out.push_str(placeable);
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// The user's language from the environment, e.g. `de` for `LANG=de_DE.UTF-8`.
+pub fn system_language() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty() && value != "C" && value != "POSIX")
+        .and_then(|value| {
+            let language = value.split(['_', '.', '@', '-']).next()?.to_lowercase();
+            (!language.is_empty()).then_some(language)
+        })
+}
+
+/// `tr!("key")` or `tr!("key", name = value, ..)`, the text for `key` in the current language.
+#[macro_export]
+macro_rules! tr {
+    ($key:expr) => {
+        $crate::localization::translate($key, &[])
+    };
+    ($key:expr, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::localization::translate(
+            $key,
+            &[$((stringify!($name), &$value as &dyn ::std::fmt::Display)),+],
+        )
+    };
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LanguageChanged {
+    pub language: String,
+}
+
+// Tells the game when the language switched, so it can rebuild text it laid out.
+#[derive(Default)]
+pub struct Localization {
+    language: String,
+    events: VecDeque<LanguageChanged>,
+}
+
+impl Localization {
+    // Events from the last tick, they are dropped on the next.
+    pub fn poll_event(&mut self) -> Option<LanguageChanged> {
+        self.events.pop_front()
+    }
+}
+
+// Loads the language tables in `dir` and switches languages with the `language` cvar.
+pub struct LocalizationPlugin {
+    dir: String,
+    fallback: String,
+}
+
+impl LocalizationPlugin {
+    pub fn new(dir: impl Into<String>) -> Self {
+        Self {
+            dir: dir.into(),
+            fallback: DEFAULT_FALLBACK.to_owned(),
+        }
+    }
+
+    pub fn with_fallback(mut self, language: impl Into<String>) -> Self {
+        self.fallback = language.into();
+        self
+    }
+}
+
+impl Plugin for LocalizationPlugin {
+    fn build(&self, engine: &mut Engine) {
+        match load_dir(&self.dir) {
+            Ok(languages) => info!("Loaded languages {:?} from {}", languages, self.dir),
+            Err(err) => error!("Failed to load languages from {}: {}", self.dir, err),
+        }
+        set_fallback(&self.fallback);
+        let available = languages();
+        let initial = system_language()
+            .filter(|language| available.contains(language))
+            .unwrap_or_else(|| self.fallback.clone());
+        cvars::cvars(engine).register("language", initial.as_str(), "language of the game's text");
+
+        engine
+            .insert_resource(Localization::default())
+            .add_system(|resources: &mut Resources| {
+                let Some(wanted) = resources
+                    .get::<Cvars>()
+                    .and_then(|cvars| cvars.get_str("language"))
+                    .map(str::to_owned)
+                else {
+                    return;
+                };
+                if wanted != language() {
+                    if let Err(err) = set_language(&wanted) {
+                        warn!("Can't switch language: {}", err);
+                        if let Some(cvars) = resources.get_mut::<Cvars>() {
+                            let _ = cvars.set("language", &language());
+                        }
+                    }
+                }
+                let Some(localization) = resources.get_mut::<Localization>() else {
+                    return;
+                };
+                localization.events.clear();
+                let current = language();
+                if localization.language != current {
+                    info!("Language is {}", current);
+                    localization.language = current.clone();
+                    localization
+                        .events
+                        .push_back(LanguageChanged { language: current });
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each test has its own catalog, the global one is shared by every test running.
+    #[test]
+    fn translates_with_args_terms_and_fallback() {
+        let mut catalog = Catalog::new();
+        catalog.add_table(
+            LanguageTable::parse(
+                "en",
+                concat!(
+                    "# English\n",
+                    "-brand = Midnight\n",
+                    "menu-play = Play\n",
+                    "greeting = Welcome to { -brand }, { $name }!\n",
+                    "credits =\n",
+                    "    Made by\n",
+                    "    everyone\n",
+                ),
+            )
+            .unwrap(),
+        );
+        catalog.add_table(
+            LanguageTable::parse("de", "-fonts = NotoSans.ttf\nmenu-play = Spielen").unwrap(),
+        );
+        catalog.set_fallback("en");
+        catalog.set_language("de").unwrap();
+        let tr = |key, args: &[(&str, &dyn Display)]| catalog.translate(key, args).unwrap();
+        assert_eq!(tr("menu-play", &[]), "Spielen");
+        assert_eq!(
+            tr("greeting", &[("name", &"Ada")]),
+            "Welcome to Midnight, Ada!"
+        );
+        assert_eq!(tr("credits", &[]), "Made by\neveryone");
+        assert_eq!(catalog.translate("missing-key", &[]), None);
+        assert_eq!(catalog.fonts(), ["NotoSans.ttf"]);
+        assert!(catalog.set_language("fr").is_err());
+        assert!(LanguageTable::parse("en", "no equals sign").is_err());
+
+        // Unclosed placeables are kept as they are.
+        let table = LanguageTable::parse("en", "").unwrap();
+        assert_eq!(format_message("a { $b", &[], &table), "a { $b");
+        assert_eq!(
+            tr!("localization-test-missing"),
+            "localization-test-missing"
+        );
+    }
+}
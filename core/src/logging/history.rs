@@ -0,0 +1,89 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use log::{Level, Record};
+
+#[derive(Clone, Debug)]
+pub struct LogLine {
+    // Increases by one per line, lets readers tell which lines they've already seen.
+    pub sequence: u64,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+struct Lines {
+    lines: VecDeque<LogLine>,
+    next_sequence: u64,
+}
+
+// Fixed size ring buffer of recent log lines, the oldest line is dropped once it's full.
+pub struct LogHistory {
+    capacity: usize,
+    inner: Mutex<Lines>,
+}
+
+impl LogHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Lines {
+                lines: VecDeque::with_capacity(capacity),
+                next_sequence: 0,
+            }),
+        }
+    }
+
+    pub fn push(&self, record: &Record) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.lines.len() == self.capacity {
+            inner.lines.pop_front();
+        }
+        let sequence = inner.next_sequence;
+        inner.next_sequence += 1;
+        inner.lines.push_back(LogLine {
+            sequence,
+            level: record.level(),
+            target: record.target().to_owned(),
+            message: record.args().to_string(),
+        });
+    }
+
+    pub fn latest_sequence(&self) -> Option<u64> {
+        self.inner.lock().unwrap().next_sequence.checked_sub(1)
+    }
+
+    // Lines matching `filter`, oldest first.
+    pub fn lines<F: Fn(&LogLine) -> bool>(&self, filter: F) -> Vec<LogLine> {
+        let inner = self.inner.lock().unwrap();
+        inner.lines.iter().filter(|line| filter(line)).cloned().collect()
+    }
+
+    // Every line, or None rather than waiting while another thread logs, e.g. from a panic hook.
+    pub fn try_lines(&self) -> Option<Vec<LogLine>> {
+        let inner = self.inner.try_lock().ok()?;
+        Some(inner.lines.iter().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_oldest_lines_when_full() {
+        let history = LogHistory::new(3);
+        for i in 0..5 {
+            history.push(
+                &Record::builder()
+                    .args(format_args!("line {}", i))
+                    .level(Level::Info)
+                    .target("test")
+                    .build(),
+            );
+        }
+        let lines = history.lines(|_| true);
+        let messages: Vec<_> = lines.iter().map(|line| line.message.as_str()).collect();
+        assert_eq!(messages, ["line 2", "line 3", "line 4"]);
+        assert_eq!(history.latest_sequence(), Some(4));
+    }
+}
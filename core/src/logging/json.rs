@@ -0,0 +1,169 @@
+use std::{
+    fmt::Write as _,
+    io::Write,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{
+    kv::{self, Key, Value, VisitSource},
+    Record,
+};
+
+use super::{thread_tag, LogSink};
+
+// Writes one JSON object per record:
+// {"timestamp":"2023-11-26T18:03:12.204Z","level":"INFO","module":"midnight2_core::app",
+//  "target":"midnight2_core::app","thread":"main#1","message":"Spawning window!","fields":{}}
+pub struct JsonSink {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonSink {
+    pub fn new<W: Write + Send + 'static>(out: W) -> Self {
+        Self {
+            out: Mutex::new(Box::new(out)),
+        }
+    }
+}
+
+impl LogSink for JsonSink {
+    fn log(&self, record: &Record) {
+        let line = format_record(record);
+        let mut out = self.out.lock().unwrap();
+        // Nowhere left to report a failing log writer.
+        let _ = out.write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = self.out.lock().unwrap().flush();
+    }
+}
+
+pub fn format_record(record: &Record) -> String {
+    let mut line = String::with_capacity(256);
+    line += "{\"timestamp\":";
+    write_string(&mut line, &rfc3339_utc(SystemTime::now()));
+    line += ",\"level\":";
+    write_string(&mut line, record.level().as_str());
+    line += ",\"module\":";
+    write_string(&mut line, record.module_path().unwrap_or_default());
+    line += ",\"target\":";
+    write_string(&mut line, record.target());
+    line += ",\"thread\":";
+    write_string(&mut line, &thread_tag());
+    line += ",\"message\":";
+    write_string(&mut line, &record.args().to_string());
+    line += ",\"fields\":{";
+    let _ = record.key_values().visit(&mut FieldWriter {
+        line: &mut line,
+        first: true,
+    });
+    line += "}}\n";
+    line
+}
+
+struct FieldWriter<'a> {
+    line: &'a mut String,
+    first: bool,
+}
+
+impl<'kvs> VisitSource<'kvs> for FieldWriter<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        if !self.first {
+            self.line.push(',');
+        }
+        self.first = false;
+        write_string(self.line, key.as_str());
+        self.line.push(':');
+        if let Some(value) = value.to_bool() {
+            let _ = write!(self.line, "{}", value);
+        } else if let Some(value) = value.to_i64() {
+            let _ = write!(self.line, "{}", value);
+        } else if let Some(value) = value.to_u64() {
+            let _ = write!(self.line, "{}", value);
+        } else if let Some(value) = value.to_f64().filter(|value| value.is_finite()) {
+            let _ = write!(self.line, "{}", value);
+        } else {
+            write_string(self.line, &value.to_string());
+        }
+        Ok(())
+    }
+}
+
+fn write_string(line: &mut String, value: &str) {
+    line.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(line, "\\u{:04x}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+// Formats as e.g. 2023-11-26T18:03:12.204Z without pulling in a date crate.
+pub fn rfc3339_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Howard Hinnant's civil_from_days.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(rfc3339_utc(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let time = UNIX_EPOCH + Duration::from_millis(1_701_021_792_204);
+        assert_eq!(rfc3339_utc(time), "2023-11-26T18:03:12.204Z");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(rfc3339_utc(leap_day), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn formats_fields_and_escapes() {
+        let fields = [("tick", Value::from(42)), ("name", Value::from("a \"b\""))];
+        let record = Record::builder()
+            .args(format_args!("line\nbreak"))
+            .level(log::Level::Warn)
+            .target("test")
+            .key_values(&fields)
+            .build();
+        let line = format_record(&record);
+        assert!(line.contains("\"level\":\"WARN\""));
+        assert!(line.contains("\"message\":\"line\\nbreak\""));
+        assert!(line.ends_with("\"fields\":{\"tick\":42,\"name\":\"a \\\"b\\\"\"}}\n"));
+    }
+}
@@ -0,0 +1,369 @@
+//! Engine logger. Every record goes to the pretty console logger first and then to each
+//! registered `LogSink`, using the same filter for all of them: the `log.filter` directives from
+//! the engine config file, overridden per module by `RUST_LOG`. Panics are logged through the
+//! same path so they reach file sinks too.
+
+extern crate pretty_env_logger;
+
+mod history;
+mod json;
+mod rate_limit;
+mod remote;
+
+use std::{
+    backtrace::Backtrace,
+    cell::Cell,
+    env,
+    fs::File,
+    io::{self, Write},
+    net::SocketAddr,
+    panic,
+    path::PathBuf,
+    sync::{Once, OnceLock, RwLock},
+    thread,
+    time::Duration,
+};
+
+use log::{Log, Metadata, Record};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use crate::{config::Config, crash_report, identifier::GlobalId};
+use rate_limit::{RateLimiter, Suppressed};
+
+pub use history::{LogHistory, LogLine};
+pub use json::{rfc3339_utc, JsonSink};
+pub use rate_limit::RateLimit;
+pub use remote::RemoteSink;
+
+const HISTORY_CAPACITY: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    // One JSON object per line, see `JsonSink`.
+    Json,
+}
+
+#[derive(Clone, Debug)]
+pub struct LogConfig {
+    // Filter directives like RUST_LOG's, read from `log.filter` in the engine config file when
+    // not set. RUST_LOG still wins for modules both mention.
+    pub filters: Option<String>,
+    // Format of the console output.
+    pub format: LogFormat,
+    // Also write JSON lines to this file, regardless of the console format.
+    pub json_file: Option<PathBuf>,
+    // Stream records to remote viewers that connect to this address, see `RemoteSink`.
+    pub remote_listen: Option<SocketAddr>,
+    // Write tracing spans (ticks, systems, frames, render passes) to this Chrome trace file.
+    // Needs the `chrome-trace` feature.
+    pub trace_file: Option<PathBuf>,
+    // Collapses floods of identical records into summaries, None logs every record.
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            filters: None,
+            format: LogFormat::default(),
+            json_file: None,
+            remote_listen: None,
+            trace_file: None,
+            rate_limit: Some(RateLimit::default()),
+        }
+    }
+}
+
+pub trait LogSink: Send + Sync {
+    fn log(&self, record: &Record);
+
+    fn flush(&self) {}
+}
+
+struct EngineLogger {
+    // Also holds the RUST_LOG filter every sink goes through.
+    console: env_logger::Logger,
+    pretty_console: bool,
+    history: LogHistory,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl EngineLogger {
+    fn dispatch(&self, record: &Record) {
+        if self.pretty_console {
+            self.console.log(record);
+        }
+        self.history.push(record);
+        for sink in SINKS.read().unwrap().iter() {
+            sink.log(record);
+        }
+    }
+
+    fn log_suppressed(&self, suppressed: Vec<Suppressed>) {
+        for suppressed in suppressed {
+            self.dispatch(
+                &Record::builder()
+                    .args(format_args!(
+                        "{} (x{} suppressed)",
+                        suppressed.message, suppressed.count
+                    ))
+                    .level(suppressed.level)
+                    .target(&suppressed.target)
+                    .build(),
+            );
+        }
+    }
+}
+
+static LOGGER: OnceLock<EngineLogger> = OnceLock::new();
+// Separate from LOGGER so sinks can be registered before init.
+static SINKS: RwLock<Vec<Box<dyn LogSink>>> = RwLock::new(Vec::new());
+thread_local! {
+    // Open log scopes on this thread, records are indented two spaces per scope.
+    static SCOPE_DEPTH: Cell<usize> = Cell::new(0);
+}
+#[cfg(feature = "chrome-trace")]
+static CHROME_TRACE: std::sync::Mutex<Option<tracing_chrome::FlushGuard>> =
+    std::sync::Mutex::new(None);
+
+impl Log for EngineLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.console.matches(record) {
+            return;
+        }
+        if let Some(limiter) = &self.rate_limiter {
+            let now = Instant::now();
+            self.log_suppressed(limiter.sweep(now));
+            let message = record.args().to_string();
+            if !limiter.allow(record.level(), record.target(), &message, now) {
+                return;
+            }
+        }
+        match SCOPE_DEPTH.with(Cell::get) {
+            0 => self.dispatch(record),
+            depth => self.dispatch(
+                &record
+                    .to_builder()
+                    .args(format_args!("{:indent$}{}", "", record.args(), indent = depth * 2))
+                    .build(),
+            ),
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(limiter) = &self.rate_limiter {
+            self.log_suppressed(limiter.drain());
+        }
+        self.console.flush();
+        for sink in SINKS.read().unwrap().iter() {
+            sink.flush();
+        }
+    }
+}
+
+pub fn init() {
+    init_with(&LogConfig::default());
+}
+
+pub fn init_with(config: &LogConfig) {
+    // Keep stdout clean for tooling reading JSON lines from it.
+    let status = |message: &str| match config.format {
+        LogFormat::Pretty => println!("{}", message),
+        LogFormat::Json => eprintln!("{}", message),
+    };
+    status("Initializing pretty_env_logger...");
+    if LOGGER.get().is_none() {
+        if config.format == LogFormat::Json {
+            add_sink(JsonSink::new(io::stdout()));
+        }
+        if let Some(path) = &config.json_file {
+            match File::create(path) {
+                Ok(file) => {
+                    add_sink(JsonSink::new(io::LineWriter::new(file)));
+                    crash_report::attach_file(path);
+                }
+                Err(err) => status(&format!("Failed to create {}: {}", path.display(), err)),
+            }
+        }
+        if let Some(address) = config.remote_listen {
+            match RemoteSink::listen(address) {
+                Ok(sink) => add_sink(sink),
+                Err(err) => status(&format!("Failed to listen on {}: {}", address, err)),
+            }
+        }
+    }
+    if let Some(path) = &config.trace_file {
+        if let Err(err) = init_trace_file(path) {
+            status(&format!("Failed to start tracing to {}: {}", path.display(), err));
+        }
+    }
+    let logger = LOGGER.get_or_init(|| {
+        let mut builder = pretty_env_logger::formatted_builder();
+        builder.format(format_console);
+        let filters = config.filters.clone().or_else(|| match Config::load_default() {
+            Ok(file) => file.get("log.filter").map(str::to_owned),
+            Err(err) => {
+                status(&format!("Failed to read the config file: {}", err));
+                None
+            }
+        });
+        if let Some(filters) = filters {
+            builder.parse_filters(&filters);
+        }
+        if let Ok(filters) = env::var("RUST_LOG") {
+            builder.parse_filters(&filters);
+        }
+        EngineLogger {
+            console: builder.build(),
+            pretty_console: config.format == LogFormat::Pretty,
+            history: LogHistory::new(HISTORY_CAPACITY),
+            rate_limiter: config.rate_limit.map(RateLimiter::new),
+        }
+    });
+    match log::set_logger(logger) {
+        Ok(()) => {
+            log::set_max_level(logger.console.filter());
+            install_panic_hook();
+            status("Done!");
+        }
+        Err(err) => status(&format!("Logger already initialized: {}", err)),
+    }
+}
+
+// pretty_env_logger's layout with the thread tag in front of the target.
+fn format_console(f: &mut env_logger::fmt::Formatter, record: &Record) -> io::Result<()> {
+    let level = f.default_styled_level(record.level());
+    let mut style = f.style();
+    let target = style.set_bold(true).value(record.target());
+    writeln!(f, " {} [{}] {} > {}", level, thread_tag(), target, record.args())
+}
+
+// Thread name plus the thread's GlobalId, e.g. "sim#2", so interleaved logs stay attributable
+// even for unnamed threads.
+fn thread_tag() -> String {
+    let current = thread::current();
+    let name = current.name().unwrap_or("thread");
+    match GlobalId::current_thread() {
+        Some(id) => format!("{}#{}", name, id.value()),
+        None => name.to_owned(),
+    }
+}
+
+// Replaces the default hook, which only writes to stderr, with one that logs the panic and its
+// backtrace through every sink and flushes them before the panic unwinds or aborts.
+fn install_panic_hook() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        panic::set_hook(Box::new(|info| {
+            let payload = info.payload();
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("Box<dyn Any>");
+            let location = info
+                .location()
+                .map(|location| location.to_string())
+                .unwrap_or_else(|| "unknown location".to_owned());
+            let thread = thread::current();
+            let summary = format!(
+                "Thread '{}' panicked at {}: {}",
+                thread.name().unwrap_or("<unnamed>"),
+                location,
+                message
+            );
+            error!(target: "panic", "{}\n{}", summary, Backtrace::force_capture());
+            crash_report::on_panic(&summary);
+            log::logger().flush();
+        }));
+    });
+}
+
+#[cfg(feature = "chrome-trace")]
+fn init_trace_file(path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    use tracing_subscriber::prelude::*;
+
+    let mut trace = CHROME_TRACE.lock().unwrap();
+    if trace.is_some() {
+        return Ok(());
+    }
+    let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new()
+        .file(path)
+        .include_args(true)
+        .build();
+    tracing_subscriber::registry().with(layer).try_init()?;
+    *trace = Some(guard);
+    Ok(())
+}
+
+#[cfg(not(feature = "chrome-trace"))]
+fn init_trace_file(_path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    Err("built without the chrome-trace feature".into())
+}
+
+// Flushes every sink and finishes the trace file, call before the process exits.
+pub fn flush() {
+    log::logger().flush();
+    #[cfg(feature = "chrome-trace")]
+    CHROME_TRACE.lock().unwrap().take();
+}
+
+// Sinks must not log themselves, the sink list is locked while they run.
+pub fn add_sink<S: LogSink + 'static>(sink: S) {
+    SINKS.write().unwrap().push(Box::new(sink));
+}
+
+/// The most recent log lines, None until `init` ran.
+pub fn history() -> Option<&'static LogHistory> {
+    LOGGER.get().map(|logger| &logger.history)
+}
+
+// Logs `name` when created and when dropped along with the elapsed time, records logged on this
+// thread in between are indented. Use `log_scope!`.
+pub struct LogScope {
+    target: &'static str,
+    name: String,
+    started: Instant,
+}
+
+impl LogScope {
+    pub fn new(target: &'static str, name: String) -> Self {
+        info!(target: target, "{}...", name);
+        SCOPE_DEPTH.with(|depth| depth.set(depth.get() + 1));
+        Self {
+            target,
+            name,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+impl Drop for LogScope {
+    fn drop(&mut self) {
+        SCOPE_DEPTH.with(|depth| depth.set(depth.get().saturating_sub(1)));
+        info!(target: self.target, "{} done in {:.2?}", self.name, self.elapsed());
+    }
+}
+
+/// Groups the records logged until the end of the enclosing block under a heading, e.g.
+/// `log_scope!("Renderer init");` or `log_scope!("Loading {}", path);`.
+#[macro_export]
+macro_rules! log_scope {
+    ($($arg:tt)+) => {
+        let _log_scope = $crate::logging::LogScope::new(module_path!(), format!($($arg)+));
+    };
+}
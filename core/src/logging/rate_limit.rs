@@ -0,0 +1,148 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+// std::time::Instant panics on wasm32-unknown-unknown.
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use log::Level;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    // Identical records (same level, target and message) are counted over this window.
+    pub window: Duration,
+    // How many of them are logged per window before the rest are collapsed into a summary.
+    pub burst: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(1),
+            burst: 5,
+        }
+    }
+}
+
+// Records that were dropped during a window, logged as "message (xN suppressed)" once it ends.
+#[derive(Debug, PartialEq)]
+pub(super) struct Suppressed {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    pub count: u32,
+}
+
+struct Seen {
+    window_start: Instant,
+    count: u32,
+}
+
+struct State {
+    seen: HashMap<(Level, String, String), Seen>,
+    last_sweep: Instant,
+}
+
+pub(super) struct RateLimiter {
+    limit: RateLimit,
+    state: Mutex<State>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(State {
+                seen: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    // Whether a record should be logged now, counting it either way.
+    pub fn allow(&self, level: Level, target: &str, message: &str, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let key = (level, target.to_owned(), message.to_owned());
+        let seen = state.seen.entry(key).or_insert(Seen {
+            window_start: now,
+            count: 0,
+        });
+        seen.count += 1;
+        seen.count <= self.limit.burst
+    }
+
+    // Summaries of windows that ended, checked at most once per window.
+    pub fn sweep(&self, now: Instant) -> Vec<Suppressed> {
+        let mut state = self.state.lock().unwrap();
+        if now.duration_since(state.last_sweep) < self.limit.window {
+            return Vec::new();
+        }
+        state.last_sweep = now;
+        self.take_expired(&mut state, |seen| {
+            now.duration_since(seen.window_start) >= self.limit.window
+        })
+    }
+
+    // Summaries of everything suppressed so far, for flushing before exit.
+    pub fn drain(&self) -> Vec<Suppressed> {
+        let mut state = self.state.lock().unwrap();
+        self.take_expired(&mut state, |_| true)
+    }
+
+    fn take_expired<F: Fn(&Seen) -> bool>(&self, state: &mut State, expired: F) -> Vec<Suppressed> {
+        let mut suppressed = Vec::new();
+        state.seen.retain(|(level, target, message), seen| {
+            if !expired(seen) {
+                return true;
+            }
+            if seen.count > self.limit.burst {
+                suppressed.push(Suppressed {
+                    level: *level,
+                    target: target.clone(),
+                    message: message.clone(),
+                    count: seen.count - self.limit.burst,
+                });
+            }
+            false
+        });
+        suppressed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collapses_repeats_per_window() {
+        let limiter = RateLimiter::new(RateLimit {
+            window: Duration::from_secs(1),
+            burst: 2,
+        });
+        let start = Instant::now();
+        let allowed = (0..1000)
+            .filter(|_| limiter.allow(Level::Trace, "render", "render loop!", start))
+            .count();
+        assert_eq!(allowed, 2);
+        assert!(limiter.allow(Level::Trace, "render", "other", start));
+        assert!(limiter.sweep(start).is_empty());
+
+        let suppressed = limiter.sweep(start + Duration::from_secs(1));
+        assert_eq!(
+            suppressed,
+            [Suppressed {
+                level: Level::Trace,
+                target: "render".to_owned(),
+                message: "render loop!".to_owned(),
+                count: 998,
+            }]
+        );
+        assert!(limiter.allow(
+            Level::Trace,
+            "render",
+            "render loop!",
+            start + Duration::from_secs(1)
+        ));
+    }
+}
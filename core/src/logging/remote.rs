@@ -0,0 +1,98 @@
+use std::{
+    io::{self, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use log::Record;
+
+use super::{json::format_record, LogSink};
+
+// A viewer that stops reading is dropped instead of stalling the thread that logs.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Streams records as JSON lines (see `JsonSink`) to remote viewers over TCP, for builds where the
+// local console can't be seen. `nc <host> <port>` is enough of a viewer.
+pub struct RemoteSink {
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl RemoteSink {
+    // Accepts viewers on `address` for as long as the process runs.
+    pub fn listen<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accepted = clients.clone();
+        thread::Builder::new()
+            .name("remote log".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream.and_then(|stream| {
+                        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                        Ok(stream)
+                    }) {
+                        Ok(stream) => {
+                            info!("Remote log viewer connected from {:?}", stream.peer_addr());
+                            accepted.lock().unwrap().push(stream);
+                        }
+                        Err(err) => warn!("Failed to accept remote log viewer: {}", err),
+                    }
+                }
+            })?;
+        Ok(Self { clients })
+    }
+
+    // Pushes records to a viewer listening on `address`, for devices that can't accept
+    // incoming connections.
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        Ok(Self {
+            clients: Arc::new(Mutex::new(vec![stream])),
+        })
+    }
+}
+
+impl LogSink for RemoteSink {
+    fn log(&self, record: &Record) {
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+        let line = format_record(record);
+        clients.retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+    }
+
+    fn flush(&self) {
+        for client in self.clients.lock().unwrap().iter_mut() {
+            let _ = client.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn streams_records_to_viewer() {
+        let viewer = TcpListener::bind("127.0.0.1:0").unwrap();
+        let sink = RemoteSink::connect(viewer.local_addr().unwrap()).unwrap();
+        let (stream, _) = viewer.accept().unwrap();
+
+        sink.log(
+            &Record::builder()
+                .args(format_args!("hello viewer"))
+                .level(log::Level::Info)
+                .target("test")
+                .build(),
+        );
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).unwrap();
+        assert!(line.contains("\"message\":\"hello viewer\""));
+    }
+}
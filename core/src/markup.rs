@@ -0,0 +1,320 @@
+// Just enough JSON and XML to read Tiled's files and the JSON other tools export, e.g. VAT clips:
+// no DTDs, processing instructions are skipped, and numbers are all f64.
+
+use std::{iter::Peekable, str::Chars};
+
+// How deep arrays, objects and elements can nest, so a hostile file can't overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut chars = source.chars().peekable();
+        let value = parse_value(&mut chars, 0)?;
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some(c) => Err(format!("unexpected {:?} after the JSON value", c)),
+            None => Ok(value),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Result<(), String> {
+    skip_whitespace(chars);
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        Some(c) => Err(format!("expected {:?}, found {:?}", expected, c)),
+        None => Err(format!("expected {:?}, found the end", expected)),
+    }
+}
+
+fn parse_value(chars: &mut Peekable<Chars>, depth: usize) -> Result<Json, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("JSON nested deeper than {}", MAX_DEPTH));
+    }
+    skip_whitespace(chars);
+    match chars.peek().copied() {
+        Some('{') => {
+            chars.next();
+            let mut fields = Vec::new();
+            skip_whitespace(chars);
+            if chars.next_if_eq(&'}').is_some() {
+                return Ok(Json::Object(fields));
+            }
+            loop {
+                skip_whitespace(chars);
+                expect(chars, '"')?;
+                let key = parse_string(chars)?;
+                expect(chars, ':')?;
+                fields.push((key, parse_value(chars, depth + 1)?));
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some('}') => return Ok(Json::Object(fields)),
+                    other => {
+                        return Err(format!("expected , or }} in an object, found {:?}", other))
+                    }
+                }
+            }
+        }
+        Some('[') => {
+            chars.next();
+            let mut items = Vec::new();
+            skip_whitespace(chars);
+            if chars.next_if_eq(&']').is_some() {
+                return Ok(Json::Array(items));
+            }
+            loop {
+                items.push(parse_value(chars, depth + 1)?);
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => continue,
+                    Some(']') => return Ok(Json::Array(items)),
+                    other => return Err(format!("expected , or ] in an array, found {:?}", other)),
+                }
+            }
+        }
+        Some('"') => {
+            chars.next();
+            parse_string(chars).map(Json::String)
+        }
+        Some(c) if c == '-' || c.is_ascii_digit() => {
+            let mut number = String::new();
+            while let Some(c) =
+                chars.next_if(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+            {
+                number.push(c);
+            }
+            number
+                .parse()
+                .map(Json::Number)
+                .map_err(|_| format!("{} is not a number", number))
+        }
+        Some(_) => {
+            let mut word = String::new();
+            while let Some(c) = chars.next_if(char::is_ascii_alphabetic) {
+                word.push(c);
+            }
+            match word.as_str() {
+                "true" => Ok(Json::Bool(true)),
+                "false" => Ok(Json::Bool(false)),
+                "null" => Ok(Json::Null),
+                _ => Err(format!("unexpected {:?}", word)),
+            }
+        }
+        None => Err("unexpected end of JSON".to_owned()),
+    }
+}
+
+// After the opening quote.
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut string = String::new();
+    loop {
+        match chars.next().ok_or("unterminated string")? {
+            '"' => return Ok(string),
+            '\\' => match chars.next().ok_or("unterminated string")? {
+                'n' => string.push('\n'),
+                't' => string.push('\t'),
+                'r' => string.push('\r'),
+                'b' => string.push('\u{8}'),
+                'f' => string.push('\u{c}'),
+                'u' => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16)
+                        .map_err(|_| format!("bad escape \\u{}", hex))?;
+                    string.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                c => string.push(c),
+            },
+            c => string.push(c),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    // Text directly inside, joined.
+    pub text: String,
+}
+
+impl Element {
+    // The document's root element.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut rest = source;
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix("<?") {
+                rest = &after[after.find("?>").ok_or("unterminated <?")? + 2..];
+            } else if let Some(after) = rest.strip_prefix("<!--") {
+                rest = &after[after.find("-->").ok_or("unterminated comment")? + 3..];
+            } else {
+                break;
+            }
+        }
+        let (element, _) = parse_element(rest, 0)?;
+        Ok(element)
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+// Parses the element `source` starts with, returning what follows it.
+fn parse_element(source: &str, depth: usize) -> Result<(Element, &str), String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("elements nested deeper than {}", MAX_DEPTH));
+    }
+    let rest = source.strip_prefix('<').ok_or("expected an element")?;
+    let end = rest
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .ok_or("unterminated tag")?;
+    let mut element = Element {
+        name: rest[..end].to_owned(),
+        ..Element::default()
+    };
+    let mut rest = &rest[end..];
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("/>") {
+            return Ok((element, after));
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            rest = after;
+            break;
+        }
+        let equals = rest.find('=').ok_or("attribute without a value")?;
+        let name = rest[..equals].trim().to_owned();
+        let value = rest[equals + 1..].trim_start();
+        let quote = value.chars().next().filter(|c| matches!(c, '"' | '\''));
+        let quote = quote.ok_or_else(|| format!("unquoted attribute {}", name))?;
+        let close = value[1..]
+            .find(quote)
+            .ok_or_else(|| format!("unterminated attribute {}", name))?;
+        element
+            .attributes
+            .push((name, unescape(&value[1..close + 1])));
+        rest = &value[close + 2..];
+    }
+    loop {
+        let open = rest
+            .find('<')
+            .ok_or_else(|| format!("unclosed <{}>", element.name))?;
+        element.text.push_str(&unescape(&rest[..open]));
+        rest = &rest[open..];
+        if let Some(after) = rest.strip_prefix("</") {
+            let close = after.find('>').ok_or("unterminated closing tag")?;
+            if after[..close].trim() != element.name {
+                return Err(format!(
+                    "<{}> closed by </{}>",
+                    element.name,
+                    after[..close].trim()
+                ));
+            }
+            return Ok((element, &after[close + 1..]));
+        }
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = &after[after.find("-->").ok_or("unterminated comment")? + 3..];
+            continue;
+        }
+        let (child, after) = parse_element(rest, depth + 1)?;
+        element.children.push(child);
+        rest = after;
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+pub fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut bits = 0u32;
+    let mut count = 0;
+    for c in text.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = match c {
+            'A'..='Z' => c as u32 - 'A' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 26,
+            '0'..='9' => c as u32 - '0' as u32 + 52,
+            '+' => 62,
+            '/' => 63,
+            _ => return Err(format!("{:?} isn't base64", c)),
+        };
+        bits = bits << 6 | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,206 @@
+//! Allocation tracking. `TrackingAllocator` wraps another global allocator and counts
+//! allocations and bytes per `MemoryTag`, the tag of the allocating thread's innermost
+//! `memory::scope`. Opt in from the game binary:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOCATOR: TrackingAllocator = TrackingAllocator::new(std::alloc::System);
+//! ```
+
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    cell::Cell,
+    sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+};
+
+use crate::{
+    engine::{Engine, Plugin},
+    metrics,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryTag {
+    Untagged,
+    Render,
+    Ecs,
+    Assets,
+    Audio,
+}
+
+impl MemoryTag {
+    pub const ALL: [MemoryTag; 5] = [
+        MemoryTag::Untagged,
+        MemoryTag::Render,
+        MemoryTag::Ecs,
+        MemoryTag::Assets,
+        MemoryTag::Audio,
+    ];
+
+    // Gauges published by `MemoryPlugin`: live bytes and allocations made since the last tick.
+    fn metric_names(self) -> (&'static str, &'static str) {
+        match self {
+            MemoryTag::Untagged => ("memory_untagged_bytes", "memory_untagged_allocs_per_tick"),
+            MemoryTag::Render => ("memory_render_bytes", "memory_render_allocs_per_tick"),
+            MemoryTag::Ecs => ("memory_ecs_bytes", "memory_ecs_allocs_per_tick"),
+            MemoryTag::Assets => ("memory_assets_bytes", "memory_assets_allocs_per_tick"),
+            MemoryTag::Audio => ("memory_audio_bytes", "memory_audio_allocs_per_tick"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub allocations: u64,
+    pub deallocations: u64,
+    // Bytes currently allocated. Memory freed under a different tag than it was allocated
+    // with is taken off the freeing tag, so one tag can go negative while another stays high.
+    // The sum over all tags is always right.
+    pub live_bytes: i64,
+    pub total_bytes: u64,
+}
+
+struct TagCounters {
+    allocations: AtomicU64,
+    deallocations: AtomicU64,
+    live_bytes: AtomicI64,
+    total_bytes: AtomicU64,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: TagCounters = TagCounters {
+    allocations: AtomicU64::new(0),
+    deallocations: AtomicU64::new(0),
+    live_bytes: AtomicI64::new(0),
+    total_bytes: AtomicU64::new(0),
+};
+
+static COUNTERS: [TagCounters; MemoryTag::ALL.len()] = [ZERO; MemoryTag::ALL.len()];
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // const so reading it from inside the allocator never allocates.
+    static CURRENT_TAG: Cell<MemoryTag> = const { Cell::new(MemoryTag::Untagged) };
+}
+
+fn current_tag() -> MemoryTag {
+    // Unavailable while the thread is being torn down.
+    CURRENT_TAG
+        .try_with(Cell::get)
+        .unwrap_or(MemoryTag::Untagged)
+}
+
+pub struct TrackingAllocator<A> {
+    inner: A,
+}
+
+impl<A> TrackingAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    fn track_alloc(&self, size: usize) {
+        let counters = &COUNTERS[current_tag() as usize];
+        counters.allocations.fetch_add(1, Ordering::Relaxed);
+        counters
+            .live_bytes
+            .fetch_add(size as i64, Ordering::Relaxed);
+        counters
+            .total_bytes
+            .fetch_add(size as u64, Ordering::Relaxed);
+        INSTALLED.store(true, Ordering::Relaxed);
+    }
+
+    fn track_dealloc(&self, size: usize) {
+        let counters = &COUNTERS[current_tag() as usize];
+        counters.deallocations.fetch_add(1, Ordering::Relaxed);
+        counters
+            .live_bytes
+            .fetch_sub(size as i64, Ordering::Relaxed);
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            self.track_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            self.track_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        self.track_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            self.track_dealloc(layout.size());
+            self.track_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+// Tags allocations on this thread until the returned guard is dropped.
+pub fn scope(tag: MemoryTag) -> TagScope {
+    TagScope {
+        previous: CURRENT_TAG.with(|current| current.replace(tag)),
+    }
+}
+
+pub struct TagScope {
+    previous: MemoryTag,
+}
+
+impl Drop for TagScope {
+    fn drop(&mut self) {
+        CURRENT_TAG.with(|current| current.set(self.previous));
+    }
+}
+
+// False unless a `TrackingAllocator` is the global allocator.
+pub fn is_tracking() -> bool {
+    INSTALLED.load(Ordering::Relaxed)
+}
+
+pub fn stats(tag: MemoryTag) -> MemoryStats {
+    let counters = &COUNTERS[tag as usize];
+    MemoryStats {
+        allocations: counters.allocations.load(Ordering::Relaxed),
+        deallocations: counters.deallocations.load(Ordering::Relaxed),
+        live_bytes: counters.live_bytes.load(Ordering::Relaxed),
+        total_bytes: counters.total_bytes.load(Ordering::Relaxed),
+    }
+}
+
+// Publishes live bytes and per tick allocation churn for every tag into metrics.
+pub struct MemoryPlugin;
+
+impl Plugin for MemoryPlugin {
+    fn build(&self, engine: &mut Engine) {
+        let mut last_allocations = [0; MemoryTag::ALL.len()];
+        engine.add_system(move |_| {
+            if !is_tracking() {
+                return;
+            }
+            for tag in MemoryTag::ALL {
+                let stats = stats(tag);
+                let (bytes_name, churn_name) = tag.metric_names();
+                let last = &mut last_allocations[tag as usize];
+                metrics::set_gauge(bytes_name, stats.live_bytes as f64);
+                metrics::set_gauge(churn_name, (stats.allocations - *last) as f64);
+                *last = stats.allocations;
+            }
+        });
+    }
+}
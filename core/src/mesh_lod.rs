@@ -0,0 +1,603 @@
+//! Mesh simplification and LOD chains, for importing meshes: `generate_lods` simplifies a mesh
+//! into coarser levels with quadric error metrics, orders each level's triangles for the
+//! post-transform vertex cache and reorders the vertices for fetching, so all the levels share
+//! one vertex buffer and the runtime only picks a level.
+//!
+//! ```ignore
+//! let chain = mesh_lod::generate_lods(&positions, &indices, &LodSettings::default());
+//! let positions = mesh_lod::remap_vertices(&positions, &chain.vertex_order);
+//! let uvs = mesh_lod::remap_vertices(&uvs, &chain.vertex_order);
+//! ```
+//!
+//! Simplifying only collapses vertices into their neighbours, so the levels index the original
+//! vertices and every attribute carries over untouched. Vertices sharing a position with
+//! another, the two sides of a UV or normal seam, stay where they are so seams don't tear, and
+//! vertices on the mesh's border only slide along it. Errors are distances relative to the
+//! mesh's largest dimension.
+
+use std::collections::HashMap;
+
+// Post-transform cache size the triangle order is tuned for, about what current GPUs have.
+const CACHE_SIZE: usize = 32;
+// How much more moving off a border costs than moving off a surface.
+const BORDER_WEIGHT: f64 = 10.0;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Lod {
+    pub indices: Vec<u32>,
+    // The largest distance the surface moved, relative to the mesh's size.
+    pub error: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct LodSettings {
+    // Fraction of the original triangles each level after the first aims for.
+    pub ratios: Vec<f32>,
+    // A level stops short of its ratio rather than move the surface further than this.
+    pub max_error: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            ratios: vec![0.5, 0.25, 0.125],
+            max_error: 0.02,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LodChain {
+    // The original index of each vertex in the optimized vertex buffer, see `remap_vertices`.
+    // Vertices no level uses are left out.
+    pub vertex_order: Vec<u32>,
+    // Finest first, indexing the reordered vertices.
+    pub lods: Vec<Lod>,
+}
+
+impl LodChain {
+    // The coarsest level that doesn't move the surface more than `max_error`.
+    pub fn select(&self, max_error: f32) -> usize {
+        self.lods
+            .iter()
+            .rposition(|lod| lod.error <= max_error)
+            .unwrap_or(0)
+    }
+}
+
+// Simplifies the mesh into levels per `settings`, then optimizes them for the vertex cache and
+// the vertices for fetching. Levels that couldn't get any coarser within the error are left out.
+pub fn generate_lods(positions: &[[f32; 3]], indices: &[u32], settings: &LodSettings) -> LodChain {
+    let mut lods = vec![Lod {
+        indices: indices.to_vec(),
+        error: 0.0,
+    }];
+    for &ratio in &settings.ratios {
+        let target = (indices.len() / 3) as f32 * ratio.clamp(0.0, 1.0);
+        let previous = lods.last().unwrap();
+        let lod = simplify(
+            positions,
+            &previous.indices,
+            target as usize * 3,
+            settings.max_error,
+        );
+        if lod.indices.len() >= previous.indices.len() {
+            break;
+        }
+        let error = lod.error.max(previous.error);
+        lods.push(Lod { error, ..lod });
+    }
+    for lod in &mut lods {
+        optimize_vertex_cache(&mut lod.indices, positions.len());
+    }
+
+    // One vertex buffer for every level, in the order the finest level first uses them.
+    let mut all: Vec<u32> = lods.iter().flat_map(|lod| lod.indices.clone()).collect();
+    let vertex_order = optimize_vertex_fetch(&mut all, positions.len());
+    let mut all = all.into_iter();
+    for lod in &mut lods {
+        lod.indices = all.by_ref().take(lod.indices.len()).collect();
+    }
+    LodChain { vertex_order, lods }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Quadric {
+    // The symmetric 4x4 matrix's upper triangle, row by row.
+    q: [f64; 10],
+    // Total area behind it, errors are averaged over it.
+    weight: f64,
+}
+
+impl Quadric {
+    fn plane(normal: [f64; 3], point: [f64; 3], weight: f64) -> Self {
+        let [a, b, c] = normal;
+        let d = -dot(normal, point);
+        let q = [
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ];
+        Self {
+            q: q.map(|x| x * weight),
+            weight,
+        }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (q, o) in self.q.iter_mut().zip(other.q) {
+            *q += o;
+        }
+        self.weight += other.weight;
+    }
+
+    // Squared distance from the planes, weighted by their areas.
+    fn error(&self, [x, y, z]: [f64; 3]) -> f64 {
+        let q = &self.q;
+        let error = q[0] * x * x
+            + 2.0 * (q[1] * x * y + q[2] * x * z + q[3] * x)
+            + q[4] * y * y
+            + 2.0 * (q[5] * y * z + q[6] * y)
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9];
+        error.max(0.0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Manifold,
+    // On an edge only one triangle uses, can only slide along such edges.
+    Border,
+    // Shares its position with another vertex, never moves.
+    Locked,
+}
+
+// Collapses vertices into their neighbours, cheapest first, until the mesh is down to
+// `target_index_count` indices or the next collapse would move the surface more than
+// `max_error`. The result indexes the same vertices.
+pub fn simplify(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    target_index_count: usize,
+    max_error: f32,
+) -> Lod {
+    assert_eq!(indices.len() % 3, 0, "indices aren't a triangle list");
+    let points = normalized(positions);
+    let kinds = kinds(positions, indices);
+    let mut quadrics = quadrics(&points, indices);
+    let mut result = indices.to_vec();
+    let mut remap: Vec<u32> = (0..positions.len() as u32).collect();
+    let limit = f64::from(max_error) * f64::from(max_error);
+    let mut error = 0.0f64;
+
+    while result.len() > target_index_count {
+        let borders = border_edges(&result);
+        let mut candidates = Vec::new();
+        for triangle in result.chunks_exact(3) {
+            for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+                let (a, b) = (triangle[a], triangle[b]);
+                let border = borders.contains_key(&edge(a, b));
+                for (from, to) in [(a, b), (b, a)] {
+                    let allowed = match kinds[from as usize] {
+                        Kind::Manifold => true,
+                        Kind::Border => border,
+                        Kind::Locked => false,
+                    };
+                    if allowed {
+                        let mut quadric = quadrics[from as usize];
+                        quadric.add(&quadrics[to as usize]);
+                        let cost = quadric.error(points[to as usize]) / quadric.weight.max(1e-12);
+                        candidates.push((cost, from, to));
+                    }
+                }
+            }
+        }
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut triangles_of = vec![Vec::new(); positions.len()];
+        for (t, triangle) in result.chunks_exact(3).enumerate() {
+            for &v in triangle {
+                triangles_of[v as usize].push(t);
+            }
+        }
+        let mut touched = vec![false; positions.len()];
+        let mut removed = 0;
+        let mut collapsed = false;
+        for (cost, from, to) in candidates {
+            if cost > limit || result.len() - removed * 3 <= target_index_count {
+                break;
+            }
+            let (from, to) = (from as usize, to as usize);
+            if touched[from] || touched[to] {
+                continue;
+            }
+            let triangles = &triangles_of[from];
+            if flips(&result, triangles, &points, from, to) {
+                continue;
+            }
+            for &t in triangles {
+                let triangle = &result[t * 3..t * 3 + 3];
+                if triangle.contains(&(to as u32)) {
+                    removed += 1;
+                }
+                for &v in triangle {
+                    touched[v as usize] = true;
+                }
+            }
+            remap[from] = to as u32;
+            let quadric = quadrics[from];
+            quadrics[to].add(&quadric);
+            error = error.max(cost);
+            collapsed = true;
+        }
+        if !collapsed {
+            break;
+        }
+        result = result
+            .chunks_exact(3)
+            .map(|triangle| triangle.iter().map(|&v| remap[v as usize]))
+            .flat_map(|triangle| {
+                let [a, b, c]: [u32; 3] = triangle.collect::<Vec<_>>().try_into().unwrap();
+                (a != b && b != c && c != a).then_some([a, b, c])
+            })
+            .flatten()
+            .collect();
+    }
+    Lod {
+        indices: result,
+        error: error.sqrt() as f32,
+    }
+}
+
+// Whether moving `from` to `to` turns any of its triangles that survive the collapse over.
+fn flips(
+    indices: &[u32],
+    triangles: &[usize],
+    points: &[[f64; 3]],
+    from: usize,
+    to: usize,
+) -> bool {
+    triangles.iter().any(|&t| {
+        let triangle: [u32; 3] = indices[t * 3..t * 3 + 3].try_into().unwrap();
+        if triangle.contains(&(to as u32)) {
+            return false;
+        }
+        let corners = triangle.map(|v| points[v as usize]);
+        let moved = triangle.map(|v| points[if v as usize == from { to } else { v as usize }]);
+        dot(normal(corners), normal(moved)) <= 0.0
+    })
+}
+
+// Positions scaled into the unit cube, so errors are relative to the mesh's size.
+fn normalized(positions: &[[f32; 3]]) -> Vec<[f64; 3]> {
+    let mut min = [f64::MAX; 3];
+    let mut max = [f64::MIN; 3];
+    for p in positions {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(f64::from(p[axis]));
+            max[axis] = max[axis].max(f64::from(p[axis]));
+        }
+    }
+    let extent = (0..3).map(|axis| max[axis] - min[axis]).fold(0.0, f64::max);
+    let scale = if extent > 0.0 { 1.0 / extent } else { 1.0 };
+    positions
+        .iter()
+        .map(|p| std::array::from_fn(|axis| (f64::from(p[axis]) - min[axis]) * scale))
+        .collect()
+}
+
+fn kinds(positions: &[[f32; 3]], indices: &[u32]) -> Vec<Kind> {
+    let mut kinds = vec![Kind::Manifold; positions.len()];
+    for (a, b) in border_edges(indices).into_values() {
+        kinds[a as usize] = Kind::Border;
+        kinds[b as usize] = Kind::Border;
+    }
+    let mut by_position: HashMap<[u32; 3], usize> = HashMap::new();
+    for p in positions {
+        *by_position.entry(p.map(f32::to_bits)).or_default() += 1;
+    }
+    for (kind, p) in kinds.iter_mut().zip(positions) {
+        if by_position[&p.map(f32::to_bits)] > 1 {
+            *kind = Kind::Locked;
+        }
+    }
+    kinds
+}
+
+// Edges only one triangle uses, keyed by `edge`, as they run in that triangle.
+fn border_edges(indices: &[u32]) -> HashMap<(u32, u32), (u32, u32)> {
+    let mut uses: HashMap<(u32, u32), (usize, (u32, u32))> = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+        for (a, b) in [(0, 1), (1, 2), (2, 0)] {
+            let (a, b) = (triangle[a], triangle[b]);
+            uses.entry(edge(a, b)).or_insert((0, (a, b))).0 += 1;
+        }
+    }
+    uses.into_iter()
+        .filter(|(_, (count, _))| *count == 1)
+        .map(|(key, (_, edge))| (key, edge))
+        .collect()
+}
+
+fn edge(a: u32, b: u32) -> (u32, u32) {
+    (a.min(b), a.max(b))
+}
+
+// Each vertex's quadric from the planes of its triangles, plus planes through border edges at
+// right angles to the surface so borders keep their shape.
+fn quadrics(points: &[[f64; 3]], indices: &[u32]) -> Vec<Quadric> {
+    let mut quadrics = vec![Quadric::default(); points.len()];
+    let borders = border_edges(indices);
+    for triangle in indices.chunks_exact(3) {
+        let corners = [0, 1, 2].map(|i| points[triangle[i] as usize]);
+        let cross = normal(corners);
+        let area = length(cross) * 0.5;
+        if area <= 0.0 {
+            continue;
+        }
+        let n = scale(cross, 1.0 / length(cross));
+        let plane = Quadric::plane(n, corners[0], area);
+        for &v in triangle {
+            quadrics[v as usize].add(&plane);
+        }
+        for (i, j) in [(0, 1), (1, 2), (2, 0)] {
+            let (a, b) = (triangle[i], triangle[j]);
+            if borders.get(&edge(a, b)) != Some(&(a, b)) {
+                continue;
+            }
+            let along = sub(corners[j], corners[i]);
+            let perpendicular = cross3(along, n);
+            let len = length(perpendicular);
+            if len <= 0.0 {
+                continue;
+            }
+            let weight = dot(along, along) * BORDER_WEIGHT;
+            let plane = Quadric::plane(scale(perpendicular, 1.0 / len), corners[i], weight);
+            quadrics[a as usize].add(&plane);
+            quadrics[b as usize].add(&plane);
+        }
+    }
+    quadrics
+}
+
+// Reorders triangles so vertices are reused while they're still in the post-transform cache,
+// after Tom Forsyth's linear-speed vertex cache optimisation.
+pub fn optimize_vertex_cache(indices: &mut [u32], vertex_count: usize) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+    let mut triangles_of = vec![Vec::new(); vertex_count];
+    for (t, triangle) in indices.chunks_exact(3).enumerate() {
+        for &v in triangle {
+            triangles_of[v as usize].push(t);
+        }
+    }
+    let mut remaining: Vec<usize> = triangles_of.iter().map(Vec::len).collect();
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut scores: Vec<f32> = (0..vertex_count)
+        .map(|v| vertex_score(None, remaining[v]))
+        .collect();
+    let mut triangle_scores: Vec<f32> = indices
+        .chunks_exact(3)
+        .map(|triangle| triangle.iter().map(|&v| scores[v as usize]).sum())
+        .collect();
+    let mut emitted = vec![false; triangle_count];
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut order = Vec::with_capacity(indices.len());
+    // Where to resume looking for a triangle when nothing in the cache has any left.
+    let mut cursor = 0;
+
+    for _ in 0..triangle_count {
+        let best = cache
+            .iter()
+            .flat_map(|&v| &triangles_of[v as usize])
+            .copied()
+            .filter(|&t| !emitted[t])
+            .max_by(|&a, &b| triangle_scores[a].total_cmp(&triangle_scores[b]))
+            .or_else(|| {
+                while emitted[cursor] {
+                    cursor += 1;
+                }
+                Some(cursor)
+            })
+            .unwrap();
+        emitted[best] = true;
+        let triangle = [0, 1, 2].map(|i| indices[best * 3 + i]);
+        order.extend(triangle);
+
+        for v in triangle {
+            remaining[v as usize] -= 1;
+            cache.retain(|&cached| cached != v);
+        }
+        let evicted = if cache.len() + 3 > CACHE_SIZE {
+            cache.split_off(CACHE_SIZE - 3)
+        } else {
+            Vec::new()
+        };
+        cache.splice(0..0, triangle);
+        for &v in &evicted {
+            cache_position[v as usize] = None;
+        }
+        for (position, &v) in cache.iter().enumerate() {
+            cache_position[v as usize] = Some(position);
+        }
+        for &v in cache.iter().chain(&evicted) {
+            let v = v as usize;
+            let score = vertex_score(cache_position[v], remaining[v]);
+            let delta = score - scores[v];
+            scores[v] = score;
+            for &t in &triangles_of[v] {
+                triangle_scores[t] += delta;
+            }
+        }
+    }
+    indices.copy_from_slice(&order);
+}
+
+fn vertex_score(cache_position: Option<usize>, remaining: usize) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache = match cache_position {
+        None => 0.0,
+        // The triangle just drawn, it doesn't matter which order it's in.
+        Some(position) if position < 3 => 0.75,
+        Some(position) => {
+            let scale = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(1.5)
+        }
+    };
+    // Favour finishing off vertices with few triangles left.
+    cache + 2.0 * (remaining as f32).powf(-0.5)
+}
+
+// Renumbers vertices in the order the indices first use them, so fetching walks the vertex
+// buffer forwards. Returns the original index of each new vertex, unused ones left out.
+pub fn optimize_vertex_fetch(indices: &mut [u32], vertex_count: usize) -> Vec<u32> {
+    let mut new_index = vec![u32::MAX; vertex_count];
+    let mut order = Vec::new();
+    for index in indices.iter_mut() {
+        let new = &mut new_index[*index as usize];
+        if *new == u32::MAX {
+            *new = order.len() as u32;
+            order.push(*index);
+        }
+        *index = *new;
+    }
+    order
+}
+
+// One vertex attribute reordered per `optimize_vertex_fetch` or `LodChain::vertex_order`.
+pub fn remap_vertices<T: Copy>(vertices: &[T], order: &[u32]) -> Vec<T> {
+    order
+        .iter()
+        .map(|&index| vertices[index as usize])
+        .collect()
+}
+
+// Average cache misses per triangle with a FIFO cache of `cache_size`, 0.5 is about as good as
+// a regular grid gets and 3 means no reuse at all.
+pub fn acmr(indices: &[u32], cache_size: usize) -> f32 {
+    if indices.is_empty() {
+        return 0.0;
+    }
+    let mut cache = std::collections::VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for &index in indices {
+        if !cache.contains(&index) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.pop_front();
+            }
+            cache.push_back(index);
+        }
+    }
+    misses as f32 / (indices.len() / 3) as f32
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: [f64; 3], s: f64) -> [f64; 3] {
+    a.map(|x| x * s)
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross3(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn length(a: [f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+// Not normalized, its length is twice the triangle's area.
+fn normal([a, b, c]: [[f64; 3]; 3]) -> [f64; 3] {
+    cross3(sub(b, a), sub(c, a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A bumpy grid of `n` by `n` quads, with the triangles in a scrambled order.
+    fn grid(n: u32, bump: f32) -> (Vec<[f32; 3]>, Vec<u32>) {
+        let positions = (0..=n)
+            .flat_map(|y| (0..=n).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let height = if (x, y) == (n / 2, n / 2) { bump } else { 0.0 };
+                [x as f32, y as f32, height]
+            })
+            .collect();
+        let mut quads: Vec<_> = (0..n).flat_map(|y| (0..n).map(move |x| (x, y))).collect();
+        quads.sort_by_key(|&(x, y)| (x * 7919 + y * 104729) % 1021);
+        let indices = quads
+            .into_iter()
+            .flat_map(|(x, y)| {
+                let corner = |dx, dy| (y + dy) * (n + 1) + x + dx;
+                let (a, b, c, d) = (corner(0, 0), corner(1, 0), corner(0, 1), corner(1, 1));
+                [a, b, d, a, d, c]
+            })
+            .collect();
+        (positions, indices)
+    }
+
+    #[test]
+    fn flat_meshes_simplify_and_reorder() {
+        let (positions, indices) = grid(16, 0.0);
+        let chain = generate_lods(&positions, &indices, &LodSettings::default());
+        assert_eq!(chain.lods.len(), 4);
+        assert_eq!(chain.lods[0].indices.len(), indices.len());
+        for (lod, ratio) in chain.lods[1..].iter().zip([0.5, 0.25, 0.125]) {
+            let triangles = lod.indices.len() / 3;
+            assert!(
+                triangles <= (512.0 * ratio) as usize,
+                "{} triangles",
+                triangles
+            );
+            assert!(lod.error < 1e-3);
+        }
+        // Every level indexes the shared, reordered vertices.
+        assert_eq!(chain.vertex_order.len(), positions.len());
+        let reordered = remap_vertices(&positions, &chain.vertex_order);
+        let area = |indices: &[u32]| -> f32 {
+            indices
+                .chunks_exact(3)
+                .map(|t| {
+                    let [a, b, c] = [0, 1, 2].map(|i| reordered[t[i] as usize]);
+                    ((b[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (b[1] - a[1])) * 0.5
+                })
+                .sum()
+        };
+        // Borders stay put and nothing flips, so every level covers the same area.
+        for lod in &chain.lods {
+            assert!((area(&lod.indices) - 256.0).abs() < 1e-3);
+        }
+        assert!(acmr(&chain.lods[0].indices, 16) < acmr(&indices, 16));
+
+        // A bump the error budget can't flatten stays.
+        let (positions, indices) = grid(8, 4.0);
+        let lod = simplify(&positions, &indices, 0, 0.01);
+        let apex = 4 * 9 + 4;
+        assert!(lod.indices.contains(&apex));
+        assert!(lod.error <= 0.01);
+    }
+}
@@ -0,0 +1,267 @@
+//! Process wide counters, gauges and histograms that subsystems publish into by name.
+//! `MetricsPlugin` logs a periodic summary, `write_prometheus`/`write_csv` export a snapshot.
+//! Names follow Prometheus conventions, e.g. `sim_tick_seconds`, `render_frames_total`.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use crate::engine::{Engine, Plugin};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metric {
+    // Only ever goes up.
+    Counter(u64),
+    // Last value set.
+    Gauge(f64),
+    Histogram(Histogram),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Histogram {
+    fn new(value: f64) -> Self {
+        Self {
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.sum / self.count as f64
+    }
+}
+
+pub(crate) type Registry = Mutex<BTreeMap<&'static str, Metric>>;
+
+static METRICS: Registry = Mutex::new(BTreeMap::new());
+// The runner's registry in a hot reloaded game library, see `hot_reload::Host`.
+static SHARED: OnceLock<&'static Registry> = OnceLock::new();
+
+pub(crate) fn registry() -> &'static Registry {
+    SHARED.get().copied().unwrap_or(&METRICS)
+}
+
+// Publishes into `registry` from now on. The names are copied into it, the library they come
+// from unloads before the runner is done with them.
+#[cfg(all(feature = "hot-reload", not(target_arch = "wasm32")))]
+pub(crate) fn share(registry: &'static Registry) {
+    let _ = SHARED.set(registry);
+}
+
+// Publishing under a name that already has a different kind replaces it, with a warning.
+fn update(name: &'static str, apply: impl FnOnce(Option<&mut Metric>) -> Option<Metric>) {
+    let mut metrics = registry().lock().unwrap();
+    if let Some(metric) = apply(metrics.get_mut(name)) {
+        let key = match metrics.get_key_value(name) {
+            Some((&key, _)) => key,
+            None if SHARED.get().is_some() => Box::leak(name.into()),
+            None => name,
+        };
+        if metrics.insert(key, metric).is_some() {
+            warn!("Metric {} changed kind", name);
+        }
+    }
+}
+
+pub fn increment(name: &'static str, by: u64) {
+    update(name, |metric| match metric {
+        Some(Metric::Counter(count)) => {
+            *count += by;
+            None
+        }
+        _ => Some(Metric::Counter(by)),
+    });
+}
+
+pub fn set_gauge(name: &'static str, value: f64) {
+    update(name, |metric| match metric {
+        Some(Metric::Gauge(gauge)) => {
+            *gauge = value;
+            None
+        }
+        _ => Some(Metric::Gauge(value)),
+    });
+}
+
+pub fn observe(name: &'static str, value: f64) {
+    update(name, |metric| match metric {
+        Some(Metric::Histogram(histogram)) => {
+            histogram.observe(value);
+            None
+        }
+        _ => Some(Metric::Histogram(Histogram::new(value))),
+    });
+}
+
+pub fn observe_duration(name: &'static str, duration: Duration) {
+    observe(name, duration.as_secs_f64());
+}
+
+pub fn get(name: &str) -> Option<Metric> {
+    registry().lock().unwrap().get(name).copied()
+}
+
+// Sorted by name.
+pub fn snapshot() -> Vec<(&'static str, Metric)> {
+    copy(&registry().lock().unwrap())
+}
+
+// None rather than waiting while another thread updates a metric, e.g. from a panic hook.
+pub(crate) fn try_snapshot() -> Option<Vec<(&'static str, Metric)>> {
+    registry().try_lock().ok().map(|metrics| copy(&metrics))
+}
+
+fn copy(metrics: &BTreeMap<&'static str, Metric>) -> Vec<(&'static str, Metric)> {
+    metrics
+        .iter()
+        .map(|(name, metric)| (*name, *metric))
+        .collect()
+}
+
+// Prometheus text exposition format, histograms are exported as summaries without quantiles.
+pub fn write_prometheus<W: Write>(out: &mut W) -> io::Result<()> {
+    for (name, metric) in snapshot() {
+        match metric {
+            Metric::Counter(count) => {
+                writeln!(out, "# TYPE {} counter", name)?;
+                writeln!(out, "{} {}", name, count)?;
+            }
+            Metric::Gauge(value) => {
+                writeln!(out, "# TYPE {} gauge", name)?;
+                writeln!(out, "{} {}", name, value)?;
+            }
+            Metric::Histogram(histogram) => {
+                writeln!(out, "# TYPE {} summary", name)?;
+                writeln!(out, "{}_sum {}", name, histogram.sum)?;
+                writeln!(out, "{}_count {}", name, histogram.count)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// One row per metric: name,kind,value,count,min,max. Value is the mean for histograms.
+pub fn write_csv<W: Write>(out: &mut W) -> io::Result<()> {
+    write_csv_of(out, &snapshot())
+}
+
+pub(crate) fn write_csv_of<W: Write>(
+    out: &mut W,
+    metrics: &[(&'static str, Metric)],
+) -> io::Result<()> {
+    writeln!(out, "name,kind,value,count,min,max")?;
+    for &(name, metric) in metrics {
+        match metric {
+            Metric::Counter(count) => writeln!(out, "{},counter,{},,,", name, count)?,
+            Metric::Gauge(value) => writeln!(out, "{},gauge,{},,,", name, value)?,
+            Metric::Histogram(histogram) => writeln!(
+                out,
+                "{},histogram,{},{},{},{}",
+                name,
+                histogram.mean(),
+                histogram.count,
+                histogram.min,
+                histogram.max
+            )?,
+        }
+    }
+    Ok(())
+}
+
+// Logs every metric under the "metrics" target at this interval.
+pub struct MetricsPlugin {
+    pub log_interval: Duration,
+}
+
+impl Default for MetricsPlugin {
+    fn default() -> Self {
+        Self {
+            log_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Plugin for MetricsPlugin {
+    fn build(&self, engine: &mut Engine) {
+        let interval = self.log_interval;
+        let mut last_log = Instant::now();
+        engine.add_system(move |_| {
+            if last_log.elapsed() < interval {
+                return;
+            }
+            last_log = Instant::now();
+            for (name, metric) in snapshot() {
+                match metric {
+                    Metric::Counter(count) => info!(target: "metrics", "{} = {}", name, count),
+                    Metric::Gauge(value) => info!(target: "metrics", "{} = {}", name, value),
+                    Metric::Histogram(histogram) => info!(
+                        target: "metrics",
+                        "{}: mean {:.6}, min {:.6}, max {:.6} over {}",
+                        name,
+                        histogram.mean(),
+                        histogram.min,
+                        histogram.max,
+                        histogram.count
+                    ),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publishes_and_exports() {
+        increment("test_loads_total", 2);
+        increment("test_loads_total", 3);
+        set_gauge("test_alive", 7.0);
+        observe("test_seconds", 1.0);
+        observe("test_seconds", 3.0);
+
+        assert_eq!(get("test_loads_total"), Some(Metric::Counter(5)));
+        let Some(Metric::Histogram(histogram)) = get("test_seconds") else {
+            panic!("test_seconds should be a histogram");
+        };
+        assert_eq!((histogram.count, histogram.mean()), (2, 2.0));
+        assert_eq!((histogram.min, histogram.max), (1.0, 3.0));
+
+        let mut text = Vec::new();
+        write_prometheus(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("# TYPE test_loads_total counter\ntest_loads_total 5\n"));
+        assert!(text.contains("test_seconds_count 2\n"));
+
+        let mut csv = Vec::new();
+        write_csv(&mut csv).unwrap();
+        assert!(String::from_utf8(csv)
+            .unwrap()
+            .contains("test_seconds,histogram,2,2,1,3\n"));
+    }
+}
@@ -0,0 +1,532 @@
+//! Content mods. Each folder under `mods/` with a `mod.cfg` manifest is a mod whose files are
+//! mounted into the `Vfs` above the game's own content, so a mod overrides a file by shipping
+//! one at the same path. The manifest uses the config file syntax:
+//!
+//! ```text
+//! id = hd_textures
+//! name = HD Textures
+//! version = 1.2.0
+//! dependencies = base_fixes >= 1.0, ui_tweaks
+//! load_order = 10
+//! ```
+//!
+//! Mods load after their dependencies, otherwise by `load_order` then id, and each overrides
+//! the ones loaded before it. A mod whose dependencies are missing, disabled, too old or
+//! circular is skipped with a warning. Every mod is enabled unless the config file turns it off
+//! under `[mods]`, as in `hd_textures = false`, and the `mod.enable` and `mod.disable` console
+//! commands toggle them for the session.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    config::Config,
+    cvars,
+    engine::{Engine, Plugin, Resources},
+    vfs::Vfs,
+};
+
+pub const MANIFEST: &str = "mod.cfg";
+
+// Mods mount from here up, the game's own content goes below.
+pub const MOD_PRIORITY: i32 = 100;
+
+const MOUNT_PREFIX: &str = "mod:";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    // Missing parts are zero, so `2` is 2.0.0.
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut parts = [0; 3];
+        for (index, part) in text.trim().split('.').enumerate() {
+            let slot = parts
+                .get_mut(index)
+                .ok_or_else(|| format!("{} isn't a version", text))?;
+            *slot = part
+                .parse()
+                .map_err(|_| format!("{} isn't a version", text))?;
+        }
+        let [major, minor, patch] = parts;
+        Ok(Version {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dependency {
+    pub id: String,
+    pub min_version: Option<Version>,
+}
+
+impl FromStr for Dependency {
+    type Err = String;
+
+    // `id` or `id >= version`.
+    fn from_str(text: &str) -> Result<Self, String> {
+        let (id, min_version) = match text.split_once(">=") {
+            Some((id, version)) => (id.trim(), Some(version.parse()?)),
+            None => (text.trim(), None),
+        };
+        if id.is_empty() {
+            return Err(format!("{} has no mod id", text));
+        }
+        Ok(Dependency {
+            id: id.to_owned(),
+            min_version,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModManifest {
+    pub id: String,
+    pub name: String,
+    pub version: Version,
+    pub dependencies: Vec<Dependency>,
+    // Lower loads first, so higher overrides.
+    pub load_order: i32,
+    // The folder mounted into the VFS.
+    pub dir: PathBuf,
+}
+
+impl ModManifest {
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let config = Config::parse(text)?;
+        let id = config.get("id").ok_or("missing id")?;
+        if id.is_empty() || id.contains(|c: char| c.is_whitespace() || c == ',') {
+            return Err(format!("{} isn't a valid mod id", id).into());
+        }
+        let dependencies = match config.get("dependencies") {
+            Some(list) => list
+                .split(',')
+                .filter(|dependency| !dependency.trim().is_empty())
+                .map(str::parse)
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        Ok(ModManifest {
+            id: id.to_owned(),
+            name: config.get("name").unwrap_or(id).to_owned(),
+            version: config.get("version").unwrap_or("0").parse()?,
+            dependencies,
+            load_order: match config.get("load_order") {
+                Some(order) => order
+                    .parse()
+                    .map_err(|_| format!("{} isn't a load order", order))?,
+                None => 0,
+            },
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    pub fn load(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = dir.join(MANIFEST);
+        let text = fs::read_to_string(&path)?;
+        Self::parse(&text, dir).map_err(|err| format!("{}: {}", path.display(), err).into())
+    }
+}
+
+// What `ModManager::resolve` decided.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LoadOrder {
+    // Ids in load order, later ones override earlier ones.
+    pub loaded: Vec<String>,
+    // Enabled mods that can't load, with why.
+    pub skipped: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+pub struct ModManager {
+    // By id.
+    mods: BTreeMap<String, ModManifest>,
+    disabled: BTreeSet<String>,
+    loaded: Vec<String>,
+}
+
+impl ModManager {
+    // Replaces the known mods with the ones in `dir`, logging folders that aren't valid mods.
+    // A missing directory has no mods. Returns how many were found.
+    pub fn discover(&mut self, dir: &Path) -> usize {
+        self.mods.clear();
+        let mut folders: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.join(MANIFEST).is_file())
+                .collect(),
+            Err(_) => return 0,
+        };
+        folders.sort();
+        for folder in folders {
+            match ModManifest::load(&folder) {
+                Ok(manifest) => match self.mods.get(&manifest.id) {
+                    Some(existing) => warn!(
+                        "Skipping mod {} in {}, already in {}",
+                        manifest.id,
+                        folder.display(),
+                        existing.dir.display()
+                    ),
+                    None => {
+                        self.mods.insert(manifest.id.clone(), manifest);
+                    }
+                },
+                Err(err) => warn!("Skipping mod: {}", err),
+            }
+        }
+        self.mods.len()
+    }
+
+    // Every known mod by id, loaded or not.
+    pub fn mods(&self) -> impl Iterator<Item = &ModManifest> {
+        self.mods.values()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&ModManifest> {
+        self.mods.get(id)
+    }
+
+    pub fn is_enabled(&self, id: &str) -> bool {
+        !self.disabled.contains(id)
+    }
+
+    // Takes effect on the next `mount`.
+    pub fn set_enabled(&mut self, id: &str, enabled: bool) {
+        if enabled {
+            self.disabled.remove(id);
+        } else {
+            self.disabled.insert(id.to_owned());
+        }
+    }
+
+    // Reads `mods.<id> = true|false` keys, from a `[mods]` section.
+    pub fn apply_config(&mut self, config: &Config) {
+        for (key, value) in config.iter() {
+            let Some(id) = key.strip_prefix("mods.") else {
+                continue;
+            };
+            match value.parse() {
+                Ok(enabled) => self.set_enabled(id, enabled),
+                Err(_) => warn!("Config key {} should be true or false, not {}", key, value),
+            }
+        }
+    }
+
+    // Ids mounted by the last `mount`, in load order.
+    pub fn loaded(&self) -> &[String] {
+        &self.loaded
+    }
+
+    pub fn resolve(&self) -> LoadOrder {
+        let mut candidates: BTreeMap<&str, &ModManifest> = self
+            .mods
+            .values()
+            .filter(|manifest| self.is_enabled(&manifest.id))
+            .map(|manifest| (manifest.id.as_str(), manifest))
+            .collect();
+        let mut order = LoadOrder::default();
+
+        // Drop mods with unmet dependencies until the rest all have theirs.
+        loop {
+            let unmet = candidates.values().find_map(|manifest| {
+                manifest.dependencies.iter().find_map(|dependency| {
+                    let reason = self.unmet(dependency, &candidates)?;
+                    Some((manifest.id.clone(), reason))
+                })
+            });
+            let Some((id, reason)) = unmet else {
+                break;
+            };
+            candidates.remove(id.as_str());
+            order.skipped.push((id, reason));
+        }
+
+        // Dependencies first, then by load order and id.
+        while !candidates.is_empty() {
+            let ready = candidates
+                .values()
+                .filter(|manifest| {
+                    manifest
+                        .dependencies
+                        .iter()
+                        .all(|dependency| order.loaded.contains(&dependency.id))
+                })
+                .min_by_key(|manifest| (manifest.load_order, &manifest.id));
+            let Some(ready) = ready else {
+                for id in candidates.keys() {
+                    order
+                        .skipped
+                        .push((id.to_string(), "dependency cycle".to_owned()));
+                }
+                break;
+            };
+            order.loaded.push(ready.id.clone());
+            candidates.remove(ready.id.as_str());
+        }
+        order
+    }
+
+    fn unmet(
+        &self,
+        dependency: &Dependency,
+        candidates: &BTreeMap<&str, &ModManifest>,
+    ) -> Option<String> {
+        let id = &dependency.id;
+        let Some(manifest) = self.mods.get(id) else {
+            return Some(format!("needs {}, which isn't installed", id));
+        };
+        if !self.is_enabled(id) {
+            return Some(format!("needs {}, which is disabled", id));
+        }
+        if let Some(min) = dependency.min_version.filter(|min| manifest.version < *min) {
+            return Some(format!(
+                "needs {} {} or newer, {} is installed",
+                id, min, manifest.version
+            ));
+        }
+        if !candidates.contains_key(id.as_str()) {
+            return Some(format!("needs {}, which was skipped", id));
+        }
+        None
+    }
+
+    // Remounts the loadable mods into `vfs` above MOD_PRIORITY, replacing earlier mod mounts.
+    pub fn mount(&mut self, vfs: &mut Vfs) -> LoadOrder {
+        let stale: Vec<String> = vfs
+            .mounts()
+            .iter()
+            .filter(|mount| mount.name.starts_with(MOUNT_PREFIX))
+            .map(|mount| mount.name.clone())
+            .collect();
+        for name in stale {
+            vfs.unmount(&name);
+        }
+        let order = self.resolve();
+        for (index, id) in order.loaded.iter().enumerate() {
+            let manifest = &self.mods[id];
+            vfs.mount(
+                &format!("{}{}", MOUNT_PREFIX, id),
+                &manifest.dir,
+                MOD_PRIORITY + index as i32,
+            );
+            info!("Mounted mod {} {}", id, manifest.version);
+        }
+        for (id, reason) in &order.skipped {
+            warn!("Skipping mod {}: {}", id, reason);
+        }
+        self.loaded = order.loaded.clone();
+        order
+    }
+}
+
+// Discovers the mods in `dir`, applies the config file and mounts them into the `Vfs`
+// resource, created if the game didn't insert one with its own content mounted.
+pub struct ModPlugin {
+    pub dir: PathBuf,
+}
+
+impl ModPlugin {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Default for ModPlugin {
+    fn default() -> Self {
+        Self::new("mods")
+    }
+}
+
+impl Plugin for ModPlugin {
+    fn build(&self, engine: &mut Engine) {
+        let mut manager = ModManager::default();
+        match Config::load_default() {
+            Ok(config) => manager.apply_config(&config),
+            Err(err) => warn!("Failed to read the config file: {}", err),
+        }
+        let found = manager.discover(&self.dir);
+        info!("Found {} mods in {}", found, self.dir.display());
+        let resources = engine.resources_mut();
+        if !resources.contains::<Vfs>() {
+            resources.insert(Vfs::default());
+        }
+        manager.mount(resources.get_mut::<Vfs>().unwrap());
+        engine.insert_resource(manager);
+
+        let cvars = cvars::cvars(engine);
+        cvars.register_command("mods", "lists the installed mods", |resources, _| {
+            let manager = resources.get::<ModManager>().ok_or("no mod manager")?;
+            for manifest in manager.mods() {
+                let state = if manager.loaded().contains(&manifest.id) {
+                    "loaded"
+                } else if manager.is_enabled(&manifest.id) {
+                    "skipped"
+                } else {
+                    "disabled"
+                };
+                info!(target: "console", "{} {} ({}), {}", manifest.id, manifest.version, manifest.name, state);
+            }
+            Ok(())
+        });
+        for (name, enabled) in [("mod.enable", true), ("mod.disable", false)] {
+            cvars.register_command(
+                name,
+                if enabled {
+                    "mod.enable <id>, loads a mod for this session"
+                } else {
+                    "mod.disable <id>, unloads a mod for this session"
+                },
+                move |resources, args| {
+                    let [id] = args else {
+                        return Err(format!("usage: {} <id>", name));
+                    };
+                    set_mod_enabled(resources, id, enabled)
+                },
+            );
+        }
+        cvars.register_command(
+            "vfs.which",
+            "vfs.which <path>, shows which mount provides a file",
+            |resources, args| {
+                let [path] = args else {
+                    return Err("usage: vfs.which <path>".to_owned());
+                };
+                let vfs = resources.get::<Vfs>().ok_or("no vfs")?;
+                let mount = vfs
+                    .source(path)
+                    .ok_or_else(|| format!("{} isn't in any mount", path))?;
+                info!(target: "console", "{} from {} ({})", path, mount.name, mount.root.display());
+                Ok(())
+            },
+        );
+    }
+}
+
+fn set_mod_enabled(resources: &mut Resources, id: &str, enabled: bool) -> Result<(), String> {
+    let mut vfs = resources.remove::<Vfs>().unwrap_or_default();
+    let result = match resources.get_mut::<ModManager>() {
+        Some(manager) if manager.get(id).is_some() => {
+            manager.set_enabled(id, enabled);
+            manager.mount(&mut vfs);
+            Ok(())
+        }
+        Some(_) => Err(format!("no mod {}", id)),
+        None => Err("no mod manager".to_owned()),
+    };
+    resources.insert(vfs);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, text: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+
+    #[test]
+    fn orders_mounts_and_overrides_mods() {
+        let dir = std::env::temp_dir().join(format!("midnight2-mods-{}", std::process::id()));
+        let game = dir.join("game");
+        let mods = dir.join("mods");
+        write(&game.join("textures/stone.png"), "game stone");
+        write(&game.join("textures/grass.png"), "game grass");
+        write(
+            &mods.join("a_hd/mod.cfg"),
+            "id = hd\nversion = 1.2\ndependencies = base >= 1.0\n",
+        );
+        write(&mods.join("a_hd/textures/stone.png"), "hd stone");
+        write(
+            &mods.join("base/mod.cfg"),
+            "id = base\nversion = 1.0.3\nload_order = 5\n",
+        );
+        write(&mods.join("base/textures/stone.png"), "base stone");
+        write(&mods.join("base/textures/moss.png"), "base moss");
+        write(
+            &mods.join("old/mod.cfg"),
+            "id = old\ndependencies = base >= 2\n",
+        );
+        write(
+            &mods.join("ring/mod.cfg"),
+            "id = ring\ndependencies = ring\n",
+        );
+        write(&mods.join("loose/readme.txt"), "not a mod");
+
+        let mut vfs = Vfs::default();
+        vfs.mount("game", &game, 0);
+        let mut manager = ModManager::default();
+        assert_eq!(manager.discover(&mods), 4);
+        let order = manager.mount(&mut vfs);
+        assert_eq!(order.loaded, ["base", "hd"]);
+        assert_eq!(
+            order.skipped,
+            [
+                (
+                    "old".to_owned(),
+                    "needs base 2.0.0 or newer, 1.0.3 is installed".to_owned()
+                ),
+                ("ring".to_owned(), "dependency cycle".to_owned()),
+            ]
+        );
+        assert_eq!(
+            vfs.read_to_string("textures/stone.png").unwrap(),
+            "hd stone"
+        );
+        assert_eq!(vfs.source("textures/moss.png").unwrap().name, "mod:base");
+        assert_eq!(
+            vfs.list("textures"),
+            [
+                "textures/grass.png",
+                "textures/moss.png",
+                "textures/stone.png"
+            ]
+        );
+        assert!(vfs.read("../secrets.txt").is_err());
+        #[cfg(unix)]
+        {
+            write(&dir.join("secrets.txt"), "secret");
+            let link = mods.join("base/textures/link.png");
+            std::os::unix::fs::symlink(dir.join("secrets.txt"), &link).unwrap();
+            assert!(vfs.read("textures/link.png").is_err());
+            assert!(!vfs
+                .list("textures")
+                .contains(&"textures/link.png".to_owned()));
+            fs::remove_file(&link).unwrap();
+        }
+
+        // Turning off a dependency takes its dependents with it.
+        manager.apply_config(&Config::parse("[mods]\nbase = false\n").unwrap());
+        let order = manager.mount(&mut vfs);
+        assert!(order.loaded.is_empty());
+        assert_eq!(
+            vfs.read_to_string("textures/stone.png").unwrap(),
+            "game stone"
+        );
+        assert_eq!(vfs.mounts().len(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
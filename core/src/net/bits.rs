@@ -0,0 +1,364 @@
+//! Bit level serialization for replicated state and messages. Values take only the bits they
+//! need: integers as varints, floats quantized to a range and precision, and transforms as deltas
+//! against a baseline both ends already have.
+
+// Writes values least significant bit first into a growing byte buffer.
+#[derive(Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    bit_len: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bit_len(&self) -> usize {
+        self.bit_len
+    }
+
+    // Pads to a whole byte, unused bits are zero.
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    // Writes the low `bits` bits of `value`, up to 64.
+    pub fn write_bits(&mut self, value: u64, bits: u32) {
+        debug_assert!(bits <= 64);
+        let mut remaining = bits;
+        let mut value = if bits < 64 {
+            value & ((1 << bits) - 1)
+        } else {
+            value
+        };
+        while remaining > 0 {
+            let offset = (self.bit_len % 8) as u32;
+            if offset == 0 {
+                self.bytes.push(0);
+            }
+            let taken = remaining.min(8 - offset);
+            let last = self.bytes.last_mut().unwrap();
+            *last |= ((value & ((1 << taken) - 1)) as u8) << offset;
+            value >>= taken;
+            remaining -= taken;
+            self.bit_len += taken as usize;
+        }
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_bits(value as u64, 1);
+    }
+
+    // 7 bits at a time with a continuation bit, small values take a byte.
+    pub fn write_varint(&mut self, mut value: u64) {
+        loop {
+            let more = value >= 0x80;
+            self.write_bits(value & 0x7f, 7);
+            self.write_bool(more);
+            value >>= 7;
+            if !more {
+                return;
+            }
+        }
+    }
+
+    // Zigzag encoded, so small negative values are small too.
+    pub fn write_signed_varint(&mut self, value: i64) {
+        self.write_varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    // Length prefixed.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_varint(bytes.len() as u64);
+        for &byte in bytes {
+            self.write_bits(byte as u64, 8);
+        }
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.write_bits(value.to_bits() as u64, 32);
+    }
+
+    pub fn write_quantized(&mut self, value: f32, quantization: Quantization) {
+        self.write_bits(quantization.quantize(value), quantization.bits);
+    }
+
+    // Smallest three: the largest component is dropped and rebuilt from the unit length, the
+    // others are in -1/sqrt(2)..1/sqrt(2). `bits` per component, plus 2 for the dropped index.
+    pub fn write_quaternion(&mut self, quaternion: [f32; 4], bits: u32) {
+        let largest = (0..4)
+            .max_by(|&a, &b| quaternion[a].abs().total_cmp(&quaternion[b].abs()))
+            .unwrap();
+        // q and -q are the same rotation, flip so the dropped component is positive.
+        let sign = if quaternion[largest] < 0.0 { -1.0 } else { 1.0 };
+        let quantization = Quantization::new(-SMALLEST_THREE_RANGE, SMALLEST_THREE_RANGE, bits);
+        self.write_bits(largest as u64, 2);
+        for (index, component) in quaternion.iter().enumerate() {
+            if index != largest {
+                self.write_quantized(component * sign, quantization);
+            }
+        }
+    }
+
+    // Each component as a varint number of `precision` steps away from the baseline, one bit if
+    // nothing moved.
+    pub fn write_vec3_delta(&mut self, value: [f32; 3], baseline: [f32; 3], precision: f32) {
+        let deltas: [i64; 3] = std::array::from_fn(|i| {
+            to_steps(value[i], precision) - to_steps(baseline[i], precision)
+        });
+        self.write_bool(deltas != [0; 3]);
+        if deltas != [0; 3] {
+            for delta in deltas {
+                self.write_signed_varint(delta);
+            }
+        }
+    }
+
+    // Only what changed from the baseline, the rotation only when it changed at all.
+    pub fn write_transform_delta(&mut self, value: &NetTransform, baseline: &NetTransform) {
+        self.write_vec3_delta(
+            value.translation,
+            baseline.translation,
+            TRANSLATION_PRECISION,
+        );
+        let rotated = value.rotation != baseline.rotation;
+        self.write_bool(rotated);
+        if rotated {
+            self.write_quaternion(value.rotation, ROTATION_BITS);
+        }
+    }
+}
+
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+// Every read returns None past the end of the data.
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    pub fn bits_left(&self) -> usize {
+        self.bytes.len() * 8 - self.position
+    }
+
+    pub fn read_bits(&mut self, bits: u32) -> Option<u64> {
+        debug_assert!(bits <= 64);
+        if bits as usize > self.bits_left() {
+            return None;
+        }
+        let mut value = 0u64;
+        let mut read = 0;
+        while read < bits {
+            let offset = (self.position % 8) as u32;
+            let taken = (bits - read).min(8 - offset);
+            let byte = self.bytes[self.position / 8] >> offset;
+            value |= ((byte & ((1u16 << taken) - 1) as u8) as u64) << read;
+            read += taken;
+            self.position += taken as usize;
+        }
+        Some(value)
+    }
+
+    pub fn read_bool(&mut self) -> Option<bool> {
+        self.read_bits(1).map(|bit| bit == 1)
+    }
+
+    pub fn read_varint(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            value |= self.read_bits(7)? << shift;
+            if !self.read_bool()? {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    pub fn read_signed_varint(&mut self) -> Option<i64> {
+        let value = self.read_varint()?;
+        Some((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    pub fn read_bytes(&mut self) -> Option<Vec<u8>> {
+        // The length comes from the peer, compare in bytes so it can't overflow.
+        let len = self.read_varint()?;
+        if len > (self.bits_left() / 8) as u64 {
+            return None;
+        }
+        (0..len)
+            .map(|_| self.read_bits(8).map(|byte| byte as u8))
+            .collect()
+    }
+
+    pub fn read_f32(&mut self) -> Option<f32> {
+        Some(f32::from_bits(self.read_bits(32)? as u32))
+    }
+
+    pub fn read_quantized(&mut self, quantization: Quantization) -> Option<f32> {
+        Some(quantization.dequantize(self.read_bits(quantization.bits)?))
+    }
+
+    pub fn read_quaternion(&mut self, bits: u32) -> Option<[f32; 4]> {
+        let largest = self.read_bits(2)? as usize;
+        let quantization = Quantization::new(-SMALLEST_THREE_RANGE, SMALLEST_THREE_RANGE, bits);
+        let mut quaternion = [0.0; 4];
+        for (index, component) in quaternion.iter_mut().enumerate() {
+            if index != largest {
+                *component = self.read_quantized(quantization)?;
+            }
+        }
+        let sum: f32 = quaternion.iter().map(|c| c * c).sum();
+        quaternion[largest] = (1.0 - sum).max(0.0).sqrt();
+        Some(quaternion)
+    }
+
+    pub fn read_vec3_delta(&mut self, baseline: [f32; 3], precision: f32) -> Option<[f32; 3]> {
+        if !self.read_bool()? {
+            return Some(baseline);
+        }
+        let mut value = [0.0; 3];
+        for (i, component) in value.iter_mut().enumerate() {
+            let steps = to_steps(baseline[i], precision).checked_add(self.read_signed_varint()?)?;
+            *component = steps as f32 * precision;
+        }
+        Some(value)
+    }
+
+    pub fn read_transform_delta(&mut self, baseline: &NetTransform) -> Option<NetTransform> {
+        let translation = self.read_vec3_delta(baseline.translation, TRANSLATION_PRECISION)?;
+        let rotation = match self.read_bool()? {
+            true => self.read_quaternion(ROTATION_BITS)?,
+            false => baseline.rotation,
+        };
+        Some(NetTransform {
+            translation,
+            rotation,
+        })
+    }
+}
+
+// Maps min..=max onto `bits` bits, values outside are clamped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quantization {
+    pub min: f32,
+    pub max: f32,
+    pub bits: u32,
+}
+
+impl Quantization {
+    pub const fn new(min: f32, max: f32, bits: u32) -> Self {
+        Self { min, max, bits }
+    }
+
+    // Largest error after a round trip.
+    pub fn precision(&self) -> f32 {
+        (self.max - self.min) / self.steps() as f32 / 2.0
+    }
+
+    fn steps(&self) -> u64 {
+        (1u64 << self.bits) - 1
+    }
+
+    fn quantize(&self, value: f32) -> u64 {
+        let normalized = ((value - self.min) / (self.max - self.min)).clamp(0.0, 1.0);
+        (normalized * self.steps() as f32).round() as u64
+    }
+
+    fn dequantize(&self, quantized: u64) -> f32 {
+        self.min
+            + (self.max - self.min) * (quantized.min(self.steps()) as f32 / self.steps() as f32)
+    }
+}
+
+// Millimeter precision for delta encoded translations.
+pub const TRANSLATION_PRECISION: f32 = 0.001;
+// Bits per smallest three quaternion component, about 0.0002 precision.
+pub const ROTATION_BITS: u32 = 12;
+const SMALLEST_THREE_RANGE: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+// Translation and rotation (x, y, z, w) of a networked entity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NetTransform {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+impl Default for NetTransform {
+    fn default() -> Self {
+        Self {
+            translation: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+fn to_steps(value: f32, precision: f32) -> i64 {
+    (value / precision).round() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b101, 3);
+        writer.write_varint(300);
+        writer.write_signed_varint(-2);
+        writer.write_bits(u64::MAX, 64);
+        let range = Quantization::new(-10.0, 10.0, 10);
+        writer.write_quantized(3.3, range);
+        writer.write_bytes(b"hi");
+        let baseline = NetTransform::default();
+        let moved = NetTransform {
+            translation: [1.5, 0.0, -2.25],
+            rotation: [0.0, 0.70710677, 0.0, 0.70710677],
+        };
+        writer.write_transform_delta(&baseline, &baseline);
+        writer.write_transform_delta(&moved, &baseline);
+        let bytes = writer.finish();
+
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(reader.read_bits(3), Some(0b101));
+        assert_eq!(reader.read_varint(), Some(300));
+        assert_eq!(reader.read_signed_varint(), Some(-2));
+        assert_eq!(reader.read_bits(64), Some(u64::MAX));
+        assert!((reader.read_quantized(range).unwrap() - 3.3).abs() <= range.precision());
+        assert_eq!(reader.read_bytes().as_deref(), Some(&b"hi"[..]));
+        assert_eq!(reader.read_transform_delta(&baseline), Some(baseline));
+        let decoded = reader.read_transform_delta(&baseline).unwrap();
+        let decoded = decoded.translation.iter().chain(&decoded.rotation);
+        let expected = moved.translation.iter().chain(&moved.rotation);
+        for (a, b) in decoded.zip(expected) {
+            assert!((a - b).abs() < 0.001);
+        }
+        assert!(reader.bits_left() < 8);
+    }
+
+    #[test]
+    fn rejects_oversized_byte_length() {
+        let mut writer = BitWriter::new();
+        writer.write_varint(u64::MAX);
+        writer.write_bits(0, 16);
+        let bytes = writer.finish();
+        assert_eq!(BitReader::new(&bytes).read_bytes(), None);
+    }
+
+    #[test]
+    fn rejects_overflowing_delta() {
+        let mut writer = BitWriter::new();
+        writer.write_bool(true);
+        writer.write_signed_varint(i64::MAX);
+        let bytes = writer.finish();
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(
+            reader.read_vec3_delta([1.0; 3], TRANSLATION_PRECISION),
+            None
+        );
+    }
+}
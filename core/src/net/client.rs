@@ -0,0 +1,248 @@
+use std::{collections::VecDeque, fmt, net::SocketAddr};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use super::{
+    clock::{ClockSync, ServerTime},
+    replication::ReplicationClient,
+    rpc::{Direction, NetMessage, RpcInbox, RpcRegistry},
+    Channel, ConnectionId, ConnectionStats, DisconnectReason, NetConditions, NetConfig, NetError,
+    NetEvent, Transport,
+};
+use super::{conditions, stats};
+use crate::{
+    cvars::{self, Cvars},
+    engine::{Engine, Plugin, Resources},
+    sim,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServerAddress {
+    Udp(SocketAddr),
+    // A `ws://` url, for servers that accept WebSocket clients. Browsers can only use these.
+    WebSocket(String),
+}
+
+impl From<SocketAddr> for ServerAddress {
+    fn from(addr: SocketAddr) -> Self {
+        ServerAddress::Udp(addr)
+    }
+}
+
+impl fmt::Display for ServerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerAddress::Udp(addr) => write!(f, "{}", addr),
+            ServerAddress::WebSocket(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientState {
+    Connecting,
+    Connected(ConnectionId),
+    // Final, connecting again needs a new `NetClient`.
+    Disconnected(DisconnectReason),
+}
+
+// A connection to a server, with the replicated world it sends. Inserted as a resource by
+// `NetClientPlugin`. Registered events are decoded into typed queues, other messages come out of
+// `poll_event`.
+pub struct NetClient {
+    transport: Transport,
+    state: ClientState,
+    replication: ReplicationClient,
+    rpc: RpcRegistry,
+    inbox: RpcInbox,
+    clock: ClockSync,
+    events: VecDeque<NetEvent>,
+}
+
+impl NetClient {
+    // The server denies the connection unless it registered the same messages in `rpc`.
+    pub fn connect(
+        server: &ServerAddress,
+        mut config: NetConfig,
+        rpc: RpcRegistry,
+    ) -> Result<Self, NetError> {
+        config.protocol_version = rpc.fingerprint();
+        let transport = match server {
+            ServerAddress::Udp(addr) => Transport::client(*addr, config)?,
+            ServerAddress::WebSocket(url) => Transport::client_websocket(url, config)?,
+        };
+        Ok(Self {
+            transport,
+            state: ClientState::Connecting,
+            replication: ReplicationClient::new(),
+            rpc,
+            inbox: RpcInbox::default(),
+            clock: ClockSync::new(Instant::now()),
+            events: VecDeque::new(),
+        })
+    }
+
+    pub fn state(&self) -> ClientState {
+        self.state
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, ClientState::Connected(_))
+    }
+
+    pub fn replication(&mut self) -> &mut ReplicationClient {
+        &mut self.replication
+    }
+
+    pub fn send(&mut self, channel: Channel, data: &[u8]) -> Result<(), NetError> {
+        match self.state {
+            ClientState::Connected(server) => self.transport.send(server, channel, data),
+            _ => Err(NetError::NotConnected),
+        }
+    }
+
+    pub fn send_message<M: NetMessage>(&mut self, message: &M) -> Result<(), NetError> {
+        let data = self.rpc.encode(message);
+        self.send(M::CHANNEL, &data)
+    }
+
+    // Events of type `M` received since the last call.
+    pub fn receive<M: NetMessage>(&mut self) -> Vec<M> {
+        self.inbox
+            .drain()
+            .into_iter()
+            .map(|(_, message)| message)
+            .collect()
+    }
+
+    // Estimated time on the server, see `ServerTime::is_synchronized`.
+    pub fn server_time(&mut self, now: Instant) -> ServerTime {
+        self.clock.server_time(now)
+    }
+
+    // Connection to the server, None until connected.
+    pub fn stats(&self) -> Option<ConnectionStats> {
+        match self.state {
+            ClientState::Connected(server) => self.transport.stats(server),
+            _ => None,
+        }
+    }
+
+    pub fn set_conditions(&mut self, conditions: NetConditions) {
+        if self.transport.conditions() != conditions {
+            info!("Simulating network conditions {:?}", conditions);
+            self.transport.set_conditions(conditions);
+        }
+    }
+
+    pub fn disconnect(&mut self) {
+        if let ClientState::Connected(server) = self.state {
+            self.transport.disconnect(server);
+        }
+        self.state = ClientState::Disconnected(DisconnectReason::Local);
+    }
+
+    // Connects, disconnects and messages that aren't replication data, for the game's
+    // fixed_update.
+    pub fn poll_event(&mut self) -> Option<NetEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn update(&mut self, now: Instant) {
+        if let ClientState::Disconnected(_) = self.state {
+            return;
+        }
+        // Queued before the update so the ping leaves right away, a late send skews the estimate.
+        if let ClientState::Connected(server) = self.state {
+            if let Some(ping) = self.clock.ping(now) {
+                if let Err(err) = self.transport.send(server, Channel::Unreliable, &ping) {
+                    warn!("Failed to ping the server: {}", err);
+                }
+            }
+        }
+        if let Err(err) = self.transport.update(now) {
+            warn!("Network update failed: {}", err);
+        }
+        while let Some(event) = self.transport.poll_event() {
+            match &event {
+                NetEvent::Connected(id) => {
+                    info!("Connected to the server as {}", id);
+                    self.state = ClientState::Connected(*id);
+                }
+                NetEvent::Disconnected(_, reason) => {
+                    info!("Disconnected from the server: {:?}", reason);
+                    self.state = ClientState::Disconnected(*reason);
+                }
+                NetEvent::Message { data, .. } if self.replication.receive(data) => continue,
+                NetEvent::Message { data, .. } if self.clock.receive(data, now) => continue,
+                NetEvent::Message {
+                    connection, data, ..
+                } if self.rpc.receive(
+                    *connection,
+                    data,
+                    Direction::ServerToClient,
+                    &mut self.inbox,
+                ) =>
+                {
+                    continue
+                }
+                NetEvent::Message { .. } => {}
+            }
+            self.events.push_back(event);
+        }
+        stats::publish_metrics(self.stats().into_iter());
+    }
+}
+
+pub struct NetClientPlugin {
+    pub server: ServerAddress,
+    pub config: NetConfig,
+    pub rpc: RpcRegistry,
+}
+
+impl NetClientPlugin {
+    pub fn new(server: impl Into<ServerAddress>) -> Self {
+        Self {
+            server: server.into(),
+            config: NetConfig::default(),
+            rpc: RpcRegistry::default(),
+        }
+    }
+
+    pub fn with_messages(mut self, rpc: RpcRegistry) -> Self {
+        self.rpc = rpc;
+        self
+    }
+}
+
+impl Plugin for NetClientPlugin {
+    fn build(&self, engine: &mut Engine) {
+        match NetClient::connect(&self.server, self.config.clone(), self.rpc.clone()) {
+            Ok(client) => {
+                info!("Connecting to {}", self.server);
+                engine.insert_resource(client);
+            }
+            Err(err) => {
+                error!("Failed to connect to {}: {}", self.server, err);
+                sim::shutdown();
+                return;
+            }
+        }
+        conditions::register_cvars(cvars::cvars(engine));
+        engine.insert_resource(ServerTime::default());
+        engine.add_system(|resources: &mut Resources| {
+            let conditions = resources.get::<Cvars>().map(conditions::from_cvars);
+            let Some(client) = resources.get_mut::<NetClient>() else {
+                return;
+            };
+            let now = Instant::now();
+            client.set_conditions(conditions.unwrap_or_default());
+            client.update(now);
+            let time = client.server_time(now);
+            resources.insert(time);
+        });
+    }
+}
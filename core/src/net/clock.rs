@@ -0,0 +1,279 @@
+//! Clock synchronization. Clients ping the server and estimate the offset between their clock and
+//! the server's from the replies, assuming the reply took half the round trip. Samples with the
+//! lowest round trips were delayed least by queuing, so the estimate averages those, and the
+//! client's clock is slewed towards it instead of jumping, so server time never runs backwards.
+//!
+//! `NetServerPlugin` and `NetClientPlugin` keep a `ServerTime` resource up to date on both ends,
+//! which interpolation, gameplay timers and replays can use as one time base across machines.
+
+use std::{collections::VecDeque, time::Duration};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use super::{
+    bits::{BitReader, BitWriter},
+    Channel, ConnectionId, NetError, Transport,
+};
+use crate::sim::FIXED_TIMESTEP;
+
+// First byte of every transport message carrying a ping, see `REPLICATION_MESSAGE`.
+pub const CLOCK_MESSAGE: u8 = 0xf3;
+
+const PING: u64 = 0;
+const PONG: u64 = 1;
+
+const SAMPLES: usize = 16;
+// Averaged out of `SAMPLES`, the ones with the lowest round trip.
+const BEST_SAMPLES: usize = 6;
+// Samples before the clock counts as synchronized, pinged for at the faster interval.
+const SYNC_SAMPLES: usize = 4;
+const SYNC_PING_INTERVAL: Duration = Duration::from_millis(100);
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+// The clock runs up to this much faster or slower while catching up with the estimate, and
+// jumps when it is further off than `SNAP_THRESHOLD`.
+const MAX_SLEW: f64 = 0.1;
+const SNAP_THRESHOLD: f64 = 0.25;
+
+// Time since the server started, the same on every machine in a session. Kept up to date as a
+// resource by the net plugins.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ServerTime {
+    elapsed: Duration,
+    synchronized: bool,
+}
+
+impl ServerTime {
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    // The server tick this time falls in, see `interpolation::tick_time`.
+    pub fn tick(&self) -> u64 {
+        (self.elapsed.as_nanos() / FIXED_TIMESTEP.as_nanos()) as u64
+    }
+
+    // False on clients until enough pings were answered, the time is only an estimate until then.
+    pub fn is_synchronized(&self) -> bool {
+        self.synchronized
+    }
+}
+
+// The server's side, answers pings with its time.
+pub struct ServerClock {
+    epoch: Instant,
+}
+
+impl ServerClock {
+    pub fn new(now: Instant) -> Self {
+        Self { epoch: now }
+    }
+
+    pub fn now(&self, now: Instant) -> ServerTime {
+        ServerTime {
+            elapsed: now.saturating_duration_since(self.epoch),
+            synchronized: true,
+        }
+    }
+
+    // Returns false if `data` wasn't a clock message, so callers can try other handlers.
+    pub fn receive(
+        &self,
+        transport: &mut Transport,
+        from: ConnectionId,
+        data: &[u8],
+        now: Instant,
+    ) -> bool {
+        let Some((&CLOCK_MESSAGE, bytes)) = data.split_first() else {
+            return false;
+        };
+        let mut reader = BitReader::new(bytes);
+        let (Some(PING), Some(client_time)) = (reader.read_varint(), reader.read_varint()) else {
+            warn!("Dropped malformed clock message from {}", from);
+            return true;
+        };
+        let mut writer = BitWriter::new();
+        writer.write_varint(PONG);
+        writer.write_varint(client_time);
+        writer.write_varint(self.now(now).elapsed.as_micros() as u64);
+        let mut pong = vec![CLOCK_MESSAGE];
+        pong.extend(writer.finish());
+        match transport.send(from, Channel::Unreliable, &pong) {
+            Ok(()) | Err(NetError::NotConnected) => {}
+            Err(err) => warn!("Failed to answer ping from {}: {}", from, err),
+        }
+        true
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Sample {
+    rtt: f64,
+    // Server minus local time, in seconds.
+    offset: f64,
+}
+
+// The client's side, estimates the server's clock.
+pub struct ClockSync {
+    epoch: Instant,
+    samples: VecDeque<Sample>,
+    samples_received: usize,
+    target: Option<f64>,
+    offset: f64,
+    last_ping: Option<Instant>,
+    last_update: Option<Instant>,
+    last_time: Duration,
+}
+
+impl ClockSync {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            epoch: now,
+            samples: VecDeque::with_capacity(SAMPLES),
+            samples_received: 0,
+            target: None,
+            offset: 0.0,
+            last_ping: None,
+            last_update: None,
+            last_time: Duration::ZERO,
+        }
+    }
+
+    // A ping to send to the server on an unreliable channel when one is due.
+    pub fn ping(&mut self, now: Instant) -> Option<Vec<u8>> {
+        let interval = if self.samples_received < SYNC_SAMPLES {
+            SYNC_PING_INTERVAL
+        } else {
+            PING_INTERVAL
+        };
+        if self
+            .last_ping
+            .is_some_and(|last| now.saturating_duration_since(last) < interval)
+        {
+            return None;
+        }
+        self.last_ping = Some(now);
+        let mut writer = BitWriter::new();
+        writer.write_varint(PING);
+        writer.write_varint(self.local(now).as_micros() as u64);
+        let mut ping = vec![CLOCK_MESSAGE];
+        ping.extend(writer.finish());
+        Some(ping)
+    }
+
+    // Takes the server's answer. Returns false if `data` wasn't a clock message.
+    pub fn receive(&mut self, data: &[u8], now: Instant) -> bool {
+        let Some((&CLOCK_MESSAGE, bytes)) = data.split_first() else {
+            return false;
+        };
+        let mut reader = BitReader::new(bytes);
+        let (Some(PONG), Some(sent), Some(server)) = (
+            reader.read_varint(),
+            reader.read_varint(),
+            reader.read_varint(),
+        ) else {
+            warn!("Dropped malformed clock message");
+            return true;
+        };
+        let local = self.local(now).as_secs_f64();
+        let sent = Duration::from_micros(sent).as_secs_f64();
+        if sent > local {
+            return true;
+        }
+        let rtt = local - sent;
+        let server = Duration::from_micros(server).as_secs_f64();
+        if self.samples.len() == SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            rtt,
+            offset: server + rtt / 2.0 - local,
+        });
+        self.samples_received += 1;
+
+        let mut best: Vec<_> = self.samples.iter().copied().collect();
+        best.sort_by(|a, b| a.rtt.total_cmp(&b.rtt));
+        best.truncate(BEST_SAMPLES);
+        let target = best.iter().map(|sample| sample.offset).sum::<f64>() / best.len() as f64;
+        if self.target.is_none() {
+            self.offset = target;
+        }
+        self.target = Some(target);
+        true
+    }
+
+    // Round trip of the best sample, None before the first reply.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.samples
+            .iter()
+            .map(|sample| sample.rtt)
+            .min_by(f64::total_cmp)
+            .map(Duration::from_secs_f64)
+    }
+
+    // Estimated server time, never earlier than the last one returned.
+    pub fn server_time(&mut self, now: Instant) -> ServerTime {
+        let elapsed = self.last_update.map_or(0.0, |last| {
+            now.saturating_duration_since(last).as_secs_f64()
+        });
+        self.last_update = Some(now);
+        if let Some(target) = self.target {
+            let error = target - self.offset;
+            self.offset = if error.abs() > SNAP_THRESHOLD {
+                target
+            } else {
+                self.offset + error.clamp(-elapsed * MAX_SLEW, elapsed * MAX_SLEW)
+            };
+        }
+        let time = (self.local(now).as_secs_f64() + self.offset).max(0.0);
+        self.last_time = self.last_time.max(Duration::from_secs_f64(time));
+        ServerTime {
+            elapsed: self.last_time,
+            synchronized: self.samples_received >= SYNC_SAMPLES,
+        }
+    }
+
+    fn local(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.epoch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_server_clock() {
+        let start = Instant::now();
+        // The server started 5s before the client and every ping takes 40ms each way.
+        let server = ServerClock::new(start);
+        let mut client = ClockSync::new(start + Duration::from_secs(5));
+        let one_way = Duration::from_millis(40);
+
+        let mut now = start + Duration::from_secs(5);
+        for _ in 0..SYNC_SAMPLES {
+            let ping = client.ping(now).unwrap();
+            let mut reader = BitReader::new(&ping[1..]);
+            assert_eq!(reader.read_varint(), Some(PING));
+            let client_time = reader.read_varint().unwrap();
+
+            let mut writer = BitWriter::new();
+            writer.write_varint(PONG);
+            writer.write_varint(client_time);
+            let server_time = server.now(now + one_way).elapsed();
+            writer.write_varint(server_time.as_micros() as u64);
+            let mut pong = vec![CLOCK_MESSAGE];
+            pong.extend(writer.finish());
+            assert!(client.receive(&pong, now + one_way * 2));
+            now += SYNC_PING_INTERVAL;
+        }
+
+        let time = client.server_time(now);
+        assert!(time.is_synchronized());
+        let error = time.elapsed().as_secs_f64() - server.now(now).elapsed().as_secs_f64();
+        assert!(error.abs() < 0.001, "off by {error}");
+        assert_eq!(client.rtt(), Some(one_way * 2));
+    }
+}
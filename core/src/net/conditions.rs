@@ -0,0 +1,133 @@
+use std::{net::SocketAddr, time::Duration};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use crate::{cvars::Cvars, identifier::Uuid};
+
+// Artificial network conditions for testing, applied to the packets a transport sends. Turn them
+// on at both ends to affect both directions. Set through the `net.sim_*` cvars when using the net
+// plugins.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NetConditions {
+    // Added to every packet.
+    pub latency: Duration,
+    // Up to this much more, random per packet, so packets also get reordered.
+    pub jitter: Duration,
+    // Fraction of packets dropped, 0..=1.
+    pub loss: f32,
+    // Fraction of packets sent twice, 0..=1.
+    pub duplicate: f32,
+}
+
+impl NetConditions {
+    pub fn is_ideal(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+pub struct ConditionSimulator {
+    conditions: NetConditions,
+    rng: u64,
+    delayed: Vec<(Instant, SocketAddr, Vec<u8>)>,
+}
+
+impl Default for ConditionSimulator {
+    fn default() -> Self {
+        let seed = u64::from_le_bytes(Uuid::new_v4().as_bytes()[..8].try_into().unwrap());
+        Self {
+            conditions: NetConditions::default(),
+            // Xorshift gets stuck on zero.
+            rng: seed | 1,
+            delayed: Vec::new(),
+        }
+    }
+}
+
+impl ConditionSimulator {
+    pub fn conditions(&self) -> NetConditions {
+        self.conditions
+    }
+
+    pub fn set_conditions(&mut self, conditions: NetConditions) {
+        self.conditions = conditions;
+    }
+
+    // Returns the packet if it should go out right away, otherwise it was dropped or delayed.
+    pub fn submit(&mut self, addr: SocketAddr, bytes: Vec<u8>, now: Instant) -> Option<Vec<u8>> {
+        if self.conditions.is_ideal() {
+            return Some(bytes);
+        }
+        if self.chance(self.conditions.loss) {
+            return None;
+        }
+        if self.chance(self.conditions.duplicate) {
+            let at = now + self.delay();
+            self.delayed.push((at, addr, bytes.clone()));
+        }
+        let at = now + self.delay();
+        self.delayed.push((at, addr, bytes));
+        None
+    }
+
+    // Delayed packets whose time has come.
+    pub fn due(&mut self, now: Instant) -> Vec<(SocketAddr, Vec<u8>)> {
+        let mut due = Vec::new();
+        self.delayed.retain_mut(|(at, addr, bytes)| {
+            if *at > now {
+                return true;
+            }
+            due.push((*addr, std::mem::take(bytes)));
+            false
+        });
+        due
+    }
+
+    fn delay(&mut self) -> Duration {
+        self.conditions.latency + self.conditions.jitter.mul_f32(self.next_f32())
+    }
+
+    fn chance(&mut self, probability: f32) -> bool {
+        probability > 0.0 && self.next_f32() < probability
+    }
+
+    // Xorshift64*, plenty for picking packets to drop.
+    fn next_f32(&mut self) -> f32 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        (self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+pub fn register_cvars(cvars: &mut Cvars) {
+    cvars.register(
+        "net.sim_latency_ms",
+        0i64,
+        "artificial latency added to sent packets",
+    );
+    cvars.register(
+        "net.sim_jitter_ms",
+        0i64,
+        "random extra latency per sent packet, up to this",
+    );
+    cvars.register("net.sim_loss", 0.0, "fraction of sent packets dropped");
+    cvars.register(
+        "net.sim_duplicate",
+        0.0,
+        "fraction of sent packets sent twice",
+    );
+}
+
+pub fn from_cvars(cvars: &Cvars) -> NetConditions {
+    let millis = |name| Duration::from_millis(cvars.get_int(name).unwrap_or(0).max(0) as u64);
+    let fraction = |name| cvars.get_float(name).unwrap_or(0.0).clamp(0.0, 1.0) as f32;
+    NetConditions {
+        latency: millis("net.sim_latency_ms"),
+        jitter: millis("net.sim_jitter_ms"),
+        loss: fraction("net.sim_loss"),
+        duplicate: fraction("net.sim_duplicate"),
+    }
+}
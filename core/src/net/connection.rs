@@ -0,0 +1,306 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use super::{
+    packet::{self, Message, Packet, MAX_PACKET_SIZE},
+    stats::StatsTracker,
+    Channel, ConnectionId, NetError,
+};
+
+// Reliable messages not yet acked, per connection. Bounded well below half the u16 id space so
+// wrapped ids can't be confused.
+const MAX_PENDING_RELIABLE: usize = 1024;
+const SENT_PACKET_HISTORY: usize = 256;
+
+// Whether sequence number a is more recent than b, allowing for wrap around.
+pub fn sequence_greater(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    // Client side, waiting for the server to accept.
+    Connecting { salt: u64 },
+    Connected,
+}
+
+#[derive(Clone)]
+struct SentPacket {
+    sequence: u16,
+    sent_at: Instant,
+    messages: Vec<(Channel, u16)>,
+}
+
+struct PendingMessage {
+    channel: Channel,
+    id: u16,
+    data: Vec<u8>,
+    last_sent: Option<Instant>,
+}
+
+pub struct Connection {
+    pub id: ConnectionId,
+    pub addr: SocketAddr,
+    pub state: ConnectionState,
+    // Server side, the salt of the request this connection was accepted for.
+    pub salt: u64,
+    pub last_received: Instant,
+    pub last_sent: Instant,
+    pub stats: StatsTracker,
+
+    local_sequence: u16,
+    remote_sequence: u16,
+    received_bits: u32,
+    received_any: bool,
+    // Reliable messages carried by recently sent packets, indexed by sequence % history size.
+    sent_packets: Vec<Option<SentPacket>>,
+    // Oldest sent sequence that may still be acked, anything before it that wasn't is lost.
+    loss_checked: u16,
+
+    unreliable: VecDeque<Vec<u8>>,
+    pending: VecDeque<PendingMessage>,
+    next_message_id: [u16; 2],
+
+    // Reliable ordered receiving: the next id to deliver and messages that arrived early.
+    next_ordered_id: u16,
+    early_ordered: HashMap<u16, Vec<u8>>,
+    // Reliable unordered receiving: everything before base was delivered, plus the ids after it.
+    unordered_base: u16,
+    unordered_received: HashSet<u16>,
+}
+
+impl Connection {
+    pub fn new(id: ConnectionId, addr: SocketAddr, state: ConnectionState, now: Instant) -> Self {
+        Self {
+            id,
+            addr,
+            state,
+            salt: 0,
+            last_received: now,
+            last_sent: now,
+            stats: StatsTracker::new(now),
+            local_sequence: 0,
+            remote_sequence: 0,
+            received_bits: 0,
+            received_any: false,
+            sent_packets: vec![None; SENT_PACKET_HISTORY],
+            loss_checked: 0,
+            unreliable: VecDeque::new(),
+            pending: VecDeque::new(),
+            next_message_id: [0; 2],
+            next_ordered_id: 0,
+            early_ordered: HashMap::new(),
+            unordered_base: 0,
+            unordered_received: HashSet::new(),
+        }
+    }
+
+    pub fn queue(&mut self, channel: Channel, data: &[u8]) -> Result<(), NetError> {
+        if data.len() > packet::MAX_MESSAGE_SIZE {
+            return Err(NetError::MessageTooLarge);
+        }
+        let Some(slot) = channel.reliable_index() else {
+            self.unreliable.push_back(data.to_vec());
+            return Ok(());
+        };
+        if self.pending.len() >= MAX_PENDING_RELIABLE {
+            return Err(NetError::SendQueueFull);
+        }
+        let id = self.next_message_id[slot];
+        self.next_message_id[slot] = id.wrapping_add(1);
+        self.pending.push_back(PendingMessage {
+            channel,
+            id,
+            data: data.to_vec(),
+            last_sent: None,
+        });
+        Ok(())
+    }
+
+    // Builds the next payload packet: reliable messages due for (re)sending first, then unreliable
+    // ones. None if there is nothing to send and no heartbeat is due.
+    pub fn next_packet(
+        &mut self,
+        now: Instant,
+        resend_delay: Duration,
+        heartbeat: Duration,
+    ) -> Option<Packet> {
+        let mut messages = Vec::new();
+        let mut reliable = Vec::new();
+        for pending in self.pending.iter_mut() {
+            if pending
+                .last_sent
+                .is_some_and(|sent| now.duration_since(sent) < resend_delay)
+            {
+                continue;
+            }
+            let message = Message {
+                channel: pending.channel,
+                id: pending.id,
+                data: pending.data.clone(),
+            };
+            if packet::payload_size(&messages) + message.encoded_size() > MAX_PACKET_SIZE
+                || messages.len() == u8::MAX as usize
+            {
+                break;
+            }
+            pending.last_sent = Some(now);
+            reliable.push((pending.channel, pending.id));
+            messages.push(message);
+        }
+        while let Some(data) = self.unreliable.front() {
+            let message = Message {
+                channel: Channel::Unreliable,
+                id: 0,
+                data: data.clone(),
+            };
+            if packet::payload_size(&messages) + message.encoded_size() > MAX_PACKET_SIZE
+                || messages.len() == u8::MAX as usize
+            {
+                break;
+            }
+            self.unreliable.pop_front();
+            messages.push(message);
+        }
+        if messages.is_empty() && now.duration_since(self.last_sent) < heartbeat {
+            return None;
+        }
+
+        let sequence = self.local_sequence;
+        self.local_sequence = sequence.wrapping_add(1);
+        self.sent_packets[sequence as usize % SENT_PACKET_HISTORY] = Some(SentPacket {
+            sequence,
+            sent_at: now,
+            messages: reliable,
+        });
+        self.last_sent = now;
+        Some(Packet::Payload {
+            sequence,
+            ack: self.remote_sequence,
+            ack_bits: self.received_bits,
+            messages,
+        })
+    }
+
+    // Processes a payload, returning the messages to hand to the game in delivery order.
+    pub fn receive_payload(
+        &mut self,
+        sequence: u16,
+        ack: u16,
+        ack_bits: u32,
+        messages: Vec<Message>,
+        now: Instant,
+    ) -> Vec<(Channel, Vec<u8>)> {
+        self.last_received = now;
+        self.process_acks(ack, ack_bits, now);
+        if !self.record_received(sequence) {
+            // Duplicate packet, its acks were still useful.
+            return Vec::new();
+        }
+
+        let mut delivered = Vec::new();
+        for message in messages {
+            match message.channel {
+                Channel::Unreliable => delivered.push((Channel::Unreliable, message.data)),
+                Channel::ReliableUnordered => {
+                    let offset = message.id.wrapping_sub(self.unordered_base);
+                    if offset >= 0x8000 || !self.unordered_received.insert(message.id) {
+                        continue;
+                    }
+                    delivered.push((Channel::ReliableUnordered, message.data));
+                    while self.unordered_received.remove(&self.unordered_base) {
+                        self.unordered_base = self.unordered_base.wrapping_add(1);
+                    }
+                }
+                Channel::ReliableOrdered => {
+                    let offset = message.id.wrapping_sub(self.next_ordered_id);
+                    if offset >= 0x8000 {
+                        continue;
+                    }
+                    self.early_ordered.insert(message.id, message.data);
+                    while let Some(data) = self.early_ordered.remove(&self.next_ordered_id) {
+                        delivered.push((Channel::ReliableOrdered, data));
+                        self.next_ordered_id = self.next_ordered_id.wrapping_add(1);
+                    }
+                }
+            }
+        }
+        delivered
+    }
+
+    // Returns false if `sequence` was already received.
+    fn record_received(&mut self, sequence: u16) -> bool {
+        if !self.received_any {
+            self.received_any = true;
+            self.remote_sequence = sequence;
+            self.received_bits = 0;
+            return true;
+        }
+        if sequence_greater(sequence, self.remote_sequence) {
+            let shift = sequence.wrapping_sub(self.remote_sequence) as u32;
+            // The previous most recent sequence becomes bit shift - 1.
+            self.received_bits = match shift {
+                1..=31 => (self.received_bits << shift) | (1 << (shift - 1)),
+                32 => 1 << 31,
+                _ => 0,
+            };
+            self.remote_sequence = sequence;
+            return true;
+        }
+        let age = self.remote_sequence.wrapping_sub(sequence) as u32;
+        if age == 0 || age > 32 || self.received_bits & (1 << (age - 1)) != 0 {
+            return false;
+        }
+        self.received_bits |= 1 << (age - 1);
+        true
+    }
+
+    fn process_acks(&mut self, ack: u16, ack_bits: u32, now: Instant) {
+        self.ack_packet(ack, now);
+        for bit in 0..32 {
+            if ack_bits & (1 << bit) != 0 {
+                self.ack_packet(ack.wrapping_sub(bit + 1), now);
+            }
+        }
+        // Packets older than the ack window can't be acked anymore.
+        let window_start = ack.wrapping_sub(32);
+        let mut checked = 0;
+        while sequence_greater(window_start, self.loss_checked) && checked < SENT_PACKET_HISTORY {
+            let slot = &mut self.sent_packets[self.loss_checked as usize % SENT_PACKET_HISTORY];
+            if slot
+                .as_ref()
+                .is_some_and(|sent| sent.sequence == self.loss_checked)
+            {
+                *slot = None;
+                self.stats.on_lost();
+            }
+            self.loss_checked = self.loss_checked.wrapping_add(1);
+            checked += 1;
+        }
+    }
+
+    fn ack_packet(&mut self, sequence: u16, now: Instant) {
+        let slot = &mut self.sent_packets[sequence as usize % SENT_PACKET_HISTORY];
+        let acked = match slot.take() {
+            Some(sent) if sent.sequence == sequence => {
+                self.stats.on_acked(sent.sent_at, now);
+                sent.messages
+            }
+            // An older packet's slot was reused, or this one was acked already.
+            other => {
+                *slot = other;
+                return;
+            }
+        };
+        self.pending
+            .retain(|pending| !acked.contains(&(pending.channel, pending.id)));
+    }
+}
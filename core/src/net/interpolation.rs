@@ -0,0 +1,247 @@
+//! Smooths remote entities by showing them a fixed delay behind the server. Received states are
+//! buffered per entity with the server time they are from, and sampled between the two that
+//! surround the render time, so late or bunched up packets don't make entities stutter as long
+//! as they arrive within the delay.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use super::{clock::ServerTime, replication::NetEntity};
+use crate::sim::FIXED_TIMESTEP;
+
+pub trait Interpolate: Clone {
+    // `t` is in 0..=1, from self to `to`.
+    fn interpolate(&self, to: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(&self, to: &Self, t: f32) -> Self {
+        self + (to - self) * t
+    }
+}
+
+impl<const N: usize> Interpolate for [f32; N] {
+    fn interpolate(&self, to: &Self, t: f32) -> Self {
+        std::array::from_fn(|i| self[i].interpolate(&to[i], t))
+    }
+}
+
+// Server time of a replication tick.
+pub fn tick_time(tick: u64) -> Duration {
+    FIXED_TIMESTEP * tick as u32
+}
+
+// States of one entity in server time order.
+pub struct SnapshotBuffer<T> {
+    snapshots: VecDeque<(Duration, T)>,
+    capacity: usize,
+}
+
+impl<T: Interpolate> SnapshotBuffer<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(2),
+        }
+    }
+
+    // States older than the newest one are dropped, they arrived too late to matter.
+    pub fn push(&mut self, time: Duration, value: T) {
+        if self.snapshots.back().is_some_and(|(last, _)| *last >= time) {
+            return;
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((time, value));
+    }
+
+    // Holds the first and last state outside the buffered range instead of extrapolating.
+    pub fn sample(&self, time: Duration) -> Option<T> {
+        let after = self.snapshots.iter().position(|(at, _)| *at > time);
+        match after {
+            Some(0) => self.snapshots.front().map(|(_, value)| value.clone()),
+            Some(index) => {
+                let (from_time, from) = &self.snapshots[index - 1];
+                let (to_time, to) = &self.snapshots[index];
+                let t = (time - *from_time).as_secs_f32() / (*to_time - *from_time).as_secs_f32();
+                Some(from.interpolate(to, t))
+            }
+            None => self.snapshots.back().map(|(_, value)| value.clone()),
+        }
+    }
+
+    // Drops states nothing before `time` will sample anymore, keeping the one `time` starts from.
+    pub fn discard_before(&mut self, time: Duration) {
+        while self.snapshots.len() > 2 && self.snapshots[1].0 <= time {
+            self.snapshots.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InterpolationConfig {
+    // How far behind the estimated server time entities are shown. Two or three server ticks of
+    // state should arrive within it, more hides more jitter and loss at the cost of latency.
+    pub delay: Duration,
+    // States kept per entity.
+    pub capacity: usize,
+    // The render clock is pulled towards the target by this fraction of the error per update,
+    // and snaps when it is further off than `snap_threshold`.
+    pub correction: f32,
+    pub snap_threshold: Duration,
+}
+
+impl Default for InterpolationConfig {
+    fn default() -> Self {
+        Self {
+            delay: Duration::from_millis(100),
+            capacity: 32,
+            correction: 0.1,
+            snap_threshold: Duration::from_millis(250),
+        }
+    }
+}
+
+// Interpolated state of every remote entity, one per interpolated component type.
+pub struct Interpolator<T> {
+    config: InterpolationConfig,
+    buffers: HashMap<NetEntity, SnapshotBuffer<T>>,
+    // Latest server time received and when, to estimate the current server time.
+    latest: Option<(Duration, Instant)>,
+    render_time: Duration,
+    last_update: Option<Instant>,
+}
+
+impl<T: Interpolate> Interpolator<T> {
+    pub fn new(config: InterpolationConfig) -> Self {
+        Self {
+            config,
+            buffers: HashMap::new(),
+            latest: None,
+            render_time: Duration::ZERO,
+            last_update: None,
+        }
+    }
+
+    pub fn config(&self) -> &InterpolationConfig {
+        &self.config
+    }
+
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.config.delay = delay;
+    }
+
+    // Records an entity's state as of `server_time`, received at `now`.
+    pub fn push(&mut self, entity: NetEntity, server_time: Duration, value: T, now: Instant) {
+        if self.latest.is_none_or(|(latest, _)| server_time > latest) {
+            self.latest = Some((server_time, now));
+        }
+        let capacity = self.config.capacity;
+        self.buffers
+            .entry(entity)
+            .or_insert_with(|| SnapshotBuffer::new(capacity))
+            .push(server_time, value);
+    }
+
+    pub fn remove(&mut self, entity: NetEntity) {
+        self.buffers.remove(&entity);
+    }
+
+    // Advances the render time, call once per frame before sampling. Estimates the server time from
+    // the latest state received, see `update_with_clock` for a synchronized one.
+    pub fn update(&mut self, now: Instant) {
+        let Some((latest, received)) = self.latest else {
+            return;
+        };
+        self.advance(now, latest + now.saturating_duration_since(received));
+    }
+
+    // Like `update`, but follows the synchronized server clock once it is available, which
+    // doesn't stall or jump with the packets of one connection.
+    pub fn update_with_clock(&mut self, now: Instant, server_time: ServerTime) {
+        if server_time.is_synchronized() {
+            self.advance(now, server_time.elapsed());
+        } else {
+            self.update(now);
+        }
+    }
+
+    fn advance(&mut self, now: Instant, server_time: Duration) {
+        let target = server_time.saturating_sub(self.config.delay);
+        let elapsed = self
+            .last_update
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last_update = Some(now);
+
+        let advanced = self.render_time + elapsed;
+        let error = target.as_secs_f64() - advanced.as_secs_f64();
+        self.render_time = if error.abs() > self.config.snap_threshold.as_secs_f64() {
+            target
+        } else {
+            Duration::from_secs_f64(
+                (advanced.as_secs_f64() + error * self.config.correction as f64).max(0.0),
+            )
+        };
+        for buffer in self.buffers.values_mut() {
+            buffer.discard_before(self.render_time);
+        }
+    }
+
+    // Server time entities are currently shown at.
+    pub fn render_time(&self) -> Duration {
+        self.render_time
+    }
+
+    pub fn sample(&self, entity: NetEntity) -> Option<T> {
+        self.buffers.get(&entity)?.sample(self.render_time)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NetEntity, T)> + '_ {
+        self.buffers
+            .iter()
+            .filter_map(|(&entity, buffer)| Some((entity, buffer.sample(self.render_time)?)))
+    }
+}
+
+impl<T: Interpolate> Default for Interpolator<T> {
+    fn default() -> Self {
+        Self::new(InterpolationConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_between_snapshots() {
+        let mut buffer = SnapshotBuffer::new(8);
+        buffer.push(Duration::from_millis(100), [0.0, 10.0]);
+        buffer.push(Duration::from_millis(200), [10.0, 20.0]);
+        // Late, dropped.
+        buffer.push(Duration::from_millis(150), [100.0, 100.0]);
+
+        assert_eq!(buffer.sample(Duration::from_millis(50)), Some([0.0, 10.0]));
+        assert_eq!(buffer.sample(Duration::from_millis(150)), Some([5.0, 15.0]));
+        assert_eq!(
+            buffer.sample(Duration::from_millis(300)),
+            Some([10.0, 20.0])
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+};
+
+// Moves whole packets to and from peers, under the transport's reliability layer. Peers are
+// identified by address even on links that aren't UDP.
+pub trait Link: Send {
+    // Errors are treated like packet loss by the caller.
+    fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> io::Result<()>;
+
+    // Ok(None) once nothing more is pending.
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>>;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Link for UdpSocket {
+    fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> io::Result<()> {
+        UdpSocket::send_to(self, bytes, addr).map(drop)
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+        loop {
+            return match UdpSocket::recv_from(self, buffer) {
+                Ok(received) => Ok(Some(received)),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+                // Windows reports ICMP port unreachable for earlier sends here, timeouts handle it.
+                Err(err) if err.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(err) => Err(err),
+            };
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+}
+
+// A UDP socket plus a WebSocket listener, so native and browser clients can join the same server.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ServerLink {
+    pub udp: UdpSocket,
+    pub websocket: super::websocket::WebSocketListener,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Link for ServerLink {
+    fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> io::Result<()> {
+        if self.websocket.has_peer(addr) {
+            self.websocket.send_to(bytes, addr)
+        } else {
+            Link::send_to(&mut self.udp, bytes, addr)
+        }
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+        match Link::recv_from(&mut self.udp, buffer)? {
+            Some(received) => Ok(Some(received)),
+            None => self.websocket.recv_from(buffer),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(&self.udp)
+    }
+}
@@ -0,0 +1,166 @@
+//! Networking over UDP. A `Transport` is either a server accepting clients or a client connected to
+//! one server, and carries messages on three channels: unreliable, reliable ordered and reliable
+//! unordered. Reliable messages are acked per packet and resent until acked.
+//!
+//! The API is poll based like the rest of the engine: call `Transport::update` once per tick, then
+//! drain `Transport::poll_event`. Nothing blocks and no async runtime is needed. `NetServerPlugin`
+//! and `NetClientPlugin` do the updating from an engine system, add replication on top and keep a
+//! synchronized `ServerTime` resource.
+
+pub mod bits;
+mod client;
+pub mod clock;
+mod conditions;
+mod connection;
+pub mod interpolation;
+mod link;
+mod packet;
+pub mod prediction;
+pub mod replication;
+pub mod rollback;
+pub mod rpc;
+mod server;
+pub mod session;
+mod stats;
+mod transport;
+pub mod websocket;
+
+use std::{fmt, io, time::Duration};
+
+pub use client::{ClientState, NetClient, NetClientPlugin, ServerAddress};
+pub use clock::ServerTime;
+pub use conditions::NetConditions;
+pub use packet::{MAX_MESSAGE_SIZE, MAX_PACKET_SIZE};
+pub use server::{NetServer, NetServerPlugin};
+pub use stats::ConnectionStats;
+pub use transport::Transport;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Channel {
+    // May be dropped, duplicated packets are filtered. Use for state that is resent anyway.
+    Unreliable,
+    // Delivered exactly once, in the order sent.
+    ReliableOrdered,
+    // Delivered exactly once, as soon as it arrives.
+    ReliableUnordered,
+}
+
+impl Channel {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Channel::Unreliable),
+            1 => Some(Channel::ReliableOrdered),
+            2 => Some(Channel::ReliableUnordered),
+            _ => None,
+        }
+    }
+
+    // Index of the channel's message id counter, None for unreliable.
+    fn reliable_index(self) -> Option<usize> {
+        match self {
+            Channel::Unreliable => None,
+            Channel::ReliableOrdered => Some(0),
+            Channel::ReliableUnordered => Some(1),
+        }
+    }
+}
+
+// Assigned by the server when a client connects. A client's only connection is the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(pub u32);
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "conn#{}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisconnectReason {
+    // Nothing was received for `NetConfig::timeout`.
+    TimedOut,
+    // The other side disconnected.
+    Remote,
+    // `Transport::disconnect` was called.
+    Local,
+    // The server is full.
+    Denied,
+    // The server was built with different messages, see `rpc::RpcRegistry::fingerprint`.
+    VersionMismatch,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetEvent {
+    Connected(ConnectionId),
+    Disconnected(ConnectionId, DisconnectReason),
+    Message {
+        connection: ConnectionId,
+        channel: Channel,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug)]
+pub enum NetError {
+    NotConnected,
+    // Larger than `MAX_MESSAGE_SIZE`, messages aren't fragmented.
+    MessageTooLarge,
+    // Too many reliable messages waiting for acks, the connection is likely dead.
+    SendQueueFull,
+    Io(io::Error),
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetError::NotConnected => write!(f, "not connected"),
+            NetError::MessageTooLarge => {
+                write!(f, "message larger than {} bytes", MAX_MESSAGE_SIZE)
+            }
+            NetError::SendQueueFull => write!(f, "too many unacked reliable messages"),
+            NetError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+impl From<io::Error> for NetError {
+    fn from(err: io::Error) -> Self {
+        NetError::Io(err)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NetConfig {
+    // Packets with a different id are dropped, bump it when the protocol changes.
+    pub protocol_id: u32,
+    // Clients with a different version are denied when connecting. The net plugins set it to the
+    // fingerprint of their `RpcRegistry`.
+    pub protocol_version: u64,
+    // Connections that receive nothing for this long are dropped.
+    pub timeout: Duration,
+    // An empty packet is sent when nothing else was sent for this long, keeping acks flowing.
+    pub heartbeat_interval: Duration,
+    // Unacked reliable messages are resent after this long.
+    pub resend_delay: Duration,
+    // Clients resend their connect request this often until accepted.
+    pub connect_retry: Duration,
+    // Server only.
+    pub max_clients: usize,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            protocol_id: 0x4d32_0001,
+            protocol_version: 0,
+            timeout: Duration::from_secs(10),
+            heartbeat_interval: Duration::from_millis(100),
+            resend_delay: Duration::from_millis(100),
+            connect_retry: Duration::from_millis(250),
+            max_clients: 32,
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use std::io;
+
+use super::Channel;
+
+// Leaves room for IP and UDP headers within the common 1280 byte IPv6 minimum MTU.
+pub const MAX_PACKET_SIZE: usize = 1200;
+// protocol id + kind + sequence + ack + ack bits + message count.
+const PAYLOAD_HEADER_SIZE: usize = 4 + 1 + 2 + 2 + 4 + 1;
+// channel + message id + length.
+const MESSAGE_HEADER_SIZE: usize = 1 + 2 + 2;
+pub const MAX_MESSAGE_SIZE: usize = MAX_PACKET_SIZE - PAYLOAD_HEADER_SIZE - MESSAGE_HEADER_SIZE;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub channel: Channel,
+    // Only meaningful on reliable channels.
+    pub id: u16,
+    pub data: Vec<u8>,
+}
+
+impl Message {
+    pub fn encoded_size(&self) -> usize {
+        MESSAGE_HEADER_SIZE + self.data.len()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet {
+    // The client's random salt ties the accept to this connection attempt. Servers deny requests
+    // with a different protocol version.
+    ConnectRequest {
+        salt: u64,
+        version: u64,
+    },
+    ConnectAccept {
+        salt: u64,
+        connection: u32,
+    },
+    ConnectDeny {
+        salt: u64,
+        reason: DenyReason,
+    },
+    Disconnect,
+    // Messages plus acks, an empty one is a heartbeat.
+    Payload {
+        sequence: u16,
+        ack: u16,
+        // Bit n set means sequence ack - 1 - n was received too.
+        ack_bits: u32,
+        messages: Vec<Message>,
+    },
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DenyReason {
+    ServerFull,
+    VersionMismatch,
+}
+
+const CONNECT_REQUEST: u8 = 0;
+const CONNECT_ACCEPT: u8 = 1;
+const CONNECT_DENY: u8 = 2;
+const DISCONNECT: u8 = 3;
+const PAYLOAD: u8 = 4;
+
+// Little endian throughout, prefixed with the protocol id so stray or foreign packets are dropped.
+impl Packet {
+    pub fn encode(&self, protocol_id: u32) -> Vec<u8> {
+        let mut out = Vec::with_capacity(64);
+        out.extend_from_slice(&protocol_id.to_le_bytes());
+        match self {
+            Packet::ConnectRequest { salt, version } => {
+                out.push(CONNECT_REQUEST);
+                out.extend_from_slice(&salt.to_le_bytes());
+                out.extend_from_slice(&version.to_le_bytes());
+            }
+            Packet::ConnectAccept { salt, connection } => {
+                out.push(CONNECT_ACCEPT);
+                out.extend_from_slice(&salt.to_le_bytes());
+                out.extend_from_slice(&connection.to_le_bytes());
+            }
+            Packet::ConnectDeny { salt, reason } => {
+                out.push(CONNECT_DENY);
+                out.extend_from_slice(&salt.to_le_bytes());
+                out.push(*reason as u8);
+            }
+            Packet::Disconnect => out.push(DISCONNECT),
+            Packet::Payload {
+                sequence,
+                ack,
+                ack_bits,
+                messages,
+            } => {
+                out.push(PAYLOAD);
+                out.extend_from_slice(&sequence.to_le_bytes());
+                out.extend_from_slice(&ack.to_le_bytes());
+                out.extend_from_slice(&ack_bits.to_le_bytes());
+                out.push(messages.len() as u8);
+                for message in messages {
+                    out.push(message.channel as u8);
+                    out.extend_from_slice(&message.id.to_le_bytes());
+                    out.extend_from_slice(&(message.data.len() as u16).to_le_bytes());
+                    out.extend_from_slice(&message.data);
+                }
+            }
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8], protocol_id: u32) -> io::Result<Packet> {
+        let mut reader = Reader { bytes };
+        if reader.u32()? != protocol_id {
+            return Err(invalid("wrong protocol id"));
+        }
+        let packet = match reader.u8()? {
+            CONNECT_REQUEST => Packet::ConnectRequest {
+                salt: reader.u64()?,
+                version: reader.u64()?,
+            },
+            CONNECT_ACCEPT => Packet::ConnectAccept {
+                salt: reader.u64()?,
+                connection: reader.u32()?,
+            },
+            CONNECT_DENY => Packet::ConnectDeny {
+                salt: reader.u64()?,
+                reason: match reader.u8()? {
+                    0 => DenyReason::ServerFull,
+                    1 => DenyReason::VersionMismatch,
+                    _ => return Err(invalid("bad deny reason")),
+                },
+            },
+            DISCONNECT => Packet::Disconnect,
+            PAYLOAD => {
+                let sequence = reader.u16()?;
+                let ack = reader.u16()?;
+                let ack_bits = reader.u32()?;
+                let count = reader.u8()?;
+                let mut messages = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    let channel =
+                        Channel::from_u8(reader.u8()?).ok_or_else(|| invalid("bad channel"))?;
+                    let id = reader.u16()?;
+                    let len = reader.u16()? as usize;
+                    messages.push(Message {
+                        channel,
+                        id,
+                        data: reader.take(len)?.to_vec(),
+                    });
+                }
+                Packet::Payload {
+                    sequence,
+                    ack,
+                    ack_bits,
+                    messages,
+                }
+            }
+            _ => return Err(invalid("unknown packet kind")),
+        };
+        if !reader.bytes.is_empty() {
+            return Err(invalid("trailing bytes"));
+        }
+        Ok(packet)
+    }
+}
+
+pub fn payload_size(messages: &[Message]) -> usize {
+    PAYLOAD_HEADER_SIZE + messages.iter().map(Message::encoded_size).sum::<usize>()
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(invalid("truncated packet"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
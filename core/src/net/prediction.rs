@@ -0,0 +1,201 @@
+//! Client side prediction. The client applies its own inputs to the player immediately through the
+//! same step function the server runs, and remembers them numbered by sequence. The server
+//! reports which input its authoritative state includes. The client then starts from that state
+//! and replays the inputs the server hadn't processed yet, so corrections never undo input that
+//! is still in flight.
+
+use std::collections::VecDeque;
+
+use crate::metrics;
+
+// Inputs older than this many are dropped if the server never acks them.
+const MAX_HISTORY: usize = 256;
+
+pub type InputSequence = u32;
+
+struct PredictedInput<I, S> {
+    sequence: InputSequence,
+    input: I,
+    // Predicted state after applying the input.
+    state: S,
+}
+
+// Predicts one locally controlled entity. `step` must be the simulation the server runs for it,
+// usually a function shared by both.
+pub struct Predictor<I, S, F> {
+    state: S,
+    step: F,
+    next_sequence: InputSequence,
+    history: VecDeque<PredictedInput<I, S>>,
+    acked: Option<InputSequence>,
+}
+
+impl<I, S, F> Predictor<I, S, F>
+where
+    I: Clone,
+    S: Clone + PartialEq,
+    F: FnMut(&mut S, &I),
+{
+    pub fn new(state: S, step: F) -> Self {
+        Self {
+            state,
+            step,
+            next_sequence: 0,
+            history: VecDeque::new(),
+            acked: None,
+        }
+    }
+
+    // Predicted state, render the player from this.
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+
+    // Applies `input` locally, returns its sequence to send to the server with it.
+    pub fn apply(&mut self, input: I) -> InputSequence {
+        let sequence = self.next_sequence;
+        self.next_sequence = sequence.wrapping_add(1);
+        (self.step)(&mut self.state, &input);
+        if self.history.len() == MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(PredictedInput {
+            sequence,
+            input,
+            state: self.state.clone(),
+        });
+        sequence
+    }
+
+    // Inputs not acknowledged yet, oldest first. Resending them with every input packet covers
+    // lost packets on an unreliable channel.
+    pub fn unacked(&self) -> impl Iterator<Item = (InputSequence, &I)> {
+        self.history
+            .iter()
+            .map(|entry| (entry.sequence, &entry.input))
+    }
+
+    pub fn last_acked(&self) -> Option<InputSequence> {
+        self.acked
+    }
+
+    // Takes the server's state of the entity after it processed input `acked`. Returns true if the
+    // prediction was wrong and inputs were replayed on top of the server's state.
+    pub fn reconcile(&mut self, acked: InputSequence, authoritative: S) -> bool {
+        if self.acked.is_some_and(|last| !sequence_newer(acked, last)) {
+            // Older than state already reconciled against, e.g. reordered.
+            return false;
+        }
+        self.acked = Some(acked);
+        while self
+            .history
+            .front()
+            .is_some_and(|entry| !sequence_newer(entry.sequence, acked))
+        {
+            let entry = self.history.pop_front().unwrap();
+            if entry.sequence == acked && entry.state == authoritative {
+                return false;
+            }
+        }
+
+        metrics::increment("net_mispredictions_total", 1);
+        let mut state = authoritative;
+        for entry in self.history.iter_mut() {
+            (self.step)(&mut state, &entry.input);
+            entry.state = state.clone();
+        }
+        self.state = state;
+        true
+    }
+}
+
+fn sequence_newer(a: InputSequence, b: InputSequence) -> bool {
+    a != b && a.wrapping_sub(b) < u32::MAX / 2
+}
+
+// Server side inputs of one client, applied in sequence order exactly once.
+pub struct InputQueue<I> {
+    pending: VecDeque<(InputSequence, I)>,
+    last_processed: Option<InputSequence>,
+}
+
+impl<I> Default for InputQueue<I> {
+    fn default() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            last_processed: None,
+        }
+    }
+}
+
+impl<I> InputQueue<I> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Duplicates and inputs already processed are ignored.
+    pub fn push(&mut self, sequence: InputSequence, input: I) {
+        if self
+            .last_processed
+            .is_some_and(|last| !sequence_newer(sequence, last))
+        {
+            return;
+        }
+        let index = self
+            .pending
+            .iter()
+            .position(|(pending, _)| !sequence_newer(sequence, *pending));
+        match index {
+            Some(index) if self.pending[index].0 == sequence => {}
+            Some(index) => self.pending.insert(index, (sequence, input)),
+            None => self.pending.push_back((sequence, input)),
+        }
+    }
+
+    // The next input to simulate. Send `last_processed` with the resulting state.
+    pub fn pop(&mut self) -> Option<I> {
+        let (sequence, input) = self.pending.pop_front()?;
+        self.last_processed = Some(sequence);
+        Some(input)
+    }
+
+    pub fn last_processed(&self) -> Option<InputSequence> {
+        self.last_processed
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_unacked_inputs() {
+        let step = |x: &mut i32, dx: &i32| *x += dx;
+        let mut predictor = Predictor::new(0, step);
+        let mut server = InputQueue::new();
+        for dx in [1, 2, 3] {
+            let sequence = predictor.apply(dx);
+            server.push(sequence, dx);
+        }
+        assert_eq!(*predictor.state(), 6);
+
+        // The server only processed the first input, and something moved the player by 10.
+        let mut authoritative = 10;
+        step(&mut authoritative, &server.pop().unwrap());
+        assert!(predictor.reconcile(server.last_processed().unwrap(), authoritative));
+        assert_eq!(*predictor.state(), 16);
+        assert_eq!(predictor.unacked().count(), 2);
+
+        // Agrees with the prediction, nothing to replay.
+        step(&mut authoritative, &server.pop().unwrap());
+        assert!(!predictor.reconcile(1, authoritative));
+    }
+}
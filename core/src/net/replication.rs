@@ -0,0 +1,534 @@
+//! Server to client replication of networked entities. The server keeps the replicated state of
+//! each entity as encoded components stamped with the tick they last changed, and sends each
+//! client spawns, the components that changed since it last heard about the entity, and despawns.
+//! An `Interest` decides which entities each client hears about at all.
+//!
+//! Entities are identified by `SnowflakeId`s so clients and servers agree on them without a
+//! lookup. Everything goes out reliable ordered, so a component is sent again only when it changes.
+
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+
+use super::{
+    bits::{BitReader, BitWriter},
+    Channel, ConnectionId, NetError, Transport, MAX_MESSAGE_SIZE,
+};
+use crate::identifier::{Name, SnowflakeId};
+
+pub type NetEntity = SnowflakeId;
+
+// First byte of every transport message carrying replication data, so it can share connections
+// with game messages. The bit packed server tick the data is from, the message count and the
+// messages follow it.
+pub const REPLICATION_MESSAGE: u8 = 0xf0;
+// The tag plus the largest varint tick and count.
+const BATCH_HEADER_BITS: usize = 8 + 80 + 24;
+
+/// A component that is sent to clients. `NAME` must be unique among replicated components and
+/// the same on both ends.
+pub trait Replicated: Sized {
+    const NAME: &'static str;
+
+    fn encode(&self, writer: &mut BitWriter);
+
+    fn decode(reader: &mut BitReader) -> Option<Self>;
+}
+
+/// Decides which entities a client receives. Entities that stop being relevant are despawned on
+/// that client and spawned again with their full state once they are relevant again.
+pub trait Interest: Send {
+    fn is_relevant(&self, client: ConnectionId, entity: NetEntity) -> bool;
+}
+
+// Every client receives every entity.
+pub struct Everything;
+
+impl Interest for Everything {
+    fn is_relevant(&self, _client: ConnectionId, _entity: NetEntity) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplicationMessage {
+    Spawn {
+        entity: NetEntity,
+    },
+    Update {
+        entity: NetEntity,
+        component: Name,
+        data: Vec<u8>,
+    },
+    Despawn {
+        entity: NetEntity,
+    },
+}
+
+const SPAWN: u64 = 0;
+const UPDATE: u64 = 1;
+const DESPAWN: u64 = 2;
+
+impl ReplicationMessage {
+    fn encode(&self, writer: &mut BitWriter) {
+        match self {
+            ReplicationMessage::Spawn { entity } => {
+                writer.write_bits(SPAWN, 2);
+                writer.write_bits(entity.to_bits(), 64);
+            }
+            ReplicationMessage::Update {
+                entity,
+                component,
+                data,
+            } => {
+                writer.write_bits(UPDATE, 2);
+                writer.write_bits(entity.to_bits(), 64);
+                writer.write_bits(component.hash(), 64);
+                writer.write_bytes(data);
+            }
+            ReplicationMessage::Despawn { entity } => {
+                writer.write_bits(DESPAWN, 2);
+                writer.write_bits(entity.to_bits(), 64);
+            }
+        }
+    }
+
+    fn encoded_bits(&self) -> usize {
+        match self {
+            ReplicationMessage::Update { data, .. } => {
+                let len_bits = (usize::BITS - data.len().leading_zeros()).max(1) as usize;
+                2 + 64 + 64 + len_bits.div_ceil(7) * 8 + data.len() * 8
+            }
+            _ => 2 + 64,
+        }
+    }
+
+    // Decodes the server tick and every message in a transport message, None if it is malformed.
+    pub fn decode_all(bytes: &[u8]) -> Option<(u64, Vec<ReplicationMessage>)> {
+        let (&REPLICATION_MESSAGE, bytes) = bytes.split_first()? else {
+            return None;
+        };
+        let mut reader = BitReader::new(bytes);
+        let tick = reader.read_varint()?;
+        let count = reader.read_varint()?;
+        let mut messages = Vec::new();
+        for _ in 0..count {
+            let kind = reader.read_bits(2)?;
+            let entity = SnowflakeId::from_bits(reader.read_bits(64)?);
+            messages.push(match kind {
+                SPAWN => ReplicationMessage::Spawn { entity },
+                UPDATE => ReplicationMessage::Update {
+                    entity,
+                    component: Name::from_hash(reader.read_bits(64)?),
+                    data: reader.read_bytes()?,
+                },
+                DESPAWN => ReplicationMessage::Despawn { entity },
+                _ => return None,
+            });
+        }
+        Some((tick, messages))
+    }
+}
+
+struct ReplicatedEntity {
+    // Component data and the tick it last changed.
+    components: HashMap<Name, (u64, Vec<u8>)>,
+}
+
+#[derive(Default)]
+struct ClientView {
+    // Entities the client has been sent, with the tick they were last sent at.
+    known: HashMap<NetEntity, u64>,
+}
+
+pub struct ReplicationServer {
+    tick: u64,
+    entities: HashMap<NetEntity, ReplicatedEntity>,
+    clients: HashMap<ConnectionId, ClientView>,
+    interest: Box<dyn Interest>,
+}
+
+impl Default for ReplicationServer {
+    fn default() -> Self {
+        Self::new(Everything)
+    }
+}
+
+impl ReplicationServer {
+    pub fn new<I: Interest + 'static>(interest: I) -> Self {
+        Self {
+            // Clients start at 0, so everything set before the first update counts as changed.
+            tick: 1,
+            entities: HashMap::new(),
+            clients: HashMap::new(),
+            interest: Box::new(interest),
+        }
+    }
+
+    pub fn set_interest<I: Interest + 'static>(&mut self, interest: I) {
+        self.interest = Box::new(interest);
+    }
+
+    pub fn spawn(&mut self, entity: NetEntity) {
+        self.entities.entry(entity).or_insert(ReplicatedEntity {
+            components: HashMap::new(),
+        });
+    }
+
+    // Sent to clients with the next update that reaches them.
+    pub fn despawn(&mut self, entity: NetEntity) {
+        self.entities.remove(&entity);
+    }
+
+    pub fn contains(&self, entity: NetEntity) -> bool {
+        self.entities.contains_key(&entity)
+    }
+
+    // Stores the component's current state, it is only marked changed if the encoding differs.
+    // Spawns the entity if needed.
+    pub fn set<C: Replicated>(&mut self, entity: NetEntity, component: &C) {
+        let mut writer = BitWriter::new();
+        component.encode(&mut writer);
+        self.set_raw(entity, Name::new(C::NAME), writer.finish());
+    }
+
+    pub fn set_raw(&mut self, entity: NetEntity, component: Name, data: Vec<u8>) {
+        let tick = self.tick;
+        let components = &mut self
+            .entities
+            .entry(entity)
+            .or_insert(ReplicatedEntity {
+                components: HashMap::new(),
+            })
+            .components;
+        match components.get_mut(&component) {
+            Some((_, current)) if *current == data => {}
+            _ => {
+                components.insert(component, (tick, data));
+            }
+        }
+    }
+
+    // Ticks advance once per `collect`, the tick the next messages will be stamped with.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn add_client(&mut self, client: ConnectionId) {
+        self.clients.entry(client).or_default();
+    }
+
+    pub fn remove_client(&mut self, client: ConnectionId) {
+        self.clients.remove(&client);
+    }
+
+    // Builds each client's messages for this tick and counts them as delivered. Call once per
+    // tick after the game updated the replicated state.
+    pub fn collect(&mut self) -> Vec<(ConnectionId, Vec<ReplicationMessage>)> {
+        let updates = self.updates();
+        self.tick += 1;
+        let mut out = Vec::with_capacity(updates.len());
+        for (client, messages, view) in updates {
+            self.clients.insert(client, view);
+            if !messages.is_empty() {
+                out.push((client, messages));
+            }
+        }
+        out
+    }
+
+    // Each client's messages for this tick, with what its view knows once they arrive. Views are
+    // left alone until the caller knows they did.
+    fn updates(&self) -> Vec<(ConnectionId, Vec<ReplicationMessage>, ClientView)> {
+        let mut out = Vec::with_capacity(self.clients.len());
+        for (&client, view) in &self.clients {
+            let mut messages = Vec::new();
+            let mut view = ClientView {
+                known: view.known.clone(),
+            };
+            view.known.retain(|&entity, _| {
+                let keep = self.entities.contains_key(&entity)
+                    && self.interest.is_relevant(client, entity);
+                if !keep {
+                    messages.push(ReplicationMessage::Despawn { entity });
+                }
+                keep
+            });
+            for (&entity, replicated) in &self.entities {
+                let sent = match view.known.get(&entity) {
+                    Some(&sent) => sent,
+                    None if self.interest.is_relevant(client, entity) => {
+                        messages.push(ReplicationMessage::Spawn { entity });
+                        0
+                    }
+                    None => continue,
+                };
+                for (&component, (changed, data)) in &replicated.components {
+                    if *changed > sent {
+                        messages.push(ReplicationMessage::Update {
+                            entity,
+                            component,
+                            data: data.clone(),
+                        });
+                    }
+                }
+                view.known.insert(entity, self.tick);
+            }
+            out.push((client, messages, view));
+        }
+        out
+    }
+
+    // Collects this tick's messages and sends them, packed into as few transport messages as fit.
+    pub fn send(&mut self, transport: &mut Transport) -> Result<(), NetError> {
+        self.send_with(|client, batch| transport.send(client, Channel::ReliableOrdered, batch))
+    }
+
+    fn send_with(
+        &mut self,
+        mut send: impl FnMut(ConnectionId, &[u8]) -> Result<(), NetError>,
+    ) -> Result<(), NetError> {
+        let tick = self.tick;
+        let updates = self.updates();
+        self.tick += 1;
+        'clients: for (client, messages, view) in updates {
+            for batch in pack(tick, &messages) {
+                match send(client, &batch) {
+                    Ok(()) => {}
+                    // Disconnected since, the transport reports it separately.
+                    Err(NetError::NotConnected) => continue 'clients,
+                    // Backed up. The client's view stays as it was, so whatever didn't make it
+                    // into the queue is sent again next tick.
+                    Err(NetError::SendQueueFull) => continue 'clients,
+                    Err(err) => return Err(err),
+                }
+            }
+            self.clients.insert(client, view);
+        }
+        Ok(())
+    }
+}
+
+// Messages too large for a transport message on their own are dropped with an error.
+fn pack(tick: u64, messages: &[ReplicationMessage]) -> Vec<Vec<u8>> {
+    let max_bits = MAX_MESSAGE_SIZE * 8;
+    let mut batches: Vec<Vec<&ReplicationMessage>> = vec![Vec::new()];
+    let mut batch_bits = BATCH_HEADER_BITS;
+    for message in messages {
+        let bits = message.encoded_bits();
+        if BATCH_HEADER_BITS + bits > max_bits {
+            error!("Replicated message too large, dropped: {:?}", message);
+            continue;
+        }
+        if batch_bits + bits > max_bits {
+            batches.push(Vec::new());
+            batch_bits = BATCH_HEADER_BITS;
+        }
+        batches.last_mut().unwrap().push(message);
+        batch_bits += bits;
+    }
+    batches
+        .into_iter()
+        .filter(|batch| !batch.is_empty())
+        .map(|batch| {
+            let mut writer = BitWriter::new();
+            writer.write_varint(tick);
+            writer.write_varint(batch.len() as u64);
+            for message in batch {
+                message.encode(&mut writer);
+            }
+            let mut bytes = vec![REPLICATION_MESSAGE];
+            bytes.extend(writer.finish());
+            bytes
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplicationEvent {
+    Spawned(NetEntity),
+    Changed(NetEntity, Name),
+    Despawned(NetEntity),
+}
+
+// The client's copy of the entities the server replicates to it.
+#[derive(Default)]
+pub struct ReplicationClient {
+    entities: HashMap<NetEntity, HashMap<Name, Vec<u8>>>,
+    events: VecDeque<ReplicationEvent>,
+    server_tick: u64,
+}
+
+impl ReplicationClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Applies a transport message, returns false if it isn't replication data.
+    pub fn receive(&mut self, data: &[u8]) -> bool {
+        if data.first() != Some(&REPLICATION_MESSAGE) {
+            return false;
+        }
+        let Some((tick, messages)) = ReplicationMessage::decode_all(data) else {
+            warn!("Dropped malformed replication message");
+            return true;
+        };
+        self.server_tick = tick;
+        for message in messages {
+            self.apply(message);
+        }
+        true
+    }
+
+    pub fn apply(&mut self, message: ReplicationMessage) {
+        match message {
+            // Sent again when the server couldn't queue everything it had for us at once.
+            ReplicationMessage::Spawn { entity } => {
+                if let Entry::Vacant(vacant) = self.entities.entry(entity) {
+                    vacant.insert(HashMap::new());
+                    self.events.push_back(ReplicationEvent::Spawned(entity));
+                }
+            }
+            ReplicationMessage::Update {
+                entity,
+                component,
+                data,
+            } => {
+                let Some(components) = self.entities.get_mut(&entity) else {
+                    warn!("Update for unknown entity {:?}", entity);
+                    return;
+                };
+                components.insert(component, data);
+                self.events
+                    .push_back(ReplicationEvent::Changed(entity, component));
+            }
+            ReplicationMessage::Despawn { entity } => {
+                if self.entities.remove(&entity).is_some() {
+                    self.events.push_back(ReplicationEvent::Despawned(entity));
+                }
+            }
+        }
+    }
+
+    // Server tick of the latest data received, the state of every entity is as of this tick.
+    pub fn server_tick(&self) -> u64 {
+        self.server_tick
+    }
+
+    pub fn poll_event(&mut self) -> Option<ReplicationEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = NetEntity> + '_ {
+        self.entities.keys().copied()
+    }
+
+    pub fn contains(&self, entity: NetEntity) -> bool {
+        self.entities.contains_key(&entity)
+    }
+
+    pub fn get<C: Replicated>(&self, entity: NetEntity) -> Option<C> {
+        C::decode(&mut BitReader::new(
+            self.get_raw(entity, Name::new(C::NAME))?,
+        ))
+    }
+
+    pub fn get_raw(&self, entity: NetEntity, component: Name) -> Option<&[u8]> {
+        self.entities
+            .get(&entity)?
+            .get(&component)
+            .map(Vec::as_slice)
+    }
+
+    // Entities that have a given component, e.g. for the game to find its players.
+    pub fn with_component<C: Replicated>(&self) -> HashSet<NetEntity> {
+        let name = Name::new(C::NAME);
+        self.entities
+            .iter()
+            .filter(|(_, components)| components.contains_key(&name))
+            .map(|(&entity, _)| entity)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Health(u32);
+
+    impl Replicated for Health {
+        const NAME: &'static str = "Health";
+
+        fn encode(&self, writer: &mut BitWriter) {
+            writer.write_varint(self.0 as u64);
+        }
+
+        fn decode(reader: &mut BitReader) -> Option<Self> {
+            Some(Health(reader.read_varint()? as u32))
+        }
+    }
+
+    struct OnlyEven;
+
+    impl Interest for OnlyEven {
+        fn is_relevant(&self, _client: ConnectionId, entity: NetEntity) -> bool {
+            entity.to_bits() & 1 == 0
+        }
+    }
+
+    fn sync(server: &mut ReplicationServer, client: &mut ReplicationClient) -> usize {
+        let mut count = 0;
+        let tick = server.tick();
+        for (_, messages) in server.collect() {
+            for batch in pack(tick, &messages) {
+                assert!(client.receive(&batch));
+            }
+            count += messages.len();
+        }
+        count
+    }
+
+    #[test]
+    fn only_changes_and_relevant_entities_are_sent() {
+        let (a, b) = (SnowflakeId::from_bits(2), SnowflakeId::from_bits(3));
+        let mut server = ReplicationServer::new(OnlyEven);
+        let mut client = ReplicationClient::new();
+        server.add_client(ConnectionId(1));
+        server.set(a, &Health(10));
+        server.set(b, &Health(20));
+
+        assert_eq!(sync(&mut server, &mut client), 2);
+        assert_eq!(client.get::<Health>(a), Some(Health(10)));
+        assert!(!client.contains(b));
+
+        server.set(a, &Health(10));
+        assert_eq!(sync(&mut server, &mut client), 0);
+        server.set(a, &Health(5));
+        assert_eq!(sync(&mut server, &mut client), 1);
+        assert_eq!(client.get::<Health>(a), Some(Health(5)));
+
+        server.despawn(a);
+        sync(&mut server, &mut client);
+        assert!(!client.contains(a));
+    }
+
+    #[test]
+    fn backed_up_sends_are_retried() {
+        let entity = SnowflakeId::from_bits(2);
+        let mut server = ReplicationServer::default();
+        let mut client = ReplicationClient::new();
+        server.add_client(ConnectionId(1));
+        server.set(entity, &Health(10));
+        server
+            .send_with(|_, _| Err(NetError::SendQueueFull))
+            .unwrap();
+        server
+            .send_with(|_, batch| {
+                assert!(client.receive(batch));
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(client.get::<Health>(entity), Some(Health(10)));
+        assert_eq!(client.poll_event(), Some(ReplicationEvent::Spawned(entity)));
+    }
+}
@@ -0,0 +1,411 @@
+//! Deterministic lockstep and rollback. Instead of replicating state, every peer runs the same
+//! deterministic simulation and only inputs are exchanged. A remote input that hasn't arrived yet
+//! is predicted by repeating the player's last known one. When the real input turns out to be
+//! different, the session loads the snapshot saved before that frame and simulates forward again
+//! with the corrected inputs. With `max_prediction` 0 nothing is predicted and the session waits
+//! for every input, which is plain lockstep.
+//!
+//! Inputs are sent unreliably, every message repeats the local inputs peers haven't acked yet. On
+//! a server that relays between clients, pass the rollback messages it receives to `relay`.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use super::{
+    bits::{BitReader, BitWriter},
+    Channel, ConnectionId, NetError, Transport, MAX_MESSAGE_SIZE,
+};
+use crate::metrics;
+
+// First byte of every transport message carrying inputs, see `REPLICATION_MESSAGE`.
+pub const ROLLBACK_MESSAGE: u8 = 0xf2;
+
+pub type Frame = u32;
+
+pub trait RollbackInput: Copy + Default + PartialEq {
+    fn encode(&self, writer: &mut BitWriter);
+
+    fn decode(reader: &mut BitReader) -> Option<Self>;
+}
+
+// The simulation being synchronized. `advance` must give the same result on every machine for the
+// same snapshot and inputs: no wall clock, unseeded randomness or iteration over hash maps.
+pub trait RollbackGame {
+    type Input: RollbackInput;
+    type Snapshot;
+
+    fn save(&self) -> Self::Snapshot;
+
+    fn load(&mut self, snapshot: &Self::Snapshot);
+
+    // Runs one frame, `inputs` is indexed by player.
+    fn advance(&mut self, inputs: &[Self::Input]);
+}
+
+// Must be the same on every peer.
+#[derive(Clone, Copy, Debug)]
+pub struct RollbackConfig {
+    pub players: usize,
+    // Local input is applied this many frames after it was added, which hides that much latency
+    // without any rollback.
+    pub input_delay: Frame,
+    // How many frames the session may run ahead of the inputs it has before it waits.
+    pub max_prediction: Frame,
+}
+
+impl Default for RollbackConfig {
+    fn default() -> Self {
+        Self {
+            players: 2,
+            input_delay: 2,
+            max_prediction: 8,
+        }
+    }
+}
+
+struct SavedFrame<G: RollbackGame> {
+    frame: Frame,
+    // State before the frame ran.
+    snapshot: G::Snapshot,
+    inputs: Vec<G::Input>,
+}
+
+pub struct RollbackSession<G: RollbackGame> {
+    config: RollbackConfig,
+    local: usize,
+    // Next frame to simulate.
+    frame: Frame,
+    // Inputs known for certain per player, plus the last one before `confirmed` to predict from.
+    inputs: Vec<BTreeMap<Frame, G::Input>>,
+    // Per player, the first frame whose input hasn't arrived.
+    confirmed: Vec<Frame>,
+    // Per player, how many of the local inputs they have.
+    acked: Vec<Frame>,
+    // Simulated frames that may still be rolled back, oldest first.
+    history: VecDeque<SavedFrame<G>>,
+    first_incorrect: Option<Frame>,
+}
+
+impl<G: RollbackGame> RollbackSession<G> {
+    // `local` is this peer's player index, agreed on by all peers beforehand.
+    pub fn new(config: RollbackConfig, local: usize) -> Self {
+        assert!(local < config.players, "Local player {local} out of range");
+        // Inputs of the frames before the delay are all defaults, so they count as confirmed.
+        Self {
+            config,
+            local,
+            frame: 0,
+            inputs: (0..config.players).map(|_| BTreeMap::new()).collect(),
+            confirmed: vec![config.input_delay; config.players],
+            acked: vec![config.input_delay; config.players],
+            history: VecDeque::new(),
+            first_incorrect: None,
+        }
+    }
+
+    pub fn frame(&self) -> Frame {
+        self.frame
+    }
+
+    pub fn local_player(&self) -> usize {
+        self.local
+    }
+
+    // Frames before this have their final inputs on this peer and won't change anymore.
+    pub fn confirmed_frame(&self) -> Frame {
+        self.confirmed.iter().copied().min().unwrap_or(self.frame)
+    }
+
+    // Sets the local input for the frame `input_delay` frames from now. Call once before each
+    // `advance`, frames it wasn't called for repeat the previous input.
+    pub fn add_local_input(&mut self, input: G::Input) {
+        let target = self.frame + self.config.input_delay;
+        let inputs = &mut self.inputs[self.local];
+        while self.confirmed[self.local] <= target {
+            inputs.insert(self.confirmed[self.local], input);
+            self.confirmed[self.local] += 1;
+        }
+    }
+
+    // Simulates the next frame, first re-simulating from the oldest frame a received input
+    // contradicts. Returns false without simulating if that would predict more than
+    // `max_prediction` frames, the caller should try again next tick.
+    pub fn advance(&mut self, game: &mut G) -> bool {
+        if self.frame >= self.confirmed_frame() + self.config.max_prediction {
+            return false;
+        }
+        if let Some(first) = self.first_incorrect.take() {
+            self.rollback(game, first);
+        }
+
+        let inputs = self.inputs_for(self.frame);
+        self.history.push_back(SavedFrame {
+            frame: self.frame,
+            snapshot: game.save(),
+            inputs: inputs.clone(),
+        });
+        game.advance(&inputs);
+        self.frame += 1;
+        self.prune();
+        true
+    }
+
+    fn rollback(&mut self, game: &mut G, first: Frame) {
+        let Some(start) = self.history.iter().position(|saved| saved.frame == first) else {
+            return;
+        };
+        game.load(&self.history[start].snapshot);
+        for index in start..self.history.len() {
+            let inputs = self.inputs_for(self.history[index].frame);
+            let saved = &mut self.history[index];
+            if index > start {
+                saved.snapshot = game.save();
+            }
+            game.advance(&inputs);
+            saved.inputs = inputs;
+        }
+        metrics::increment("net_rollbacks_total", 1);
+        metrics::increment(
+            "net_rollback_frames_total",
+            (self.history.len() - start) as u64,
+        );
+    }
+
+    fn inputs_for(&self, frame: Frame) -> Vec<G::Input> {
+        self.inputs
+            .iter()
+            .map(|inputs| {
+                inputs
+                    .range(..=frame)
+                    .next_back()
+                    .map(|(_, input)| *input)
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    // Forgets frames that can't be rolled back to anymore and inputs no one needs.
+    fn prune(&mut self) {
+        let confirmed = self.confirmed_frame();
+        while self
+            .history
+            .front()
+            .is_some_and(|saved| saved.frame < confirmed)
+        {
+            self.history.pop_front();
+        }
+        let acked = self.remote_players().map(|player| self.acked[player]).min();
+        let keep_from = confirmed.min(acked.unwrap_or(confirmed));
+        for inputs in &mut self.inputs {
+            let kept = inputs.split_off(&keep_from);
+            let previous = inputs.pop_last();
+            *inputs = kept;
+            if let Some((frame, input)) = previous {
+                inputs.insert(frame, input);
+            }
+        }
+    }
+
+    fn remote_players(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.config.players).filter(|&player| player != self.local)
+    }
+
+    // The message to send this tick: local inputs not acked by every peer, and acks for theirs.
+    pub fn outgoing(&self) -> Vec<u8> {
+        let local = self.confirmed[self.local];
+        let from = self
+            .remote_players()
+            .map(|player| self.acked[player])
+            .min()
+            .unwrap_or(local);
+        let mut writer = BitWriter::new();
+        writer.write_varint(self.local as u64);
+        for &confirmed in &self.confirmed {
+            writer.write_varint(confirmed as u64);
+        }
+        writer.write_varint(from as u64);
+        for frame in from..local {
+            // Whatever doesn't fit is sent once the first part is acked.
+            if writer.bit_len() > (MAX_MESSAGE_SIZE - 64) * 8 {
+                break;
+            }
+            writer.write_bool(true);
+            self.inputs[self.local][&frame].encode(&mut writer);
+        }
+        writer.write_bool(false);
+        let mut bytes = vec![ROLLBACK_MESSAGE];
+        bytes.extend(writer.finish());
+        bytes
+    }
+
+    pub fn send(&self, transport: &mut Transport) -> Result<(), NetError> {
+        transport.broadcast(Channel::Unreliable, &self.outgoing())
+    }
+
+    // Takes a message from `outgoing` of another peer. Returns false if it wasn't a rollback
+    // message, so callers can try other handlers.
+    pub fn receive(&mut self, data: &[u8]) -> bool {
+        let Some((&ROLLBACK_MESSAGE, bytes)) = data.split_first() else {
+            return false;
+        };
+        if self.read_message(&mut BitReader::new(bytes)).is_none() {
+            warn!("Dropped malformed rollback message");
+        }
+        true
+    }
+
+    fn read_message(&mut self, reader: &mut BitReader) -> Option<()> {
+        let player = reader.read_varint()? as usize;
+        if player >= self.config.players || player == self.local {
+            return None;
+        }
+        let mut acks = Vec::with_capacity(self.config.players);
+        for _ in 0..self.config.players {
+            acks.push(reader.read_varint()? as Frame);
+        }
+        self.acked[player] = self.acked[player].max(acks[self.local]);
+
+        let mut frame = reader.read_varint()? as Frame;
+        while reader.read_bool()? {
+            let input = G::Input::decode(reader)?;
+            if frame == self.confirmed[player] {
+                self.confirm(player, frame, input);
+            }
+            frame += 1;
+        }
+        Some(())
+    }
+
+    fn confirm(&mut self, player: usize, frame: Frame, input: G::Input) {
+        let predicted = self
+            .history
+            .iter()
+            .find(|saved| saved.frame == frame)
+            .map(|saved| saved.inputs[player]);
+        if predicted.is_some_and(|predicted| predicted != input) {
+            self.first_incorrect =
+                Some(self.first_incorrect.map_or(frame, |first| first.min(frame)));
+        }
+        self.inputs[player].insert(frame, input);
+        self.confirmed[player] = frame + 1;
+    }
+}
+
+// Forwards a rollback message a server received from one client to all others.
+pub fn relay(transport: &mut Transport, from: ConnectionId, data: &[u8]) -> Result<(), NetError> {
+    let connections: Vec<_> = transport.connections().filter(|&id| id != from).collect();
+    for connection in connections {
+        match transport.send(connection, Channel::Unreliable, data) {
+            Err(NetError::NotConnected) => {}
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Default, PartialEq, Debug)]
+    struct Input(u8);
+
+    impl RollbackInput for Input {
+        fn encode(&self, writer: &mut BitWriter) {
+            writer.write_bits(self.0 as u64, 8);
+        }
+
+        fn decode(reader: &mut BitReader) -> Option<Self> {
+            Some(Input(reader.read_bits(8)? as u8))
+        }
+    }
+
+    // Order dependent, so any input applied to the wrong frame shows.
+    #[derive(Default)]
+    struct Game(u64);
+
+    impl RollbackGame for Game {
+        type Input = Input;
+        type Snapshot = u64;
+
+        fn save(&self) -> u64 {
+            self.0
+        }
+
+        fn load(&mut self, snapshot: &u64) {
+            self.0 = *snapshot;
+        }
+
+        fn advance(&mut self, inputs: &[Input]) {
+            for (player, input) in inputs.iter().enumerate() {
+                self.0 = self
+                    .0
+                    .wrapping_mul(31)
+                    .wrapping_add(input.0 as u64 * (player as u64 + 1));
+            }
+        }
+    }
+
+    #[test]
+    fn late_inputs_roll_back() {
+        let config = RollbackConfig {
+            players: 2,
+            input_delay: 1,
+            max_prediction: 8,
+        };
+        let mut sessions = [
+            RollbackSession::<Game>::new(config, 0),
+            RollbackSession::<Game>::new(config, 1),
+        ];
+        let mut games = [Game::default(), Game::default()];
+        let input = |player: usize, frame: Frame| Input((frame * 7 + player as u32 * 3) as u8 % 5);
+
+        for frame in 0..60 {
+            for player in 0..2 {
+                sessions[player].add_local_input(input(player, frame));
+                assert!(sessions[player].advance(&mut games[player]));
+            }
+            let to_second = sessions[0].outgoing();
+            sessions[1].receive(&to_second);
+            // The second player's inputs arrive in bursts and are mispredicted in between.
+            if frame % 4 == 0 {
+                let to_first = sessions[1].outgoing();
+                sessions[0].receive(&to_first);
+            }
+        }
+        let to_first = sessions[1].outgoing();
+        sessions[0].receive(&to_first);
+        for player in 0..2 {
+            sessions[player].add_local_input(input(player, 60));
+            assert!(sessions[player].advance(&mut games[player]));
+        }
+
+        let mut expected = Game::default();
+        for frame in 0..61 {
+            let inputs: Vec<_> = (0..2)
+                .map(|player| match frame {
+                    0 => Input::default(),
+                    _ => input(player, frame - 1),
+                })
+                .collect();
+            expected.advance(&inputs);
+        }
+        assert_eq!(games[0].0, expected.0);
+        assert_eq!(games[1].0, expected.0);
+    }
+
+    #[test]
+    fn lockstep_waits_for_inputs() {
+        let config = RollbackConfig {
+            players: 2,
+            input_delay: 1,
+            max_prediction: 0,
+        };
+        let mut session = RollbackSession::<Game>::new(config, 0);
+        let mut game = Game::default();
+        session.add_local_input(Input(1));
+        // Frame 0 uses the delay's default inputs.
+        assert!(session.advance(&mut game));
+        session.add_local_input(Input(1));
+        assert!(!session.advance(&mut game));
+    }
+}
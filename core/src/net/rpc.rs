@@ -0,0 +1,241 @@
+//! Typed messages between clients and servers. Message types are registered on both ends with the
+//! direction they travel in, and sent and received as values instead of bytes. Every type has a
+//! name and a version; the registry's fingerprint over all of them is the protocol version, so a
+//! client built with different messages is denied at connect time instead of misreading them.
+
+use std::{
+    any::{Any, TypeId},
+    collections::{HashMap, VecDeque},
+};
+
+use super::{
+    bits::{BitReader, BitWriter},
+    Channel, ConnectionId,
+};
+use crate::identifier::Name;
+
+// First byte of every transport message carrying an RPC, see `REPLICATION_MESSAGE`.
+pub const RPC_MESSAGE: u8 = 0xf1;
+
+pub trait NetMessage: Sized + Send + 'static {
+    // Unique among registered messages.
+    const NAME: &'static str;
+    // Bump when the encoding changes.
+    const VERSION: u16 = 1;
+    const CHANNEL: Channel = Channel::ReliableOrdered;
+
+    fn encode(&self, writer: &mut BitWriter);
+
+    fn decode(reader: &mut BitReader) -> Option<Self>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    // Commands.
+    ClientToServer,
+    // Events.
+    ServerToClient,
+}
+
+type AnyMessage = Box<dyn Any + Send>;
+type Decoder = fn(&mut BitReader) -> Option<AnyMessage>;
+
+#[derive(Clone)]
+struct Registration {
+    name: &'static str,
+    version: u16,
+    direction: Direction,
+    type_id: TypeId,
+    decode: Decoder,
+}
+
+// Wire id of a message type, covering its version so old and new encodings never mix.
+fn message_id<M: NetMessage>() -> u64 {
+    Name::new(&format!("{}@{}", M::NAME, M::VERSION)).hash()
+}
+
+// Must list the same messages on the client and the server, usually built by code both share.
+#[derive(Clone, Default)]
+pub struct RpcRegistry {
+    messages: HashMap<u64, Registration>,
+}
+
+impl RpcRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<M: NetMessage>(&mut self, direction: Direction) -> &mut Self {
+        let registration = Registration {
+            name: M::NAME,
+            version: M::VERSION,
+            direction,
+            type_id: TypeId::of::<M>(),
+            decode: |reader| Some(Box::new(M::decode(reader)?)),
+        };
+        if let Some(existing) = self.messages.insert(message_id::<M>(), registration) {
+            panic!("Net message {} registered twice", existing.name);
+        }
+        self
+    }
+
+    pub fn command<M: NetMessage>(&mut self) -> &mut Self {
+        self.register::<M>(Direction::ClientToServer)
+    }
+
+    pub fn event<M: NetMessage>(&mut self) -> &mut Self {
+        self.register::<M>(Direction::ServerToClient)
+    }
+
+    // Changes whenever a message is added, removed, renamed, re-versioned or changes direction.
+    pub fn fingerprint(&self) -> u64 {
+        let mut messages: Vec<_> = self
+            .messages
+            .values()
+            .map(|message| {
+                format!(
+                    "{}@{}:{:?}",
+                    message.name, message.version, message.direction
+                )
+            })
+            .collect();
+        messages.sort();
+        Name::new(&messages.join(",")).hash()
+    }
+
+    pub fn encode<M: NetMessage>(&self, message: &M) -> Vec<u8> {
+        debug_assert!(
+            self.messages.contains_key(&message_id::<M>()),
+            "Net message {} isn't registered",
+            M::NAME
+        );
+        let mut writer = BitWriter::new();
+        writer.write_bits(message_id::<M>(), 64);
+        message.encode(&mut writer);
+        let mut bytes = vec![RPC_MESSAGE];
+        bytes.extend(writer.finish());
+        bytes
+    }
+
+    // Decodes a transport message into `inbox`. Returns false if it isn't an RPC, RPCs that are
+    // unknown, malformed or sent in the wrong direction are dropped with a warning.
+    pub fn receive(
+        &self,
+        from: ConnectionId,
+        data: &[u8],
+        direction: Direction,
+        inbox: &mut RpcInbox,
+    ) -> bool {
+        let Some((&RPC_MESSAGE, bytes)) = data.split_first() else {
+            return false;
+        };
+        let mut reader = BitReader::new(bytes);
+        let Some(registration) = reader.read_bits(64).and_then(|id| self.messages.get(&id)) else {
+            warn!("Dropped unknown RPC from {}", from);
+            return true;
+        };
+        if registration.direction != direction {
+            warn!(
+                "Dropped {} from {}, wrong direction",
+                registration.name, from
+            );
+            return true;
+        }
+        match (registration.decode)(&mut reader) {
+            Some(message) => inbox
+                .queues
+                .entry(registration.type_id)
+                .or_default()
+                .push_back((from, message)),
+            None => warn!("Dropped malformed {} from {}", registration.name, from),
+        }
+        true
+    }
+}
+
+// Received messages by type, until the game takes them.
+#[derive(Default)]
+pub struct RpcInbox {
+    queues: HashMap<TypeId, VecDeque<(ConnectionId, AnyMessage)>>,
+}
+
+impl RpcInbox {
+    // Every received `M` in arrival order, with the connection it came from.
+    pub fn drain<M: NetMessage>(&mut self) -> Vec<(ConnectionId, M)> {
+        let Some(queue) = self.queues.get_mut(&TypeId::of::<M>()) else {
+            return Vec::new();
+        };
+        queue
+            .drain(..)
+            .filter_map(|(from, message)| Some((from, *message.downcast::<M>().ok()?)))
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.queues.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Chat(String);
+
+    impl NetMessage for Chat {
+        const NAME: &'static str = "Chat";
+
+        fn encode(&self, writer: &mut BitWriter) {
+            writer.write_bytes(self.0.as_bytes());
+        }
+
+        fn decode(reader: &mut BitReader) -> Option<Self> {
+            String::from_utf8(reader.read_bytes()?).ok().map(Chat)
+        }
+    }
+
+    struct ChatV2;
+
+    impl NetMessage for ChatV2 {
+        const NAME: &'static str = "Chat";
+        const VERSION: u16 = 2;
+
+        fn encode(&self, _writer: &mut BitWriter) {}
+
+        fn decode(_reader: &mut BitReader) -> Option<Self> {
+            Some(ChatV2)
+        }
+    }
+
+    #[test]
+    fn typed_round_trip_and_versions() {
+        let mut registry = RpcRegistry::new();
+        registry.command::<Chat>();
+        let mut inbox = RpcInbox::default();
+        let bytes = registry.encode(&Chat("hello".to_owned()));
+
+        assert!(registry.receive(
+            ConnectionId(1),
+            &bytes,
+            Direction::ServerToClient,
+            &mut inbox
+        ));
+        assert!(inbox.drain::<Chat>().is_empty());
+        registry.receive(
+            ConnectionId(1),
+            &bytes,
+            Direction::ClientToServer,
+            &mut inbox,
+        );
+        let received = inbox.drain::<Chat>();
+        assert_eq!(received.len(), 1);
+        assert_eq!(
+            (received[0].0, received[0].1 .0.as_str()),
+            (ConnectionId(1), "hello")
+        );
+
+        let mut newer = RpcRegistry::new();
+        newer.command::<ChatV2>();
+        assert_ne!(registry.fingerprint(), newer.fingerprint());
+    }
+}
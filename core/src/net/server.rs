@@ -0,0 +1,249 @@
+#[cfg(target_arch = "wasm32")]
+use std::io;
+use std::{collections::VecDeque, net::SocketAddr};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use super::{
+    clock::{ServerClock, ServerTime},
+    replication::ReplicationServer,
+    rpc::{Direction, NetMessage, RpcInbox, RpcRegistry},
+    Channel, ConnectionId, ConnectionStats, NetConditions, NetConfig, NetError, NetEvent,
+    Transport,
+};
+use super::{conditions, stats};
+use crate::{
+    cvars::{self, Cvars},
+    engine::{Engine, Plugin, Resources},
+    sim,
+};
+
+// The authoritative side of a session: accepts clients, replicates the world to them and collects
+// what they send. Inserted as a resource by `NetServerPlugin`. Registered commands are decoded
+// into typed queues, other messages come out of `poll_event`.
+pub struct NetServer {
+    transport: Transport,
+    replication: ReplicationServer,
+    rpc: RpcRegistry,
+    inbox: RpcInbox,
+    clock: ServerClock,
+    clients: Vec<ConnectionId>,
+    events: VecDeque<NetEvent>,
+}
+
+impl NetServer {
+    // Clients have to register the same messages in `rpc` to connect.
+    // With a `websocket` address browser clients can join too.
+    pub fn bind(
+        addr: SocketAddr,
+        websocket: Option<SocketAddr>,
+        mut config: NetConfig,
+        rpc: RpcRegistry,
+    ) -> Result<Self, NetError> {
+        config.protocol_version = rpc.fingerprint();
+        let transport = match websocket {
+            #[cfg(not(target_arch = "wasm32"))]
+            Some(websocket) => Transport::server_with_websocket(addr, websocket, config)?,
+            #[cfg(target_arch = "wasm32")]
+            Some(_) => return Err(io::Error::from(io::ErrorKind::Unsupported).into()),
+            None => Transport::server(addr, config)?,
+        };
+        Ok(Self {
+            transport,
+            replication: ReplicationServer::default(),
+            rpc,
+            inbox: RpcInbox::default(),
+            clock: ServerClock::new(Instant::now()),
+            clients: Vec::new(),
+            events: VecDeque::new(),
+        })
+    }
+
+    pub fn clients(&self) -> &[ConnectionId] {
+        &self.clients
+    }
+
+    pub fn replication(&mut self) -> &mut ReplicationServer {
+        &mut self.replication
+    }
+
+    pub fn send(
+        &mut self,
+        client: ConnectionId,
+        channel: Channel,
+        data: &[u8],
+    ) -> Result<(), NetError> {
+        self.transport.send(client, channel, data)
+    }
+
+    pub fn broadcast(&mut self, channel: Channel, data: &[u8]) -> Result<(), NetError> {
+        self.transport.broadcast(channel, data)
+    }
+
+    pub fn send_message<M: NetMessage>(
+        &mut self,
+        client: ConnectionId,
+        message: &M,
+    ) -> Result<(), NetError> {
+        let data = self.rpc.encode(message);
+        self.transport.send(client, M::CHANNEL, &data)
+    }
+
+    pub fn broadcast_message<M: NetMessage>(&mut self, message: &M) -> Result<(), NetError> {
+        let data = self.rpc.encode(message);
+        self.transport.broadcast(M::CHANNEL, &data)
+    }
+
+    // Commands of type `M` received since the last call.
+    pub fn receive<M: NetMessage>(&mut self) -> Vec<(ConnectionId, M)> {
+        self.inbox.drain()
+    }
+
+    pub fn server_time(&self, now: Instant) -> ServerTime {
+        self.clock.now(now)
+    }
+
+    pub fn stats(&self, client: ConnectionId) -> Option<ConnectionStats> {
+        self.transport.stats(client)
+    }
+
+    pub fn set_conditions(&mut self, conditions: NetConditions) {
+        if self.transport.conditions() != conditions {
+            info!("Simulating network conditions {:?}", conditions);
+            self.transport.set_conditions(conditions);
+        }
+    }
+
+    pub fn kick(&mut self, client: ConnectionId) {
+        self.transport.disconnect(client);
+        self.handle_events(Instant::now());
+    }
+
+    // Connects, disconnects and messages since the last call, for the game's fixed_update.
+    pub fn poll_event(&mut self) -> Option<NetEvent> {
+        self.events.pop_front()
+    }
+
+    // Sends the replicated state set during the last tick, then exchanges packets.
+    pub fn update(&mut self, now: Instant) {
+        if let Err(err) = self.replication.send(&mut self.transport) {
+            warn!("Replication failed: {}", err);
+        }
+        if let Err(err) = self.transport.update(now) {
+            warn!("Network update failed: {}", err);
+        }
+        self.handle_events(now);
+        stats::publish_metrics(self.transport.all_stats().map(|(_, stats)| stats));
+    }
+
+    fn handle_events(&mut self, now: Instant) {
+        while let Some(event) = self.transport.poll_event() {
+            match &event {
+                NetEvent::Connected(client) => {
+                    info!("Client {} connected", client);
+                    self.clients.push(*client);
+                    self.replication.add_client(*client);
+                }
+                NetEvent::Disconnected(client, reason) => {
+                    info!("Client {} disconnected: {:?}", client, reason);
+                    self.clients.retain(|connected| connected != client);
+                    self.replication.remove_client(*client);
+                }
+                NetEvent::Message {
+                    connection, data, ..
+                } if self
+                    .clock
+                    .receive(&mut self.transport, *connection, data, now) =>
+                {
+                    continue
+                }
+                NetEvent::Message {
+                    connection, data, ..
+                } if self.rpc.receive(
+                    *connection,
+                    data,
+                    Direction::ClientToServer,
+                    &mut self.inbox,
+                ) =>
+                {
+                    continue
+                }
+                NetEvent::Message { .. } => {}
+            }
+            self.events.push_back(event);
+        }
+    }
+}
+
+pub struct NetServerPlugin {
+    pub addr: SocketAddr,
+    // Where browser clients connect, None to only accept UDP clients.
+    pub websocket_addr: Option<SocketAddr>,
+    pub config: NetConfig,
+    pub rpc: RpcRegistry,
+}
+
+impl NetServerPlugin {
+    // Listens on every interface.
+    pub fn new(port: u16) -> Self {
+        Self {
+            addr: ([0, 0, 0, 0], port).into(),
+            websocket_addr: None,
+            config: NetConfig::default(),
+            rpc: RpcRegistry::default(),
+        }
+    }
+
+    // Also accepts WebSocket clients on `port`, every interface.
+    pub fn with_websocket(mut self, port: u16) -> Self {
+        self.websocket_addr = Some(([0, 0, 0, 0], port).into());
+        self
+    }
+
+    pub fn with_messages(mut self, rpc: RpcRegistry) -> Self {
+        self.rpc = rpc;
+        self
+    }
+}
+
+impl Plugin for NetServerPlugin {
+    fn build(&self, engine: &mut Engine) {
+        match NetServer::bind(
+            self.addr,
+            self.websocket_addr,
+            self.config.clone(),
+            self.rpc.clone(),
+        ) {
+            Ok(server) => {
+                info!("Listening for clients on {}", self.addr);
+                if let Some(websocket_addr) = self.websocket_addr {
+                    info!("Listening for WebSocket clients on {}", websocket_addr);
+                }
+                engine.insert_resource(server);
+            }
+            Err(err) => {
+                // A server that can't listen has nothing to do, and `run_headless` would
+                // otherwise spin until someone kills it.
+                error!("Failed to listen on {}: {}", self.addr, err);
+                sim::shutdown();
+                return;
+            }
+        }
+        conditions::register_cvars(cvars::cvars(engine));
+        engine.insert_resource(ServerTime::default());
+        engine.add_system(|resources: &mut Resources| {
+            let conditions = resources.get::<Cvars>().map(conditions::from_cvars);
+            let Some(server) = resources.get_mut::<NetServer>() else {
+                return;
+            };
+            let now = Instant::now();
+            server.set_conditions(conditions.unwrap_or_default());
+            server.update(now);
+            let time = server.server_time(now);
+            resources.insert(time);
+        });
+    }
+}
@@ -0,0 +1,545 @@
+//! Lobbies and sessions, independent of how players end up connected. A `Session` tracks the lobby
+//! the local player is in and who else is there, while a `SessionBackend` does the actual
+//! creating, finding and joining, e.g. through a platform matchmaking service, a web API or
+//! `LocalSessions` in process. Requests complete asynchronously and report back as
+//! `SessionEvent`s, so slow backends never block a tick.
+//!
+//! When the host leaves, the backend picks a new one and the session calls the host migration
+//! hook, where the game can start a server on the new host and reconnect everyone else to it.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use crate::engine::{Engine, Plugin, Resources};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LobbyId(pub u64);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PlayerId(pub u64);
+
+impl fmt::Display for LobbyId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "lobby#{}", self.0)
+    }
+}
+
+impl fmt::Display for PlayerId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "player#{}", self.0)
+    }
+}
+
+// Free form key value pairs, e.g. a map name or a player's team. Backends may limit their size.
+pub type Metadata = BTreeMap<String, String>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerInfo {
+    pub id: PlayerId,
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LobbySettings {
+    pub name: String,
+    // Counts the host, so anything below 1 can't even be created.
+    pub max_players: usize,
+    // Private lobbies can only be joined by id, they don't show up in `find_lobbies`.
+    pub private: bool,
+    pub metadata: Metadata,
+}
+
+impl Default for LobbySettings {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            max_players: 8,
+            private: false,
+            metadata: Metadata::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LobbyInfo {
+    pub id: LobbyId,
+    pub settings: LobbySettings,
+    pub host: PlayerId,
+    // In join order.
+    pub players: Vec<PlayerInfo>,
+}
+
+impl LobbyInfo {
+    pub fn player(&self, id: PlayerId) -> Option<&PlayerInfo> {
+        self.players.iter().find(|player| player.id == id)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.players.len() >= self.settings.max_players
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionError {
+    NotInLobby,
+    AlreadyInLobby,
+    LobbyNotFound,
+    LobbyFull,
+    Backend(String),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionError::NotInLobby => write!(f, "not in a lobby"),
+            SessionError::AlreadyInLobby => write!(f, "already in a lobby"),
+            SessionError::LobbyNotFound => write!(f, "lobby not found"),
+            SessionError::LobbyFull => write!(f, "lobby is full"),
+            SessionError::Backend(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionEvent {
+    // The local player created or joined a lobby, with everyone already in it.
+    Joined(LobbyInfo),
+    Left(LobbyId),
+    PlayerJoined(PlayerInfo),
+    PlayerLeft(PlayerId),
+    PlayerUpdated(PlayerInfo),
+    HostChanged { previous: PlayerId, host: PlayerId },
+    LobbiesFound(Vec<LobbyInfo>),
+    // A request failed, the session is unchanged.
+    Failed(SessionError),
+}
+
+// A matchmaking service. Requests are answered with events from `poll_event`, which may come in
+// later ticks. Events about the lobby only concern the one the local player is in.
+pub trait SessionBackend: Send {
+    fn create_lobby(&mut self, settings: LobbySettings, player: &PlayerInfo);
+
+    fn join_lobby(&mut self, lobby: LobbyId, player: &PlayerInfo);
+
+    fn leave_lobby(&mut self);
+
+    // Lobbies that can be joined, answered with `SessionEvent::LobbiesFound`.
+    fn find_lobbies(&mut self, player: &PlayerInfo);
+
+    // Changes the local player's metadata, everyone in the lobby sees `PlayerUpdated`.
+    fn set_player_metadata(&mut self, metadata: Metadata);
+
+    fn poll_event(&mut self) -> Option<SessionEvent>;
+
+    // Called once per tick before polling, for backends that need to pump a connection.
+    fn update(&mut self) {}
+}
+
+type HostMigrationHook = Box<dyn FnMut(&LobbyInfo, PlayerId) + Send>;
+
+// The local player's view of matchmaking, inserted as a resource and updated by `SessionPlugin`.
+pub struct Session {
+    backend: Box<dyn SessionBackend>,
+    player: PlayerInfo,
+    lobby: Option<LobbyInfo>,
+    on_host_migration: Option<HostMigrationHook>,
+    events: VecDeque<SessionEvent>,
+}
+
+impl Session {
+    // `player.id` must be unique within the backend, usually an account id it handed out.
+    pub fn new(backend: impl SessionBackend + 'static, player: PlayerInfo) -> Self {
+        Self {
+            backend: Box::new(backend),
+            player,
+            lobby: None,
+            on_host_migration: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn local_player(&self) -> &PlayerInfo {
+        &self.player
+    }
+
+    pub fn lobby(&self) -> Option<&LobbyInfo> {
+        self.lobby.as_ref()
+    }
+
+    pub fn is_host(&self) -> bool {
+        self.lobby
+            .as_ref()
+            .is_some_and(|lobby| lobby.host == self.player.id)
+    }
+
+    // Called with the lobby and the new host when the host changes, after the lobby was updated.
+    pub fn on_host_migration(&mut self, hook: impl FnMut(&LobbyInfo, PlayerId) + Send + 'static) {
+        self.on_host_migration = Some(Box::new(hook));
+    }
+
+    pub fn create_lobby(&mut self, settings: LobbySettings) {
+        if self.lobby.is_some() {
+            self.fail(SessionError::AlreadyInLobby);
+            return;
+        }
+        self.backend.create_lobby(settings, &self.player);
+    }
+
+    pub fn join_lobby(&mut self, lobby: LobbyId) {
+        if self.lobby.is_some() {
+            self.fail(SessionError::AlreadyInLobby);
+            return;
+        }
+        self.backend.join_lobby(lobby, &self.player);
+    }
+
+    pub fn leave_lobby(&mut self) {
+        if self.lobby.is_none() {
+            self.fail(SessionError::NotInLobby);
+            return;
+        }
+        self.backend.leave_lobby();
+    }
+
+    pub fn find_lobbies(&mut self) {
+        self.backend.find_lobbies(&self.player);
+    }
+
+    pub fn set_player_metadata(&mut self, key: &str, value: &str) {
+        self.player
+            .metadata
+            .insert(key.to_owned(), value.to_owned());
+        self.backend
+            .set_player_metadata(self.player.metadata.clone());
+    }
+
+    // Lobby changes since the last call, for the game's fixed_update.
+    pub fn poll_event(&mut self) -> Option<SessionEvent> {
+        self.events.pop_front()
+    }
+
+    // Applies the backend's events to the lobby state and queues them for `poll_event`.
+    pub fn update(&mut self) {
+        self.backend.update();
+        while let Some(event) = self.backend.poll_event() {
+            self.apply(&event);
+            self.events.push_back(event);
+        }
+    }
+
+    fn apply(&mut self, event: &SessionEvent) {
+        match event {
+            SessionEvent::Joined(lobby) => {
+                info!("Joined {} hosted by {}", lobby.id, lobby.host);
+                self.lobby = Some(lobby.clone());
+            }
+            SessionEvent::Left(lobby) => {
+                info!("Left {}", lobby);
+                self.lobby = None;
+            }
+            SessionEvent::Failed(err) => warn!("Session request failed: {}", err),
+            _ => {}
+        }
+        let Some(lobby) = self.lobby.as_mut() else {
+            return;
+        };
+        match event {
+            SessionEvent::PlayerJoined(player) => lobby.players.push(player.clone()),
+            SessionEvent::PlayerLeft(id) => lobby.players.retain(|player| player.id != *id),
+            SessionEvent::PlayerUpdated(updated) => {
+                if let Some(player) = lobby.players.iter_mut().find(|p| p.id == updated.id) {
+                    *player = updated.clone();
+                }
+            }
+            SessionEvent::HostChanged { previous, host } => {
+                info!("Host of {} moved from {} to {}", lobby.id, previous, host);
+                lobby.host = *host;
+                if let Some(hook) = self.on_host_migration.as_mut() {
+                    hook(lobby, *host);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn fail(&mut self, err: SessionError) {
+        warn!("Session request failed: {}", err);
+        self.events.push_back(SessionEvent::Failed(err));
+    }
+}
+
+// Updates the `Session` resource every tick, insert one to use it.
+pub struct SessionPlugin;
+
+impl Plugin for SessionPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine.add_system(|resources: &mut Resources| {
+            if let Some(session) = resources.get_mut::<Session>() {
+                session.update();
+            }
+        });
+    }
+}
+
+#[derive(Default)]
+struct Directory {
+    next_lobby: u64,
+    lobbies: BTreeMap<LobbyId, LobbyInfo>,
+    // Event queues of every backend, by the player using it.
+    inboxes: HashMap<PlayerId, VecDeque<SessionEvent>>,
+}
+
+impl Directory {
+    fn send(&mut self, player: PlayerId, event: SessionEvent) {
+        self.inboxes.entry(player).or_default().push_back(event);
+    }
+
+    fn broadcast(&mut self, lobby: LobbyId, event: SessionEvent) {
+        let players: Vec<_> = self.lobbies[&lobby]
+            .players
+            .iter()
+            .map(|player| player.id)
+            .collect();
+        for player in players {
+            self.send(player, event.clone());
+        }
+    }
+
+    // Returns false if the player couldn't join, after telling them why.
+    fn join(&mut self, lobby: LobbyId, player: &PlayerInfo) -> bool {
+        let Some(info) = self.lobbies.get(&lobby) else {
+            self.send(player.id, SessionEvent::Failed(SessionError::LobbyNotFound));
+            return false;
+        };
+        if info.is_full() {
+            self.send(player.id, SessionEvent::Failed(SessionError::LobbyFull));
+            return false;
+        }
+        self.broadcast(lobby, SessionEvent::PlayerJoined(player.clone()));
+        let info = self.lobbies.get_mut(&lobby).unwrap();
+        info.players.push(player.clone());
+        let info = info.clone();
+        self.send(player.id, SessionEvent::Joined(info));
+        true
+    }
+}
+
+// An in process backend, for offline play, tests and several local players. Clones share the
+// same lobbies, give each player one.
+#[derive(Clone)]
+pub struct LocalSessions {
+    directory: Arc<Mutex<Directory>>,
+    player: Option<PlayerId>,
+    lobby: Option<LobbyId>,
+}
+
+impl Default for LocalSessions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LocalSessions {
+    pub fn new() -> Self {
+        Self {
+            directory: Arc::default(),
+            player: None,
+            lobby: None,
+        }
+    }
+}
+
+impl SessionBackend for LocalSessions {
+    fn create_lobby(&mut self, settings: LobbySettings, player: &PlayerInfo) {
+        self.player = Some(player.id);
+        let mut directory = self.directory.lock().unwrap();
+        directory.next_lobby += 1;
+        let id = LobbyId(directory.next_lobby);
+        directory.lobbies.insert(
+            id,
+            LobbyInfo {
+                id,
+                settings,
+                host: player.id,
+                players: Vec::new(),
+            },
+        );
+        if directory.join(id, player) {
+            self.lobby = Some(id);
+        } else {
+            // Nobody could ever be in it.
+            directory.lobbies.remove(&id);
+        }
+    }
+
+    fn join_lobby(&mut self, lobby: LobbyId, player: &PlayerInfo) {
+        self.player = Some(player.id);
+        let mut directory = self.directory.lock().unwrap();
+        if directory.join(lobby, player) {
+            self.lobby = Some(lobby);
+        }
+    }
+
+    fn leave_lobby(&mut self) {
+        let (Some(player), Some(id)) = (self.player, self.lobby.take()) else {
+            return;
+        };
+        let mut directory = self.directory.lock().unwrap();
+        directory.send(player, SessionEvent::Left(id));
+        let Some(lobby) = directory.lobbies.get_mut(&id) else {
+            return;
+        };
+        lobby.players.retain(|p| p.id != player);
+        // The oldest remaining player takes over, the lobby closes with the last one.
+        let Some(next) = lobby.players.first().map(|p| p.id) else {
+            directory.lobbies.remove(&id);
+            return;
+        };
+        let migrated = lobby.host == player;
+        if migrated {
+            lobby.host = next;
+        }
+        directory.broadcast(id, SessionEvent::PlayerLeft(player));
+        if migrated {
+            directory.broadcast(
+                id,
+                SessionEvent::HostChanged {
+                    previous: player,
+                    host: next,
+                },
+            );
+        }
+    }
+
+    fn find_lobbies(&mut self, player: &PlayerInfo) {
+        let player = *self.player.insert(player.id);
+        let mut directory = self.directory.lock().unwrap();
+        let lobbies = directory
+            .lobbies
+            .values()
+            .filter(|lobby| !lobby.settings.private && !lobby.is_full())
+            .cloned()
+            .collect();
+        directory.send(player, SessionEvent::LobbiesFound(lobbies));
+    }
+
+    fn set_player_metadata(&mut self, metadata: Metadata) {
+        let (Some(player), Some(id)) = (self.player, self.lobby) else {
+            return;
+        };
+        let mut directory = self.directory.lock().unwrap();
+        let Some(info) = directory
+            .lobbies
+            .get_mut(&id)
+            .and_then(|lobby| lobby.players.iter_mut().find(|p| p.id == player))
+        else {
+            return;
+        };
+        info.metadata = metadata;
+        let info = info.clone();
+        directory.broadcast(id, SessionEvent::PlayerUpdated(info));
+    }
+
+    fn poll_event(&mut self) -> Option<SessionEvent> {
+        let player = self.player?;
+        self.directory
+            .lock()
+            .unwrap()
+            .inboxes
+            .get_mut(&player)?
+            .pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(id: u64, name: &str) -> PlayerInfo {
+        PlayerInfo {
+            id: PlayerId(id),
+            name: name.to_owned(),
+            metadata: Metadata::new(),
+        }
+    }
+
+    #[test]
+    fn host_migrates_to_oldest_player() {
+        let sessions = LocalSessions::new();
+        let mut host = Session::new(sessions.clone(), player(1, "host"));
+        let mut second = Session::new(sessions.clone(), player(2, "second"));
+        let mut third = Session::new(sessions, player(3, "third"));
+
+        host.create_lobby(LobbySettings {
+            name: "test".to_owned(),
+            max_players: 3,
+            ..Default::default()
+        });
+        host.update();
+        let lobby = host.lobby().unwrap().id;
+        assert!(host.is_host());
+
+        second.find_lobbies();
+        second.update();
+        assert!(matches!(
+            second.poll_event(),
+            Some(SessionEvent::LobbiesFound(found)) if found.len() == 1
+        ));
+        second.join_lobby(lobby);
+        third.join_lobby(lobby);
+        second.set_player_metadata("team", "red");
+        for session in [&mut host, &mut second, &mut third] {
+            session.update();
+        }
+        assert_eq!(host.lobby().unwrap().players.len(), 3);
+        assert_eq!(
+            third.lobby().unwrap().player(PlayerId(2)).unwrap().metadata["team"],
+            "red"
+        );
+
+        let migrated = Arc::new(Mutex::new(None));
+        let hook = migrated.clone();
+        third.on_host_migration(move |_, host| *hook.lock().unwrap() = Some(host));
+        host.leave_lobby();
+        for session in [&mut host, &mut second, &mut third] {
+            session.update();
+        }
+        assert!(host.lobby().is_none());
+        assert!(second.is_host());
+        assert_eq!(third.lobby().unwrap().players.len(), 2);
+        assert_eq!(*migrated.lock().unwrap(), Some(PlayerId(2)));
+    }
+
+    #[test]
+    fn lobbies_the_host_cant_join_are_dropped() {
+        let sessions = LocalSessions::new();
+        let mut host = Session::new(sessions.clone(), player(1, "host"));
+        let mut other = Session::new(sessions, player(2, "other"));
+        host.create_lobby(LobbySettings {
+            max_players: 0,
+            ..Default::default()
+        });
+        host.update();
+        assert!(host.lobby().is_none());
+        assert_eq!(
+            host.poll_event(),
+            Some(SessionEvent::Failed(SessionError::LobbyFull))
+        );
+
+        other.find_lobbies();
+        other.update();
+        assert_eq!(
+            other.poll_event(),
+            Some(SessionEvent::LobbiesFound(Vec::new()))
+        );
+        other.create_lobby(LobbySettings::default());
+        other.update();
+        assert!(other.is_host());
+    }
+}
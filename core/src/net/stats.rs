@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use crate::metrics;
+
+// Weight of a new sample in the smoothed values.
+const SMOOTHING: f64 = 0.1;
+const LOSS_SMOOTHING: f64 = 0.01;
+const BANDWIDTH_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConnectionStats {
+    // Smoothed round trip time from packet acks.
+    pub rtt: Duration,
+    // Smoothed deviation of the round trip time.
+    pub jitter: Duration,
+    // Fraction of packets never acked, smoothed over roughly the last hundred.
+    pub packet_loss: f64,
+    // Over the last second, including packet headers.
+    pub sent_bytes_per_second: f64,
+    pub received_bytes_per_second: f64,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    pub packets_lost: u64,
+}
+
+pub struct StatsTracker {
+    stats: ConnectionStats,
+    has_rtt: bool,
+    window_start: Instant,
+    window_sent: usize,
+    window_received: usize,
+}
+
+impl StatsTracker {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            stats: ConnectionStats::default(),
+            has_rtt: false,
+            window_start: now,
+            window_sent: 0,
+            window_received: 0,
+        }
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats
+    }
+
+    pub fn on_sent(&mut self, bytes: usize, now: Instant) {
+        self.stats.packets_sent += 1;
+        self.window_sent += bytes;
+        self.roll_window(now);
+    }
+
+    pub fn on_received(&mut self, bytes: usize, now: Instant) {
+        self.stats.packets_received += 1;
+        self.window_received += bytes;
+        self.roll_window(now);
+    }
+
+    pub fn on_acked(&mut self, sent_at: Instant, now: Instant) {
+        let sample = now.saturating_duration_since(sent_at).as_secs_f64();
+        if self.has_rtt {
+            let rtt = self.stats.rtt.as_secs_f64();
+            let jitter = self.stats.jitter.as_secs_f64();
+            self.stats.jitter =
+                Duration::from_secs_f64(jitter + ((sample - rtt).abs() - jitter) * SMOOTHING);
+            self.stats.rtt = Duration::from_secs_f64(rtt + (sample - rtt) * SMOOTHING);
+        } else {
+            self.has_rtt = true;
+            self.stats.rtt = Duration::from_secs_f64(sample);
+        }
+        self.stats.packet_loss -= self.stats.packet_loss * LOSS_SMOOTHING;
+    }
+
+    pub fn on_lost(&mut self) {
+        self.stats.packets_lost += 1;
+        self.stats.packet_loss += (1.0 - self.stats.packet_loss) * LOSS_SMOOTHING;
+    }
+
+    fn roll_window(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        if elapsed < BANDWIDTH_WINDOW {
+            return;
+        }
+        let seconds = elapsed.as_secs_f64();
+        self.stats.sent_bytes_per_second = self.window_sent as f64 / seconds;
+        self.stats.received_bytes_per_second = self.window_received as f64 / seconds;
+        (self.window_start, self.window_sent, self.window_received) = (now, 0, 0);
+    }
+}
+
+// Publishes the worst latency and loss and the total bandwidth of `connections` as net_* gauges.
+pub fn publish_metrics(connections: impl Iterator<Item = ConnectionStats>) {
+    let mut total = ConnectionStats::default();
+    let mut count = 0;
+    for stats in connections {
+        total.rtt = total.rtt.max(stats.rtt);
+        total.jitter = total.jitter.max(stats.jitter);
+        total.packet_loss = total.packet_loss.max(stats.packet_loss);
+        total.sent_bytes_per_second += stats.sent_bytes_per_second;
+        total.received_bytes_per_second += stats.received_bytes_per_second;
+        count += 1;
+    }
+    metrics::set_gauge("net_connections", count as f64);
+    metrics::set_gauge("net_rtt_seconds", total.rtt.as_secs_f64());
+    metrics::set_gauge("net_jitter_seconds", total.jitter.as_secs_f64());
+    metrics::set_gauge("net_packet_loss", total.packet_loss);
+    metrics::set_gauge("net_sent_bytes_per_second", total.sent_bytes_per_second);
+    metrics::set_gauge(
+        "net_received_bytes_per_second",
+        total.received_bytes_per_second,
+    );
+}
@@ -0,0 +1,511 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use super::{
+    conditions::{ConditionSimulator, NetConditions},
+    connection::{Connection, ConnectionState},
+    link::Link,
+    packet::{DenyReason, Packet, MAX_PACKET_SIZE},
+    stats::ConnectionStats,
+    websocket::WebSocketClient,
+    Channel, ConnectionId, DisconnectReason, NetConfig, NetError, NetEvent,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use super::{link::ServerLink, websocket::WebSocketListener};
+use crate::identifier::Uuid;
+
+// Connection id of a client's server connection until the server assigns the real one.
+const UNASSIGNED: ConnectionId = ConnectionId(0);
+// Disconnects aren't acked, send a few so one is likely to arrive.
+const DISCONNECT_REPEATS: usize = 3;
+
+enum Role {
+    Server { next_connection: u32 },
+    Client { server: SocketAddr },
+}
+
+pub struct Transport {
+    link: Box<dyn Link>,
+    config: NetConfig,
+    role: Role,
+    connections: HashMap<SocketAddr, Connection>,
+    events: VecDeque<NetEvent>,
+    conditions: ConditionSimulator,
+}
+
+impl Transport {
+    // Listens on `addr` and accepts up to `config.max_clients` clients.
+    pub fn server<A: ToSocketAddrs>(addr: A, config: NetConfig) -> Result<Self, NetError> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self::new(
+            Box::new(socket),
+            config,
+            Role::Server { next_connection: 1 },
+        ))
+    }
+
+    // Also accepts WebSocket clients on `websocket_addr`, for browser builds.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn server_with_websocket<A: ToSocketAddrs, W: ToSocketAddrs>(
+        addr: A,
+        websocket_addr: W,
+        config: NetConfig,
+    ) -> Result<Self, NetError> {
+        let udp = UdpSocket::bind(addr)?;
+        udp.set_nonblocking(true)?;
+        let link = ServerLink {
+            udp,
+            websocket: WebSocketListener::bind(websocket_addr)?,
+        };
+        Ok(Self::new(
+            Box::new(link),
+            config,
+            Role::Server { next_connection: 1 },
+        ))
+    }
+
+    // Starts connecting to `server_addr`, `NetEvent::Connected` follows once it accepts.
+    pub fn client<A: ToSocketAddrs>(server_addr: A, config: NetConfig) -> Result<Self, NetError> {
+        let server = server_addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no server address"))?;
+        let local: SocketAddr = if server.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self::connect(Box::new(socket), server, config))
+    }
+
+    // Connects over a WebSocket, e.g. `ws://example.com:27016`, to a server started with
+    // `server_with_websocket`. The only way to connect from a browser.
+    pub fn client_websocket(url: &str, config: NetConfig) -> Result<Self, NetError> {
+        let client = WebSocketClient::connect(url)?;
+        let server = client.server_addr();
+        Ok(Self::connect(Box::new(client), server, config))
+    }
+
+    fn connect(link: Box<dyn Link>, server: SocketAddr, config: NetConfig) -> Self {
+        let mut transport = Self::new(link, config, Role::Client { server });
+        let salt = u64::from_le_bytes(Uuid::new_v4().as_bytes()[..8].try_into().unwrap());
+        let now = Instant::now();
+        let mut connection = Connection::new(
+            UNASSIGNED,
+            server,
+            ConnectionState::Connecting { salt },
+            now,
+        );
+        // Makes the first update send the request right away.
+        connection.last_sent = now - transport.config.connect_retry;
+        transport.connections.insert(server, connection);
+        transport
+    }
+
+    fn new(link: Box<dyn Link>, config: NetConfig, role: Role) -> Self {
+        Self {
+            link,
+            config,
+            role,
+            connections: HashMap::new(),
+            events: VecDeque::new(),
+            conditions: ConditionSimulator::default(),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.link.local_addr()
+    }
+
+    pub fn is_server(&self) -> bool {
+        matches!(self.role, Role::Server { .. })
+    }
+
+    // Established connections. For a client that is the server once connected.
+    pub fn connections(&self) -> impl Iterator<Item = ConnectionId> + '_ {
+        self.connections
+            .values()
+            .filter(|connection| connection.state == ConnectionState::Connected)
+            .map(|connection| connection.id)
+    }
+
+    pub fn is_connected(&self, id: ConnectionId) -> bool {
+        self.connections().any(|connection| connection == id)
+    }
+
+    pub fn stats(&self, id: ConnectionId) -> Option<ConnectionStats> {
+        self.connections
+            .values()
+            .find(|connection| connection.id == id)
+            .map(|connection| connection.stats.stats())
+    }
+
+    // Stats of every established connection.
+    pub fn all_stats(&self) -> impl Iterator<Item = (ConnectionId, ConnectionStats)> + '_ {
+        self.connections
+            .values()
+            .filter(|connection| connection.state == ConnectionState::Connected)
+            .map(|connection| (connection.id, connection.stats.stats()))
+    }
+
+    pub fn conditions(&self) -> NetConditions {
+        self.conditions.conditions()
+    }
+
+    // Simulates a worse network for the packets this end sends, see `NetConditions`.
+    pub fn set_conditions(&mut self, conditions: NetConditions) {
+        self.conditions.set_conditions(conditions);
+    }
+
+    // Queues a message, it goes out with the next `update`.
+    pub fn send(
+        &mut self,
+        id: ConnectionId,
+        channel: Channel,
+        data: &[u8],
+    ) -> Result<(), NetError> {
+        self.connections
+            .values_mut()
+            .find(|connection| {
+                connection.id == id && connection.state == ConnectionState::Connected
+            })
+            .ok_or(NetError::NotConnected)?
+            .queue(channel, data)
+    }
+
+    pub fn broadcast(&mut self, channel: Channel, data: &[u8]) -> Result<(), NetError> {
+        for connection in self.connections.values_mut() {
+            if connection.state == ConnectionState::Connected {
+                connection.queue(channel, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Drops the connection without waiting for queued messages.
+    pub fn disconnect(&mut self, id: ConnectionId) {
+        let Some(addr) = self
+            .connections
+            .values()
+            .find(|connection| connection.id == id)
+            .map(|connection| connection.addr)
+        else {
+            return;
+        };
+        self.close(addr, DisconnectReason::Local);
+        for _ in 0..DISCONNECT_REPEATS {
+            self.send_packet(addr, &Packet::Disconnect);
+        }
+    }
+
+    pub fn poll_event(&mut self) -> Option<NetEvent> {
+        self.events.pop_front()
+    }
+
+    // Receives everything pending, drops timed out connections and sends queued messages, resends
+    // and heartbeats. Call once per tick.
+    pub fn update(&mut self, now: Instant) -> Result<(), NetError> {
+        profile_scope!("net_update");
+        self.send_delayed(now);
+        self.receive(now)?;
+
+        let timed_out: Vec<_> = self
+            .connections
+            .values()
+            .filter(|connection| now.duration_since(connection.last_received) > self.config.timeout)
+            .map(|connection| connection.addr)
+            .collect();
+        for addr in timed_out {
+            self.close(addr, DisconnectReason::TimedOut);
+        }
+
+        let mut outgoing = Vec::new();
+        for connection in self.connections.values_mut() {
+            match connection.state {
+                ConnectionState::Connecting { salt } => {
+                    if now.duration_since(connection.last_sent) >= self.config.connect_retry {
+                        connection.last_sent = now;
+                        outgoing.push((
+                            connection.addr,
+                            Packet::ConnectRequest {
+                                salt,
+                                version: self.config.protocol_version,
+                            },
+                        ));
+                    }
+                }
+                ConnectionState::Connected => {
+                    while let Some(packet) = connection.next_packet(
+                        now,
+                        self.config.resend_delay,
+                        self.config.heartbeat_interval,
+                    ) {
+                        outgoing.push((connection.addr, packet));
+                    }
+                }
+            }
+        }
+        for (addr, packet) in outgoing {
+            self.send_packet(addr, &packet);
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, now: Instant) -> Result<(), NetError> {
+        let mut buffer = [0; MAX_PACKET_SIZE];
+        loop {
+            let Some((len, from)) = self.link.recv_from(&mut buffer)? else {
+                return Ok(());
+            };
+            match Packet::decode(&buffer[..len], self.config.protocol_id) {
+                Ok(packet) => {
+                    if let Some(connection) = self.connections.get_mut(&from) {
+                        connection.stats.on_received(len, now);
+                    }
+                    self.handle_packet(from, packet, now)
+                }
+                Err(err) => trace!("Dropped packet from {}: {}", from, err),
+            }
+        }
+    }
+
+    fn handle_packet(&mut self, from: SocketAddr, packet: Packet, now: Instant) {
+        if let Role::Client { server } = self.role {
+            if from != server {
+                return;
+            }
+        }
+        match packet {
+            Packet::ConnectRequest { salt, version } => {
+                self.handle_connect_request(from, salt, version, now)
+            }
+            Packet::ConnectAccept { salt, connection } => {
+                let Some(client) = self.connections.get_mut(&from) else {
+                    return;
+                };
+                if client.state == (ConnectionState::Connecting { salt }) {
+                    client.state = ConnectionState::Connected;
+                    client.id = ConnectionId(connection);
+                    client.last_received = now;
+                    self.events.push_back(NetEvent::Connected(client.id));
+                }
+            }
+            Packet::ConnectDeny { salt, reason } => {
+                if let Some(client) = self.connections.get(&from) {
+                    if client.state == (ConnectionState::Connecting { salt }) {
+                        let reason = match reason {
+                            DenyReason::ServerFull => DisconnectReason::Denied,
+                            DenyReason::VersionMismatch => DisconnectReason::VersionMismatch,
+                        };
+                        self.close(from, reason);
+                    }
+                }
+            }
+            Packet::Disconnect => {
+                if self.connections.contains_key(&from) {
+                    self.close(from, DisconnectReason::Remote);
+                }
+            }
+            Packet::Payload {
+                sequence,
+                ack,
+                ack_bits,
+                messages,
+            } => {
+                let Some(connection) = self.connections.get_mut(&from) else {
+                    return;
+                };
+                if connection.state != ConnectionState::Connected {
+                    return;
+                }
+                let id = connection.id;
+                for (channel, data) in
+                    connection.receive_payload(sequence, ack, ack_bits, messages, now)
+                {
+                    self.events.push_back(NetEvent::Message {
+                        connection: id,
+                        channel,
+                        data,
+                    });
+                }
+            }
+        }
+    }
+
+    fn handle_connect_request(&mut self, from: SocketAddr, salt: u64, version: u64, now: Instant) {
+        let Role::Server { next_connection } = &mut self.role else {
+            return;
+        };
+        let reply = match self.connections.get(&from) {
+            _ if version != self.config.protocol_version => {
+                debug!("Denied {}, protocol version {:x}", from, version);
+                Packet::ConnectDeny {
+                    salt,
+                    reason: DenyReason::VersionMismatch,
+                }
+            }
+            // The accept got lost, send it again.
+            Some(connection) if connection.salt == salt => Packet::ConnectAccept {
+                salt,
+                connection: connection.id.0,
+            },
+            _ if self.connections.len() >= self.config.max_clients
+                && !self.connections.contains_key(&from) =>
+            {
+                Packet::ConnectDeny {
+                    salt,
+                    reason: DenyReason::ServerFull,
+                }
+            }
+            existing => {
+                let restarted = existing.is_some();
+                let id = ConnectionId(*next_connection);
+                *next_connection += 1;
+                if restarted {
+                    // Same address, new salt: the client restarted.
+                    self.close(from, DisconnectReason::Remote);
+                }
+                let mut connection = Connection::new(id, from, ConnectionState::Connected, now);
+                connection.salt = salt;
+                self.connections.insert(from, connection);
+                self.events.push_back(NetEvent::Connected(id));
+                Packet::ConnectAccept {
+                    salt,
+                    connection: id.0,
+                }
+            }
+        };
+        self.send_packet(from, &reply);
+    }
+
+    fn close(&mut self, addr: SocketAddr, reason: DisconnectReason) {
+        if let Some(connection) = self.connections.remove(&addr) {
+            // Clients never connected have no id the game knows about.
+            if connection.state == ConnectionState::Connected || !self.is_server() {
+                self.events
+                    .push_back(NetEvent::Disconnected(connection.id, reason));
+            }
+        }
+    }
+
+    fn send_packet(&mut self, addr: SocketAddr, packet: &Packet) {
+        let bytes = packet.encode(self.config.protocol_id);
+        let now = Instant::now();
+        if let Some(connection) = self.connections.get_mut(&addr) {
+            connection.stats.on_sent(bytes.len(), now);
+        }
+        if let Some(bytes) = self.conditions.submit(addr, bytes, now) {
+            self.send_raw(addr, &bytes);
+        }
+    }
+
+    fn send_delayed(&mut self, now: Instant) {
+        for (addr, bytes) in self.conditions.due(now) {
+            self.send_raw(addr, &bytes);
+        }
+    }
+
+    // Send failures are treated like packet loss.
+    fn send_raw(&mut self, addr: SocketAddr, bytes: &[u8]) {
+        if let Err(err) = self.link.send_to(bytes, addr) {
+            if err.kind() != io::ErrorKind::WouldBlock {
+                debug!("Failed to send to {}: {}", addr, err);
+            }
+        }
+    }
+}
+
+impl Drop for Transport {
+    fn drop(&mut self) {
+        let connected: Vec<_> = self
+            .connections
+            .values()
+            .filter(|connection| connection.state == ConnectionState::Connected)
+            .map(|connection| connection.addr)
+            .collect();
+        for addr in connected {
+            for _ in 0..DISCONNECT_REPEATS {
+                self.send_packet(addr, &Packet::Disconnect);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn loopback_reliable_ordered() {
+        let mut server = Transport::server("127.0.0.1:0", NetConfig::default()).unwrap();
+        let mut client =
+            Transport::client(server.local_addr().unwrap(), NetConfig::default()).unwrap();
+        exchange(&mut server, &mut client);
+    }
+
+    #[test]
+    fn websocket_client() {
+        let link = ServerLink {
+            udp: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            websocket: WebSocketListener::bind("127.0.0.1:0").unwrap(),
+        };
+        link.udp.set_nonblocking(true).unwrap();
+        let url = format!("ws://{}", link.websocket.local_addr().unwrap());
+        let mut server = Transport::new(
+            Box::new(link),
+            NetConfig::default(),
+            Role::Server { next_connection: 1 },
+        );
+        // The handshake blocks until the server answers it from update.
+        let connecting =
+            thread::spawn(move || Transport::client_websocket(&url, NetConfig::default()));
+        while !connecting.is_finished() {
+            server.update(Instant::now()).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        let mut client = connecting.join().unwrap().unwrap();
+        exchange(&mut server, &mut client);
+    }
+
+    fn exchange(server: &mut Transport, client: &mut Transport) {
+        let mut received = Vec::new();
+        let mut server_side = None;
+        for _ in 0..200 {
+            let now = Instant::now();
+            client.update(now).unwrap();
+            server.update(now).unwrap();
+            while let Some(event) = server.poll_event() {
+                match event {
+                    NetEvent::Connected(id) => server_side = Some(id),
+                    NetEvent::Message { data, .. } => received.push(data),
+                    NetEvent::Disconnected(..) => panic!("unexpected disconnect"),
+                }
+            }
+            while let Some(event) = client.poll_event() {
+                if let NetEvent::Connected(id) = event {
+                    for i in 0..10u8 {
+                        client.send(id, Channel::ReliableOrdered, &[i]).unwrap();
+                    }
+                }
+            }
+            if received.len() == 10 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(server_side.is_some());
+        assert_eq!(received, (0..10u8).map(|i| vec![i]).collect::<Vec<_>>());
+    }
+}
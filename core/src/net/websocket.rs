@@ -0,0 +1,298 @@
+//! Packets over WebSockets, one binary message each, for browsers which can't send UDP. The
+//! transport's acks and resends still run on top, unchanged, so both kinds of client speak the
+//! same protocol and servers treat them alike. Only plain `ws://` is supported.
+
+use std::{io, net::SocketAddr};
+
+use super::link::Link;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::{WebSocketClient, WebSocketListener};
+#[cfg(target_arch = "wasm32")]
+pub use web::WebSocketClient;
+
+impl Link for WebSocketClient {
+    // Everything goes to the server the client connected to.
+    fn send_to(&mut self, bytes: &[u8], _addr: SocketAddr) -> io::Result<()> {
+        self.send(bytes)
+    }
+
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+        Ok(self.recv(buffer)?.map(|len| (len, self.server_addr())))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "WebSockets have no local address",
+        ))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::{
+        collections::HashMap,
+        io,
+        net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    };
+
+    use tungstenite::{
+        handshake::{server::NoCallback, server::ServerHandshake, HandshakeError, MidHandshake},
+        Message, WebSocket,
+    };
+
+    type Handshake = MidHandshake<ServerHandshake<TcpStream, NoCallback>>;
+
+    // Accepts WebSocket clients on a TCP port.
+    pub struct WebSocketListener {
+        listener: TcpListener,
+        handshakes: Vec<Handshake>,
+        peers: HashMap<SocketAddr, WebSocket<TcpStream>>,
+    }
+
+    impl WebSocketListener {
+        pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+            let listener = TcpListener::bind(addr)?;
+            listener.set_nonblocking(true)?;
+            Ok(Self {
+                listener,
+                handshakes: Vec::new(),
+                peers: HashMap::new(),
+            })
+        }
+
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.listener.local_addr()
+        }
+
+        pub fn has_peer(&self, addr: SocketAddr) -> bool {
+            self.peers.contains_key(&addr)
+        }
+
+        pub fn send_to(&mut self, bytes: &[u8], addr: SocketAddr) -> io::Result<()> {
+            let Some(socket) = self.peers.get_mut(&addr) else {
+                return Err(io::ErrorKind::NotConnected.into());
+            };
+            let result = send(socket, bytes);
+            if result.is_err() {
+                self.peers.remove(&addr);
+            }
+            result
+        }
+
+        pub fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+            self.accept();
+            let mut received = None;
+            let mut closed = Vec::new();
+            for (&addr, socket) in self.peers.iter_mut() {
+                match recv(socket, buffer) {
+                    Ok(Some(len)) => {
+                        received = Some((len, addr));
+                        break;
+                    }
+                    Ok(None) => {}
+                    Err(err) => {
+                        debug!("WebSocket peer {} closed: {}", addr, err);
+                        closed.push(addr);
+                    }
+                }
+            }
+            for addr in closed {
+                self.peers.remove(&addr);
+            }
+            Ok(received)
+        }
+
+        fn accept(&mut self) {
+            loop {
+                match self.listener.accept() {
+                    Ok((stream, addr)) => {
+                        if let Err(err) = stream
+                            .set_nonblocking(true)
+                            .and_then(|()| stream.set_nodelay(true))
+                        {
+                            debug!("Dropped WebSocket connection from {}: {}", addr, err);
+                            continue;
+                        }
+                        self.finish_handshake(tungstenite::accept(stream));
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => {
+                        debug!("WebSocket accept failed: {}", err);
+                        break;
+                    }
+                }
+            }
+            for handshake in std::mem::take(&mut self.handshakes) {
+                self.finish_handshake(handshake.handshake());
+            }
+        }
+
+        fn finish_handshake(
+            &mut self,
+            result: Result<
+                WebSocket<TcpStream>,
+                HandshakeError<ServerHandshake<TcpStream, NoCallback>>,
+            >,
+        ) {
+            match result {
+                Ok(socket) => match socket.get_ref().peer_addr() {
+                    Ok(addr) => {
+                        self.peers.insert(addr, socket);
+                    }
+                    Err(err) => debug!("Dropped WebSocket peer: {}", err),
+                },
+                Err(HandshakeError::Interrupted(handshake)) => self.handshakes.push(handshake),
+                Err(HandshakeError::Failure(err)) => debug!("WebSocket handshake failed: {}", err),
+            }
+        }
+    }
+
+    pub struct WebSocketClient {
+        socket: WebSocket<TcpStream>,
+        server: SocketAddr,
+    }
+
+    impl WebSocketClient {
+        // Blocks until the handshake is done, e.g. `ws://127.0.0.1:27016`.
+        pub fn connect(url: &str) -> io::Result<Self> {
+            let host = url
+                .strip_prefix("ws://")
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "expected a ws:// url"))?
+                .split('/')
+                .next()
+                .unwrap_or_default();
+            let stream = TcpStream::connect(host)?;
+            let server = stream.peer_addr()?;
+            let (socket, _) = tungstenite::client(url, stream).map_err(|err| match err {
+                HandshakeError::Failure(err) => io::Error::other(err),
+                HandshakeError::Interrupted(_) => io::ErrorKind::WouldBlock.into(),
+            })?;
+            socket.get_ref().set_nonblocking(true)?;
+            socket.get_ref().set_nodelay(true)?;
+            Ok(Self { socket, server })
+        }
+
+        pub fn server_addr(&self) -> SocketAddr {
+            self.server
+        }
+
+        pub fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+            send(&mut self.socket, bytes)
+        }
+
+        pub fn recv(&mut self, buffer: &mut [u8]) -> io::Result<Option<usize>> {
+            recv(&mut self.socket, buffer)
+        }
+    }
+
+    // Writes that would block stay queued in the socket and go out with later calls.
+    fn send(socket: &mut WebSocket<TcpStream>, bytes: &[u8]) -> io::Result<()> {
+        match socket.send(Message::Binary(bytes.to_vec())) {
+            Ok(()) => Ok(()),
+            Err(tungstenite::Error::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(err) => Err(io::Error::other(err)),
+        }
+    }
+
+    // The next binary message, Ok(None) if there is none yet. Errors mean the socket is closed.
+    fn recv(socket: &mut WebSocket<TcpStream>, buffer: &mut [u8]) -> io::Result<Option<usize>> {
+        let would_block = |err: &tungstenite::Error| matches!(err, tungstenite::Error::Io(err) if err.kind() == io::ErrorKind::WouldBlock);
+        match socket.flush() {
+            Err(err) if !would_block(&err) => return Err(io::Error::other(err)),
+            _ => {}
+        }
+        loop {
+            match socket.read() {
+                Ok(Message::Binary(data)) if data.len() <= buffer.len() => {
+                    buffer[..data.len()].copy_from_slice(&data);
+                    return Ok(Some(data.len()));
+                }
+                // Oversized, pings and text aren't packets.
+                Ok(_) => {}
+                Err(err) if would_block(&err) => return Ok(None),
+                Err(err) => return Err(io::Error::other(err)),
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::{cell::RefCell, collections::VecDeque, io, net::SocketAddr, rc::Rc};
+
+    use wasm_bindgen::{closure::Closure, JsCast};
+    use web_sys::{BinaryType, MessageEvent, WebSocket};
+
+    // The browser's WebSocket, received messages are queued by its callback until polled. A
+    // closed socket just stops receiving, the transport's timeout notices.
+    pub struct WebSocketClient {
+        socket: WebSocket,
+        inbox: Rc<RefCell<VecDeque<Vec<u8>>>>,
+        _on_message: Closure<dyn FnMut(MessageEvent)>,
+    }
+
+    // wasm32-unknown-unknown runs everything on the browser's one thread, nothing can move this
+    // to another thread. Needed because engine resources must be Send.
+    unsafe impl Send for WebSocketClient {}
+
+    fn js_error(err: wasm_bindgen::JsValue) -> io::Error {
+        io::Error::other(format!("{:?}", err))
+    }
+
+    impl WebSocketClient {
+        // Returns right away, packets sent before the socket opened are lost and resent by the
+        // transport.
+        pub fn connect(url: &str) -> io::Result<Self> {
+            let socket = WebSocket::new(url).map_err(js_error)?;
+            socket.set_binary_type(BinaryType::Arraybuffer);
+            let inbox = Rc::new(RefCell::new(VecDeque::new()));
+
+            let queue = inbox.clone();
+            let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+                if let Ok(buffer) = event.data().dyn_into::<js_sys::ArrayBuffer>() {
+                    queue
+                        .borrow_mut()
+                        .push_back(js_sys::Uint8Array::new(&buffer).to_vec());
+                }
+            });
+            socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+            Ok(Self {
+                socket,
+                inbox,
+                _on_message: on_message,
+            })
+        }
+
+        // Browsers don't tell, every packet is from the server.
+        pub fn server_addr(&self) -> SocketAddr {
+            ([0, 0, 0, 0], 0).into()
+        }
+
+        pub fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+            if self.socket.ready_state() != WebSocket::OPEN {
+                return Err(io::ErrorKind::NotConnected.into());
+            }
+            self.socket.send_with_u8_array(bytes).map_err(js_error)
+        }
+
+        pub fn recv(&mut self, buffer: &mut [u8]) -> io::Result<Option<usize>> {
+            while let Some(data) = self.inbox.borrow_mut().pop_front() {
+                if data.len() <= buffer.len() {
+                    buffer[..data.len()].copy_from_slice(&data);
+                    return Ok(Some(data.len()));
+                }
+            }
+            Ok(None)
+        }
+    }
+
+    impl Drop for WebSocketClient {
+        fn drop(&mut self) {
+            self.socket.set_onmessage(None);
+            let _ = self.socket.close();
+        }
+    }
+}
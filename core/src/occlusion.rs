@@ -0,0 +1,275 @@
+//! How much of something small is visible, for lens flares and other effects that fade as it
+//! goes behind things. Each `Proxy` is a sphere, a sun disk or a light bulb, that the renderer
+//! draws with depth testing inside an occlusion query, and the samples that pass over the
+//! samples the proxy covers on screen is how visible it is. Results come back a few frames late,
+//! so visibility is smoothed towards them instead of popping.
+//!
+//! The sim publishes its proxies and camera here, and the render thread reports sample counts
+//! back, the way the frame graph gets its samples. The renderer doesn't query anything yet, it
+//! has no pipeline to draw proxies or depth buffer to test them against, so until results
+//! arrive a proxy counts as fully visible and only leaving the screen or going behind the
+//! camera fades it.
+
+use std::{collections::BTreeMap, f32::consts::PI, fmt, sync::Mutex, time::Duration};
+
+use crate::{
+    editor::EditorCamera,
+    engine::{Engine, Plugin, Resources},
+    metrics,
+    sim::Time,
+};
+
+const DEFAULT_FADE: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OcclusionId(u64);
+
+impl fmt::Display for OcclusionId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "occlusion#{}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Proxy {
+    pub center: [f32; 3],
+    pub radius: f32,
+}
+
+// What the render thread draws this frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProxyFrame {
+    pub camera: EditorCamera,
+    // With the samples each would pass unoccluded, proxies off screen are left out.
+    pub proxies: Vec<(OcclusionId, Proxy, u64)>,
+}
+
+static PROXIES: Mutex<Option<ProxyFrame>> = Mutex::new(None);
+static RESULTS: Mutex<Vec<(OcclusionId, u64)>> = Mutex::new(Vec::new());
+
+// The latest proxies from the sim, for the render thread.
+pub fn proxies() -> Option<ProxyFrame> {
+    PROXIES.lock().unwrap().clone()
+}
+
+// Called by the render thread with the samples that passed for each proxy once its queries
+// have been read back.
+pub fn report(results: impl IntoIterator<Item = (OcclusionId, u64)>) {
+    RESULTS.lock().unwrap().extend(results);
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct State {
+    // Of the proxy's screen area that's inside the viewport, in front of the camera.
+    on_screen: f32,
+    // Of the samples the proxy should pass that did, from the last result.
+    unoccluded: Option<f32>,
+    expected: u64,
+    visibility: f32,
+    // Normalized device coordinates of the center, for placing flare ghosts.
+    screen: Option<[f32; 2]>,
+}
+
+pub struct Occlusion {
+    next_id: u64,
+    proxies: BTreeMap<OcclusionId, (Proxy, State)>,
+    camera: Option<EditorCamera>,
+    // How long visibility takes to catch up with a change.
+    pub fade: Duration,
+}
+
+impl Default for Occlusion {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            proxies: BTreeMap::new(),
+            camera: None,
+            fade: DEFAULT_FADE,
+        }
+    }
+}
+
+impl Occlusion {
+    pub fn add(&mut self, proxy: Proxy) -> OcclusionId {
+        self.next_id += 1;
+        let id = OcclusionId(self.next_id);
+        self.proxies.insert(id, (proxy, State::default()));
+        id
+    }
+
+    // Moves the proxy, e.g. with the light it stands for.
+    pub fn set(&mut self, id: OcclusionId, proxy: Proxy) {
+        if let Some((current, _)) = self.proxies.get_mut(&id) {
+            *current = proxy;
+        }
+    }
+
+    pub fn remove(&mut self, id: OcclusionId) -> Option<Proxy> {
+        self.proxies.remove(&id).map(|(proxy, _)| proxy)
+    }
+
+    pub fn len(&self) -> usize {
+        self.proxies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.proxies.is_empty()
+    }
+
+    // Set by the game every frame, the view proxies are tested from.
+    pub fn set_camera(&mut self, camera: EditorCamera) {
+        self.camera = Some(camera);
+    }
+
+    // 0..1, smoothed.
+    pub fn visibility(&self, id: OcclusionId) -> f32 {
+        self.proxies
+            .get(&id)
+            .map_or(0.0, |(_, state)| state.visibility)
+    }
+
+    // Where the proxy is in normalized device coordinates, None behind the camera.
+    pub fn screen_position(&self, id: OcclusionId) -> Option<[f32; 2]> {
+        self.proxies.get(&id)?.1.screen
+    }
+
+    // Visibility, faded out further towards the screen edges, what a lens flare is drawn at.
+    pub fn flare_intensity(&self, id: OcclusionId) -> f32 {
+        let Some([x, y]) = self.screen_position(id) else {
+            return 0.0;
+        };
+        let edge = x.abs().max(y.abs());
+        let t = ((edge - 0.7) / 0.3).clamp(0.0, 1.0);
+        self.visibility(id) * (1.0 - t * t * (3.0 - 2.0 * t))
+    }
+
+    // Sample counts from the render thread, against what each proxy would pass unoccluded.
+    pub fn apply_results(&mut self, results: impl IntoIterator<Item = (OcclusionId, u64)>) {
+        for (id, samples) in results {
+            if let Some((_, state)) = self.proxies.get_mut(&id) {
+                state.unoccluded =
+                    (state.expected > 0).then(|| (samples as f32 / state.expected as f32).min(1.0));
+            }
+        }
+    }
+
+    pub fn update(&mut self, delta: Duration) {
+        let step = delta.as_secs_f32() / self.fade.as_secs_f32().max(f32::EPSILON);
+        for (proxy, state) in self.proxies.values_mut() {
+            let (on_screen, expected, screen) = match &self.camera {
+                Some(camera) => coverage(camera, proxy),
+                None => (0.0, 0, None),
+            };
+            state.on_screen = on_screen;
+            state.expected = expected;
+            state.screen = screen;
+            let target = state.on_screen * state.unoccluded.unwrap_or(1.0);
+            state.visibility += (target - state.visibility).clamp(-step, step);
+        }
+    }
+
+    // The proxies on screen, for the render thread to draw.
+    pub fn frame(&self) -> Option<ProxyFrame> {
+        let camera = self.camera?;
+        let proxies = self
+            .proxies
+            .iter()
+            .filter(|(_, (_, state))| state.expected > 0)
+            .map(|(id, (proxy, state))| (*id, *proxy, state.expected))
+            .collect();
+        Some(ProxyFrame { camera, proxies })
+    }
+}
+
+// The fraction of the proxy inside the viewport, the samples it covers there and where its
+// center lands in normalized device coordinates.
+fn coverage(camera: &EditorCamera, proxy: &Proxy) -> (f32, u64, Option<[f32; 2]>) {
+    let Some(center) = camera.project(proxy.center) else {
+        return (0.0, 0, None);
+    };
+    // The largest the radius gets on screen along any axis, close enough for a sphere.
+    let radius = (0..3)
+        .filter_map(|axis| {
+            let mut edge = proxy.center;
+            edge[axis] += proxy.radius;
+            let edge = camera.project(edge)?;
+            Some(((edge[0] - center[0]).powi(2) + (edge[1] - center[1]).powi(2)).sqrt())
+        })
+        .fold(0.0f32, f32::max)
+        .max(0.5);
+    let [width, height] = camera.viewport;
+    let clipped = |from: f32, to: f32, size: f32| (to.min(size) - from.max(0.0)).max(0.0);
+    let inside = clipped(center[0] - radius, center[0] + radius, width)
+        * clipped(center[1] - radius, center[1] + radius, height);
+    let on_screen = inside / (4.0 * radius * radius);
+    let expected = (PI * radius * radius * on_screen).round() as u64;
+    let screen = [
+        center[0] / width * 2.0 - 1.0,
+        1.0 - center[1] / height * 2.0,
+    ];
+    (on_screen, expected, Some(screen))
+}
+
+pub struct OcclusionPlugin;
+
+impl Plugin for OcclusionPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(Occlusion::default())
+            .add_system(|resources: &mut Resources| {
+                let delta = resources
+                    .get::<Time>()
+                    .map_or(Duration::ZERO, |time| time.delta);
+                let Some(occlusion) = resources.get_mut::<Occlusion>() else {
+                    return;
+                };
+                occlusion.apply_results(std::mem::take(&mut *RESULTS.lock().unwrap()));
+                occlusion.update(delta);
+                *PROXIES.lock().unwrap() = occlusion.frame();
+                metrics::set_gauge("occlusion_proxies", occlusion.len() as f64);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portal::View;
+
+    #[test]
+    fn visibility_follows_screen_and_results() {
+        let projection = View::perspective(1.0, 1.0, 0.1, 100.0);
+        let view = View::look_at([0.0; 3], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0], projection);
+        let mut occlusion = Occlusion::default();
+        occlusion.set_camera(view.editor_camera([800.0, 800.0]));
+        let ahead = occlusion.add(Proxy {
+            center: [0.0, 0.0, -10.0],
+            radius: 0.5,
+        });
+        let behind = occlusion.add(Proxy {
+            center: [0.0, 0.0, 10.0],
+            radius: 0.5,
+        });
+        let frame = Duration::from_millis(50);
+
+        occlusion.update(frame);
+        assert_eq!(occlusion.visibility(ahead), 0.5);
+        occlusion.update(frame);
+        assert_eq!(occlusion.visibility(ahead), 1.0);
+        assert_eq!(occlusion.flare_intensity(ahead), 1.0);
+        assert_eq!(occlusion.visibility(behind), 0.0);
+        assert_eq!(occlusion.screen_position(behind), None);
+
+        // Only the proxy in view is sent to be drawn, half its samples pass.
+        let drawn = occlusion.frame().unwrap().proxies;
+        assert_eq!(drawn.len(), 1);
+        let (id, _, expected) = drawn[0];
+        assert_eq!(id, ahead);
+        assert!(expected > 100);
+        occlusion.apply_results([(ahead, expected / 2)]);
+        for _ in 0..4 {
+            occlusion.update(frame);
+        }
+        assert!((occlusion.visibility(ahead) - 0.5).abs() < 0.01);
+    }
+}
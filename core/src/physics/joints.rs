@@ -0,0 +1,406 @@
+//! Joints between physics bodies, solved by moving the bodies' current poses, position based
+//! dynamics style, after whatever integrates them. A joint is a component on an entity of its
+//! own, so a body can have any number:
+//!
+//! ```ignore
+//! let hinge = world.spawn();
+//! world.insert(hinge, Joint::new(frame, door, JointKind::Hinge { axis: [0.0, 1.0, 0.0] })
+//!     .with_anchors([0.5, 0.0, 0.0], [-0.5, 0.0, 0.0])
+//!     .with_motor(JointMotor { target_velocity: 1.0, max_force: 50.0 })
+//!     .with_break_force(1000.0));
+//! ```
+//!
+//! Bodies are points with an orientation to the solver, anchors off a body's center don't turn
+//! it. A joint that needs more force than its break force to hold is despawned and reported
+//! through the world's `Events<JointBroken>`.
+
+use std::time::Duration;
+
+use log::warn;
+
+use super::{BodyTransform, Pose, RigidBody};
+use crate::ecs::{EcsWorld, Entity};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JointKind {
+    // Holds the bodies together at `rotation`, b's orientation in a's.
+    Fixed {
+        rotation: [f32; 4],
+    },
+    // Holds the anchors together and lets b turn about `axis`, in a's space, only.
+    Hinge {
+        axis: [f32; 3],
+    },
+    // Holds the anchors together.
+    Ball,
+    // Lets b slide along `axis`, in a's space, between `limits` away from a's anchor if given.
+    Prismatic {
+        axis: [f32; 3],
+        limits: Option<(f32, f32)>,
+    },
+    // Pulls the anchors towards `rest_length` apart, harder the stiffer.
+    Spring {
+        rest_length: f32,
+        stiffness: f32,
+    },
+}
+
+// Drives a hinge's or prismatic joint's free axis, in radians or units per second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JointMotor {
+    pub target_velocity: f32,
+    pub max_force: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Joint {
+    pub body_a: Entity,
+    pub body_b: Entity,
+    // Where the joint attaches, in each body's space.
+    pub anchor_a: [f32; 3],
+    pub anchor_b: [f32; 3],
+    pub kind: JointKind,
+    pub motor: Option<JointMotor>,
+    // The force it takes to break the joint, unbreakable without one.
+    pub break_force: Option<f32>,
+}
+
+impl Joint {
+    pub fn new(body_a: Entity, body_b: Entity, kind: JointKind) -> Self {
+        Self {
+            body_a,
+            body_b,
+            anchor_a: [0.0; 3],
+            anchor_b: [0.0; 3],
+            kind,
+            motor: None,
+            break_force: None,
+        }
+    }
+
+    pub fn with_anchors(mut self, anchor_a: [f32; 3], anchor_b: [f32; 3]) -> Self {
+        self.anchor_a = anchor_a;
+        self.anchor_b = anchor_b;
+        self
+    }
+
+    pub fn with_motor(mut self, motor: JointMotor) -> Self {
+        self.motor = Some(motor);
+        self
+    }
+
+    pub fn with_break_force(mut self, break_force: f32) -> Self {
+        self.break_force = Some(break_force);
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JointBroken {
+    pub joint: Entity,
+    pub body_a: Entity,
+    pub body_b: Entity,
+    pub force: f32,
+}
+
+// One body's side of a joint while it's solved.
+struct Side {
+    pose: Pose,
+    inverse_mass: f32,
+}
+
+// Solves every joint `substeps` times over `tick`, despawns the ones that broke and returns them.
+pub fn solve_joints(world: &mut EcsWorld, tick: Duration, substeps: u32) -> Vec<JointBroken> {
+    let joints: Vec<(Entity, Joint)> = world
+        .query::<Joint>()
+        .map(|(entity, joint)| (entity, *joint))
+        .collect();
+    let substeps = substeps.max(1);
+    let dt = (tick / substeps).as_secs_f32();
+    let mut broken: Vec<JointBroken> = Vec::new();
+    for _ in 0..substeps {
+        for (entity, joint) in &joints {
+            if broken.iter().any(|broken| broken.joint == *entity) {
+                continue;
+            }
+            let (Some(mut a), Some(mut b)) = (side(world, joint.body_a), side(world, joint.body_b))
+            else {
+                continue;
+            };
+            if joint.body_a == joint.body_b {
+                warn!("Joint {} connects {} to itself", entity, joint.body_a);
+                continue;
+            }
+            let force = solve(joint, &mut a, &mut b, dt);
+            if joint.break_force.is_some_and(|limit| force > limit) {
+                broken.push(JointBroken {
+                    joint: *entity,
+                    body_a: joint.body_a,
+                    body_b: joint.body_b,
+                    force,
+                });
+                continue;
+            }
+            for (body, side) in [(joint.body_a, a), (joint.body_b, b)] {
+                if let Some(transform) = world.get_mut::<BodyTransform>(body) {
+                    transform.current = side.pose;
+                }
+            }
+        }
+    }
+    for broken in &broken {
+        world.despawn(broken.joint);
+    }
+    broken
+}
+
+fn side(world: &EcsWorld, body: Entity) -> Option<Side> {
+    let pose = world.get::<BodyTransform>(body)?.current;
+    let inverse_mass = world
+        .get::<RigidBody>(body)
+        .map_or(0.0, |body| body.inverse_mass);
+    Some(Side { pose, inverse_mass })
+}
+
+// Moves the bodies to satisfy the joint and returns the force that took.
+fn solve(joint: &Joint, a: &mut Side, b: &mut Side, dt: f32) -> f32 {
+    let total = a.inverse_mass + b.inverse_mass;
+    if total <= 0.0 {
+        return 0.0;
+    }
+    let anchor_a = add(a.pose.position, rotate(a.pose.rotation, joint.anchor_a));
+    let anchor_b = add(b.pose.position, rotate(b.pose.rotation, joint.anchor_b));
+    let offset = sub(anchor_b, anchor_a);
+
+    // How far each anchor has to move towards the other, and how far b has to turn, to hold
+    // the joint, then on top of that for the motor.
+    let mut drive = ([0.0; 3], [0.0; 3]);
+    let (linear, angular) = match joint.kind {
+        JointKind::Ball => (offset, [0.0; 3]),
+        JointKind::Hinge { axis } => {
+            let axis_a = rotate(a.pose.rotation, axis);
+            let axis_b = rotate(b.pose.rotation, axis);
+            if let Some(motor) = joint.motor {
+                drive.1 = scale(normalize(axis_a), motor_step(motor, dt, total));
+            }
+            (offset, cross(axis_b, axis_a))
+        }
+        JointKind::Fixed { rotation } => {
+            let target = multiply(a.pose.rotation, rotation);
+            (offset, rotation_between(b.pose.rotation, target))
+        }
+        JointKind::Prismatic { axis, limits } => {
+            let axis = normalize(rotate(a.pose.rotation, axis));
+            let along = dot(offset, axis);
+            let limit = |x: f32| limits.map_or(x, |(min, max)| x.clamp(min, max));
+            let held = limit(along);
+            if let Some(motor) = joint.motor {
+                let driven = limit(held + motor_step(motor, dt, total));
+                drive.0 = scale(axis, held - driven);
+            }
+            let across = sub(offset, scale(axis, along));
+            (add(across, scale(axis, along - held)), [0.0; 3])
+        }
+        JointKind::Spring {
+            rest_length,
+            stiffness,
+        } => {
+            let length = dot(offset, offset).sqrt();
+            if length <= 0.0 || stiffness <= 0.0 {
+                return 0.0;
+            }
+            // Compliance, how soft the constraint is this substep, see XPBD.
+            let compliance = 1.0 / (stiffness * dt * dt);
+            let error = (length - rest_length) * total / (total + compliance);
+            (scale(offset, error / length), [0.0; 3])
+        }
+    };
+
+    // What moving them that far in one substep takes, as if pushing unit masses. The motor's
+    // drive doesn't strain the joint.
+    let correction = dot(linear, linear).sqrt() + dot(angular, angular).sqrt();
+    let force = correction / (total * dt * dt);
+
+    let (linear, angular) = (add(linear, drive.0), add(angular, drive.1));
+    let weight_a = a.inverse_mass / total;
+    let weight_b = b.inverse_mass / total;
+    a.pose.position = add(a.pose.position, scale(linear, weight_a));
+    b.pose.position = sub(b.pose.position, scale(linear, weight_b));
+    a.pose.rotation = turn(a.pose.rotation, scale(angular, -weight_a));
+    b.pose.rotation = turn(b.pose.rotation, scale(angular, weight_b));
+    force
+}
+
+// How far a motor moves its axis this substep, as far as its force allows.
+fn motor_step(motor: JointMotor, dt: f32, total: f32) -> f32 {
+    let reach = motor.max_force * dt * dt * total;
+    (motor.target_velocity * dt).clamp(-reach, reach)
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale(a: [f32; 3], s: f32) -> [f32; 3] {
+    a.map(|x| x * s)
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let length = dot(a, a).sqrt();
+    if length > 0.0 {
+        scale(a, 1.0 / length)
+    } else {
+        a
+    }
+}
+
+fn multiply(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    let [ax, ay, az, aw] = a;
+    let [bx, by, bz, bw] = b;
+    [
+        aw * bx + ax * bw + ay * bz - az * by,
+        aw * by - ax * bz + ay * bw + az * bx,
+        aw * bz + ax * by - ay * bx + az * bw,
+        aw * bw - ax * bx - ay * by - az * bz,
+    ]
+}
+
+fn rotate(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let [x, y, z, _] = multiply(multiply(q, [v[0], v[1], v[2], 0.0]), conjugate(q));
+    [x, y, z]
+}
+
+fn conjugate(q: [f32; 4]) -> [f32; 4] {
+    [-q[0], -q[1], -q[2], q[3]]
+}
+
+// The rotation vector that turns `from` into `to`.
+fn rotation_between(from: [f32; 4], to: [f32; 4]) -> [f32; 3] {
+    let [x, y, z, w] = multiply(to, conjugate(from));
+    // q and -q are the same rotation, take the short way round.
+    let sign = if w < 0.0 { -2.0 } else { 2.0 };
+    [x * sign, y * sign, z * sign]
+}
+
+// Turns `q` by a small rotation vector.
+fn turn(q: [f32; 4], by: [f32; 3]) -> [f32; 4] {
+    let [dx, dy, dz, dw] = multiply([by[0], by[1], by[2], 0.0], q);
+    let turned = [
+        q[0] + dx * 0.5,
+        q[1] + dy * 0.5,
+        q[2] + dz * 0.5,
+        q[3] + dw * 0.5,
+    ];
+    let length = turned.iter().map(|x| x * x).sum::<f32>().sqrt();
+    turned.map(|x| x / length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(world: &mut EcsWorld, position: [f32; 3], inverse_mass: f32) -> Entity {
+        let entity = world.spawn();
+        world.insert(
+            entity,
+            BodyTransform::new(Pose {
+                position,
+                ..Pose::default()
+            }),
+        );
+        world.insert(entity, RigidBody { inverse_mass });
+        entity
+    }
+
+    fn position(world: &EcsWorld, body: Entity) -> [f32; 3] {
+        world.get::<BodyTransform>(body).unwrap().current.position
+    }
+
+    #[test]
+    fn joints_pull_bodies_together_until_they_break() {
+        let tick = Duration::from_micros(16_667);
+        let mut world = EcsWorld::new();
+        // A static anchor and a free body hanging off it from a ball joint a unit below.
+        let anchor = body(&mut world, [0.0; 3], 0.0);
+        let bob = body(&mut world, [0.5, -1.0, 0.0], 1.0);
+        let ball = world.spawn();
+        world.insert(
+            ball,
+            Joint::new(anchor, bob, JointKind::Ball).with_anchors([0.0, -1.0, 0.0], [0.0; 3]),
+        );
+        assert!(solve_joints(&mut world, tick, 4).is_empty());
+        assert_eq!(position(&world, anchor), [0.0; 3]);
+        let [x, y, _] = position(&world, bob);
+        assert!(x.abs() < 1e-5 && (y + 1.0).abs() < 1e-5);
+
+        // A slider only keeps the part of the offset across its axis, within its limits.
+        let slider = body(&mut world, [3.0, 0.5, 0.0], 1.0);
+        let prismatic = world.spawn();
+        let axis = [1.0, 0.0, 0.0];
+        world.insert(
+            prismatic,
+            Joint::new(
+                anchor,
+                slider,
+                JointKind::Prismatic {
+                    axis,
+                    limits: Some((0.0, 2.0)),
+                },
+            ),
+        );
+        solve_joints(&mut world, tick, 1);
+        let [x, y, _] = position(&world, slider);
+        assert!((x - 2.0).abs() < 1e-5 && y.abs() < 1e-5);
+
+        // Yanking the bob far away takes more force than a weak joint can hold.
+        world.get_mut::<Joint>(ball).unwrap().break_force = Some(100.0);
+        world
+            .get_mut::<BodyTransform>(bob)
+            .unwrap()
+            .current
+            .position = [10.0, 0.0, 0.0];
+        let broken = solve_joints(&mut world, tick, 1);
+        assert_eq!(broken.len(), 1);
+        assert_eq!(broken[0].joint, ball);
+        assert!(!world.contains(ball) && world.contains(prismatic));
+        assert_eq!(position(&world, bob), [10.0, 0.0, 0.0]);
+
+        // A hinge motor turns b about the axis.
+        let door = body(&mut world, [0.0; 3], 1.0);
+        let hinge = world.spawn();
+        let motor = JointMotor {
+            target_velocity: 1.0,
+            max_force: 1000.0,
+        };
+        world.insert(
+            hinge,
+            Joint::new(
+                anchor,
+                door,
+                JointKind::Hinge {
+                    axis: [0.0, 1.0, 0.0],
+                },
+            )
+            .with_motor(motor),
+        );
+        solve_joints(&mut world, tick, 1);
+        let rotation = world.get::<BodyTransform>(door).unwrap().current.rotation;
+        assert!(rotation[1] > 0.0 && rotation[0].abs() < 1e-6 && rotation[2].abs() < 1e-6);
+    }
+}
@@ -0,0 +1,232 @@
+//! Fixed-step physics timing: substeps within a sim tick and interpolation between ticks, plus
+//! `joints` between bodies. There is no integrator in the engine yet, whatever moves bodies runs
+//! as a scheduled system between `SNAPSHOT` and `JOINTS`, steps each tick in
+//! `PhysicsSettings::substeps` and writes the result to `BodyTransform::current`:
+//!
+//! ```ignore
+//! physics::add_systems(schedule::schedule(engine));
+//! schedule::schedule(engine)
+//!     .add_system("integrate", |world| {
+//!         let settings = world.res::<PhysicsSettings>().cloned().unwrap_or_default();
+//!         for mut body in world.query::<&mut BodyTransform, ()>().iter_mut() {
+//!             for dt in settings.substeps(FIXED_TIMESTEP) {
+//!                 integrate(&mut body.current, dt);
+//!             }
+//!         }
+//!     })
+//!     .writes::<BodyTransform>()
+//!     .reads_resource::<PhysicsSettings>()
+//!     .after(physics::SNAPSHOT)
+//!     .before(physics::JOINTS);
+//! ```
+//!
+//! `SNAPSHOT` keeps each body's pose from the tick before, so drawing bodies at
+//! `sim::tick_fraction()` between the two stays smooth when frames come faster than ticks.
+
+pub mod joints;
+
+pub use joints::{Joint, JointBroken, JointKind, JointMotor};
+
+use std::time::Duration;
+
+use crate::{
+    ecs::{EcsWorld, Entity, Events},
+    net::interpolation::Interpolate,
+    schedule::Schedule,
+    sim::FIXED_TIMESTEP,
+};
+
+// The system that moves `BodyTransform::current` into `previous` every tick.
+pub const SNAPSHOT: &str = "physics_snapshot";
+// The system that solves `Joint`s, see `joints::solve_joints`.
+pub const JOINTS: &str = "physics_joints";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhysicsSettings {
+    // Steps per sim tick, more keeps stiff springs and fast bodies stable at a cost.
+    pub substeps: u32,
+}
+
+impl Default for PhysicsSettings {
+    fn default() -> Self {
+        Self { substeps: 1 }
+    }
+}
+
+impl PhysicsSettings {
+    pub fn substep(&self, tick: Duration) -> Duration {
+        tick / self.substeps.max(1)
+    }
+
+    // The length of each substep of `tick`, which add up to it exactly.
+    pub fn substeps(&self, tick: Duration) -> impl Iterator<Item = Duration> {
+        let count = self.substeps.max(1);
+        let step = tick / count;
+        let last = tick - step * (count - 1);
+        (0..count).map(move |i| if i + 1 == count { last } else { step })
+    }
+}
+
+// Position, and rotation as a unit quaternion (x, y, z, w).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose {
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+impl Default for Pose {
+    fn default() -> Self {
+        Self {
+            position: [0.0; 3],
+            rotation: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+impl Interpolate for Pose {
+    // Normalized lerp for the rotation, close enough to slerp over one tick.
+    fn interpolate(&self, to: &Self, t: f32) -> Self {
+        let dot: f32 = (0..4).map(|i| self.rotation[i] * to.rotation[i]).sum();
+        // q and -q are the same rotation, take the short way round.
+        let to_rotation = if dot < 0.0 {
+            to.rotation.map(|x| -x)
+        } else {
+            to.rotation
+        };
+        let rotation = self.rotation.interpolate(&to_rotation, t);
+        let length = rotation.iter().map(|x| x * x).sum::<f32>().sqrt();
+        Self {
+            position: self.position.interpolate(&to.position, t),
+            rotation: if length > 0.0 {
+                rotation.map(|x| x / length)
+            } else {
+                to.rotation
+            },
+        }
+    }
+}
+
+// How much a body resists being moved, bodies without one don't move at all.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RigidBody {
+    // One over the mass, zero for bodies nothing moves.
+    pub inverse_mass: f32,
+}
+
+impl RigidBody {
+    pub fn with_mass(mass: f32) -> Self {
+        Self {
+            inverse_mass: if mass > 0.0 { 1.0 / mass } else { 0.0 },
+        }
+    }
+}
+
+// A physics body's pose at the last two ticks.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BodyTransform {
+    pub previous: Pose,
+    pub current: Pose,
+}
+
+impl BodyTransform {
+    pub fn new(pose: Pose) -> Self {
+        Self {
+            previous: pose,
+            current: pose,
+        }
+    }
+
+    // Moves the body without it sweeping there over the next frames.
+    pub fn teleport(&mut self, pose: Pose) {
+        *self = Self::new(pose);
+    }
+
+    // `t` is how far into the next tick, see `sim::tick_fraction`.
+    pub fn interpolated(&self, t: f32) -> Pose {
+        self.previous.interpolate(&self.current, t.clamp(0.0, 1.0))
+    }
+}
+
+pub fn add_systems(schedule: &mut Schedule) {
+    schedule
+        .add_system(SNAPSHOT, |world| {
+            for mut body in world.query::<&mut BodyTransform, ()>().iter_mut() {
+                body.previous = body.current;
+            }
+        })
+        .writes::<BodyTransform>();
+    schedule
+        .add_system(JOINTS, |world| {
+            let world = world.world_mut();
+            let substeps = world.res::<PhysicsSettings>().map_or(1, |s| s.substeps);
+            let broken = joints::solve_joints(world, FIXED_TIMESTEP, substeps);
+            world.add_event::<JointBroken>();
+            if let Some(events) = world.res_mut::<Events<JointBroken>>() {
+                events.send_batch(broken);
+            }
+        })
+        .exclusive()
+        .after(SNAPSHOT);
+}
+
+// Every body where it should be drawn, `t` into the next tick.
+pub fn interpolated_poses(world: &EcsWorld, t: f32) -> Vec<(Entity, Pose)> {
+    world
+        .query::<BodyTransform>()
+        .map(|(entity, body)| (entity, body.interpolated(t)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Resources;
+
+    #[test]
+    fn bodies_interpolate_between_ticks() {
+        let settings = PhysicsSettings { substeps: 3 };
+        let tick = Duration::from_micros(16_667);
+        assert_eq!(settings.substeps(tick).sum::<Duration>(), tick);
+        assert_eq!(settings.substeps(tick).count(), 3);
+
+        let mut schedule = Schedule::default();
+        add_systems(&mut schedule);
+        schedule
+            .add_system("integrate", |world| {
+                let substeps = world.res::<PhysicsSettings>().unwrap().substeps;
+                for mut body in world.query::<&mut BodyTransform, ()>().iter_mut() {
+                    for _ in 0..substeps {
+                        body.current.position[0] += 1.0;
+                    }
+                }
+            })
+            .writes::<BodyTransform>()
+            .reads_resource::<PhysicsSettings>()
+            .after(SNAPSHOT)
+            .before(JOINTS);
+        assert_eq!(schedule.order().unwrap(), [SNAPSHOT, "integrate", JOINTS]);
+
+        let mut resources = Resources::default();
+        let mut world = EcsWorld::new();
+        world.insert_resource(settings);
+        let body = world.spawn();
+        world.insert(body, BodyTransform::new(Pose::default()));
+        resources.insert(world);
+        schedule.run(&mut resources);
+        schedule.run(&mut resources);
+
+        let world = resources.get::<EcsWorld>().unwrap();
+        let poses = interpolated_poses(world, 0.5);
+        assert_eq!(poses.len(), 1);
+        assert_eq!(poses[0].1.position, [4.5, 0.0, 0.0]);
+
+        // Halfway to half a turn about z is a quarter turn.
+        let turned = Pose {
+            rotation: [0.0, 0.0, -1.0, 0.0],
+            ..Pose::default()
+        };
+        let halfway = Pose::default().interpolate(&turned, 0.5).rotation;
+        let expected = [0.0, 0.0, -(0.5f32.sqrt()), 0.5f32.sqrt()];
+        assert!((0..4).all(|i| (halfway[i] - expected[i]).abs() < 1e-6));
+    }
+}
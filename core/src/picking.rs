@@ -0,0 +1,412 @@
+//! Picking, finding the entity under a screen position for mouse interaction with the world.
+//!
+//! The renderer has no entity id target or readback yet, so picks are answered by casting a ray
+//! from the camera against bounds the game keeps up to date. `pick` still hands back an id and
+//! the answer arrives as a `PickEvent` on the next tick, the way a readback would frames later,
+//! so callers won't change when one exists. `raycast` answers right away.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+use crate::{
+    editor::{EditorCamera, Transform},
+    engine::{Engine, Plugin, Resources},
+    identifier::SnowflakeId,
+    metrics,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: [f32; 3],
+    // Unit length.
+    pub direction: [f32; 3],
+}
+
+impl Ray {
+    // The ray through a pixel, from the camera's near plane. None if the camera can't be inverted.
+    pub fn from_screen(camera: &EditorCamera, cursor: [f32; 2]) -> Option<Self> {
+        let inverse = invert(&camera.view_projection)?;
+        let x = cursor[0] / camera.viewport[0] * 2.0 - 1.0;
+        let y = 1.0 - cursor[1] / camera.viewport[1] * 2.0;
+        let near = unproject(&inverse, [x, y, 0.0])?;
+        let far = unproject(&inverse, [x, y, 1.0])?;
+        let along = std::array::from_fn(|i| far[i] - near[i]);
+        let length = dot(along, along).sqrt();
+        (length > f32::EPSILON).then(|| Self {
+            origin: near,
+            direction: along.map(|c| c / length),
+        })
+    }
+
+    pub fn at(&self, distance: f32) -> [f32; 3] {
+        std::array::from_fn(|i| self.origin[i] + self.direction[i] * distance)
+    }
+}
+
+// World space bounds to pick against.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Aabb {
+    // Bounds around a unit cube moved, rotated and scaled by `transform`.
+    pub fn from_transform(transform: &Transform) -> Self {
+        let mut bounds = Self {
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+        };
+        for corner in 0..8 {
+            let local: [f32; 3] = std::array::from_fn(|axis| {
+                let sign = if corner & (1 << axis) == 0 { -0.5 } else { 0.5 };
+                sign * transform.scale[axis]
+            });
+            let rotated = rotate(transform.rotation, local);
+            for (axis, offset) in rotated.into_iter().enumerate() {
+                let world = offset + transform.translation[axis];
+                bounds.min[axis] = bounds.min[axis].min(world);
+                bounds.max[axis] = bounds.max[axis].max(world);
+            }
+        }
+        bounds
+    }
+
+    // Distance along the ray to where it enters the box, zero if it starts inside.
+    pub fn intersect(&self, ray: &Ray) -> Option<f32> {
+        let (mut near, mut far) = (0.0f32, f32::INFINITY);
+        for axis in 0..3 {
+            let (origin, direction) = (ray.origin[axis], ray.direction[axis]);
+            if direction.abs() <= f32::EPSILON {
+                if origin < self.min[axis] || origin > self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let a = (self.min[axis] - origin) / direction;
+            let b = (self.max[axis] - origin) / direction;
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PickId(u64);
+
+impl fmt::Display for PickId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pick#{}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PickHit {
+    pub entity: SnowflakeId,
+    // Where the ray entered the entity's bounds.
+    pub point: [f32; 3],
+    pub distance: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PickEvent {
+    pub id: PickId,
+    // None when nothing is under the position or there was no camera to pick through.
+    pub hit: Option<PickHit>,
+}
+
+#[derive(Default)]
+pub struct Picking {
+    camera: Option<EditorCamera>,
+    bounds: HashMap<SnowflakeId, Aabb>,
+    pending: Vec<(PickId, [f32; 2])>,
+    events: VecDeque<PickEvent>,
+    next_id: u64,
+}
+
+impl Picking {
+    // The camera picks go through, usually the same one the editor gets.
+    pub fn set_camera(&mut self, camera: EditorCamera) {
+        self.camera = Some(camera);
+    }
+
+    pub fn camera(&self) -> Option<&EditorCamera> {
+        self.camera.as_ref()
+    }
+
+    // Makes `entity` pickable, or moves its bounds.
+    pub fn insert(&mut self, entity: SnowflakeId, bounds: Aabb) {
+        self.bounds.insert(entity, bounds);
+    }
+
+    pub fn remove(&mut self, entity: SnowflakeId) -> bool {
+        self.bounds.remove(&entity).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.bounds.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.bounds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bounds.is_empty()
+    }
+
+    // Asks what's under a pixel, answered by a `PickEvent` with the returned id.
+    pub fn pick(&mut self, x: f32, y: f32) -> PickId {
+        let id = PickId(self.next_id);
+        self.next_id += 1;
+        self.pending.push((id, [x, y]));
+        id
+    }
+
+    pub fn poll_event(&mut self) -> Option<PickEvent> {
+        self.events.pop_front()
+    }
+
+    // The closest entity the ray hits.
+    pub fn raycast(&self, ray: &Ray) -> Option<PickHit> {
+        closest(
+            self.bounds
+                .iter()
+                .map(|(entity, bounds)| (*entity, *bounds)),
+            ray,
+        )
+    }
+
+    // What's under a pixel right now.
+    pub fn pick_now(&self, cursor: [f32; 2]) -> Option<PickHit> {
+        let ray = Ray::from_screen(self.camera.as_ref()?, cursor)?;
+        self.raycast(&ray)
+    }
+
+    fn resolve(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        for (id, cursor) in pending {
+            let hit = self.pick_now(cursor);
+            self.events.push_back(PickEvent { id, hit });
+        }
+    }
+}
+
+// The closest of `candidates` the ray hits, shared with the editor's click selection.
+pub(crate) fn closest(
+    candidates: impl Iterator<Item = (SnowflakeId, Aabb)>,
+    ray: &Ray,
+) -> Option<PickHit> {
+    candidates
+        .filter_map(|(entity, bounds)| {
+            let distance = bounds.intersect(ray)?;
+            Some(PickHit {
+                entity,
+                point: ray.at(distance),
+                distance,
+            })
+        })
+        // Ties go to the lower id so the answer doesn't depend on map order.
+        .min_by(|a, b| {
+            a.distance
+                .total_cmp(&b.distance)
+                .then(a.entity.cmp(&b.entity))
+        })
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+// `v` rotated by the unit quaternion `q`.
+pub(crate) fn rotate(q: [f32; 4], v: [f32; 3]) -> [f32; 3] {
+    let axis = [q[0], q[1], q[2]];
+    let t = cross(axis, v).map(|c| c * 2.0);
+    let u = cross(axis, t);
+    std::array::from_fn(|i| v[i] + q[3] * t[i] + u[i])
+}
+
+fn unproject(inverse: &[[f32; 4]; 4], ndc: [f32; 3]) -> Option<[f32; 3]> {
+    let m = inverse;
+    let world: [f32; 4] = std::array::from_fn(|row| {
+        m[0][row] * ndc[0] + m[1][row] * ndc[1] + m[2][row] * ndc[2] + m[3][row]
+    });
+    (world[3].abs() > f32::EPSILON).then(|| [world[0], world[1], world[2]].map(|c| c / world[3]))
+}
+
+// Inverse of a column major 4x4 matrix by cofactors, None if it's singular.
+pub(crate) fn invert(m: &[[f32; 4]; 4]) -> Option<[[f32; 4]; 4]> {
+    let a: [f32; 16] = std::array::from_fn(|i| m[i / 4][i % 4]);
+    let mut inv = [0.0f32; 16];
+    inv[0] = a[5] * a[10] * a[15] - a[5] * a[11] * a[14] - a[9] * a[6] * a[15]
+        + a[9] * a[7] * a[14]
+        + a[13] * a[6] * a[11]
+        - a[13] * a[7] * a[10];
+    inv[4] = -a[4] * a[10] * a[15] + a[4] * a[11] * a[14] + a[8] * a[6] * a[15]
+        - a[8] * a[7] * a[14]
+        - a[12] * a[6] * a[11]
+        + a[12] * a[7] * a[10];
+    inv[8] = a[4] * a[9] * a[15] - a[4] * a[11] * a[13] - a[8] * a[5] * a[15]
+        + a[8] * a[7] * a[13]
+        + a[12] * a[5] * a[11]
+        - a[12] * a[7] * a[9];
+    inv[12] = -a[4] * a[9] * a[14] + a[4] * a[10] * a[13] + a[8] * a[5] * a[14]
+        - a[8] * a[6] * a[13]
+        - a[12] * a[5] * a[10]
+        + a[12] * a[6] * a[9];
+    inv[1] = -a[1] * a[10] * a[15] + a[1] * a[11] * a[14] + a[9] * a[2] * a[15]
+        - a[9] * a[3] * a[14]
+        - a[13] * a[2] * a[11]
+        + a[13] * a[3] * a[10];
+    inv[5] = a[0] * a[10] * a[15] - a[0] * a[11] * a[14] - a[8] * a[2] * a[15]
+        + a[8] * a[3] * a[14]
+        + a[12] * a[2] * a[11]
+        - a[12] * a[3] * a[10];
+    inv[9] = -a[0] * a[9] * a[15] + a[0] * a[11] * a[13] + a[8] * a[1] * a[15]
+        - a[8] * a[3] * a[13]
+        - a[12] * a[1] * a[11]
+        + a[12] * a[3] * a[9];
+    inv[13] = a[0] * a[9] * a[14] - a[0] * a[10] * a[13] - a[8] * a[1] * a[14]
+        + a[8] * a[2] * a[13]
+        + a[12] * a[1] * a[10]
+        - a[12] * a[2] * a[9];
+    inv[2] = a[1] * a[6] * a[15] - a[1] * a[7] * a[14] - a[5] * a[2] * a[15]
+        + a[5] * a[3] * a[14]
+        + a[13] * a[2] * a[7]
+        - a[13] * a[3] * a[6];
+    inv[6] = -a[0] * a[6] * a[15] + a[0] * a[7] * a[14] + a[4] * a[2] * a[15]
+        - a[4] * a[3] * a[14]
+        - a[12] * a[2] * a[7]
+        + a[12] * a[3] * a[6];
+    inv[10] = a[0] * a[5] * a[15] - a[0] * a[7] * a[13] - a[4] * a[1] * a[15]
+        + a[4] * a[3] * a[13]
+        + a[12] * a[1] * a[7]
+        - a[12] * a[3] * a[5];
+    inv[14] = -a[0] * a[5] * a[14] + a[0] * a[6] * a[13] + a[4] * a[1] * a[14]
+        - a[4] * a[2] * a[13]
+        - a[12] * a[1] * a[6]
+        + a[12] * a[2] * a[5];
+    inv[3] = -a[1] * a[6] * a[11] + a[1] * a[7] * a[10] + a[5] * a[2] * a[11]
+        - a[5] * a[3] * a[10]
+        - a[9] * a[2] * a[7]
+        + a[9] * a[3] * a[6];
+    inv[7] = a[0] * a[6] * a[11] - a[0] * a[7] * a[10] - a[4] * a[2] * a[11]
+        + a[4] * a[3] * a[10]
+        + a[8] * a[2] * a[7]
+        - a[8] * a[3] * a[6];
+    inv[11] = -a[0] * a[5] * a[11] + a[0] * a[7] * a[9] + a[4] * a[1] * a[11]
+        - a[4] * a[3] * a[9]
+        - a[8] * a[1] * a[7]
+        + a[8] * a[3] * a[5];
+    inv[15] = a[0] * a[5] * a[10] - a[0] * a[6] * a[9] - a[4] * a[1] * a[10]
+        + a[4] * a[2] * a[9]
+        + a[8] * a[1] * a[6]
+        - a[8] * a[2] * a[5];
+    let det = a[0] * inv[0] + a[1] * inv[4] + a[2] * inv[8] + a[3] * inv[12];
+    if det.abs() <= f32::EPSILON * f32::EPSILON {
+        return None;
+    }
+    Some(std::array::from_fn(|column| {
+        std::array::from_fn(|row| inv[column * 4 + row] / det)
+    }))
+}
+
+pub struct PickingPlugin;
+
+impl Plugin for PickingPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(Picking::default())
+            .add_system(|resources: &mut Resources| {
+                let Some(picking) = resources.get_mut::<Picking>() else {
+                    return;
+                };
+                picking.resolve();
+                metrics::set_gauge("pickables", picking.len() as f64);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Orthographic, looking down -z, one world unit is 100 pixels and depth covers z 10 to -10.
+    fn camera() -> EditorCamera {
+        EditorCamera {
+            view_projection: [
+                [0.2, 0.0, 0.0, 0.0],
+                [0.0, 0.2, 0.0, 0.0],
+                [0.0, 0.0, -0.05, 0.0],
+                [0.0, 0.0, 0.5, 1.0],
+            ],
+            viewport: [1000.0, 1000.0],
+        }
+    }
+
+    #[test]
+    fn picks_the_closest_entity_under_the_cursor() {
+        let mut picking = Picking::default();
+        picking.set_camera(camera());
+        let (near, far, aside) = (
+            SnowflakeId::from_bits(1),
+            SnowflakeId::from_bits(2),
+            SnowflakeId::from_bits(3),
+        );
+        let at = |translation: [f32; 3]| {
+            Aabb::from_transform(&Transform {
+                translation,
+                ..Transform::default()
+            })
+        };
+        picking.insert(far, at([0.0, 0.0, -3.0]));
+        picking.insert(near, at([0.0, 0.0, 2.0]));
+        picking.insert(aside, at([3.0, 1.0, 0.0]));
+
+        let center = picking.pick(500.0, 500.0);
+        let right = picking.pick(800.0, 400.0);
+        let empty = picking.pick(100.0, 100.0);
+        assert_eq!(picking.poll_event(), None);
+        picking.resolve();
+
+        let hit = picking.poll_event().unwrap();
+        assert_eq!(hit.id, center);
+        let hit = hit.hit.unwrap();
+        assert_eq!(hit.entity, near);
+        assert!((hit.point[2] - 2.5).abs() < 1e-4);
+        let hit = picking.poll_event().unwrap();
+        assert_eq!(
+            (hit.id, hit.hit.map(|hit| hit.entity)),
+            (right, Some(aside))
+        );
+        assert_eq!(
+            picking.poll_event(),
+            Some(PickEvent {
+                id: empty,
+                hit: None
+            })
+        );
+
+        // Rotated 45 degrees around z the box reaches further out along x.
+        let (sin, cos) = std::f32::consts::FRAC_PI_8.sin_cos();
+        let rotated = Aabb::from_transform(&Transform {
+            rotation: [0.0, 0.0, sin, cos],
+            ..Transform::default()
+        });
+        assert!((rotated.max[0] - 0.5f32.hypot(0.5)).abs() < 1e-5);
+    }
+}
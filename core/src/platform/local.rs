@@ -0,0 +1,135 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fs,
+    path::PathBuf,
+};
+
+use super::{PlatformServices, UserInfo};
+
+const PROGRESS_FILE: &str = "progress.txt";
+
+// Keeps achievements and stats in a plain text file in the per-user data directory.
+pub struct LocalPlatform {
+    data_dir: PathBuf,
+    achievements: BTreeSet<String>,
+    stats: BTreeMap<String, i64>,
+    dirty: bool,
+}
+
+impl LocalPlatform {
+    pub fn new(app_name: &str) -> Self {
+        Self::with_data_dir(user_data_dir().join(app_name))
+    }
+
+    pub fn with_data_dir(data_dir: PathBuf) -> Self {
+        let mut platform = Self {
+            data_dir,
+            achievements: BTreeSet::new(),
+            stats: BTreeMap::new(),
+            dirty: false,
+        };
+        platform.load();
+        platform
+    }
+
+    fn load(&mut self) {
+        let Ok(contents) = fs::read_to_string(self.data_dir.join(PROGRESS_FILE)) else {
+            return;
+        };
+        for line in contents.lines() {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some("achievement"), Some(id), None) => {
+                    self.achievements.insert(id.to_owned());
+                }
+                (Some("stat"), Some(name), Some(value)) => match value.parse() {
+                    Ok(value) => {
+                        self.stats.insert(name.to_owned(), value);
+                    }
+                    Err(_) => warn!("Ignoring malformed stat line: {}", line),
+                },
+                _ => warn!("Ignoring malformed progress line: {}", line),
+            }
+        }
+    }
+}
+
+impl PlatformServices for LocalPlatform {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    fn user(&self) -> UserInfo {
+        let login = env::var("USERNAME")
+            .or_else(|_| env::var("USER"))
+            .unwrap_or_else(|_| "player".to_owned());
+        UserInfo {
+            id: login.clone(),
+            display_name: login,
+        }
+    }
+
+    fn unlock_achievement(&mut self, id: &str) -> bool {
+        let unlocked = self.achievements.insert(id.to_owned());
+        if unlocked {
+            info!("Achievement unlocked: {}", id);
+            self.dirty = true;
+        }
+        unlocked
+    }
+
+    fn is_achievement_unlocked(&self, id: &str) -> bool {
+        self.achievements.contains(id)
+    }
+
+    fn set_stat(&mut self, name: &str, value: i64) {
+        if self.stats.insert(name.to_owned(), value) != Some(value) {
+            self.dirty = true;
+        }
+    }
+
+    fn stat(&self, name: &str) -> Option<i64> {
+        self.stats.get(name).copied()
+    }
+
+    fn save_directory(&self) -> PathBuf {
+        self.data_dir.join("saves")
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut contents = String::new();
+        for id in &self.achievements {
+            contents += &format!("achievement {}\n", id);
+        }
+        for (name, value) in &self.stats {
+            contents += &format!("stat {} {}\n", name, value);
+        }
+        fs::create_dir_all(&self.data_dir)?;
+        fs::write(self.data_dir.join(PROGRESS_FILE), contents)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Drop for LocalPlatform {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            error!("Failed to write local progress: {}", err);
+        }
+    }
+}
+
+fn user_data_dir() -> PathBuf {
+    let from_env = |name: &str| env::var_os(name).map(PathBuf::from);
+    if cfg!(windows) {
+        from_env("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        from_env("HOME").map(|home| home.join("Library/Application Support"))
+    } else {
+        from_env("XDG_DATA_HOME").or_else(|| from_env("HOME").map(|home| home.join(".local/share")))
+    }
+    .unwrap_or_else(|| PathBuf::from("."))
+}
@@ -0,0 +1,83 @@
+//! Platform services (user identity, achievements, stats, save locations) behind a trait, so game
+//! code never talks to a storefront SDK directly.
+
+mod local;
+
+use std::path::PathBuf;
+
+use crate::engine::{Engine, Plugin};
+
+pub use local::LocalPlatform;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserInfo {
+    // Stable id from the platform, e.g. a Steam id. Local users just get their login name.
+    pub id: String,
+    pub display_name: String,
+}
+
+// Implement this for each storefront (Steamworks, console SDKs...), `LocalPlatform` is the
+// fallback used when no storefront is available.
+pub trait PlatformServices: Send {
+    fn name(&self) -> &str;
+
+    fn user(&self) -> UserInfo;
+
+    // Returns true if the achievement was newly unlocked.
+    fn unlock_achievement(&mut self, id: &str) -> bool;
+
+    fn is_achievement_unlocked(&self, id: &str) -> bool;
+
+    fn set_stat(&mut self, name: &str, value: i64);
+
+    fn stat(&self, name: &str) -> Option<i64>;
+
+    fn add_stat(&mut self, name: &str, delta: i64) -> i64 {
+        let value = self.stat(name).unwrap_or(0) + delta;
+        self.set_stat(name, value);
+        value
+    }
+
+    // Directory save games should be written to, platforms with cloud saves sync this folder.
+    fn save_directory(&self) -> PathBuf;
+
+    // Pushes pending achievements/stats to the backing store.
+    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+/// Resource holding the active platform implementation.
+pub struct Platform(pub Box<dyn PlatformServices>);
+
+impl std::ops::Deref for Platform {
+    type Target = dyn PlatformServices;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl std::ops::DerefMut for Platform {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0.as_mut()
+    }
+}
+
+/// Inserts a `Platform` resource backed by `LocalPlatform`, unless something else already did.
+pub struct PlatformPlugin {
+    pub app_name: String,
+}
+
+impl Plugin for PlatformPlugin {
+    fn build(&self, engine: &mut Engine) {
+        if engine.resources().contains::<Platform>() {
+            return;
+        }
+        let platform = LocalPlatform::new(&self.app_name);
+        info!(
+            "Using {} platform services, saving to {}",
+            platform.name(),
+            platform.save_directory().display()
+        );
+        engine.insert_resource(Platform(Box::new(platform)));
+    }
+}
@@ -0,0 +1,388 @@
+//! Scripted input timelines. A timeline replaces window input with events read from a file and
+//! drives the sim headless, one tick at a time, so gameplay can be tested without a window.
+//!
+//! One event per line, prefixed with the tick it is delivered on. An optional `seed` line makes
+//! the run's random numbers (see `rand`) the same every time:
+//!
+//! ```text
+//! seed 1234
+//! # tick event  args
+//! 0      cursor 640 360
+//! 10     key    KeyW down
+//! 40     key    KeyW up
+//! 50     button Left down
+//! 55     wheel  0 -20
+//! 56     text   hello world
+//! 60     touch  1 start 100 200
+//! 60     check  ui 9c1e0f6a3b2d4e58
+//! 120    quit
+//! ```
+//!
+//! `#` starts a comment at the start of a line or after whitespace, so `text` can hold `a#b` but
+//! not `a #b`. `check` lines are checkpoints written by `regression` recordings, they are skipped
+//! when playing back and compared when verifying.
+
+use std::{fmt, fs, path::Path};
+
+use winit::event::TouchPhase;
+
+use crate::{
+    app::Application,
+    engine::Engine,
+    input::{self, InputEvent},
+    rand::Random,
+    sim::{SimEvent, SimLoop},
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TimelineAction {
+    Input(InputEvent),
+    // A capture's hash at this tick, see `regression`.
+    Checkpoint { name: String, hash: u64 },
+    Quit,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct InputTimeline {
+    // Sorted by tick, events on the same tick keep their file order.
+    entries: Vec<(u64, TimelineAction)>,
+    // Seeds `Random` before the first tick.
+    pub seed: Option<u64>,
+}
+
+impl InputTimeline {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read timeline {}: {}", path.display(), err))?;
+        Self::parse(&source)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut entries = Vec::new();
+        let mut seed = None;
+        for (index, line) in source.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(value) = line.strip_prefix("seed ") {
+                let value = parse_number(value.trim())
+                    .map_err(|err| format!("Timeline line {}: {}", index + 1, err))?;
+                seed = Some(value);
+                continue;
+            }
+            let entry = parse_line(line)
+                .map_err(|err| format!("Timeline line {}: {} ({})", index + 1, err, line))?;
+            entries.push(entry);
+        }
+        entries.sort_by_key(|(tick, _)| *tick);
+        Ok(Self { entries, seed })
+    }
+
+    pub fn push(&mut self, tick: u64, action: TimelineAction) {
+        let index = self.entries.partition_point(|(at, _)| *at <= tick);
+        self.entries.insert(index, (tick, action));
+    }
+
+    pub fn entries(&self) -> &[(u64, TimelineAction)] {
+        &self.entries
+    }
+
+    pub fn last_tick(&self) -> u64 {
+        self.entries.last().map_or(0, |(tick, _)| *tick)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        fs::write(path, self.to_string())
+            .map_err(|err| format!("Failed to write timeline {}: {}", path.display(), err))?;
+        Ok(())
+    }
+}
+
+// Writes the timeline back out in the format `parse` reads.
+impl fmt::Display for InputTimeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(seed) = self.seed {
+            writeln!(f, "seed {}", seed)?;
+        }
+        for (tick, action) in &self.entries {
+            match action {
+                TimelineAction::Input(event) => match format_input(event) {
+                    Some(line) => writeln!(f, "{} {}", tick, line)?,
+                    // Kept as a comment so the file still says something was there.
+                    None => writeln!(f, "# {} {:?}", tick, event)?,
+                },
+                TimelineAction::Checkpoint { name, hash } => {
+                    writeln!(f, "{} check {} {:016x}", tick, name, hash)?
+                }
+                TimelineAction::Quit => writeln!(f, "{} quit", tick)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// None for events the format can't express, keys without a name and text that would be cut short.
+fn format_input(event: &InputEvent) -> Option<String> {
+    let state = |pressed: bool| if pressed { "down" } else { "up" };
+    Some(match event {
+        InputEvent::Key { code, pressed } => {
+            format!("key {} {}", input::key_name(*code)?, state(*pressed))
+        }
+        InputEvent::Text(text) => {
+            if text.trim() != text || strip_comment(text) != text || text.contains(['\n', '\r']) {
+                return None;
+            }
+            format!("text {}", text)
+        }
+        InputEvent::CursorMoved { x, y } => format!("cursor {} {}", x, y),
+        InputEvent::MouseButton { button, pressed } => format!(
+            "button {} {}",
+            input::mouse_button_name(*button),
+            state(*pressed)
+        ),
+        InputEvent::MouseWheel { dx, dy } => format!("wheel {} {}", dx, dy),
+        InputEvent::Touch { id, phase, x, y } => {
+            let phase = match phase {
+                TouchPhase::Started => "start",
+                TouchPhase::Moved => "move",
+                TouchPhase::Ended => "end",
+                TouchPhase::Cancelled => "cancel",
+            };
+            format!("touch {} {} {} {}", id, phase, x, y)
+        }
+    })
+}
+
+fn parse_line(line: &str) -> Result<(u64, TimelineAction), String> {
+    let mut parts = line.split_whitespace();
+    let mut next = |what: &str| parts.next().ok_or_else(|| format!("missing {}", what));
+
+    let tick = next("tick")?.parse().map_err(|_| "tick is not a number")?;
+    let kind = next("event")?;
+    let action = match kind {
+        "quit" => TimelineAction::Quit,
+        "check" => {
+            let name = next("checkpoint name")?.to_owned();
+            let hash = next("hash")?;
+            let hash =
+                u64::from_str_radix(hash, 16).map_err(|_| format!("{} isn't a hash", hash))?;
+            TimelineAction::Checkpoint { name, hash }
+        }
+        // Everything after the event name, so text can contain spaces.
+        "text" => {
+            let text = skip_token(skip_token(line));
+            return Ok((
+                tick,
+                TimelineAction::Input(InputEvent::Text(text.trim().to_owned())),
+            ));
+        }
+        "key" => {
+            let name = next("key name")?;
+            let code = input::key_from_name(name).ok_or_else(|| format!("unknown key {}", name))?;
+            let pressed = parse_pressed(next("down/up")?)?;
+            TimelineAction::Input(InputEvent::Key { code, pressed })
+        }
+        "button" => {
+            let name = next("button name")?;
+            let button = input::mouse_button_from_name(name)
+                .ok_or_else(|| format!("unknown mouse button {}", name))?;
+            let pressed = parse_pressed(next("down/up")?)?;
+            TimelineAction::Input(InputEvent::MouseButton { button, pressed })
+        }
+        "cursor" => TimelineAction::Input(InputEvent::CursorMoved {
+            x: parse_number(next("x")?)?,
+            y: parse_number(next("y")?)?,
+        }),
+        "wheel" => TimelineAction::Input(InputEvent::MouseWheel {
+            dx: parse_number(next("dx")?)?,
+            dy: parse_number(next("dy")?)?,
+        }),
+        "touch" => {
+            let id = parse_number(next("touch id")?)?;
+            let phase = match next("touch phase")? {
+                "start" => TouchPhase::Started,
+                "move" => TouchPhase::Moved,
+                "end" => TouchPhase::Ended,
+                "cancel" => TouchPhase::Cancelled,
+                other => return Err(format!("unknown touch phase {}", other)),
+            };
+            TimelineAction::Input(InputEvent::Touch {
+                id,
+                phase,
+                x: parse_number(next("x")?)?,
+                y: parse_number(next("y")?)?,
+            })
+        }
+        other => return Err(format!("unknown event {}", other)),
+    };
+    match parts.next() {
+        Some(extra) => Err(format!("unexpected {}", extra)),
+        None => Ok((tick, action)),
+    }
+}
+
+// `line` without its first whitespace separated token.
+fn skip_token(line: &str) -> &str {
+    line.trim_start()
+        .split_once(char::is_whitespace)
+        .map_or("", |(_, rest)| rest)
+}
+
+// Cuts `line` at a `#` that starts the line or follows whitespace.
+fn strip_comment(line: &str) -> &str {
+    let mut previous: Option<char> = None;
+    for (index, c) in line.char_indices() {
+        if c == '#' && previous.is_none_or(char::is_whitespace) {
+            return &line[..index];
+        }
+        previous = Some(c);
+    }
+    line
+}
+
+fn parse_pressed(state: &str) -> Result<bool, String> {
+    match state {
+        "down" => Ok(true),
+        "up" => Ok(false),
+        other => Err(format!("expected down or up, got {}", other)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} is not a number", value))
+}
+
+/// Runs `app` without a window, feeding it `timeline` and stepping the sim as fast as possible
+/// until the timeline quits or runs out. Returns the number of ticks simulated.
+pub fn play<A: Application>(app: A, engine: Engine, timeline: &InputTimeline) -> u64 {
+    let mut sim_loop = start(app, engine, timeline);
+    let ticks = drive(&mut sim_loop, timeline);
+    sim_loop.shutdown();
+    ticks
+}
+
+// A headless sim seeded for `timeline`.
+pub(crate) fn start<A: Application>(
+    app: A,
+    mut engine: Engine,
+    timeline: &InputTimeline,
+) -> SimLoop<A> {
+    if let Some(seed) = timeline.seed {
+        match engine.resources_mut().get_mut::<Random>() {
+            Some(random) => random.reseed(seed),
+            None => {
+                engine.insert_resource(Random::new(seed));
+            }
+        }
+    }
+    SimLoop::headless(app, engine)
+}
+
+// Steps `sim_loop` through `timeline`, the caller shuts it down.
+pub(crate) fn drive<A: Application>(sim_loop: &mut SimLoop<A>, timeline: &InputTimeline) -> u64 {
+    let mut pending = timeline.entries().iter().peekable();
+    let mut tick = 0;
+    while tick <= timeline.last_tick() {
+        while let Some((_, action)) = pending.next_if(|(at, _)| *at <= tick) {
+            match action {
+                TimelineAction::Input(event) => sim_loop.send(SimEvent::Input(event.clone())),
+                TimelineAction::Checkpoint { .. } => {}
+                TimelineAction::Quit => return tick,
+            }
+        }
+        sim_loop.step();
+        tick += 1;
+    }
+    tick
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use winit::keyboard::KeyCode;
+
+    #[derive(Default)]
+    struct Recorder {
+        ticks: u64,
+        inputs: Arc<Mutex<Vec<(u64, InputEvent)>>>,
+    }
+
+    impl Application for Recorder {
+        fn fixed_update(&mut self, _engine: &mut Engine, _dt: std::time::Duration) {
+            self.ticks += 1;
+        }
+
+        fn handle_input(&mut self, _engine: &mut Engine, event: &InputEvent) {
+            self.inputs
+                .lock()
+                .unwrap()
+                .push((self.ticks, event.clone()));
+        }
+    }
+
+    #[test]
+    fn plays_timeline_in_tick_order() {
+        let timeline = InputTimeline::parse(
+            "# comment
+             seed 99
+             5 key KeyW up
+             2 key KeyW down
+             3 cursor 10.5 20
+             9 quit
+             12 key Space down",
+        )
+        .unwrap();
+
+        let recorder = Recorder::default();
+        let inputs = recorder.inputs.clone();
+        assert_eq!(timeline.seed, Some(99));
+        assert_eq!(play(recorder, Engine::new(), &timeline), 9);
+
+        let key_w = |pressed| InputEvent::Key {
+            code: KeyCode::KeyW,
+            pressed,
+        };
+        let cursor = InputEvent::CursorMoved { x: 10.5, y: 20.0 };
+        assert_eq!(
+            *inputs.lock().unwrap(),
+            vec![(2, key_w(true)), (3, cursor), (5, key_w(false))]
+        );
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        let err = InputTimeline::parse("1 key Nope down").unwrap_err();
+        assert!(err.to_string().contains("line 1"));
+        assert!(InputTimeline::parse("1 cursor 10").is_err());
+        assert!(InputTimeline::parse("1 quit now").is_err());
+    }
+
+    #[test]
+    fn parses_text_in_aligned_columns() {
+        let timeline = InputTimeline::parse(
+            "56     text   hello world
+             57     text   press#1 # comment",
+        )
+        .unwrap();
+        let text = |text: &str| TimelineAction::Input(InputEvent::Text(text.to_owned()));
+        assert_eq!(
+            timeline.entries(),
+            [(56, text("hello world")), (57, text("press#1"))]
+        );
+
+        let mut timeline = InputTimeline::default();
+        timeline.push(1, text("a#b"));
+        timeline.push(2, text("a #b"));
+        let written = timeline.to_string();
+        assert_eq!(
+            InputTimeline::parse(&written).unwrap().entries(),
+            [(1, text("a#b"))]
+        );
+    }
+}
@@ -0,0 +1,533 @@
+//! Render targets seen through surfaces in the world: security monitors showing what a fixed
+//! camera sees, mirrors and simple portals. Each surface is a quad that samples a render target,
+//! and the scene is rendered into that target from a second camera before the view that sees the
+//! surface. Surfaces seen from those cameras recurse, up to a depth limit, where the renderer
+//! falls back to the target's clear color.
+//!
+//! `Portals` works out which passes a frame needs and the cameras and resolutions for them. The
+//! renderer can't render into offscreen targets yet, so nothing consumes the passes so far.
+
+use std::fmt;
+
+use crate::{
+    editor::{EditorCamera, Transform},
+    engine::{Engine, Plugin, Resources},
+    metrics,
+    picking::{invert, rotate},
+    sim::Viewport,
+};
+
+pub type Matrix = [[f32; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+const DEFAULT_MAX_DEPTH: usize = 2;
+const DEFAULT_MAX_PASSES: usize = 16;
+
+// Column major, right handed looking down -z, depth 0..1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct View {
+    pub view: Matrix,
+    pub projection: Matrix,
+}
+
+impl View {
+    pub fn look_at(eye: [f32; 3], target: [f32; 3], up: [f32; 3], projection: Matrix) -> Self {
+        let forward = normalize(sub(target, eye));
+        let side = normalize(cross(forward, up));
+        let up = cross(side, forward);
+        Self {
+            view: [
+                [side[0], up[0], -forward[0], 0.0],
+                [side[1], up[1], -forward[1], 0.0],
+                [side[2], up[2], -forward[2], 0.0],
+                [-dot(side, eye), -dot(up, eye), dot(forward, eye), 1.0],
+            ],
+            projection,
+        }
+    }
+
+    // `fov_y` in radians.
+    pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Matrix {
+        let f = 1.0 / (fov_y * 0.5).tan();
+        [
+            [f / aspect, 0.0, 0.0, 0.0],
+            [0.0, f, 0.0, 0.0],
+            [0.0, 0.0, far / (near - far), -1.0],
+            [0.0, 0.0, near * far / (near - far), 0.0],
+        ]
+    }
+
+    pub fn view_projection(&self) -> Matrix {
+        multiply(&self.projection, &self.view)
+    }
+
+    // Where the camera is in the world.
+    pub fn position(&self) -> [f32; 3] {
+        let world = invert(&self.view).unwrap_or(IDENTITY);
+        [world[3][0], world[3][1], world[3][2]]
+    }
+
+    pub fn editor_camera(&self, viewport: [f32; 2]) -> EditorCamera {
+        EditorCamera {
+            view_projection: self.view_projection(),
+            viewport,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RenderTargetId(u64);
+
+impl fmt::Display for RenderTargetId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "target#{}", self.0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderTarget {
+    // Of the window's resolution, a small monitor doesn't need it all.
+    pub scale: f32,
+    // Shown past the recursion limit.
+    pub clear_color: [f32; 4],
+}
+
+impl Default for RenderTarget {
+    fn default() -> Self {
+        Self {
+            scale: 0.5,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SurfaceKind {
+    // Shows what a fixed camera sees, wherever the viewer is.
+    Monitor(View),
+    Mirror,
+    // Looks out of `exit` as if the viewer had stepped through. The exit's front is the side
+    // things come out of.
+    Portal { exit: Transform },
+}
+
+// A quad a unit across in its local x and y, scaled by the transform, seen from its +z side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Surface {
+    pub transform: Transform,
+    pub target: RenderTargetId,
+    pub kind: SurfaceKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SurfaceId(u64);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TargetPass {
+    pub surface: SurfaceId,
+    pub target: RenderTargetId,
+    // 1 for surfaces the main camera sees. A target needs a texture per depth it's seen at.
+    pub depth: usize,
+    // The pass whose view sees this surface, None for the main view. Always later in the list.
+    pub parent: Option<usize>,
+    pub size: [u32; 2],
+    pub view: View,
+    // World space plane, geometry with a·p + d < 0 is behind the surface and must be clipped.
+    pub clip_plane: Option<[f32; 4]>,
+    // Mirrors turn triangles inside out, seen in a second mirror they're the right way again.
+    pub flip_winding: bool,
+}
+
+pub struct Portals {
+    targets: Vec<(RenderTargetId, RenderTarget)>,
+    surfaces: Vec<(SurfaceId, Surface)>,
+    next_id: u64,
+    camera: Option<View>,
+    max_depth: usize,
+    max_passes: usize,
+    passes: Vec<TargetPass>,
+}
+
+impl Default for Portals {
+    fn default() -> Self {
+        Self {
+            targets: Vec::new(),
+            surfaces: Vec::new(),
+            next_id: 0,
+            camera: None,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_passes: DEFAULT_MAX_PASSES,
+            passes: Vec::new(),
+        }
+    }
+}
+
+impl Portals {
+    pub fn add_target(&mut self, target: RenderTarget) -> RenderTargetId {
+        let id = RenderTargetId(self.next_id());
+        self.targets.push((id, target));
+        id
+    }
+
+    // Also removes the surfaces showing it.
+    pub fn remove_target(&mut self, id: RenderTargetId) {
+        self.targets.retain(|(target, _)| *target != id);
+        self.surfaces.retain(|(_, surface)| surface.target != id);
+    }
+
+    pub fn target(&self, id: RenderTargetId) -> Option<&RenderTarget> {
+        self.targets
+            .iter()
+            .find(|(target, _)| *target == id)
+            .map(|(_, target)| target)
+    }
+
+    pub fn target_mut(&mut self, id: RenderTargetId) -> Option<&mut RenderTarget> {
+        self.targets
+            .iter_mut()
+            .find(|(target, _)| *target == id)
+            .map(|(_, target)| target)
+    }
+
+    pub fn add_surface(&mut self, surface: Surface) -> Result<SurfaceId, String> {
+        if self.target(surface.target).is_none() {
+            return Err(format!("{} doesn't exist", surface.target));
+        }
+        let id = SurfaceId(self.next_id());
+        self.surfaces.push((id, surface));
+        Ok(id)
+    }
+
+    pub fn remove_surface(&mut self, id: SurfaceId) -> Option<Surface> {
+        let index = self
+            .surfaces
+            .iter()
+            .position(|(surface, _)| *surface == id)?;
+        Some(self.surfaces.remove(index).1)
+    }
+
+    pub fn surface_mut(&mut self, id: SurfaceId) -> Option<&mut Surface> {
+        self.surfaces
+            .iter_mut()
+            .find(|(surface, _)| *surface == id)
+            .map(|(_, surface)| surface)
+    }
+
+    // Set by the game every frame, the main view the passes start from.
+    pub fn set_camera(&mut self, camera: View) {
+        self.camera = Some(camera);
+    }
+
+    // How many surfaces deep a view can see through others, 0 turns them all off.
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    // Caps the passes a frame can take however many surfaces see each other.
+    pub fn set_max_passes(&mut self, passes: usize) {
+        self.max_passes = passes;
+    }
+
+    // This frame's passes from the last `update`, in the order to render them.
+    pub fn passes(&self) -> &[TargetPass] {
+        &self.passes
+    }
+
+    pub fn update(&mut self, viewport: [f32; 2]) {
+        self.passes = match self.camera {
+            Some(camera) => self.plan(&camera, viewport),
+            None => Vec::new(),
+        };
+    }
+
+    // Every pass needed to draw the surfaces `camera` sees, deepest first so each target is
+    // rendered before the views that sample it.
+    pub fn plan(&self, camera: &View, viewport: [f32; 2]) -> Vec<TargetPass> {
+        let mut passes = Vec::new();
+        // Breadth first, so the pass limit cuts off the deepest levels rather than one branch.
+        let mut level = vec![(None, *camera, false)];
+        for depth in 1..=self.max_depth {
+            let mut next = Vec::new();
+            for (parent, view, parent_flip) in level {
+                let view_projection = view.view_projection();
+                let eye = view.position();
+                for (id, surface) in &self.surfaces {
+                    if passes.len() >= self.max_passes {
+                        break;
+                    }
+                    let Some(target) = self.target(surface.target) else {
+                        continue;
+                    };
+                    if !surface.faces(eye) || !surface.in_view(&view_projection) {
+                        continue;
+                    }
+                    let (view, clip_plane, flip) = surface.view_through(&view);
+                    // A reflection of a reflection is the right way out again.
+                    let flip_winding = parent_flip ^ flip;
+                    // Halved per level, the exponent capped before it's past anything an f32 holds.
+                    let scale = target.scale * 0.5f32.powi((depth - 1).min(64) as i32);
+                    passes.push(TargetPass {
+                        surface: *id,
+                        target: surface.target,
+                        depth,
+                        parent,
+                        size: viewport.map(|size| ((size * scale) as u32).max(1)),
+                        view,
+                        clip_plane,
+                        flip_winding,
+                    });
+                    next.push((Some(passes.len() - 1), view, flip_winding));
+                }
+            }
+            level = next;
+        }
+        // Reversed, so parents come after their children.
+        let count = passes.len();
+        passes.reverse();
+        for pass in &mut passes {
+            pass.parent = pass.parent.map(|parent| count - 1 - parent);
+        }
+        passes
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+}
+
+impl Surface {
+    fn matrix(&self) -> Matrix {
+        matrix(&self.transform)
+    }
+
+    fn normal(&self) -> [f32; 3] {
+        normalize(rotate(self.transform.rotation, [0.0, 0.0, 1.0]))
+    }
+
+    fn faces(&self, eye: [f32; 3]) -> bool {
+        dot(sub(eye, self.transform.translation), self.normal()) > 0.0
+    }
+
+    // Conservative, only false if every corner is outside the same clip plane.
+    fn in_view(&self, view_projection: &Matrix) -> bool {
+        let world = multiply(view_projection, &self.matrix());
+        let corners = [[-0.5, -0.5], [0.5, -0.5], [-0.5, 0.5], [0.5, 0.5]]
+            .map(|[x, y]| transform(&world, [x, y, 0.0, 1.0]));
+        let outside = |test: &dyn Fn(&[f32; 4]) -> bool| corners.iter().all(test);
+        !(outside(&|c| c[3] <= 0.0)
+            || outside(&|c| c[0] < -c[3])
+            || outside(&|c| c[0] > c[3])
+            || outside(&|c| c[1] < -c[3])
+            || outside(&|c| c[1] > c[3])
+            || outside(&|c| c[2] > c[3]))
+    }
+
+    fn view_through(&self, view: &View) -> (View, Option<[f32; 4]>, bool) {
+        match self.kind {
+            SurfaceKind::Monitor(camera) => (camera, None, false),
+            SurfaceKind::Mirror => {
+                let normal = self.normal();
+                let d = -dot(normal, self.transform.translation);
+                let mut reflect = IDENTITY;
+                for (column, values) in reflect.iter_mut().enumerate().take(3) {
+                    for (row, value) in values.iter_mut().enumerate().take(3) {
+                        *value -= 2.0 * normal[row] * normal[column];
+                    }
+                }
+                reflect[3] = [
+                    -2.0 * d * normal[0],
+                    -2.0 * d * normal[1],
+                    -2.0 * d * normal[2],
+                    1.0,
+                ];
+                let view = View {
+                    view: multiply(&view.view, &reflect),
+                    projection: view.projection,
+                };
+                (view, Some([normal[0], normal[1], normal[2], d]), true)
+            }
+            SurfaceKind::Portal { exit } => {
+                // Into the entrance's space, turned around to come out of the exit's front.
+                let turn = [
+                    [-1.0, 0.0, 0.0, 0.0],
+                    [0.0, 1.0, 0.0, 0.0],
+                    [0.0, 0.0, -1.0, 0.0],
+                    [0.0, 0.0, 0.0, 1.0],
+                ];
+                let to_exit = invert(&matrix(&exit)).unwrap_or(IDENTITY);
+                let view = View {
+                    view: multiply(
+                        &view.view,
+                        &multiply(&self.matrix(), &multiply(&turn, &to_exit)),
+                    ),
+                    projection: view.projection,
+                };
+                let normal = normalize(rotate(exit.rotation, [0.0, 0.0, 1.0]));
+                let d = -dot(normal, exit.translation);
+                (view, Some([normal[0], normal[1], normal[2], d]), false)
+            }
+        }
+    }
+}
+
+fn matrix(transform: &Transform) -> Matrix {
+    let mut m = IDENTITY;
+    for (axis, column) in m.iter_mut().enumerate().take(3) {
+        let mut unit = [0.0; 3];
+        unit[axis] = transform.scale[axis];
+        let rotated = rotate(transform.rotation, unit);
+        column[..3].copy_from_slice(&rotated);
+    }
+    m[3] = [
+        transform.translation[0],
+        transform.translation[1],
+        transform.translation[2],
+        1.0,
+    ];
+    m
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    std::array::from_fn(|column| {
+        std::array::from_fn(|row| (0..4).map(|k| a[k][row] * b[column][k]).sum())
+    })
+}
+
+fn transform(m: &Matrix, v: [f32; 4]) -> [f32; 4] {
+    std::array::from_fn(|row| (0..4).map(|k| m[k][row] * v[k]).sum())
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let length = dot(a, a).sqrt();
+    if length <= f32::EPSILON {
+        return [0.0; 3];
+    }
+    a.map(|c| c / length)
+}
+
+pub struct PortalPlugin;
+
+impl Plugin for PortalPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(Portals::default())
+            .add_system(|resources: &mut Resources| {
+                let viewport = resources.get::<Viewport>().copied().unwrap_or_default();
+                if let Some(portals) = resources.get_mut::<Portals>() {
+                    portals.update(viewport.size);
+                    metrics::set_gauge("portal_passes", portals.passes().len() as f64);
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        (0..3).all(|i| (a[i] - b[i]).abs() < 1e-3)
+    }
+
+    #[test]
+    fn mirrors_facing_each_other_recurse_to_the_limit() {
+        let mut portals = Portals::default();
+        let target = portals.add_target(RenderTarget::default());
+        let mirror = |z: f32, rotation: [f32; 4]| Surface {
+            transform: Transform {
+                translation: [0.0, 0.0, z],
+                rotation,
+                scale: [4.0, 4.0, 1.0],
+            },
+            target,
+            kind: SurfaceKind::Mirror,
+        };
+        let front = portals
+            .add_surface(mirror(-5.0, [0.0, 0.0, 0.0, 1.0]))
+            .unwrap();
+        let back = portals
+            .add_surface(mirror(5.0, [0.0, 1.0, 0.0, 0.0]))
+            .unwrap();
+        portals.set_max_depth(3);
+        let projection = View::perspective(1.2, 16.0 / 9.0, 0.1, 100.0);
+        let camera = View::look_at([0.0; 3], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0], projection);
+        portals.set_camera(camera);
+        portals.update([1280.0, 720.0]);
+
+        // Only the mirror in front is seen, then each reflection sees the other one.
+        let passes = portals.passes();
+        assert_eq!(passes.len(), 3);
+        let order: Vec<_> = passes
+            .iter()
+            .map(|pass| (pass.surface, pass.depth))
+            .collect();
+        assert_eq!(order, [(front, 3), (back, 2), (front, 1)]);
+        assert_eq!(
+            passes.iter().map(|pass| pass.parent).collect::<Vec<_>>(),
+            [Some(1), Some(2), None]
+        );
+        assert!(close(passes[2].view.position(), [0.0, 0.0, -10.0]));
+        assert!(close(passes[1].view.position(), [0.0, 0.0, 20.0]));
+        assert_eq!(passes[2].clip_plane, Some([0.0, 0.0, 1.0, 5.0]));
+        assert_eq!(
+            passes
+                .iter()
+                .map(|pass| pass.flip_winding)
+                .collect::<Vec<_>>(),
+            [true, false, true]
+        );
+        assert_eq!(passes[2].size, [640, 360]);
+        assert_eq!(passes[0].size, [160, 90]);
+
+        // Deep enough that the scale underflows, the passes just bottom out at a pixel.
+        portals.set_max_depth(40);
+        portals.set_max_passes(40);
+        let far = View::perspective(1.2, 16.0 / 9.0, 0.1, 1000.0);
+        let far = View::look_at([0.0; 3], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0], far);
+        let passes = portals.plan(&far, [1280.0, 720.0]);
+        assert_eq!((passes.len(), passes[0].depth), (40, 40));
+        assert_eq!(passes[0].size, [1, 1]);
+        portals.set_max_depth(3);
+
+        // A portal whose exit is at x = 10 turned to face +x.
+        portals.remove_surface(back);
+        let exit = Transform {
+            translation: [10.0, 0.0, 0.0],
+            rotation: [
+                0.0,
+                std::f32::consts::FRAC_1_SQRT_2,
+                0.0,
+                std::f32::consts::FRAC_1_SQRT_2,
+            ],
+            scale: [4.0, 4.0, 1.0],
+        };
+        portals.surface_mut(front).unwrap().kind = SurfaceKind::Portal { exit };
+        let passes = portals.plan(&camera, [1280.0, 720.0]);
+        assert_eq!(passes.len(), 1);
+        // Five in front of the entrance is five behind the exit, looking out of it along +x.
+        assert!(close(passes[0].view.position(), [5.0, 0.0, 0.0]));
+        assert!(!passes[0].flip_winding);
+    }
+}
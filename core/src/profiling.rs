@@ -0,0 +1,214 @@
+//! Built-in CPU profiler. `profile_scope!` times the rest of the enclosing block, each thread
+//! collects its scopes until `finish_frame` publishes them for viewers. With the `tracy` feature
+//! the same scopes are also sent to a connected Tracy client.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static FRAMES: Mutex<BTreeMap<&'static str, FrameProfile>> = Mutex::new(BTreeMap::new());
+
+#[derive(Clone, Debug)]
+pub struct ScopeRecord {
+    pub name: &'static str,
+    // Nesting level, 0 for scopes opened outside any other scope.
+    pub depth: u32,
+    // Relative to the start of the frame.
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameProfile {
+    pub duration: Duration,
+    // Sorted by start time, parents before their children.
+    pub scopes: Vec<ScopeRecord>,
+}
+
+impl FrameProfile {
+    // Total time and call count per scope name, slowest first.
+    pub fn totals(&self) -> Vec<(&'static str, Duration, u32)> {
+        let mut totals: BTreeMap<&'static str, (Duration, u32)> = BTreeMap::new();
+        for scope in &self.scopes {
+            let entry = totals.entry(scope.name).or_default();
+            entry.0 += scope.duration;
+            entry.1 += 1;
+        }
+        let mut totals: Vec<_> = totals
+            .into_iter()
+            .map(|(name, (duration, count))| (name, duration, count))
+            .collect();
+        totals.sort_by_key(|total| std::cmp::Reverse(total.1));
+        totals
+    }
+}
+
+struct ThreadProfile {
+    frame_start: Instant,
+    depth: u32,
+    scopes: Vec<ScopeRecord>,
+}
+
+thread_local! {
+    static THREAD_PROFILE: RefCell<ThreadProfile> = RefCell::new(ThreadProfile {
+        frame_start: Instant::now(),
+        depth: 0,
+        scopes: Vec::new(),
+    });
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub struct ProfileScope {
+    name: &'static str,
+    start: Instant,
+    depth: u32,
+    #[cfg(feature = "tracy")]
+    _tracy: Option<tracy_client::Span>,
+}
+
+impl ProfileScope {
+    // Use `profile_scope!` instead of calling this directly.
+    pub fn new(name: &'static str, _file: &'static str, _line: u32) -> Option<Self> {
+        if !is_enabled() {
+            return None;
+        }
+        let depth = THREAD_PROFILE.with(|profile| {
+            let mut profile = profile.borrow_mut();
+            profile.depth += 1;
+            profile.depth - 1
+        });
+        Some(Self {
+            name,
+            start: Instant::now(),
+            depth,
+            #[cfg(feature = "tracy")]
+            _tracy: tracy_client::Client::running()
+                .map(|client| client.span_alloc(Some(name), "", _file, _line, 0)),
+        })
+    }
+}
+
+impl Drop for ProfileScope {
+    fn drop(&mut self) {
+        let duration = self.start.elapsed();
+        THREAD_PROFILE.with(|profile| {
+            let mut profile = profile.borrow_mut();
+            profile.depth = profile.depth.saturating_sub(1);
+            let start = self.start.saturating_duration_since(profile.frame_start);
+            profile.scopes.push(ScopeRecord {
+                name: self.name,
+                depth: self.depth,
+                start,
+                duration,
+            });
+        });
+    }
+}
+
+/// Times the rest of the enclosing block, e.g. `profile_scope!("physics");`.
+#[macro_export]
+macro_rules! profile_scope {
+    ($name:expr) => {
+        let _profile_scope = $crate::profiling::ProfileScope::new($name, file!(), line!());
+    };
+}
+
+// Publishes the scopes the calling thread recorded since its last call under `label`
+// (e.g. "sim", "render") and starts a new frame.
+pub fn finish_frame(label: &'static str) {
+    let profile = THREAD_PROFILE.with(|profile| {
+        let mut profile = profile.borrow_mut();
+        let now = Instant::now();
+        let mut scopes = std::mem::take(&mut profile.scopes);
+        scopes.sort_by_key(|scope| (scope.start, scope.depth));
+        let frame = FrameProfile {
+            duration: now - profile.frame_start,
+            scopes,
+        };
+        profile.frame_start = now;
+        frame
+    });
+
+    #[cfg(feature = "tracy")]
+    if let Some(client) = tracy_client::Client::running() {
+        if label == "render" {
+            client.frame_mark();
+        }
+    }
+
+    if is_enabled() {
+        FRAMES.lock().unwrap().insert(label, profile);
+    }
+}
+
+pub fn latest_frame(label: &str) -> Option<FrameProfile> {
+    FRAMES.lock().unwrap().get(label).cloned()
+}
+
+// Latest frame of every thread that called `finish_frame`, for the profiler view.
+pub fn latest_frames() -> Vec<(&'static str, FrameProfile)> {
+    FRAMES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(label, frame)| (*label, frame.clone()))
+        .collect()
+}
+
+// Starts the Tracy client so a Tracy profiler can connect, no-op without the `tracy` feature.
+pub fn start_tracy() {
+    #[cfg(feature = "tracy")]
+    {
+        tracy_client::Client::start();
+        set_enabled(true);
+    }
+    #[cfg(not(feature = "tracy"))]
+    warn!("Built without the tracy feature, not starting the Tracy client!");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_nested_scopes() {
+        set_enabled(true);
+        {
+            profile_scope!("outer");
+            for _ in 0..2 {
+                profile_scope!("inner");
+            }
+        }
+        finish_frame("profiling test");
+
+        let frame = latest_frame("profiling test").unwrap();
+        let scopes: Vec<_> = frame
+            .scopes
+            .iter()
+            .map(|scope| (scope.name, scope.depth))
+            .collect();
+        assert_eq!(scopes, [("outer", 0), ("inner", 1), ("inner", 1)]);
+        let totals = frame.totals();
+        assert_eq!(totals[0].0, "outer");
+        assert_eq!(totals.iter().find(|t| t.0 == "inner").unwrap().2, 2);
+    }
+}
@@ -0,0 +1,279 @@
+//! Seeded random numbers for gameplay. Everything random in the sim should come from the `Random`
+//! resource, so a run is reproduced from its seed: input timelines record it, rollback snapshots
+//! carry `RandomState`, and the seed is logged on startup for bug reports.
+//!
+//! Draws are split into named streams, one per system or feature. A stream's sequence only
+//! depends on the seed and its name, so adding a system, reordering them or running them in
+//! parallel doesn't change what the others get. Work spread over threads forks a stream per job
+//! in a fixed order with `Rng::fork`.
+
+use std::{collections::BTreeMap, ops::Range};
+
+use uuid::Uuid;
+
+use crate::{
+    cvars,
+    engine::{Engine, Plugin},
+};
+
+// Xoshiro256**, fast with a small state and good enough for anything but cryptography.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // SplitMix64 spreads the seed over the state, which must not be all zero.
+        let mut seed = seed;
+        let mut state = [0; 4];
+        for word in &mut state {
+            *word = split_mix(&mut seed);
+        }
+        Self { state }
+    }
+
+    pub fn from_state(state: [u64; 4]) -> Self {
+        if state == [0; 4] {
+            return Self::new(0);
+        }
+        Self { state }
+    }
+
+    pub fn state(&self) -> [u64; 4] {
+        self.state
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let [s0, s1, s2, s3] = &mut self.state;
+        let result = s1.wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = *s1 << 17;
+        *s2 ^= *s0;
+        *s3 ^= *s1;
+        *s1 ^= *s2;
+        *s0 ^= *s3;
+        *s2 ^= t;
+        *s3 = s3.rotate_left(45);
+        result
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    // In [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // In [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in `range`, which must not be empty.
+    pub fn int(&mut self, range: Range<i64>) -> i64 {
+        assert!(range.start < range.end, "empty range {:?}", range);
+        let span = range.end.wrapping_sub(range.start) as u64;
+        range.start.wrapping_add(self.below(span) as i64)
+    }
+
+    // Uniform in `range`, which must not be empty.
+    pub fn float(&mut self, range: Range<f32>) -> f32 {
+        assert!(range.start < range.end, "empty range {:?}", range);
+        let value = range.start + (range.end - range.start) * self.next_f32();
+        // Rounding can land on the end, rarely enough that starting over doesn't skew it.
+        if value < range.end {
+            value
+        } else {
+            range.start
+        }
+    }
+
+    // True with probability `p`.
+    pub fn chance(&mut self, p: f32) -> bool {
+        self.next_f32() < p
+    }
+
+    pub fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        match items.len() {
+            0 => None,
+            len => items.get(self.below(len as u64) as usize),
+        }
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i as u64 + 1) as usize);
+        }
+    }
+
+    // An independent generator seeded from this one. Forking in the same order gives the same
+    // generators, e.g. one per job of a parallel system.
+    pub fn fork(&mut self) -> Rng {
+        Rng::new(self.next_u64())
+    }
+
+    // Uniform in [0, bound) without modulo bias, Lemire's multiply and reject.
+    fn below(&mut self, bound: u64) -> u64 {
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let product = u128::from(self.next_u64()) * u128::from(bound);
+            if (product as u64) >= threshold {
+                return (product >> 64) as u64;
+            }
+        }
+    }
+}
+
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// FNV-1a, stable across platforms and releases unlike the std hasher.
+fn hash_name(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// Everything needed to continue the random sequences exactly, for snapshots.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RandomState {
+    pub seed: u64,
+    pub streams: BTreeMap<String, [u64; 4]>,
+}
+
+// The sim's random numbers, see the module docs.
+#[derive(Debug)]
+pub struct Random {
+    seed: u64,
+    streams: BTreeMap<String, Rng>,
+}
+
+impl Default for Random {
+    // Seeded from entropy.
+    fn default() -> Self {
+        let seed = u64::from_le_bytes(Uuid::new_v4().as_bytes()[..8].try_into().unwrap());
+        Self::new(seed)
+    }
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            streams: BTreeMap::new(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Starts every stream over from `seed`.
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.streams.clear();
+    }
+
+    // The stream for a system or feature, e.g. `random.stream("loot")`.
+    pub fn stream(&mut self, name: &str) -> &mut Rng {
+        if !self.streams.contains_key(name) {
+            let rng = Rng::new(self.seed ^ hash_name(name));
+            self.streams.insert(name.to_owned(), rng);
+        }
+        self.streams.get_mut(name).unwrap()
+    }
+
+    pub fn save(&self) -> RandomState {
+        RandomState {
+            seed: self.seed,
+            streams: self
+                .streams
+                .iter()
+                .map(|(name, rng)| (name.clone(), rng.state()))
+                .collect(),
+        }
+    }
+
+    pub fn load(&mut self, state: &RandomState) {
+        self.seed = state.seed;
+        self.streams = state
+            .streams
+            .iter()
+            .map(|(name, state)| (name.clone(), Rng::from_state(*state)))
+            .collect();
+    }
+}
+
+// Inserts `Random`, unless the app or a timeline already did, and adds `rand.reseed`.
+pub struct RandomPlugin;
+
+impl Plugin for RandomPlugin {
+    fn build(&self, engine: &mut Engine) {
+        if !engine.resources().contains::<Random>() {
+            engine.insert_resource(Random::default());
+        }
+        let seed = engine.resources().get::<Random>().map(Random::seed);
+        info!("Random seed {}", seed.unwrap_or_default());
+        cvars::cvars(engine).register_command(
+            "rand.reseed",
+            "rand.reseed <seed>, restarts the sim's random numbers from a seed",
+            |resources, args| {
+                let [seed] = args else {
+                    return Err("usage: rand.reseed <seed>".to_owned());
+                };
+                let seed = seed
+                    .parse()
+                    .map_err(|_| format!("{} is not a seed", seed))?;
+                resources
+                    .get_mut::<Random>()
+                    .ok_or("no random resource")?
+                    .reseed(seed);
+                Ok(())
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streams_are_reproducible_and_independent() {
+        let mut a = Random::new(42);
+        let mut b = Random::new(42);
+        // Streams don't care which was used first.
+        let loot: Vec<u64> = (0..4).map(|_| a.stream("loot").next_u64()).collect();
+        let _ = b.stream("ai").next_u64();
+        let saved = b.save();
+        assert_eq!(
+            (0..4)
+                .map(|_| b.stream("loot").next_u64())
+                .collect::<Vec<_>>(),
+            loot
+        );
+        assert_ne!(a.stream("ai").state(), b.stream("ai").state());
+
+        b.load(&saved);
+        let mut c = Random::new(7);
+        c.load(&saved);
+        assert_eq!(c.stream("loot").next_u64(), loot[0]);
+
+        let rng = a.stream("ranges");
+        for _ in 0..1000 {
+            assert!((-3..5).contains(&rng.int(-3..5)));
+            assert!((0.5..0.75).contains(&rng.float(0.5..0.75)));
+        }
+        let mut items = [1, 2, 3, 4, 5];
+        rng.shuffle(&mut items);
+        items.sort();
+        assert_eq!(items, [1, 2, 3, 4, 5]);
+    }
+}
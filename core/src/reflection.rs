@@ -0,0 +1,402 @@
+//! Local reflections from probes placed through the level. A `ReflectionProbe` is a component
+//! on an entity with a `Transform`, it captures a cubemap of its surroundings from where it
+//! stands, and surfaces inside its box sample that cubemap box projected: the reflected ray is
+//! intersected with the box and the cubemap looked up towards the hit from the capture point, so
+//! reflections line up with the room's walls instead of sliding along with the camera.
+//!
+//! Probes are captured once when they first show up, a baked probe never again and an on demand
+//! one again whenever it's asked to. Captures are spread over frames, each is six views the render thread
+//! renders into a slot of a cubemap array. Neither the offscreen passes nor a PBR shader to
+//! sample the array exist yet, the renderer doesn't draw the scene at all so far. This module only
+//! places, blends and schedules the probes, `ProbeFrame` is what the passes would take through
+//! `take_frame`. Until something takes them, captures wait there, at most one per slot.
+
+use std::{collections::BTreeMap, f32::consts::FRAC_PI_2, sync::Mutex};
+
+use crate::{
+    cvars,
+    ecs::{EcsWorld, Entity, Query},
+    editor::Transform,
+    engine::{Engine, Plugin, Resources},
+    metrics,
+    portal::View,
+};
+
+const DEFAULT_SLOTS: usize = 16;
+const DEFAULT_CAPTURES_PER_FRAME: usize = 1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProbeRefresh {
+    // Captured once, when the probe is first seen, usually while the level loads.
+    #[default]
+    Baked,
+    // Captured when first seen and again whenever `ReflectionProbes::refresh` asks.
+    OnDemand,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReflectionProbe {
+    // Half the size of the box the probe affects and projects onto, around the entity.
+    pub extents: [f32; 3],
+    // Where the cubemap is captured from, relative to the entity.
+    pub capture_offset: [f32; 3],
+    // Pixels along a cubemap face.
+    pub resolution: u32,
+    pub refresh: ProbeRefresh,
+    // Meters inside the box over which the probe fades out towards its edge.
+    pub blend_distance: f32,
+}
+
+impl Default for ReflectionProbe {
+    fn default() -> Self {
+        Self {
+            extents: [5.0; 3],
+            capture_offset: [0.0; 3],
+            resolution: 128,
+            refresh: ProbeRefresh::default(),
+            blend_distance: 1.0,
+        }
+    }
+}
+
+// A probe as the shader sees it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProbeData {
+    pub entity: Entity,
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub capture_position: [f32; 3],
+    pub blend_distance: f32,
+    // Layer of the cubemap array, six faces from here.
+    pub slot: usize,
+}
+
+impl ProbeData {
+    // The direction to sample the cubemap in for a ray reflected at `position` along
+    // `direction`, what the shader does.
+    pub fn box_project(&self, position: [f32; 3], direction: [f32; 3]) -> [f32; 3] {
+        let mut distance = f32::INFINITY;
+        for axis in 0..3 {
+            if direction[axis].abs() > f32::EPSILON {
+                let plane = if direction[axis] > 0.0 {
+                    self.max[axis]
+                } else {
+                    self.min[axis]
+                };
+                distance = distance.min((plane - position[axis]) / direction[axis]);
+            }
+        }
+        if !distance.is_finite() || distance < 0.0 {
+            return direction;
+        }
+        let hit: [f32; 3] = std::array::from_fn(|i| position[i] + direction[i] * distance);
+        normalize(std::array::from_fn(|i| hit[i] - self.capture_position[i]))
+    }
+
+    // 1 well inside the box, falling to 0 at its edge over the blend distance.
+    pub fn weight(&self, position: [f32; 3]) -> f32 {
+        let inside = (0..3)
+            .map(|axis| (position[axis] - self.min[axis]).min(self.max[axis] - position[axis]))
+            .fold(f32::INFINITY, f32::min);
+        if inside < 0.0 {
+            0.0
+        } else {
+            (inside / self.blend_distance.max(f32::EPSILON)).min(1.0)
+        }
+    }
+}
+
+// The six views a probe is captured with, in cubemap face order +x, -x, +y, -y, +z, -z.
+pub fn face_views(position: [f32; 3], far: f32) -> [View; 6] {
+    const FACES: [([f32; 3], [f32; 3]); 6] = [
+        ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+        ([-1.0, 0.0, 0.0], [0.0, -1.0, 0.0]),
+        ([0.0, 1.0, 0.0], [0.0, 0.0, 1.0]),
+        ([0.0, -1.0, 0.0], [0.0, 0.0, -1.0]),
+        ([0.0, 0.0, 1.0], [0.0, -1.0, 0.0]),
+        ([0.0, 0.0, -1.0], [0.0, -1.0, 0.0]),
+    ];
+    let projection = View::perspective(FRAC_PI_2, 1.0, 0.05, far.max(0.1));
+    FACES.map(|(forward, up)| {
+        let target = std::array::from_fn(|i| position[i] + forward[i]);
+        View::look_at(position, target, up, projection)
+    })
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capture {
+    pub entity: Entity,
+    pub slot: usize,
+    pub resolution: u32,
+    pub faces: [View; 6],
+}
+
+// What the render thread does with probes this frame.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProbeFrame {
+    pub probes: Vec<ProbeData>,
+    pub captures: Vec<Capture>,
+}
+
+static FRAME: Mutex<Option<ProbeFrame>> = Mutex::new(None);
+
+// The probes and captures from the sim's last tick, for the render thread. Captures are only
+// handed out once.
+pub fn take_frame() -> Option<ProbeFrame> {
+    FRAME.lock().unwrap().take()
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Tracked {
+    slot: usize,
+    captured: bool,
+    refresh: ProbeRefresh,
+}
+
+pub struct ReflectionProbes {
+    tracked: BTreeMap<Entity, Tracked>,
+    probes: Vec<ProbeData>,
+    pending: Vec<Capture>,
+    // Layers of the cubemap array, probes past this many are ignored.
+    pub slots: usize,
+    pub captures_per_frame: usize,
+}
+
+impl Default for ReflectionProbes {
+    fn default() -> Self {
+        Self {
+            tracked: BTreeMap::new(),
+            probes: Vec::new(),
+            pending: Vec::new(),
+            slots: DEFAULT_SLOTS,
+            captures_per_frame: DEFAULT_CAPTURES_PER_FRAME,
+        }
+    }
+}
+
+impl ReflectionProbes {
+    pub fn probes(&self) -> &[ProbeData] {
+        &self.probes
+    }
+
+    // Recaptures an on demand probe, returns false for baked and unknown ones.
+    pub fn refresh(&mut self, entity: Entity) -> bool {
+        match self.tracked.get_mut(&entity) {
+            Some(tracked) if tracked.refresh == ProbeRefresh::OnDemand => {
+                tracked.captured = false;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Recaptures every on demand probe, after the level changed, say.
+    pub fn refresh_all(&mut self) {
+        for tracked in self.tracked.values_mut() {
+            if tracked.refresh == ProbeRefresh::OnDemand {
+                tracked.captured = false;
+            }
+        }
+    }
+
+    // Picks up probes from the world, drops despawned ones and queues the captures due.
+    pub fn update(&mut self, world: &EcsWorld) {
+        let found: BTreeMap<Entity, (ReflectionProbe, [f32; 3])> =
+            Query::<(Entity, &ReflectionProbe, &Transform)>::read(world)
+                .iter()
+                .map(|(entity, probe, transform)| (entity, (*probe, transform.translation)))
+                .collect();
+        self.tracked.retain(|entity, _| found.contains_key(entity));
+
+        self.probes.clear();
+        self.pending.clear();
+        for (entity, (probe, position)) in &found {
+            let tracked = match self.tracked.get_mut(entity) {
+                Some(tracked) => {
+                    tracked.refresh = probe.refresh;
+                    *tracked
+                }
+                None => {
+                    let Some(slot) =
+                        (0..self.slots).find(|slot| self.tracked.values().all(|t| t.slot != *slot))
+                    else {
+                        continue;
+                    };
+                    let tracked = Tracked {
+                        slot,
+                        captured: false,
+                        refresh: probe.refresh,
+                    };
+                    self.tracked.insert(*entity, tracked);
+                    tracked
+                }
+            };
+            let capture_position = std::array::from_fn(|i| position[i] + probe.capture_offset[i]);
+            self.probes.push(ProbeData {
+                entity: *entity,
+                min: std::array::from_fn(|i| position[i] - probe.extents[i]),
+                max: std::array::from_fn(|i| position[i] + probe.extents[i]),
+                capture_position,
+                blend_distance: probe.blend_distance,
+                slot: tracked.slot,
+            });
+            if !tracked.captured && self.pending.len() < self.captures_per_frame {
+                let far = probe.extents.iter().fold(0.0f32, |a, b| a.max(*b)) * 4.0;
+                self.pending.push(Capture {
+                    entity: *entity,
+                    slot: tracked.slot,
+                    resolution: probe.resolution,
+                    faces: face_views(capture_position, far),
+                });
+                self.tracked.get_mut(entity).unwrap().captured = true;
+            }
+        }
+    }
+
+    pub fn captures(&self) -> &[Capture] {
+        &self.pending
+    }
+
+    // The captures still owed from earlier frames plus this frame's. Those of despawned probes
+    // and those a newer capture of the same slot replaces are dropped, so there's at most one
+    // per slot.
+    fn owed_captures(&self, mut owed: Vec<Capture>) -> Vec<Capture> {
+        owed.retain(|capture| {
+            self.tracked
+                .get(&capture.entity)
+                .is_some_and(|tracked| tracked.slot == capture.slot)
+                && self
+                    .pending
+                    .iter()
+                    .all(|pending| pending.slot != capture.slot)
+        });
+        owed.extend(self.pending.iter().cloned());
+        owed
+    }
+
+    // The two probes with the most weight at `position` and their weights, normalized when they
+    // add up to more than 1, the rest of the way is the sky's.
+    pub fn blend(&self, position: [f32; 3]) -> Vec<(&ProbeData, f32)> {
+        let mut weighted: Vec<_> = self
+            .probes
+            .iter()
+            .map(|probe| (probe, probe.weight(position)))
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect();
+        weighted.sort_by(|a, b| b.1.total_cmp(&a.1));
+        weighted.truncate(2);
+        let total: f32 = weighted.iter().map(|(_, weight)| weight).sum();
+        if total > 1.0 {
+            for (_, weight) in &mut weighted {
+                *weight /= total;
+            }
+        }
+        weighted
+    }
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length > 0.0 {
+        v.map(|c| c / length)
+    } else {
+        v
+    }
+}
+
+pub struct ReflectionPlugin;
+
+impl Plugin for ReflectionPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(ReflectionProbes::default())
+            .add_system(|resources: &mut Resources| {
+                let Some(mut probes) = resources.remove::<ReflectionProbes>() else {
+                    return;
+                };
+                if let Some(world) = resources.get::<EcsWorld>() {
+                    probes.update(world);
+                }
+                metrics::set_gauge("reflection_probes", probes.probes().len() as f64);
+                let mut frame = FRAME.lock().unwrap();
+                // Captures the render thread hasn't taken yet are still owed.
+                let owed = frame.take().map(|f| f.captures).unwrap_or_default();
+                *frame = Some(ProbeFrame {
+                    probes: probes.probes().to_vec(),
+                    captures: probes.owed_captures(owed),
+                });
+                drop(frame);
+                resources.insert(probes);
+            });
+
+        cvars::cvars(engine).register_command(
+            "reflections.refresh",
+            "reflections.refresh, recaptures every on demand reflection probe",
+            |resources, _| {
+                resources
+                    .get_mut::<ReflectionProbes>()
+                    .ok_or("no reflection probes")?
+                    .refresh_all();
+                Ok(())
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probes_capture_and_box_project() {
+        let mut world = EcsWorld::new();
+        let room = world.spawn();
+        world.insert(room, Transform::default());
+        world.insert(
+            room,
+            ReflectionProbe {
+                extents: [2.0, 1.0, 2.0],
+                refresh: ProbeRefresh::OnDemand,
+                ..ReflectionProbe::default()
+            },
+        );
+        let hall = world.spawn();
+        world.insert(
+            hall,
+            Transform {
+                translation: [10.0, 0.0, 0.0],
+                ..Transform::default()
+            },
+        );
+        world.insert(hall, ReflectionProbe::default());
+
+        // One capture a frame, each probe once.
+        let mut probes = ReflectionProbes::default();
+        let mut captured = Vec::new();
+        let mut owed = Vec::new();
+        for _ in 0..3 {
+            probes.update(&world);
+            captured.extend(probes.captures().iter().map(|capture| capture.entity));
+            owed = probes.owed_captures(owed);
+        }
+        assert_eq!(captured, [room, hall]);
+        assert!(probes.refresh(room) && !probes.refresh(hall));
+        probes.update(&world);
+        assert_eq!(probes.captures()[0].entity, room);
+        // Untaken captures don't pile up, the new one replaces the room's old one.
+        owed = probes.owed_captures(owed);
+        assert_eq!(owed.len(), 2);
+
+        // A ray from off center hits the wall at x = 2, seen from the middle it's further over.
+        let data = probes.probes()[0];
+        let projected = data.box_project([1.0, 0.0, 0.0], [1.0, 0.0, 1.0]);
+        assert!((projected[0] - projected[2]).abs() > 0.1);
+        assert_eq!(probes.blend([0.0, 0.0, 0.0]), [(&data, 1.0)]);
+        assert!(probes.blend([0.0, 5.0, 0.0]).is_empty());
+
+        world.despawn(room);
+        probes.update(&world);
+        assert_eq!(probes.probes().len(), 1);
+        assert_eq!(probes.owed_captures(owed).len(), 1);
+    }
+}
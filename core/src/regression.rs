@@ -0,0 +1,553 @@
+//! Regression capture. A recording writes the input stream to a timeline together with
+//! checkpoints, hashes of the game's state taken every few ticks. Verifying plays the timeline
+//! back headless and compares every checkpoint, so a change that alters what the game does or
+//! shows with the same input is caught.
+//!
+//! What gets hashed comes from named capture sources. `ui` hashes the UI's draw list, games add
+//! their own for whatever state matters to them:
+//!
+//! ```ignore
+//! regression.add_capture("player", |resources, hasher| {
+//!     if let Some(player) = resources.get::<Player>() {
+//!         player.position.map(f32::to_bits).hash(hasher);
+//!     }
+//! });
+//! ```
+//!
+//! The sim never sees the framebuffer, so captures hash what the sim hands the renderer.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    hash::Hasher,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    app::Application,
+    cvars,
+    engine::{Engine, Plugin, Resources},
+    input::InputEvent,
+    playback::{self, InputTimeline, TimelineAction},
+    rand::Random,
+    sim::Time,
+    ui::{Align, DrawCommand, Rect, Ui},
+};
+
+// One second at the fixed tick rate.
+const DEFAULT_INTERVAL: u64 = 60;
+
+// FNV-1a, unlike `DefaultHasher` it's the same from build to build. Integers are hashed in native
+// byte order, so recordings are only comparable on platforms with the same endianness and width.
+pub struct StateHasher(u64);
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for StateHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+type Capture = Box<dyn Fn(&Resources, &mut StateHasher) + Send>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    pub tick: u64,
+    pub name: String,
+    pub expected: u64,
+    // None when the run never got there or nothing captures under the name anymore.
+    pub actual: Option<u64>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.actual {
+            Some(actual) => write!(
+                f,
+                "tick {}: {} is {:016x}, expected {:016x}",
+                self.tick, self.name, actual, self.expected
+            ),
+            None => write!(f, "tick {}: {} wasn't captured", self.tick, self.name),
+        }
+    }
+}
+
+enum Mode {
+    Idle,
+    Recording {
+        path: PathBuf,
+        timeline: InputTimeline,
+    },
+    Verifying {
+        // Checkpoints not reached yet, by tick.
+        expected: BTreeMap<u64, Vec<(String, u64)>>,
+        mismatches: Vec<Mismatch>,
+    },
+}
+
+pub struct Regression {
+    captures: Vec<(String, Capture)>,
+    // Ticks between checkpoints while recording.
+    interval: u64,
+    mode: Mode,
+    // The tick input arriving now is delivered on.
+    next_tick: u64,
+}
+
+impl Default for Regression {
+    fn default() -> Self {
+        Self {
+            captures: Vec::new(),
+            interval: DEFAULT_INTERVAL,
+            mode: Mode::Idle,
+            next_tick: 0,
+        }
+    }
+}
+
+impl Regression {
+    // Replaces a capture with the same name. Names can't contain whitespace.
+    pub fn add_capture<F>(&mut self, name: &str, capture: F)
+    where
+        F: Fn(&Resources, &mut StateHasher) + Send + 'static,
+    {
+        assert!(
+            !name.is_empty() && !name.contains(char::is_whitespace),
+            "Capture name {:?} has to be a single word",
+            name
+        );
+        self.captures.retain(|(existing, _)| existing != name);
+        self.captures.push((name.to_owned(), Box::new(capture)));
+    }
+
+    pub fn set_interval(&mut self, ticks: u64) {
+        self.interval = ticks.max(1);
+    }
+
+    // Starts recording to `path`, written by `save` and when the recording is dropped.
+    pub fn record(&mut self, path: impl Into<PathBuf>) {
+        self.mode = Mode::Recording {
+            path: path.into(),
+            timeline: InputTimeline::default(),
+        };
+    }
+
+    // Compares against the checkpoints in `timeline` from now on.
+    pub fn verify(&mut self, timeline: &InputTimeline) {
+        let mut expected = BTreeMap::<u64, Vec<_>>::new();
+        for (tick, action) in timeline.entries() {
+            if let TimelineAction::Checkpoint { name, hash } = action {
+                expected
+                    .entry(*tick)
+                    .or_default()
+                    .push((name.clone(), *hash));
+            }
+        }
+        self.mode = Mode::Verifying {
+            expected,
+            mismatches: Vec::new(),
+        };
+    }
+
+    pub fn is_recording(&self) -> bool {
+        matches!(self.mode, Mode::Recording { .. })
+    }
+
+    pub fn timeline(&self) -> Option<&InputTimeline> {
+        match &self.mode {
+            Mode::Recording { timeline, .. } => Some(timeline),
+            _ => None,
+        }
+    }
+
+    pub fn save(&self) -> Result<&Path, Box<dyn std::error::Error>> {
+        let Mode::Recording { path, timeline } = &self.mode else {
+            return Err("not recording".into());
+        };
+        timeline.save(path)?;
+        Ok(path)
+    }
+
+    // Ends verifying, every checkpoint that didn't match or wasn't reached.
+    pub fn finish(&mut self) -> Vec<Mismatch> {
+        let Mode::Verifying {
+            expected,
+            mut mismatches,
+        } = std::mem::replace(&mut self.mode, Mode::Idle)
+        else {
+            return Vec::new();
+        };
+        for (tick, checkpoints) in expected {
+            mismatches.extend(checkpoints.into_iter().map(|(name, hash)| Mismatch {
+                tick,
+                name,
+                expected: hash,
+                actual: None,
+            }));
+        }
+        mismatches
+    }
+
+    pub fn capture(&self, name: &str, resources: &Resources) -> Option<u64> {
+        let (_, capture) = self
+            .captures
+            .iter()
+            .find(|(existing, _)| existing == name)?;
+        let mut hasher = StateHasher::default();
+        capture(resources, &mut hasher);
+        Some(hasher.finish())
+    }
+
+    // Called by the sim for every input event before anything can consume it.
+    pub(crate) fn record_input(&mut self, event: &InputEvent) {
+        if let Mode::Recording { timeline, .. } = &mut self.mode {
+            timeline.push(self.next_tick, TimelineAction::Input(event.clone()));
+        }
+    }
+
+    fn checkpoint(&mut self, tick: u64, resources: &Resources) {
+        self.next_tick = tick + 1;
+        match &self.mode {
+            Mode::Idle => {}
+            Mode::Recording { .. } => {
+                if !tick.is_multiple_of(self.interval) {
+                    return;
+                }
+                let hashes: Vec<_> = self
+                    .captures
+                    .iter()
+                    .map(|(name, _)| (name.clone(), self.capture(name, resources).unwrap()))
+                    .collect();
+                let seed = resources.get::<Random>().map(Random::seed);
+                let Mode::Recording { timeline, .. } = &mut self.mode else {
+                    unreachable!();
+                };
+                timeline.seed = timeline.seed.or(seed);
+                for (name, hash) in hashes {
+                    timeline.push(tick, TimelineAction::Checkpoint { name, hash });
+                }
+            }
+            Mode::Verifying { expected, .. } => {
+                let Some(checkpoints) = expected.get(&tick) else {
+                    return;
+                };
+                let found: Vec<_> = checkpoints
+                    .iter()
+                    .filter_map(|(name, hash)| {
+                        let actual = self.capture(name, resources);
+                        (actual != Some(*hash)).then(|| Mismatch {
+                            tick,
+                            name: name.clone(),
+                            expected: *hash,
+                            actual,
+                        })
+                    })
+                    .collect();
+                let Mode::Verifying {
+                    expected,
+                    mismatches,
+                } = &mut self.mode
+                else {
+                    unreachable!();
+                };
+                expected.remove(&tick);
+                for mismatch in found {
+                    warn!("Regression: {}", mismatch);
+                    mismatches.push(mismatch);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Regression {
+    fn drop(&mut self) {
+        if self.is_recording() {
+            match self.save() {
+                Ok(path) => info!("Recording saved to {}", path.display()),
+                Err(err) => error!("{}", err),
+            }
+        }
+    }
+}
+
+/// Plays `timeline` back like `playback::play` and compares its checkpoints against the run.
+/// Returns the number of ticks simulated, or every checkpoint that didn't match.
+pub fn verify<A: Application>(
+    app: A,
+    mut engine: Engine,
+    timeline: &InputTimeline,
+) -> Result<u64, Vec<Mismatch>> {
+    if engine.resources().get::<Regression>().is_none() {
+        engine.add_plugins(RegressionPlugin::default());
+    }
+    engine
+        .resources_mut()
+        .get_mut::<Regression>()
+        .unwrap()
+        .verify(timeline);
+
+    let mut sim_loop = playback::start(app, engine, timeline);
+    let ticks = playback::drive(&mut sim_loop, timeline);
+    let mismatches = sim_loop
+        .engine_mut()
+        .resources_mut()
+        .get_mut::<Regression>()
+        .map(Regression::finish)
+        .unwrap_or_default();
+    sim_loop.shutdown();
+    match mismatches.is_empty() {
+        true => Ok(ticks),
+        false => Err(mismatches),
+    }
+}
+
+#[derive(Default)]
+pub struct RegressionPlugin {
+    record: Option<PathBuf>,
+    interval: Option<u64>,
+}
+
+impl RegressionPlugin {
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Self {
+            record: Some(path.into()),
+            interval: None,
+        }
+    }
+
+    pub fn with_interval(mut self, ticks: u64) -> Self {
+        self.interval = Some(ticks);
+        self
+    }
+}
+
+impl Plugin for RegressionPlugin {
+    fn build(&self, engine: &mut Engine) {
+        let mut regression = Regression::default();
+        regression.add_capture("ui", |resources, hasher| {
+            if let Some(ui) = resources.get::<Ui>() {
+                for command in ui.draw() {
+                    hash_draw_command(&command, hasher);
+                }
+            }
+        });
+        if let Some(interval) = self.interval {
+            regression.set_interval(interval);
+        }
+        if let Some(path) = &self.record {
+            info!("Recording input to {}", path.display());
+            regression.record(path);
+        }
+        engine
+            .insert_resource(regression)
+            .add_system(|resources: &mut Resources| {
+                let tick = resources.get::<Time>().map_or(0, |time| time.tick);
+                // Out of the resources while it runs, so the captures can read them.
+                let Some(mut regression) = resources.remove::<Regression>() else {
+                    return;
+                };
+                regression.checkpoint(tick, resources);
+                resources.insert(regression);
+            });
+
+        cvars::cvars(engine).register_command(
+            "record.save",
+            "writes the input recording so far",
+            |resources, _| {
+                let regression = resources
+                    .get::<Regression>()
+                    .ok_or("no regression capture")?;
+                let path = regression.save().map_err(|err| err.to_string())?;
+                info!("Recording saved to {}", path.display());
+                Ok(())
+            },
+        );
+    }
+}
+
+// Floats by their bits, so the hash doesn't depend on how `Debug` rounds them.
+fn hash_draw_command(command: &DrawCommand, hasher: &mut StateHasher) {
+    let hash_rect = |hasher: &mut StateHasher, rect: &Rect| {
+        for value in [rect.x, rect.y, rect.width, rect.height] {
+            hasher.write_u32(value.to_bits());
+        }
+    };
+    let hash_color = |hasher: &mut StateHasher, color: &[f32; 4]| {
+        for value in color {
+            hasher.write_u32(value.to_bits());
+        }
+    };
+    let hash_str = |hasher: &mut StateHasher, text: &str| {
+        hasher.write_usize(text.len());
+        hasher.write(text.as_bytes());
+    };
+    match command {
+        DrawCommand::Quad { rect, color } => {
+            hasher.write_u8(0);
+            hash_rect(hasher, rect);
+            hash_color(hasher, color);
+        }
+        DrawCommand::Outline { rect, color } => {
+            hasher.write_u8(1);
+            hash_rect(hasher, rect);
+            hash_color(hasher, color);
+        }
+        DrawCommand::Text {
+            rect,
+            text,
+            size,
+            color,
+            align,
+        } => {
+            hasher.write_u8(2);
+            hash_rect(hasher, rect);
+            hash_str(hasher, text);
+            hasher.write_u32(size.to_bits());
+            hash_color(hasher, color);
+            hasher.write_u8(match align {
+                Align::Start => 0,
+                Align::Center => 1,
+                Align::End => 2,
+            });
+        }
+        DrawCommand::Image { rect, path, tint } => {
+            hasher.write_u8(3);
+            hash_rect(hasher, rect);
+            hash_str(hasher, path);
+            hash_color(hasher, tint);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{SimEvent, SimLoop};
+    use std::hash::Hash;
+    use winit::keyboard::KeyCode;
+
+    #[derive(Default)]
+    struct Counter;
+
+    impl Application for Counter {
+        fn handle_input(&mut self, engine: &mut Engine, event: &InputEvent) {
+            if let InputEvent::Key { pressed: true, .. } = event {
+                *engine.resources_mut().get_mut::<u32>().unwrap() += 1;
+            }
+        }
+    }
+
+    fn engine() -> Engine {
+        let mut engine = Engine::new();
+        engine
+            .insert_resource(0u32)
+            .add_plugins(RegressionPlugin::default().with_interval(5));
+        let regression = engine.resources_mut().get_mut::<Regression>().unwrap();
+        regression.add_capture("presses", |resources, hasher| {
+            resources.get::<u32>().hash(hasher);
+        });
+        engine
+    }
+
+    #[test]
+    fn recording_verifies_against_the_same_run() {
+        let mut sim = SimLoop::headless(Counter, engine());
+        let press = InputEvent::Key {
+            code: KeyCode::Space,
+            pressed: true,
+        };
+        let resources = sim.engine_mut().resources_mut();
+        resources.get_mut::<Regression>().unwrap().record("unused");
+        sim.step_n(3);
+        sim.send(SimEvent::Input(press.clone()));
+        sim.step_n(8);
+        let mut regression = sim
+            .engine_mut()
+            .resources_mut()
+            .remove::<Regression>()
+            .unwrap();
+        let timeline = InputTimeline::parse(&regression.timeline().unwrap().to_string()).unwrap();
+        // Nothing to write to.
+        regression.mode = Mode::Idle;
+
+        assert_eq!(
+            timeline.entries()[..2],
+            [
+                (
+                    0,
+                    TimelineAction::Checkpoint {
+                        name: "ui".to_owned(),
+                        hash: StateHasher::default().finish()
+                    }
+                ),
+                (
+                    0,
+                    TimelineAction::Checkpoint {
+                        name: "presses".to_owned(),
+                        hash: presses_hash(0)
+                    }
+                ),
+            ]
+        );
+        assert_eq!(timeline.entries()[2], (3, TimelineAction::Input(press)));
+        assert_eq!(timeline.last_tick(), 10);
+        assert_eq!(verify(Counter, engine(), &timeline), Ok(11));
+
+        // One press fewer, every checkpoint after it is off.
+        let mut changed = InputTimeline::default();
+        for (tick, action) in timeline.entries() {
+            if !matches!(action, TimelineAction::Input(_)) {
+                changed.push(*tick, action.clone());
+            }
+        }
+        let mismatches = verify(Counter, engine(), &changed).unwrap_err();
+        let ticks: Vec<_> = mismatches
+            .iter()
+            .map(|mismatch| (mismatch.tick, mismatch.name.as_str()))
+            .collect();
+        assert_eq!(ticks, [(5, "presses"), (10, "presses")]);
+        assert_eq!(mismatches[0].actual, Some(presses_hash(0)));
+    }
+
+    #[test]
+    fn consumed_input_is_still_recorded() {
+        let mut engine = engine();
+        engine.add_input_handler(|_: &mut Resources, _: &InputEvent| true);
+        let mut sim = SimLoop::headless(Counter, engine);
+        let press = InputEvent::Key {
+            code: KeyCode::Space,
+            pressed: true,
+        };
+        let resources = sim.engine_mut().resources_mut();
+        resources.get_mut::<Regression>().unwrap().record("unused");
+        sim.step();
+        sim.send(SimEvent::Input(press.clone()));
+        sim.step();
+        let resources = sim.engine_mut().resources_mut();
+        assert_eq!(resources.get::<u32>(), Some(&0));
+        let timeline = resources.get::<Regression>().unwrap().timeline().unwrap();
+        assert!(timeline
+            .entries()
+            .contains(&(1, TimelineAction::Input(press))));
+        resources.get_mut::<Regression>().unwrap().mode = Mode::Idle;
+    }
+
+    fn presses_hash(presses: u32) -> u64 {
+        let mut hasher = StateHasher::default();
+        Some(&presses).hash(&mut hasher);
+        hasher.finish()
+    }
+}
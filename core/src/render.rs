@@ -4,10 +4,20 @@ extern crate wgpu_types as wgt;
 use std::{
     borrow::Borrow,
     iter,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
     thread::{self, JoinHandle},
+    time::Duration,
 };
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+// std::time::Instant panics on wasm32-unknown-unknown.
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
 use hal::{
     Adapter as _, Api, CommandEncoder as _, Device as _, Instance as _, Queue as _, Surface as _,
 };
@@ -15,7 +25,20 @@ use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 
 use winit::window;
 
+use crate::{
+    crash_report,
+    frame_graph::{self, FrameSeries, PassStats},
+    gpu_memory::{self, GpuMemoryCategory},
+    memory::{self, MemoryTag},
+    metrics,
+    profiling,
+    render_graph::{self, EncodePass},
+    sim,
+};
+
 const MAX_FRAMES_IN_FLIGHT: u32 = 3;
+// Render passes a frame gets pipeline statistics for.
+const MAX_STATISTICS_PASSES: u32 = 32;
 
 cfg_if::cfg_if! {
     // Apple + Metal
@@ -47,6 +70,8 @@ pub struct RenderFrame<A: hal::Api> {
     used_views: Vec<A::TextureView>,
     used_cmd_bufs: Vec<A::CommandBuffer>,
     frames_recorded: usize,
+    // None where the backend has no pipeline statistics queries.
+    statistics: Option<PipelineStatisticsPool<A>>,
 }
 
 impl<A: hal::Api> RenderFrame<A> {
@@ -62,6 +87,246 @@ impl<A: hal::Api> RenderFrame<A> {
     unsafe fn destroy(self, device: &A::Device) {
         device.destroy_command_encoder(self.encoder);
         device.destroy_fence(self.fence);
+        if let Some(statistics) = self.statistics {
+            statistics.destroy(device);
+        }
+    }
+}
+
+// Pipeline statistics queries, one per render pass, and the buffer their results are copied
+// into. One per frame in flight, so results are read once that frame's fence has passed instead
+// of stalling on the GPU.
+pub struct PipelineStatisticsPool<A: hal::Api> {
+    set: A::QuerySet,
+    readback: A::Buffer,
+    capacity: u32,
+    // The pass each query recorded this frame is for, in query order.
+    passes: Vec<&'static str>,
+    // The readback starts out unused, afterwards it's left as MAP_READ between frames.
+    resolved: bool,
+}
+
+impl<A: hal::Api> PipelineStatisticsPool<A> {
+    // Results come out as one u64 per type, in the order of the types' bits.
+    const TYPES: wgt::PipelineStatisticsTypes =
+        wgt::PipelineStatisticsTypes::VERTEX_SHADER_INVOCATIONS
+            .union(wgt::PipelineStatisticsTypes::CLIPPER_INVOCATIONS)
+            .union(wgt::PipelineStatisticsTypes::CLIPPER_PRIMITIVES_OUT)
+            .union(wgt::PipelineStatisticsTypes::FRAGMENT_SHADER_INVOCATIONS);
+    const RESULT_SIZE: u64 = 4 * 8;
+
+    unsafe fn new(device: &A::Device, capacity: u32) -> Result<Self, hal::DeviceError> {
+        let set = device.create_query_set(&wgt::QuerySetDescriptor {
+            label: Some("pipeline statistics"),
+            ty: wgt::QueryType::PipelineStatistics(Self::TYPES),
+            count: capacity,
+        })?;
+        let size = u64::from(capacity) * Self::RESULT_SIZE;
+        let readback = device.create_buffer(&hal::BufferDescriptor {
+            label: Some("pipeline statistics readback"),
+            size,
+            usage: hal::BufferUses::MAP_READ | hal::BufferUses::COPY_DST,
+            memory_flags: hal::MemoryFlags::empty(),
+        })?;
+        gpu_memory::record_alloc(GpuMemoryCategory::Buffers, size);
+        Ok(Self {
+            set,
+            readback,
+            capacity,
+            passes: Vec::new(),
+            resolved: false,
+        })
+    }
+
+    // Before the frame's first pass.
+    unsafe fn begin_frame(&mut self, encoder: &mut A::CommandEncoder) {
+        self.passes.clear();
+        encoder.reset_queries(&self.set, 0..self.capacity);
+    }
+
+    // Inside the render pass, around everything it records. Passes past the capacity go
+    // unqueried.
+    unsafe fn pass(
+        &mut self,
+        encoder: &mut A::CommandEncoder,
+        name: &'static str,
+        record: impl FnOnce(&mut A::CommandEncoder),
+    ) {
+        if self.passes.len() as u32 == self.capacity {
+            record(encoder);
+            return;
+        }
+        let index = self.passes.len() as u32;
+        self.passes.push(name);
+        encoder.begin_query(&self.set, index);
+        record(encoder);
+        encoder.end_query(&self.set, index);
+    }
+
+    // After the frame's last pass.
+    unsafe fn resolve(&mut self, encoder: &mut A::CommandEncoder) {
+        if self.passes.is_empty() {
+            return;
+        }
+        let previous = if self.resolved {
+            hal::BufferUses::MAP_READ
+        } else {
+            hal::BufferUses::empty()
+        };
+        self.resolved = true;
+        encoder.transition_buffers(iter::once(hal::BufferBarrier::<A> {
+            buffer: &self.readback,
+            usage: previous..hal::BufferUses::COPY_DST,
+        }));
+        encoder.copy_query_results(
+            &self.set,
+            0..self.passes.len() as u32,
+            &self.readback,
+            0,
+            wgt::BufferSize::new(Self::RESULT_SIZE).unwrap(),
+        );
+        encoder.transition_buffers(iter::once(hal::BufferBarrier::<A> {
+            buffer: &self.readback,
+            usage: hal::BufferUses::COPY_DST..hal::BufferUses::MAP_READ,
+        }));
+    }
+
+    // Once the frame's fence has passed.
+    unsafe fn read(
+        &mut self,
+        device: &A::Device,
+    ) -> Result<Vec<(&'static str, PassStats)>, hal::DeviceError> {
+        if self.passes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let size = self.passes.len() as u64 * Self::RESULT_SIZE;
+        let mapping = device.map_buffer(&self.readback, 0..size)?;
+        if !mapping.is_coherent {
+            device.invalidate_mapped_ranges(&self.readback, iter::once(0..size));
+        }
+        let values = mapping.ptr.as_ptr() as *const u64;
+        let results = self
+            .passes
+            .drain(..)
+            .enumerate()
+            .map(|(index, name)| {
+                let value = |i: usize| values.add(index * 4 + i).read_unaligned();
+                let stats = PassStats {
+                    vertex_invocations: value(0),
+                    clipper_invocations: value(1),
+                    primitives_out: value(2),
+                    fragment_invocations: value(3),
+                };
+                (name, stats)
+            })
+            .collect();
+        device.unmap_buffer(&self.readback)?;
+        Ok(results)
+    }
+
+    unsafe fn destroy(self, device: &A::Device) {
+        device.destroy_query_set(self.set);
+        device.destroy_buffer(self.readback);
+        gpu_memory::record_free(
+            GpuMemoryCategory::Buffers,
+            u64::from(self.capacity) * Self::RESULT_SIZE,
+        );
+    }
+}
+
+// What a custom render pass records with: the frame's encoder, inside a render pass over the
+// surface that loads what the passes before it drew.
+pub struct PassEncoder<'a> {
+    name: &'static str,
+    device: &'a <TargetApi as hal::Api>::Device,
+    encoder: &'a mut <TargetApi as hal::Api>::CommandEncoder,
+    extent: [u32; 2],
+}
+
+impl<'a> PassEncoder<'a> {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // The surface's size in pixels.
+    pub fn extent(&self) -> [u32; 2] {
+        self.extent
+    }
+
+    // For creating the pass's pipelines and buffers, which it owns and has to destroy.
+    pub fn device(&self) -> &<TargetApi as hal::Api>::Device {
+        self.device
+    }
+
+    // Safety: recording has the same requirements as with any wgpu-hal encoder, and the pass
+    // mustn't end the render pass or begin another.
+    pub unsafe fn raw(&mut self) -> &mut <TargetApi as hal::Api>::CommandEncoder {
+        self.encoder
+    }
+
+    // Covers the whole surface by default.
+    pub fn set_viewport(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        let rect = hal::Rect {
+            x,
+            y,
+            w: width,
+            h: height,
+        };
+        unsafe { self.encoder.set_viewport(&rect, 0.0..1.0) };
+    }
+}
+
+// Records the custom passes after the renderer's own, each in a render pass of its own.
+unsafe fn encode_custom_passes(
+    device: &<TargetApi as hal::Api>::Device,
+    encoder: &mut <TargetApi as hal::Api>::CommandEncoder,
+    statistics: &mut Option<PipelineStatisticsPool<TargetApi>>,
+    surface_view: &<TargetApi as hal::Api>::TextureView,
+    extent: [u32; 2],
+) {
+    for (name, encode) in render_graph::ordered_passes() {
+        // Only the pass is locked while it records, not the graph, so it can register or remove
+        // passes.
+        let mut guard = encode.lock().unwrap();
+        let encode: &mut EncodePass = &mut guard;
+        let _pass_span = tracing::debug_span!("render_pass", name = name).entered();
+        profile_scope!(name);
+        encoder.begin_render_pass(&hal::RenderPassDescriptor {
+            label: Some(name),
+            extent: wgt::Extent3d {
+                width: extent[0],
+                height: extent[1],
+                depth_or_array_layers: 1,
+            },
+            sample_count: 1,
+            color_attachments: &[Some(hal::ColorAttachment {
+                target: hal::Attachment::<TargetApi> {
+                    view: surface_view,
+                    usage: hal::TextureUses::COLOR_TARGET,
+                },
+                resolve_target: None,
+                ops: hal::AttachmentOps::LOAD | hal::AttachmentOps::STORE,
+                clear_value: wgt::Color::TRANSPARENT,
+            })],
+            depth_stencil_attachment: None,
+            multiview: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        let mut record = |encoder: &mut <TargetApi as hal::Api>::CommandEncoder| {
+            encode(&mut PassEncoder {
+                name,
+                device,
+                encoder,
+                extent,
+            })
+        };
+        match statistics.as_mut() {
+            Some(statistics) => statistics.pass(encoder, name, record),
+            None => record(encoder),
+        }
+        encoder.end_render_pass();
+        metrics::increment("render_passes_total", 1);
     }
 }
 
@@ -69,8 +334,11 @@ impl<A: hal::Api> RenderFrame<A> {
 pub struct GameRenderer<A: hal::Api> {
     instance: A::Instance,
     adapter: A::Adapter,
-    surface: A::Surface,
+    // None while the app is suspended, mobile platforms destroy the native window underneath us.
+    surface: Option<A::Surface>,
     surface_format: wgt::TextureFormat,
+    surface_config: hal::SurfaceConfiguration,
+    window: Arc<window::Window>,
     device: A::Device,
     queue: A::Queue,
     frames_in_flight: [Option<RenderFrame<A>>; MAX_FRAMES_IN_FLIGHT as usize],
@@ -79,7 +347,17 @@ pub struct GameRenderer<A: hal::Api> {
 }
 
 impl<A: hal::Api> GameRenderer<A> {
-    fn init(window: &winit::window::Window) -> Result<Self, Box<dyn std::error::Error>> {
+    unsafe fn create_surface(
+        instance: &A::Instance,
+        window: &window::Window,
+    ) -> Result<A::Surface, Box<dyn std::error::Error>> {
+        let raw_window_handle = window.window_handle()?.as_raw();
+        let raw_display_handle = window.display_handle()?.as_raw();
+
+        Ok(instance.create_surface(raw_display_handle, raw_window_handle)?)
+    }
+
+    fn init(window: Arc<window::Window>) -> Result<Self, Box<dyn std::error::Error>> {
         let instance_desc = hal::InstanceDescriptor {
             name: "Midnight2Instance",
             flags: wgt::InstanceFlags::from_build_config().with_env(),
@@ -91,23 +369,15 @@ impl<A: hal::Api> GameRenderer<A> {
         };
 
         let instance = unsafe { A::Instance::init(&instance_desc)? };
-        let surface = {
-            let raw_window_handle = window.window_handle()?.as_raw();
-            let raw_display_handle = window.display_handle()?.as_raw();
-
-            unsafe {
-                instance
-                    .create_surface(raw_display_handle, raw_window_handle)
-                    .unwrap()
-            }
-        };
-        let (adapter, capabilities) = unsafe {
+        let surface = unsafe { Self::create_surface(&instance, &window)? };
+        let (adapter, features) = unsafe {
             let mut adapters = instance.enumerate_adapters();
             if adapters.is_empty() {
                 return Err("no adapters found".into());
             }
             let exposed = adapters.swap_remove(0);
-            (exposed.adapter, exposed.capabilities)
+            crash_report::set_gpu_info(format!("{:?}", exposed.info));
+            (exposed.adapter, exposed.features)
         };
 
         let surface_caps = unsafe { adapter.surface_capabilities(&surface) }
@@ -116,7 +386,10 @@ impl<A: hal::Api> GameRenderer<A> {
 
         let hal::OpenDevice { device, queue } = unsafe {
             adapter
-                .open(wgt::Features::empty(), &wgt::Limits::default())
+                .open(
+                    features & wgt::Features::PIPELINE_STATISTICS_QUERY,
+                    &wgt::Limits::default(),
+                )
                 .unwrap()
         };
 
@@ -140,6 +413,10 @@ impl<A: hal::Api> GameRenderer<A> {
         unsafe {
             surface.configure(&device, &surface_config).unwrap();
         };
+        gpu_memory::record_alloc(
+            GpuMemoryCategory::RenderTargets,
+            swap_chain_bytes(&surface_config),
+        );
 
         let frame_data: [Option<RenderFrame<A>>; MAX_FRAMES_IN_FLIGHT as usize] = core::array::from_fn(|_| {
             unsafe {
@@ -155,6 +432,11 @@ impl<A: hal::Api> GameRenderer<A> {
                     used_views: Vec::new(),
                     used_cmd_bufs: Vec::new(),
                     frames_recorded: 0,
+                    statistics: features
+                        .contains(wgt::Features::PIPELINE_STATISTICS_QUERY)
+                        .then(|| {
+                            PipelineStatisticsPool::new(&device, MAX_STATISTICS_PASSES).unwrap()
+                        }),
                 };
                 Some(frame)
             }
@@ -164,8 +446,10 @@ impl<A: hal::Api> GameRenderer<A> {
         Ok(Self {
             instance: instance,
             adapter: adapter,
-            surface: surface,
+            surface: Some(surface),
             surface_format: surface_config.format,
+            surface_config: surface_config,
+            window: window,
             device: device,
             queue: queue,
             frames_in_flight: frame_data,
@@ -173,14 +457,65 @@ impl<A: hal::Api> GameRenderer<A> {
             extent: [window_size.0, window_size.1],
         })
     }
+
+    // Waits for the GPU to finish with every frame in flight, surface texture views included.
+    fn wait_idle(&mut self) {
+        for frame in self.frames_in_flight.iter_mut().flatten() {
+            unsafe { frame.wait_and_clear(&self.device) };
+        }
+    }
+
+    fn release_surface(&mut self) {
+        if self.surface.is_none() {
+            return;
+        }
+        self.wait_idle();
+        if let Some(surface) = self.surface.take() {
+            unsafe {
+                surface.unconfigure(&self.device);
+                self.instance.destroy_surface(surface);
+            }
+            gpu_memory::record_free(
+                GpuMemoryCategory::RenderTargets,
+                swap_chain_bytes(&self.surface_config),
+            );
+        }
+    }
+
+    fn recreate_surface(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let surface = unsafe { Self::create_surface(&self.instance, &self.window)? };
+
+        // The window may have been resized or rotated while we were suspended.
+        let window_size: (u32, u32) = self.window.inner_size().into();
+        self.surface_config.extent = wgt::Extent3d {
+            width: window_size.0,
+            height: window_size.1,
+            depth_or_array_layers: 1,
+        };
+        self.extent = [window_size.0, window_size.1];
+
+        unsafe { surface.configure(&self.device, &self.surface_config)? };
+        gpu_memory::record_alloc(
+            GpuMemoryCategory::RenderTargets,
+            swap_chain_bytes(&self.surface_config),
+        );
+        self.surface = Some(surface);
+        Ok(())
+    }
+
     fn exit(mut self) {
-        let frame = &mut self.frames_in_flight[self.frame_index].as_mut().unwrap();
+        self.wait_idle();
+        if let Some(surface) = self.surface.take() {
+            unsafe {
+                surface.unconfigure(&self.device);
+                self.instance.destroy_surface(surface);
+            }
+            gpu_memory::record_free(
+                GpuMemoryCategory::RenderTargets,
+                swap_chain_bytes(&self.surface_config),
+            );
+        }
         unsafe {
-            self.queue
-                .submit(&[], Some((&mut frame.fence, frame.fence_value)))
-                .unwrap();
-            frame.wait_and_clear(&self.device);
-
             for i in 0..MAX_FRAMES_IN_FLIGHT {
                 self.frames_in_flight[i as usize]
                     .take()
@@ -188,33 +523,90 @@ impl<A: hal::Api> GameRenderer<A> {
                     .destroy(&self.device);
             }
 
-            self.surface.unconfigure(&self.device);
             self.device.exit(self.queue);
-            self.instance.destroy_surface(self.surface);
             drop(self.adapter);
         }
     }
 }
 
-static mut S_SHUTDOWN: AtomicBool = AtomicBool::new(false);
+// Estimate of what the backend allocates for the swap chain, surface formats are 4 bytes per texel.
+fn swap_chain_bytes(config: &hal::SurfaceConfiguration) -> u64 {
+    let texels = u64::from(config.extent.width) * u64::from(config.extent.height);
+    texels * 4 * u64::from(config.swap_chain_size)
+}
+
+static S_SHUTDOWN: AtomicBool = AtomicBool::new(false);
 
-pub unsafe fn should_shutdown() -> bool {
+pub fn should_shutdown() -> bool {
     S_SHUTDOWN.load(Ordering::Relaxed)
 }
 
-pub unsafe fn shutdown() {
+pub fn shutdown() {
     S_SHUTDOWN.store(true, Ordering::Relaxed);
 }
 
+static S_FRAME: AtomicU64 = AtomicU64::new(0);
+
+// Number of frames submitted so far, readable from any thread.
+pub fn current_frame() -> u64 {
+    S_FRAME.load(Ordering::Relaxed)
+}
+
+static S_SUSPENDED: AtomicBool = AtomicBool::new(false);
+static S_SURFACE_RELEASED: AtomicBool = AtomicBool::new(false);
+
+pub fn is_suspended() -> bool {
+    S_SUSPENDED.load(Ordering::Relaxed)
+}
+
+// Android destroys the native window as soon as the suspend callback returns, so this blocks
+// until the render thread has let go of the surface.
+pub fn suspend() {
+    S_SUSPENDED.store(true, Ordering::Relaxed);
+    let started = Instant::now();
+    while !S_SURFACE_RELEASED.load(Ordering::Acquire) {
+        if should_shutdown() || started.elapsed() > Duration::from_secs(2) {
+            warn!("Render thread did not release the surface in time!");
+            break;
+        }
+        thread::yield_now();
+    }
+}
+
+pub fn resume() {
+    S_SUSPENDED.store(false, Ordering::Relaxed);
+}
+
 fn render_loop(game_renderer: &mut GameRenderer<TargetApi>) {
+    // sim_tick ties the frame to the latest sim state it could have seen.
+    let _frame_span = tracing::info_span!(
+        "frame",
+        frame = current_frame(),
+        sim_tick = sim::current_tick()
+    )
+    .entered();
+    profile_scope!("frame");
+    let started = Instant::now();
     let device = &game_renderer.device;
     let queue = &game_renderer.queue;
-    let surface = &game_renderer.surface;
+    let Some(surface) = game_renderer.surface.as_ref() else {
+        return;
+    };
 
     let frame = &mut game_renderer.frames_in_flight[game_renderer.frame_index]
         .as_mut()
         .unwrap();
     unsafe {
+        // The GPU is done with what this frame slot submitted last time around, its command
+        // buffers and views can go and its statistics can be read.
+        frame.wait_and_clear(device);
+        if let Some(statistics) = frame.statistics.as_mut() {
+            match statistics.read(device) {
+                Ok(passes) if !passes.is_empty() => frame_graph::record_pass_stats(passes),
+                Ok(_) => {}
+                Err(err) => warn!("Failed to read pipeline statistics: {}", err),
+            }
+        }
         let surface_tex = surface.acquire_texture(None).unwrap().unwrap().texture;
         let encoder = &mut frame.encoder;
         let target_barrier0: hal::TextureBarrier<'_, TargetApi> = hal::TextureBarrier {
@@ -223,6 +615,9 @@ fn render_loop(game_renderer: &mut GameRenderer<TargetApi>) {
             usage: hal::TextureUses::UNINITIALIZED..hal::TextureUses::COLOR_TARGET,
         };
         encoder.begin_encoding(Some("frame")).unwrap();
+        if let Some(statistics) = frame.statistics.as_mut() {
+            statistics.begin_frame(encoder);
+        }
         encoder.transition_textures(iter::once(target_barrier0));
 
         let surface_view_desc = hal::TextureViewDescriptor {
@@ -262,6 +657,8 @@ fn render_loop(game_renderer: &mut GameRenderer<TargetApi>) {
             timestamp_writes: None,
             occlusion_query_set: None,
         };
+        let pass_span = tracing::debug_span!("render_pass", name = "clear").entered();
+        let pass_scope = profiling::ProfileScope::new("render_pass", file!(), line!());
         encoder.begin_render_pass(&pass_desc);
 
         let target_barrier1 = hal::TextureBarrier::<TargetApi> {
@@ -270,34 +667,86 @@ fn render_loop(game_renderer: &mut GameRenderer<TargetApi>) {
             usage: hal::TextureUses::COLOR_TARGET..hal::TextureUses::PRESENT,
         };
         encoder.end_render_pass();
+        metrics::increment("render_passes_total", 1);
+        drop(pass_scope);
+        drop(pass_span);
+        encode_custom_passes(
+            device,
+            encoder,
+            &mut frame.statistics,
+            &surface_tex_view,
+            game_renderer.extent,
+        );
         encoder.transition_textures(iter::once(target_barrier1));
-        let fence_param: Option<(&mut hal::dx12::Fence, u64)> = if true {
-            Some((&mut frame.fence, frame.fence_value))
-        } else {
-            None
-        };
-
+        if let Some(statistics) = frame.statistics.as_mut() {
+            statistics.resolve(encoder);
+        }
         let cmd_buf = encoder.end_encoding().unwrap();
-        queue.submit(&[&cmd_buf], fence_param).unwrap();
+        // The slot's fence reaches this value once the GPU is done with the frame.
+        frame.fence_value += 1;
+        queue
+            .submit(&[&cmd_buf], Some((&mut frame.fence, frame.fence_value)))
+            .unwrap();
         queue.present(&surface, surface_tex).unwrap();
         frame.used_cmd_bufs.push(cmd_buf);
         frame.used_views.push(surface_tex_view);
     }
+    game_renderer.frame_index = (game_renderer.frame_index + 1) % MAX_FRAMES_IN_FLIGHT as usize;
+    // GPU frame times need timestamp queries, which the renderer doesn't issue yet.
+    frame_graph::record(FrameSeries::CpuFrame, started.elapsed());
+    metrics::observe_duration("render_frame_seconds", started.elapsed());
+    metrics::increment("render_frames_total", 1);
+    S_FRAME.fetch_add(1, Ordering::Relaxed);
 
     trace!("render loop! Renderer at {:p}", game_renderer);
 }
 
-pub fn init(window: &winit::window::Window) -> Result<JoinHandle<()>, Box<dyn std::error::Error>> {
-    let mut game_renderer = GameRenderer::<TargetApi>::init(window)?;
+pub type Renderer = GameRenderer<TargetApi>;
+
+// Creates the renderer without a thread of its own, for targets that have to drive
+// rendering from the event loop.
+pub fn create(window: Arc<window::Window>) -> Result<Renderer, Box<dyn std::error::Error>> {
+    GameRenderer::<TargetApi>::init(window)
+}
+
+pub fn render_frame(game_renderer: &mut Renderer) {
+    render_loop(game_renderer);
+    profiling::finish_frame("render");
+}
 
-    Ok(thread::spawn(move || loop {
+pub fn destroy(game_renderer: Renderer) {
+    game_renderer.exit();
+}
+
+pub fn init(window: Arc<window::Window>) -> Result<JoinHandle<()>, Box<dyn std::error::Error>> {
+    let mut game_renderer = create(window)?;
+
+    Ok(thread::Builder::new().name("render".to_owned()).spawn(move || loop {
+        let _memory_tag = memory::scope(MemoryTag::Render);
         
-        unsafe {
-            if should_shutdown() {
-                game_renderer.exit();
-                break;
+        if should_shutdown() {
+            game_renderer.exit();
+            break;
+        }
+        if is_suspended() {
+            if game_renderer.surface.is_some() {
+                info!("Suspended, releasing surface!");
+                game_renderer.release_surface();
+                S_SURFACE_RELEASED.store(true, Ordering::Release);
             }
+            thread::sleep(Duration::from_millis(10));
+            continue;
+        }
+        if game_renderer.surface.is_none() {
+            info!("Resumed, recreating surface!");
+            if let Err(err) = game_renderer.recreate_surface() {
+                error!("Failed to recreate surface: {}", err);
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            S_SURFACE_RELEASED.store(false, Ordering::Release);
         }
         render_loop(&mut game_renderer);
-    }))
-}
+        profiling::finish_frame("render");
+    })?)
+}
\ No newline at end of file
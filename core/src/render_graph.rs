@@ -0,0 +1,266 @@
+//! Custom render passes from game and plugin code. A pass declares the resources it reads and
+//! writes and gets an encode callback, which the render thread calls every frame inside a render
+//! pass of its own:
+//!
+//! ```ignore
+//! render_graph::register_pass(
+//!     RenderPassDesc::new("outline").reads("scene").writes(render_graph::SURFACE),
+//!     |pass| unsafe { draw_outlines(pass.raw(), pass.extent()) },
+//! )?;
+//! ```
+//!
+//! Passes run after the renderer's own, in an order that puts every pass reading a resource
+//! after the passes registered before it that write it, and every pass writing one after those
+//! registered before it that touch it at all; `before` and `after` order passes explicitly.
+//! Resources are names the passes agree on. The only one the renderer itself provides is
+//! `SURFACE`, the frame's color target, which every pass's render pass draws into.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use crate::{render::PassEncoder, schedule::topological_sort};
+
+// The surface texture the frame is presented from.
+pub const SURFACE: &str = "surface";
+
+// Passes the renderer records itself, before any custom pass.
+pub const BUILTIN_PASSES: &[&str] = &["clear"];
+
+pub type EncodePass = Box<dyn FnMut(&mut PassEncoder) + Send>;
+
+#[derive(Clone, Debug)]
+pub struct RenderPassDesc {
+    pub name: &'static str,
+    pub reads: Vec<&'static str>,
+    pub writes: Vec<&'static str>,
+    pub before: Vec<&'static str>,
+    pub after: Vec<&'static str>,
+}
+
+impl RenderPassDesc {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            before: Vec::new(),
+            after: Vec::new(),
+        }
+    }
+
+    pub fn reads(mut self, resource: &'static str) -> Self {
+        self.reads.push(resource);
+        self
+    }
+
+    pub fn writes(mut self, resource: &'static str) -> Self {
+        self.writes.push(resource);
+        self
+    }
+
+    // Runs before the named pass, if it's registered.
+    pub fn before(mut self, name: &'static str) -> Self {
+        self.before.push(name);
+        self
+    }
+
+    // Runs after the named pass, if it's registered.
+    pub fn after(mut self, name: &'static str) -> Self {
+        self.after.push(name);
+        self
+    }
+
+    fn touches(&self, resource: &str) -> bool {
+        self.reads.contains(&resource) || self.writes.contains(&resource)
+    }
+}
+
+struct Pass {
+    desc: RenderPassDesc,
+    // Shared with the render thread while it encodes, see `ordered_passes`.
+    encode: Arc<Mutex<EncodePass>>,
+}
+
+#[derive(Default)]
+pub struct RenderGraph {
+    passes: Vec<Pass>,
+    // Indices into `passes` in the order they run.
+    order: Vec<usize>,
+}
+
+impl RenderGraph {
+    // Fails if the name is taken or the pass's ordering would make a cycle, leaving the graph
+    // as it was.
+    pub fn add_pass(&mut self, desc: RenderPassDesc, encode: EncodePass) -> Result<(), String> {
+        if BUILTIN_PASSES.contains(&desc.name) || self.contains(desc.name) {
+            return Err(format!("render pass {} is already registered", desc.name));
+        }
+        self.passes.push(Pass {
+            desc,
+            encode: Arc::new(Mutex::new(encode)),
+        });
+        match self.sort() {
+            Ok(order) => {
+                self.order = order;
+                Ok(())
+            }
+            Err(err) => {
+                self.passes.pop();
+                Err(err)
+            }
+        }
+    }
+
+    pub fn remove_pass(&mut self, name: &str) -> bool {
+        let Some(index) = self.passes.iter().position(|pass| pass.desc.name == name) else {
+            return false;
+        };
+        self.passes.remove(index);
+        // Taking a pass out can't make a cycle.
+        self.order = self.sort().unwrap();
+        true
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.passes.iter().any(|pass| pass.desc.name == name)
+    }
+
+    pub fn desc(&self, name: &str) -> Option<&RenderPassDesc> {
+        self.passes
+            .iter()
+            .map(|pass| &pass.desc)
+            .find(|desc| desc.name == name)
+    }
+
+    // The custom passes' names in the order they run.
+    pub fn order(&self) -> Vec<&'static str> {
+        self.order
+            .iter()
+            .map(|&index| self.passes[index].desc.name)
+            .collect()
+    }
+
+    // Each pass's name and encode callback, in order.
+    pub fn passes(&self) -> Vec<(&'static str, Arc<Mutex<EncodePass>>)> {
+        self.order
+            .iter()
+            .map(|&index| {
+                let pass = &self.passes[index];
+                (pass.desc.name, pass.encode.clone())
+            })
+            .collect()
+    }
+
+    // Topological, taking the earliest registered of the passes that are free to go next.
+    fn sort(&self) -> Result<Vec<usize>, String> {
+        let index: HashMap<_, _> = self
+            .passes
+            .iter()
+            .enumerate()
+            .map(|(i, pass)| (pass.desc.name, i))
+            .collect();
+        let mut edges = Vec::new();
+        for (i, pass) in self.passes.iter().enumerate() {
+            let desc = &pass.desc;
+            for (j, earlier) in self.passes[..i].iter().enumerate() {
+                let earlier = &earlier.desc;
+                let depends = desc.reads.iter().any(|r| earlier.writes.contains(r))
+                    || desc.writes.iter().any(|&w| earlier.touches(w));
+                if depends {
+                    edges.push((j, i));
+                }
+            }
+            for to in desc.before.iter().filter_map(|name| index.get(name)) {
+                edges.push((i, *to));
+            }
+            for from in desc.after.iter().filter_map(|name| index.get(name)) {
+                edges.push((*from, i));
+            }
+        }
+        topological_sort(self.passes.len(), edges).map_err(|cycle| {
+            let names: Vec<_> = cycle
+                .into_iter()
+                .map(|i| self.passes[i].desc.name)
+                .collect();
+            format!("render passes {} are ordered in a cycle", names.join(", "))
+        })
+    }
+}
+
+static GRAPH: Mutex<Option<RenderGraph>> = Mutex::new(None);
+
+// Runs `f` with the graph the render thread encodes from.
+pub fn with_graph<R>(f: impl FnOnce(&mut RenderGraph) -> R) -> R {
+    f(GRAPH
+        .lock()
+        .unwrap()
+        .get_or_insert_with(RenderGraph::default))
+}
+
+// The passes to encode this frame, taken out of the graph so it isn't locked while they run.
+pub fn ordered_passes() -> Vec<(&'static str, Arc<Mutex<EncodePass>>)> {
+    with_graph(|graph| graph.passes())
+}
+
+pub fn register_pass(
+    desc: RenderPassDesc,
+    encode: impl FnMut(&mut PassEncoder) + Send + 'static,
+) -> Result<(), String> {
+    with_graph(|graph| graph.add_pass(desc, Box::new(encode)))
+}
+
+pub fn remove_pass(name: &str) -> bool {
+    with_graph(|graph| graph.remove_pass(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(graph: &mut RenderGraph, desc: RenderPassDesc) -> Result<(), String> {
+        graph.add_pass(desc, Box::new(|_| {}))
+    }
+
+    #[test]
+    fn passes_are_ordered_by_resource_usage() {
+        let mut graph = RenderGraph::default();
+        add(&mut graph, RenderPassDesc::new("sky").writes("hdr")).unwrap();
+        add(
+            &mut graph,
+            RenderPassDesc::new("tonemap").reads("hdr").writes(SURFACE),
+        )
+        .unwrap();
+        add(&mut graph, RenderPassDesc::new("debug").writes(SURFACE)).unwrap();
+        add(
+            &mut graph,
+            RenderPassDesc::new("bloom").reads("hdr").before("tonemap"),
+        )
+        .unwrap();
+        assert_eq!(graph.order(), ["sky", "bloom", "tonemap", "debug"]);
+
+        assert!(add(&mut graph, RenderPassDesc::new("clear")).is_err());
+        assert!(add(&mut graph, RenderPassDesc::new("sky")).is_err());
+        let cycle = add(
+            &mut graph,
+            RenderPassDesc::new("fog").after("debug").before("sky"),
+        );
+        assert!(cycle.is_err());
+        assert!(!graph.contains("fog"));
+
+        assert!(graph.remove_pass("tonemap"));
+        assert_eq!(graph.order(), ["sky", "debug", "bloom"]);
+    }
+
+    #[test]
+    fn the_graph_can_change_while_passes_encode() {
+        register_pass(RenderPassDesc::new("once"), |_| {}).unwrap();
+        let passes = ordered_passes();
+        assert_eq!(passes.len(), 1);
+        // As the render thread holds it while the pass records.
+        let _encoding = passes[0].1.lock().unwrap();
+        assert!(remove_pass("once"));
+        assert!(ordered_passes().is_empty());
+    }
+}
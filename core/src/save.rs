@@ -0,0 +1,553 @@
+//! Save games. Each game profile gets a directory under the platform's save directory (see
+//! `PlatformServices::save_directory`, which is per user and synced by platforms with cloud
+//! saves), holding one file per slot:
+//!
+//! ```text
+//! <save directory>/profiles/<profile>/slot-1.sav
+//! <save directory>/profiles/<profile>/named-quicksave.sav
+//! ```
+//!
+//! A save holds metadata for the load menu (title, timestamp, playtime, a thumbnail) and the
+//! game's world snapshot as a `ScriptValue`, e.g. a table of entity states, or an editor `Scene`
+//! as text. Slots are listed without reading the snapshots.
+//!
+//! Saves record the game's data version. Loading an older save runs the migrations registered
+//! for each version in between, saves from a newer build are refused.
+
+use std::{
+    cmp::Reverse, collections::BTreeMap, error::Error, fmt, fs, path::PathBuf, time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(target_arch = "wasm32")]
+use web_time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    engine::{Engine, Plugin},
+    metrics,
+    platform::Platform,
+    scripting::ScriptValue,
+};
+
+const MAGIC: &[u8; 4] = b"M2SV";
+// Version of the file layout below, not of the game's data.
+const FORMAT_VERSION: u32 = 1;
+const EXTENSION: &str = "sav";
+const PROFILES_DIR: &str = "profiles";
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Slot {
+    Numbered(u32),
+    // E.g. `quicksave` or `autosave`.
+    Named(String),
+}
+
+// File names start with these, so no named slot can have a numbered one's file.
+const NUMBERED_PREFIX: &str = "slot-";
+const NAMED_PREFIX: &str = "named-";
+
+impl Slot {
+    fn file_stem(&self) -> String {
+        match self {
+            Slot::Numbered(number) => format!("{}{}", NUMBERED_PREFIX, number),
+            Slot::Named(name) => format!("{}{}", NAMED_PREFIX, name),
+        }
+    }
+
+    // None for files no slot saves to.
+    fn from_file_stem(stem: &str) -> Option<Slot> {
+        if let Some(name) = stem.strip_prefix(NAMED_PREFIX) {
+            return Some(Slot::Named(name.to_owned())).filter(|_| !name.is_empty());
+        }
+        let number: u32 = stem.strip_prefix(NUMBERED_PREFIX)?.parse().ok()?;
+        Some(Slot::Numbered(number)).filter(|slot| slot.file_stem() == stem)
+    }
+}
+
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Slot::Numbered(number) => write!(f, "slot {}", number),
+            Slot::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// Small RGBA8 image shown in the load menu, e.g. a downscaled screenshot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub width: u16,
+    pub height: u16,
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveMeta {
+    pub title: String,
+    pub timestamp: SystemTime,
+    pub playtime: Duration,
+    // Set on save to the game's data version.
+    pub version: u32,
+    // Whatever else the load menu shows, e.g. the chapter or character level.
+    pub extra: BTreeMap<String, ScriptValue>,
+}
+
+impl Default for SaveMeta {
+    fn default() -> Self {
+        Self {
+            title: String::new(),
+            timestamp: SystemTime::now(),
+            playtime: Duration::ZERO,
+            version: 0,
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveGame {
+    pub meta: SaveMeta,
+    pub thumbnail: Option<Thumbnail>,
+    pub data: ScriptValue,
+}
+
+// A slot as the load menu lists it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveInfo {
+    pub slot: Slot,
+    pub meta: SaveMeta,
+    pub thumbnail: Option<Thumbnail>,
+}
+
+// Upgrades save data written by one version to the next.
+pub type Migration = Box<dyn Fn(&mut ScriptValue) -> Result<(), String> + Send>;
+
+pub struct SaveGames {
+    root: PathBuf,
+    profile: String,
+    version: u32,
+    // By the version they upgrade from.
+    migrations: BTreeMap<u32, Migration>,
+}
+
+impl SaveGames {
+    // Saves under `save_dir`, writing data version `version`.
+    pub fn new(save_dir: impl Into<PathBuf>, version: u32) -> Self {
+        Self {
+            root: save_dir.into().join(PROFILES_DIR),
+            profile: DEFAULT_PROFILE.to_owned(),
+            version,
+            migrations: BTreeMap::new(),
+        }
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    // Registers the upgrade of data from version `from` to `from + 1`.
+    pub fn add_migration<F>(&mut self, from: u32, migrate: F) -> &mut Self
+    where
+        F: Fn(&mut ScriptValue) -> Result<(), String> + Send + 'static,
+    {
+        self.migrations.insert(from, Box::new(migrate));
+        self
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    // Switches to `name`, it's created on the first save.
+    pub fn set_profile(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        check_name(name)?;
+        self.profile = name.to_owned();
+        Ok(())
+    }
+
+    pub fn profiles(&self) -> Vec<String> {
+        let mut profiles: Vec<String> = fs::read_dir(&self.root)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        profiles.sort();
+        profiles
+    }
+
+    // Deletes a profile with all its saves.
+    pub fn delete_profile(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        check_name(name)?;
+        fs::remove_dir_all(self.root.join(name))?;
+        info!("Deleted save profile {}", name);
+        Ok(())
+    }
+
+    fn profile_dir(&self) -> PathBuf {
+        self.root.join(&self.profile)
+    }
+
+    fn slot_path(&self, slot: &Slot) -> Result<PathBuf, Box<dyn Error>> {
+        let stem = slot.file_stem();
+        check_name(&stem)?;
+        Ok(self.profile_dir().join(format!("{}.{}", stem, EXTENSION)))
+    }
+
+    pub fn save(&self, slot: &Slot, save: &SaveGame) -> Result<(), Box<dyn Error>> {
+        let path = self.slot_path(slot)?;
+        fs::create_dir_all(self.profile_dir())?;
+        let mut bytes = Vec::new();
+        encode(save, self.version, &mut bytes);
+        // Written next to it first, so a crash mid-save doesn't lose the previous one.
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, &bytes)?;
+        fs::rename(&temp, &path)?;
+        metrics::increment("saves_written_total", 1);
+        info!("Saved {} to {}", slot, path.display());
+        Ok(())
+    }
+
+    pub fn load(&self, slot: &Slot) -> Result<SaveGame, Box<dyn Error>> {
+        let path = self.slot_path(slot)?;
+        let bytes = fs::read(&path)?;
+        let mut save =
+            decode(&bytes, true).map_err(|err| format!("{}: {}", path.display(), err))?;
+        self.migrate(&mut save)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        Ok(save)
+    }
+
+    pub fn delete(&self, slot: &Slot) -> Result<(), Box<dyn Error>> {
+        fs::remove_file(self.slot_path(slot)?)?;
+        Ok(())
+    }
+
+    // The current profile's saves, newest first. Unreadable files are skipped with a warning.
+    pub fn slots(&self) -> Vec<SaveInfo> {
+        let mut slots = Vec::new();
+        for entry in fs::read_dir(self.profile_dir()).into_iter().flatten() {
+            let Ok(path) = entry.map(|entry| entry.path()) else {
+                continue;
+            };
+            if path.extension() != Some(EXTENSION.as_ref()) {
+                continue;
+            }
+            let Some(slot) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(Slot::from_file_stem)
+            else {
+                continue;
+            };
+            match fs::read(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|bytes| decode(&bytes, false))
+            {
+                Ok(save) => slots.push(SaveInfo {
+                    slot,
+                    meta: save.meta,
+                    thumbnail: save.thumbnail,
+                }),
+                Err(err) => warn!("Skipping save {}: {}", path.display(), err),
+            }
+        }
+        slots.sort_by_key(|info| Reverse(info.meta.timestamp));
+        slots
+    }
+
+    fn migrate(&self, save: &mut SaveGame) -> Result<(), String> {
+        if save.meta.version > self.version {
+            return Err(format!(
+                "saved by a newer version ({}, this is {})",
+                save.meta.version, self.version
+            ));
+        }
+        for version in save.meta.version..self.version {
+            if let Some(migrate) = self.migrations.get(&version) {
+                migrate(&mut save.data)
+                    .map_err(|err| format!("migrating from version {}: {}", version, err))?;
+            }
+        }
+        if save.meta.version != self.version {
+            info!(
+                "Migrated save from version {} to {}",
+                save.meta.version, self.version
+            );
+            save.meta.version = self.version;
+        }
+        Ok(())
+    }
+}
+
+// Profile and slot names become file names.
+fn check_name(name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ' ' | '.'));
+    if !valid {
+        return Err(format!("{:?} can't be used as a save name", name));
+    }
+    Ok(())
+}
+
+// Magic, format version, data version, then the metadata, thumbnail and data, each prefixed with
+// its length so listing can stop before the data.
+fn encode(save: &SaveGame, version: u32, out: &mut Vec<u8>) {
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&version.to_le_bytes());
+    let timestamp = save
+        .meta
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let meta = ScriptValue::Table(BTreeMap::from([
+        (
+            "title".to_owned(),
+            ScriptValue::String(save.meta.title.clone()),
+        ),
+        (
+            "timestamp".to_owned(),
+            ScriptValue::Number(timestamp.as_secs_f64()),
+        ),
+        (
+            "playtime".to_owned(),
+            ScriptValue::Number(save.meta.playtime.as_secs_f64()),
+        ),
+        (
+            "extra".to_owned(),
+            ScriptValue::Table(save.meta.extra.clone()),
+        ),
+    ]));
+    let mut section = Vec::new();
+    meta.encode(&mut section);
+    push_section(out, &section);
+
+    section.clear();
+    if let Some(thumbnail) = &save.thumbnail {
+        section.extend_from_slice(&thumbnail.width.to_le_bytes());
+        section.extend_from_slice(&thumbnail.height.to_le_bytes());
+        section.extend_from_slice(&thumbnail.rgba);
+    }
+    push_section(out, &section);
+
+    section.clear();
+    save.data.encode(&mut section);
+    push_section(out, &section);
+}
+
+fn push_section(out: &mut Vec<u8>, section: &[u8]) {
+    out.extend_from_slice(&(section.len() as u32).to_le_bytes());
+    out.extend_from_slice(section);
+}
+
+fn decode(bytes: &[u8], with_data: bool) -> Result<SaveGame, String> {
+    let mut reader = Reader(bytes);
+    if reader.take(4) != Some(MAGIC.as_slice()) {
+        return Err("not a save game".to_owned());
+    }
+    let format = reader.u32().ok_or("truncated")?;
+    if format != FORMAT_VERSION {
+        return Err(format!("unknown save format {}", format));
+    }
+    let version = reader.u32().ok_or("truncated")?;
+
+    let meta = reader
+        .section()
+        .and_then(ScriptValue::decode)
+        .ok_or("corrupt metadata")?;
+    let seconds = |key| {
+        meta.get(key)
+            .and_then(ScriptValue::as_f64)
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .unwrap_or_default()
+    };
+    let meta = SaveMeta {
+        title: meta
+            .get("title")
+            .and_then(ScriptValue::as_str)
+            .unwrap_or_default()
+            .to_owned(),
+        timestamp: UNIX_EPOCH
+            .checked_add(seconds("timestamp"))
+            .ok_or("corrupt metadata")?,
+        playtime: seconds("playtime"),
+        version,
+        extra: match meta.get("extra") {
+            Some(ScriptValue::Table(extra)) => extra.clone(),
+            _ => BTreeMap::new(),
+        },
+    };
+
+    let thumbnail = match reader.section().ok_or("corrupt thumbnail")? {
+        [] => None,
+        [w0, w1, h0, h1, rgba @ ..] => {
+            let (width, height) = (
+                u16::from_le_bytes([*w0, *w1]),
+                u16::from_le_bytes([*h0, *h1]),
+            );
+            if rgba.len() != usize::from(width) * usize::from(height) * 4 {
+                return Err("corrupt thumbnail".to_owned());
+            }
+            Some(Thumbnail {
+                width,
+                height,
+                rgba: rgba.to_vec(),
+            })
+        }
+        _ => return Err("corrupt thumbnail".to_owned()),
+    };
+
+    let data = if with_data {
+        reader
+            .section()
+            .and_then(ScriptValue::decode)
+            .ok_or("corrupt data")?
+    } else {
+        ScriptValue::Nil
+    };
+    Ok(SaveGame {
+        meta,
+        thumbnail,
+        data,
+    })
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn section(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+// Inserts `SaveGames` in the `Platform`'s save directory, add it after `PlatformPlugin`.
+pub struct SaveGamePlugin {
+    // The game's current data version.
+    pub version: u32,
+}
+
+impl Plugin for SaveGamePlugin {
+    fn build(&self, engine: &mut Engine) {
+        let Some(save_dir) = engine
+            .resources()
+            .get::<Platform>()
+            .map(|platform| platform.save_directory())
+        else {
+            error!("SaveGamePlugin needs a Platform, add PlatformPlugin first");
+            return;
+        };
+        engine.insert_resource(SaveGames::new(save_dir, self.version));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_lists_and_migrates_slots() {
+        let dir = std::env::temp_dir().join(format!("midnight2-saves-{}", std::process::id()));
+        let old = SaveGames::new(&dir, 1);
+        let save = SaveGame {
+            meta: SaveMeta {
+                title: "Harbor".to_owned(),
+                playtime: Duration::from_secs(95),
+                ..SaveMeta::default()
+            },
+            thumbnail: Some(Thumbnail {
+                width: 1,
+                height: 2,
+                rgba: vec![255; 8],
+            }),
+            data: "{ hp = 3 }".parse().unwrap(),
+        };
+        old.save(&Slot::Numbered(1), &save).unwrap();
+        old.save(&Slot::Named("quicksave".to_owned()), &save)
+            .unwrap();
+
+        let slots = old.slots();
+        assert_eq!(slots.len(), 2);
+        assert_eq!(slots[0].meta.title, "Harbor");
+        assert_eq!(slots[0].thumbnail, save.thumbnail);
+        assert!(old.slot_path(&Slot::Named("../escape".to_owned())).is_err());
+        // A named slot never shares a numbered one's file.
+        let named = Slot::Named("slot-1".to_owned());
+        assert_ne!(
+            old.slot_path(&named).unwrap(),
+            old.slot_path(&Slot::Numbered(1)).unwrap()
+        );
+        assert_eq!(Slot::from_file_stem(&named.file_stem()), Some(named));
+        assert_eq!(Slot::from_file_stem("slot-01"), None);
+
+        // Version 2 renamed hp to health.
+        let mut new = SaveGames::new(&dir, 2);
+        new.add_migration(1, |data| {
+            let ScriptValue::Table(table) = data else {
+                return Err("expected a table".to_owned());
+            };
+            let hp = table.remove("hp").ok_or("no hp")?;
+            table.insert("health".to_owned(), hp);
+            Ok(())
+        });
+        let loaded = new.load(&Slot::Numbered(1)).unwrap();
+        assert_eq!(loaded.meta.version, 2);
+        assert_eq!(loaded.data, "{ health = 3 }".parse().unwrap());
+        new.save(&Slot::Numbered(1), &loaded).unwrap();
+        assert!(old.load(&Slot::Numbered(1)).is_err());
+
+        new.delete_profile(DEFAULT_PROFILE).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn skips_saves_from_beyond_the_clock() {
+        let dir = std::env::temp_dir().join(format!("midnight2-far-saves-{}", std::process::id()));
+        let mut saves = SaveGames::new(&dir, 1);
+        let save = SaveGame {
+            meta: SaveMeta::default(),
+            thumbnail: None,
+            data: ScriptValue::Nil,
+        };
+        saves.save(&Slot::Numbered(1), &save).unwrap();
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        let mut meta = Vec::new();
+        ScriptValue::Table(BTreeMap::from([(
+            "timestamp".to_owned(),
+            ScriptValue::Number(1e19),
+        )]))
+        .encode(&mut meta);
+        push_section(&mut bytes, &meta);
+        push_section(&mut bytes, &[]);
+        assert_eq!(decode(&bytes, false).unwrap_err(), "corrupt metadata");
+        fs::write(saves.slot_path(&Slot::Numbered(2)).unwrap(), &bytes).unwrap();
+        assert_eq!(saves.slots().len(), 1);
+
+        saves.delete_profile(DEFAULT_PROFILE).unwrap();
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
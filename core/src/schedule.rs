@@ -0,0 +1,897 @@
+//! Gameplay systems over the ECS world, run by the sim every tick it runs the game's fixed
+//! update, just before it. Unlike the engine's systems each one is named, declares the
+//! components and resources it reads and writes, and can be ordered before or after others by
+//! name:
+//!
+//! ```ignore
+//! schedule::schedule(engine)
+//!     .add_system("movement", |world| {
+//!         for (mut position, velocity) in world.query::<(&mut Position, &Velocity), ()>().iter_mut() {
+//!             position.0 += velocity.0;
+//!         }
+//!     })
+//!     .writes::<Position>()
+//!     .reads::<Velocity>()
+//!     .after("input");
+//! ```
+//!
+//! A system only gets at what it declared, `SystemWorld` panics on anything else, so the
+//! declarations can be trusted to say which systems touch the same data. Systems that spawn,
+//! despawn or add and remove components queue that with `commands`, which the schedule applies
+//! once the system's stage is done, or need the whole world right away and declare themselves
+//! `exclusive`. Systems talk to each other through the world's `Events`, which every run
+//! updates first, so a reader sees each event once however it's ordered against the writer.
+//!
+//! That lets the schedule run systems that don't conflict at the same time: it splits the order
+//! into stages of systems that neither conflict nor are ordered against each other, and runs
+//! each stage across a worker pool. Conflicting systems still run in the order above, so the
+//! results are the same as running them one by one. What several systems read at once has to be
+//! `Sync`.
+
+use std::{
+    any::{Any, TypeId},
+    collections::{BTreeSet, HashMap},
+    ptr::NonNull,
+};
+
+use log::error;
+
+use crate::{
+    ecs::{
+        Access, CommandQueue, Commands, Component, ComponentType, EcsWorld, Entity, EventCursor,
+        EventReader, EventWriter, Events, Fetch, Filter, Query,
+    },
+    engine::{Engine, Resources},
+    workers::WorkerPool,
+};
+
+pub type ScheduledSystem = Box<dyn FnMut(&mut SystemWorld) + Send>;
+
+// What a system touches. Resources are listed by their types the same way components are.
+#[derive(Clone, Debug, Default)]
+pub struct SystemAccess {
+    pub components: Access,
+    pub resources: Access,
+    // Needs the whole world and every resource, so it can't share them with anything.
+    pub exclusive: bool,
+}
+
+impl SystemAccess {
+    // Whether the two can't run at the same time.
+    pub fn conflicts_with(&self, other: &SystemAccess) -> bool {
+        self.exclusive
+            || other.exclusive
+            || self.components.conflicts_with(&other.components)
+            || self.resources.conflicts_with(&other.resources)
+    }
+}
+
+// The world and resources the systems of a stage share. Their declared access keeps them from
+// touching the same things, everything below relies on the checks in `SystemWorld`.
+#[derive(Clone, Copy)]
+struct Shared<'a> {
+    world: NonNull<EcsWorld>,
+    resources: SharedResources<'a>,
+}
+
+#[derive(Clone, Copy)]
+enum SharedResources<'a> {
+    // A system running on its own.
+    All(NonNull<Resources>),
+    // The engine's and the world's resources the stage's systems declared.
+    Declared {
+        engine: &'a HashMap<TypeId, NonNull<dyn Any + Send>>,
+        world: &'a HashMap<TypeId, NonNull<dyn Any + Send>>,
+    },
+}
+
+unsafe impl Send for Shared<'_> {}
+
+// What a running system sees of the world and the resources, limited to what it declared.
+pub struct SystemWorld<'a> {
+    name: &'static str,
+    access: &'a SystemAccess,
+    // The world's change tick when the system last ran.
+    last_run: u64,
+    shared: Shared<'a>,
+    commands: &'a mut CommandQueue,
+    event_cursors: &'a mut HashMap<TypeId, EventCursor>,
+}
+
+impl<'a> SystemWorld<'a> {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    // Change filters see changes since the system last ran.
+    pub fn query<Q: Fetch, F: Filter>(&mut self) -> Query<'_, Q, F> {
+        let mut access = Access::default();
+        Q::access(&mut access);
+        F::access(&mut access);
+        self.check(&self.access.components, &access, "component");
+        let change_tick = unsafe { self.shared.world.as_ref() }.change_tick();
+        let archetypes = if self.access.exclusive {
+            NonNull::from(unsafe { self.shared.world.as_mut() }.archetypes_mut())
+        } else {
+            // Nothing adds or removes archetypes while systems share the world.
+            NonNull::from(unsafe { self.shared.world.as_ref() }.archetypes())
+        };
+        unsafe { Query::from_raw(archetypes, self.last_run, change_tick) }
+    }
+
+    pub fn get<T: Component>(&self, entity: Entity) -> Option<&T> {
+        self.check_component::<T>(false);
+        unsafe { self.shared.world.as_ref() }.get(entity)
+    }
+
+    pub fn get_mut<T: Component>(&mut self, entity: Entity) -> Option<&mut T> {
+        self.check_component::<T>(true);
+        unsafe { self.shared.world.as_ref() }
+            .get_ptr(entity)
+            .map(|component| unsafe { &mut *component.as_ptr() })
+    }
+
+    // One of the engine's resources.
+    pub fn resource<T: Component>(&self) -> Option<&T> {
+        self.check_resource::<T>(false);
+        match self.shared.resources {
+            SharedResources::All(resources) => unsafe { resources.as_ref() }.get(),
+            SharedResources::Declared { engine, .. } => declared::<T>(engine)
+                .and_then(|resource| unsafe { resource.as_ref() }.downcast_ref()),
+        }
+    }
+
+    pub fn resource_mut<T: Component>(&mut self) -> Option<&mut T> {
+        self.check_resource::<T>(true);
+        match self.shared.resources {
+            SharedResources::All(mut resources) => unsafe { resources.as_mut() }.get_mut(),
+            SharedResources::Declared { engine, .. } => declared::<T>(engine)
+                .and_then(|resource| unsafe { &mut *resource.as_ptr() }.downcast_mut()),
+        }
+    }
+
+    // One of the world's resources, declared the same way as the engine's.
+    pub fn res<T: Component>(&self) -> Option<&T> {
+        self.check_resource::<T>(false);
+        self.world_res()
+    }
+
+    pub fn res_mut<T: Component>(&mut self) -> Option<&mut T> {
+        self.check_resource::<T>(true);
+        match self.shared.resources {
+            SharedResources::All(_) => unsafe { self.shared.world.as_mut() }.res_mut(),
+            SharedResources::Declared { world, .. } => declared::<T>(world)
+                .and_then(|resource| unsafe { &mut *resource.as_ptr() }.downcast_mut()),
+        }
+    }
+
+    // The events of that type sent since the system last read them, the world needs to have
+    // added them with `EcsWorld::add_event`. Declared with `reads_events`.
+    pub fn event_reader<T: Component + Sync>(&mut self) -> Option<EventReader<'_, T>> {
+        self.check_resource::<Events<T>>(false);
+        let events = self.world_res::<Events<T>>()?;
+        let cursor = self.event_cursors.entry(TypeId::of::<T>()).or_default();
+        Some(EventReader::new(events, cursor))
+    }
+
+    // Declared with `writes_events`.
+    pub fn event_writer<T: Component>(&mut self) -> Option<EventWriter<'_, T>> {
+        self.res_mut::<Events<T>>().map(EventWriter::new)
+    }
+
+    // Structural changes for after the system's stage. They don't need declaring, nothing else
+    // sees them until then.
+    pub fn commands(&mut self) -> Commands<'_> {
+        Commands::new(self.commands, unsafe { self.shared.world.as_ref() })
+    }
+
+    // Only for exclusive systems.
+    pub fn world_mut(&mut self) -> &mut EcsWorld {
+        self.check_exclusive("the world");
+        unsafe { self.shared.world.as_mut() }
+    }
+
+    // Only for exclusive systems.
+    pub fn resources_mut(&mut self) -> &mut Resources {
+        self.check_exclusive("every resource");
+        match self.shared.resources {
+            SharedResources::All(mut resources) => unsafe { resources.as_mut() },
+            SharedResources::Declared { .. } => unreachable!("exclusive systems run on their own"),
+        }
+    }
+
+    // Borrowed for as long as the system runs rather than from `self`, so it can be handed out
+    // alongside the system's own state.
+    fn world_res<T: Component>(&self) -> Option<&'a T> {
+        match self.shared.resources {
+            SharedResources::All(_) => unsafe { self.shared.world.as_ref() }.res(),
+            SharedResources::Declared { world, .. } => declared::<T>(world)
+                .and_then(|resource| unsafe { resource.as_ref() }.downcast_ref()),
+        }
+    }
+
+    fn check_component<T: Component>(&self, write: bool) {
+        self.check(&self.access.components, &single::<T>(write), "component");
+    }
+
+    fn check_resource<T: Component>(&self, write: bool) {
+        self.check(&self.access.resources, &single::<T>(write), "resource");
+    }
+
+    fn check(&self, declared: &Access, wanted: &Access, kind: &str) {
+        if self.access.exclusive {
+            return;
+        }
+        if let Some(ty) = declared.uncovered(wanted) {
+            let how = if wanted.writes().contains(&ty) {
+                "write"
+            } else {
+                "read"
+            };
+            panic!(
+                "system {} didn't declare it would {} {} {}",
+                self.name,
+                how,
+                kind,
+                ty.name()
+            );
+        }
+    }
+
+    fn check_exclusive(&self, what: &str) {
+        if !self.access.exclusive {
+            panic!("system {} needs {} but isn't exclusive", self.name, what);
+        }
+    }
+}
+
+fn declared<T: Component>(
+    resources: &HashMap<TypeId, NonNull<dyn Any + Send>>,
+) -> Option<NonNull<dyn Any + Send>> {
+    resources.get(&TypeId::of::<T>()).copied()
+}
+
+fn single<T: Component>(write: bool) -> Access {
+    let mut access = Access::default();
+    if write {
+        access.write(ComponentType::of::<T>());
+    } else {
+        access.read(ComponentType::of::<T>());
+    }
+    access
+}
+
+struct Entry {
+    name: &'static str,
+    access: SystemAccess,
+    before: Vec<&'static str>,
+    after: Vec<&'static str>,
+    system: ScheduledSystem,
+    last_run: u64,
+    commands: CommandQueue,
+    event_cursors: HashMap<TypeId, EventCursor>,
+    // When it was added, see `Schedule::mark`.
+    serial: u64,
+}
+
+// The systems added at some point, see `Schedule::mark`.
+#[derive(Clone, Copy, Debug)]
+pub struct ScheduleMark {
+    serial: u64,
+}
+
+// Declarations for the system just added.
+pub struct SystemConfig<'s> {
+    entry: &'s mut Entry,
+}
+
+impl<'s> SystemConfig<'s> {
+    pub fn reads<T: Component + Sync>(self) -> Self {
+        self.entry.access.components.read(ComponentType::of::<T>());
+        self
+    }
+
+    pub fn writes<T: Component>(self) -> Self {
+        self.entry.access.components.write(ComponentType::of::<T>());
+        self
+    }
+
+    pub fn reads_resource<T: Component + Sync>(self) -> Self {
+        self.entry.access.resources.read(ComponentType::of::<T>());
+        self
+    }
+
+    pub fn writes_resource<T: Component>(self) -> Self {
+        self.entry.access.resources.write(ComponentType::of::<T>());
+        self
+    }
+
+    pub fn reads_events<T: Component + Sync>(self) -> Self {
+        self.reads_resource::<Events<T>>()
+    }
+
+    pub fn writes_events<T: Component>(self) -> Self {
+        self.writes_resource::<Events<T>>()
+    }
+
+    pub fn exclusive(self) -> Self {
+        self.entry.access.exclusive = true;
+        self
+    }
+
+    // Runs before the named system, if there is one.
+    pub fn before(self, name: &'static str) -> Self {
+        self.entry.before.push(name);
+        self
+    }
+
+    // Runs after the named system, if there is one.
+    pub fn after(self, name: &'static str) -> Self {
+        self.entry.after.push(name);
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct Schedule {
+    systems: Vec<Entry>,
+    // Indices into `systems` in the order they run, split into stages that run one after
+    // another. Worked out again after a change.
+    stages: Option<Vec<Vec<usize>>>,
+    // Started on the first stage with more than one system.
+    workers: Option<WorkerPool>,
+    next_serial: u64,
+}
+
+impl Schedule {
+    // Adds a system, replacing one with the same name. Systems without constraints between them
+    // run in the order they were added.
+    pub fn add_system<S: FnMut(&mut SystemWorld) + Send + 'static>(
+        &mut self,
+        name: &'static str,
+        system: S,
+    ) -> SystemConfig<'_> {
+        self.stages = None;
+        self.next_serial += 1;
+        self.systems.retain(|entry| entry.name != name);
+        self.systems.push(Entry {
+            name,
+            access: SystemAccess::default(),
+            before: Vec::new(),
+            after: Vec::new(),
+            system: Box::new(system),
+            last_run: 0,
+            commands: CommandQueue::default(),
+            event_cursors: HashMap::new(),
+            serial: self.next_serial,
+        });
+        SystemConfig {
+            entry: self.systems.last_mut().unwrap(),
+        }
+    }
+
+    pub fn remove_system(&mut self, name: &str) -> bool {
+        let len = self.systems.len();
+        self.systems.retain(|entry| entry.name != name);
+        self.stages = None;
+        self.systems.len() != len
+    }
+
+    // Remembers the systems added so far for `rollback`. Hot reload uses it to drop a game
+    // library's systems before unloading it.
+    pub fn mark(&self) -> ScheduleMark {
+        ScheduleMark {
+            serial: self.next_serial,
+        }
+    }
+
+    // Drops the systems added since `mark`, including those that replaced older ones.
+    pub fn rollback(&mut self, mark: ScheduleMark) {
+        self.systems.retain(|entry| entry.serial <= mark.serial);
+        self.stages = None;
+    }
+
+    pub fn access(&self, name: &str) -> Option<&SystemAccess> {
+        self.systems
+            .iter()
+            .find(|entry| entry.name == name)
+            .map(|entry| &entry.access)
+    }
+
+    pub fn len(&self) -> usize {
+        self.systems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.systems.is_empty()
+    }
+
+    // The names in the order they run, or the systems whose constraints make a cycle.
+    pub fn order(&self) -> Result<Vec<&'static str>, String> {
+        Ok(self
+            .sort()?
+            .into_iter()
+            .map(|index| self.systems[index].name)
+            .collect())
+    }
+
+    // Topological, taking the earliest added of the systems that are free to go next.
+    fn sort(&self) -> Result<Vec<usize>, String> {
+        let index: HashMap<_, _> = self
+            .systems
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.name, i))
+            .collect();
+        let edges = self.systems.iter().enumerate().flat_map(|(i, entry)| {
+            let before = entry.before.iter().filter_map(|name| index.get(name));
+            let after = entry.after.iter().filter_map(|name| index.get(name));
+            before
+                .map(move |&to| (i, to))
+                .chain(after.map(move |&from| (from, i)))
+        });
+        topological_sort(self.systems.len(), edges).map_err(|cycle| {
+            let names: Vec<_> = cycle.into_iter().map(|i| self.systems[i].name).collect();
+            format!("systems {} are ordered in a cycle", names.join(", "))
+        })
+    }
+
+    // The names of the systems that can run at the same time, stage by stage.
+    pub fn stages(&self) -> Result<Vec<Vec<&'static str>>, String> {
+        Ok(self
+            .split(self.sort()?)
+            .into_iter()
+            .map(|stage| stage.into_iter().map(|i| self.systems[i].name).collect())
+            .collect())
+    }
+
+    // Greedily, each system joins the stage before it unless it conflicts with or is ordered
+    // against something there, which keeps conflicting systems in order.
+    fn split(&self, order: Vec<usize>) -> Vec<Vec<usize>> {
+        let ordered = |a: &Entry, b: &Entry| {
+            a.before.contains(&b.name)
+                || a.after.contains(&b.name)
+                || b.before.contains(&a.name)
+                || b.after.contains(&a.name)
+        };
+        let mut stages: Vec<Vec<usize>> = Vec::new();
+        for index in order {
+            let entry = &self.systems[index];
+            match stages.last_mut() {
+                Some(stage)
+                    if stage.iter().all(|&other| {
+                        let other = &self.systems[other];
+                        !entry.access.conflicts_with(&other.access) && !ordered(entry, other)
+                    }) =>
+                {
+                    stage.push(index)
+                }
+                _ => stages.push(vec![index]),
+            }
+        }
+        stages
+    }
+
+    // Uses that many workers besides the sim thread for stages with several systems, none runs
+    // everything on the sim thread. Defaults to one per core.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.workers = Some(WorkerPool::new(threads));
+    }
+
+    // Runs every system once. A schedule with a cycle runs one by one in the order systems
+    // were added.
+    pub fn run(&mut self, resources: &mut Resources) {
+        if self.stages.is_none() {
+            self.stages = Some(match self.sort() {
+                Ok(order) => self.split(order),
+                Err(err) => {
+                    error!("{}, running them in the order they were added", err);
+                    (0..self.systems.len()).map(|i| vec![i]).collect()
+                }
+            });
+        }
+        let mut world = resources.remove::<EcsWorld>().unwrap_or_default();
+        world.update_events();
+        // What systems change this run is what plain queries see until the next.
+        world.clear_trackers();
+        for stage in self.stages.as_ref().unwrap() {
+            // Each stage stamps its changes apart, so a system sees what ran after it last time.
+            let change_tick = world.increment_change_tick();
+            if let [index] = stage[..] {
+                let entry = &mut self.systems[index];
+                profile_scope!(entry.name);
+                run_system(
+                    entry,
+                    Shared {
+                        world: NonNull::from(&mut world),
+                        resources: SharedResources::All(NonNull::from(&mut *resources)),
+                    },
+                    change_tick,
+                );
+                // Stamped apart from the stage so the system sees them as changes next run.
+                world.increment_change_tick();
+                entry.commands.apply(&mut world);
+                continue;
+            }
+            profile_scope!("parallel systems");
+            let types: Vec<_> = stage
+                .iter()
+                .flat_map(|&i| {
+                    let access = &self.systems[i].access.resources;
+                    access
+                        .reads()
+                        .iter()
+                        .chain(access.writes())
+                        .map(|ty| ty.id())
+                })
+                .collect();
+            let engine = resources.ptrs(types.iter().copied());
+            let world_resources = world.resources_mut().ptrs(types);
+            let shared = Shared {
+                world: NonNull::from(&mut world),
+                resources: SharedResources::Declared {
+                    engine: &engine,
+                    world: &world_resources,
+                },
+            };
+            let jobs = self
+                .systems
+                .iter_mut()
+                .enumerate()
+                .filter(|(i, _)| stage.contains(i))
+                .map(|(_, entry)| {
+                    Box::new(move || run_system(entry, shared, change_tick))
+                        as Box<dyn FnOnce() + Send + '_>
+                })
+                .collect();
+            self.workers
+                .get_or_insert_with(WorkerPool::for_cores)
+                .run(jobs);
+            // In the order the systems would have run one by one, stamped apart from the stage so
+            // its systems see them as changes next run.
+            world.increment_change_tick();
+            for &index in stage {
+                self.systems[index].commands.apply(&mut world);
+            }
+        }
+        // Changes made between runs get a tick of their own, newer than any system's last run.
+        world.increment_change_tick();
+        resources.insert(world);
+    }
+}
+
+fn run_system(entry: &mut Entry, shared: Shared, change_tick: u64) {
+    let _span = tracing::debug_span!("system", name = entry.name).entered();
+    (entry.system)(&mut SystemWorld {
+        name: entry.name,
+        access: &entry.access,
+        last_run: entry.last_run,
+        shared,
+        commands: &mut entry.commands,
+        event_cursors: &mut entry.event_cursors,
+    });
+    entry.last_run = change_tick;
+}
+
+// Kahn's algorithm over `count` nodes, taking the lowest index of the nodes that are free to go
+// next, so ties keep the order things were added in. Fails with the nodes left in or behind a
+// cycle. The render graph orders its passes with it too.
+pub(crate) fn topological_sort(
+    count: usize,
+    edges: impl IntoIterator<Item = (usize, usize)>,
+) -> Result<Vec<usize>, Vec<usize>> {
+    let mut outgoing = vec![Vec::new(); count];
+    let mut incoming = vec![0; count];
+    for (from, to) in edges {
+        outgoing[from].push(to);
+        incoming[to] += 1;
+    }
+    let mut ready: BTreeSet<_> = (0..count).filter(|&i| incoming[i] == 0).collect();
+    let mut order = Vec::with_capacity(count);
+    while let Some(next) = ready.pop_first() {
+        order.push(next);
+        for &to in &outgoing[next] {
+            incoming[to] -= 1;
+            if incoming[to] == 0 {
+                ready.insert(to);
+            }
+        }
+    }
+    if order.len() < count {
+        return Err((0..count).filter(|&i| incoming[i] > 0).collect());
+    }
+    Ok(order)
+}
+
+// The engine's schedule, created on first use.
+pub fn schedule(engine: &mut Engine) -> &mut Schedule {
+    let resources = engine.resources_mut();
+    if !resources.contains::<Schedule>() {
+        resources.insert(Schedule::default());
+    }
+    resources.get_mut::<Schedule>().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ecs::Added;
+
+    struct Position(f32);
+    struct Velocity(f32);
+
+    #[test]
+    fn systems_run_in_order_with_declared_access() {
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system("log", |world| {
+                let position = world
+                    .query::<&Position, ()>()
+                    .iter_mut()
+                    .map(|p| p.0)
+                    .sum::<f32>();
+                world.resource_mut::<Vec<f32>>().unwrap().push(position);
+            })
+            .reads::<Position>()
+            .writes_resource::<Vec<f32>>()
+            .after("movement");
+        schedule
+            .add_system("movement", |world| {
+                for (mut position, velocity) in
+                    world.query::<(&mut Position, &Velocity), ()>().iter_mut()
+                {
+                    position.0 += velocity.0;
+                }
+            })
+            .writes::<Position>()
+            .reads::<Velocity>();
+        schedule
+            .add_system("spawn", |world| {
+                let world = world.world_mut();
+                let entity = world.spawn();
+                world.insert(entity, Position(0.0));
+                world.insert(entity, Velocity(1.0));
+            })
+            .exclusive()
+            .before("movement");
+        assert_eq!(schedule.order().unwrap(), ["spawn", "movement", "log"]);
+        let access = |name| schedule.access(name).unwrap();
+        assert!(access("movement").conflicts_with(access("log")));
+        assert!(access("spawn").conflicts_with(&SystemAccess::default()));
+
+        let mut resources = Resources::default();
+        resources.insert(Vec::<f32>::new());
+        schedule.run(&mut resources);
+        schedule.run(&mut resources);
+        assert_eq!(resources.get::<Vec<f32>>().unwrap(), &[1.0, 3.0]);
+
+        schedule
+            .add_system("movement", |_| {})
+            .after("log")
+            .before("spawn");
+        assert!(schedule.order().is_err());
+    }
+
+    #[test]
+    fn systems_without_conflicts_share_a_stage() {
+        let mut schedule = Schedule::default();
+        schedule.set_threads(2);
+        schedule
+            .add_system("movement", |world| {
+                for (mut position, velocity) in
+                    world.query::<(&mut Position, &Velocity), ()>().iter_mut()
+                {
+                    position.0 += velocity.0;
+                }
+            })
+            .writes::<Position>()
+            .reads::<Velocity>();
+        schedule
+            .add_system("drag", |world| {
+                let drag = *world.res::<f32>().unwrap();
+                for mut velocity in world.query::<&mut Velocity, ()>().iter_mut() {
+                    velocity.0 *= drag;
+                }
+            })
+            .writes::<Velocity>()
+            .reads_resource::<f32>();
+        schedule
+            .add_system("count", |world| {
+                let count = world.query::<&Position, ()>().count();
+                world.resource_mut::<Vec<usize>>().unwrap().push(count);
+            })
+            .reads::<Position>()
+            .writes_resource::<Vec<usize>>();
+        schedule
+            .add_system("spawn", |world| {
+                let world = world.world_mut();
+                let entity = world.spawn();
+                world.insert(entity, Position(0.0));
+                world.insert(entity, Velocity(2.0));
+            })
+            .exclusive();
+        // Drag can't run alongside movement, which reads what it writes, so it goes after
+        // it just as if they'd run one by one.
+        assert_eq!(
+            schedule.stages().unwrap(),
+            [vec!["movement"], vec!["drag", "count"], vec!["spawn"]]
+        );
+
+        let mut resources = Resources::default();
+        let mut world = EcsWorld::new();
+        world.insert_resource(0.5f32);
+        resources.insert(world);
+        resources.insert(Vec::<usize>::new());
+        for _ in 0..3 {
+            schedule.run(&mut resources);
+        }
+        assert_eq!(resources.get::<Vec<usize>>().unwrap(), &[0, 1, 2]);
+        let world = resources.get::<EcsWorld>().unwrap();
+        let mut positions: Vec<_> = Query::<&Position>::read(world)
+            .iter()
+            .map(|p| p.0)
+            .collect();
+        positions.sort_by(f32::total_cmp);
+        assert_eq!(positions, [0.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn commands_apply_after_the_stage() {
+        let mut schedule = Schedule::default();
+        schedule.set_threads(2);
+        schedule
+            .add_system("spawn", |world| {
+                let spawned = world.commands().spawn().insert(Position(1.0)).id();
+                // Still queued while the stage runs.
+                assert!(world.get::<Position>(spawned).is_none());
+            })
+            .reads::<Position>();
+        schedule
+            .add_system("cull", |world| {
+                let doomed: Vec<_> = world
+                    .query::<(Entity, &Velocity), ()>()
+                    .iter_mut()
+                    .map(|(entity, _)| entity)
+                    .collect();
+                let mut commands = world.commands();
+                for entity in doomed {
+                    commands.despawn(entity);
+                }
+            })
+            .reads::<Velocity>();
+        schedule
+            .add_system("count", |world| {
+                let count = world.query::<&Position, ()>().count();
+                world.resource_mut::<Vec<usize>>().unwrap().push(count);
+            })
+            .reads::<Position>()
+            .writes_resource::<Vec<usize>>()
+            .after("spawn");
+        assert_eq!(
+            schedule.stages().unwrap(),
+            [vec!["spawn", "cull"], vec!["count"]]
+        );
+
+        let mut resources = Resources::default();
+        let mut world = EcsWorld::new();
+        let moving = world.spawn();
+        world.insert(moving, Velocity(1.0));
+        resources.insert(world);
+        resources.insert(Vec::<usize>::new());
+        schedule.run(&mut resources);
+        schedule.run(&mut resources);
+        assert_eq!(resources.get::<Vec<usize>>().unwrap(), &[1, 2]);
+        let world = resources.get::<EcsWorld>().unwrap();
+        assert!(!world.contains(moving));
+        assert_eq!(world.len(), 2);
+    }
+
+    #[test]
+    fn events_reach_systems_on_either_side_of_the_writer() {
+        struct Hit(u32);
+
+        let mut schedule = Schedule::default();
+        let read = |world: &mut SystemWorld| {
+            let hits: Vec<_> = world
+                .event_reader::<Hit>()
+                .unwrap()
+                .read()
+                .map(|h| h.0)
+                .collect();
+            world.resource_mut::<Vec<Vec<u32>>>().unwrap().push(hits);
+        };
+        schedule
+            .add_system("before", read)
+            .reads_events::<Hit>()
+            .writes_resource::<Vec<Vec<u32>>>();
+        schedule
+            .add_system("combat", {
+                let mut hits = 0;
+                move |world| {
+                    hits += 1;
+                    world.event_writer::<Hit>().unwrap().send(Hit(hits));
+                }
+            })
+            .writes_events::<Hit>()
+            .after("before");
+        schedule
+            .add_system("after", read)
+            .reads_events::<Hit>()
+            .writes_resource::<Vec<Vec<u32>>>()
+            .after("combat");
+
+        let mut resources = Resources::default();
+        let mut world = EcsWorld::new();
+        world.add_event::<Hit>();
+        resources.insert(world);
+        resources.insert(Vec::<Vec<u32>>::new());
+        for _ in 0..3 {
+            schedule.run(&mut resources);
+        }
+        // Each reader sees every hit once, the one before combat a run later.
+        let seen = resources.get::<Vec<Vec<u32>>>().unwrap();
+        assert_eq!(seen, &[vec![], vec![1], vec![1], vec![2], vec![2], vec![3]]);
+    }
+
+    #[test]
+    fn added_sees_inserts_made_between_runs() {
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system("count_added", |world| {
+                let added = world.query::<Entity, Added<Position>>().count();
+                world.resource_mut::<Vec<usize>>().unwrap().push(added);
+            })
+            .reads::<Position>()
+            .writes_resource::<Vec<usize>>();
+
+        let mut resources = Resources::default();
+        resources.insert(EcsWorld::new());
+        resources.insert(Vec::<usize>::new());
+        schedule.run(&mut resources);
+        let world = resources.get_mut::<EcsWorld>().unwrap();
+        let entity = world.spawn();
+        world.insert(entity, Position(0.0));
+        schedule.run(&mut resources);
+        schedule.run(&mut resources);
+        assert_eq!(resources.get::<Vec<usize>>().unwrap(), &[0, 1, 0]);
+    }
+
+    #[test]
+    fn stage_peers_see_commands_as_added() {
+        let mut schedule = Schedule::default();
+        schedule.set_threads(2);
+        schedule
+            .add_system("spawn", |world| {
+                world.commands().spawn().insert(Position(0.0));
+            })
+            .reads::<Position>();
+        schedule
+            .add_system("count_added", |world| {
+                let added = world.query::<Entity, Added<Position>>().count();
+                world.resource_mut::<Vec<usize>>().unwrap().push(added);
+            })
+            .reads::<Position>()
+            .writes_resource::<Vec<usize>>();
+        assert_eq!(schedule.stages().unwrap(), [vec!["spawn", "count_added"]]);
+
+        let mut resources = Resources::default();
+        resources.insert(EcsWorld::new());
+        resources.insert(Vec::<usize>::new());
+        for _ in 0..3 {
+            schedule.run(&mut resources);
+        }
+        assert_eq!(resources.get::<Vec<usize>>().unwrap(), &[0, 1, 1]);
+    }
+
+    #[test]
+    #[should_panic(expected = "didn't declare it would write")]
+    fn undeclared_access_panics() {
+        let mut schedule = Schedule::default();
+        schedule
+            .add_system("sneaky", |world| {
+                world.query::<&mut Position, ()>();
+            })
+            .reads::<Position>();
+        schedule.run(&mut Resources::default());
+    }
+}
@@ -0,0 +1,415 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use mlua::{FromLua, Function, IntoLua, Lua, RegistryKey, Table, Value, Variadic};
+
+use super::{ScriptHost, ScriptValue, ScriptWatcher};
+use crate::{
+    engine::{Engine, Plugin, Resources},
+    identifier::SnowflakeId,
+};
+
+// Registered by a script file, dropped when the file reloads.
+struct Registered {
+    file: PathBuf,
+    // The system's name, or the event for handlers.
+    name: String,
+    key: RegistryKey,
+}
+
+// Shared with the engine functions scripts call, through the Lua app data.
+#[derive(Default)]
+struct State {
+    host: Option<Box<dyn ScriptHost>>,
+    // The file being run, what it registers belongs to it.
+    loading: Option<PathBuf>,
+    systems: Vec<Registered>,
+    handlers: HashMap<String, Vec<Registered>>,
+    // Emitted by scripts, for their handlers and for the game.
+    emitted: VecDeque<(String, ScriptValue)>,
+    outbox: VecDeque<(String, ScriptValue)>,
+}
+
+// The Lua runtime, inserted as a resource by `ScriptPlugin`. Scripts reach the engine through the
+// global `engine` table:
+//
+//     engine.log(message)
+//     engine.spawn() -> entity
+//     engine.despawn(entity) -> bool
+//     engine.get(entity, component) -> value or nil
+//     engine.set(entity, component, value)
+//     engine.query(component, ...) -> { entity, ... }
+//     engine.on(event, function(value) end)
+//     engine.emit(event, value)
+//     engine.system(name, function() end)
+//
+// Systems run every tick in the order they were registered. Every file has its own globals,
+// which survive reloads; what a file registered is dropped and registered again when it reloads.
+pub struct LuaScripts {
+    lua: Lua,
+    environments: HashMap<PathBuf, RegistryKey>,
+    watcher: ScriptWatcher,
+    last_scan: Option<Instant>,
+    reload_interval: Duration,
+    // Emitted by the game for script handlers.
+    inbox: VecDeque<(String, ScriptValue)>,
+}
+
+impl LuaScripts {
+    pub fn new(dir: impl Into<PathBuf>) -> mlua::Result<Self> {
+        let lua = Lua::new();
+        lua.set_app_data(State::default());
+        lua.globals().set("engine", engine_table(&lua)?)?;
+        Ok(Self {
+            lua,
+            environments: HashMap::new(),
+            watcher: ScriptWatcher::new(dir, "lua"),
+            last_scan: None,
+            reload_interval: Duration::from_millis(500),
+            inbox: VecDeque::new(),
+        })
+    }
+
+    // What scripts spawn, read and change entities through. Set it before the first update, the
+    // scripts load then.
+    pub fn set_host(&mut self, host: Box<dyn ScriptHost>) {
+        if let Some(mut state) = self.lua.app_data_mut::<State>() {
+            state.host = Some(host);
+        }
+    }
+
+    // How often the script directory is checked for changes, zero turns hot reload off after
+    // the first load.
+    pub fn set_reload_interval(&mut self, interval: Duration) {
+        self.reload_interval = interval;
+    }
+
+    // Calls the handlers scripts registered for `event` on the next update.
+    pub fn emit(&mut self, event: &str, value: ScriptValue) {
+        self.inbox.push_back((event.to_owned(), value));
+    }
+
+    // Events scripts emitted, for the game's fixed_update.
+    pub fn poll_event(&mut self) -> Option<(String, ScriptValue)> {
+        self.lua
+            .app_data_mut::<State>()
+            .and_then(|mut state| state.outbox.pop_front())
+    }
+
+    // Reloads changed files, delivers events and runs the script systems, once per tick.
+    pub fn update(&mut self) {
+        let scan = match self.last_scan {
+            None => true,
+            Some(last) => !self.reload_interval.is_zero() && last.elapsed() >= self.reload_interval,
+        };
+        if scan {
+            self.last_scan = Some(Instant::now());
+            match self.watcher.changed() {
+                Ok(files) => {
+                    for file in files {
+                        self.load(&file);
+                    }
+                }
+                Err(err) => warn!(
+                    "Failed to scan scripts in {}: {}",
+                    self.watcher.dir().display(),
+                    err
+                ),
+            }
+        }
+
+        while let Some((event, value)) = self.inbox.pop_front() {
+            self.dispatch(&event, value);
+        }
+        let systems = match self.functions(None) {
+            Ok(systems) => systems,
+            Err(err) => {
+                error!("Failed to get script systems: {}", err);
+                return;
+            }
+        };
+        for (name, system) in systems {
+            if let Err(err) = system.call::<_, ()>(()) {
+                error!("Script system {} failed: {}", name, err);
+            }
+            self.dispatch_emitted();
+        }
+    }
+
+    fn load(&mut self, path: &Path) {
+        if let Some(mut state) = self.lua.app_data_mut::<State>() {
+            state.systems.retain(|system| system.file != path);
+            for handlers in state.handlers.values_mut() {
+                handlers.retain(|handler| handler.file != path);
+            }
+            state.loading = Some(path.to_owned());
+        }
+        match self.run_file(path) {
+            Ok(()) => info!("Loaded script {}", path.display()),
+            Err(err) => error!("Failed to load script {}: {}", path.display(), err),
+        }
+        if let Some(mut state) = self.lua.app_data_mut::<State>() {
+            state.loading = None;
+        }
+        self.dispatch_emitted();
+    }
+
+    fn run_file(&mut self, path: &Path) -> mlua::Result<()> {
+        let source = fs::read_to_string(path).map_err(mlua::Error::external)?;
+        let environment: Table = match self.environments.get(path) {
+            Some(key) => self.lua.registry_value(key)?,
+            None => {
+                // Globals a file doesn't set itself are looked up in the shared ones.
+                let environment = self.lua.create_table()?;
+                let metatable = self.lua.create_table()?;
+                metatable.set("__index", self.lua.globals())?;
+                environment.set_metatable(Some(metatable));
+                let key = self.lua.create_registry_value(environment.clone())?;
+                self.environments.insert(path.to_owned(), key);
+                environment
+            }
+        };
+        self.lua
+            .load(&source)
+            .set_name(path.display().to_string())
+            .set_environment(environment)
+            .exec()
+    }
+
+    // Hands events scripts emitted to the handlers, including those emitted by handlers.
+    fn dispatch_emitted(&self) {
+        loop {
+            let next = self
+                .lua
+                .app_data_mut::<State>()
+                .and_then(|mut state| state.emitted.pop_front());
+            let Some((event, value)) = next else {
+                break;
+            };
+            self.dispatch(&event, value);
+        }
+    }
+
+    fn dispatch(&self, event: &str, value: ScriptValue) {
+        let handlers = match self.functions(Some(event)) {
+            Ok(handlers) => handlers,
+            Err(err) => {
+                error!("Failed to get handlers for {}: {}", event, err);
+                return;
+            }
+        };
+        for (_, handler) in handlers {
+            if let Err(err) = handler.call::<_, ()>(value.clone()) {
+                error!("Script handler for {} failed: {}", event, err);
+            }
+        }
+    }
+
+    // The script systems, or the handlers for an event, looked up so they can be called without
+    // the state borrowed.
+    fn functions(&self, event: Option<&str>) -> mlua::Result<Vec<(String, Function<'_>)>> {
+        let state = self
+            .lua
+            .app_data_ref::<State>()
+            .ok_or_else(|| mlua::Error::RuntimeError("script state missing".to_owned()))?;
+        let registered = match event {
+            Some(event) => state.handlers.get(event).map_or(&[][..], Vec::as_slice),
+            None => &state.systems,
+        };
+        registered
+            .iter()
+            .map(|registered| {
+                Ok((
+                    registered.name.clone(),
+                    self.lua.registry_value(&registered.key)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+fn state_mut(lua: &Lua) -> mlua::Result<mlua::AppDataRefMut<'_, State>> {
+    lua.app_data_mut::<State>()
+        .ok_or_else(|| mlua::Error::RuntimeError("script state missing".to_owned()))
+}
+
+fn with_host<R>(
+    lua: &Lua,
+    run: impl FnOnce(&mut dyn ScriptHost) -> mlua::Result<R>,
+) -> mlua::Result<R> {
+    let mut state = state_mut(lua)?;
+    let host = state
+        .host
+        .as_deref_mut()
+        .ok_or_else(|| mlua::Error::RuntimeError("no script host set".to_owned()))?;
+    run(host)
+}
+
+// Registrations belong to the file being loaded, so they can be dropped when it reloads.
+fn register(lua: &Lua, name: String, function: Function) -> mlua::Result<Registered> {
+    let key = lua.create_registry_value(function)?;
+    let file = state_mut(lua)?.loading.clone().ok_or_else(|| {
+        mlua::Error::RuntimeError("handlers and systems can only be added while loading".to_owned())
+    })?;
+    Ok(Registered { file, name, key })
+}
+
+fn entity(bits: i64) -> SnowflakeId {
+    SnowflakeId::from_bits(bits as u64)
+}
+
+fn engine_table(lua: &Lua) -> mlua::Result<Table<'_>> {
+    let engine = lua.create_table()?;
+    engine.set(
+        "log",
+        lua.create_function(|_, message: String| {
+            info!(target: "script", "{}", message);
+            Ok(())
+        })?,
+    )?;
+    engine.set(
+        "spawn",
+        lua.create_function(|lua, ()| with_host(lua, |host| Ok(host.spawn().to_bits() as i64)))?,
+    )?;
+    engine.set(
+        "despawn",
+        lua.create_function(|lua, id: i64| with_host(lua, |host| Ok(host.despawn(entity(id)))))?,
+    )?;
+    engine.set(
+        "get",
+        lua.create_function(|lua, (id, component): (i64, String)| {
+            with_host(lua, |host| Ok(host.component(entity(id), &component)))
+        })?,
+    )?;
+    engine.set(
+        "set",
+        lua.create_function(|lua, (id, component, value): (i64, String, ScriptValue)| {
+            with_host(lua, |host| {
+                host.set_component(entity(id), &component, value)
+                    .map_err(mlua::Error::RuntimeError)
+            })
+        })?,
+    )?;
+    engine.set(
+        "query",
+        lua.create_function(|lua, components: Variadic<String>| {
+            with_host(lua, |host| {
+                Ok(host
+                    .query(&components)
+                    .into_iter()
+                    .map(|id| id.to_bits() as i64)
+                    .collect::<Vec<_>>())
+            })
+        })?,
+    )?;
+    engine.set(
+        "on",
+        lua.create_function(|lua, (event, handler): (String, Function)| {
+            let registered = register(lua, event.clone(), handler)?;
+            state_mut(lua)?
+                .handlers
+                .entry(event)
+                .or_default()
+                .push(registered);
+            Ok(())
+        })?,
+    )?;
+    engine.set(
+        "emit",
+        lua.create_function(|lua, (event, value): (String, ScriptValue)| {
+            let mut state = state_mut(lua)?;
+            state.emitted.push_back((event.clone(), value.clone()));
+            state.outbox.push_back((event, value));
+            Ok(())
+        })?,
+    )?;
+    engine.set(
+        "system",
+        lua.create_function(|lua, (name, system): (String, Function)| {
+            let registered = register(lua, name, system)?;
+            state_mut(lua)?.systems.push(registered);
+            Ok(())
+        })?,
+    )?;
+    Ok(engine)
+}
+
+impl<'lua> IntoLua<'lua> for ScriptValue {
+    fn into_lua(self, lua: &'lua Lua) -> mlua::Result<Value<'lua>> {
+        Ok(match self {
+            ScriptValue::Nil => Value::Nil,
+            ScriptValue::Bool(value) => Value::Boolean(value),
+            ScriptValue::Int(value) => Value::Integer(value),
+            ScriptValue::Number(value) => Value::Number(value),
+            ScriptValue::String(value) => Value::String(lua.create_string(&value)?),
+            ScriptValue::List(values) => Value::Table(lua.create_sequence_from(values)?),
+            ScriptValue::Table(fields) => Value::Table(lua.create_table_from(fields)?),
+        })
+    }
+}
+
+impl<'lua> FromLua<'lua> for ScriptValue {
+    fn from_lua(value: Value<'lua>, _lua: &'lua Lua) -> mlua::Result<Self> {
+        Ok(match value {
+            Value::Nil => ScriptValue::Nil,
+            Value::Boolean(value) => ScriptValue::Bool(value),
+            Value::Integer(value) => ScriptValue::Int(value),
+            Value::Number(value) => ScriptValue::Number(value),
+            Value::String(value) => ScriptValue::String(value.to_str()?.to_owned()),
+            Value::Table(table) => {
+                let len = table.raw_len();
+                if len > 0 && table.clone().pairs::<Value, Value>().count() == len {
+                    ScriptValue::List(table.sequence_values().collect::<mlua::Result<_>>()?)
+                } else {
+                    ScriptValue::Table(table.pairs().collect::<mlua::Result<_>>()?)
+                }
+            }
+            other => {
+                return Err(mlua::Error::FromLuaConversionError {
+                    from: other.type_name(),
+                    to: "ScriptValue",
+                    message: Some("only nil, booleans, numbers, strings and tables".to_owned()),
+                })
+            }
+        })
+    }
+}
+
+// Loads the scripts in `dir` and runs them every tick, see `LuaScripts`.
+pub struct ScriptPlugin {
+    pub dir: PathBuf,
+}
+
+impl ScriptPlugin {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Plugin for ScriptPlugin {
+    fn build(&self, engine: &mut Engine) {
+        match LuaScripts::new(&self.dir) {
+            Ok(scripts) => {
+                engine.insert_resource(scripts);
+            }
+            Err(err) => {
+                error!("Failed to start Lua, running without scripts: {}", err);
+                return;
+            }
+        }
+        engine.add_system(|resources: &mut Resources| {
+            if let Some(scripts) = resources.get_mut::<LuaScripts>() {
+                scripts.update();
+            }
+        });
+    }
+}
@@ -0,0 +1,543 @@
+//! Game logic in script files, reloaded while the game runs whenever a file changes on disk.
+//!
+//! The runtime is Lua, embedded through mlua behind the `lua` feature, see `lua.rs`. What's here
+//! doesn't depend on it: the values scripts exchange with the engine, the `ScriptHost` games
+//! implement to give scripts their entities, and the file watcher driving hot reload.
+//!
+//! There is no ECS to bind to yet, so entities and components reach scripts through the game's
+//! `ScriptHost`, components by name with their fields as `ScriptValue`s.
+//!
+//! Third-party mods run as WASM modules in a wasmtime sandbox behind the `wasm-mods` feature, see
+//! `wasm.rs` for the ABI. They can only reach the engine through the same host.
+
+#[cfg(feature = "lua")]
+mod lua;
+#[cfg(all(feature = "wasm-mods", not(target_arch = "wasm32")))]
+mod wasm;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::SystemTime,
+};
+
+#[cfg(feature = "lua")]
+pub use lua::{LuaScripts, ScriptPlugin};
+#[cfg(all(feature = "wasm-mods", not(target_arch = "wasm32")))]
+pub use wasm::{ModLimits, WasmModPlugin, WasmMods, ABI_VERSION};
+
+use crate::identifier::SnowflakeId;
+
+// A value passed between scripts and the engine. Script tables with keys 1 to n are lists, other
+// tables need string keys.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ScriptValue {
+    #[default]
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Number(f64),
+    String(String),
+    List(Vec<ScriptValue>),
+    Table(BTreeMap<String, ScriptValue>),
+}
+
+impl ScriptValue {
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            ScriptValue::Int(value) => Some(value as f64),
+            ScriptValue::Number(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ScriptValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, field: &str) -> Option<&ScriptValue> {
+        match self {
+            ScriptValue::Table(fields) => fields.get(field),
+            _ => None,
+        }
+    }
+
+    // The binary form values cross the WASM mod ABI in, part of the ABI so it can't change. A tag
+    // byte, then for bools a byte, ints and numbers 8 bytes, strings a u32 length and UTF-8,
+    // lists a u32 count and the values, tables a u32 count and key string and value pairs. All
+    // little endian.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            ScriptValue::Nil => out.push(0),
+            ScriptValue::Bool(value) => out.extend_from_slice(&[1, *value as u8]),
+            ScriptValue::Int(value) => {
+                out.push(2);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            ScriptValue::Number(value) => {
+                out.push(3);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            ScriptValue::String(value) => {
+                out.push(4);
+                encode_str(value, out);
+            }
+            ScriptValue::List(values) => {
+                out.push(5);
+                out.extend_from_slice(&(values.len() as u32).to_le_bytes());
+                for value in values {
+                    value.encode(out);
+                }
+            }
+            ScriptValue::Table(fields) => {
+                out.push(6);
+                out.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+                for (key, value) in fields {
+                    encode_str(key, out);
+                    value.encode(out);
+                }
+            }
+        }
+    }
+
+    // None unless `bytes` is exactly one encoded value.
+    pub fn decode(bytes: &[u8]) -> Option<ScriptValue> {
+        let mut reader = bytes;
+        let value = decode_value(&mut reader, 0)?;
+        reader.is_empty().then_some(value)
+    }
+}
+
+// Text like `{ health = 87.5, tags = [-3, true], name = "crate" }`, what the editor shows and
+// reads back. Numbers always have a `.` or an exponent so they stay numbers, table keys that
+// aren't identifiers are quoted.
+impl fmt::Display for ScriptValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptValue::Nil => write!(f, "nil"),
+            ScriptValue::Bool(value) => write!(f, "{}", value),
+            ScriptValue::Int(value) => write!(f, "{}", value),
+            ScriptValue::Number(value) => write!(f, "{:?}", value),
+            ScriptValue::String(value) => write!(f, "{:?}", value),
+            ScriptValue::List(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { ", " };
+                    write!(f, "{}{}", separator, value)?;
+                }
+                write!(f, "]")
+            }
+            ScriptValue::Table(fields) if fields.is_empty() => write!(f, "{{}}"),
+            ScriptValue::Table(fields) => {
+                write!(f, "{{ ")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    let separator = if i == 0 { "" } else { ", " };
+                    if is_identifier(key) {
+                        write!(f, "{}{} = {}", separator, key, value)?;
+                    } else {
+                        write!(f, "{}{:?} = {}", separator, key, value)?;
+                    }
+                }
+                write!(f, " }}")
+            }
+        }
+    }
+}
+
+impl FromStr for ScriptValue {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut parser = TextParser { text, position: 0 };
+        let value = parser.value(0)?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(value),
+            Some(c) => Err(format!("unexpected {:?} at {}", c, parser.position)),
+        }
+    }
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !matches!(text, "nil" | "true" | "false")
+}
+
+struct TextParser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl TextParser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek().filter(|c| c.is_whitespace()) {
+            self.position += c.len_utf8();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(c) => Err(format!(
+                "expected {:?}, got {:?} at {}",
+                expected, c, self.position
+            )),
+            None => Err(format!("expected {:?}, got the end", expected)),
+        }
+    }
+
+    // Numbers and the bare words, up to the next delimiter.
+    fn word(&mut self) -> &str {
+        let start = self.position;
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_whitespace() && !",=[]{}\"".contains(c))
+        {
+            self.position += self.peek().map_or(0, char::len_utf8);
+        }
+        &self.text[start..self.position]
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut value = String::new();
+        let mut chars = self.text[self.position..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += i + 1;
+                    return Ok(value);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some('0') => value.push('\0'),
+                    Some(c @ ('"' | '\\' | '\'')) => value.push(c),
+                    Some('u') => {
+                        // `\u{..}`, the only other escape `{:?}` writes.
+                        let rest = &self.text[self.position + i + 2..];
+                        let end = rest.find('}').ok_or("unterminated \\u escape")?;
+                        let code = rest
+                            .strip_prefix('{')
+                            .and_then(|hex| u32::from_str_radix(&hex[..end - 1], 16).ok())
+                            .and_then(char::from_u32)
+                            .ok_or("bad \\u escape")?;
+                        value.push(code);
+                        for _ in 0..=end {
+                            chars.next();
+                        }
+                    }
+                    _ => return Err(format!("bad escape at {}", self.position + i)),
+                },
+                c => value.push(c),
+            }
+        }
+        Err("unterminated string".to_owned())
+    }
+
+    fn value(&mut self, depth: usize) -> Result<ScriptValue, String> {
+        if depth > MAX_DEPTH {
+            return Err("nested too deep".to_owned());
+        }
+        self.skip_whitespace();
+        match self.peek() {
+            Some('"') => Ok(ScriptValue::String(self.string()?)),
+            Some('[') => {
+                self.position += 1;
+                let mut values = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() == Some(']') {
+                        self.position += 1;
+                        return Ok(ScriptValue::List(values));
+                    }
+                    values.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    if self.peek() != Some(']') {
+                        self.expect(',')?;
+                    }
+                }
+            }
+            Some('{') => {
+                self.position += 1;
+                let mut fields = BTreeMap::new();
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() == Some('}') {
+                        self.position += 1;
+                        return Ok(ScriptValue::Table(fields));
+                    }
+                    let key = match self.peek() {
+                        Some('"') => self.string()?,
+                        _ => self.word().to_owned(),
+                    };
+                    if key.is_empty() {
+                        return Err(format!("expected a key at {}", self.position));
+                    }
+                    self.expect('=')?;
+                    fields.insert(key, self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    if self.peek() != Some('}') {
+                        self.expect(',')?;
+                    }
+                }
+            }
+            Some(_) => {
+                let start = self.position;
+                let word = self.word();
+                match word {
+                    "nil" => Ok(ScriptValue::Nil),
+                    "true" => Ok(ScriptValue::Bool(true)),
+                    "false" => Ok(ScriptValue::Bool(false)),
+                    _ => {
+                        if let Ok(value) = word.parse() {
+                            Ok(ScriptValue::Int(value))
+                        } else if let Ok(value) = word.parse() {
+                            Ok(ScriptValue::Number(value))
+                        } else {
+                            Err(format!("unexpected {:?} at {}", word, start))
+                        }
+                    }
+                }
+            }
+            None => Err("expected a value, got the end".to_owned()),
+        }
+    }
+}
+
+fn encode_str(value: &str, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    out.extend_from_slice(value.as_bytes());
+}
+
+// Deeper nesting than any sane component, keeps hostile input from overflowing the stack.
+const MAX_DEPTH: usize = 64;
+
+fn take<'a>(reader: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if reader.len() < len {
+        return None;
+    }
+    let (bytes, rest) = reader.split_at(len);
+    *reader = rest;
+    Some(bytes)
+}
+
+fn take_u32(reader: &mut &[u8]) -> Option<usize> {
+    Some(u32::from_le_bytes(take(reader, 4)?.try_into().ok()?) as usize)
+}
+
+fn take_str(reader: &mut &[u8]) -> Option<String> {
+    let len = take_u32(reader)?;
+    String::from_utf8(take(reader, len)?.to_vec()).ok()
+}
+
+fn decode_value(reader: &mut &[u8], depth: usize) -> Option<ScriptValue> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+    Some(match take(reader, 1)?[0] {
+        0 => ScriptValue::Nil,
+        1 => ScriptValue::Bool(take(reader, 1)?[0] != 0),
+        2 => ScriptValue::Int(i64::from_le_bytes(take(reader, 8)?.try_into().ok()?)),
+        3 => ScriptValue::Number(f64::from_le_bytes(take(reader, 8)?.try_into().ok()?)),
+        4 => ScriptValue::String(take_str(reader)?),
+        5 => {
+            let count = take_u32(reader)?;
+            // Every value is at least a byte, so a bogus count can't reserve much.
+            let mut values = Vec::with_capacity(count.min(reader.len()));
+            for _ in 0..count {
+                values.push(decode_value(reader, depth + 1)?);
+            }
+            ScriptValue::List(values)
+        }
+        6 => {
+            let count = take_u32(reader)?;
+            let mut fields = BTreeMap::new();
+            for _ in 0..count {
+                let key = take_str(reader)?;
+                fields.insert(key, decode_value(reader, depth + 1)?);
+            }
+            ScriptValue::Table(fields)
+        }
+        _ => return None,
+    })
+}
+
+// The game world as scripts see it. Games implement it over their entity storage and hand it to
+// the script runtime. Errors are reported back to the calling script.
+pub trait ScriptHost: Send {
+    fn spawn(&mut self) -> SnowflakeId;
+    // False if the entity didn't exist.
+    fn despawn(&mut self, entity: SnowflakeId) -> bool;
+    fn component(&self, entity: SnowflakeId, component: &str) -> Option<ScriptValue>;
+    fn set_component(
+        &mut self,
+        entity: SnowflakeId,
+        component: &str,
+        value: ScriptValue,
+    ) -> Result<(), String>;
+    // Entities that have all of `components`.
+    fn query(&self, components: &[String]) -> Vec<SnowflakeId>;
+}
+
+// Finds script files that changed since the last scan by their modification time. Polling keeps
+// it portable, a directory of scripts is cheap to stat a couple of times a second.
+pub struct ScriptWatcher {
+    dir: PathBuf,
+    extension: &'static str,
+    modified: HashMap<PathBuf, SystemTime>,
+}
+
+impl ScriptWatcher {
+    pub fn new(dir: impl Into<PathBuf>, extension: &'static str) -> Self {
+        Self {
+            dir: dir.into(),
+            extension,
+            modified: HashMap::new(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Files that are new or changed since the last call, sorted so scripts load in a stable
+    // order. Every file counts as new on the first call.
+    pub fn changed(&mut self) -> io::Result<Vec<PathBuf>> {
+        let mut changed = Vec::new();
+        let mut stack = vec![self.dir.clone()];
+        while let Some(dir) = stack.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                let metadata = entry.metadata()?;
+                if metadata.is_dir() {
+                    stack.push(path);
+                    continue;
+                }
+                if path.extension().and_then(|ext| ext.to_str()) != Some(self.extension) {
+                    continue;
+                }
+                let modified = metadata.modified()?;
+                if self.modified.insert(path.clone(), modified) != Some(modified) {
+                    changed.push(path);
+                }
+            }
+        }
+        changed.sort();
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_round_trip_through_the_abi_encoding() {
+        let value = ScriptValue::Table(BTreeMap::from([
+            ("health".to_owned(), ScriptValue::Number(87.5)),
+            ("name".to_owned(), ScriptValue::String("crate".to_owned())),
+            (
+                "tags".to_owned(),
+                ScriptValue::List(vec![ScriptValue::Int(-3), ScriptValue::Bool(true)]),
+            ),
+            ("target".to_owned(), ScriptValue::Nil),
+        ]));
+        let mut bytes = Vec::new();
+        value.encode(&mut bytes);
+        assert_eq!(ScriptValue::decode(&bytes), Some(value));
+
+        // Truncated, trailing bytes and an unknown tag.
+        assert_eq!(ScriptValue::decode(&bytes[..bytes.len() - 1]), None);
+        bytes.push(0);
+        assert_eq!(ScriptValue::decode(&bytes), None);
+        assert_eq!(ScriptValue::decode(&[9]), None);
+    }
+
+    #[test]
+    fn values_round_trip_through_text() {
+        let value = ScriptValue::Table(BTreeMap::from([
+            ("scale".to_owned(), ScriptValue::Number(1.0)),
+            (
+                "label".to_owned(),
+                ScriptValue::String("say \"hi\"\n\u{301}".to_owned()),
+            ),
+            (
+                "has space".to_owned(),
+                ScriptValue::List(vec![ScriptValue::Int(-3), ScriptValue::Nil]),
+            ),
+            ("empty".to_owned(), ScriptValue::Table(BTreeMap::new())),
+        ]));
+        let text = value.to_string();
+        assert_eq!(text.parse::<ScriptValue>(), Ok(value));
+        assert_eq!(
+            "{ a = 1, b = [2.5, false] }"
+                .parse::<ScriptValue>()
+                .unwrap(),
+            ScriptValue::Table(BTreeMap::from([
+                ("a".to_owned(), ScriptValue::Int(1)),
+                (
+                    "b".to_owned(),
+                    ScriptValue::List(vec![ScriptValue::Number(2.5), ScriptValue::Bool(false)]),
+                ),
+            ]))
+        );
+        assert!("{ a = }".parse::<ScriptValue>().is_err());
+        assert!("[1, 2".parse::<ScriptValue>().is_err());
+    }
+
+    #[test]
+    fn text_can_have_non_ascii_whitespace() {
+        assert_eq!(
+            "[\u{a0}1,\u{2003}2\u{a0}]".parse::<ScriptValue>(),
+            Ok(ScriptValue::List(vec![
+                ScriptValue::Int(1),
+                ScriptValue::Int(2)
+            ]))
+        );
+    }
+
+    #[test]
+    fn watcher_reports_new_and_changed_files() {
+        let dir = std::env::temp_dir().join(format!("midnight2-scripts-{}", std::process::id()));
+        fs::create_dir_all(dir.join("ai")).unwrap();
+        fs::write(dir.join("main.lua"), "-- main").unwrap();
+        fs::write(dir.join("ai/enemy.lua"), "-- enemy").unwrap();
+        fs::write(dir.join("notes.txt"), "not a script").unwrap();
+
+        let mut watcher = ScriptWatcher::new(&dir, "lua");
+        assert_eq!(
+            watcher.changed().unwrap(),
+            [dir.join("ai/enemy.lua"), dir.join("main.lua")]
+        );
+        assert!(watcher.changed().unwrap().is_empty());
+
+        let file = fs::File::options()
+            .write(true)
+            .open(dir.join("main.lua"))
+            .unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(watcher.changed().unwrap(), [dir.join("main.lua")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,586 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use wasmtime::{
+    Caller, Config, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+use super::{ScriptHost, ScriptValue};
+use crate::{
+    engine::{Engine, Plugin, Resources},
+    identifier::SnowflakeId,
+};
+
+// Bumped on any change to the imports or exports below, mods built for another version don't
+// load.
+pub const ABI_VERSION: i32 = 1;
+const ABI_MODULE: &str = "midnight2";
+
+// What a single mod may use. Fuel is roughly a count of WASM instructions, every call into the
+// mod gets a fresh budget, so a runaway loop traps instead of hanging the tick. Emitting more
+// events than `events_per_call` in one call traps too.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ModLimits {
+    pub memory: usize,
+    pub fuel_per_call: u64,
+    pub events_per_call: usize,
+}
+
+impl Default for ModLimits {
+    fn default() -> Self {
+        Self {
+            memory: 64 << 20,
+            fuel_per_call: 10_000_000,
+            events_per_call: 256,
+        }
+    }
+}
+
+struct ModState {
+    name: String,
+    // Lent to the mod for the duration of a call.
+    host: Option<Box<dyn ScriptHost>>,
+    limits: StoreLimits,
+    // Cleared after every call, see `ModLimits::events_per_call`.
+    emitted: Vec<(String, ScriptValue)>,
+    max_emitted: usize,
+}
+
+struct LoadedMod {
+    name: String,
+    store: Store<ModState>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    update: Option<TypedFunc<(), ()>>,
+    on_event: Option<TypedFunc<(i32, i32, i32, i32), ()>>,
+    // Set once the mod traps, it isn't called again.
+    failed: bool,
+}
+
+impl LoadedMod {
+    fn run<R>(
+        &mut self,
+        host: &mut Option<Box<dyn ScriptHost>>,
+        fuel: u64,
+        call: impl FnOnce(&mut Store<ModState>) -> wasmtime::Result<R>,
+    ) -> wasmtime::Result<R> {
+        self.store.set_fuel(fuel)?;
+        self.store.data_mut().host = host.take();
+        let result = call(&mut self.store);
+        *host = self.store.data_mut().host.take();
+        result
+    }
+
+    fn send_event(
+        &mut self,
+        host: &mut Option<Box<dyn ScriptHost>>,
+        fuel: u64,
+        event: &str,
+        value: &[u8],
+    ) -> wasmtime::Result<()> {
+        let Some(on_event) = self.on_event.clone() else {
+            return Ok(());
+        };
+        let (alloc, memory) = (self.alloc.clone(), self.memory);
+        self.run(host, fuel, |store| {
+            let name = alloc.call(&mut *store, event.len() as i32)?;
+            memory.write(&mut *store, name as u32 as usize, event.as_bytes())?;
+            let data = alloc.call(&mut *store, value.len() as i32)?;
+            memory.write(&mut *store, data as u32 as usize, value)?;
+            on_event.call(store, (name, event.len() as i32, data, value.len() as i32))
+        })
+    }
+}
+
+// Third-party mods as sandboxed WASM modules, inserted as a resource by `WasmModPlugin`. A mod
+// can't touch anything but its own memory and the imports below, and only for as long as its
+// fuel lasts; one that traps is disabled and the game carries on.
+//
+// Imports, from the "midnight2" module. Strings and values are pointer and length pairs into the
+// mod's memory, values in the `ScriptValue::encode` format, entities are i64s.
+//
+//     log(level: i32, message: *u8, len: i32)    0 error, 1 warn, 2 info, 3 debug
+//     spawn() -> i64
+//     despawn(entity: i64) -> i32                1 if it existed
+//     get_component(entity: i64, name: *u8, name_len: i32, out: *u8, out_cap: i32) -> i32
+//         The length of the encoded value, -1 if the entity doesn't have it. Nothing is written
+//         when it's larger than out_cap, call again with a larger buffer.
+//     set_component(entity: i64, name: *u8, name_len: i32, value: *u8, value_len: i32) -> i32
+//         0, or -1 if the host refused it.
+//     query(names: *u8, names_len: i32, out: *i64, out_cap: i32) -> i32
+//         names is an encoded list of component names, writes up to out_cap entities and
+//         returns how many there are.
+//     emit(event: *u8, event_len: i32, value: *u8, value_len: i32)
+//
+// Exports:
+//
+//     memory
+//     midnight2_abi_version() -> i32
+//     alloc(len: i32) -> *u8                     buffers for on_event, the mod frees them
+//     init()                                     optional, once after loading
+//     update()                                   optional, once per tick
+//     on_event(event: *u8, event_len: i32, value: *u8, value_len: i32)    optional
+//
+// Events the game emits reach the mods on the next update, as do events mods emit, which the game
+// also gets from `poll_event`. A mod doesn't get its own events back.
+pub struct WasmMods {
+    engine: wasmtime::Engine,
+    linker: Linker<ModState>,
+    limits: ModLimits,
+    mods: Vec<LoadedMod>,
+    host: Option<Box<dyn ScriptHost>>,
+    // With the index of the mod that emitted it, None for the game's.
+    inbox: VecDeque<(Option<usize>, String, ScriptValue)>,
+    outbox: VecDeque<(String, ScriptValue)>,
+}
+
+impl WasmMods {
+    pub fn new(limits: ModLimits) -> wasmtime::Result<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = wasmtime::Engine::new(&config)?;
+        let mut linker = Linker::new(&engine);
+        link(&mut linker)?;
+        Ok(Self {
+            engine,
+            linker,
+            limits,
+            mods: Vec::new(),
+            host: None,
+            inbox: VecDeque::new(),
+            outbox: VecDeque::new(),
+        })
+    }
+
+    // What mods spawn, read and change entities through.
+    pub fn set_host(&mut self, host: Box<dyn ScriptHost>) {
+        self.host = Some(host);
+    }
+
+    // Loads a module in the binary or text format and runs its init.
+    pub fn load(&mut self, name: &str, bytes: &[u8]) -> wasmtime::Result<()> {
+        let module = Module::new(&self.engine, bytes)?;
+        let state = ModState {
+            name: name.to_owned(),
+            host: None,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.limits.memory)
+                .instances(1)
+                .build(),
+            emitted: Vec::new(),
+            max_emitted: self.limits.events_per_call,
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.limits.fuel_per_call)?;
+        let instance = self.linker.instantiate(&mut store, &module)?;
+        let version = instance
+            .get_typed_func::<(), i32>(&mut store, "midnight2_abi_version")?
+            .call(&mut store, ())?;
+        if version != ABI_VERSION {
+            return Err(wasmtime::Error::msg(format!(
+                "built for ABI version {}, the engine has {}",
+                version, ABI_VERSION
+            )));
+        }
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("exports no memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let update = instance.get_typed_func::<(), ()>(&mut store, "update").ok();
+        let on_event = instance
+            .get_typed_func::<(i32, i32, i32, i32), ()>(&mut store, "on_event")
+            .ok();
+        let init = instance.get_typed_func::<(), ()>(&mut store, "init").ok();
+        let mut loaded = LoadedMod {
+            name: name.to_owned(),
+            store,
+            memory,
+            alloc,
+            update,
+            on_event,
+            failed: false,
+        };
+        if let Some(init) = init {
+            loaded.run(&mut self.host, self.limits.fuel_per_call, |store| {
+                init.call(store, ())
+            })?;
+        }
+        self.mods.push(loaded);
+        let index = self.mods.len() - 1;
+        self.collect_events(index);
+        Ok(())
+    }
+
+    pub fn load_file(&mut self, path: &Path) -> wasmtime::Result<()> {
+        let bytes = fs::read(path)?;
+        let name = path.file_stem().map_or_else(
+            || path.display().to_string(),
+            |stem| stem.to_string_lossy().into_owned(),
+        );
+        self.load(&name, &bytes)
+    }
+
+    // Loads every .wasm file in `dir` in name order, logging the ones that fail. Returns how many
+    // loaded.
+    pub fn load_dir(&mut self, dir: &Path) -> usize {
+        let mut files: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "wasm"))
+                .collect(),
+            Err(err) => {
+                warn!("Failed to read mods in {}: {}", dir.display(), err);
+                return 0;
+            }
+        };
+        files.sort();
+        let mut loaded = 0;
+        for file in files {
+            match self.load_file(&file) {
+                Ok(()) => {
+                    info!("Loaded mod {}", file.display());
+                    loaded += 1;
+                }
+                Err(err) => error!("Failed to load mod {}: {:#}", file.display(), err),
+            }
+        }
+        loaded
+    }
+
+    // Names of the loaded mods, and whether they still run.
+    pub fn mods(&self) -> impl Iterator<Item = (&str, bool)> {
+        self.mods
+            .iter()
+            .map(|loaded| (loaded.name.as_str(), !loaded.failed))
+    }
+
+    // Calls the mods' on_event on the next update.
+    pub fn emit(&mut self, event: &str, value: ScriptValue) {
+        self.inbox.push_back((None, event.to_owned(), value));
+    }
+
+    // Events mods emitted, for the game's fixed_update.
+    pub fn poll_event(&mut self) -> Option<(String, ScriptValue)> {
+        self.outbox.pop_front()
+    }
+
+    // Delivers events and runs the mods' update, once per tick.
+    pub fn update(&mut self) {
+        let fuel = self.limits.fuel_per_call;
+        let events: Vec<_> = self.inbox.drain(..).collect();
+        for (sender, event, value) in events {
+            let mut bytes = Vec::new();
+            value.encode(&mut bytes);
+            for index in 0..self.mods.len() {
+                let loaded = &mut self.mods[index];
+                if loaded.failed || sender == Some(index) {
+                    continue;
+                }
+                let result = loaded.send_event(&mut self.host, fuel, &event, &bytes);
+                self.finish(index, result);
+            }
+        }
+        for index in 0..self.mods.len() {
+            let loaded = &mut self.mods[index];
+            let Some(update) = loaded.update.clone() else {
+                continue;
+            };
+            if loaded.failed {
+                continue;
+            }
+            let result = loaded.run(&mut self.host, fuel, |store| update.call(store, ()));
+            self.finish(index, result);
+        }
+    }
+
+    fn finish(&mut self, index: usize, result: wasmtime::Result<()>) {
+        self.collect_events(index);
+        if let Err(err) = result {
+            let loaded = &mut self.mods[index];
+            error!("Mod {} failed and was disabled: {:#}", loaded.name, err);
+            loaded.failed = true;
+        }
+    }
+
+    fn collect_events(&mut self, index: usize) {
+        for (event, value) in self.mods[index].store.data_mut().emitted.drain(..) {
+            self.outbox.push_back((event.clone(), value.clone()));
+            self.inbox.push_back((Some(index), event, value));
+        }
+    }
+}
+
+fn entity(bits: i64) -> SnowflakeId {
+    SnowflakeId::from_bits(bits as u64)
+}
+
+fn host<'a>(
+    caller: &'a mut Caller<'_, ModState>,
+) -> wasmtime::Result<&'a mut (dyn ScriptHost + 'static)> {
+    caller
+        .data_mut()
+        .host
+        .as_deref_mut()
+        .ok_or_else(|| wasmtime::Error::msg("no script host set"))
+}
+
+// Out of bounds pointers trap the mod.
+fn bytes<'a>(
+    caller: &'a mut Caller<'_, ModState>,
+    ptr: i32,
+    len: i32,
+) -> wasmtime::Result<&'a mut [u8]> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| wasmtime::Error::msg("exports no memory"))?;
+    let start = ptr as u32 as usize;
+    let end = start
+        .checked_add(len as u32 as usize)
+        .ok_or_else(|| wasmtime::Error::msg("pointer out of bounds"))?;
+    memory
+        .data_mut(caller)
+        .get_mut(start..end)
+        .ok_or_else(|| wasmtime::Error::msg("pointer out of bounds"))
+}
+
+fn read_str(caller: &mut Caller<'_, ModState>, ptr: i32, len: i32) -> wasmtime::Result<String> {
+    Ok(String::from_utf8(bytes(caller, ptr, len)?.to_vec())?)
+}
+
+fn read_value(
+    caller: &mut Caller<'_, ModState>,
+    ptr: i32,
+    len: i32,
+) -> wasmtime::Result<ScriptValue> {
+    ScriptValue::decode(bytes(caller, ptr, len)?)
+        .ok_or_else(|| wasmtime::Error::msg("malformed value"))
+}
+
+fn link(linker: &mut Linker<ModState>) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        ABI_MODULE,
+        "log",
+        |mut caller: Caller<'_, ModState>,
+         level: i32,
+         ptr: i32,
+         len: i32|
+         -> wasmtime::Result<()> {
+            let message = read_str(&mut caller, ptr, len)?;
+            let name = &caller.data().name;
+            match level {
+                0 => error!(target: "mod", "{}: {}", name, message),
+                1 => warn!(target: "mod", "{}: {}", name, message),
+                2 => info!(target: "mod", "{}: {}", name, message),
+                _ => debug!(target: "mod", "{}: {}", name, message),
+            }
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        ABI_MODULE,
+        "spawn",
+        |mut caller: Caller<'_, ModState>| -> wasmtime::Result<i64> {
+            Ok(host(&mut caller)?.spawn().to_bits() as i64)
+        },
+    )?;
+    linker.func_wrap(
+        ABI_MODULE,
+        "despawn",
+        |mut caller: Caller<'_, ModState>, id: i64| -> wasmtime::Result<i32> {
+            Ok(host(&mut caller)?.despawn(entity(id)) as i32)
+        },
+    )?;
+    linker.func_wrap(
+        ABI_MODULE,
+        "get_component",
+        |mut caller: Caller<'_, ModState>,
+         id: i64,
+         name: i32,
+         name_len: i32,
+         out: i32,
+         out_cap: i32|
+         -> wasmtime::Result<i32> {
+            let name = read_str(&mut caller, name, name_len)?;
+            let Some(value) = host(&mut caller)?.component(entity(id), &name) else {
+                return Ok(-1);
+            };
+            let mut encoded = Vec::new();
+            value.encode(&mut encoded);
+            if encoded.len() <= out_cap.max(0) as usize {
+                bytes(&mut caller, out, encoded.len() as i32)?.copy_from_slice(&encoded);
+            }
+            Ok(encoded.len() as i32)
+        },
+    )?;
+    linker.func_wrap(
+        ABI_MODULE,
+        "set_component",
+        |mut caller: Caller<'_, ModState>,
+         id: i64,
+         name: i32,
+         name_len: i32,
+         value: i32,
+         value_len: i32|
+         -> wasmtime::Result<i32> {
+            let name = read_str(&mut caller, name, name_len)?;
+            let value = read_value(&mut caller, value, value_len)?;
+            match host(&mut caller)?.set_component(entity(id), &name, value) {
+                Ok(()) => Ok(0),
+                Err(err) => {
+                    warn!(target: "mod", "{}: set_component {}: {}", caller.data().name, name, err);
+                    Ok(-1)
+                }
+            }
+        },
+    )?;
+    linker.func_wrap(
+        ABI_MODULE,
+        "query",
+        |mut caller: Caller<'_, ModState>,
+         names: i32,
+         names_len: i32,
+         out: i32,
+         out_cap: i32|
+         -> wasmtime::Result<i32> {
+            let components = match read_value(&mut caller, names, names_len)? {
+                ScriptValue::List(names) => names
+                    .into_iter()
+                    .map(|name| match name {
+                        ScriptValue::String(name) => Ok(name),
+                        _ => Err(wasmtime::Error::msg("component names must be strings")),
+                    })
+                    .collect::<wasmtime::Result<Vec<_>>>()?,
+                _ => return Err(wasmtime::Error::msg("query takes a list of names")),
+            };
+            let entities = host(&mut caller)?.query(&components);
+            let written = entities.len().min(out_cap.max(0) as usize);
+            let out = bytes(&mut caller, out, written as i32 * 8)?;
+            for (slot, id) in out.chunks_exact_mut(8).zip(&entities) {
+                slot.copy_from_slice(&id.to_bits().to_le_bytes());
+            }
+            Ok(entities.len() as i32)
+        },
+    )?;
+    linker.func_wrap(
+        ABI_MODULE,
+        "emit",
+        |mut caller: Caller<'_, ModState>,
+         event: i32,
+         event_len: i32,
+         value: i32,
+         value_len: i32|
+         -> wasmtime::Result<()> {
+            let state = caller.data();
+            if state.emitted.len() >= state.max_emitted {
+                return Err(wasmtime::Error::msg(format!(
+                    "emitted more than {} events in one call",
+                    state.max_emitted
+                )));
+            }
+            let event = read_str(&mut caller, event, event_len)?;
+            let value = read_value(&mut caller, value, value_len)?;
+            caller.data_mut().emitted.push((event, value));
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+// Loads the mods in `dir` on the first tick, after the game set the host, and updates them every
+// tick, see `WasmMods`.
+pub struct WasmModPlugin {
+    pub dir: PathBuf,
+    pub limits: ModLimits,
+}
+
+impl WasmModPlugin {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            limits: ModLimits::default(),
+        }
+    }
+}
+
+impl Plugin for WasmModPlugin {
+    fn build(&self, engine: &mut Engine) {
+        match WasmMods::new(self.limits) {
+            Ok(mods) => {
+                engine.insert_resource(mods);
+            }
+            Err(err) => {
+                error!(
+                    "Failed to start the mod runtime, running without mods: {:#}",
+                    err
+                );
+                return;
+            }
+        }
+        let mut dir = Some(self.dir.clone());
+        engine.add_system(move |resources: &mut Resources| {
+            if let Some(mods) = resources.get_mut::<WasmMods>() {
+                if let Some(dir) = dir.take() {
+                    mods.load_dir(&dir);
+                }
+                mods.update();
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PONG: &str = r#"(module
+        (import "midnight2" "emit" (func $emit (param i32 i32 i32 i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 0) "pong\00")
+        (global $next (mut i32) (i32.const 1024))
+        (func (export "midnight2_abi_version") (result i32) (i32.const 1))
+        (func (export "alloc") (param $len i32) (result i32)
+            (global.get $next)
+            (global.set $next (i32.add (global.get $next) (local.get $len))))
+        (func (export "on_event") (param i32 i32 i32 i32)
+            (call $emit (i32.const 0) (i32.const 4) (i32.const 4) (i32.const 1))))"#;
+
+    const SPAM: &str = r#"(module
+        (import "midnight2" "emit" (func $emit (param i32 i32 i32 i32)))
+        (memory (export "memory") 1)
+        (data (i32.const 0) "spam\00")
+        (func (export "midnight2_abi_version") (result i32) (i32.const 1))
+        (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+        (func (export "update")
+            (loop $again
+                (call $emit (i32.const 0) (i32.const 4) (i32.const 4) (i32.const 1))
+                (br $again))))"#;
+
+    #[test]
+    fn mods_dont_hear_themselves_or_flood_the_tick() {
+        let mut mods = WasmMods::new(ModLimits::default()).unwrap();
+        mods.load("pong", PONG.as_bytes()).unwrap();
+        mods.emit("ping", ScriptValue::Nil);
+        mods.update();
+        mods.update();
+        // One answer to the game's ping, the mod didn't get its own pong to answer again.
+        assert_eq!(
+            mods.poll_event(),
+            Some(("pong".to_owned(), ScriptValue::Nil))
+        );
+        assert_eq!(mods.poll_event(), None);
+
+        // Stopped at the cap long before its fuel runs out.
+        mods.load("spam", SPAM.as_bytes()).unwrap();
+        mods.update();
+        assert_eq!(
+            mods.mods().collect::<Vec<_>>(),
+            [("pong", true), ("spam", false)]
+        );
+        assert_eq!(std::iter::from_fn(|| mods.poll_event()).count(), 256);
+    }
+}
@@ -1,29 +1,466 @@
 use std::{
-    sync::atomic::{AtomicBool, Ordering},
+    collections::BTreeSet,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+    sync::mpsc::{self, Receiver},
+    sync::OnceLock,
     thread::{JoinHandle, self},
+    time::Duration,
 };
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+// std::time::Instant panics on wasm32-unknown-unknown.
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use winit::{event::WindowEvent, window::Window};
+
 use crate::{
-    ecs::ecs_world
+    app::Application,
+    ecs::{ecs_world, Events},
+    engine::Engine,
+    frame_graph::{self, FrameSeries},
+    input::InputEvent,
+    loading::LoadingPhase,
+    metrics, profiling,
+    regression::Regression,
+    schedule::Schedule,
+};
+
+pub enum SimEvent {
+    Window(WindowEvent),
+    // Input that didn't come from the window, e.g. a playback timeline.
+    Input(InputEvent),
+    // Command line of a later launch, see `EngineBuilder::with_single_instance`.
+    InstanceLaunched(Vec<String>),
+    // The window's size and scale when it's created, winit only reports later changes.
+    Viewport(Viewport),
+}
+
+pub const FIXED_TIMESTEP: Duration = Duration::from_micros(16_667);
+
+// What other threads read of the sim.
+pub(crate) struct SimState {
+    shutdown: AtomicBool,
+    tick: AtomicU64,
+    // f32 bits, see `tick_fraction`.
+    tick_fraction: AtomicU32,
+}
+
+static S_STATE: SimState = SimState {
+    shutdown: AtomicBool::new(false),
+    tick: AtomicU64::new(0),
+    tick_fraction: AtomicU32::new(0),
 };
+// The runner's state in a hot reloaded game library, see `hot_reload::Host`.
+static S_SHARED: OnceLock<&'static SimState> = OnceLock::new();
+
+pub(crate) fn state() -> &'static SimState {
+    S_SHARED.get().copied().unwrap_or(&S_STATE)
+}
+
+#[cfg(all(feature = "hot-reload", not(target_arch = "wasm32")))]
+pub(crate) fn share(state: &'static SimState) {
+    let _ = S_SHARED.set(state);
+}
+
+pub fn should_shutdown() -> bool {
+    state().shutdown.load(Ordering::Relaxed)
+}
+
+pub fn shutdown() {
+    state().shutdown.store(true, Ordering::Relaxed);
+}
+
+// Number of ticks the sim has completed, readable from any thread.
+pub fn current_tick() -> u64 {
+    state().tick.load(Ordering::Relaxed)
+}
+
+// How far the wall clock is into the next tick, 0 to 1, for drawing state between the last two
+// ticks. Readable from any thread.
+pub fn tick_fraction() -> f32 {
+    f32::from_bits(state().tick_fraction.load(Ordering::Relaxed))
+}
+
+// Pauses the game, e.g. while the editor is open. Engine systems keep running so tools stay live,
+// the application's fixed_update doesn't. Every reason has to resume before the game does.
+#[derive(Default)]
+pub struct SimPause {
+    reasons: BTreeSet<&'static str>,
+}
 
-static mut S_SHUTDOWN: AtomicBool = AtomicBool::new(false);
+impl SimPause {
+    pub fn pause(&mut self, reason: &'static str) {
+        self.reasons.insert(reason);
+    }
 
-pub unsafe fn should_shutdown() -> bool {
-    S_SHUTDOWN.load(Ordering::Relaxed)
+    pub fn resume(&mut self, reason: &'static str) {
+        self.reasons.remove(reason);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        !self.reasons.is_empty()
+    }
+}
+
+// Sim time, updated at the start of every tick before the systems run.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Time {
+    pub tick: u64,
+    // Game time so far, doesn't advance while the sim is paused.
+    pub elapsed: Duration,
+    // Game time this tick covers, zero while paused.
+    pub delta: Duration,
+    // The tick's length even while paused, for things like menus that keep moving.
+    pub real_delta: Duration,
+}
+
+// The window's size in physical pixels and its DPI scale, kept up to date from window events.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub size: [f32; 2],
+    pub scale_factor: f32,
+}
+
+impl Viewport {
+    pub fn of(window: &Window) -> Self {
+        let size = window.inner_size();
+        Self {
+            size: [size.width as f32, size.height as f32],
+            scale_factor: window.scale_factor() as f32,
+        }
+    }
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            size: [1280.0, 720.0],
+            scale_factor: 1.0,
+        }
+    }
 }
 
-pub unsafe fn shutdown() {
-    S_SHUTDOWN.store(true, Ordering::Relaxed);
+// To the engine's input handlers, then the application and the world's events unless a handler
+// consumed it. Recordings see it first, so they keep what handlers consume too.
+fn dispatch_input<A: Application>(app: &mut A, engine: &mut Engine, input: InputEvent) {
+    if let Some(regression) = engine.resources_mut().get_mut::<Regression>() {
+        regression.record_input(&input);
+    }
+    if !engine.handle_input(&input) {
+        app.handle_input(engine, &input);
+        send_input(engine, input);
+    }
 }
 
-pub fn init() -> Result<JoinHandle<()>, Box<dyn std::error::Error>> {
-    Ok(thread::spawn(move || loop {
-        unsafe {
+// Input the engine didn't take also goes to gameplay systems, through the world's
+// `Events<InputEvent>`.
+fn send_input(engine: &mut Engine, input: InputEvent) {
+    let world = engine.resources_mut().get_mut::<ecs_world::EcsWorld>();
+    if let Some(events) = world.and_then(|world| world.res_mut::<Events<InputEvent>>()) {
+        events.send(input);
+    }
+}
+
+fn update_viewport(engine: &mut Engine, event: &WindowEvent) {
+    let Some(viewport) = engine.resources_mut().get_mut::<Viewport>() else {
+        return;
+    };
+    match event {
+        WindowEvent::Resized(size) => viewport.size = [size.width as f32, size.height as f32],
+        WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+            viewport.scale_factor = *scale_factor as f32
+        }
+        _ => {}
+    }
+}
+
+// Owns the application and runs its fixed ticks, driven either by the sim thread
+// or inline from the event loop on targets without threads.
+pub struct SimLoop<A: Application> {
+    app: A,
+    engine: Engine,
+    events: Receiver<SimEvent>,
+    // Sent with `send`, delivered after the window's.
+    sent: Vec<SimEvent>,
+    last_tick: Instant,
+    accumulator: Duration,
+    tick: u64,
+}
+
+impl<A: Application> SimLoop<A> {
+    pub fn new(mut app: A, mut engine: Engine, events: Receiver<SimEvent>) -> Self {
+        engine.insert_resource(Time::default());
+        if !engine.resources().contains::<ecs_world::EcsWorld>() {
+            engine.insert_resource(ecs_world::EcsWorld::new());
+        }
+        if let Some(world) = engine.resources_mut().get_mut::<ecs_world::EcsWorld>() {
+            world.add_event::<InputEvent>();
+        }
+        if !engine.resources().contains::<Viewport>() {
+            engine.insert_resource(Viewport::default());
+        }
+        if !engine.resources().contains::<Schedule>() {
+            engine.insert_resource(Schedule::default());
+        }
+        app.setup(&mut engine);
+        Self {
+            app,
+            engine,
+            events,
+            sent: Vec::new(),
+            last_tick: Instant::now(),
+            accumulator: Duration::ZERO,
+            tick: 0,
+        }
+    }
+
+    // A sim without a window or a thread, for tests and benchmarks. It only ticks when stepped
+    // and every tick covers FIXED_TIMESTEP, so the same steps and events give the same run.
+    pub fn headless(app: A, engine: Engine) -> Self {
+        let (_, events) = mpsc::channel();
+        Self::new(app, engine, events)
+    }
+
+    // Delivers pending events and runs every tick that is due, returns the time left until the next one.
+    pub fn update(&mut self) -> Duration {
+        self.deliver_events();
+
+        let now = Instant::now();
+        self.accumulator += now - self.last_tick;
+        self.last_tick = now;
+        while self.accumulator >= FIXED_TIMESTEP {
+            self.tick();
+            self.accumulator -= FIXED_TIMESTEP;
+        }
+        let fraction = self.accumulator.as_secs_f32() / FIXED_TIMESTEP.as_secs_f32();
+        state()
+            .tick_fraction
+            .store(fraction.to_bits(), Ordering::Relaxed);
+        FIXED_TIMESTEP - self.accumulator
+    }
+
+    // Delivers pending events and runs exactly one tick, regardless of wall clock time.
+    pub fn step(&mut self) {
+        self.deliver_events();
+        self.tick();
+    }
+
+    // Runs `ticks` ticks back to back with `step`.
+    pub fn step_n(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+    // Queues an event as if the window had sent it, delivered before the next tick.
+    pub fn send(&mut self, event: SimEvent) {
+        self.sent.push(event);
+    }
+
+    // Ticks run so far.
+    pub fn ticks(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn app(&self) -> &A {
+        &self.app
+    }
+
+    pub fn app_mut(&mut self) -> &mut A {
+        &mut self.app
+    }
+
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+
+    fn deliver_events(&mut self) {
+        let sent = std::mem::take(&mut self.sent);
+        let events: Vec<SimEvent> = self.events.try_iter().chain(sent).collect();
+        for event in events {
+            match event {
+                SimEvent::Window(event) => {
+                    update_viewport(&mut self.engine, &event);
+                    let (app, engine) = (&mut self.app, &mut self.engine);
+                    InputEvent::from_window_event(&event, |input| {
+                        dispatch_input(app, engine, input)
+                    });
+                    self.app.handle_event(&mut self.engine, &event);
+                }
+                SimEvent::Input(input) => dispatch_input(&mut self.app, &mut self.engine, input),
+                SimEvent::InstanceLaunched(args) => {
+                    self.app.handle_instance_launch(&mut self.engine, &args)
+                }
+                SimEvent::Viewport(viewport) => {
+                    self.engine.insert_resource(viewport);
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        let started = Instant::now();
+        {
+            let _span = tracing::info_span!("tick", tick = self.tick).entered();
+            profile_scope!("tick");
+            self.advance_time();
+            self.engine.run_systems();
+            let paused = self
+                .engine
+                .resources()
+                .get::<SimPause>()
+                .is_some_and(SimPause::is_paused);
+            if self.loaded() && !paused {
+                self.run_schedule();
+                let _span = tracing::debug_span!("fixed_update").entered();
+                profile_scope!("fixed_update");
+                self.app.fixed_update(&mut self.engine, FIXED_TIMESTEP);
+            } else {
+                self.update_events();
+            }
+        }
+        let elapsed = started.elapsed();
+        frame_graph::record(FrameSeries::SimTick, elapsed);
+        metrics::observe_duration("sim_tick_seconds", elapsed);
+        metrics::increment("sim_ticks_total", 1);
+        profiling::finish_frame("sim");
+
+        self.tick += 1;
+        state().tick.store(self.tick, Ordering::Relaxed);
+    }
+
+    // Gameplay systems, see `schedule`. They stop with the game while it's paused or loading.
+    fn run_schedule(&mut self) {
+        let resources = self.engine.resources_mut();
+        if let Some(mut schedule) = resources.remove::<Schedule>() {
+            schedule.run(resources);
+            resources.insert(schedule);
+        }
+    }
+
+    // The schedule updates the world's events when it runs, this keeps them from piling up
+    // while it doesn't.
+    fn update_events(&mut self) {
+        let resources = self.engine.resources_mut();
+        if let Some(world) = resources.get_mut::<ecs_world::EcsWorld>() {
+            world.update_events();
+        }
+    }
+
+    fn advance_time(&mut self) {
+        let resources = self.engine.resources_mut();
+        let paused = resources.get::<SimPause>().is_some_and(SimPause::is_paused);
+        if let Some(time) = resources.get_mut::<Time>() {
+            time.tick = self.tick;
+            time.real_delta = FIXED_TIMESTEP;
+            time.delta = if paused {
+                Duration::ZERO
+            } else {
+                FIXED_TIMESTEP
+            };
+            time.elapsed += time.delta;
+        }
+    }
+
+    fn loaded(&mut self) -> bool {
+        let Some(loading) = self.engine.resources_mut().get_mut::<LoadingPhase>() else {
+            return true;
+        };
+        let just_finished = loading.update();
+        let finished = loading.is_finished();
+        if just_finished {
+            self.app.on_loaded(&mut self.engine);
+        }
+        finished
+    }
+
+    pub fn shutdown(mut self) {
+        self.app.shutdown(&mut self.engine);
+    }
+}
+
+pub fn init<A: Application>(
+    app: A,
+    engine: Engine,
+    events: Receiver<SimEvent>,
+) -> Result<JoinHandle<()>, Box<dyn std::error::Error>> {
+    Ok(thread::Builder::new().name("sim".to_owned()).spawn(move || {
+        let mut sim = {
+            log_scope!("Sim setup");
+            SimLoop::new(app, engine, events)
+        };
+        loop {
             if should_shutdown() {
+                sim.shutdown();
                 break;
             }
+            thread::sleep(sim.update());
+        }
+    })?)
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::keyboard::KeyCode;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Counter {
+        updates: u64,
+        keys: Vec<(u64, KeyCode)>,
+    }
+
+    impl Application for Counter {
+        fn setup(&mut self, engine: &mut Engine) {
+            engine.insert_resource(0u64).add_system(|resources| {
+                *resources.get_mut::<u64>().unwrap() += 1;
+            });
+        }
+
+        fn fixed_update(&mut self, _engine: &mut Engine, _dt: Duration) {
+            self.updates += 1;
+        }
+
+        fn handle_input(&mut self, _engine: &mut Engine, event: &InputEvent) {
+            if let InputEvent::Key { code, .. } = event {
+                self.keys.push((self.updates, *code));
+            }
         }
-        // render_loop(&mut game_renderer);
-    }))
-}
\ No newline at end of file
+    }
+
+    #[test]
+    fn headless_sim_steps_deterministically() {
+        let mut sim = SimLoop::headless(Counter::default(), Engine::new());
+        sim.step_n(3);
+        sim.send(SimEvent::Input(InputEvent::Key {
+            code: KeyCode::Space,
+            pressed: true,
+        }));
+        sim.engine_mut()
+            .insert_resource(SimPause::default())
+            .resources_mut()
+            .get_mut::<SimPause>()
+            .unwrap()
+            .pause("test");
+        sim.step_n(2);
+
+        assert_eq!(sim.ticks(), 5);
+        assert_eq!(sim.app().updates, 3);
+        assert_eq!(sim.app().keys, [(3, KeyCode::Space)]);
+        let resources = sim.engine().resources();
+        assert_eq!(resources.get::<u64>(), Some(&5));
+        let time = resources.get::<Time>().unwrap();
+        assert_eq!(time.elapsed, FIXED_TIMESTEP * 3);
+        assert_eq!(time.tick, 4);
+        // Input sent while paused doesn't pile up.
+        let world = resources.get::<ecs_world::EcsWorld>().unwrap();
+        assert!(world.res::<Events<InputEvent>>().unwrap().is_empty());
+    }
+}
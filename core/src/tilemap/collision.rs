@@ -0,0 +1,55 @@
+// Merges solid tiles into rectangles: runs along each row first, then runs spanning the same
+// columns in consecutive rows grow downwards. Not the fewest possible, but close for level
+// geometry and stable as tiles change elsewhere.
+
+use std::collections::BTreeMap;
+
+// World space, y up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+// First and last tile of each rectangle, inclusive, in tile coordinates.
+pub(super) fn merge(solid: impl Iterator<Item = [i32; 2]>) -> Vec<[[i32; 2]; 2]> {
+    let mut rows: BTreeMap<i32, Vec<i32>> = BTreeMap::new();
+    for [x, y] in solid {
+        rows.entry(y).or_default().push(x);
+    }
+    let mut done = Vec::new();
+    // Open rectangles by their column span, with the row they started on.
+    let mut open: BTreeMap<(i32, i32), i32> = BTreeMap::new();
+    let mut previous_row = None;
+    for (y, mut columns) in rows {
+        if let Some(previous) = previous_row.filter(|previous| *previous != y - 1) {
+            close(&mut done, std::mem::take(&mut open), previous);
+        }
+        columns.sort_unstable();
+        let mut runs = Vec::new();
+        for x in columns {
+            match runs.last_mut() {
+                Some((_, end)) if *end + 1 == x => *end = x,
+                _ => runs.push((x, x)),
+            }
+        }
+        let next = runs
+            .into_iter()
+            .map(|run| (run, open.remove(&run).unwrap_or(y)))
+            .collect();
+        // Whatever didn't carry on ended on the row above.
+        close(&mut done, std::mem::replace(&mut open, next), y - 1);
+        previous_row = Some(y);
+    }
+    if let Some(y) = previous_row {
+        close(&mut done, open, y);
+    }
+    done
+}
+
+fn close(done: &mut Vec<[[i32; 2]; 2]>, open: BTreeMap<(i32, i32), i32>, last_row: i32) {
+    done.extend(
+        open.into_iter()
+            .map(|((start, end), first_row)| [[start, first_row], [end, last_row]]),
+    );
+}
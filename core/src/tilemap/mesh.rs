@@ -0,0 +1,96 @@
+// A chunk's tiles as quads, one batch per tileset so each is a single draw with its atlas bound.
+
+use std::time::Duration;
+
+use super::{Tile, Tileset, CHUNK_SIZE};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TileVertex {
+    // World space, without the layer's offset.
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileBatch {
+    // Index into the map's tilesets.
+    pub tileset: usize,
+    // Four per tile, clockwise from the top left.
+    pub vertices: Vec<TileVertex>,
+    // Two triangles per tile, a full chunk stays well inside u16.
+    pub indices: Vec<u16>,
+}
+
+// The batches and whether any tile in them is animated.
+pub(super) fn build(
+    chunk: [i32; 2],
+    tiles: &[Option<Tile>],
+    tilesets: &[Tileset],
+    tile_size: [f32; 2],
+    time: Duration,
+) -> (Vec<TileBatch>, bool) {
+    let mut batches: Vec<TileBatch> = Vec::new();
+    let mut animated = false;
+    for (index, tile) in tiles.iter().enumerate() {
+        let Some(tile) = tile else {
+            continue;
+        };
+        let Some((set, tileset)) = tilesets
+            .iter()
+            .enumerate()
+            .find(|(_, tileset)| tileset.contains(tile.id))
+        else {
+            continue;
+        };
+        let local = tile.id - tileset.first_id;
+        animated |= tileset.animation(local).is_some();
+        let [uv_min, uv_max] = tileset.uv(tileset.frame(local, time));
+
+        let index = index as i32;
+        let at = [
+            chunk[0] * CHUNK_SIZE + index % CHUNK_SIZE,
+            chunk[1] * CHUNK_SIZE + index / CHUNK_SIZE,
+        ];
+        let left = at[0] as f32 * tile_size[0];
+        let top = -at[1] as f32 * tile_size[1];
+
+        let batch = match batches.iter_mut().position(|batch| batch.tileset == set) {
+            Some(batch) => &mut batches[batch],
+            None => {
+                batches.push(TileBatch {
+                    tileset: set,
+                    vertices: Vec::new(),
+                    indices: Vec::new(),
+                });
+                batches.last_mut().unwrap()
+            }
+        };
+        let first = batch.vertices.len() as u16;
+        for corner in [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]] {
+            let [mut s, mut t] = corner;
+            if tile.flip.diagonal {
+                (s, t) = (t, s);
+            }
+            if tile.flip.x {
+                s = 1.0 - s;
+            }
+            if tile.flip.y {
+                t = 1.0 - t;
+            }
+            batch.vertices.push(TileVertex {
+                position: [
+                    left + corner[0] * tile_size[0],
+                    top - corner[1] * tile_size[1],
+                ],
+                uv: [
+                    uv_min[0] + (uv_max[0] - uv_min[0]) * s,
+                    uv_min[1] + (uv_max[1] - uv_min[1]) * t,
+                ],
+            });
+        }
+        batch
+            .indices
+            .extend([0, 1, 2, 0, 2, 3].map(|corner| first + corner));
+    }
+    (batches, animated)
+}
@@ -0,0 +1,588 @@
+//! Tilemaps for 2D games. A map has tilesets, atlas images cut into equal tiles, and layers of
+//! tiles stored in square chunks so large and unbounded maps only keep the parts that have
+//! tiles. Tiles can be animated, flipped, and marked solid in their tileset.
+//!
+//! Every chunk is batched into one quad list per tileset, rebuilt only when its tiles or one of
+//! its animations change, which is what the renderer uploads. Solid tiles merge into as few
+//! rectangles as possible for collision. Maps load from Tiled's `.tmj` and `.tmx` files, see
+//! `tiled.rs`.
+//!
+//! The renderer has no sprite pass and there is no physics subsystem yet, so the batches and
+//! collision rectangles are built here for them to take up.
+
+mod collision;
+mod mesh;
+mod tiled;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    time::Duration,
+};
+
+pub use collision::Rect;
+pub use mesh::{TileBatch, TileVertex};
+pub use tiled::{load, parse_tmj, parse_tmx};
+
+use crate::{
+    engine::{Engine, Plugin, Resources},
+    metrics,
+    sim::Time,
+};
+
+// Tiles along each side of a chunk.
+pub const CHUNK_SIZE: i32 = 16;
+
+// Tiled applies the diagonal flip first, then horizontal, then vertical.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Flip {
+    pub x: bool,
+    pub y: bool,
+    pub diagonal: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Tile {
+    // Unique across the map's tilesets, see `Tileset::first_id`.
+    pub id: u32,
+    pub flip: Flip,
+}
+
+impl Tile {
+    pub fn new(id: u32) -> Self {
+        Self {
+            id,
+            flip: Flip::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    // Local to the tileset.
+    pub tile: u32,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tileset {
+    pub name: String,
+    // The map wide id of the tileset's first tile.
+    pub first_id: u32,
+    // The atlas image, as a virtual path.
+    pub image: String,
+    pub image_size: [u32; 2],
+    pub tile_size: [u32; 2],
+    // Pixels around the atlas and between tiles.
+    pub margin: u32,
+    pub spacing: u32,
+    pub columns: u32,
+    pub count: u32,
+    animations: HashMap<u32, Vec<Frame>>,
+    solid: HashSet<u32>,
+}
+
+impl Tileset {
+    pub fn new(name: &str, image: &str, image_size: [u32; 2], tile_size: [u32; 2]) -> Self {
+        let tile_size = tile_size.map(|side| side.max(1));
+        let columns = image_size[0] / tile_size[0];
+        Self {
+            name: name.to_owned(),
+            first_id: 1,
+            image: image.to_owned(),
+            image_size,
+            tile_size,
+            margin: 0,
+            spacing: 0,
+            columns,
+            count: columns * (image_size[1] / tile_size[1]),
+            animations: HashMap::new(),
+            solid: HashSet::new(),
+        }
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        id >= self.first_id && id - self.first_id < self.count
+    }
+
+    // Texture coordinates of a local tile, top left and bottom right.
+    pub fn uv(&self, tile: u32) -> [[f32; 2]; 2] {
+        let columns = self.columns.max(1);
+        let (column, row) = (tile % columns, tile / columns);
+        let stride = self.tile_size.map(|side| side + self.spacing);
+        let min = [
+            self.margin + column * stride[0],
+            self.margin + row * stride[1],
+        ];
+        let size = self.image_size.map(|side| side.max(1) as f32);
+        [
+            std::array::from_fn(|axis| min[axis] as f32 / size[axis]),
+            std::array::from_fn(|axis| (min[axis] + self.tile_size[axis]) as f32 / size[axis]),
+        ]
+    }
+
+    // Animates a local tile, every tile placed with it plays the frames in a loop.
+    pub fn set_animation(&mut self, tile: u32, frames: Vec<Frame>) {
+        if frames.is_empty() {
+            self.animations.remove(&tile);
+        } else {
+            self.animations.insert(tile, frames);
+        }
+    }
+
+    pub fn animation(&self, tile: u32) -> Option<&[Frame]> {
+        self.animations.get(&tile).map(Vec::as_slice)
+    }
+
+    pub fn set_solid(&mut self, tile: u32, solid: bool) {
+        if solid {
+            self.solid.insert(tile);
+        } else {
+            self.solid.remove(&tile);
+        }
+    }
+
+    pub fn is_solid(&self, tile: u32) -> bool {
+        self.solid.contains(&tile)
+    }
+
+    // The local tile showing at `time` for an animated one.
+    fn frame(&self, tile: u32, time: Duration) -> u32 {
+        let Some(frames) = self.animations.get(&tile) else {
+            return tile;
+        };
+        let total: Duration = frames.iter().map(|frame| frame.duration).sum();
+        if total.is_zero() {
+            return frames[0].tile;
+        }
+        let mut at = Duration::from_nanos((time.as_nanos() % total.as_nanos()) as u64);
+        for frame in frames {
+            if at < frame.duration {
+                return frame.tile;
+            }
+            at -= frame.duration;
+        }
+        frames[frames.len() - 1].tile
+    }
+}
+
+struct Chunk {
+    // Row by row, CHUNK_SIZE².
+    tiles: Vec<Option<Tile>>,
+    filled: usize,
+    // None when the tiles changed since the batches were built.
+    batches: Option<Vec<TileBatch>>,
+    animated: bool,
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self {
+            tiles: vec![None; (CHUNK_SIZE * CHUNK_SIZE) as usize],
+            filled: 0,
+            batches: None,
+            animated: false,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct TileLayer {
+    pub name: String,
+    pub visible: bool,
+    pub opacity: f32,
+    // World units the whole layer is moved by.
+    pub offset: [f32; 2],
+    chunks: HashMap<[i32; 2], Chunk>,
+}
+
+impl TileLayer {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            visible: true,
+            opacity: 1.0,
+            ..Self::default()
+        }
+    }
+
+    pub fn get(&self, at: [i32; 2]) -> Option<Tile> {
+        let (chunk, index) = chunk_index(at);
+        self.chunks.get(&chunk)?.tiles[index]
+    }
+
+    // Chunks whose last tile is cleared are dropped.
+    fn set(&mut self, at: [i32; 2], tile: Option<Tile>) {
+        let (key, index) = chunk_index(at);
+        let chunk = self.chunks.entry(key).or_default();
+        let previous = std::mem::replace(&mut chunk.tiles[index], tile);
+        chunk.filled = chunk.filled + tile.is_some() as usize - previous.is_some() as usize;
+        chunk.batches = None;
+        if chunk.filled == 0 {
+            self.chunks.remove(&key);
+        }
+    }
+
+    // Positions of every tile, in no particular order.
+    pub fn tiles(&self) -> impl Iterator<Item = ([i32; 2], Tile)> + '_ {
+        self.chunks.iter().flat_map(|(key, chunk)| {
+            chunk
+                .tiles
+                .iter()
+                .enumerate()
+                .filter_map(move |(index, tile)| {
+                    let index = index as i32;
+                    let at = [
+                        key[0] * CHUNK_SIZE + index % CHUNK_SIZE,
+                        key[1] * CHUNK_SIZE + index / CHUNK_SIZE,
+                    ];
+                    tile.map(|tile| (at, tile))
+                })
+        })
+    }
+
+    // The batches of every chunk, as of the last `Tilemap::update_batches`.
+    pub fn batches(&self) -> impl Iterator<Item = ([i32; 2], &[TileBatch])> {
+        self.chunks
+            .iter()
+            .filter_map(|(key, chunk)| Some((*key, chunk.batches.as_deref()?)))
+    }
+}
+
+fn chunk_index(at: [i32; 2]) -> ([i32; 2], usize) {
+    let chunk = at.map(|coordinate| coordinate.div_euclid(CHUNK_SIZE));
+    let local = at.map(|coordinate| coordinate.rem_euclid(CHUNK_SIZE));
+    (chunk, (local[1] * CHUNK_SIZE + local[0]) as usize)
+}
+
+// Tile coordinates count right and down like Tiled's, world y is up, so row 0 sits just below
+// the map's origin.
+pub struct Tilemap {
+    // World units one tile covers.
+    pub tile_size: [f32; 2],
+    tilesets: Vec<Tileset>,
+    layers: Vec<TileLayer>,
+    time: Duration,
+}
+
+impl Tilemap {
+    pub fn new(tile_size: [f32; 2]) -> Self {
+        Self {
+            tile_size,
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            time: Duration::ZERO,
+        }
+    }
+
+    pub fn add_tileset(&mut self, tileset: Tileset) -> usize {
+        self.tilesets.push(tileset);
+        self.tilesets.len() - 1
+    }
+
+    pub fn tilesets(&self) -> &[Tileset] {
+        &self.tilesets
+    }
+
+    pub fn tileset_of(&self, id: u32) -> Option<(usize, &Tileset)> {
+        self.tilesets
+            .iter()
+            .enumerate()
+            .find(|(_, tileset)| tileset.contains(id))
+    }
+
+    pub fn add_layer(&mut self, layer: TileLayer) -> usize {
+        self.layers.push(layer);
+        self.layers.len() - 1
+    }
+
+    pub fn layers(&self) -> &[TileLayer] {
+        &self.layers
+    }
+
+    pub fn layer(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.name == name)
+    }
+
+    pub fn layer_mut(&mut self, layer: usize) -> Option<&mut TileLayer> {
+        self.layers.get_mut(layer)
+    }
+
+    pub fn get(&self, layer: usize, at: [i32; 2]) -> Option<Tile> {
+        self.layers.get(layer)?.get(at)
+    }
+
+    pub fn set(&mut self, layer: usize, at: [i32; 2], tile: Option<Tile>) {
+        if let Some(layer) = self.layers.get_mut(layer) {
+            layer.set(at, tile);
+        }
+    }
+
+    // The world area a tile covers, without the layer's offset.
+    pub fn tile_rect(&self, at: [i32; 2]) -> Rect {
+        let [width, height] = self.tile_size;
+        Rect {
+            min: [at[0] as f32 * width, -(at[1] + 1) as f32 * height],
+            max: [(at[0] + 1) as f32 * width, -at[1] as f32 * height],
+        }
+    }
+
+    // The tile a world point is on.
+    pub fn tile_at(&self, point: [f32; 2]) -> [i32; 2] {
+        [
+            (point[0] / self.tile_size[0]).floor() as i32,
+            (-point[1] / self.tile_size[1]).floor() as i32,
+        ]
+    }
+
+    pub fn is_solid(&self, tile: Tile) -> bool {
+        self.tileset_of(tile.id)
+            .is_some_and(|(_, tileset)| tileset.is_solid(tile.id - tileset.first_id))
+    }
+
+    // Moves animations along, chunks with an animated tile whose frame changed are rebuilt.
+    pub fn advance(&mut self, delta: Duration) {
+        let before = self.time;
+        self.time += delta;
+        let changed: HashSet<u32> = self
+            .tilesets
+            .iter()
+            .flat_map(|tileset| {
+                tileset
+                    .animations
+                    .keys()
+                    .filter(|tile| {
+                        tileset.frame(**tile, before) != tileset.frame(**tile, self.time)
+                    })
+                    .map(|tile| tile + tileset.first_id)
+            })
+            .collect();
+        if changed.is_empty() {
+            return;
+        }
+        for chunk in self
+            .layers
+            .iter_mut()
+            .flat_map(|layer| layer.chunks.values_mut())
+        {
+            if chunk.animated
+                && chunk
+                    .tiles
+                    .iter()
+                    .flatten()
+                    .any(|tile| changed.contains(&tile.id))
+            {
+                chunk.batches = None;
+            }
+        }
+    }
+
+    // Rebuilds the batches of chunks that changed, returns how many were rebuilt.
+    pub fn update_batches(&mut self) -> usize {
+        let mut rebuilt = 0;
+        for layer in &mut self.layers {
+            for (key, chunk) in &mut layer.chunks {
+                if chunk.batches.is_some() {
+                    continue;
+                }
+                let (batches, animated) = mesh::build(
+                    *key,
+                    &chunk.tiles,
+                    &self.tilesets,
+                    self.tile_size,
+                    self.time,
+                );
+                chunk.batches = Some(batches);
+                chunk.animated = animated;
+                rebuilt += 1;
+            }
+        }
+        rebuilt
+    }
+
+    // Solid tiles merged into rectangles, in world space with the layer's offset.
+    pub fn collision_shapes(&self, layer: usize) -> Vec<Rect> {
+        let Some(tile_layer) = self.layers.get(layer) else {
+            return Vec::new();
+        };
+        let solid = tile_layer
+            .tiles()
+            .filter(|(_, tile)| self.is_solid(*tile))
+            .map(|(at, _)| at);
+        collision::merge(solid)
+            .into_iter()
+            .map(|[min, max]| {
+                let (low, high) = (
+                    self.tile_rect([min[0], max[1]]),
+                    self.tile_rect([max[0], min[1]]),
+                );
+                Rect {
+                    min: std::array::from_fn(|axis| low.min[axis] + tile_layer.offset[axis]),
+                    max: std::array::from_fn(|axis| high.max[axis] + tile_layer.offset[axis]),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TilemapId(u64);
+
+impl fmt::Display for TilemapId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Default)]
+pub struct Tilemaps {
+    next_id: u64,
+    maps: Vec<(TilemapId, Tilemap)>,
+}
+
+impl Tilemaps {
+    pub fn add(&mut self, map: Tilemap) -> TilemapId {
+        let id = TilemapId(self.next_id);
+        self.next_id += 1;
+        self.maps.push((id, map));
+        id
+    }
+
+    pub fn remove(&mut self, id: TilemapId) -> Option<Tilemap> {
+        let index = self.maps.iter().position(|(map, _)| *map == id)?;
+        Some(self.maps.remove(index).1)
+    }
+
+    pub fn get(&self, id: TilemapId) -> Option<&Tilemap> {
+        self.maps
+            .iter()
+            .find(|(map, _)| *map == id)
+            .map(|(_, map)| map)
+    }
+
+    pub fn get_mut(&mut self, id: TilemapId) -> Option<&mut Tilemap> {
+        self.maps
+            .iter_mut()
+            .find(|(map, _)| *map == id)
+            .map(|(_, map)| map)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (TilemapId, &Tilemap)> {
+        self.maps.iter().map(|(id, map)| (*id, map))
+    }
+}
+
+pub struct TilemapPlugin;
+
+impl Plugin for TilemapPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(Tilemaps::default())
+            .add_system(|resources: &mut Resources| {
+                let delta = resources
+                    .get::<Time>()
+                    .map_or(Duration::ZERO, |time| time.delta);
+                let Some(tilemaps) = resources.get_mut::<Tilemaps>() else {
+                    return;
+                };
+                let mut rebuilt = 0;
+                for (_, map) in &mut tilemaps.maps {
+                    map.advance(delta);
+                    rebuilt += map.update_batches();
+                }
+                metrics::set_gauge("tile_chunks_rebuilt", rebuilt as f64);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = r#"{
+        "width": 4, "height": 3, "tilewidth": 16, "tileheight": 16, "infinite": false,
+        "tilesets": [{
+            "firstgid": 1, "name": "ground", "image": "../tiles/ground.png",
+            "imagewidth": 64, "imageheight": 32, "tilewidth": 16, "tileheight": 16,
+            "columns": 4, "tilecount": 8,
+            "tiles": [
+                {"id": 0, "properties": [{"name": "solid", "type": "bool", "value": true}]},
+                {"id": 4, "animation": [{"tileid": 4, "duration": 100}, {"tileid": 5, "duration": 100}]}
+            ]
+        }],
+        "layers": [{
+            "type": "tilelayer", "name": "ground", "width": 4, "height": 3,
+            "data": [0, 0, 0, 5,
+                     1, 1, 0, 0,
+                     1, 1, 2147483650, 0]
+        }]
+    }"#;
+
+    #[test]
+    fn loads_batches_and_collides() {
+        let mut map = parse_tmj(MAP, "maps/level.tmj", |_| unreachable!()).unwrap();
+        assert_eq!(map.tilesets()[0].image, "tiles/ground.png");
+        let ground = map.layer("ground").unwrap();
+        assert_eq!(
+            map.get(ground, [2, 2]),
+            Some(Tile {
+                id: 2,
+                flip: Flip {
+                    x: true,
+                    ..Flip::default()
+                }
+            })
+        );
+
+        // The four solid tiles merge into one 32x32 box below the top row.
+        assert_eq!(
+            map.collision_shapes(ground),
+            [Rect {
+                min: [0.0, -48.0],
+                max: [32.0, -16.0]
+            }]
+        );
+
+        assert_eq!(map.update_batches(), 1);
+        let (_, batches) = map.layers()[ground].batches().next().unwrap();
+        assert_eq!(batches[0].vertices.len(), 6 * 4);
+        assert_eq!(batches[0].indices.len(), 6 * 6);
+        // The flipped tile samples its atlas cell right to left.
+        let flipped = &batches[0].vertices[5 * 4..];
+        assert_eq!((flipped[0].uv[0], flipped[1].uv[0]), (0.5, 0.25));
+
+        // Only the animation flipping frames rebuilds the chunk.
+        map.advance(Duration::from_millis(50));
+        assert_eq!(map.update_batches(), 0);
+        map.advance(Duration::from_millis(60));
+        assert_eq!(map.update_batches(), 1);
+        let (_, batches) = map.layers()[ground].batches().next().unwrap();
+        assert_eq!(batches[0].vertices[0].uv[0], 0.25);
+    }
+
+    #[test]
+    fn ids_stay_exact_and_nesting_is_bounded() {
+        let map = parse_tmx(
+            r#"<map orientation="orthogonal" tilewidth="16" tileheight="16">
+                <tileset firstgid="16777217" name="ground" tilewidth="16" tileheight="16"
+                    columns="4" tilecount="8">
+                    <image source="ground.png" width="64" height="32"/>
+                </tileset>
+                <layer name="ground" width="2">
+                    <data><tile gid="0"/><tile gid="2164260867"/></data>
+                </layer>
+            </map>"#,
+            "maps/level.tmx",
+            |_| unreachable!(),
+        )
+        .unwrap();
+        assert_eq!(map.tilesets()[0].first_id, 16777217);
+        let tile = map.get(map.layer("ground").unwrap(), [1, 0]).unwrap();
+        assert_eq!((tile.id, tile.flip.x), (16777219, true));
+
+        let deep = "[".repeat(10_000) + &"]".repeat(10_000);
+        assert!(crate::markup::Json::parse(&deep).is_err());
+        let deep = "<a>".repeat(10_000) + &"</a>".repeat(10_000);
+        assert!(crate::markup::Element::parse(&deep).is_err());
+    }
+}
@@ -0,0 +1,409 @@
+// Maps from the Tiled editor, JSON (`.tmj`, `.tsj`) and XML (`.tmx`, `.tsx`). Orthogonal tile
+// layers only, group layers are flattened, object and image layers are skipped. Tile data can be
+// CSV, arrays or uncompressed base64, compressed data has to be re-saved without compression.
+//
+// A tile is solid if it has a `solid` bool property or any collision shape, drawn shapes become
+// the whole tile. One world unit is one pixel, scale `Tilemap::tile_size` for anything else.
+
+use std::time::Duration;
+
+use super::{Flip, Frame, Tile, TileLayer, Tilemap, Tileset};
+use crate::{
+    markup::{decode_base64, Element, Json},
+    vfs::Vfs,
+};
+
+const FLIP_X: u32 = 0x8000_0000;
+const FLIP_Y: u32 = 0x4000_0000;
+const FLIP_DIAGONAL: u32 = 0x2000_0000;
+// Also masks out the hexagonal rotation bit.
+const ID_MASK: u32 = 0x0fff_ffff;
+
+// Reads the map and its external tilesets through the VFS.
+pub fn load(vfs: &Vfs, path: &str) -> Result<Tilemap, String> {
+    let read = |path: &str| {
+        vfs.read_to_string(path)
+            .map_err(|err| format!("Failed to read {}: {}", path, err))
+    };
+    let source = read(path)?;
+    let map = if path.ends_with(".tmx") {
+        parse_tmx(&source, path, read)
+    } else {
+        parse_tmj(&source, path, read)
+    };
+    map.map_err(|err| format!("{}: {}", path, err))
+}
+
+// `path` is where the map is, external tilesets and images are relative to it. `read` gets the
+// virtual path of an external tileset.
+pub fn parse_tmj(
+    source: &str,
+    path: &str,
+    mut read: impl FnMut(&str) -> Result<String, String>,
+) -> Result<Tilemap, String> {
+    let map = Json::parse(source)?;
+    check_orientation(map.get("orientation").and_then(Json::as_str))?;
+    let mut tilemap = Tilemap::new([number(&map, "tilewidth")?, number(&map, "tileheight")?]);
+    for tileset in map.get("tilesets").map_or(&[][..], Json::as_array) {
+        let first_id = id(tileset, "firstgid")?;
+        let mut tileset = match tileset.get("source").and_then(Json::as_str) {
+            Some(source) => external_tileset(path, source, &mut read)?,
+            None => tsj(tileset, path)?,
+        };
+        tileset.first_id = first_id;
+        tilemap.add_tileset(tileset);
+    }
+    tmj_layers(&mut tilemap, &map, [0.0; 2])?;
+    Ok(tilemap)
+}
+
+pub fn parse_tmx(
+    source: &str,
+    path: &str,
+    mut read: impl FnMut(&str) -> Result<String, String>,
+) -> Result<Tilemap, String> {
+    let map = Element::parse(source)?;
+    if map.name != "map" {
+        return Err(format!("expected <map>, found <{}>", map.name));
+    }
+    check_orientation(map.attribute("orientation"))?;
+    let mut tilemap = Tilemap::new([
+        attribute(&map, "tilewidth")?,
+        attribute(&map, "tileheight")?,
+    ]);
+    for tileset in map.children_named("tileset") {
+        let first_id = id_attribute(tileset, "firstgid")?;
+        let mut tileset = match tileset.attribute("source") {
+            Some(source) => external_tileset(path, source, &mut read)?,
+            None => tsx(tileset, path)?,
+        };
+        tileset.first_id = first_id;
+        tilemap.add_tileset(tileset);
+    }
+    tmx_layers(&mut tilemap, &map, [0.0; 2])?;
+    Ok(tilemap)
+}
+
+fn check_orientation(orientation: Option<&str>) -> Result<(), String> {
+    match orientation {
+        None | Some("orthogonal") => Ok(()),
+        Some(other) => Err(format!("{} maps aren't supported", other)),
+    }
+}
+
+fn external_tileset(
+    map_path: &str,
+    source: &str,
+    read: &mut impl FnMut(&str) -> Result<String, String>,
+) -> Result<Tileset, String> {
+    let path = resolve(map_path, source);
+    let text = read(&path)?;
+    let tileset = if path.ends_with(".tsx") {
+        tsx(&Element::parse(&text)?, &path)
+    } else {
+        tsj(&Json::parse(&text)?, &path)
+    };
+    tileset.map_err(|err| format!("{}: {}", path, err))
+}
+
+fn tsj(tileset: &Json, path: &str) -> Result<Tileset, String> {
+    let image = tileset
+        .get("image")
+        .and_then(Json::as_str)
+        .ok_or("tilesets made of separate images aren't supported")?;
+    let mut result = Tileset::new(
+        tileset
+            .get("name")
+            .and_then(Json::as_str)
+            .unwrap_or_default(),
+        &resolve(path, image),
+        [
+            number(tileset, "imagewidth")? as u32,
+            number(tileset, "imageheight")? as u32,
+        ],
+        [
+            number(tileset, "tilewidth")? as u32,
+            number(tileset, "tileheight")? as u32,
+        ],
+    );
+    result.margin = optional(tileset, "margin") as u32;
+    result.spacing = optional(tileset, "spacing") as u32;
+    result.columns = number(tileset, "columns")? as u32;
+    result.count = number(tileset, "tilecount")? as u32;
+    for tile in tileset.get("tiles").map_or(&[][..], Json::as_array) {
+        let id = number(tile, "id")? as u32;
+        let frames = tile
+            .get("animation")
+            .map_or(&[][..], Json::as_array)
+            .iter()
+            .map(|frame| {
+                Ok(Frame {
+                    tile: number(frame, "tileid")? as u32,
+                    duration: Duration::from_millis(number(frame, "duration")? as u64),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        result.set_animation(id, frames);
+        let solid_property = tile
+            .get("properties")
+            .map_or(&[][..], Json::as_array)
+            .iter()
+            .any(|property| {
+                property.get("name").and_then(Json::as_str) == Some("solid")
+                    && property.get("value").and_then(Json::as_bool) == Some(true)
+            });
+        let has_shapes = tile
+            .get("objectgroup")
+            .and_then(|group| group.get("objects"))
+            .is_some_and(|objects| !objects.as_array().is_empty());
+        result.set_solid(id, solid_property || has_shapes);
+    }
+    Ok(result)
+}
+
+fn tsx(tileset: &Element, path: &str) -> Result<Tileset, String> {
+    let image = tileset
+        .child("image")
+        .ok_or("tilesets made of separate images aren't supported")?;
+    let mut result = Tileset::new(
+        tileset.attribute("name").unwrap_or_default(),
+        &resolve(
+            path,
+            image.attribute("source").ok_or("image has no source")?,
+        ),
+        [
+            attribute(image, "width")? as u32,
+            attribute(image, "height")? as u32,
+        ],
+        [
+            attribute(tileset, "tilewidth")? as u32,
+            attribute(tileset, "tileheight")? as u32,
+        ],
+    );
+    let optional = |name| attribute(tileset, name).unwrap_or_default() as u32;
+    result.margin = optional("margin");
+    result.spacing = optional("spacing");
+    result.columns = attribute(tileset, "columns")? as u32;
+    result.count = attribute(tileset, "tilecount")? as u32;
+    for tile in tileset.children_named("tile") {
+        let id = attribute(tile, "id")? as u32;
+        let frames = tile
+            .child("animation")
+            .map(|animation| {
+                animation
+                    .children_named("frame")
+                    .map(|frame| {
+                        Ok(Frame {
+                            tile: attribute(frame, "tileid")? as u32,
+                            duration: Duration::from_millis(attribute(frame, "duration")? as u64),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, String>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+        result.set_animation(id, frames);
+        let solid_property = tile
+            .child("properties")
+            .into_iter()
+            .flat_map(|properties| properties.children_named("property"))
+            .any(|property| {
+                property.attribute("name") == Some("solid")
+                    && property.attribute("value") == Some("true")
+            });
+        let has_shapes = tile
+            .child("objectgroup")
+            .is_some_and(|group| group.child("object").is_some());
+        result.set_solid(id, solid_property || has_shapes);
+    }
+    Ok(result)
+}
+
+fn tmj_layers(tilemap: &mut Tilemap, parent: &Json, offset: [f32; 2]) -> Result<(), String> {
+    for layer in parent.get("layers").map_or(&[][..], Json::as_array) {
+        let offset = [
+            offset[0] + optional(layer, "offsetx"),
+            offset[1] - optional(layer, "offsety"),
+        ];
+        match layer.get("type").and_then(Json::as_str) {
+            Some("group") => tmj_layers(tilemap, layer, offset)?,
+            Some("tilelayer") => {
+                let mut tiles =
+                    TileLayer::new(layer.get("name").and_then(Json::as_str).unwrap_or_default());
+                tiles.visible = layer.get("visible").and_then(Json::as_bool) != Some(false);
+                tiles.opacity = layer.get("opacity").and_then(Json::as_f64).unwrap_or(1.0) as f32;
+                tiles.offset = offset;
+                let encoding = layer.get("encoding").and_then(Json::as_str);
+                if let Some(compression) = layer
+                    .get("compression")
+                    .and_then(Json::as_str)
+                    .filter(|compression| !compression.is_empty())
+                {
+                    return Err(format!(
+                        "{} compressed layers aren't supported",
+                        compression
+                    ));
+                }
+                let data = |holder: &Json| match (holder.get("data"), encoding) {
+                    (Some(Json::String(text)), Some("base64")) => base64_ids(text),
+                    (Some(Json::Array(ids)), _) => Ok(ids
+                        .iter()
+                        .map(|id| id.as_f64().unwrap_or_default() as u32)
+                        .collect()),
+                    _ => Err("layer has no tile data".to_owned()),
+                };
+                match layer.get("chunks") {
+                    Some(chunks) => {
+                        for chunk in chunks.as_array() {
+                            let origin = [number(chunk, "x")?, number(chunk, "y")?];
+                            place(&mut tiles, &data(chunk)?, origin, number(chunk, "width")?);
+                        }
+                    }
+                    None => place(&mut tiles, &data(layer)?, [0.0; 2], number(layer, "width")?),
+                }
+                tilemap.add_layer(tiles);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn tmx_layers(tilemap: &mut Tilemap, parent: &Element, offset: [f32; 2]) -> Result<(), String> {
+    for layer in &parent.children {
+        let offset = [
+            offset[0] + attribute(layer, "offsetx").unwrap_or_default(),
+            offset[1] - attribute(layer, "offsety").unwrap_or_default(),
+        ];
+        match layer.name.as_str() {
+            "group" => tmx_layers(tilemap, layer, offset)?,
+            "layer" => {
+                let mut tiles = TileLayer::new(layer.attribute("name").unwrap_or_default());
+                tiles.visible = layer.attribute("visible") != Some("0");
+                tiles.opacity = attribute(layer, "opacity").unwrap_or(1.0);
+                tiles.offset = offset;
+                let data = layer.child("data").ok_or("layer has no tile data")?;
+                if let Some(compression) = data.attribute("compression") {
+                    return Err(format!(
+                        "{} compressed layers aren't supported",
+                        compression
+                    ));
+                }
+                let ids = |holder: &Element| match data.attribute("encoding") {
+                    Some("csv") => holder
+                        .text
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|id| !id.is_empty())
+                        .map(|id| id.parse().map_err(|_| format!("{} isn't a tile id", id)))
+                        .collect(),
+                    Some("base64") => base64_ids(&holder.text),
+                    _ => Ok(holder
+                        .children_named("tile")
+                        .map(|tile| id_attribute(tile, "gid").unwrap_or_default())
+                        .collect()),
+                };
+                if data.child("chunk").is_some() {
+                    for chunk in data.children_named("chunk") {
+                        let origin = [attribute(chunk, "x")?, attribute(chunk, "y")?];
+                        place(&mut tiles, &ids(chunk)?, origin, attribute(chunk, "width")?);
+                    }
+                } else {
+                    place(
+                        &mut tiles,
+                        &ids(data)?,
+                        [0.0; 2],
+                        attribute(layer, "width")?,
+                    );
+                }
+                tilemap.add_layer(tiles);
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn place(layer: &mut TileLayer, ids: &[u32], origin: [f32; 2], width: f32) {
+    let width = (width as usize).max(1);
+    for (index, gid) in ids.iter().enumerate() {
+        let at = [
+            origin[0] as i32 + (index % width) as i32,
+            origin[1] as i32 + (index / width) as i32,
+        ];
+        let id = gid & ID_MASK;
+        if id != 0 {
+            let flip = Flip {
+                x: gid & FLIP_X != 0,
+                y: gid & FLIP_Y != 0,
+                diagonal: gid & FLIP_DIAGONAL != 0,
+            };
+            layer.set(at, Some(Tile { id, flip }));
+        }
+    }
+}
+
+fn base64_ids(text: &str) -> Result<Vec<u32>, String> {
+    Ok(decode_base64(text)?
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect())
+}
+
+fn number(value: &Json, key: &str) -> Result<f32, String> {
+    value
+        .get(key)
+        .and_then(Json::as_f64)
+        .map(|number| number as f32)
+        .ok_or_else(|| format!("missing {}", key))
+}
+
+fn optional(value: &Json, key: &str) -> f32 {
+    number(value, key).unwrap_or_default()
+}
+
+// Tile ids keep the flip flags in their top bits, past what an f32 holds exactly.
+fn id(value: &Json, key: &str) -> Result<u32, String> {
+    let number = value
+        .get(key)
+        .and_then(Json::as_f64)
+        .ok_or_else(|| format!("missing {}", key))?;
+    if number.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(&number) {
+        return Err(format!("{} {} isn't a tile id", key, number));
+    }
+    Ok(number as u32)
+}
+
+fn attribute(element: &Element, name: &str) -> Result<f32, String> {
+    let value = element
+        .attribute(name)
+        .ok_or_else(|| format!("<{}> has no {}", element.name, name))?;
+    value
+        .parse()
+        .map_err(|_| format!("{} {} is not a number", name, value))
+}
+
+fn id_attribute(element: &Element, name: &str) -> Result<u32, String> {
+    let value = element
+        .attribute(name)
+        .ok_or_else(|| format!("<{}> has no {}", element.name, name))?;
+    value
+        .parse()
+        .map_err(|_| format!("{} {} isn't a tile id", name, value))
+}
+
+// `relative` from the directory `base` is in, as a virtual path.
+fn resolve(base: &str, relative: &str) -> String {
+    let mut parts: Vec<&str> = base.split('/').collect();
+    parts.pop();
+    for part in relative.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
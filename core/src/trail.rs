@@ -0,0 +1,377 @@
+//! Trails and ribbons, sword swipes and projectile trails. A `Trail` records where the thing it
+//! follows has been and builds a ribbon through those points that faces the camera, tapers and
+//! fades towards the tail and stretches its texture along its length.
+//!
+//! Points are recorded and aged on the sim side, the mesh is rebuilt from them whenever it's
+//! asked for, since it depends on where the camera is. The renderer has no pass for it yet, the
+//! meshes are what it would upload as a dynamic vertex buffer each frame.
+
+use std::{collections::VecDeque, fmt, time::Duration};
+
+use crate::{
+    engine::{Engine, Plugin, Resources},
+    metrics,
+    sim::Time,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrailStyle {
+    // How long a point stays before it's dropped.
+    pub lifetime: Duration,
+    // Width at the head and at the tail, the ribbon tapers between them.
+    pub width: [f32; 2],
+    // RGBA at the head and at the tail.
+    pub color: [[f32; 4]; 2],
+    // A new point is only recorded once the head moved this far.
+    pub min_distance: f32,
+    pub max_points: usize,
+    pub texture: Option<String>,
+    // World units one repeat of the texture covers, None stretches it over the whole ribbon.
+    pub texture_length: Option<f32>,
+}
+
+impl Default for TrailStyle {
+    fn default() -> Self {
+        Self {
+            lifetime: Duration::from_millis(500),
+            width: [0.5, 0.0],
+            color: [[1.0; 4], [1.0, 1.0, 1.0, 0.0]],
+            min_distance: 0.05,
+            max_points: 64,
+            texture: None,
+            texture_length: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrailVertex {
+    pub position: [f32; 3],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrailMesh {
+    // Two per point, left then right, head first.
+    pub vertices: Vec<TrailVertex>,
+    pub indices: Vec<u32>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Point {
+    position: [f32; 3],
+    age: Duration,
+}
+
+pub struct Trail {
+    pub style: TrailStyle,
+    // Head first.
+    points: VecDeque<Point>,
+    // Where the head is now, even if it hasn't moved far enough for a new point.
+    head: Option<[f32; 3]>,
+    emitting: bool,
+}
+
+impl Trail {
+    pub fn new(style: TrailStyle) -> Self {
+        Self {
+            style,
+            points: VecDeque::new(),
+            head: None,
+            emitting: true,
+        }
+    }
+
+    // Moves the head, recording a point if it went far enough.
+    pub fn follow(&mut self, position: [f32; 3]) {
+        if !self.emitting {
+            return;
+        }
+        self.head = Some(position);
+        let moved = self
+            .points
+            .front()
+            .map_or(f32::INFINITY, |last| length(sub(position, last.position)));
+        if moved >= self.style.min_distance {
+            self.points.push_front(Point {
+                position,
+                age: Duration::ZERO,
+            });
+            self.points.truncate(self.style.max_points.max(2));
+        }
+    }
+
+    // Stops recording, what's there fades out over its lifetime.
+    pub fn stop(&mut self) {
+        self.emitting = false;
+        self.head = None;
+    }
+
+    pub fn start(&mut self) {
+        self.emitting = true;
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.head = None;
+    }
+
+    pub fn is_emitting(&self) -> bool {
+        self.emitting
+    }
+
+    // Nothing left to draw.
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn advance(&mut self, delta: Duration) {
+        for point in &mut self.points {
+            point.age += delta;
+        }
+        while self
+            .points
+            .back()
+            .is_some_and(|point| point.age >= self.style.lifetime)
+        {
+            self.points.pop_back();
+        }
+    }
+
+    // The ribbon as seen from `camera`, empty if there are fewer than two points.
+    pub fn mesh(&self, camera: [f32; 3]) -> TrailMesh {
+        let mut positions: Vec<([f32; 3], Duration)> = self
+            .points
+            .iter()
+            .map(|point| (point.position, point.age))
+            .collect();
+        // The head follows the entity between recorded points.
+        if let Some(head) = self
+            .head
+            .filter(|head| positions.first().is_some_and(|(first, _)| *first != *head))
+        {
+            positions.insert(0, (head, Duration::ZERO));
+        }
+        let mut mesh = TrailMesh::default();
+        if positions.len() < 2 {
+            return mesh;
+        }
+
+        let lengths: Vec<f32> = std::iter::once(0.0)
+            .chain(positions.windows(2).scan(0.0, |total, pair| {
+                *total += length(sub(pair[1].0, pair[0].0));
+                Some(*total)
+            }))
+            .collect();
+        let total = lengths[lengths.len() - 1].max(f32::EPSILON);
+        let lifetime = self.style.lifetime.as_secs_f32().max(f32::EPSILON);
+        let last = positions.len() - 1;
+        for (index, (position, age)) in positions.iter().enumerate() {
+            // Along the ribbon, from the neighbours on either side.
+            let tangent = sub(
+                positions[index.saturating_sub(1)].0,
+                positions[(index + 1).min(last)].0,
+            );
+            let side = normalize(cross(tangent, sub(camera, *position)));
+            // Tapers along the length and with age, so a trail that stopped moving still shrinks
+            // away instead of hanging there at full width.
+            let t = (age.as_secs_f32() / lifetime)
+                .max(lengths[index] / total * (1.0 - age.as_secs_f32() / lifetime))
+                .clamp(0.0, 1.0);
+            let half_width = lerp(self.style.width[0], self.style.width[1], t) * 0.5;
+            let color = std::array::from_fn(|channel| {
+                lerp(
+                    self.style.color[0][channel],
+                    self.style.color[1][channel],
+                    t,
+                )
+            });
+            let u = match self.style.texture_length {
+                Some(repeat) if repeat > 0.0 => lengths[index] / repeat,
+                _ => lengths[index] / total,
+            };
+            for (sign, v) in [(-1.0, 0.0), (1.0, 1.0)] {
+                mesh.vertices.push(TrailVertex {
+                    position: std::array::from_fn(|axis| {
+                        position[axis] + side[axis] * half_width * sign
+                    }),
+                    uv: [u, v],
+                    color,
+                });
+            }
+        }
+        for segment in 0..last as u32 {
+            let [a, b, c, d] = [0, 1, 2, 3].map(|corner| segment * 2 + corner);
+            mesh.indices.extend([a, b, c, b, d, c]);
+        }
+        mesh
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn length(a: [f32; 3]) -> f32 {
+    (a[0] * a[0] + a[1] * a[1] + a[2] * a[2]).sqrt()
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let length = length(a);
+    if length <= f32::EPSILON {
+        return [0.0; 3];
+    }
+    a.map(|c| c / length)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TrailId(u64);
+
+impl fmt::Display for TrailId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Default)]
+pub struct Trails {
+    next_id: u64,
+    // The flag removes the trail once it stopped and faded out.
+    trails: Vec<(TrailId, Trail, bool)>,
+}
+
+impl Trails {
+    pub fn add(&mut self, trail: Trail) -> TrailId {
+        let id = TrailId(self.next_id);
+        self.next_id += 1;
+        self.trails.push((id, trail, false));
+        id
+    }
+
+    // Stops the trail and removes it once it faded, e.g. when a projectile hits.
+    pub fn release(&mut self, id: TrailId) {
+        if let Some((_, trail, release)) = self.trails.iter_mut().find(|(trail, ..)| *trail == id) {
+            trail.stop();
+            *release = true;
+        }
+    }
+
+    pub fn remove(&mut self, id: TrailId) -> Option<Trail> {
+        let index = self.trails.iter().position(|(trail, ..)| *trail == id)?;
+        Some(self.trails.remove(index).1)
+    }
+
+    pub fn get(&self, id: TrailId) -> Option<&Trail> {
+        self.trails
+            .iter()
+            .find(|(trail, ..)| *trail == id)
+            .map(|(_, trail, _)| trail)
+    }
+
+    pub fn get_mut(&mut self, id: TrailId) -> Option<&mut Trail> {
+        self.trails
+            .iter_mut()
+            .find(|(trail, ..)| *trail == id)
+            .map(|(_, trail, _)| trail)
+    }
+
+    pub fn len(&self) -> usize {
+        self.trails.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trails.is_empty()
+    }
+
+    pub fn advance(&mut self, delta: Duration) {
+        for (_, trail, _) in &mut self.trails {
+            trail.advance(delta);
+        }
+        self.trails
+            .retain(|(_, trail, release)| !*release || !trail.is_empty());
+    }
+
+    // Every trail with something to draw.
+    pub fn meshes(&self, camera: [f32; 3]) -> Vec<(TrailId, TrailMesh)> {
+        self.trails
+            .iter()
+            .map(|(id, trail, _)| (*id, trail.mesh(camera)))
+            .filter(|(_, mesh)| !mesh.vertices.is_empty())
+            .collect()
+    }
+}
+
+pub struct TrailPlugin;
+
+impl Plugin for TrailPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(Trails::default())
+            .add_system(|resources: &mut Resources| {
+                let delta = resources
+                    .get::<Time>()
+                    .map_or(Duration::ZERO, |time| time.delta);
+                if let Some(trails) = resources.get_mut::<Trails>() {
+                    trails.advance(delta);
+                    metrics::set_gauge("trails", trails.len() as f64);
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_tapers_and_fades_out() {
+        let mut trails = Trails::default();
+        let id = trails.add(Trail::new(TrailStyle {
+            lifetime: Duration::from_secs(1),
+            width: [2.0, 0.0],
+            min_distance: 1.0,
+            ..TrailStyle::default()
+        }));
+        let tick = Duration::from_millis(100);
+        let trail = trails.get_mut(id).unwrap();
+        for x in [0.0, 0.5, 1.0, 2.0] {
+            trail.follow([x, 0.0, 0.0]);
+            trail.advance(tick);
+        }
+        // Half a unit wasn't far enough for a point, the head still draws there.
+        trail.follow([2.5, 0.0, 0.0]);
+
+        // Seen from above the ribbon lies flat in x/z.
+        let mesh = trail.mesh([0.0, 10.0, 0.0]);
+        assert_eq!(mesh.vertices.len(), 4 * 2);
+        assert_eq!(mesh.indices.len(), 3 * 6);
+        let head = &mesh.vertices[..2];
+        assert_eq!(head[0].position, [2.5, 0.0, -1.0]);
+        assert_eq!(head[1].position, [2.5, 0.0, 1.0]);
+        assert_eq!(head[0].uv, [0.0, 0.0]);
+        assert_eq!(mesh.vertices[7].uv, [1.0, 1.0]);
+        // The oldest point is narrower and more transparent than the head.
+        let tail = &mesh.vertices[6..];
+        assert!(tail[1].position[2] < 1.0 && tail[1].position[2] > 0.0);
+        assert!(tail[1].color[3] < head[1].color[3]);
+
+        trails.release(id);
+        trails.advance(Duration::from_millis(500));
+        assert_eq!(trails.meshes([0.0; 3]).len(), 1);
+        trails.advance(Duration::from_millis(500));
+        assert!(trails.is_empty());
+    }
+}
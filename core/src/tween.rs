@@ -0,0 +1,435 @@
+//! Tweens move a value from one state to another over time along an easing curve: UI slides and
+//! fades, hit flashes, camera moves, anything that should feel smooth rather than snap.
+//!
+//! A tween writes its value into a resource every tick through a setter, e.g. a material
+//! parameter on the game's renderer resource or a widget property on its UI resource, so nothing
+//! here has to know about either. Tweens follow `sim::Time`, they stop with the game while it's
+//! paused unless they `ignore_pause`.
+//!
+//! ```ignore
+//! let flash = Tween::new(1.0, 0.0, Duration::from_millis(300)).ease(Ease::QuadOut);
+//! tweens.animate(flash, |hud: &mut Hud, value| hud.damage_flash = value);
+//! ```
+
+use std::{any::Any, collections::VecDeque, f32::consts::PI, fmt, time::Duration};
+
+use crate::{
+    editor::Transform,
+    engine::{Engine, Plugin, Resources},
+    metrics,
+    sim::Time,
+};
+
+pub trait Lerp: Copy + Send + 'static {
+    fn lerp(self, to: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        self + (to - self) * t
+    }
+}
+
+impl Lerp for f64 {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        self + (to - self) * f64::from(t)
+    }
+}
+
+impl<const N: usize> Lerp for [f32; N] {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        std::array::from_fn(|i| self[i].lerp(to[i], t))
+    }
+}
+
+impl Lerp for Transform {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        // Normalized lerp the short way around, close enough to slerp for animation.
+        let dot: f32 = (0..4).map(|i| self.rotation[i] * to.rotation[i]).sum();
+        let to_rotation = if dot < 0.0 {
+            to.rotation.map(|v| -v)
+        } else {
+            to.rotation
+        };
+        let rotation = self.rotation.lerp(to_rotation, t);
+        let length = rotation.iter().map(|v| v * v).sum::<f32>().sqrt();
+        Transform {
+            translation: self.translation.lerp(to.translation, t),
+            rotation: if length > 0.0 {
+                rotation.map(|v| v / length)
+            } else {
+                self.rotation
+            },
+            scale: self.scale.lerp(to.scale, t),
+        }
+    }
+}
+
+// Easing curves, see easings.net for what they look like. All go from 0 at t = 0 to 1 at t = 1,
+// Back and Elastic overshoot in between.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Ease {
+    #[default]
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    SineIn,
+    SineOut,
+    SineInOut,
+    ExpoIn,
+    ExpoOut,
+    BackIn,
+    BackOut,
+    ElasticOut,
+    BounceOut,
+}
+
+impl Ease {
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        // How far Back pulls past the ends.
+        const BACK: f32 = 1.70158;
+        match self {
+            Ease::Linear => t,
+            Ease::QuadIn => t * t,
+            Ease::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Ease::QuadInOut if t < 0.5 => 2.0 * t * t,
+            Ease::QuadInOut => 1.0 - (-2.0 * t + 2.0).powi(2) / 2.0,
+            Ease::CubicIn => t * t * t,
+            Ease::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Ease::CubicInOut if t < 0.5 => 4.0 * t * t * t,
+            Ease::CubicInOut => 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0,
+            Ease::SineIn => 1.0 - (t * PI / 2.0).cos(),
+            Ease::SineOut => (t * PI / 2.0).sin(),
+            Ease::SineInOut => -((PI * t).cos() - 1.0) / 2.0,
+            Ease::ExpoIn if t == 0.0 => 0.0,
+            Ease::ExpoIn => 2f32.powf(10.0 * t - 10.0),
+            Ease::ExpoOut if t == 1.0 => 1.0,
+            Ease::ExpoOut => 1.0 - 2f32.powf(-10.0 * t),
+            Ease::BackIn => (BACK + 1.0) * t * t * t - BACK * t * t,
+            Ease::BackOut => 1.0 + (BACK + 1.0) * (t - 1.0).powi(3) + BACK * (t - 1.0).powi(2),
+            Ease::ElasticOut if t == 0.0 || t == 1.0 => t,
+            Ease::ElasticOut => {
+                2f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * (2.0 * PI / 3.0)).sin() + 1.0
+            }
+            Ease::BounceOut => {
+                const N: f32 = 7.5625;
+                const D: f32 = 2.75;
+                if t < 1.0 / D {
+                    N * t * t
+                } else if t < 2.0 / D {
+                    let t = t - 1.5 / D;
+                    N * t * t + 0.75
+                } else if t < 2.5 / D {
+                    let t = t - 2.25 / D;
+                    N * t * t + 0.9375
+                } else {
+                    let t = t - 2.625 / D;
+                    N * t * t + 0.984375
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Repeat {
+    #[default]
+    Once,
+    // Plays this many times in total.
+    Times(u32),
+    Forever,
+}
+
+// When and how a tween plays, independent of what it animates.
+#[derive(Clone, Copy, Debug, Default)]
+struct Timing {
+    duration: Duration,
+    delay: Duration,
+    ease: Ease,
+    repeat: Repeat,
+    yoyo: bool,
+    ignore_pause: bool,
+}
+
+impl Timing {
+    fn plays(&self) -> u32 {
+        match self.repeat {
+            Repeat::Once => 1,
+            Repeat::Times(plays) => plays.max(1),
+            Repeat::Forever => u32::MAX,
+        }
+    }
+
+    // The eased progress of play `play` at `t` into it.
+    fn progress(&self, play: u32, t: f32) -> f32 {
+        let t = if self.yoyo && play % 2 == 1 {
+            1.0 - t
+        } else {
+            t
+        };
+        self.ease.apply(t)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Tween<V> {
+    from: V,
+    to: V,
+    timing: Timing,
+}
+
+impl<V: Lerp> Tween<V> {
+    pub fn new(from: V, to: V, duration: Duration) -> Self {
+        Self {
+            from,
+            to,
+            timing: Timing {
+                duration,
+                ..Timing::default()
+            },
+        }
+    }
+
+    pub fn ease(mut self, ease: Ease) -> Self {
+        self.timing.ease = ease;
+        self
+    }
+
+    // Holds the starting value this long before moving.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.timing.delay = delay;
+        self
+    }
+
+    pub fn repeat(mut self, repeat: Repeat) -> Self {
+        self.timing.repeat = repeat;
+        self
+    }
+
+    // Every other play runs backwards, e.g. for pulsing.
+    pub fn yoyo(mut self) -> Self {
+        self.timing.yoyo = true;
+        self
+    }
+
+    // Keeps playing while the sim is paused, for menus and the like.
+    pub fn ignore_pause(mut self) -> Self {
+        self.timing.ignore_pause = true;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TweenId(u64);
+
+impl fmt::Display for TweenId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TweenEvent {
+    // A repeating tween started its next play.
+    Looped(TweenId),
+    // Reached its final value and was removed.
+    Completed(TweenId),
+}
+
+// Writes the value for an eased progress, false if its resource is gone.
+type Setter = Box<dyn FnMut(&mut Resources, f32) -> bool + Send>;
+
+struct Running {
+    id: TweenId,
+    timing: Timing,
+    elapsed: Duration,
+    // Which play it's in, to notice loops.
+    play: u32,
+    apply: Setter,
+}
+
+// The running tweens, advanced by `TweenPlugin`'s system every tick.
+#[derive(Default)]
+pub struct Tweens {
+    next_id: u64,
+    running: Vec<Running>,
+    events: VecDeque<TweenEvent>,
+}
+
+impl Tweens {
+    // Plays `tween`, writing its value into resource `R` with `set` every tick until it completes
+    // or is cancelled.
+    pub fn animate<R, V, F>(&mut self, tween: Tween<V>, mut set: F) -> TweenId
+    where
+        R: Any + Send,
+        V: Lerp,
+        F: FnMut(&mut R, V) + Send + 'static,
+    {
+        self.next_id += 1;
+        let id = TweenId(self.next_id);
+        let (from, to) = (tween.from, tween.to);
+        self.running.push(Running {
+            id,
+            timing: tween.timing,
+            elapsed: Duration::ZERO,
+            play: 0,
+            apply: Box::new(move |resources, progress| match resources.get_mut::<R>() {
+                Some(target) => {
+                    set(target, from.lerp(to, progress));
+                    true
+                }
+                None => false,
+            }),
+        });
+        id
+    }
+
+    // Stops a tween where it is, without a completion event.
+    pub fn cancel(&mut self, id: TweenId) -> bool {
+        let count = self.running.len();
+        self.running.retain(|tween| tween.id != id);
+        self.running.len() != count
+    }
+
+    pub fn is_running(&self, id: TweenId) -> bool {
+        self.running.iter().any(|tween| tween.id == id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.running.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    // Events from the last advance, they are dropped on the next.
+    pub fn poll_event(&mut self) -> Option<TweenEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn advance(&mut self, time: &Time, resources: &mut Resources) {
+        self.events.clear();
+        let events = &mut self.events;
+        self.running.retain_mut(|tween| {
+            let timing = tween.timing;
+            tween.elapsed += if timing.ignore_pause {
+                time.real_delta
+            } else {
+                time.delta
+            };
+            let t = tween.elapsed.saturating_sub(timing.delay);
+            let plays = timing.plays();
+            let play = match timing.duration.as_secs_f64() {
+                duration if duration > 0.0 => t.as_secs_f64() / duration,
+                _ => f64::INFINITY,
+            };
+            let (progress, finished) = if play >= f64::from(plays) {
+                (timing.progress(plays - 1, 1.0), true)
+            } else {
+                let (index, local) = (play as u32, play.fract() as f32);
+                if index > tween.play {
+                    tween.play = index;
+                    events.push_back(TweenEvent::Looped(tween.id));
+                }
+                (timing.progress(index, local), false)
+            };
+            if !(tween.apply)(resources, progress) {
+                warn!("Cancelled tween {}, its target resource is gone", tween.id);
+                return false;
+            }
+            if finished {
+                events.push_back(TweenEvent::Completed(tween.id));
+            }
+            !finished
+        });
+    }
+}
+
+pub struct TweenPlugin;
+
+impl Plugin for TweenPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(Tweens::default())
+            .add_system(|resources: &mut Resources| {
+                let time = resources.get::<Time>().copied().unwrap_or_default();
+                // Out of the resources while it runs, so the setters can borrow them.
+                let Some(mut tweens) = resources.remove::<Tweens>() else {
+                    return;
+                };
+                tweens.advance(&time, resources);
+                metrics::set_gauge("tweens_running", tweens.len() as f64);
+                resources.insert(tweens);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Hud {
+        alpha: f32,
+        offset: [f32; 2],
+    }
+
+    #[test]
+    fn plays_with_delay_loops_and_completion() {
+        let mut resources = Resources::default();
+        resources.insert(Hud::default());
+        let mut tweens = Tweens::default();
+        let tick = Duration::from_millis(100);
+        let time = Time {
+            delta: tick,
+            real_delta: tick,
+            ..Time::default()
+        };
+        let fade = tweens.animate(
+            Tween::new(0.0, 1.0, Duration::from_millis(400)).delay(Duration::from_millis(200)),
+            |hud: &mut Hud, alpha| hud.alpha = alpha,
+        );
+        let pulse = tweens.animate(
+            Tween::new([0.0, 0.0], [10.0, -10.0], Duration::from_millis(200))
+                .ease(Ease::QuadInOut)
+                .repeat(Repeat::Times(2))
+                .yoyo(),
+            |hud: &mut Hud, offset| hud.offset = offset,
+        );
+        let hud = |resources: &Resources| {
+            let hud = resources.get::<Hud>().unwrap();
+            (hud.alpha, hud.offset)
+        };
+
+        tweens.advance(&time, &mut resources);
+        assert_eq!(hud(&resources), (0.0, [5.0, -5.0]));
+        tweens.advance(&time, &mut resources);
+        assert_eq!(tweens.poll_event(), Some(TweenEvent::Looped(pulse)));
+        assert_eq!(hud(&resources), (0.0, [10.0, -10.0]));
+        tweens.advance(&time, &mut resources);
+        assert_eq!(hud(&resources).0, 0.25);
+        tweens.advance(&time, &mut resources);
+        // The second play ran backwards.
+        assert_eq!(hud(&resources), (0.5, [0.0, 0.0]));
+        assert_eq!(tweens.poll_event(), Some(TweenEvent::Completed(pulse)));
+
+        // Paused sims hold tweens.
+        let paused = Time {
+            delta: Duration::ZERO,
+            ..time
+        };
+        tweens.advance(&paused, &mut resources);
+        assert_eq!(hud(&resources).0, 0.5);
+        tweens.advance(&time, &mut resources);
+        tweens.advance(&time, &mut resources);
+        assert_eq!(hud(&resources).0, 1.0);
+        assert_eq!(tweens.poll_event(), Some(TweenEvent::Completed(fade)));
+        assert!(tweens.is_empty());
+    }
+}
@@ -0,0 +1,714 @@
+// Flex and grid layout for styled widgets. A widget with a `Style` has its rect computed from
+// its parent's each time the tree or the viewport changes, widgets without one keep the rect
+// they were given and lay out their styled children inside it.
+//
+// Lengths in `Px` are logical pixels, scaled by the window's DPI factor, so a 200 px wide panel
+// is 400 window pixels on a 2x display.
+
+use super::{Rect, Ui, Widget, WidgetId};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Val {
+    // Sized to the content, or stretched where the parent's alignment says so.
+    #[default]
+    Auto,
+    Px(f32),
+    // Of the parent's content box.
+    Percent(f32),
+}
+
+impl Val {
+    // In window pixels, None for `Auto`.
+    fn resolve(self, parent: f32, scale: f32) -> Option<f32> {
+        match self {
+            Val::Auto => None,
+            Val::Px(px) => Some(px * scale),
+            Val::Percent(percent) => Some(parent * percent / 100.0),
+        }
+    }
+}
+
+// In logical pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Edges {
+    pub left: f32,
+    pub right: f32,
+    pub top: f32,
+    pub bottom: f32,
+}
+
+impl Edges {
+    pub const fn all(value: f32) -> Self {
+        Self::symmetric(value, value)
+    }
+
+    pub const fn symmetric(horizontal: f32, vertical: f32) -> Self {
+        Self {
+            left: horizontal,
+            right: horizontal,
+            top: vertical,
+            bottom: vertical,
+        }
+    }
+
+    fn scaled(self, scale: f32) -> Self {
+        Self {
+            left: self.left * scale,
+            right: self.right * scale,
+            top: self.top * scale,
+            bottom: self.bottom * scale,
+        }
+    }
+
+    // Start and end along x, or along y.
+    fn axis(self, x: bool) -> [f32; 2] {
+        if x {
+            [self.left, self.right]
+        } else {
+            [self.top, self.bottom]
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Direction {
+    Row,
+    #[default]
+    Column,
+}
+
+// Where children go along the direction when they don't fill it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Justify {
+    #[default]
+    Start,
+    Center,
+    End,
+    SpaceBetween,
+    SpaceAround,
+}
+
+// Where children go across the direction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlignItems {
+    Start,
+    Center,
+    End,
+    // Children without a size across fill it.
+    #[default]
+    Stretch,
+}
+
+// How a widget arranges its children in flow. Gaps are in logical pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    Flex {
+        direction: Direction,
+        justify: Justify,
+        align: AlignItems,
+        gap: f32,
+    },
+    // Rows of equal width cells, each row as tall as its tallest child.
+    Grid {
+        columns: u16,
+        gap: [f32; 2],
+    },
+}
+
+impl Layout {
+    pub fn row(gap: f32) -> Self {
+        Layout::Flex {
+            direction: Direction::Row,
+            justify: Justify::Start,
+            align: AlignItems::Stretch,
+            gap,
+        }
+    }
+
+    pub fn column(gap: f32) -> Self {
+        Layout::Flex {
+            direction: Direction::Column,
+            justify: Justify::Start,
+            align: AlignItems::Stretch,
+            gap,
+        }
+    }
+
+    pub fn grid(columns: u16, gap: f32) -> Self {
+        Layout::Grid {
+            columns,
+            gap: [gap; 2],
+        }
+    }
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout::column(0.0)
+    }
+}
+
+// Fractions of the parent's content box, (0, 0) its top left. Where `min` and `max` differ on
+// an axis the widget stretches between them less its margins, where they're equal the widget
+// keeps its size and the same point of it sits on the anchor, so (1, 1) pins its bottom right
+// corner. `offset` moves it from there in logical pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Anchors {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+    pub offset: [f32; 2],
+}
+
+impl Anchors {
+    pub const TOP_LEFT: Anchors = Anchors::point(0.0, 0.0);
+    pub const TOP: Anchors = Anchors::point(0.5, 0.0);
+    pub const TOP_RIGHT: Anchors = Anchors::point(1.0, 0.0);
+    pub const LEFT: Anchors = Anchors::point(0.0, 0.5);
+    pub const CENTER: Anchors = Anchors::point(0.5, 0.5);
+    pub const RIGHT: Anchors = Anchors::point(1.0, 0.5);
+    pub const BOTTOM_LEFT: Anchors = Anchors::point(0.0, 1.0);
+    pub const BOTTOM: Anchors = Anchors::point(0.5, 1.0);
+    pub const BOTTOM_RIGHT: Anchors = Anchors::point(1.0, 1.0);
+    pub const FILL: Anchors = Anchors {
+        min: [0.0; 2],
+        max: [1.0; 2],
+        offset: [0.0; 2],
+    };
+
+    pub const fn point(x: f32, y: f32) -> Self {
+        Self {
+            min: [x, y],
+            max: [x, y],
+            offset: [0.0; 2],
+        }
+    }
+
+    pub const fn with_offset(mut self, x: f32, y: f32) -> Self {
+        self.offset = [x, y];
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Position {
+    // Placed by the parent's layout.
+    #[default]
+    Flow,
+    // Placed against the parent, outside its layout, e.g. a HUD corner or a centered dialog.
+    Anchored(Anchors),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Style {
+    pub position: Position,
+    pub width: Val,
+    pub height: Val,
+    pub margin: Edges,
+    pub padding: Edges,
+    // Share of the free space along the parent's direction, 0 keeps the size.
+    pub grow: f32,
+    pub layout: Layout,
+}
+
+impl Style {
+    pub fn anchored(anchors: Anchors) -> Self {
+        Self {
+            position: Position::Anchored(anchors),
+            ..Self::default()
+        }
+    }
+
+    pub fn size(mut self, width: Val, height: Val) -> Self {
+        (self.width, self.height) = (width, height);
+        self
+    }
+
+    pub fn margin(mut self, margin: Edges) -> Self {
+        self.margin = margin;
+        self
+    }
+
+    pub fn padding(mut self, padding: Edges) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn grow(mut self, grow: f32) -> Self {
+        self.grow = grow;
+        self
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+}
+
+// Measures text in window pixels at a size, for widgets sized to their text. Without a font
+// system the default guesses from the character count.
+pub type TextMeasure = Box<dyn Fn(&str, f32) -> [f32; 2] + Send>;
+
+// A flow child's size and margins along and across its parent's direction.
+struct FlexItem {
+    main: f32,
+    cross: f32,
+    main_margin: [f32; 2],
+    cross_margin: [f32; 2],
+    grow: f32,
+}
+
+fn estimate_text(text: &str, size: f32) -> [f32; 2] {
+    [text.chars().count() as f32 * size * 0.55, size * 1.25]
+}
+
+impl Ui {
+    // The viewport is an implicit column that stretches its children.
+    pub(super) fn run_layout(&mut self) {
+        let [width, height] = self.viewport.size;
+        let roots = self.roots.clone();
+        self.layout_children(
+            &roots,
+            Rect::new(0.0, 0.0, width, height),
+            Layout::default(),
+            [0.0; 2],
+        );
+    }
+
+    // Places `children` in `content`, a window rect, storing their rects relative to `origin`,
+    // where the parent is, then lays out their own children.
+    fn layout_children(
+        &mut self,
+        children: &[WidgetId],
+        content: Rect,
+        layout: Layout,
+        origin: [f32; 2],
+    ) {
+        let mut flow = Vec::new();
+        for &child in children {
+            let Some(style) = self
+                .node(child)
+                .filter(|node| node.visible)
+                .and_then(|node| node.style)
+            else {
+                continue;
+            };
+            match style.position {
+                Position::Flow => flow.push(child),
+                Position::Anchored(anchors) => {
+                    let rect = self.anchored(child, &style, anchors, content);
+                    self.place(child, rect, origin);
+                }
+            }
+        }
+        match layout {
+            Layout::Flex {
+                direction,
+                justify,
+                align,
+                gap,
+            } => self.flex(&flow, content, direction, justify, align, gap, origin),
+            Layout::Grid { columns, gap } => self.grid(&flow, content, columns, gap, origin),
+        }
+
+        let scale = self.viewport.scale_factor;
+        for &child in children {
+            let Some(node) = self.node(child).filter(|node| node.visible) else {
+                continue;
+            };
+            let rect = node.rect.offset(origin);
+            let style = node.style.unwrap_or_default();
+            let padding = style.padding.scaled(scale);
+            let content = Rect::new(
+                rect.x + padding.left,
+                rect.y + padding.top,
+                (rect.width - padding.left - padding.right).max(0.0),
+                (rect.height - padding.top - padding.bottom).max(0.0),
+            );
+            let grandchildren = node.children.clone();
+            self.layout_children(&grandchildren, content, style.layout, [rect.x, rect.y]);
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn flex(
+        &mut self,
+        items: &[WidgetId],
+        content: Rect,
+        direction: Direction,
+        justify: Justify,
+        align: AlignItems,
+        gap: f32,
+        origin: [f32; 2],
+    ) {
+        if items.is_empty() {
+            return;
+        }
+        let scale = self.viewport.scale_factor;
+        let row = direction == Direction::Row;
+        let gap = gap * scale;
+        let (main_start, main_length) = if row {
+            (content.x, content.width)
+        } else {
+            (content.y, content.height)
+        };
+        let (cross_start, cross_length) = if row {
+            (content.y, content.height)
+        } else {
+            (content.x, content.width)
+        };
+
+        let mut sized: Vec<FlexItem> = items
+            .iter()
+            .map(|&id| {
+                let style = self.node(id).and_then(|node| node.style).unwrap();
+                let margin = style.margin.scaled(scale);
+                let (main_margin, cross_margin) = (margin.axis(row), margin.axis(!row));
+                let natural = self.content_size(id);
+                let (main_val, cross_val) = if row {
+                    (style.width, style.height)
+                } else {
+                    (style.height, style.width)
+                };
+                let main = main_val
+                    .resolve(main_length, scale)
+                    .unwrap_or(natural[!row as usize]);
+                let cross = cross_val.resolve(cross_length, scale).unwrap_or(
+                    if align == AlignItems::Stretch {
+                        (cross_length - cross_margin[0] - cross_margin[1]).max(0.0)
+                    } else {
+                        natural[row as usize]
+                    },
+                );
+                FlexItem {
+                    main,
+                    cross,
+                    main_margin,
+                    cross_margin,
+                    grow: style.grow.max(0.0),
+                }
+            })
+            .collect();
+
+        let used: f32 = sized
+            .iter()
+            .map(|item| item.main + item.main_margin[0] + item.main_margin[1])
+            .sum::<f32>()
+            + gap * (items.len() - 1) as f32;
+        let mut free = main_length - used;
+        let grow: f32 = sized.iter().map(|item| item.grow).sum();
+        if free > 0.0 && grow > 0.0 {
+            for item in &mut sized {
+                item.main += free * item.grow / grow;
+            }
+            free = 0.0;
+        } else if free < 0.0 {
+            // Too big, everything shrinks in proportion to its size.
+            let total: f32 = sized.iter().map(|item| item.main).sum();
+            if total > 0.0 {
+                for item in &mut sized {
+                    item.main = (item.main + free * item.main / total).max(0.0);
+                }
+            }
+            free = 0.0;
+        }
+        let count = items.len() as f32;
+        let (lead, between) = match justify {
+            Justify::Start => (0.0, 0.0),
+            Justify::Center => (free / 2.0, 0.0),
+            Justify::End => (free, 0.0),
+            Justify::SpaceBetween if items.len() > 1 => (0.0, free / (count - 1.0)),
+            Justify::SpaceBetween => (0.0, 0.0),
+            Justify::SpaceAround => (free / count / 2.0, free / count),
+        };
+
+        let mut cursor = main_start + lead;
+        for (&id, item) in items.iter().zip(sized) {
+            let FlexItem {
+                main,
+                cross,
+                main_margin,
+                cross_margin,
+                ..
+            } = item;
+            cursor += main_margin[0];
+            let across = match align {
+                AlignItems::Start | AlignItems::Stretch => cross_start + cross_margin[0],
+                AlignItems::Center => {
+                    cross_start
+                        + (cross_length - cross - cross_margin[0] - cross_margin[1]) / 2.0
+                        + cross_margin[0]
+                }
+                AlignItems::End => cross_start + cross_length - cross_margin[1] - cross,
+            };
+            let rect = if row {
+                Rect::new(cursor, across, main, cross)
+            } else {
+                Rect::new(across, cursor, cross, main)
+            };
+            self.place(id, rect, origin);
+            cursor += main + main_margin[1] + gap + between;
+        }
+    }
+
+    fn grid(
+        &mut self,
+        items: &[WidgetId],
+        content: Rect,
+        columns: u16,
+        gap: [f32; 2],
+        origin: [f32; 2],
+    ) {
+        let scale = self.viewport.scale_factor;
+        let columns = columns.max(1) as usize;
+        let gap = [gap[0] * scale, gap[1] * scale];
+        let cell = ((content.width - gap[0] * (columns - 1) as f32) / columns as f32).max(0.0);
+        let mut y = content.y;
+        for row in items.chunks(columns) {
+            let mut row_height: f32 = 0.0;
+            let mut placed = Vec::with_capacity(row.len());
+            for (column, &id) in row.iter().enumerate() {
+                let style = self.node(id).and_then(|node| node.style).unwrap();
+                let margin = style.margin.scaled(scale);
+                let natural = self.content_size(id);
+                let width = style
+                    .width
+                    .resolve(cell, scale)
+                    .unwrap_or((cell - margin.left - margin.right).max(0.0));
+                let height = style
+                    .height
+                    .resolve(content.height, scale)
+                    .unwrap_or(natural[1]);
+                row_height = row_height.max(height + margin.top + margin.bottom);
+                let x = content.x + column as f32 * (cell + gap[0]) + margin.left;
+                placed.push((id, Rect::new(x, y + margin.top, width, height)));
+            }
+            for (id, rect) in placed {
+                self.place(id, rect, origin);
+            }
+            y += row_height + gap[1];
+        }
+    }
+
+    fn anchored(&self, id: WidgetId, style: &Style, anchors: Anchors, parent: Rect) -> Rect {
+        let scale = self.viewport.scale_factor;
+        let margin = style.margin.scaled(scale);
+        let natural = self.content_size(id);
+        let axis = |x: bool| {
+            let i = !x as usize;
+            let (start, length, val) = if x {
+                (parent.x, parent.width, style.width)
+            } else {
+                (parent.y, parent.height, style.height)
+            };
+            let [margin_start, margin_end] = margin.axis(x);
+            let offset = anchors.offset[i] * scale;
+            let (min, max) = (anchors.min[i], anchors.max[i]);
+            if max > min {
+                let from = start + length * min + margin_start;
+                let to = start + length * max - margin_end;
+                (from + offset, (to - from).max(0.0))
+            } else {
+                let size = val.resolve(length, scale).unwrap_or(natural[i]);
+                let at = start + length * min - size * min + margin_start * (1.0 - min)
+                    - margin_end * min;
+                (at + offset, size)
+            }
+        };
+        let ((x, width), (y, height)) = (axis(true), axis(false));
+        Rect::new(x, y, width, height)
+    }
+
+    // The size a styled widget wants in window pixels, from its set size, else its text or
+    // its flow children, plus padding.
+    fn content_size(&self, id: WidgetId) -> [f32; 2] {
+        let Some(node) = self.node(id) else {
+            return [0.0; 2];
+        };
+        let scale = self.viewport.scale_factor;
+        let style = node.style.unwrap_or_default();
+        let text_size = self.theme.text_size * scale;
+        let own = match &node.widget {
+            Widget::Label { text, .. } | Widget::Button { text, .. } => {
+                self.measure_text(text, text_size)
+            }
+            Widget::Slider { .. } => [self.theme.slider_handle_width * scale * 10.0, text_size],
+            Widget::Panel { .. } | Widget::Image { .. } => [0.0; 2],
+        };
+
+        let flow: Vec<[f32; 2]> = node
+            .children
+            .iter()
+            .filter_map(|&child| {
+                let child_node = self.node(child).filter(|node| node.visible)?;
+                let child_style = child_node.style?;
+                if child_style.position != Position::Flow {
+                    return None;
+                }
+                let size = self.content_size(child);
+                let margin = child_style.margin.scaled(scale);
+                Some([
+                    size[0] + margin.left + margin.right,
+                    size[1] + margin.top + margin.bottom,
+                ])
+            })
+            .collect();
+        let children = match style.layout {
+            _ if flow.is_empty() => [0.0; 2],
+            Layout::Flex { direction, gap, .. } => {
+                let main = (direction != Direction::Row) as usize;
+                let mut size = [0.0; 2];
+                size[main] = flow.iter().map(|size| size[main]).sum::<f32>()
+                    + gap * scale * (flow.len() - 1) as f32;
+                size[1 - main] = flow.iter().map(|size| size[1 - main]).fold(0.0, f32::max);
+                size
+            }
+            Layout::Grid { columns, gap } => {
+                let columns = (columns.max(1) as usize).min(flow.len());
+                let rows = flow.len().div_ceil(columns);
+                let cell = flow.iter().map(|size| size[0]).fold(0.0, f32::max);
+                let height: f32 = flow
+                    .chunks(columns)
+                    .map(|row| row.iter().map(|size| size[1]).fold(0.0, f32::max))
+                    .sum();
+                [
+                    cell * columns as f32 + gap[0] * scale * (columns - 1) as f32,
+                    height + gap[1] * scale * (rows - 1) as f32,
+                ]
+            }
+        };
+
+        let padding = style.padding.scaled(scale);
+        let fixed = |val: Val| match val {
+            Val::Px(px) => Some(px * scale),
+            _ => None,
+        };
+        [
+            fixed(style.width).unwrap_or(own[0].max(children[0]) + padding.left + padding.right),
+            fixed(style.height).unwrap_or(own[1].max(children[1]) + padding.top + padding.bottom),
+        ]
+    }
+
+    fn measure_text(&self, text: &str, size: f32) -> [f32; 2] {
+        match &self.measure {
+            Some(measure) => measure(text, size),
+            None => estimate_text(text, size),
+        }
+    }
+
+    // Stores a window rect relative to the parent at `origin`.
+    fn place(&mut self, id: WidgetId, rect: Rect, origin: [f32; 2]) {
+        if let Some(node) = self.node_mut(id) {
+            node.rect = Rect::new(
+                rect.x - origin[0],
+                rect.y - origin[1],
+                rect.width,
+                rect.height,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lays_out_rows_grids_and_anchors_at_any_scale() {
+        let mut ui = Ui::default();
+        ui.set_viewport([800.0, 600.0], 1.0);
+        let bar = ui
+            .add_styled(
+                None,
+                Widget::panel([0.0; 4]),
+                Style::default()
+                    .size(Val::Auto, Val::Px(40.0))
+                    .padding(Edges::all(5.0))
+                    .layout(Layout::row(10.0)),
+            )
+            .unwrap();
+        let fixed = ui
+            .add_styled(
+                Some(bar),
+                Widget::button("Menu"),
+                Style::default().size(Val::Px(100.0), Val::Auto),
+            )
+            .unwrap();
+        let fill = ui
+            .add_styled(
+                Some(bar),
+                Widget::label("Score"),
+                Style::default().grow(1.0),
+            )
+            .unwrap();
+        let corner = ui
+            .add_styled(
+                None,
+                Widget::panel([0.0; 4]),
+                Style::anchored(Anchors::BOTTOM_RIGHT.with_offset(-10.0, -10.0))
+                    .size(Val::Px(200.0), Val::Px(100.0))
+                    .layout(Layout::grid(2, 0.0)),
+            )
+            .unwrap();
+        let cells: Vec<WidgetId> = (0..3)
+            .map(|_| {
+                ui.add_styled(
+                    Some(corner),
+                    Widget::button(""),
+                    Style::default().size(Val::Auto, Val::Px(20.0)),
+                )
+                .unwrap()
+            })
+            .collect();
+        ui.update_layout();
+
+        assert_eq!(ui.rect(bar), Some(Rect::new(0.0, 0.0, 800.0, 40.0)));
+        assert_eq!(ui.rect(fixed), Some(Rect::new(5.0, 5.0, 100.0, 30.0)));
+        assert_eq!(ui.rect(fill), Some(Rect::new(115.0, 5.0, 680.0, 30.0)));
+        assert_eq!(ui.rect(corner), Some(Rect::new(590.0, 490.0, 200.0, 100.0)));
+        assert_eq!(ui.rect(cells[1]), Some(Rect::new(100.0, 0.0, 100.0, 20.0)));
+        assert_eq!(
+            ui.absolute_rect(cells[2]),
+            Some(Rect::new(590.0, 510.0, 100.0, 20.0))
+        );
+
+        // A window resized and moved to a 2x display.
+        ui.set_viewport([1920.0, 1080.0], 2.0);
+        ui.update_layout();
+        assert_eq!(ui.rect(bar), Some(Rect::new(0.0, 0.0, 1920.0, 80.0)));
+        assert_eq!(ui.rect(fill), Some(Rect::new(230.0, 10.0, 1680.0, 60.0)));
+        assert_eq!(
+            ui.rect(corner),
+            Some(Rect::new(1500.0, 860.0, 400.0, 200.0))
+        );
+
+        // Hiding a widget gives its space to the others.
+        ui.set_visible(fixed, false);
+        ui.update_layout();
+        assert_eq!(ui.rect(fill), Some(Rect::new(10.0, 10.0, 1900.0, 60.0)));
+    }
+
+    #[test]
+    fn styled_children_follow_a_placed_parent() {
+        let mut ui = Ui::default();
+        let window = ui
+            .add(
+                None,
+                Widget::panel([0.0; 4]),
+                Rect::new(0.0, 0.0, 100.0, 100.0),
+            )
+            .unwrap();
+        let close = ui
+            .add_styled(
+                Some(window),
+                Widget::button("x"),
+                Style::anchored(Anchors::TOP_RIGHT).size(Val::Px(10.0), Val::Px(10.0)),
+            )
+            .unwrap();
+        ui.update_layout();
+        assert_eq!(ui.rect(close), Some(Rect::new(90.0, 0.0, 10.0, 10.0)));
+
+        ui.set_rect(window, Rect::new(0.0, 0.0, 300.0, 100.0));
+        ui.update_layout();
+        assert_eq!(ui.rect(close), Some(Rect::new(290.0, 0.0, 10.0, 10.0)));
+    }
+}
@@ -0,0 +1,644 @@
+//! Retained-mode UI for menus and HUDs: a tree of panels, labels, buttons, sliders and images
+//! that persists between frames, with hover, press and keyboard focus handled here. The game
+//! builds the tree once, reacts to `UiEvent`s and changes widgets as its state changes.
+//!
+//! Widget rects are relative to their parent, in window pixels. Widgets given a `Style` are
+//! placed by a flex and grid layout pass instead, in DPI-scaled units with anchors, which reruns
+//! when the tree, a widget or the window size changes so the UI follows the resolution. Input is
+//! routed to the topmost widget under the cursor, and clicks over any widget, panels included,
+//! don't reach the game.
+//! Tab and Shift+Tab move focus between buttons and sliders, Enter or Space presses the focused
+//! button and the arrow keys move the focused slider.
+//!
+//! The renderer has no sprite or text pass yet, so like the debug HUD the UI produces what to
+//! draw, `Ui::draw` gives quads, text and images back to front for the game's overlay.
+
+mod layout;
+mod widget;
+
+use std::collections::VecDeque;
+
+use winit::{event::MouseButton, event::TouchPhase, keyboard::KeyCode};
+
+pub use layout::{
+    AlignItems, Anchors, Direction, Edges, Justify, Layout, Position, Style, TextMeasure, Val,
+};
+pub use widget::{Align, Color, DrawCommand, Rect, Theme, Widget};
+
+use widget::WidgetState;
+
+use crate::{
+    engine::{Engine, Plugin, Resources},
+    identifier::{GenerationalAllocator, GenerationalId},
+    input::InputEvent,
+    sim::Viewport,
+};
+
+pub type WidgetId = GenerationalId;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UiEvent {
+    Clicked(WidgetId),
+    // A slider moved, by the user rather than the game.
+    Changed { id: WidgetId, value: f32 },
+}
+
+struct Node {
+    widget: Widget,
+    rect: Rect,
+    parent: Option<WidgetId>,
+    children: Vec<WidgetId>,
+    visible: bool,
+    // None keeps `rect` as set.
+    style: Option<Style>,
+}
+
+#[derive(Default)]
+pub struct Ui {
+    allocator: GenerationalAllocator,
+    // By id index.
+    nodes: Vec<Option<Node>>,
+    roots: Vec<WidgetId>,
+    pub theme: Theme,
+    cursor: [f32; 2],
+    hovered: Option<WidgetId>,
+    pressed: Option<WidgetId>,
+    focused: Option<WidgetId>,
+    shift: bool,
+    events: VecDeque<UiEvent>,
+    viewport: Viewport,
+    measure: Option<TextMeasure>,
+    layout_dirty: bool,
+}
+
+impl Ui {
+    // Adds a widget on top of its siblings, `rect` is relative to the parent. Fails if the parent
+    // was removed.
+    pub fn add(
+        &mut self,
+        parent: Option<WidgetId>,
+        widget: Widget,
+        rect: Rect,
+    ) -> Result<WidgetId, String> {
+        if let Some(parent) = parent.filter(|parent| !self.contains(*parent)) {
+            return Err(format!("no widget {:?} to add to", parent));
+        }
+        let id = self.allocator.allocate().ok_or("out of widget ids")?;
+        match parent {
+            Some(parent) => self.node_mut(parent).unwrap().children.push(id),
+            None => self.roots.push(id),
+        }
+        let index = id.index() as usize;
+        if self.nodes.len() <= index {
+            self.nodes.resize_with(index + 1, || None);
+        }
+        self.nodes[index] = Some(Node {
+            widget,
+            rect,
+            parent,
+            children: Vec::new(),
+            visible: true,
+            style: None,
+        });
+        self.layout_dirty = true;
+        Ok(id)
+    }
+
+    // Adds a widget placed by the layout pass.
+    pub fn add_styled(
+        &mut self,
+        parent: Option<WidgetId>,
+        widget: Widget,
+        style: Style,
+    ) -> Result<WidgetId, String> {
+        let id = self.add(parent, widget, Rect::default())?;
+        self.set_style(id, Some(style));
+        Ok(id)
+    }
+
+    // Removes a widget with its children.
+    pub fn remove(&mut self, id: WidgetId) -> bool {
+        let Some(node) = self.node(id) else {
+            return false;
+        };
+        match node.parent {
+            Some(parent) => {
+                if let Some(parent) = self.node_mut(parent) {
+                    parent.children.retain(|child| *child != id);
+                }
+            }
+            None => self.roots.retain(|root| *root != id),
+        }
+        self.layout_dirty = true;
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some(node) = self.nodes[id.index() as usize].take() {
+                stack.extend(node.children);
+            }
+            self.allocator.free(id);
+            for state in [&mut self.hovered, &mut self.pressed, &mut self.focused] {
+                if *state == Some(id) {
+                    *state = None;
+                }
+            }
+        }
+        true
+    }
+
+    pub fn clear(&mut self) {
+        for root in self.roots.clone() {
+            self.remove(root);
+        }
+    }
+
+    pub fn contains(&self, id: WidgetId) -> bool {
+        self.node(id).is_some()
+    }
+
+    pub fn widget(&self, id: WidgetId) -> Option<&Widget> {
+        self.node(id).map(|node| &node.widget)
+    }
+
+    // Relayouts on the next update, the widget's text may have changed its size.
+    pub fn widget_mut(&mut self, id: WidgetId) -> Option<&mut Widget> {
+        if !self.contains(id) {
+            return None;
+        }
+        self.layout_dirty = true;
+        self.node_mut(id).map(|node| &mut node.widget)
+    }
+
+    pub fn children(&self, id: WidgetId) -> &[WidgetId] {
+        self.node(id).map_or(&[], |node| &node.children)
+    }
+
+    pub fn rect(&self, id: WidgetId) -> Option<Rect> {
+        self.node(id).map(|node| node.rect)
+    }
+
+    // For widgets without a style, the layout pass overwrites the others. Styled children are
+    // laid out in it again.
+    pub fn set_rect(&mut self, id: WidgetId, rect: Rect) {
+        if let Some(node) = self.node_mut(id) {
+            node.rect = rect;
+            self.layout_dirty = true;
+        }
+    }
+
+    // The rect in window pixels.
+    pub fn absolute_rect(&self, id: WidgetId) -> Option<Rect> {
+        let node = self.node(id)?;
+        let mut rect = node.rect;
+        let mut parent = node.parent;
+        while let Some(node) = parent.and_then(|parent| self.node(parent)) {
+            rect = rect.offset([node.rect.x, node.rect.y]);
+            parent = node.parent;
+        }
+        Some(rect)
+    }
+
+    pub fn style(&self, id: WidgetId) -> Option<&Style> {
+        self.node(id)?.style.as_ref()
+    }
+
+    // None leaves the widget where the layout last put it.
+    pub fn set_style(&mut self, id: WidgetId, style: Option<Style>) {
+        if let Some(node) = self.node_mut(id) {
+            node.style = style;
+            self.layout_dirty = true;
+        }
+    }
+
+    // The window size in pixels and its DPI scale, styled widgets are laid out against it.
+    pub fn set_viewport(&mut self, size: [f32; 2], scale_factor: f32) {
+        let viewport = Viewport { size, scale_factor };
+        if self.viewport != viewport {
+            self.viewport = viewport;
+            self.layout_dirty = true;
+        }
+    }
+
+    // For widgets sized to their text, which otherwise is estimated from its length.
+    pub fn set_text_measure(&mut self, measure: TextMeasure) {
+        self.measure = Some(measure);
+        self.layout_dirty = true;
+    }
+
+    // Places styled widgets if anything changed since the last layout, `UiPlugin` calls it
+    // every tick.
+    pub fn update_layout(&mut self) {
+        if self.layout_dirty {
+            self.layout_dirty = false;
+            self.run_layout();
+        }
+    }
+
+    // Hidden widgets and their children aren't drawn and don't take input or space.
+    pub fn set_visible(&mut self, id: WidgetId, visible: bool) {
+        if let Some(node) = self.node_mut(id) {
+            node.visible = visible;
+            self.layout_dirty = true;
+        }
+        if !visible {
+            let hidden: Vec<WidgetId> = [self.hovered, self.pressed, self.focused]
+                .into_iter()
+                .flatten()
+                .filter(|state| !self.is_shown(*state))
+                .collect();
+            for state in [&mut self.hovered, &mut self.pressed, &mut self.focused] {
+                if state.is_some_and(|state| hidden.contains(&state)) {
+                    *state = None;
+                }
+            }
+        }
+    }
+
+    pub fn hovered(&self) -> Option<WidgetId> {
+        self.hovered
+    }
+
+    pub fn focused(&self) -> Option<WidgetId> {
+        self.focused
+    }
+
+    // Only buttons and sliders take focus.
+    pub fn focus(&mut self, id: Option<WidgetId>) {
+        self.focused = id.filter(|id| self.is_interactive(*id) && self.is_shown(*id));
+    }
+
+    pub fn poll_event(&mut self) -> Option<UiEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn draw(&self) -> Vec<DrawCommand> {
+        let mut out = Vec::new();
+        for (id, rect) in self.shown() {
+            let state = WidgetState {
+                hovered: self.hovered == Some(id),
+                pressed: self.pressed == Some(id),
+                focused: self.focused == Some(id),
+            };
+            self.node(id).unwrap().widget.draw(
+                rect,
+                state,
+                &self.theme,
+                self.viewport.scale_factor,
+                &mut out,
+            );
+        }
+        out
+    }
+
+    // Returns true if the UI took the event.
+    pub fn handle_input(&mut self, event: &InputEvent) -> bool {
+        match event {
+            InputEvent::CursorMoved { x, y } => self.move_cursor([*x as f32, *y as f32]),
+            InputEvent::MouseButton {
+                button: MouseButton::Left,
+                pressed: true,
+            } => self.press(),
+            InputEvent::MouseButton {
+                button: MouseButton::Left,
+                pressed: false,
+            } => self.release(),
+            InputEvent::Touch { phase, x, y, .. } => {
+                let over = self.move_cursor([*x as f32, *y as f32]);
+                match phase {
+                    TouchPhase::Started => self.press(),
+                    TouchPhase::Moved => over || self.pressed.is_some(),
+                    TouchPhase::Ended => {
+                        let released = self.release();
+                        self.hovered = None;
+                        released
+                    }
+                    TouchPhase::Cancelled => {
+                        (self.pressed, self.hovered) = (None, None);
+                        over
+                    }
+                }
+            }
+            InputEvent::Key {
+                code: KeyCode::ShiftLeft | KeyCode::ShiftRight,
+                pressed,
+            } => {
+                self.shift = *pressed;
+                false
+            }
+            InputEvent::Key {
+                code,
+                pressed: true,
+            } => self.key(*code),
+            _ => false,
+        }
+    }
+
+    fn node(&self, id: WidgetId) -> Option<&Node> {
+        if !self.allocator.is_alive(id) {
+            return None;
+        }
+        self.nodes.get(id.index() as usize)?.as_ref()
+    }
+
+    fn node_mut(&mut self, id: WidgetId) -> Option<&mut Node> {
+        if !self.allocator.is_alive(id) {
+            return None;
+        }
+        self.nodes.get_mut(id.index() as usize)?.as_mut()
+    }
+
+    fn is_interactive(&self, id: WidgetId) -> bool {
+        self.widget(id).is_some_and(Widget::is_interactive)
+    }
+
+    fn is_shown(&self, id: WidgetId) -> bool {
+        let mut current = Some(id);
+        while let Some(node) = current.and_then(|id| self.node(id)) {
+            if !node.visible {
+                return false;
+            }
+            current = node.parent;
+        }
+        current.is_none()
+    }
+
+    // Visible widgets with their window rects, back to front.
+    fn shown(&self) -> Vec<(WidgetId, Rect)> {
+        let mut shown = Vec::new();
+        let mut stack: Vec<(WidgetId, [f32; 2])> = self
+            .roots
+            .iter()
+            .rev()
+            .map(|root| (*root, [0.0; 2]))
+            .collect();
+        while let Some((id, origin)) = stack.pop() {
+            let Some(node) = self.node(id).filter(|node| node.visible) else {
+                continue;
+            };
+            let rect = node.rect.offset(origin);
+            shown.push((id, rect));
+            stack.extend(
+                node.children
+                    .iter()
+                    .rev()
+                    .map(|child| (*child, [rect.x, rect.y])),
+            );
+        }
+        shown
+    }
+
+    // The topmost widget under `point`.
+    fn hit(&self, point: [f32; 2]) -> Option<WidgetId> {
+        self.shown()
+            .into_iter()
+            .rev()
+            .find(|(_, rect)| rect.contains(point))
+            .map(|(id, _)| id)
+    }
+
+    fn move_cursor(&mut self, cursor: [f32; 2]) -> bool {
+        self.cursor = cursor;
+        if let Some(pressed) = self.pressed {
+            self.drag_slider(pressed);
+        }
+        let hit = self.hit(cursor);
+        self.hovered = hit.filter(|id| self.is_interactive(*id));
+        hit.is_some()
+    }
+
+    fn press(&mut self) -> bool {
+        let Some(hit) = self.hit(self.cursor) else {
+            self.focused = None;
+            return false;
+        };
+        if self.is_interactive(hit) {
+            self.pressed = Some(hit);
+            self.focused = Some(hit);
+            self.drag_slider(hit);
+        } else {
+            self.focused = None;
+        }
+        true
+    }
+
+    fn release(&mut self) -> bool {
+        let Some(pressed) = self.pressed.take() else {
+            return false;
+        };
+        let clicked = matches!(
+            self.widget(pressed),
+            Some(Widget::Button { enabled: true, .. })
+        );
+        if clicked && self.hit(self.cursor) == Some(pressed) {
+            self.events.push_back(UiEvent::Clicked(pressed));
+        }
+        true
+    }
+
+    fn drag_slider(&mut self, id: WidgetId) {
+        let Some(rect) = self.absolute_rect(id) else {
+            return;
+        };
+        let handle = self.theme.slider_handle_width * self.viewport.scale_factor;
+        let travel = (rect.width - handle).max(1.0);
+        let fraction = ((self.cursor[0] - rect.x - handle / 2.0) / travel).clamp(0.0, 1.0);
+        if let Some(Widget::Slider { min, max, .. }) = self.widget(id) {
+            let value = min + (max - min) * fraction;
+            self.set_slider(id, value);
+        }
+    }
+
+    // Moves a slider as the user, with a `Changed` event if it moved.
+    fn set_slider(&mut self, id: WidgetId, new_value: f32) {
+        let Some(Widget::Slider {
+            value, min, max, ..
+        }) = self.widget_mut(id)
+        else {
+            return;
+        };
+        let new_value = widget::clamp_slider(new_value, *min, *max);
+        if *value != new_value {
+            *value = new_value;
+            self.events.push_back(UiEvent::Changed {
+                id,
+                value: new_value,
+            });
+        }
+    }
+
+    fn key(&mut self, code: KeyCode) -> bool {
+        if code == KeyCode::Tab {
+            return self.cycle_focus(if self.shift { -1 } else { 1 });
+        }
+        let Some(focused) = self.focused else {
+            return false;
+        };
+        match (code, self.widget(focused)) {
+            (
+                KeyCode::Enter | KeyCode::NumpadEnter | KeyCode::Space,
+                Some(Widget::Button { .. }),
+            ) => {
+                self.events.push_back(UiEvent::Clicked(focused));
+            }
+            (
+                KeyCode::ArrowLeft | KeyCode::ArrowRight,
+                Some(Widget::Slider { value, step, .. }),
+            ) => {
+                let step = if code == KeyCode::ArrowLeft {
+                    -step
+                } else {
+                    *step
+                };
+                self.set_slider(focused, value + step);
+            }
+            (KeyCode::Escape, _) => self.focused = None,
+            _ => return false,
+        }
+        true
+    }
+
+    fn cycle_focus(&mut self, step: isize) -> bool {
+        let focusable: Vec<WidgetId> = self
+            .shown()
+            .into_iter()
+            .map(|(id, _)| id)
+            .filter(|id| self.is_interactive(*id))
+            .collect();
+        if focusable.is_empty() {
+            return false;
+        }
+        let len = focusable.len() as isize;
+        let next = match self
+            .focused
+            .and_then(|focused| focusable.iter().position(|id| *id == focused))
+        {
+            Some(index) => (index as isize + step).rem_euclid(len),
+            None if step < 0 => len - 1,
+            None => 0,
+        };
+        self.focused = Some(focusable[next as usize]);
+        true
+    }
+}
+
+pub struct UiPlugin;
+
+impl Plugin for UiPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(Ui::default())
+            .add_system(|resources: &mut Resources| {
+                let viewport = resources.get::<Viewport>().copied().unwrap_or_default();
+                if let Some(ui) = resources.get_mut::<Ui>() {
+                    ui.set_viewport(viewport.size, viewport.scale_factor);
+                    ui.update_layout();
+                }
+            })
+            .add_input_handler(|resources: &mut Resources, event: &InputEvent| {
+                resources
+                    .get_mut::<Ui>()
+                    .is_some_and(|ui| ui.handle_input(event))
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(x: f64, y: f64) -> InputEvent {
+        InputEvent::CursorMoved { x, y }
+    }
+
+    fn left(pressed: bool) -> InputEvent {
+        InputEvent::MouseButton {
+            button: MouseButton::Left,
+            pressed,
+        }
+    }
+
+    #[test]
+    fn routes_clicks_drags_and_focus() {
+        let mut ui = Ui::default();
+        let menu = ui
+            .add(
+                None,
+                Widget::panel([0.0; 4]),
+                Rect::new(100.0, 100.0, 200.0, 200.0),
+            )
+            .unwrap();
+        let play = ui
+            .add(
+                Some(menu),
+                Widget::button("Play"),
+                Rect::new(10.0, 10.0, 180.0, 40.0),
+            )
+            .unwrap();
+        let volume = ui
+            .add(
+                Some(menu),
+                Widget::slider(0.5, 0.0, 1.0),
+                Rect::new(10.0, 60.0, 110.0, 20.0),
+            )
+            .unwrap();
+
+        // Over the panel the UI takes the click even though nothing reacts to it.
+        assert!(ui.handle_input(&cursor(150.0, 250.0)));
+        assert_eq!(ui.hovered(), None);
+        assert!(!ui.handle_input(&cursor(10.0, 10.0)));
+
+        ui.handle_input(&cursor(150.0, 120.0));
+        assert_eq!(ui.hovered(), Some(play));
+        ui.handle_input(&left(true));
+        ui.handle_input(&left(false));
+        assert_eq!(ui.poll_event(), Some(UiEvent::Clicked(play)));
+        assert_eq!(ui.focused(), Some(play));
+
+        // Dragging the slider to its right end, the handle is 10 pixels wide.
+        ui.handle_input(&cursor(115.0, 165.0));
+        ui.handle_input(&left(true));
+        ui.handle_input(&cursor(400.0, 300.0));
+        ui.handle_input(&left(false));
+        assert_eq!(
+            ui.poll_event(),
+            Some(UiEvent::Changed {
+                id: volume,
+                value: 0.0
+            })
+        );
+        assert_eq!(
+            ui.poll_event(),
+            Some(UiEvent::Changed {
+                id: volume,
+                value: 1.0
+            })
+        );
+        assert_eq!(ui.poll_event(), None);
+
+        let tab = InputEvent::Key {
+            code: KeyCode::Tab,
+            pressed: true,
+        };
+        ui.handle_input(&tab);
+        assert_eq!(ui.focused(), Some(play));
+        ui.set_visible(menu, false);
+        assert_eq!(ui.focused(), None);
+        assert!(ui.draw().is_empty());
+        ui.set_visible(menu, true);
+        // Panel, the button's quad and text, the slider's three quads.
+        assert_eq!(ui.draw().len(), 6);
+
+        ui.remove(menu);
+        assert!(!ui.contains(play));
+        assert!(ui.draw().is_empty());
+        assert!(ui
+            .add(Some(menu), Widget::label(""), Rect::default())
+            .is_err());
+
+        // Bad bounds don't panic.
+        assert_eq!(Widget::slider(5.0, 1.0, 0.0), Widget::slider(1.0, 0.0, 1.0));
+        let Widget::Slider { value, .. } = Widget::slider(0.5, f32::NAN, 0.25) else {
+            unreachable!();
+        };
+        assert_eq!(value, 0.25);
+    }
+}
@@ -0,0 +1,278 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn contains(&self, [x, y]: [f32; 2]) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+
+    pub fn offset(&self, [x, y]: [f32; 2]) -> Rect {
+        Rect::new(self.x + x, self.y + y, self.width, self.height)
+    }
+}
+
+pub type Color = [f32; 4];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Start,
+    Center,
+    End,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Widget {
+    // A background and a container for other widgets.
+    Panel {
+        color: Color,
+    },
+    Label {
+        text: String,
+        align: Align,
+    },
+    Button {
+        text: String,
+        enabled: bool,
+    },
+    Slider {
+        value: f32,
+        min: f32,
+        max: f32,
+        // What the arrow keys move it by.
+        step: f32,
+    },
+    // An image asset by path, stretched over the widget.
+    Image {
+        path: String,
+        tint: Color,
+    },
+}
+
+// `f32::clamp` without the panics, for slider fields that can be set to anything: NaN bounds are
+// ignored, reversed ones swapped, and a NaN value goes to the bottom.
+pub(super) fn clamp_slider(value: f32, min: f32, max: f32) -> f32 {
+    value.max(min.min(max)).min(max.max(min))
+}
+
+impl Widget {
+    pub fn panel(color: Color) -> Self {
+        Widget::Panel { color }
+    }
+
+    pub fn label(text: impl Into<String>) -> Self {
+        Widget::Label {
+            text: text.into(),
+            align: Align::Start,
+        }
+    }
+
+    pub fn button(text: impl Into<String>) -> Self {
+        Widget::Button {
+            text: text.into(),
+            enabled: true,
+        }
+    }
+
+    // Bounds can come in either order, a NaN one is replaced by the other.
+    pub fn slider(value: f32, min: f32, max: f32) -> Self {
+        let (min, max) = (min.min(max), max.max(min));
+        Widget::Slider {
+            value: clamp_slider(value, min, max),
+            min,
+            max,
+            step: (max - min) / 10.0,
+        }
+    }
+
+    pub fn image(path: impl Into<String>) -> Self {
+        Widget::Image {
+            path: path.into(),
+            tint: [1.0; 4],
+        }
+    }
+
+    // Whether it takes focus and reacts to clicks and keys.
+    pub fn is_interactive(&self) -> bool {
+        match self {
+            Widget::Button { enabled, .. } => *enabled,
+            Widget::Slider { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+// Colors and sizes the widgets are drawn with, sizes in logical pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub text: Color,
+    pub text_disabled: Color,
+    pub text_size: f32,
+    pub button: Color,
+    pub button_hovered: Color,
+    pub button_pressed: Color,
+    pub focus_outline: Color,
+    pub slider_track: Color,
+    pub slider_fill: Color,
+    pub slider_handle: Color,
+    pub slider_handle_width: f32,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            text: [0.95, 0.95, 0.95, 1.0],
+            text_disabled: [0.5, 0.5, 0.5, 1.0],
+            text_size: 18.0,
+            button: [0.2, 0.22, 0.26, 1.0],
+            button_hovered: [0.28, 0.31, 0.36, 1.0],
+            button_pressed: [0.14, 0.15, 0.18, 1.0],
+            focus_outline: [0.4, 0.65, 1.0, 1.0],
+            slider_track: [0.15, 0.16, 0.19, 1.0],
+            slider_fill: [0.3, 0.5, 0.85, 1.0],
+            slider_handle: [0.9, 0.9, 0.92, 1.0],
+            slider_handle_width: 10.0,
+        }
+    }
+}
+
+// What the renderer draws for the UI, back to front. Rects are in window pixels.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawCommand {
+    Quad {
+        rect: Rect,
+        color: Color,
+    },
+    // A one pixel frame just inside `rect`, e.g. the focus outline.
+    Outline {
+        rect: Rect,
+        color: Color,
+    },
+    // Text vertically centered in `rect`.
+    Text {
+        rect: Rect,
+        text: String,
+        size: f32,
+        color: Color,
+        align: Align,
+    },
+    Image {
+        rect: Rect,
+        path: String,
+        tint: Color,
+    },
+}
+
+// How a widget is drawn right now.
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct WidgetState {
+    pub hovered: bool,
+    pub pressed: bool,
+    pub focused: bool,
+}
+
+impl Widget {
+    pub(super) fn draw(
+        &self,
+        rect: Rect,
+        state: WidgetState,
+        theme: &Theme,
+        scale: f32,
+        out: &mut Vec<DrawCommand>,
+    ) {
+        let text_size = theme.text_size * scale;
+        match self {
+            Widget::Panel { color } => out.push(DrawCommand::Quad {
+                rect,
+                color: *color,
+            }),
+            Widget::Label { text, align } => out.push(DrawCommand::Text {
+                rect,
+                text: text.clone(),
+                size: text_size,
+                color: theme.text,
+                align: *align,
+            }),
+            Widget::Button { text, enabled } => {
+                let color = match (*enabled, state.pressed, state.hovered) {
+                    (true, true, _) => theme.button_pressed,
+                    (true, false, true) => theme.button_hovered,
+                    _ => theme.button,
+                };
+                out.push(DrawCommand::Quad { rect, color });
+                out.push(DrawCommand::Text {
+                    rect,
+                    text: text.clone(),
+                    size: text_size,
+                    color: if *enabled {
+                        theme.text
+                    } else {
+                        theme.text_disabled
+                    },
+                    align: Align::Center,
+                });
+            }
+            Widget::Slider {
+                value, min, max, ..
+            } => {
+                let fraction = slider_fraction(*value, *min, *max);
+                let handle = theme.slider_handle_width * scale;
+                let travel = (rect.width - handle).max(0.0);
+                out.push(DrawCommand::Quad {
+                    rect,
+                    color: theme.slider_track,
+                });
+                out.push(DrawCommand::Quad {
+                    rect: Rect::new(
+                        rect.x,
+                        rect.y,
+                        travel * fraction + handle / 2.0,
+                        rect.height,
+                    ),
+                    color: theme.slider_fill,
+                });
+                out.push(DrawCommand::Quad {
+                    rect: Rect::new(rect.x + travel * fraction, rect.y, handle, rect.height),
+                    color: if state.hovered || state.pressed {
+                        theme.text
+                    } else {
+                        theme.slider_handle
+                    },
+                });
+            }
+            Widget::Image { path, tint } => out.push(DrawCommand::Image {
+                rect,
+                path: path.clone(),
+                tint: *tint,
+            }),
+        }
+        if state.focused {
+            out.push(DrawCommand::Outline {
+                rect,
+                color: theme.focus_outline,
+            });
+        }
+    }
+}
+
+fn slider_fraction(value: f32, min: f32, max: f32) -> f32 {
+    if max > min {
+        ((value - min) / (max - min)).clamp(0.0, 1.0)
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,441 @@
+//! Vertex animation textures, animations baked into textures ahead of time with a texel per
+//! vertex per frame, so crowds, foliage and cloth play them in the vertex shader with no skeleton
+//! or simulation at runtime. Each frame takes one or more rows, vertex `v` of frame `f` is at
+//! `(v % width, f * rows_per_frame + v / width)`.
+//!
+//! Positions are stored normalized to the animation's bounds in 16 bits per channel, normals in
+//! 8 bit signed, the layout exporters use and what gets uploaded as-is. `position` and `sample`
+//! decode them the way the shader does. The renderer has no mesh pipeline to sample them from
+//! yet.
+
+use std::path::Path;
+
+use crate::{markup::Json, vfs::Vfs};
+
+// Wider animations wrap onto more rows per frame, WebGL 2 only guarantees 4096.
+pub const MAX_TEXTURE_WIDTH: u32 = 4096;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VatClip {
+    pub name: String,
+    pub start: u32,
+    pub frames: u32,
+    pub looping: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VertexAnimation {
+    vertex_count: u32,
+    frame_count: u32,
+    pub fps: f32,
+    bounds: [[f32; 3]; 2],
+    positions: Vec<[u16; 4]>,
+    normals: Option<Vec<[i8; 4]>>,
+    clips: Vec<VatClip>,
+}
+
+impl VertexAnimation {
+    // Bakes per frame vertex positions, and normals if there are any, e.g. from a tool.
+    pub fn bake(
+        frames: &[Vec<[f32; 3]>],
+        normals: Option<&[Vec<[f32; 3]>]>,
+        fps: f32,
+    ) -> Result<Self, String> {
+        let vertex_count = frames.first().map_or(0, Vec::len);
+        if vertex_count == 0 {
+            return Err("nothing to bake".to_owned());
+        }
+        if frames.iter().any(|frame| frame.len() != vertex_count) {
+            return Err("every frame needs the same vertices".to_owned());
+        }
+        let mut bounds = [[f32::INFINITY; 3], [f32::NEG_INFINITY; 3]];
+        for position in frames.iter().flatten() {
+            for axis in 0..3 {
+                bounds[0][axis] = bounds[0][axis].min(position[axis]);
+                bounds[1][axis] = bounds[1][axis].max(position[axis]);
+            }
+        }
+        let mut animation = Self {
+            vertex_count: u32::try_from(vertex_count).map_err(|_| "too many vertices")?,
+            frame_count: u32::try_from(frames.len()).map_err(|_| "too many frames")?,
+            fps,
+            bounds,
+            positions: Vec::new(),
+            normals: None,
+            clips: Vec::new(),
+        };
+        let texels = animation
+            .texel_count()
+            .ok_or("too many vertices and frames")?;
+        animation.positions = vec![[0; 4]; texels];
+        for (frame, positions) in frames.iter().enumerate() {
+            for (vertex, position) in positions.iter().enumerate() {
+                let texel = animation.texel(vertex as u32, frame as u32);
+                animation.positions[texel] = animation.encode(*position);
+            }
+        }
+        if let Some(normals) = normals {
+            if normals.len() != frames.len() || normals.iter().any(|n| n.len() != vertex_count) {
+                return Err("normals need to match the positions".to_owned());
+            }
+            let mut texels = vec![[0; 4]; texels];
+            for (frame, normals) in normals.iter().enumerate() {
+                for (vertex, normal) in normals.iter().enumerate() {
+                    let [x, y, z] = normal.map(|c| (c.clamp(-1.0, 1.0) * 127.0).round() as i8);
+                    texels[animation.texel(vertex as u32, frame as u32)] = [x, y, z, 0];
+                }
+            }
+            animation.normals = Some(texels);
+        }
+        Ok(animation)
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertex_count
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
+    pub fn bounds(&self) -> [[f32; 3]; 2] {
+        self.bounds
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.frame_count as f32 / self.fps.max(f32::EPSILON)
+    }
+
+    // Width and height of both textures.
+    pub fn texture_size(&self) -> [u32; 2] {
+        [
+            self.vertex_count.min(MAX_TEXTURE_WIDTH),
+            self.rows_per_frame() * self.frame_count,
+        ]
+    }
+
+    fn rows_per_frame(&self) -> u32 {
+        self.vertex_count.div_ceil(MAX_TEXTURE_WIDTH)
+    }
+
+    // None if the textures, at 8 bytes a position texel, would be too big to address, e.g. for a
+    // broken description.
+    fn texel_count(&self) -> Option<usize> {
+        let height = self.rows_per_frame().checked_mul(self.frame_count)?;
+        let width = self.vertex_count.min(MAX_TEXTURE_WIDTH) as usize;
+        let texels = width.checked_mul(height as usize)?;
+        texels.checked_mul(8).map(|_| texels)
+    }
+
+    fn texel(&self, vertex: u32, frame: u32) -> usize {
+        let width = self.texture_size()[0];
+        let row = frame * self.rows_per_frame() + vertex / width;
+        row as usize * width as usize + (vertex % width) as usize
+    }
+
+    // Rgba16Unorm, row by row.
+    pub fn position_texels(&self) -> &[[u16; 4]] {
+        &self.positions
+    }
+
+    // Rgba8Snorm, row by row.
+    pub fn normal_texels(&self) -> Option<&[[i8; 4]]> {
+        self.normals.as_deref()
+    }
+
+    fn encode(&self, position: [f32; 3]) -> [u16; 4] {
+        let [min, max] = self.bounds;
+        let [x, y, z] = std::array::from_fn(|axis| {
+            let extent = (max[axis] - min[axis]).max(f32::EPSILON);
+            ((position[axis] - min[axis]) / extent * 65535.0).round() as u16
+        });
+        [x, y, z, 0]
+    }
+
+    pub fn position(&self, vertex: u32, frame: u32) -> [f32; 3] {
+        let texel = self.positions[self.texel(vertex, frame.min(self.frame_count - 1))];
+        let [min, max] = self.bounds;
+        std::array::from_fn(|axis| {
+            min[axis] + texel[axis] as f32 / 65535.0 * (max[axis] - min[axis])
+        })
+    }
+
+    pub fn normal(&self, vertex: u32, frame: u32) -> Option<[f32; 3]> {
+        let normals = self.normals.as_ref()?;
+        let texel = normals[self.texel(vertex, frame.min(self.frame_count - 1))];
+        Some(std::array::from_fn(|axis| texel[axis] as f32 / 127.0))
+    }
+
+    // Blended between the two frames either side of `playback`'s time.
+    pub fn sample(&self, vertex: u32, playback: &VatPlayback) -> [f32; 3] {
+        let [from, to, blend] = playback.frames(self);
+        let a = self.position(vertex, from as u32);
+        let b = self.position(vertex, to as u32);
+        std::array::from_fn(|axis| a[axis] + (b[axis] - a[axis]) * blend)
+    }
+
+    pub fn add_clip(&mut self, clip: VatClip) -> Result<(), String> {
+        let end = clip.start.checked_add(clip.frames);
+        if clip.frames == 0 || !matches!(end, Some(end) if end <= self.frame_count) {
+            return Err(format!(
+                "clip {} is outside the {} frames",
+                clip.name, self.frame_count
+            ));
+        }
+        self.clips.push(clip);
+        Ok(())
+    }
+
+    pub fn clips(&self) -> &[VatClip] {
+        &self.clips
+    }
+
+    pub fn clip(&self, name: &str) -> Option<&VatClip> {
+        self.clips.iter().find(|clip| clip.name == name)
+    }
+}
+
+// Where one instance is in its animation. Crowds give each instance its own so they don't move
+// in lockstep, and what it turns into per instance data is a few floats.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VatPlayback {
+    // The whole animation if None.
+    pub clip: Option<VatClip>,
+    pub time: f32,
+    pub speed: f32,
+}
+
+impl VatPlayback {
+    pub fn new(clip: Option<VatClip>) -> Self {
+        Self {
+            clip,
+            time: 0.0,
+            speed: 1.0,
+        }
+    }
+
+    // Starts `seconds` in, e.g. random per instance.
+    pub fn with_offset(mut self, seconds: f32) -> Self {
+        self.time = seconds;
+        self
+    }
+
+    pub fn advance(&mut self, seconds: f32) {
+        self.time += seconds * self.speed;
+    }
+
+    // The frames to blend between and how far between them, what the shader gets per instance.
+    pub fn frames(&self, animation: &VertexAnimation) -> [f32; 3] {
+        let (start, frames, looping) = match &self.clip {
+            Some(clip) => (clip.start, clip.frames, clip.looping),
+            None => (0, animation.frame_count, true),
+        };
+        // Clips can be made by hand without `add_clip`, an empty one plays its first frame.
+        let frames = frames.max(1);
+        let position = (self.time * animation.fps).max(0.0);
+        let last = (frames - 1) as f32;
+        let position = if looping {
+            position % frames as f32
+        } else {
+            position.min(last)
+        };
+        let from = position.floor();
+        let to = if from >= last {
+            if looping {
+                0.0
+            } else {
+                last
+            }
+        } else {
+            from + 1.0
+        };
+        [start as f32 + from, start as f32 + to, position - from]
+    }
+}
+
+// Reads an animation through the VFS, see `parse`.
+pub fn load(vfs: &Vfs, path: &str) -> Result<VertexAnimation, String> {
+    let source = vfs
+        .read_to_string(path)
+        .map_err(|err| format!("Failed to read {}: {}", path, err))?;
+    parse(&source, |file| {
+        let file = relative(path, file);
+        vfs.read(&file)
+            .map_err(|err| format!("Failed to read {}: {}", file, err))
+    })
+    .map_err(|err| format!("{}: {}", path, err))
+}
+
+// The JSON description exporters write next to the baked data:
+//
+//     { "vertexCount": 1200, "frameCount": 60, "fps": 30,
+//       "boundsMin": [-1, 0, -1], "boundsMax": [1, 2, 1],
+//       "positions": "walk_positions.bin", "normals": "walk_normals.bin",
+//       "clips": [{ "name": "walk", "start": 0, "frames": 30, "loop": true }] }
+//
+// The data files hold the texels in texture order, little endian u16 RGBA for positions and i8
+// RGBA for normals, which are optional. Image formats aren't read, exporters that only write
+// EXR or PNG need their textures converted. `read` gets file names as they're written in the
+// description.
+pub fn parse(
+    source: &str,
+    mut read: impl FnMut(&str) -> Result<Vec<u8>, String>,
+) -> Result<VertexAnimation, String> {
+    let json = Json::parse(source)?;
+    let number = |key: &str| {
+        json.get(key)
+            .and_then(Json::as_f64)
+            .ok_or_else(|| format!("missing {}", key))
+    };
+    let vector = |key: &str| -> Result<[f32; 3], String> {
+        match json.get(key).map(Json::as_array) {
+            Some([x, y, z]) => [x, y, z]
+                .map(|c| c.as_f64().map(|c| c as f32))
+                .into_iter()
+                .collect::<Option<Vec<_>>>()
+                .map(|c| [c[0], c[1], c[2]])
+                .ok_or_else(|| format!("{} isn't three numbers", key)),
+            _ => Err(format!("{} isn't three numbers", key)),
+        }
+    };
+    let mut animation = VertexAnimation {
+        vertex_count: number("vertexCount")? as u32,
+        frame_count: number("frameCount")? as u32,
+        fps: number("fps")? as f32,
+        bounds: [vector("boundsMin")?, vector("boundsMax")?],
+        positions: Vec::new(),
+        normals: None,
+        clips: Vec::new(),
+    };
+    if animation.vertex_count == 0 || animation.frame_count == 0 {
+        return Err("no vertices or frames".to_owned());
+    }
+    let texels = animation
+        .texel_count()
+        .ok_or("too many vertices and frames")?;
+
+    let file = json
+        .get("positions")
+        .and_then(Json::as_str)
+        .ok_or("missing positions")?;
+    let bytes = read(file)?;
+    if bytes.len() != texels * 8 {
+        return Err(format!("{} should be {} bytes", file, texels * 8));
+    }
+    animation.positions = bytes
+        .chunks_exact(8)
+        .map(|texel| std::array::from_fn(|c| u16::from_le_bytes([texel[c * 2], texel[c * 2 + 1]])))
+        .collect();
+
+    if let Some(file) = json.get("normals").and_then(Json::as_str) {
+        let bytes = read(file)?;
+        if bytes.len() != texels * 4 {
+            return Err(format!("{} should be {} bytes", file, texels * 4));
+        }
+        animation.normals = Some(
+            bytes
+                .chunks_exact(4)
+                .map(|texel| std::array::from_fn(|c| texel[c] as i8))
+                .collect(),
+        );
+    }
+
+    for clip in json.get("clips").map_or(&[][..], Json::as_array) {
+        let field = |key: &str| {
+            clip.get(key)
+                .and_then(Json::as_f64)
+                .ok_or_else(|| format!("clip without {}", key))
+        };
+        animation.add_clip(VatClip {
+            name: clip
+                .get("name")
+                .and_then(Json::as_str)
+                .ok_or("clip without a name")?
+                .to_owned(),
+            start: field("start")? as u32,
+            frames: field("frames")? as u32,
+            looping: clip.get("loop").and_then(Json::as_bool).unwrap_or(true),
+        })?;
+    }
+    Ok(animation)
+}
+
+// `file` relative to the directory `path` is in.
+fn relative(path: &str, file: &str) -> String {
+    match Path::new(path).parent().and_then(Path::to_str) {
+        Some(dir) if !dir.is_empty() => format!("{}/{}", dir, file),
+        _ => file.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bakes_and_imports() {
+        // A triangle that rises two units over three frames.
+        let frames: Vec<Vec<[f32; 3]>> = (0..3)
+            .map(|frame| {
+                let y = frame as f32;
+                vec![[0.0, y, 0.0], [1.0, y, 0.0], [0.0, y, -1.0]]
+            })
+            .collect();
+        let mut baked = VertexAnimation::bake(&frames, None, 4.0).unwrap();
+        assert_eq!(baked.texture_size(), [3, 3]);
+        assert_eq!(baked.position(1, 2), [1.0, 2.0, 0.0]);
+        baked
+            .add_clip(VatClip {
+                name: "rise".to_owned(),
+                start: 1,
+                frames: 2,
+                looping: false,
+            })
+            .unwrap();
+        let mut playback = VatPlayback::new(baked.clip("rise").cloned());
+        playback.advance(0.125);
+        assert_eq!(playback.frames(&baked), [1.0, 2.0, 0.5]);
+        assert!((baked.sample(0, &playback)[1] - 1.5).abs() < 1e-4);
+        // Clips that don't loop hold their last frame.
+        playback.advance(1.0);
+        assert_eq!(baked.sample(0, &playback), [0.0, 2.0, 0.0]);
+
+        // The same through the importer.
+        let description = r#"{
+            "vertexCount": 3, "frameCount": 3, "fps": 4,
+            "boundsMin": [0, 0, -1], "boundsMax": [1, 2, 0],
+            "positions": "rise.bin",
+            "clips": [{ "name": "rise", "start": 1, "frames": 2, "loop": false }]
+        }"#;
+        let bytes: Vec<u8> = baked
+            .position_texels()
+            .iter()
+            .flatten()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        let imported = parse(description, |file| {
+            assert_eq!(file, "rise.bin");
+            Ok(bytes.clone())
+        })
+        .unwrap();
+        assert_eq!(imported, baked);
+        assert!(parse(description, |_| Ok(vec![0; 8])).is_err());
+        assert_eq!(relative("vat/crowd.json", "rise.bin"), "vat/rise.bin");
+
+        // Sizes that would overflow are errors rather than panics or wrapped bounds.
+        let huge = description.replace(r#""frameCount": 3"#, r#""frameCount": 4294967295"#);
+        let huge = huge.replace(r#""vertexCount": 3"#, r#""vertexCount": 8193"#);
+        assert!(parse(&huge, |_| unreachable!()).is_err());
+        let clip = |start, frames| VatClip {
+            name: "bad".to_owned(),
+            start,
+            frames,
+            looping: true,
+        };
+        assert!(baked.add_clip(clip(u32::MAX, 2)).is_err());
+        assert!(baked.add_clip(clip(0, 0)).is_err());
+        let mut playback = VatPlayback::new(Some(clip(1, 0)));
+        playback.advance(1.0);
+        assert_eq!(playback.frames(&baked), [1.0, 1.0, 0.0]);
+    }
+}
@@ -0,0 +1,129 @@
+//! Virtual file system. Game content is read by virtual path, `textures/stone.png`, from
+//! directories mounted with a priority. The highest priority mount that has a file provides
+//! it, so mods mounted above the game's own content override its files without touching them.
+//!
+//! Virtual paths are relative with `/` separators, `..` and absolute paths are refused so a
+//! mount can't reach outside its directory. Neither can symlinks in it, files are only found if
+//! they resolve to somewhere under the mount's root.
+
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mount {
+    pub name: String,
+    pub root: PathBuf,
+    pub priority: i32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Vfs {
+    // Highest priority first, among equals the latest mounted first.
+    mounts: Vec<Mount>,
+}
+
+impl Vfs {
+    // Replaces a mount with the same name.
+    pub fn mount(&mut self, name: &str, root: impl Into<PathBuf>, priority: i32) {
+        self.unmount(name);
+        let index = self
+            .mounts
+            .iter()
+            .position(|mount| mount.priority <= priority)
+            .unwrap_or(self.mounts.len());
+        self.mounts.insert(
+            index,
+            Mount {
+                name: name.to_owned(),
+                root: root.into(),
+                priority,
+            },
+        );
+    }
+
+    pub fn unmount(&mut self, name: &str) -> bool {
+        let len = self.mounts.len();
+        self.mounts.retain(|mount| mount.name != name);
+        self.mounts.len() != len
+    }
+
+    // Highest priority first.
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    // The mount that provides `path`.
+    pub fn source(&self, path: &str) -> Option<&Mount> {
+        let relative = relative(path)?;
+        self.mounts
+            .iter()
+            .find(|mount| contained_file(&mount.root, &relative).is_some())
+    }
+
+    // Where `path` is on disk.
+    pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let relative = relative(path)?;
+        self.mounts
+            .iter()
+            .find_map(|mount| contained_file(&mount.root, &relative))
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.resolve(path).is_some()
+    }
+
+    pub fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.resolve(path).ok_or_else(|| not_found(path))?)
+    }
+
+    pub fn read_to_string(&self, path: &str) -> io::Result<String> {
+        fs::read_to_string(self.resolve(path).ok_or_else(|| not_found(path))?)
+    }
+
+    // Virtual paths of the files directly in `dir` across every mount, sorted. "" is the root.
+    pub fn list(&self, dir: &str) -> Vec<String> {
+        let Some(relative) = relative(dir) else {
+            return Vec::new();
+        };
+        let prefix = dir.trim_matches('/');
+        let mut files = BTreeSet::new();
+        for mount in &self.mounts {
+            let Ok(entries) = fs::read_dir(mount.root.join(&relative)) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if contained_file(&mount.root, &relative.join(entry.file_name())).is_some() {
+                    files.insert(match prefix {
+                        "" => name,
+                        prefix => format!("{}/{}", prefix, name),
+                    });
+                }
+            }
+        }
+        files.into_iter().collect()
+    }
+}
+
+fn relative(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        .then(|| path.to_path_buf())
+}
+
+// `relative` under `root` with symlinks resolved, if it's a file that stays under the root.
+fn contained_file(root: &Path, relative: &Path) -> Option<PathBuf> {
+    let real = root.join(relative).canonicalize().ok()?;
+    (real.is_file() && real.starts_with(root.canonicalize().ok()?)).then_some(real)
+}
+
+fn not_found(path: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} isn't in any mount", path),
+    )
+}
@@ -0,0 +1,168 @@
+// AV1 in IVF files through libdav1d, which has to be installed where the game builds. dav1d
+// decodes on worker threads of its own, frames come out in presentation order and are converted
+// from 8-bit YUV to RGBA here.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek},
+    path::Path,
+    time::Duration,
+};
+
+use dav1d::{PixelLayout, PlanarImageComponent};
+
+use super::{IvfReader, VideoDecoder, VideoError, VideoFrame};
+
+pub struct Av1Decoder<R: Read + Seek> {
+    ivf: IvfReader<R>,
+    decoder: dav1d::Decoder,
+    // A packet dav1d couldn't take yet, it's sent again once a picture is out.
+    pending: bool,
+    // The file ran out, dav1d is handing out what it still has.
+    draining: bool,
+}
+
+impl Av1Decoder<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, VideoError> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|err| VideoError::Format(format!("{}: {}", path.display(), err)))?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> Av1Decoder<R> {
+    pub fn new(reader: R) -> Result<Self, VideoError> {
+        let ivf = IvfReader::new(reader)?;
+        if &ivf.header().fourcc != b"AV01" {
+            return Err(VideoError::Format(format!(
+                "{} isn't AV1",
+                String::from_utf8_lossy(&ivf.header().fourcc)
+            )));
+        }
+        Ok(Self {
+            ivf,
+            decoder: new_decoder()?,
+            pending: false,
+            draining: false,
+        })
+    }
+}
+
+impl<R: Read + Seek + Send> VideoDecoder for Av1Decoder<R> {
+    fn size(&self) -> [u32; 2] {
+        let header = self.ivf.header();
+        [header.width as u32, header.height as u32]
+    }
+
+    // IVF only has a frame count, which some encoders leave at zero.
+    fn duration(&self) -> Option<Duration> {
+        None
+    }
+
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>, VideoError> {
+        loop {
+            match self.decoder.get_picture() {
+                Ok(picture) => return to_frame(&picture, self.ivf.header()).map(Some),
+                Err(dav1d::Error::Again) if self.draining => return Ok(None),
+                Err(dav1d::Error::Again) => {}
+                Err(err) => return Err(decode_error(err)),
+            }
+            if self.pending {
+                match self.decoder.send_pending_data() {
+                    Ok(()) => self.pending = false,
+                    Err(dav1d::Error::Again) => {}
+                    Err(err) => return Err(decode_error(err)),
+                }
+                continue;
+            }
+            let Some(packet) = self.ivf.next_packet()? else {
+                self.draining = true;
+                continue;
+            };
+            match self
+                .decoder
+                .send_data(packet.data, None, Some(packet.timestamp as i64), None)
+            {
+                Ok(()) => {}
+                Err(dav1d::Error::Again) => self.pending = true,
+                Err(err) => return Err(decode_error(err)),
+            }
+        }
+    }
+
+    fn rewind(&mut self) -> Result<(), VideoError> {
+        self.ivf.rewind()?;
+        self.decoder.flush();
+        self.pending = false;
+        self.draining = false;
+        Ok(())
+    }
+}
+
+fn new_decoder() -> Result<dav1d::Decoder, VideoError> {
+    let mut settings = dav1d::Settings::new();
+    // Leave a core for the sim and render threads.
+    let threads = std::thread::available_parallelism().map_or(1, |count| count.get());
+    settings.set_n_threads(threads.saturating_sub(1).max(1) as u32);
+    dav1d::Decoder::with_settings(&settings).map_err(decode_error)
+}
+
+fn decode_error(err: dav1d::Error) -> VideoError {
+    VideoError::Decode(err.to_string())
+}
+
+// BT.709 limited range YUV to RGBA.
+fn to_frame(picture: &dav1d::Picture, header: &super::IvfHeader) -> Result<VideoFrame, VideoError> {
+    if picture.bit_depth() != 8 {
+        return Err(VideoError::Format(format!(
+            "{}-bit video isn't supported",
+            picture.bit_depth()
+        )));
+    }
+    let (width, height) = (picture.width() as usize, picture.height() as usize);
+    // How many luma pixels share a chroma sample across and down.
+    let subsampling = match picture.pixel_layout() {
+        PixelLayout::I400 => None,
+        PixelLayout::I420 => Some((2, 2)),
+        PixelLayout::I422 => Some((2, 1)),
+        PixelLayout::I444 => Some((1, 1)),
+    };
+    let luma = picture.plane(PlanarImageComponent::Y);
+    let luma_stride = picture.stride(PlanarImageComponent::Y) as usize;
+    let chroma = subsampling.map(|subsampling| {
+        (
+            picture.plane(PlanarImageComponent::U),
+            picture.plane(PlanarImageComponent::V),
+            picture.stride(PlanarImageComponent::U) as usize,
+            subsampling,
+        )
+    });
+
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for row in 0..height {
+        for column in 0..width {
+            let y = (luma[row * luma_stride + column] as f32 - 16.0) * 1.164;
+            let (u, v) = match &chroma {
+                Some((u, v, stride, (across, down))) => {
+                    let at = row / down * stride + column / across;
+                    (u[at] as f32 - 128.0, v[at] as f32 - 128.0)
+                }
+                None => (0.0, 0.0),
+            };
+            let channel = |value: f32| value.round().clamp(0.0, 255.0) as u8;
+            pixels.extend([
+                channel(y + 1.793 * v),
+                channel(y - 0.213 * u - 0.533 * v),
+                channel(y + 2.112 * u),
+                255,
+            ]);
+        }
+    }
+    Ok(VideoFrame {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+        timestamp: header.time(picture.timestamp().unwrap_or_default().max(0) as u64),
+    })
+}
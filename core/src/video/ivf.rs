@@ -0,0 +1,134 @@
+// IVF, the minimal container ffmpeg and the AV1 and VP9 encoders write raw streams into: a 32
+// byte header, then each frame's size and timestamp followed by its compressed data.
+
+use std::{
+    io::{self, Read, Seek, SeekFrom},
+    time::Duration,
+};
+
+use super::VideoError;
+
+const SIGNATURE: &[u8; 4] = b"DKIF";
+const HEADER_LEN: u64 = 32;
+// Far more than any sane compressed frame, a size past it means the file is broken.
+const MAX_FRAME_LEN: u64 = 64 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IvfHeader {
+    // The codec, e.g. `AV01` or `VP90`.
+    pub fourcc: [u8; 4],
+    pub width: u16,
+    pub height: u16,
+    // Timestamps count in units of numerator / denominator seconds.
+    pub timebase: [u32; 2],
+    pub frame_count: u32,
+}
+
+impl IvfHeader {
+    pub fn time(&self, timestamp: u64) -> Duration {
+        let [numerator, denominator] = self.timebase;
+        if denominator == 0 {
+            return Duration::ZERO;
+        }
+        let nanos = timestamp as u128 * numerator as u128 * 1_000_000_000 / denominator as u128;
+        Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IvfPacket {
+    pub timestamp: u64,
+    pub data: Vec<u8>,
+}
+
+pub struct IvfReader<R: Read + Seek> {
+    reader: R,
+    header: IvfHeader,
+    // Where the first frame starts.
+    frames_start: u64,
+}
+
+impl<R: Read + Seek> IvfReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, VideoError> {
+        let mut bytes = [0; HEADER_LEN as usize];
+        reader.read_exact(&mut bytes)?;
+        if &bytes[0..4] != SIGNATURE {
+            return Err(VideoError::Format("not an IVF file".to_owned()));
+        }
+        let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let frames_start = (u16_at(6) as u64).max(HEADER_LEN);
+        let header = IvfHeader {
+            fourcc: bytes[8..12].try_into().unwrap(),
+            width: u16_at(12),
+            height: u16_at(14),
+            timebase: [u32_at(20), u32_at(16)],
+            frame_count: u32_at(24),
+        };
+        reader.seek(SeekFrom::Start(frames_start))?;
+        Ok(Self {
+            reader,
+            header,
+            frames_start,
+        })
+    }
+
+    pub fn header(&self) -> &IvfHeader {
+        &self.header
+    }
+
+    // None at the end of the file.
+    pub fn next_packet(&mut self) -> Result<Option<IvfPacket>, VideoError> {
+        let mut bytes = [0; 12];
+        match self.reader.read_exact(&mut bytes) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let size = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as u64;
+        let timestamp = u64::from_le_bytes(bytes[4..12].try_into().unwrap());
+        if size > MAX_FRAME_LEN {
+            return Err(VideoError::Format(format!("{} byte frame", size)));
+        }
+        // Grown as it's read rather than allocated up front, in case the file is cut short.
+        let mut data = Vec::new();
+        (&mut self.reader).take(size).read_to_end(&mut data)?;
+        if data.len() as u64 != size {
+            return Err(VideoError::Format("truncated frame".to_owned()));
+        }
+        Ok(Some(IvfPacket { timestamp, data }))
+    }
+
+    pub fn rewind(&mut self) -> Result<(), VideoError> {
+        self.reader.seek(SeekFrom::Start(self.frames_start))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broken_frame_sizes_are_errors() {
+        let mut file = SIGNATURE.to_vec();
+        file.extend([0, 0, 32, 0]);
+        file.extend(b"AV01");
+        file.extend([0; 20]);
+        let packet = |size: u32, data: &[u8]| {
+            let mut bytes = file.clone();
+            bytes.extend(size.to_le_bytes());
+            bytes.extend(7u64.to_le_bytes());
+            bytes.extend(data);
+            IvfReader::new(io::Cursor::new(bytes))
+                .unwrap()
+                .next_packet()
+        };
+        assert!(matches!(
+            packet(3, &[1, 2, 3]),
+            Ok(Some(IvfPacket { timestamp: 7, .. }))
+        ));
+        assert!(matches!(packet(10, &[1, 2]), Err(VideoError::Format(_))));
+        assert!(matches!(packet(u32::MAX, &[]), Err(VideoError::Format(_))));
+    }
+}
@@ -0,0 +1,560 @@
+//! Video playback for cutscenes and menu backgrounds. A `Video` pulls frames from a
+//! `VideoDecoder` as sim time reaches them and publishes the latest one to a `VideoTexture`,
+//! which the renderer uploads from whenever its generation changes. A soundtrack, if given,
+//! streams through the mixer and is restarted from the right spot on seek and resume.
+//!
+//! `FramesDecoder` plays frames already in memory. AV1 in IVF files decodes through libdav1d with
+//! the `video` feature, see `av1.rs`. IVF carries no audio, so cutscenes pair it with a
+//! soundtrack, e.g. an Ogg file.
+//!
+//! The renderer has no textured pass yet, so nothing draws a `VideoTexture` on its own, the game
+//! hands its frames to whatever overlay it draws with.
+
+#[cfg(all(feature = "video", not(target_arch = "wasm32")))]
+mod av1;
+mod ivf;
+
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::warn;
+
+#[cfg(all(feature = "video", not(target_arch = "wasm32")))]
+pub use av1::Av1Decoder;
+pub use ivf::{IvfHeader, IvfPacket, IvfReader};
+
+use crate::{
+    audio::{Audio, AudioError, Decoder, VoiceId, VoiceParams},
+    engine::{Engine, Plugin, Resources},
+    metrics,
+    sim::Time,
+};
+
+#[derive(Debug)]
+pub enum VideoError {
+    Io(io::Error),
+    // The container is malformed or isn't one we read.
+    Format(String),
+    Decode(String),
+}
+
+impl fmt::Display for VideoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VideoError::Io(err) => write!(f, "{}", err),
+            VideoError::Format(err) => write!(f, "unsupported video: {}", err),
+            VideoError::Decode(err) => write!(f, "failed to decode video: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for VideoError {}
+
+impl From<io::Error> for VideoError {
+    fn from(err: io::Error) -> Self {
+        VideoError::Io(err)
+    }
+}
+
+// A decoded picture, tightly packed RGBA8 rows top to bottom.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VideoFrame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    // When it's shown, from the start of the video.
+    pub timestamp: Duration,
+}
+
+// A source of frames decoded one at a time.
+pub trait VideoDecoder: Send {
+    fn size(&self) -> [u32; 2];
+    // None if the container doesn't say.
+    fn duration(&self) -> Option<Duration>;
+    // The next frame in presentation order, None at the end.
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>, VideoError>;
+    // Goes back to the first frame.
+    fn rewind(&mut self) -> Result<(), VideoError>;
+}
+
+// Plays frames that are already in memory, for short loops, generated content and tests.
+pub struct FramesDecoder {
+    frames: Vec<VideoFrame>,
+    next: usize,
+}
+
+impl FramesDecoder {
+    pub fn new(frames: Vec<VideoFrame>) -> Self {
+        Self { frames, next: 0 }
+    }
+}
+
+impl VideoDecoder for FramesDecoder {
+    fn size(&self) -> [u32; 2] {
+        self.frames
+            .first()
+            .map_or([0; 2], |frame| [frame.width, frame.height])
+    }
+
+    // The last frame is shown as long as the one before it.
+    fn duration(&self) -> Option<Duration> {
+        match self.frames.as_slice() {
+            [.., before, last] => Some(last.timestamp * 2 - before.timestamp),
+            [only] => Some(only.timestamp),
+            [] => None,
+        }
+    }
+
+    fn next_frame(&mut self) -> Result<Option<VideoFrame>, VideoError> {
+        let frame = self.frames.get(self.next).cloned();
+        self.next += frame.is_some() as usize;
+        Ok(frame)
+    }
+
+    fn rewind(&mut self) -> Result<(), VideoError> {
+        self.next = 0;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Published {
+    frame: Option<Arc<VideoFrame>>,
+    generation: u64,
+}
+
+// Where a video's current frame is handed to the renderer. Cheap to clone.
+#[derive(Clone, Default)]
+pub struct VideoTexture(Arc<Mutex<Published>>);
+
+impl VideoTexture {
+    // The current frame if it changed since generation `seen`, which is updated.
+    pub fn latest(&self, seen: &mut u64) -> Option<Arc<VideoFrame>> {
+        let published = self.0.lock().unwrap();
+        if published.generation == *seen {
+            return None;
+        }
+        *seen = published.generation;
+        published.frame.clone()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.0.lock().unwrap().generation
+    }
+
+    fn publish(&self, frame: VideoFrame) {
+        let mut published = self.0.lock().unwrap();
+        published.frame = Some(Arc::new(frame));
+        published.generation += 1;
+    }
+}
+
+// Makes a soundtrack decoder from the start, a video restarts it on seek and resume.
+pub type Soundtrack = Box<dyn FnMut() -> Result<Box<dyn Decoder>, AudioError> + Send>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoState {
+    Playing,
+    Paused,
+    Finished,
+}
+
+pub struct Video {
+    decoder: Box<dyn VideoDecoder>,
+    texture: VideoTexture,
+    state: VideoState,
+    position: Duration,
+    // Decoded but not due yet.
+    next: Option<VideoFrame>,
+    // Timestamp of the last frame shown and the gap before it, the video ends one gap after its
+    // last frame if the decoder doesn't know its duration.
+    last_shown: Duration,
+    interval: Duration,
+    looping: bool,
+    ignore_pause: bool,
+    soundtrack: Option<Soundtrack>,
+    voice_params: VoiceParams,
+    voice: Option<VoiceId>,
+}
+
+impl Video {
+    // Paused on the first frame until played.
+    pub fn new(decoder: Box<dyn VideoDecoder>) -> Self {
+        Self {
+            decoder,
+            texture: VideoTexture::default(),
+            state: VideoState::Paused,
+            position: Duration::ZERO,
+            next: None,
+            last_shown: Duration::ZERO,
+            interval: Duration::ZERO,
+            looping: false,
+            ignore_pause: false,
+            soundtrack: None,
+            voice_params: VoiceParams::default(),
+            voice: None,
+        }
+    }
+
+    pub fn looping(mut self) -> Self {
+        self.looping = true;
+        self
+    }
+
+    // Keeps playing while the sim is paused, for menu backgrounds.
+    pub fn ignore_pause(mut self) -> Self {
+        self.ignore_pause = true;
+        self
+    }
+
+    pub fn with_soundtrack(mut self, soundtrack: Soundtrack, params: VoiceParams) -> Self {
+        self.soundtrack = Some(soundtrack);
+        self.voice_params = params;
+        self
+    }
+
+    pub fn texture(&self) -> VideoTexture {
+        self.texture.clone()
+    }
+
+    pub fn state(&self) -> VideoState {
+        self.state
+    }
+
+    pub fn position(&self) -> Duration {
+        self.position
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.decoder.duration()
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        self.decoder.size()
+    }
+
+    // Plays from where it was, or from the start once finished.
+    pub fn play(&mut self, audio: Option<&mut Audio>) -> Result<(), VideoError> {
+        if self.state == VideoState::Finished {
+            self.seek(Duration::ZERO, None)?;
+        }
+        self.state = VideoState::Playing;
+        self.restart_soundtrack(audio);
+        Ok(())
+    }
+
+    pub fn pause(&mut self, audio: Option<&mut Audio>) {
+        if self.state == VideoState::Playing {
+            self.state = VideoState::Paused;
+            self.stop_soundtrack(audio);
+        }
+    }
+
+    // Shows the frame at `to`, decoding from the start when going back.
+    pub fn seek(&mut self, to: Duration, audio: Option<&mut Audio>) -> Result<(), VideoError> {
+        if to < self.position || self.state == VideoState::Finished {
+            self.decoder.rewind()?;
+            self.next = None;
+        }
+        self.position = to;
+        if self.state == VideoState::Finished {
+            self.state = VideoState::Paused;
+        }
+        self.show_due()?;
+        if self.state == VideoState::Playing {
+            self.restart_soundtrack(audio);
+        }
+        Ok(())
+    }
+
+    // Moves the video on by `delta` and publishes the frame that's due.
+    pub fn advance(
+        &mut self,
+        delta: Duration,
+        audio: Option<&mut Audio>,
+    ) -> Result<(), VideoError> {
+        if self.state != VideoState::Playing {
+            return Ok(());
+        }
+        self.position += delta;
+        if !self.show_due()? {
+            return Ok(());
+        }
+        let end = self.end();
+        if self.position < end {
+            return Ok(());
+        }
+        if self.looping {
+            self.decoder.rewind()?;
+            self.position = self.position.saturating_sub(end);
+            self.show_due()?;
+            self.restart_soundtrack(audio);
+        } else {
+            self.state = VideoState::Finished;
+            self.stop_soundtrack(audio);
+        }
+        Ok(())
+    }
+
+    fn end(&self) -> Duration {
+        self.decoder
+            .duration()
+            .unwrap_or(self.last_shown + self.interval)
+    }
+
+    // Publishes the last frame due at the current position, returns true once out of frames.
+    fn show_due(&mut self) -> Result<bool, VideoError> {
+        let mut due = None;
+        loop {
+            if self.next.is_none() {
+                self.next = self.decoder.next_frame()?;
+            }
+            match self.next.take() {
+                Some(frame) if frame.timestamp <= self.position => {
+                    if frame.timestamp > self.last_shown {
+                        self.interval = frame.timestamp - self.last_shown;
+                    }
+                    self.last_shown = frame.timestamp;
+                    due = Some(frame);
+                }
+                Some(frame) => {
+                    self.next = Some(frame);
+                    break;
+                }
+                None => break,
+            }
+        }
+        let ended = self.next.is_none();
+        if let Some(frame) = due {
+            self.texture.publish(frame);
+        }
+        Ok(ended)
+    }
+
+    fn restart_soundtrack(&mut self, mut audio: Option<&mut Audio>) {
+        self.stop_soundtrack(audio.as_deref_mut());
+        let (Some(soundtrack), Some(audio)) = (&mut self.soundtrack, audio) else {
+            return;
+        };
+        let started = soundtrack().and_then(|decoder| {
+            let decoder = Box::new(Skip::new(decoder, self.position));
+            audio.play_stream(decoder, None, self.voice_params)
+        });
+        match started {
+            Ok(voice) => self.voice = Some(voice),
+            Err(err) => warn!("Failed to start a video soundtrack: {}", err),
+        }
+    }
+
+    fn stop_soundtrack(&mut self, audio: Option<&mut Audio>) {
+        if let (Some(voice), Some(audio)) = (self.voice.take(), audio) {
+            audio.stop(voice);
+        }
+    }
+}
+
+// Starts a soundtrack `offset` in by dropping the samples before it.
+struct Skip {
+    decoder: Box<dyn Decoder>,
+    skip: usize,
+}
+
+impl Skip {
+    fn new(decoder: Box<dyn Decoder>, offset: Duration) -> Self {
+        let frames = (offset.as_secs_f64() * decoder.sample_rate() as f64) as usize;
+        let skip = frames * decoder.channels() as usize;
+        Self { decoder, skip }
+    }
+}
+
+impl Decoder for Skip {
+    fn channels(&self) -> u16 {
+        self.decoder.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.decoder.sample_rate()
+    }
+
+    fn decode(&mut self, out: &mut Vec<f32>) -> Result<bool, AudioError> {
+        while self.skip > 0 {
+            let start = out.len();
+            if !self.decoder.decode(out)? {
+                return Ok(false);
+            }
+            let skipped = (out.len() - start).min(self.skip);
+            out.drain(start..start + skipped);
+            self.skip -= skipped;
+            if out.len() > start {
+                return Ok(true);
+            }
+        }
+        self.decoder.decode(out)
+    }
+
+    fn rewind(&mut self) -> Result<(), AudioError> {
+        self.skip = 0;
+        self.decoder.rewind()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VideoId(u64);
+
+impl fmt::Display for VideoId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoEvent {
+    // Played to the end without looping.
+    Finished(VideoId),
+}
+
+// The videos the sim controls, advanced by `VideoPlugin`'s system every tick.
+#[derive(Default)]
+pub struct Videos {
+    next_id: u64,
+    videos: Vec<(VideoId, Video)>,
+    events: VecDeque<VideoEvent>,
+}
+
+impl Videos {
+    // Takes `video` as it is, call `play` to start it.
+    pub fn add(&mut self, video: Video) -> VideoId {
+        let id = VideoId(self.next_id);
+        self.next_id += 1;
+        self.videos.push((id, video));
+        id
+    }
+
+    // Stops its soundtrack too.
+    pub fn remove(&mut self, id: VideoId, audio: Option<&mut Audio>) -> Option<Video> {
+        let index = self.videos.iter().position(|(video, _)| *video == id)?;
+        let (_, mut video) = self.videos.remove(index);
+        video.stop_soundtrack(audio);
+        Some(video)
+    }
+
+    pub fn get(&self, id: VideoId) -> Option<&Video> {
+        self.videos
+            .iter()
+            .find(|(video, _)| *video == id)
+            .map(|(_, video)| video)
+    }
+
+    pub fn get_mut(&mut self, id: VideoId) -> Option<&mut Video> {
+        self.videos
+            .iter_mut()
+            .find(|(video, _)| *video == id)
+            .map(|(_, video)| video)
+    }
+
+    pub fn len(&self) -> usize {
+        self.videos.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.videos.is_empty()
+    }
+
+    pub fn poll_event(&mut self) -> Option<VideoEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn advance(&mut self, time: &Time, mut audio: Option<&mut Audio>) {
+        for (id, video) in &mut self.videos {
+            let delta = if video.ignore_pause {
+                time.real_delta
+            } else {
+                time.delta
+            };
+            let was_playing = video.state == VideoState::Playing;
+            if let Err(err) = video.advance(delta, audio.as_deref_mut()) {
+                warn!("Stopping video {}: {}", id, err);
+                video.state = VideoState::Finished;
+                video.stop_soundtrack(audio.as_deref_mut());
+            }
+            if was_playing && video.state == VideoState::Finished {
+                self.events.push_back(VideoEvent::Finished(*id));
+            }
+        }
+    }
+}
+
+pub struct VideoPlugin;
+
+impl Plugin for VideoPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(Videos::default())
+            .add_system(|resources: &mut Resources| {
+                let time = resources.get::<Time>().copied().unwrap_or_default();
+                let Some(mut videos) = resources.remove::<Videos>() else {
+                    return;
+                };
+                videos.advance(&time, resources.get_mut::<Audio>());
+                let playing = videos
+                    .videos
+                    .iter()
+                    .filter(|(_, video)| video.state == VideoState::Playing)
+                    .count();
+                metrics::set_gauge("videos_playing", playing as f64);
+                resources.insert(videos);
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(millis: u64, shade: u8) -> VideoFrame {
+        VideoFrame {
+            width: 1,
+            height: 1,
+            pixels: vec![shade, shade, shade, 255],
+            timestamp: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn shows_due_frames_seeks_and_loops() {
+        let frames = (0..4)
+            .map(|index| frame(index * 100, index as u8))
+            .collect();
+        let mut video = Video::new(Box::new(FramesDecoder::new(frames))).looping();
+        let texture = video.texture();
+        let mut seen = 0;
+        let shade = |texture: &VideoTexture, seen: &mut u64| {
+            texture.latest(seen).map(|frame| frame.pixels[0])
+        };
+
+        video.seek(Duration::ZERO, None).unwrap();
+        assert_eq!(shade(&texture, &mut seen), Some(0));
+        video.advance(Duration::from_millis(50), None).unwrap();
+        assert_eq!(shade(&texture, &mut seen), None);
+
+        video.play(None).unwrap();
+        video.advance(Duration::from_millis(120), None).unwrap();
+        assert_eq!(shade(&texture, &mut seen), Some(1));
+        // Frames that fall between ticks are skipped, the last one stays up for a frame.
+        video.advance(Duration::from_millis(200), None).unwrap();
+        assert_eq!(shade(&texture, &mut seen), Some(3));
+        assert_eq!(video.state(), VideoState::Playing);
+        video.seek(Duration::from_millis(150), None).unwrap();
+        assert_eq!(shade(&texture, &mut seen), Some(1));
+
+        // Past the end at 400ms it wraps around.
+        video.advance(Duration::from_millis(300), None).unwrap();
+        assert_eq!(video.position(), Duration::from_millis(50));
+        assert_eq!(shade(&texture, &mut seen), Some(0));
+        assert_eq!(video.state(), VideoState::Playing);
+    }
+}
@@ -0,0 +1,489 @@
+//! Time of day, sky and weather. `TimeOfDay` moves the sun, `Sky` is what the sky looks like for
+//! it, from a cheap single scattering approximation that reddens the sun near the horizon, and
+//! `Weather` fades rain and snow in and out and keeps the wetness materials darken and shine by.
+//!
+//! It's all sim state so saves, replays and the network see the same weather. The renderer has
+//! no sky pass or particle system yet: `Sky::sample` is what the sky shader has to compute per
+//! pixel, and `Weather::precipitation` is what a particle emitter around the camera would spawn.
+
+use std::{f32::consts::PI, fmt, time::Duration};
+
+use crate::{
+    cvars,
+    engine::{Engine, Plugin, Resources},
+    metrics,
+    sim::Time,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeOfDay {
+    // 0..24.
+    pub hours: f32,
+    // Real time a whole day takes.
+    pub day_length: Duration,
+    pub paused: bool,
+    // In degrees, north positive.
+    pub latitude: f32,
+    // 0..365, for how high the sun gets.
+    pub day_of_year: u32,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        Self {
+            hours: 12.0,
+            day_length: Duration::from_secs(20 * 60),
+            paused: false,
+            latitude: 45.0,
+            day_of_year: 172,
+        }
+    }
+}
+
+impl TimeOfDay {
+    pub fn advance(&mut self, delta: Duration) {
+        if self.paused || self.day_length.is_zero() {
+            return;
+        }
+        self.hours += 24.0 * delta.as_secs_f32() / self.day_length.as_secs_f32();
+        while self.hours >= 24.0 {
+            self.hours -= 24.0;
+            self.day_of_year = (self.day_of_year + 1) % 365;
+        }
+    }
+
+    // Unit vector towards the sun, y up, x east and -z north.
+    pub fn sun_direction(&self) -> [f32; 3] {
+        let declination =
+            23.44f32.to_radians() * (2.0 * PI * (284 + self.day_of_year) as f32 / 365.0).sin();
+        let hour_angle = ((self.hours - 12.0) * 15.0).to_radians();
+        let latitude = self.latitude.to_radians();
+        let up = latitude.sin() * declination.sin()
+            + latitude.cos() * declination.cos() * hour_angle.cos();
+        let east = -declination.cos() * hour_angle.sin();
+        let north = declination.sin() * latitude.cos()
+            - declination.cos() * latitude.sin() * hour_angle.cos();
+        normalize([east, up, -north])
+    }
+}
+
+// How much more each channel scatters than red, roughly λ⁻⁴ for red, green and blue.
+const RAYLEIGH: [f32; 3] = [0.17, 0.40, 1.0];
+// Optical depth of the atmosphere straight up, for blue.
+const THICKNESS: f32 = 0.25;
+const MIE_G: f32 = 0.76;
+const MIE_STRENGTH: f32 = 0.05;
+const NIGHT: [f32; 3] = [0.01, 0.015, 0.03];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sky {
+    pub sun_direction: [f32; 3],
+    // 0..1, clouds that grey the sky and dim the sun.
+    pub overcast: f32,
+    // Of the sunlight reaching the ground, for the directional light.
+    pub sun_color: [f32; 3],
+    // For ambient light and fog.
+    pub zenith: [f32; 3],
+    pub horizon: [f32; 3],
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self::new(TimeOfDay::default().sun_direction(), 0.0)
+    }
+}
+
+impl Sky {
+    pub fn new(sun_direction: [f32; 3], overcast: f32) -> Self {
+        let mut sky = Self {
+            sun_direction,
+            overcast: overcast.clamp(0.0, 1.0),
+            sun_color: [0.0; 3],
+            zenith: [0.0; 3],
+            horizon: [0.0; 3],
+        };
+        let light = sky.sunlight();
+        sky.sun_color = light.map(|c| c * (1.0 - 0.8 * sky.overcast));
+        sky.zenith = sky.sample([0.0, 1.0, 0.0]);
+        let across = normalize([sun_direction[2], 0.0, -sun_direction[0]]);
+        sky.horizon = sky.sample(if across == [0.0; 3] {
+            [1.0, 0.0, 0.0]
+        } else {
+            across
+        });
+        sky
+    }
+
+    // Sunlight left after crossing the atmosphere, fading out once the sun has set.
+    fn sunlight(&self) -> [f32; 3] {
+        let set = smoothstep(-0.1, 0.05, self.sun_direction[1]);
+        let depth = air_mass(self.sun_direction[1]) * THICKNESS;
+        RAYLEIGH.map(|beta| (-beta * depth).exp() * set)
+    }
+
+    // Radiance of the sky looking along `direction`, below the horizon as at it.
+    pub fn sample(&self, direction: [f32; 3]) -> [f32; 3] {
+        let direction = normalize([direction[0], direction[1].max(0.0), direction[2]]);
+        let light = self.sunlight();
+        let cos = dot(direction, self.sun_direction);
+        let depth = air_mass(direction[1]) * THICKNESS;
+        let rayleigh = 0.75 * (1.0 + cos * cos);
+        let mie = MIE_STRENGTH * (1.0 - MIE_G * MIE_G)
+            / (4.0 * PI * (1.0 + MIE_G * MIE_G - 2.0 * MIE_G * cos).powf(1.5));
+        let clear: [f32; 3] = std::array::from_fn(|i| {
+            let scattered = light[i] * (1.0 - (-RAYLEIGH[i] * depth).exp()) * rayleigh;
+            let haze = light[i] * mie * (1.0 - (-0.1 * depth).exp());
+            scattered + haze + NIGHT[i]
+        });
+        let grey = 0.6 * (0.2126 * clear[0] + 0.7152 * clear[1] + 0.0722 * clear[2]);
+        clear.map(|c| c + (grey - c) * self.overcast)
+    }
+}
+
+// Kasten and Young, relative to straight up, clamped just below the horizon.
+fn air_mass(sin_elevation: f32) -> f32 {
+    let zenith = sin_elevation.clamp(-1.0, 1.0).acos().to_degrees().min(93.0);
+    1.0 / (zenith.to_radians().cos().max(0.0) + 0.50572 * (96.07995 - zenith).powf(-1.6364))
+}
+
+fn smoothstep(from: f32, to: f32, x: f32) -> f32 {
+    let t = ((x - from) / (to - from)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    let length = dot(a, a).sqrt();
+    if length <= f32::EPSILON {
+        return [0.0; 3];
+    }
+    a.map(|c| c / length)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WeatherKind {
+    #[default]
+    Clear,
+    Rain,
+    Snow,
+}
+
+impl WeatherKind {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "clear" => Some(Self::Clear),
+            "rain" => Some(Self::Rain),
+            "snow" => Some(Self::Snow),
+            _ => None,
+        }
+    }
+
+    pub fn precipitation(self) -> Option<Precipitation> {
+        match self {
+            Self::Clear => None,
+            Self::Rain => Some(Precipitation::rain()),
+            Self::Snow => Some(Precipitation::snow()),
+        }
+    }
+}
+
+impl fmt::Display for WeatherKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Clear => "clear",
+            Self::Rain => "rain",
+            Self::Snow => "snow",
+        })
+    }
+}
+
+// A particle preset for what falls from the sky.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Precipitation {
+    // Per square meter around the camera per second, at full intensity.
+    pub rate: f32,
+    // Meters per second.
+    pub fall_speed: f32,
+    // How much of the wind a particle picks up.
+    pub drag: f32,
+    // Side to side drift in meters, snow flutters, rain doesn't.
+    pub sway: f32,
+    // Billboard width and height in meters.
+    pub size: [f32; 2],
+    // Rain is drawn stretched along its velocity.
+    pub streak: bool,
+    pub color: [f32; 4],
+    pub lifetime: Duration,
+}
+
+impl Precipitation {
+    pub fn rain() -> Self {
+        Self {
+            rate: 400.0,
+            fall_speed: 9.0,
+            drag: 0.3,
+            sway: 0.0,
+            size: [0.01, 0.4],
+            streak: true,
+            color: [0.7, 0.75, 0.8, 0.4],
+            lifetime: Duration::from_secs(2),
+        }
+    }
+
+    pub fn snow() -> Self {
+        Self {
+            rate: 120.0,
+            fall_speed: 1.0,
+            drag: 0.8,
+            sway: 0.3,
+            size: [0.03, 0.03],
+            streak: false,
+            color: [1.0, 1.0, 1.0, 0.9],
+            lifetime: Duration::from_secs(10),
+        }
+    }
+}
+
+// How long full rain takes to soak dry ground, and dry ground to dry out again in full sun.
+const SOAK_TIME: f32 = 120.0;
+const DRY_TIME: f32 = 600.0;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Weather {
+    kind: WeatherKind,
+    // 0..1 of the kind that's falling.
+    amount: f32,
+    target: WeatherKind,
+    target_intensity: f32,
+    // Amount per second while changing.
+    rate: f32,
+    // Meters per second, blows rain and snow sideways.
+    pub wind: [f32; 3],
+    wetness: f32,
+    snow_cover: f32,
+}
+
+impl Weather {
+    // Fades to `kind` over `transition`, letting what falls now stop first.
+    pub fn set(&mut self, kind: WeatherKind, intensity: f32, transition: Duration) {
+        self.target = kind;
+        self.target_intensity = if kind == WeatherKind::Clear {
+            0.0
+        } else {
+            intensity.clamp(0.0, 1.0)
+        };
+        self.rate = 1.0 / transition.as_secs_f32().max(f32::EPSILON);
+    }
+
+    pub fn kind(&self) -> WeatherKind {
+        self.kind
+    }
+
+    pub fn target(&self) -> WeatherKind {
+        self.target
+    }
+
+    // 0..1 of full strength.
+    pub fn intensity(&self) -> f32 {
+        self.amount
+    }
+
+    // For the sky, heavier weather brings more cloud.
+    pub fn overcast(&self) -> f32 {
+        self.amount
+    }
+
+    // The material parameter, 0 dry to 1 soaked.
+    pub fn wetness(&self) -> f32 {
+        self.wetness
+    }
+
+    // 0..1, how white the ground is.
+    pub fn snow_cover(&self) -> f32 {
+        self.snow_cover
+    }
+
+    // The preset and how many per square meter per second to spawn, None in clear weather.
+    pub fn precipitation(&self) -> Option<(Precipitation, f32)> {
+        let preset = self.kind.precipitation()?;
+        let rate = preset.rate * self.amount;
+        (rate > 0.0).then_some((preset, rate))
+    }
+
+    // `sun` is how strong the sunlight is, 0..1, it dries things faster.
+    pub fn advance(&mut self, delta: Duration, sun: f32) {
+        let seconds = delta.as_secs_f32();
+        let step = self.rate * seconds;
+        if self.kind != self.target && self.amount > 0.0 {
+            self.amount = (self.amount - step).max(0.0);
+        } else {
+            self.kind = self.target;
+            if self.amount < self.target_intensity {
+                self.amount = (self.amount + step).min(self.target_intensity);
+            } else {
+                self.amount = (self.amount - step).max(self.target_intensity);
+            }
+        }
+
+        let drying = (0.2 + 0.8 * sun.clamp(0.0, 1.0)) * seconds / DRY_TIME;
+        match self.kind {
+            WeatherKind::Rain => {
+                self.wetness = (self.wetness + self.amount * seconds / SOAK_TIME).min(1.0);
+                // Rain washes snow away.
+                self.snow_cover = (self.snow_cover - self.amount * seconds / SOAK_TIME).max(0.0);
+            }
+            WeatherKind::Snow => {
+                self.snow_cover = (self.snow_cover + self.amount * seconds / SOAK_TIME).min(1.0);
+                self.wetness = (self.wetness - drying).max(0.0);
+            }
+            WeatherKind::Clear => {
+                self.wetness = (self.wetness - drying).max(0.0);
+                self.snow_cover = (self.snow_cover - drying).max(0.0);
+            }
+        }
+    }
+}
+
+pub struct WeatherPlugin;
+
+impl Plugin for WeatherPlugin {
+    fn build(&self, engine: &mut Engine) {
+        engine
+            .insert_resource(TimeOfDay::default())
+            .insert_resource(Weather::default())
+            .insert_resource(Sky::default())
+            .add_system(|resources: &mut Resources| {
+                let delta = resources
+                    .get::<Time>()
+                    .map_or(Duration::ZERO, |time| time.delta);
+                let Some(time_of_day) = resources.get_mut::<TimeOfDay>() else {
+                    return;
+                };
+                time_of_day.advance(delta);
+                let sun_direction = time_of_day.sun_direction();
+                let sun = sun_direction[1].max(0.0);
+                let overcast = resources.get_mut::<Weather>().map_or(0.0, |weather| {
+                    weather.advance(delta, sun);
+                    metrics::set_gauge("wetness", weather.wetness() as f64);
+                    weather.overcast()
+                });
+                resources.insert(Sky::new(sun_direction, overcast));
+            });
+
+        let cvars = cvars::cvars(engine);
+        cvars.register_command(
+            "sky.time",
+            "sky.time <hours>, sets the time of day",
+            |resources, args| {
+                let [hours] = args else {
+                    return Err("usage: sky.time <hours>".to_owned());
+                };
+                let hours = hours
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|hours| (0.0..24.0).contains(hours))
+                    .ok_or_else(|| format!("{} isn't an hour of the day", hours))?;
+                resources
+                    .get_mut::<TimeOfDay>()
+                    .ok_or("no time of day")?
+                    .hours = hours;
+                Ok(())
+            },
+        );
+        cvars.register_command(
+            "weather.set",
+            "weather.set <clear|rain|snow> [intensity] [seconds], changes the weather",
+            |resources, args| {
+                let usage = || "usage: weather.set <clear|rain|snow> [intensity] [seconds]";
+                let (kind, rest) = args.split_first().ok_or_else(usage)?;
+                let kind = WeatherKind::parse(kind).ok_or_else(usage)?;
+                let number = |index: usize, default: f32| match rest.get(index) {
+                    Some(text) => text
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|value| value.is_finite() && *value >= 0.0)
+                        .ok_or_else(|| format!("{} isn't a positive number", text)),
+                    None => Ok(default),
+                };
+                if rest.len() > 2 {
+                    return Err(usage().to_owned());
+                }
+                let intensity = number(0, 1.0)?;
+                let seconds = number(1, 10.0)?;
+                let duration = Duration::try_from_secs_f32(seconds)
+                    .map_err(|_| format!("{} isn't a duration", seconds))?;
+                resources
+                    .get_mut::<Weather>()
+                    .ok_or("no weather")?
+                    .set(kind, intensity, duration);
+                Ok(())
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cvars::Cvars;
+
+    #[test]
+    fn sun_sky_and_rain() {
+        // At the equator on the equinox the sun rises in the east and is overhead at noon.
+        let mut time = TimeOfDay {
+            hours: 6.0,
+            latitude: 0.0,
+            day_of_year: 81,
+            ..TimeOfDay::default()
+        };
+        let sunrise = time.sun_direction();
+        assert!(sunrise[0] > 0.99 && sunrise[1].abs() < 0.01);
+        time.advance(Duration::from_secs(5 * 60));
+        assert_eq!(time.hours, 12.0);
+        assert!(time.sun_direction()[1] > 0.99);
+
+        // Blue overhead at noon, a red sun low down and nothing once it's set.
+        let noon = Sky::new(time.sun_direction(), 0.0);
+        assert!(noon.zenith[2] > noon.zenith[0]);
+        let dusk = Sky::new(normalize([-1.0, 0.02, 0.0]), 0.0);
+        assert!(dusk.sun_color[0] > dusk.sun_color[2] * 2.0);
+        assert!(dusk.sample([-1.0, 0.0, 0.0])[0] > dusk.sample([1.0, 0.0, 0.0])[0]);
+        assert_eq!(Sky::new([0.0, -1.0, 0.0], 0.0).sun_color, [0.0; 3]);
+        let overcast = Sky::new(time.sun_direction(), 1.0);
+        assert!(overcast.sun_color[1] < noon.sun_color[1] * 0.5);
+
+        // Rain fades in, soaks the ground, then stops and it dries.
+        let mut weather = Weather::default();
+        weather.set(WeatherKind::Rain, 1.0, Duration::from_secs(10));
+        weather.advance(Duration::from_secs(5), 1.0);
+        assert_eq!(weather.intensity(), 0.5);
+        assert!(weather.precipitation().unwrap().0.streak);
+        for _ in 0..30 {
+            weather.advance(Duration::from_secs(5), 1.0);
+        }
+        assert_eq!(weather.wetness(), 1.0);
+        weather.set(WeatherKind::Snow, 1.0, Duration::from_secs(10));
+        weather.advance(Duration::from_secs(10), 1.0);
+        assert_eq!(weather.kind(), WeatherKind::Rain);
+        assert_eq!(weather.precipitation(), None);
+        weather.advance(Duration::from_secs(5), 1.0);
+        assert_eq!(weather.kind(), WeatherKind::Snow);
+        assert!(!weather.precipitation().unwrap().0.streak);
+        weather.advance(Duration::from_secs(300), 1.0);
+        assert!(weather.wetness() < 0.6 && weather.snow_cover() == 1.0);
+
+        let mut engine = Engine::new();
+        engine.add_plugins(WeatherPlugin);
+        for line in [
+            "weather.set rain inf",
+            "weather.set rain 1 inf",
+            "weather.set rain 1 1e30",
+        ] {
+            assert!(Cvars::execute(engine.resources_mut(), line).is_err());
+        }
+        assert!(Cvars::execute(engine.resources_mut(), "weather.set rain 1 2").is_ok());
+    }
+}
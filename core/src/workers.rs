@@ -0,0 +1,162 @@
+//! A pool of worker threads for spreading a batch of work over the cores, the schedule's systems
+//! for one. `WorkerPool::run` hands the jobs out, runs one on the calling thread and returns once
+//! every job is done, so jobs can borrow from the caller like scoped threads without paying for
+//! spawning threads every time.
+//!
+//! Targets without threads get a pool without workers that runs everything on the caller.
+
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use log::error;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+pub struct WorkerPool {
+    // None once the pool is shutting down.
+    jobs: Option<Sender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    // `threads` workers besides the thread calling `run`.
+    pub fn new(threads: usize) -> Self {
+        let threads = if cfg!(target_arch = "wasm32") {
+            0
+        } else {
+            threads
+        };
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let threads = (0..threads)
+            .filter_map(|index| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("worker {}", index))
+                    .spawn(move || work(&receiver))
+                    .map_err(|err| error!("Failed to start worker {}: {}", index, err))
+                    .ok()
+            })
+            .collect();
+        Self {
+            jobs: Some(jobs),
+            threads,
+        }
+    }
+
+    // A worker per core, less the one the caller runs on.
+    pub fn for_cores() -> Self {
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+        Self::new(cores - 1)
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    // Runs every job and returns once they're all done. If any panicked, the panic carries on
+    // on this thread after the others finish.
+    pub fn run<'a>(&self, jobs: Vec<Box<dyn FnOnce() + Send + 'a>>) {
+        let mut jobs = jobs.into_iter();
+        let Some(first) = jobs.next() else {
+            return;
+        };
+        let (done, finished) = mpsc::channel::<Option<Box<dyn Any + Send>>>();
+        let mut pending = 0;
+        for job in jobs {
+            let done = done.clone();
+            let job: Box<dyn FnOnce() + Send + 'a> = Box::new(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(job));
+                let _ = done.send(result.err());
+            });
+            // Safety: every job is waited for below before returning, panicking or not, so
+            // nothing it borrows for 'a is gone while it runs.
+            let job: Job = unsafe { std::mem::transmute(job) };
+            pending += 1;
+            if let Err(mpsc::SendError(job)) = self.send(job) {
+                job();
+            }
+        }
+        let mut panicked = panic::catch_unwind(AssertUnwindSafe(first)).err();
+        for _ in 0..pending {
+            let payload = finished.recv().expect("a job was dropped without running");
+            panicked = panicked.or(payload);
+        }
+        if let Some(payload) = panicked {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    fn send(&self, job: Job) -> Result<(), mpsc::SendError<Job>> {
+        match &self.jobs {
+            Some(jobs) if !self.threads.is_empty() => jobs.send(job),
+            _ => Err(mpsc::SendError(job)),
+        }
+    }
+}
+
+fn work(jobs: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = jobs.lock().unwrap().recv();
+        match job {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // Closing the channel lets the workers run out of jobs and return.
+        self.jobs = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn runs_borrowed_jobs_to_completion() {
+        let pool = WorkerPool::new(3);
+        let counter = AtomicUsize::new(0);
+        let mut results = vec![0; 8];
+        let jobs: Vec<Box<dyn FnOnce() + Send + '_>> = results
+            .iter_mut()
+            .enumerate()
+            .map(|(i, result)| {
+                let counter = &counter;
+                Box::new(move || {
+                    *result = i * i;
+                    counter.fetch_add(1, Ordering::Relaxed);
+                }) as Box<dyn FnOnce() + Send + '_>
+            })
+            .collect();
+        pool.run(jobs);
+        assert_eq!(counter.load(Ordering::Relaxed), 8);
+        assert_eq!(results, [0, 1, 4, 9, 16, 25, 36, 49]);
+
+        let panicked = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.run(vec![Box::new(|| {}), Box::new(|| panic!("job failed"))]);
+        }));
+        assert!(panicked.is_err());
+        // The pool still works after a job panicked.
+        let ran = AtomicUsize::new(0);
+        pool.run(vec![Box::new(|| {
+            ran.fetch_add(1, Ordering::Relaxed);
+        })]);
+        assert_eq!(ran.load(Ordering::Relaxed), 1);
+    }
+}
@@ -19,82 +19,86 @@ extern crate midnight2_core as core;
 #[macro_use]
 extern crate log;
 
-use core::identifier;
-use core::render::{self};
-use core::sim::{self};
-use std::{os::windows::io::AsHandle, thread::JoinHandle};
+use core::app::{Application, EngineBuilder};
+use core::net::{NetClientPlugin, NetServerPlugin, ServerAddress};
+use core::{log_scope, logging};
+use core::playback::InputTimeline;
+use core::rand::RandomPlugin;
+use core::regression::RegressionPlugin;
 
-use crate::core::logging;
+const DEFAULT_PORT: u16 = 27015;
 
-//use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
-use winit::{
-    dpi::LogicalSize,
-    event::{self, ElementState, Event, KeyEvent, WindowEvent},
-    event_loop::ControlFlow,
-    keyboard::{Key, NamedKey},
-};
+struct Midnight;
 
-fn spawn_world() -> Result<JoinHandle<()>, Box<dyn std::error::Error>> {
-    info!("Initializing sim thread!");
-    sim::init()
-}
-
-fn spawn_window() {
-    info!("Spawning window!");
+impl Application for Midnight {}
 
-    let event_loop = winit::event_loop::EventLoop::new().unwrap();
-    let window = winit::window::WindowBuilder::new()
+fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let builder = EngineBuilder::new()
         .with_title("Midnight2 Application")
-        .with_inner_size(LogicalSize::new(1280.0, 720.0))
-        .build(&event_loop)
-        .unwrap();
-
-    let mut render_thread: Option<std::thread::JoinHandle<()>> =
-        Some(render::init(&window).unwrap());
+        .with_logging(false)
+        .add_plugins(RandomPlugin);
 
-    event_loop
-        .run(move |e, target| {
-            let _ = &window;
-            target.set_control_flow(ControlFlow::Poll);
-            match e {
-                Event::LoopExiting => {
-                    info!("Spinning down render!");
-                    unsafe { render::shutdown() };
-                    render_thread.take().map(JoinHandle::join);
-                    info!("Done!");
-
-                    info!("Spinning down sim!");
-                    unsafe { sim::shutdown() };
-                    info!("Done!");
-                }
-                Event::WindowEvent { event, .. } => match event {
-                    WindowEvent::KeyboardInput {
-                        event:
-                            KeyEvent {
-                                logical_key: Key::Named(NamedKey::Escape),
-                                state: ElementState::Pressed,
-                                ..
-                            },
-                        ..
-                    }
-                    | WindowEvent::CloseRequested => target.exit(),
-                    _ => {}
-                },
-                _ => {}
-            }
-        })
-        .unwrap();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let value_of = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .map(|index| args.get(index + 1).map(String::as_str))
+    };
+    if let Some(path) = value_of("--playback") {
+        let path = path.ok_or("--playback expects a timeline file")?;
+        let timeline = {
+            log_scope!("Loading timeline {}", path);
+            InputTimeline::load(path)?
+        };
+        return builder.run_playback(Midnight, &timeline);
+    }
+    if let Some(path) = value_of("--verify") {
+        let path = path.ok_or("--verify expects a recording")?;
+        let timeline = {
+            log_scope!("Loading recording {}", path);
+            InputTimeline::load(path)?
+        };
+        return builder.run_verify(Midnight, &timeline);
+    }
+    if let Some(path) = value_of("--record") {
+        let path = path.ok_or("--record expects a file to record to")?;
+        return builder.add_plugins(RegressionPlugin::record(path)).run(Midnight);
+    }
+    if args.iter().any(|arg| arg == "--server") {
+        let port = match value_of("--port") {
+            Some(port) => port.ok_or("--port expects a port number")?.parse()?,
+            None => DEFAULT_PORT,
+        };
+        let mut server = NetServerPlugin::new(port);
+        if let Some(ws_port) = value_of("--ws-port") {
+            server = server.with_websocket(ws_port.ok_or("--ws-port expects a port number")?.parse()?);
+        }
+        return builder.add_plugins(server).run_headless(Midnight);
+    }
+    #[cfg(feature = "hot-reload")]
+    if let Some(path) = value_of("--game") {
+        let path = path.ok_or("--game expects the game library")?;
+        return builder.run(core::hot_reload::HotReloadGame::new(path)?);
+    }
+    if let Some(addr) = value_of("--connect") {
+        let addr = addr.ok_or("--connect expects a server address")?;
+        let server = if addr.starts_with("ws://") {
+            ServerAddress::WebSocket(addr.to_owned())
+        } else {
+            std::net::ToSocketAddrs::to_socket_addrs(addr)?
+                .next()
+                .ok_or("--connect address didn't resolve")?
+                .into()
+        };
+        return builder.add_plugins(NetClientPlugin::new(server)).run(Midnight);
+    }
+    builder.run(Midnight)
 }
 
 fn main() {
+    // Initialized up front so argument errors are logged too.
     logging::init();
-    info!("Hello midnight!");
-    info!("Initializing game sim!");
-    let id = identifier::ThreadLocalId::allocate().unwrap();
-    info!("Main Thread ID: {:?}", id);
-    if let Ok(sim_thread) = spawn_world() {
-        spawn_window();
-        info!("Shutting down, joining sim thread!");
-        sim_thread.join().expect("Failed to join sim thread from the main thread!, typically this ocurrs during shutdown");
+    if let Err(err) = run() {
+        error!("Midnight exited with an error: {}", err);
     }
 }